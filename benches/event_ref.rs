@@ -0,0 +1,114 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    io::Cursor,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use mariadb_binlog_parse::event_ref::parse_event_ref;
+use mariadb_binlog_parse::model::EventBodyTypeCode19;
+use mariadb_binlog_parse::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+mod fixtures;
+use fixtures::*;
+
+// 每个事务4个事件
+const TRANSACTIONS: usize = 10_000;
+
+/// 统计分配次数，比较两种解析方式每个事件的分配
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// get_event_body，每个事件都读取body并转换成model中的结构
+fn parse_owned(buffer: &[u8], table_info: &EventBodyTypeCode19) -> usize {
+    let mut file = Cursor::new(buffer);
+    let mut table_structs = HashMap::from([(table_info.table_id, Arc::new(table_info.clone()))]);
+
+    let mut events = 0;
+    let mut offset = 4;
+    while offset < buffer.len() as u64 {
+        let header = get_event_header(&mut file, offset).unwrap();
+        let body = get_event_body(
+            &mut file,
+            offset,
+            &header,
+            &mut table_structs,
+            CHECKSUM_LENGTH,
+        )
+        .unwrap();
+        criterion::black_box(body);
+
+        events += 1;
+        offset = header.next_event_position as u64;
+    }
+
+    events
+}
+
+/// parse_event_ref，直接借用buffer中的一段
+fn parse_borrowed(buffer: &[u8]) -> usize {
+    let mut events = 0;
+    let mut offset = 4;
+    while offset < buffer.len() {
+        let event_length = u32::from_le_bytes(buffer[offset + 9..offset + 13].try_into().unwrap());
+        let event = parse_event_ref(&buffer[offset..offset + event_length as usize]).unwrap();
+        criterion::black_box(&event);
+
+        events += 1;
+        offset = event.header.next_event_position as usize;
+    }
+
+    events
+}
+
+fn print_allocations(name: &str, parse: impl Fn() -> usize) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let events = parse();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "{}: {} allocations for {} events ({:.2} per event)",
+        name,
+        allocations,
+        events,
+        allocations as f64 / events as f64
+    );
+}
+
+fn bench_event_ref(c: &mut Criterion) {
+    let (table_info, _) = mixed();
+    let buffer = transactions_file(TRANSACTIONS);
+
+    // 先各解析一遍，输出每个事件的分配次数
+    print_allocations("owned", || parse_owned(&buffer, &table_info));
+    print_allocations("borrowed", || parse_borrowed(&buffer));
+
+    let mut group = c.benchmark_group("event_ref");
+    group.throughput(Throughput::Bytes(buffer.len() as u64));
+    group.bench_function("owned", |b| b.iter(|| parse_owned(&buffer, &table_info)));
+    group.bench_function("borrowed", |b| b.iter(|| parse_borrowed(&buffer)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_event_ref);
+criterion_main!(benches);
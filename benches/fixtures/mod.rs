@@ -0,0 +1,365 @@
+// 性能测试用的合成事件，每个bench文件只用到其中一部分
+#![allow(dead_code)]
+
+use mariadb_binlog_parse::field_type::FieldType;
+use mariadb_binlog_parse::model::{Bitmap, ColumnDef, EventBodyTypeCode19};
+use mariadb_binlog_parse::table_structure::column_descriptors;
+
+// 一个行事件中的行数，和批量导入时的大事件差不多
+pub const ROWS: usize = 1000;
+
+pub const BINLOG_MAGIC_NUMBER: [u8; 4] = [0xfe, 0x62, 0x69, 0x6e];
+
+/// 字段类型和对应的元数据，没有元数据的类型为None
+pub fn table_map(columns: &[(u8, &str, Option<Vec<u8>>)]) -> EventBodyTypeCode19 {
+    let metadata_block_data_raw: Vec<Vec<u8>> =
+        columns.iter().filter_map(|v| v.2.clone()).collect();
+
+    #[allow(deprecated)]
+    let mut table_info = EventBodyTypeCode19 {
+        table_id: 70,
+        reserved_for_future_use: 1,
+        database_name_length: 5,
+        database_name: "bench".to_string(),
+        table_name_length: 1,
+        table_name: "t".to_string(),
+        number_of_columns: columns.len() as u64,
+        column_types: columns.iter().map(|v| v.0).collect(),
+        column_types_string_for_human: columns.iter().map(|v| v.1.to_string()).collect(),
+        number_of_metadata_block: metadata_block_data_raw.concat().len() as u64,
+        metadata_block: metadata_block_data_raw.concat(),
+        metadata_block_string_for_human: Vec::new(),
+        metadata_block_data_raw,
+        columns_can_be_null: Bitmap::repeat(true, columns.len()),
+        optional_metadata_block: Vec::new(),
+        columns: columns
+            .iter()
+            .map(|v| ColumnDef {
+                field_type: FieldType::try_from(v.0).unwrap(),
+                metadata_raw: v.2.clone().unwrap_or_default(),
+                metadata_description: String::new(),
+                nullable: true,
+                charset: None,
+                unsigned: None,
+                name: None,
+            })
+            .collect(),
+        column_names: None,
+        primary_key: None,
+        primary_key_prefixes: None,
+        column_descriptors: Vec::new(),
+        column_index: Default::default(),
+    };
+
+    // 和deal_type_code_19一样预先计算
+    table_info.column_descriptors = column_descriptors(&table_info);
+    table_info
+}
+
+/// ROWS行，每行都是null bitmap加上同样的字段数据
+pub fn row_images(number_of_columns: usize, row: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for _ in 0..ROWS {
+        buffer.extend(vec![0u8; number_of_columns.div_ceil(8)]);
+        buffer.extend_from_slice(row);
+    }
+
+    buffer
+}
+
+pub fn all_int() -> (EventBodyTypeCode19, Vec<u8>) {
+    let mut columns = Vec::new();
+    let mut row = Vec::new();
+    for _ in 0..2 {
+        columns.push((1, "MYSQL_TYPE_TINY", None));
+        row.push(7);
+        columns.push((2, "MYSQL_TYPE_SHORT", None));
+        row.extend(1234i16.to_le_bytes());
+        columns.push((3, "MYSQL_TYPE_LONG", None));
+        row.extend(123456i32.to_le_bytes());
+        columns.push((8, "MYSQL_TYPE_LONGLONG", None));
+        row.extend(1234567890123i64.to_le_bytes());
+    }
+
+    let images = row_images(columns.len(), &row);
+    (table_map(&columns), images)
+}
+
+pub fn wide_varchar() -> (EventBodyTypeCode19, Vec<u8>) {
+    let mut columns = Vec::new();
+    let mut row = Vec::new();
+    for _ in 0..10 {
+        // varchar(1024)，长度用2个字节
+        columns.push((
+            15,
+            "MYSQL_TYPE_VARCHAR",
+            Some(1024u16.to_le_bytes().to_vec()),
+        ));
+        let value = "a moderately long varchar value for the benchmark".repeat(4);
+        row.extend((value.len() as u16).to_le_bytes());
+        row.extend(value.as_bytes());
+    }
+
+    let images = row_images(columns.len(), &row);
+    (table_map(&columns), images)
+}
+
+pub fn blob_heavy() -> (EventBodyTypeCode19, Vec<u8>) {
+    let mut columns = vec![(3, "MYSQL_TYPE_LONG", None)];
+    let mut row = Vec::new();
+    row.extend(42i32.to_le_bytes());
+    for _ in 0..4 {
+        // blob，长度用2个字节
+        columns.push((252, "MYSQL_TYPE_BLOB", Some(vec![2])));
+        let blob = vec![b'x'; 2000];
+        row.extend((blob.len() as u16).to_le_bytes());
+        row.extend(&blob);
+    }
+
+    let images = row_images(columns.len(), &row);
+    (table_map(&columns), images)
+}
+
+pub fn temporal_heavy() -> (EventBodyTypeCode19, Vec<u8>) {
+    let mut columns = Vec::new();
+    let mut row = Vec::new();
+    for _ in 0..2 {
+        columns.push((10, "MYSQL_TYPE_DATE", None));
+        row.extend(date());
+        columns.push((19, "MYSQL_TYPE_TIME2", Some(vec![0])));
+        // 12:34:56
+        row.extend(&(0x800000u32 + ((12 << 12) | (34 << 6) | 56)).to_be_bytes()[1..]);
+        columns.push((18, "MYSQL_TYPE_DATETIME2", Some(vec![0])));
+        row.extend(datetime());
+        columns.push((17, "MYSQL_TYPE_TIMESTAMP2", Some(vec![0])));
+        row.extend(1715920496u32.to_be_bytes());
+    }
+
+    let images = row_images(columns.len(), &row);
+    (table_map(&columns), images)
+}
+
+pub fn mixed() -> (EventBodyTypeCode19, Vec<u8>) {
+    let columns = vec![
+        (3, "MYSQL_TYPE_LONG", None),
+        (15, "MYSQL_TYPE_VARCHAR", Some(64u16.to_le_bytes().to_vec())),
+        (246, "MYSQL_TYPE_NEWDECIMAL", Some(vec![10, 2])),
+        (252, "MYSQL_TYPE_BLOB", Some(vec![2])),
+        (10, "MYSQL_TYPE_DATE", None),
+        (18, "MYSQL_TYPE_DATETIME2", Some(vec![0])),
+    ];
+
+    let mut row = Vec::new();
+    row.extend(42i32.to_le_bytes());
+    row.push(5);
+    row.extend(b"hello");
+    row.extend(decimal());
+    let blob = vec![b'x'; 300];
+    row.extend((blob.len() as u16).to_le_bytes());
+    row.extend(&blob);
+    row.extend(date());
+    row.extend(datetime());
+
+    let images = row_images(columns.len(), &row);
+    (table_map(&columns), images)
+}
+
+/// 所有的表结构，按名字区分性能测试的结果
+pub fn table_shapes() -> Vec<(&'static str, (EventBodyTypeCode19, Vec<u8>))> {
+    vec![
+        ("all_int", all_int()),
+        ("wide_varchar", wide_varchar()),
+        ("blob_heavy", blob_heavy()),
+        ("temporal_heavy", temporal_heavy()),
+        ("mixed", mixed()),
+    ]
+}
+
+/// decimal(10,2)的12345.67
+pub fn decimal() -> Vec<u8> {
+    let mut buffer = 0x80003039u32.to_be_bytes().to_vec();
+    buffer.push(67);
+    buffer
+}
+
+// 2024-05-17
+fn date() -> Vec<u8> {
+    (17u32 | (5 << 5) | (2024 << 9)).to_le_bytes()[..3].to_vec()
+}
+
+// 2024-05-17 12:34:56
+fn datetime() -> Vec<u8> {
+    let date_value = ((2024u64 * 13 + 5) << 5) | 17;
+    let time_value = (12u64 << 12) | (34 << 6) | 56;
+    (0x8000000000 + ((date_value << 17) | time_value)).to_be_bytes()[3..].to_vec()
+}
+
+/// 在file的末尾追加一个事件，next_event_position按追加之后的长度计算
+/// body的最后4个字节换成整个事件的CRC32，和服务端写的一样可以通过`--verify-checksums`
+pub fn append_event(file: &mut Vec<u8>, type_code: u8, body: &[u8]) {
+    let start = file.len();
+    let event_length = 19 + body.len() as u32;
+    let next_event_position = file.len() as u32 + event_length;
+
+    file.extend(0u32.to_le_bytes());
+    file.push(type_code);
+    file.extend(1u32.to_le_bytes());
+    file.extend(event_length.to_le_bytes());
+    file.extend(next_event_position.to_le_bytes());
+    file.extend(0u16.to_le_bytes());
+    file.extend(body);
+
+    let checksum_start = file.len() - 4;
+    let checksum = crc32fast::hash(&file[start..checksum_start]);
+    file[checksum_start..].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// insert事件的body，包括校验和，所有字段都在columns_used中
+pub fn rows_event_body(table_info: &EventBodyTypeCode19, images: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend(&table_info.table_id.to_le_bytes()[..6]);
+    body.extend(0u16.to_le_bytes());
+    body.push(table_info.number_of_columns as u8);
+    body.extend(vec![
+        0xffu8;
+        (table_info.number_of_columns as usize).div_ceil(8)
+    ]);
+    body.extend_from_slice(images);
+    // 校验和
+    body.extend([0u8; 4]);
+
+    body
+}
+
+/// magic number加上events个insert事件，每个事件都是ROWS行，table map需要事先放进table_structs
+pub fn rows_events_file(table_info: &EventBodyTypeCode19, images: &[u8], events: usize) -> Vec<u8> {
+    let body = rows_event_body(table_info, images);
+
+    let mut file = BINLOG_MAGIC_NUMBER.to_vec();
+    for _ in 0..events {
+        append_event(&mut file, 23, &body);
+    }
+
+    file
+}
+
+/// magic number加上events个大小不同的query事件，只用来读取事件头，body的内容没有意义
+pub fn small_events_file(events: usize) -> Vec<u8> {
+    let mut file = BINLOG_MAGIC_NUMBER.to_vec();
+    for i in 0..events {
+        append_event(&mut file, 2, &vec![0u8; 40 + i % 200]);
+    }
+
+    file
+}
+
+/// magic number加上transactions个事务，每个事务是GTID、BEGIN、一行的insert和XID
+/// 行事件是mixed的表结构，table map需要事先放进table_structs
+pub fn transactions_file(transactions: usize) -> Vec<u8> {
+    let (table_info, images) = mixed();
+    let rows_body = rows_event_body(&table_info, &images[..images.len() / ROWS]);
+
+    let mut query_body = Vec::new();
+    // thread id、执行时间、库名长度、错误码、status variables长度
+    query_body.extend(1u32.to_le_bytes());
+    query_body.extend(0u32.to_le_bytes());
+    query_body.push(5);
+    query_body.extend(0u16.to_le_bytes());
+    query_body.extend(0u16.to_le_bytes());
+    query_body.extend(b"bench\0BEGIN");
+    query_body.extend([0u8; 4]);
+
+    let mut file = BINLOG_MAGIC_NUMBER.to_vec();
+    for i in 0..transactions as u64 {
+        let mut gtid_body = Vec::new();
+        gtid_body.extend(i.to_le_bytes());
+        gtid_body.extend(0u32.to_le_bytes());
+        gtid_body.push(0);
+        gtid_body.extend([0u8; 4]);
+        append_event(&mut file, 162, &gtid_body);
+
+        append_event(&mut file, 2, &query_body);
+        append_event(&mut file, 23, &rows_body);
+
+        let mut xid_body = i.to_le_bytes().to_vec();
+        xid_body.extend([0u8; 4]);
+        append_event(&mut file, 16, &xid_body);
+    }
+
+    file
+}
+
+/// table map的body，包括校验和，元数据按字段的顺序拼接
+pub fn table_map_body(table_info: &EventBodyTypeCode19) -> Vec<u8> {
+    let metadata: Vec<u8> = table_info
+        .columns
+        .iter()
+        .flat_map(|v| v.metadata_raw.clone())
+        .collect();
+
+    let mut body = Vec::new();
+    body.extend(&table_info.table_id.to_le_bytes()[..6]);
+    body.extend(1u16.to_le_bytes());
+    body.push(table_info.database_name.len() as u8);
+    body.extend(table_info.database_name.as_bytes());
+    body.push(0);
+    body.push(table_info.table_name.len() as u8);
+    body.extend(table_info.table_name.as_bytes());
+    body.push(0);
+    body.push(table_info.number_of_columns as u8);
+    body.extend(table_info.columns.iter().map(|v| v.type_code()));
+    body.push(metadata.len() as u8);
+    body.extend(metadata);
+    body.extend(vec![
+        0xffu8;
+        (table_info.number_of_columns as usize).div_ceil(8)
+    ]);
+    // 校验和
+    body.extend([0u8; 4]);
+
+    body
+}
+
+/// magic number加上events个table map，表结构是all_int和wide_varchar的字段合在一起
+pub fn table_maps_file(events: usize) -> Vec<u8> {
+    let (all_int, _) = all_int();
+    let (wide_varchar, _) = wide_varchar();
+
+    let mut table_info = all_int.clone();
+    table_info.number_of_columns += wide_varchar.number_of_columns;
+    table_info.columns.extend(wide_varchar.columns);
+    let body = table_map_body(&table_info);
+
+    let mut file = BINLOG_MAGIC_NUMBER.to_vec();
+    for _ in 0..events {
+        append_event(&mut file, 19, &body);
+    }
+
+    file
+}
+
+/// magic number加上两张表的table map和events个insert事件，每20个事件中只有1个是`bench.t`的，
+/// 其他都是`bench.excluded`的，每个事件10行，两张表都是mixed的表结构
+pub fn mostly_excluded_file(events: usize) -> Vec<u8> {
+    let (table_info, images) = mixed();
+    let mut excluded = table_info.clone();
+    excluded.table_id += 1;
+    excluded.table_name = "excluded".to_string();
+    excluded.table_name_length = excluded.table_name.len() as u8;
+
+    let images = &images[..images.len() / ROWS * 10];
+    let included_body = rows_event_body(&table_info, images);
+    let excluded_body = rows_event_body(&excluded, images);
+
+    let mut file = BINLOG_MAGIC_NUMBER.to_vec();
+    append_event(&mut file, 19, &table_map_body(&table_info));
+    append_event(&mut file, 19, &table_map_body(&excluded));
+    for i in 0..events {
+        match i % 20 {
+            0 => append_event(&mut file, 23, &included_body),
+            _ => append_event(&mut file, 23, &excluded_body),
+        }
+    }
+
+    file
+}
@@ -0,0 +1,153 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    io::Cursor,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use mariadb_binlog_parse::model::EventBodyTypeCode19;
+use mariadb_binlog_parse::service::{
+    get_event_body_with_value_limit, get_event_header, CHECKSUM_LENGTH,
+};
+
+mod fixtures;
+use fixtures::*;
+
+// 一个行事件中的LONGBLOB的大小
+const BLOB_LENGTH: usize = 64 * 1024 * 1024;
+
+// `--max-value-length 1KB`
+const MAX_VALUE_LENGTH: usize = 1024;
+
+/// 记录当前分配的字节数和其中的峰值
+struct PeakAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    // 字符串增长时按新旧大小的差计算，和System一样可能原地扩展
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            let allocated = ALLOCATED.fetch_add(new_size - layout.size(), Ordering::Relaxed)
+                + new_size
+                - layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        } else {
+            ALLOCATED.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAllocator = PeakAllocator;
+
+/// 一个int字段和一个LONGBLOB字段的表，文件中只有一个插入一行的行事件
+fn large_blob_file(blob: &[u8]) -> (EventBodyTypeCode19, Vec<u8>) {
+    let table_info = table_map(&[
+        (3, "MYSQL_TYPE_LONG", None),
+        (252, "MYSQL_TYPE_BLOB", Some(vec![4])),
+    ]);
+
+    let mut images = vec![0u8];
+    images.extend(42i32.to_le_bytes());
+    images.extend((blob.len() as u32).to_le_bytes());
+    images.extend(blob);
+
+    let mut file = BINLOG_MAGIC_NUMBER.to_vec();
+    append_event(&mut file, 23, &rows_event_body(&table_info, &images));
+
+    (table_info, file)
+}
+
+/// 和main中一样解码这个事件，包括读取body
+fn decode(
+    file: &[u8],
+    table_info: &EventBodyTypeCode19,
+    max_value_length: Option<usize>,
+) -> String {
+    let mut cursor = Cursor::new(file);
+    let mut table_structs = HashMap::from([(table_info.table_id, Arc::new(table_info.clone()))]);
+
+    let header = get_event_header(&mut cursor, 4).unwrap();
+    let body = get_event_body_with_value_limit(
+        &mut cursor,
+        4,
+        &header,
+        &mut table_structs,
+        CHECKSUM_LENGTH,
+        max_value_length,
+    )
+    .unwrap();
+
+    // 截断的值可以直接输出，输出的字符串也计入峰值
+    match max_value_length {
+        Some(_) => format!("{:#?}", body),
+        None => String::new(),
+    }
+}
+
+fn print_peak(name: &str, event_length: usize, decode: impl Fn() -> String) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+
+    criterion::black_box(decode());
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+
+    println!(
+        "{}: peak {} bytes for a {} bytes event ({:.2}x)",
+        name,
+        peak,
+        event_length,
+        peak as f64 / event_length as f64
+    );
+}
+
+fn bench_large_blob(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_blob");
+    group.sample_size(10);
+
+    // 文本按String解码，不是文本的按Bytes解码
+    for (name, byte) in [("text", b'x'), ("binary", 0xffu8)] {
+        let (table_info, file) = large_blob_file(&vec![byte; BLOB_LENGTH]);
+        let event_length = file.len() - BINLOG_MAGIC_NUMBER.len();
+
+        // 峰值不包括文件本身
+        print_peak(&format!("{}_full", name), event_length, || {
+            decode(&file, &table_info, None)
+        });
+        print_peak(&format!("{}_truncated", name), event_length, || {
+            decode(&file, &table_info, Some(MAX_VALUE_LENGTH))
+        });
+
+        group.throughput(Throughput::Bytes(event_length as u64));
+        group.bench_function(format!("{}_full", name), |b| {
+            b.iter(|| decode(&file, &table_info, None))
+        });
+        group.bench_function(format!("{}_truncated", name), |b| {
+            b.iter(|| decode(&file, &table_info, Some(MAX_VALUE_LENGTH)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_large_blob);
+criterion_main!(benches);
@@ -0,0 +1,293 @@
+use std::{collections::HashMap, fs, io::Write, sync::Arc};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use mariadb_binlog_parse::checksum::ChecksumVerifier;
+use mariadb_binlog_parse::model::{Bitmap, ValueOptions};
+use mariadb_binlog_parse::parser::BinlogParser;
+use mariadb_binlog_parse::reader::BinlogReader;
+use mariadb_binlog_parse::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+use mariadb_binlog_parse::util::{
+    bin_to_decimal, parse_bitmap, parse_column_data_for_row_event, parse_lenenc,
+};
+
+mod fixtures;
+use fixtures::*;
+
+// 只读取事件头的文件中的事件数，大约25MB
+const SMALL_EVENTS: usize = 200_000;
+
+// 完整解码的文件中的行事件数
+const ROWS_EVENTS: usize = 32;
+
+// 只有table map的文件中的事件数，OLTP的binlog中每条语句都有一个table map
+const TABLE_MAP_EVENTS: usize = 1000;
+
+// 大部分行事件属于不需要解码的表的文件中的行事件数
+const FILTERED_EVENTS: usize = 2000;
+
+// 比较是否校验CRC32的文件中的事务数，每个事务4个事件
+const CHECKSUM_TRANSACTIONS: usize = 20_000;
+
+/// 合成的文件写到临时目录，和实际解析一样通过BinlogReader读取
+fn bench_header_scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan_headers");
+    group.sample_size(10);
+
+    let buffer = small_events_file(SMALL_EVENTS);
+    let file_path = std::env::temp_dir().join("mariadb_binlog_parse_bench_headers.bin");
+    fs::File::create(&file_path)
+        .and_then(|mut file| file.write_all(&buffer))
+        .unwrap();
+
+    let file_length = buffer.len() as u64;
+    group.throughput(Throughput::Bytes(file_length));
+    group.bench_function("small_events", |b| {
+        b.iter(|| {
+            let mut file = BinlogReader::new(fs::File::open(&file_path).unwrap());
+
+            let mut offset = 4;
+            while offset < file_length {
+                let header = get_event_header(&mut file, offset).unwrap();
+                offset = header.next_event_position as u64;
+            }
+        })
+    });
+
+    group.finish();
+    fs::remove_file(&file_path).unwrap();
+}
+
+// 按事件解码，包括读取body、解析行事件的头部和所有字段的值
+fn bench_full_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full_decode");
+    group.sample_size(10);
+
+    let (table_info, images) = mixed();
+    let buffer = rows_events_file(&table_info, &images, ROWS_EVENTS);
+    let file_length = buffer.len() as u64;
+
+    group.throughput(Throughput::Bytes(file_length));
+    group.bench_function("mixed_rows_events", |b| {
+        b.iter(|| {
+            let mut file = std::io::Cursor::new(&buffer);
+            let mut table_structs =
+                HashMap::from([(table_info.table_id, Arc::new(table_info.clone()))]);
+
+            let mut offset = 4;
+            while offset < file_length {
+                let header = get_event_header(&mut file, offset).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    offset,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                criterion::black_box(body);
+                offset = header.next_event_position as u64;
+            }
+        })
+    });
+
+    group.finish();
+}
+
+// 包括解析后保存到table_structs，每个事件都重新定义同一个table id
+fn bench_table_maps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("table_maps");
+    group.sample_size(10);
+
+    let buffer = table_maps_file(TABLE_MAP_EVENTS);
+    let file_length = buffer.len() as u64;
+
+    group.throughput(Throughput::Bytes(file_length));
+    group.bench_function("wide_table", |b| {
+        b.iter(|| {
+            let mut file = std::io::Cursor::new(&buffer);
+            let mut table_structs = HashMap::new();
+
+            let mut offset = 4;
+            while offset < file_length {
+                let header = get_event_header(&mut file, offset).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    offset,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                criterion::black_box(body);
+                offset = header.next_event_position as u64;
+            }
+        })
+    });
+
+    group.finish();
+}
+
+// 95%的行事件属于decode_only排除的表，和解码所有的表比较
+fn bench_decode_only(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_only");
+    group.sample_size(10);
+
+    let buffer = mostly_excluded_file(FILTERED_EVENTS);
+    let file_length = buffer.len() as u64;
+
+    group.throughput(Throughput::Bytes(file_length));
+    for (name, filter) in [("all_tables", false), ("one_table", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut file = std::io::Cursor::new(&buffer);
+                let mut parser = BinlogParser::new();
+                if filter {
+                    parser.decode_only(|_, table| table == "t");
+                }
+
+                let mut offset = 4;
+                while offset < file_length {
+                    let header = get_event_header(&mut file, offset).unwrap();
+                    let body = parser.get_event_body(&mut file, offset, &header).unwrap();
+                    criterion::black_box(body);
+                    offset = header.next_event_position as u64;
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// 和main一样从BinlogReader读取并解析所有事件，只比较--verify-checksums的开销
+fn bench_checksum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checksum");
+    group.sample_size(10);
+
+    let (table_info, _) = mixed();
+    let buffer = transactions_file(CHECKSUM_TRANSACTIONS);
+    let file_path = std::env::temp_dir().join("mariadb_binlog_parse_bench_checksum.bin");
+    fs::File::create(&file_path)
+        .and_then(|mut file| file.write_all(&buffer))
+        .unwrap();
+
+    let file_length = buffer.len() as u64;
+    group.throughput(Throughput::Bytes(file_length));
+    for verify in [false, true] {
+        let name = match verify {
+            true => "verify_on",
+            false => "verify_off",
+        };
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut file = BinlogReader::new(fs::File::open(&file_path).unwrap());
+                let mut table_structs =
+                    HashMap::from([(table_info.table_id, Arc::new(table_info.clone()))]);
+                let mut checksum_verifier = ChecksumVerifier::new();
+
+                let mut offset = 4;
+                while offset < file_length {
+                    let header = get_event_header(&mut file, offset).unwrap();
+                    if verify {
+                        checksum_verifier
+                            .verify(&mut file, offset, &header)
+                            .unwrap();
+                    }
+
+                    let body = get_event_body(
+                        &mut file,
+                        offset,
+                        &header,
+                        &mut table_structs,
+                        CHECKSUM_LENGTH,
+                    )
+                    .unwrap();
+                    criterion::black_box(body);
+                    offset = header.next_event_position as u64;
+                }
+            })
+        });
+    }
+
+    group.finish();
+    fs::remove_file(&file_path).unwrap();
+}
+
+fn bench_primitives(c: &mut Criterion) {
+    let mut group = c.benchmark_group("primitives");
+
+    // 1、3、4、9个字节的长度编码
+    for (name, buffer) in [
+        ("lenenc_1", vec![250u8]),
+        ("lenenc_3", vec![252, 0x34, 0x12]),
+        ("lenenc_4", vec![253, 0x56, 0x34, 0x12]),
+        ("lenenc_9", vec![254, 1, 2, 3, 4, 5, 6, 7, 8]),
+    ] {
+        group.bench_function(name, |b| b.iter(|| parse_lenenc(&buffer).unwrap()));
+    }
+
+    // 宽表的null bitmap
+    for columns in [8u64, 64, 512] {
+        let buffer = vec![0xa5u8; (columns as usize).div_ceil(8)];
+        group.bench_function(format!("bitmap_{}", columns), |b| {
+            b.iter(|| parse_bitmap(&buffer, columns))
+        });
+    }
+
+    // decimal解码时会修改缓冲区，每次都用新的副本
+    let decimal = decimal();
+    group.bench_function("bin_to_decimal", |b| {
+        b.iter_batched(
+            || decimal.clone(),
+            |mut buffer| bin_to_decimal(&mut buffer, 10, 2).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+// 逐行调用parse_column_data_for_row_event，不包括parse_row_image中null bitmap的解析
+fn bench_column_data(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_column_data_for_row_event");
+
+    for (name, (table_info, images)) in table_shapes() {
+        let bitmap_length = (table_info.number_of_columns as usize).div_ceil(8);
+        let null_bitmap = Bitmap::repeat(false, table_info.number_of_columns as usize);
+
+        group.throughput(Throughput::Bytes(images.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut offset = 0;
+                while offset < images.len() {
+                    offset += bitmap_length;
+                    let (values, skip) = parse_column_data_for_row_event(
+                        &images[offset..],
+                        &table_info,
+                        &null_bitmap,
+                        ValueOptions::default(),
+                    )
+                    .unwrap();
+                    criterion::black_box(values);
+                    offset += skip;
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_header_scan,
+    bench_full_decode,
+    bench_table_maps,
+    bench_decode_only,
+    bench_checksum,
+    bench_primitives,
+    bench_column_data
+);
+criterion_main!(benches);
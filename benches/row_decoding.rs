@@ -0,0 +1,116 @@
+use std::{collections::HashMap, io::Cursor, sync::Arc};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use mariadb_binlog_parse::model::ValueOptions;
+use mariadb_binlog_parse::parallel::ParallelDecoder;
+use mariadb_binlog_parse::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+use mariadb_binlog_parse::util::{count_row_images, parse_row_image};
+
+mod fixtures;
+use fixtures::*;
+
+fn bench_row_decoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_row_image");
+
+    for (name, (table_info, images)) in table_shapes() {
+        group.throughput(Throughput::Bytes(images.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut offset = 0;
+                while offset < images.len() {
+                    let (_, values, skip) = parse_row_image(
+                        &images[offset..],
+                        table_info.number_of_columns,
+                        &table_info,
+                        ValueOptions::default(),
+                    )
+                    .unwrap();
+                    criterion::black_box(values);
+                    offset += skip;
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// 只需要行数时不解码字段的值
+fn bench_row_counting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("count_row_images");
+
+    for (name, (table_info, images)) in table_shapes() {
+        group.throughput(Throughput::Bytes(images.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| count_row_images(&images, table_info.number_of_columns, &table_info).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+// 并行解码时一个文件中的行事件数
+const EVENTS: usize = 64;
+
+// --decode-threads，1是顺序解码
+fn bench_parallel_decoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel_decoding");
+    group.sample_size(10);
+
+    let (table_info, images) = mixed();
+    let file = rows_events_file(&table_info, &images, EVENTS);
+    let file_length = file.len() as u64;
+    group.throughput(Throughput::Bytes(file_length));
+
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let mut file = Cursor::new(&file);
+                    let mut table_structs =
+                        HashMap::from([(table_info.table_id, Arc::new(table_info.clone()))]);
+                    let mut parallel_decoder =
+                        ParallelDecoder::new(threads, &[], ValueOptions::default());
+
+                    let mut offset = 4;
+                    while offset < file_length {
+                        let header = get_event_header(&mut file, offset).unwrap();
+                        let body = match threads {
+                            1 => get_event_body(
+                                &mut file,
+                                offset,
+                                &header,
+                                &mut table_structs,
+                                CHECKSUM_LENGTH,
+                            ),
+                            _ => parallel_decoder.get_event_body(
+                                &mut file,
+                                file_length,
+                                offset,
+                                &header,
+                                &mut table_structs,
+                                CHECKSUM_LENGTH,
+                            ),
+                        }
+                        .unwrap();
+                        criterion::black_box(body);
+                        offset = header.next_event_position as u64;
+                    }
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_row_decoding,
+    bench_row_counting,
+    bench_parallel_decoding
+);
+criterion_main!(benches);
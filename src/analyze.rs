@@ -0,0 +1,576 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::ddl::DdlExtractor;
+use crate::error::find_unsupported;
+use crate::model::*;
+use crate::statement_tables::StatementTableExtractor;
+use crate::transaction::{TransactionSummary, TransactionTracker};
+
+/// 一张表被修改的行数
+#[derive(Debug, Clone, Serialize)]
+pub struct TableImpact {
+    /// `库名.表名`
+    pub table: String,
+    pub inserted_rows: u64,
+    pub updated_rows: u64,
+    pub deleted_rows: u64,
+    /// 引用这张表的语句数，需要sqlparser特性
+    #[serde(skip_serializing_if = "is_zero")]
+    pub statements: u64,
+}
+
+impl Display for TableImpact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} insert {}, update {}, delete {}",
+            self.table, self.inserted_rows, self.updated_rows, self.deleted_rows
+        )?;
+
+        if self.statements > 0 {
+            write!(f, ", statements {}", self.statements)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// 一个事务会修改的表、执行的DDL，以及影响闪回的问题
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionImpact {
+    pub gtid: String,
+    pub start_position: u64,
+    pub end_position: u64,
+    pub committed: bool,
+    /// 按第一次出现的顺序
+    pub tables: Vec<TableImpact>,
+    pub ddl: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Display for TransactionImpact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gtid={} start={} end={}",
+            self.gtid, self.start_position, self.end_position
+        )?;
+
+        if !self.committed {
+            write!(f, " NOT COMMITTED")?;
+        }
+
+        for table in &self.tables {
+            write!(f, "\n  {}", table)?;
+        }
+
+        for sql in &self.ddl {
+            write!(f, "\n  DDL: {}", sql)?;
+        }
+
+        for warning in &self.warnings {
+            write!(f, "\n  WARNING: {}", warning)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 同一个问题出现在多少个事务中
+#[derive(Debug, Clone, Serialize)]
+pub struct WarningCount {
+    pub warning: String,
+    pub transactions: u64,
+}
+
+/// 所有事务的汇总
+#[derive(Debug, Clone, Serialize)]
+pub struct ImpactReport {
+    pub transactions: u64,
+    pub tables: Vec<TableImpact>,
+    pub ddl_statements: u64,
+    /// 按推断的binlog_row_image统计的行事件数，闪回需要都是FULL
+    pub row_images: BTreeMap<String, u64>,
+    pub warnings: Vec<WarningCount>,
+    /// 没有任何问题时才可以闪回
+    pub flashback_safe: bool,
+    /// SQL解析器无法解析的语句数
+    #[serde(skip_serializing_if = "is_zero")]
+    pub unparsed_statements: u64,
+}
+
+impl Display for ImpactReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "transactions: {}", self.transactions)?;
+
+        writeln!(f, "tables: {}", self.tables.len())?;
+        for table in &self.tables {
+            writeln!(f, "  {}", table)?;
+        }
+
+        writeln!(f, "ddl statements: {}", self.ddl_statements)?;
+
+        if !self.row_images.is_empty() {
+            let row_images = self
+                .row_images
+                .iter()
+                .map(|(row_image, count)| format!("{} {}", row_image, count))
+                .collect::<Vec<String>>();
+            writeln!(f, "row images: {}", row_images.join(", "))?;
+        }
+
+        if self.unparsed_statements > 0 {
+            writeln!(f, "unparsed statements: {}", self.unparsed_statements)?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(
+                f,
+                "WARNING: {} (in {} transactions)",
+                warning.warning, warning.transactions
+            )?;
+        }
+
+        match self.flashback_safe {
+            true => write!(f, "flashback: possible"),
+            false => write!(f, "flashback: blocked"),
+        }
+    }
+}
+
+impl ImpactReport {
+    /// `--jobs`时按文件的顺序合并每个文件的汇总，表和问题的顺序和依次处理这些文件时一样
+    pub fn merge(&mut self, other: &ImpactReport) {
+        self.transactions += other.transactions;
+        self.ddl_statements += other.ddl_statements;
+        self.unparsed_statements += other.unparsed_statements;
+
+        for (row_image, count) in &other.row_images {
+            *self.row_images.entry(row_image.clone()).or_default() += count;
+        }
+
+        for table in &other.tables {
+            let total = find_table(&mut self.tables, &table.table);
+            total.inserted_rows += table.inserted_rows;
+            total.updated_rows += table.updated_rows;
+            total.deleted_rows += table.deleted_rows;
+            total.statements += table.statements;
+        }
+
+        for warning in &other.warnings {
+            match self
+                .warnings
+                .iter_mut()
+                .find(|v| v.warning == warning.warning)
+            {
+                Some(count) => count.transactions += warning.transactions,
+                None => self.warnings.push(warning.clone()),
+            }
+        }
+
+        self.flashback_safe &= other.flashback_safe;
+    }
+}
+
+/// `--analyze`，按事务统计会修改的表和行数，不输出行的内容
+/// 每个事件都要经过process，事务的边界由TransactionTracker决定
+pub struct ImpactAnalyzer {
+    transaction_tracker: TransactionTracker,
+    ddl_extractor: DdlExtractor,
+    statement_tables: StatementTableExtractor,
+    tables: Vec<TableImpact>,
+    ddl: Vec<String>,
+    warnings: Vec<String>,
+    report: ImpactReport,
+}
+
+impl Default for ImpactAnalyzer {
+    fn default() -> Self {
+        ImpactAnalyzer {
+            transaction_tracker: TransactionTracker::new(),
+            ddl_extractor: DdlExtractor::new(true),
+            statement_tables: StatementTableExtractor::new(),
+            tables: Vec::new(),
+            ddl: Vec::new(),
+            warnings: Vec::new(),
+            report: ImpactReport {
+                transactions: 0,
+                tables: Vec::new(),
+                ddl_statements: 0,
+                row_images: BTreeMap::new(),
+                warnings: Vec::new(),
+                flashback_safe: true,
+                unparsed_statements: 0,
+            },
+        }
+    }
+}
+
+impl ImpactAnalyzer {
+    pub fn new() -> ImpactAnalyzer {
+        ImpactAnalyzer::default()
+    }
+
+    /// 返回这个事件结束的事务，和TransactionTracker::process一样
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Option<TransactionImpact> {
+        let ddl_statement = self.ddl_extractor.process(header, position, body);
+
+        // GTID事件结束的是上一个事务，先把已经记录的内容交给它，事务之外的事件不计入
+        if matches!(header.type_code, 162 | 33) {
+            let impact = self
+                .transaction_tracker
+                .process(header, position, body, table_structs)
+                .map(|v| self.finish_transaction(v));
+
+            self.tables.clear();
+            self.ddl.clear();
+            self.warnings.clear();
+
+            return impact;
+        }
+
+        if let Some(statement) = ddl_statement {
+            self.ddl.push(statement.sql);
+            self.push_warning("DDL can not be flashed back".to_string());
+        }
+
+        if let Some(issue) = find_unsupported(header, position, body) {
+            self.push_warning(format!(
+                "{} {}",
+                issue.event_type.unwrap_or_default(),
+                issue.message
+            ));
+        }
+
+        if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+            self.record_rows(header.type_code, rows_event, table_structs);
+        }
+
+        for table in self.statement_tables.process(body).unwrap_or_default() {
+            find_table(&mut self.tables, &table.to_string()).statements += 1;
+        }
+        self.report.unparsed_statements = self.statement_tables.unparsed();
+
+        let summary = self
+            .transaction_tracker
+            .process(header, position, body, table_structs)?;
+
+        Some(self.finish_transaction(summary))
+    }
+
+    /// 文件结束时还没有提交的事务
+    pub fn finish(&mut self) -> Option<TransactionImpact> {
+        let summary = self.transaction_tracker.finish()?;
+
+        Some(self.finish_transaction(summary))
+    }
+
+    pub fn report(&self) -> &ImpactReport {
+        &self.report
+    }
+
+    fn record_rows(
+        &mut self,
+        type_code: u8,
+        rows_event: &EventBodyTypeCode23To25,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        let table = table_structs
+            .get(&rows_event.table_id)
+            .map(|v| format!("{}.{}", v.database_name, v.table_name))
+            .unwrap_or_else(|| format!("table_id {}", rows_event.table_id));
+
+        // MINIMAL和NOBLOB时行镜像中只有部分字段，无法还原修改前的行
+        let row_image = rows_event.row_image;
+        if !row_image.is_full() {
+            self.push_warning(format!("row image of {} is {}, not FULL", table, row_image));
+        }
+        *self
+            .report
+            .row_images
+            .entry(row_image.value.to_string())
+            .or_default() += 1;
+
+        let rows = rows_event.number_of_rows;
+        add_rows(&mut self.tables, &table, type_code, rows);
+    }
+
+    fn push_warning(&mut self, warning: String) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    // 把记录的内容和事务的概要合并，并计入汇总
+    fn finish_transaction(&mut self, summary: TransactionSummary) -> TransactionImpact {
+        let impact = TransactionImpact {
+            gtid: summary.gtid,
+            start_position: summary.start_position,
+            end_position: summary.end_position,
+            committed: summary.committed,
+            tables: std::mem::take(&mut self.tables),
+            ddl: std::mem::take(&mut self.ddl),
+            warnings: std::mem::take(&mut self.warnings),
+        };
+
+        self.report.transactions += 1;
+        self.report.ddl_statements += impact.ddl.len() as u64;
+
+        for table in &impact.tables {
+            let total = find_table(&mut self.report.tables, &table.table);
+            total.inserted_rows += table.inserted_rows;
+            total.updated_rows += table.updated_rows;
+            total.deleted_rows += table.deleted_rows;
+            total.statements += table.statements;
+        }
+
+        for warning in &impact.warnings {
+            match self
+                .report
+                .warnings
+                .iter_mut()
+                .find(|v| &v.warning == warning)
+            {
+                Some(count) => count.transactions += 1,
+                None => self.report.warnings.push(WarningCount {
+                    warning: warning.clone(),
+                    transactions: 1,
+                }),
+            }
+            self.report.flashback_safe = false;
+        }
+
+        impact
+    }
+}
+
+fn add_rows(tables: &mut Vec<TableImpact>, table: &str, type_code: u8, rows: u64) {
+    let impact = find_table(tables, table);
+
+    match type_code {
+        23 | 30 => impact.inserted_rows += rows,
+        24 | 31 => impact.updated_rows += rows,
+        _ => impact.deleted_rows += rows,
+    }
+}
+
+fn find_table<'a>(tables: &'a mut Vec<TableImpact>, table: &str) -> &'a mut TableImpact {
+    let index = match tables.iter().position(|v| v.table == table) {
+        Some(index) => index,
+        None => {
+            tables.push(TableImpact {
+                table: table.to_string(),
+                inserted_rows: 0,
+                updated_rows: 0,
+                deleted_rows: 0,
+                statements: 0,
+            });
+            tables.len() - 1
+        }
+    };
+
+    &mut tables[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    fn gtid(sequence: u64, flags: u8) -> EventBuilder {
+        EventBuilder::from_body(&GtidEventBuilder::new(sequence).flags(flags))
+    }
+
+    fn table(name: &str, table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new("shop", name)
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Long, &[])
+    }
+
+    fn row(id: i64, value: i64) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(id), ColumnValue::Int(value)]
+    }
+
+    // 两个DML事务之间有一个ALTER，最后一个事务的update是MINIMAL
+    fn fixture() -> Vec<u8> {
+        let orders = table("orders", 7);
+        let customers = table("customers", 8);
+
+        BinlogBuilder::new()
+            .push(gtid(1, 0))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&orders)
+            .event(
+                &RowsEventBuilder::insert(&orders)
+                    .row(row(1, 10))
+                    .row(row(2, 20)),
+            )
+            .event(&RowsEventBuilder::update(&orders).update_row(row(1, 10), row(1, 11)))
+            .push(xid(1))
+            .push(gtid(2, 1))
+            .event(&QueryEventBuilder::new(
+                "shop",
+                "ALTER TABLE orders ADD COLUMN note INT",
+            ))
+            .push(gtid(3, 0))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&orders)
+            .event(&customers)
+            .event(&RowsEventBuilder::delete(&customers).row(row(5, 50)))
+            .event(&RowsEventBuilder::insert(&orders).row(row(3, 30)))
+            .push(xid(1))
+            .push(gtid(4, 0))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&orders)
+            .event(
+                &RowsEventBuilder::update(&orders)
+                    .columns_used(&[true, false])
+                    .columns_used_for_update(&[false, true])
+                    .update_row(row(3, 30), row(3, 31)),
+            )
+            .push(xid(1))
+            .build()
+    }
+
+    // 分析[start, end)之间的事件
+    fn analyze(binlog: &[u8], start: u64, end: u64) -> (Vec<TransactionImpact>, ImpactReport) {
+        let mut analyzer = ImpactAnalyzer::new();
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut table_structs = HashMap::new();
+        let mut impacts = Vec::new();
+        let mut position = start;
+        while position < end {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            impacts.extend(analyzer.process(&header, position, body.as_ref(), &table_structs));
+            position = header.next_event_position as u64;
+        }
+        impacts.extend(analyzer.finish());
+
+        (impacts, analyzer.report().clone())
+    }
+
+    #[test]
+    fn mixed_dml_and_alter() {
+        let binlog = fixture();
+        let (impacts, report) = analyze(&binlog, 4, binlog.len() as u64);
+
+        let summary = impacts
+            .iter()
+            .map(|v| {
+                (
+                    v.gtid.as_str(),
+                    v.tables.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+                    v.ddl.clone(),
+                    v.warnings.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // 开启sqlparser时ALTER语句也会计入它引用的表
+        let altered_tables = if cfg!(feature = "sqlparser") {
+            vec!["shop.orders insert 0, update 0, delete 0, statements 1".to_string()]
+        } else {
+            vec![]
+        };
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "0-1-1",
+                    vec!["shop.orders insert 2, update 1, delete 0".to_string()],
+                    vec![],
+                    vec![],
+                ),
+                (
+                    "0-1-2",
+                    altered_tables,
+                    vec!["ALTER TABLE orders ADD COLUMN note INT".to_string()],
+                    vec!["DDL can not be flashed back".to_string()],
+                ),
+                (
+                    "0-1-3",
+                    vec![
+                        "shop.customers insert 0, update 0, delete 1".to_string(),
+                        "shop.orders insert 1, update 0, delete 0".to_string(),
+                    ],
+                    vec![],
+                    vec![],
+                ),
+                (
+                    "0-1-4",
+                    vec!["shop.orders insert 0, update 1, delete 0".to_string()],
+                    vec![],
+                    vec!["row image of shop.orders is MINIMAL, not FULL".to_string()],
+                ),
+            ]
+        );
+        assert!(impacts.iter().all(|v| v.committed));
+
+        let orders_statements = if cfg!(feature = "sqlparser") {
+            ", statements 1"
+        } else {
+            ""
+        };
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "transactions: 4
+tables: 2
+  shop.orders insert 3, update 2, delete 0{orders_statements}
+  shop.customers insert 0, update 0, delete 1
+ddl statements: 1
+row images: FULL 4, MINIMAL 1
+WARNING: DDL can not be flashed back (in 1 transactions)
+WARNING: row image of shop.orders is MINIMAL, not FULL (in 1 transactions)
+flashback: blocked"
+            )
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["flashback_safe"], false);
+        assert_eq!(json["tables"][0]["inserted_rows"], 3);
+        assert!(json.get("unparsed_statements").is_none());
+    }
+
+    #[test]
+    fn merged_reports_match_a_single_pass() {
+        let binlog = fixture();
+        let length = binlog.len() as u64;
+        let (impacts, single) = analyze(&binlog, 4, length);
+
+        // `--jobs`时每个文件单独分析，这里在第三个事务之前分开
+        let split = impacts[2].start_position;
+        let (_, mut merged) = analyze(&binlog, 4, split);
+        merged.merge(&analyze(&binlog, split, length).1);
+
+        assert_eq!(merged.to_string(), single.to_string());
+    }
+}
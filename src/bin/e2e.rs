@@ -0,0 +1,430 @@
+//! 用testcontainers启动MariaDB，执行e2e/workload.json中的语句，把生成的binlog复制出来解析，
+//! 和workload中写的期望结果比较
+//!
+//! 每个用例之前执行`FLUSH BINARY LOGS`，所以每个用例在单独的binlog文件中，失败时可以直接用这个文件复现。
+//! 需要docker，用`cargo run --bin e2e --features e2e`运行
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use mysql::prelude::Queryable;
+use mysql::{Conn, OptsBuilder, Row};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use testcontainers::core::{ExecCommand, IntoContainerPort, WaitFor};
+use testcontainers::runners::SyncRunner;
+use testcontainers::{Container, GenericImage, ImageExt};
+
+use mariadb_binlog_parse::checksum::ChecksumVerifier;
+use mariadb_binlog_parse::model::{EventBodyTypeCode23To25, EventBodyTypeSkip, MyError};
+use mariadb_binlog_parse::reader::BinlogReader;
+use mariadb_binlog_parse::service::{
+    get_event_body, get_event_header, update_checksum_length, CHECKSUM_LENGTH,
+};
+
+type BoxedError = Box<dyn std::error::Error>;
+
+const DEFAULT_IMAGE: &str = "mariadb:11.4";
+
+const DEFAULT_WORKLOAD: &str = "e2e/workload.json";
+
+const ROOT_PASSWORD: &str = "e2e";
+
+// 镜像中的datadir，binlog也在这里
+const DATA_DIR: &str = "/var/lib/mysql";
+
+// 第一次连接之前最多等待的秒数
+const CONNECT_ATTEMPTS: u32 = 60;
+
+#[derive(Deserialize)]
+struct Workload {
+    columns: Vec<ColumnCase>,
+    statements: Vec<StatementCase>,
+}
+
+/// 一种字段类型，values是插入的SQL字面量和json输出中期望的值，同一个用例中的值不能相同
+#[derive(Deserialize)]
+struct ColumnCase {
+    name: String,
+    column: String,
+    values: Vec<(String, Value)>,
+}
+
+/// 一组语句，events是这些语句所在的binlog中必须出现的事件类型，
+/// column_names是e2e库中的表在table map中的字段名，primary_keys是主键的(字段序号, 前缀长度)，
+/// 都是binlog_row_metadata=FULL时才有
+#[derive(Deserialize)]
+struct StatementCase {
+    name: String,
+    sql: Vec<String>,
+    events: Vec<u8>,
+    #[serde(default)]
+    column_names: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    primary_keys: HashMap<String, Vec<(usize, u64)>>,
+}
+
+enum Expected {
+    /// e2e库中这个表的所有行，格式和json输出中行事件的rows一样
+    Rows { table: String, rows: Vec<Value> },
+    Events {
+        events: Vec<u8>,
+        column_names: HashMap<String, Vec<String>>,
+        primary_keys: HashMap<String, Vec<(usize, u64)>>,
+    },
+}
+
+struct Case {
+    name: String,
+    sql: Vec<String>,
+    expected: Expected,
+}
+
+impl ColumnCase {
+    /// 建一个只有主键和这个类型字段的表，逐行插入，把最后一行改成第一个值，最后删除所有行
+    fn to_case(&self) -> Case {
+        let table = format!("t_{}", self.name);
+        let mut sql = vec![format!(
+            "CREATE TABLE e2e.{} (id INT PRIMARY KEY, v {})",
+            table, self.column
+        )];
+        let mut rows = Vec::new();
+        let mut current = Vec::new();
+
+        for (i, (literal, value)) in self.values.iter().enumerate() {
+            let id = i + 1;
+            sql.push(format!(
+                "INSERT INTO e2e.{} VALUES ({}, {})",
+                table, id, literal
+            ));
+            rows.push(json!({"before": null, "after": [id, value]}));
+            current.push(value.clone());
+        }
+
+        if self.values.len() > 1 {
+            let id = self.values.len();
+            let (literal, value) = &self.values[0];
+            sql.push(format!(
+                "UPDATE e2e.{} SET v = {} WHERE id = {}",
+                table, literal, id
+            ));
+            rows.push(json!({"before": [id, current[id - 1]], "after": [id, value]}));
+            current[id - 1] = value.clone();
+        }
+
+        sql.push(format!("DELETE FROM e2e.{}", table));
+        for (i, value) in current.iter().enumerate() {
+            rows.push(json!({"before": [i + 1, value], "after": null}));
+        }
+
+        Case {
+            name: self.name.clone(),
+            sql,
+            expected: Expected::Rows { table, rows },
+        }
+    }
+}
+
+impl StatementCase {
+    fn to_case(&self) -> Case {
+        Case {
+            name: self.name.clone(),
+            sql: self.sql.clone(),
+            expected: Expected::Events {
+                events: self.events.clone(),
+                column_names: self.column_names.clone(),
+                primary_keys: self.primary_keys.clone(),
+            },
+        }
+    }
+}
+
+struct Options {
+    workload: String,
+    image: String,
+    fixture_dir: PathBuf,
+}
+
+fn parse_args() -> Result<Options, BoxedError> {
+    let mut options = Options {
+        workload: DEFAULT_WORKLOAD.to_string(),
+        image: DEFAULT_IMAGE.to_string(),
+        fixture_dir: std::env::temp_dir().join("mariadb_binlog_parse_e2e"),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--image" | "--fixture-dir" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| MyError(format!("`{}` requires a value", arg)))?;
+                match arg.as_str() {
+                    "--image" => options.image = value,
+                    _ => options.fixture_dir = PathBuf::from(value),
+                }
+            }
+            option if option.starts_with("--") => {
+                return Err(Box::new(MyError(format!("unknown option `{}`", option))))
+            }
+            _ => options.workload = arg,
+        }
+    }
+
+    Ok(options)
+}
+
+fn start_server(image: &str) -> Result<Container<GenericImage>, BoxedError> {
+    let (name, tag) = image.split_once(':').unwrap_or((image, "latest"));
+
+    let container = GenericImage::new(name, tag)
+        .with_exposed_port(3306.tcp())
+        .with_wait_for(WaitFor::message_on_stderr("ready for connections"))
+        .with_env_var("MARIADB_ROOT_PASSWORD", ROOT_PASSWORD)
+        .with_cmd([
+            "--log-bin=mysql-bin",
+            "--binlog-format=ROW",
+            "--server-id=1",
+        ])
+        .start()?;
+
+    Ok(container)
+}
+
+// 镜像初始化时会先启动一次不监听端口的服务端，日志出现之后端口还不一定可以连接
+fn connect(container: &Container<GenericImage>) -> Result<Conn, BoxedError> {
+    let opts = OptsBuilder::new()
+        .ip_or_hostname(Some(container.get_host()?.to_string()))
+        .tcp_port(container.get_host_port_ipv4(3306.tcp())?)
+        .user(Some("root"))
+        .pass(Some(ROOT_PASSWORD));
+
+    let mut attempts = 0;
+    loop {
+        match Conn::new(opts.clone()) {
+            Ok(conn) => return Ok(conn),
+            Err(_e) if attempts < CONNECT_ATTEMPTS => {
+                attempts += 1;
+                thread::sleep(Duration::from_secs(1));
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+}
+
+/// 执行用例的语句，返回它所在的binlog文件名
+fn run_case(conn: &mut Conn, case: &Case) -> Result<String, BoxedError> {
+    // 用例中可能改成了STATEMENT
+    conn.query_drop("SET SESSION binlog_format = 'ROW'")?;
+    conn.query_drop("FLUSH BINARY LOGS")?;
+
+    let status: Option<Row> = conn.query_first("SHOW MASTER STATUS")?;
+    let file: String = status
+        .and_then(|row| row.get(0))
+        .ok_or_else(|| MyError("binary log is not enabled on the server".to_string()))?;
+
+    for sql in &case.sql {
+        conn.query_drop(sql)
+            .map_err(|e| MyError(format!("`{}` failed in case `{}`: {}", sql, case.name, e)))?;
+    }
+
+    Ok(file)
+}
+
+fn copy_binlog(
+    container: &Container<GenericImage>,
+    file: &str,
+    path: &Path,
+) -> Result<(), BoxedError> {
+    let mut result = container.exec(ExecCommand::new([
+        "cat".to_string(),
+        format!("{}/{}", DATA_DIR, file),
+    ]))?;
+
+    fs::write(path, result.stdout_to_vec()?)?;
+
+    Ok(())
+}
+
+/// 解析整个文件，所有事件都要能解析、校验和正确，再和用例期望的结果比较
+fn check(case: &Case, path: &Path) -> Result<(), BoxedError> {
+    let file_length = fs::metadata(path)?.len();
+    let mut file = BinlogReader::new(fs::File::open(path)?);
+    let mut table_structs = HashMap::new();
+    let mut checksum_verifier = ChecksumVerifier::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+
+    let mut type_codes = Vec::new();
+    let mut rows = Vec::new();
+
+    let mut offset = 4;
+    while offset < file_length {
+        let header = get_event_header(&mut file, offset)?;
+        checksum_verifier.verify(&mut file, offset, &header)?;
+
+        let body = get_event_body(
+            &mut file,
+            offset,
+            &header,
+            &mut table_structs,
+            checksum_length,
+        )?;
+        update_checksum_length(&mut checksum_length, body.as_ref());
+
+        if body.downcast_ref::<EventBodyTypeSkip>().is_some() {
+            return Err(Box::new(MyError(format!(
+                "event type {} at {} is not supported",
+                header.type_code, offset
+            ))));
+        }
+
+        if let (Some(rows_event), Expected::Rows { table, .. }) = (
+            body.downcast_ref::<EventBodyTypeCode23To25>(),
+            &case.expected,
+        ) {
+            let is_case_table = table_structs
+                .get(&rows_event.table_id)
+                .is_some_and(|t| t.database_name == "e2e" && t.table_name == *table);
+            if is_case_table {
+                for row in &rows_event.rows {
+                    rows.push(serde_json::to_value(row)?);
+                }
+            }
+        }
+
+        type_codes.push(header.type_code);
+        offset = header.next_event_position as u64;
+    }
+
+    match &case.expected {
+        Expected::Rows { rows: expected, .. } => {
+            if let Some(i) =
+                (0..expected.len().max(rows.len())).find(|&i| rows.get(i) != expected.get(i))
+            {
+                return Err(Box::new(MyError(format!(
+                    "row {} is {}, expected {}",
+                    i,
+                    rows.get(i).unwrap_or(&Value::Null),
+                    expected.get(i).unwrap_or(&Value::Null)
+                ))));
+            }
+        }
+        Expected::Events {
+            events: expected,
+            column_names,
+            primary_keys,
+        } => {
+            let missing: Vec<&u8> = expected
+                .iter()
+                .filter(|t| !type_codes.contains(t))
+                .collect();
+            if !missing.is_empty() {
+                return Err(Box::new(MyError(format!(
+                    "event types {:?} are missing, the binlog has {:?}",
+                    missing, type_codes
+                ))));
+            }
+
+            for (table, expected) in column_names {
+                let names = table_structs
+                    .values()
+                    .find(|t| t.database_name == "e2e" && t.table_name == *table)
+                    .map(|t| t.column_names.clone());
+                if names.as_ref() != Some(&Some(expected.clone())) {
+                    return Err(Box::new(MyError(format!(
+                        "the column names of {} are {:?}, expected {:?}",
+                        table, names, expected
+                    ))));
+                }
+            }
+
+            for (table, expected) in primary_keys {
+                // 没有前缀索引时primary_key_prefixes为None，前缀长度都是0
+                let primary_key = table_structs
+                    .values()
+                    .find(|t| t.database_name == "e2e" && t.table_name == *table)
+                    .and_then(|t| {
+                        let prefixes = t.primary_key_prefixes.clone().unwrap_or_default();
+                        t.primary_key.as_ref().map(|columns| {
+                            columns
+                                .iter()
+                                .enumerate()
+                                .map(|(i, index)| (*index, prefixes.get(i).copied().unwrap_or(0)))
+                                .collect::<Vec<(usize, u64)>>()
+                        })
+                    });
+                if primary_key.as_ref() != Some(expected) {
+                    return Err(Box::new(MyError(format!(
+                        "the primary key of {} is {:?}, expected {:?}",
+                        table, primary_key, expected
+                    ))));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), BoxedError> {
+    let options = parse_args()?;
+
+    let workload: Workload = serde_json::from_str(&fs::read_to_string(&options.workload)?)?;
+    let cases: Vec<Case> = workload
+        .columns
+        .iter()
+        .map(ColumnCase::to_case)
+        .chain(workload.statements.iter().map(StatementCase::to_case))
+        .collect();
+
+    let container = start_server(&options.image)?;
+    let mut conn = connect(&container)?;
+
+    conn.query_drop("CREATE DATABASE e2e")?;
+    // 解析时TIMESTAMP按东八区输出，这样期望的值和插入的字面量一样
+    conn.query_drop("SET time_zone = '+08:00'")?;
+
+    let mut files = Vec::new();
+    for case in &cases {
+        files.push(run_case(&mut conn, case)?);
+    }
+    // 最后一个用例的文件也以rotate结束
+    conn.query_drop("FLUSH BINARY LOGS")?;
+
+    fs::create_dir_all(&options.fixture_dir)?;
+
+    let mut failed = 0;
+    for (case, file) in cases.iter().zip(&files) {
+        let path = options.fixture_dir.join(format!("{}.{}", case.name, file));
+        copy_binlog(&container, file, &path)?;
+
+        match check(case, &path) {
+            Ok(()) => println!("ok {}", case.name),
+            Err(e) => {
+                failed += 1;
+                println!("FAILED {} ({}): {}", case.name, path.display(), e);
+            }
+        }
+    }
+
+    println!(
+        "{} cases, {} failed, binlogs are in {}",
+        cases.len(),
+        failed,
+        options.fixture_dir.display()
+    );
+
+    if failed > 0 {
+        return Err(Box::new(MyError(format!(
+            "{} of {} cases failed",
+            failed,
+            cases.len()
+        ))));
+    }
+
+    Ok(())
+}
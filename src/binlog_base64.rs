@@ -0,0 +1,1074 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Write},
+};
+
+use base64::prelude::*;
+
+use crate::cli::FromBase64Options;
+use crate::collation;
+use crate::ddl::DdlStatement;
+use crate::diagnostic::Diagnostic;
+use crate::model::*;
+use crate::pipeline::{EventPipeline, EventWriter};
+use crate::service::get_event_header;
+use crate::style::ColorChoice;
+use crate::util::bin_to_decimal;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+// mysqlbinlog输出base64时每行76个字符
+const BASE64_LINE_LENGTH: usize = 76;
+
+// rows event的flags中表示语句结束的标志位
+const STMT_END_F: u16 = 0x0001;
+
+// query事件flags2中mysqlbinlog会输出的几个标志位
+const OPTION_AUTO_IS_NULL: u32 = 1 << 14;
+const OPTION_NOT_AUTOCOMMIT: u32 = 1 << 19;
+const OPTION_NO_FOREIGN_KEY_CHECKS: u32 = 1 << 26;
+const OPTION_RELAXED_UNIQUE_CHECKS: u32 = 1 << 27;
+
+// GTID事件的flags，单独的语句没有事务，XA事务由XA语句开始
+const FL_STANDALONE: u8 = 0x01;
+const FL_PREPARED_XA: u8 = 0x40;
+const FL_COMPLETED_XA: u8 = 0x80;
+
+// 已经输出过的会话变量，和mysqlbinlog一样只在变化时输出
+#[derive(Default)]
+struct SessionContext {
+    database: Option<String>,
+    flags2: Option<u32>,
+    sql_mode: Option<u64>,
+    auto_increment: Option<(u16, u16)>,
+    charset: Option<(u16, u16, u16)>,
+    time_zone: Option<String>,
+    lc_time_names: Option<u16>,
+    // Some(None)是DEFAULT
+    charset_database: Option<Option<u16>>,
+    gtid_domain_id: Option<u32>,
+    server_id: Option<u32>,
+}
+
+/// 生成和mysqlbinlog相同的`BINLOG '...'`语句，可以直接通过mysql客户端重放行事件
+/// 先输出format description事件，之后每组table map + rows event输出为一个BINLOG语句
+/// 事件的原始字节（包括header和校验和）原样编码，不做任何修改
+///
+/// `--replayable`时还和mysqlbinlog一样输出query事件的SQL和它之前的会话变量（时间、sql_mode、字符集等，
+/// 只在变化时输出），intvar、rand和user var事件的SET语句，GTID开始事务，XID提交事务，
+/// 整个输出可以直接交给mysql客户端执行
+#[derive(Default)]
+pub struct BinlogBase64Writer {
+    pending: String,
+    // 缓存中第一个事件的位置
+    pending_position: Option<u64>,
+    replayable: bool,
+    session: SessionContext,
+    // GTID已经开始了事务，之后的BEGIN不再输出
+    in_transaction: bool,
+}
+
+impl BinlogBase64Writer {
+    pub fn new() -> BinlogBase64Writer {
+        BinlogBase64Writer::default()
+    }
+
+    pub fn replayable(&mut self, replayable: bool) -> &mut BinlogBase64Writer {
+        self.replayable = replayable;
+        self
+    }
+
+    pub fn header(&self) -> String {
+        [
+            "/*!50530 SET @@SESSION.PSEUDO_SLAVE_MODE=1*/;",
+            "/*!40019 SET @@session.max_insert_delayed_threads=0*/;",
+            "/*!50003 SET @OLD_COMPLETION_TYPE=@@COMPLETION_TYPE,COMPLETION_TYPE=0*/;",
+            "DELIMITER /*!*/;",
+            "",
+        ]
+        .join("\n")
+    }
+
+    pub fn footer(&self) -> String {
+        [
+            "DELIMITER ;",
+            "# End of log file",
+            "ROLLBACK /* added by mysqlbinlog */;",
+            "/*!50003 SET COMPLETION_TYPE=@OLD_COMPLETION_TYPE*/;",
+            "/*!50530 SET @@SESSION.PSEUDO_SLAVE_MODE=0*/;",
+            "",
+        ]
+        .join("\n")
+    }
+
+    /// raw_event是包含header的完整事件，body是解析之后的事件
+    /// 返回需要输出的内容，table map事件会先缓存起来，等到语句结束的rows event再一起输出
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        raw_event: &[u8],
+    ) -> Option<String> {
+        if self.replayable {
+            if let Some(statement) = self.session_statement(header, body, raw_event) {
+                return Some(format!("# at {}\n{}", position, statement));
+            }
+        }
+
+        match header.type_code {
+            15 => Some(format!(
+                "# at {}\nBINLOG '\n{}'/*!*/;\n",
+                position,
+                encode_event(raw_event)
+            )),
+            19 => {
+                self.pending_position.get_or_insert(position);
+                self.pending.push_str(&encode_event(raw_event));
+                None
+            }
+            23..=25 | 30..=32 => {
+                self.pending_position.get_or_insert(position);
+                self.pending.push_str(&encode_event(raw_event));
+
+                let flags = u16::from_le_bytes([raw_event[19 + 6], raw_event[19 + 7]]);
+                if flags & STMT_END_F == 0 {
+                    return None;
+                }
+
+                let result = format!(
+                    "# at {}\nBINLOG '\n{}'/*!*/;\n",
+                    self.pending_position.take().unwrap_or(position),
+                    self.pending
+                );
+                self.pending.clear();
+
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+
+    // --replayable时query、intvar、rand、user var、GTID和XID事件对应的语句，没有时为None
+    fn session_statement(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+        raw_event: &[u8],
+    ) -> Option<String> {
+        if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+            return self.query(header, query_event);
+        }
+
+        if let Some(intvar) = body.downcast_ref::<EventBodyTypeCode5>() {
+            let name = match intvar.data_type {
+                1 => "LAST_INSERT_ID",
+                _ => "INSERT_ID",
+            };
+            return Some(format!("SET {}={}/*!*/;\n", name, intvar.value));
+        }
+
+        if let Some(rand) = body.downcast_ref::<EventBodyTypeCode13>() {
+            return Some(format!(
+                "SET @@RAND_SEED1={}, @@RAND_SEED2={}/*!*/;\n",
+                rand.first_seed, rand.second_seed
+            ));
+        }
+
+        if let Some(user_var) = body.downcast_ref::<EventBodyTypeCode14>() {
+            return Some(format!(
+                "SET @`{}`:={}/*!*/;\n",
+                user_var.name_of_user_variable.replace('`', "``"),
+                user_variable_value(user_var, raw_event).unwrap_or_else(|| "NULL".to_string())
+            ));
+        }
+
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            return Some(self.gtid(header, gtid_event));
+        }
+
+        if let Some(xid_event) = body.downcast_ref::<EventBodyTypeCode16>() {
+            self.in_transaction = false;
+            return Some(format!(
+                "COMMIT/* xid={} *//*!*/;\n",
+                xid_event.xid_transaction_number
+            ));
+        }
+
+        None
+    }
+
+    fn gtid(&mut self, header: &EventHeader, gtid_event: &EventBodyTypeCode162) -> String {
+        let mut result = String::new();
+
+        if self.session.gtid_domain_id != Some(gtid_event.replication_domain_id) {
+            self.session.gtid_domain_id = Some(gtid_event.replication_domain_id);
+            result.push_str(&format!(
+                "/*!100001 SET @@session.gtid_domain_id={}*//*!*/;\n",
+                gtid_event.replication_domain_id
+            ));
+        }
+        if self.session.server_id != Some(header.server_id) {
+            self.session.server_id = Some(header.server_id);
+            result.push_str(&format!(
+                "/*!100001 SET @@session.server_id={}*//*!*/;\n",
+                header.server_id
+            ));
+        }
+        result.push_str(&format!(
+            "/*!100001 SET @@session.gtid_seq_no={}*//*!*/;\n",
+            gtid_event.gtid_sequence
+        ));
+
+        self.in_transaction =
+            gtid_event.flags & (FL_STANDALONE | FL_PREPARED_XA | FL_COMPLETED_XA) == 0;
+        if self.in_transaction {
+            result.push_str("START TRANSACTION\n/*!*/;\n");
+        }
+
+        result
+    }
+
+    fn query(&mut self, header: &EventHeader, query_event: &EventBodyTypeCode2) -> Option<String> {
+        let sql = query_event.sql.trim();
+        let is_begin = sql.eq_ignore_ascii_case("BEGIN");
+
+        // MariaDB的GTID已经开始了事务
+        if is_begin && self.in_transaction {
+            return None;
+        }
+        self.in_transaction = match is_begin {
+            true => true,
+            false => self.in_transaction && !is_transaction_end(sql),
+        };
+
+        let mut result = String::new();
+
+        let database = &query_event.database_name;
+        if !database.is_empty() && self.session.database.as_ref() != Some(database) {
+            self.session.database = Some(database.clone());
+            result.push_str(&format!("use `{}`/*!*/;\n", database.replace('`', "``")));
+        }
+
+        // 带微秒时和mysqlbinlog一样输出小数
+        let microseconds = query_event
+            .typed_status_variables
+            .iter()
+            .find_map(|v| match v {
+                StatusVariable::MariadbHrNow(v) | StatusVariable::Microseconds(v) => Some(*v),
+                _ => None,
+            });
+        match microseconds {
+            Some(microseconds) => result.push_str(&format!(
+                "SET TIMESTAMP={}.{:06}/*!*/;\n",
+                header.timestamp, microseconds
+            )),
+            None => result.push_str(&format!("SET TIMESTAMP={}/*!*/;\n", header.timestamp)),
+        }
+        result.push_str(&format!(
+            "SET @@session.pseudo_thread_id={}/*!*/;\n",
+            query_event.id_of_thread
+        ));
+
+        result.push_str(&self.session_variables(&query_event.typed_status_variables));
+        result.push_str(&format!("{}\n/*!*/;\n", query_event.sql));
+
+        Some(result)
+    }
+
+    // 没有auto_increment、lc_time_names和charset_database时是服务端的默认值
+    fn session_variables(&mut self, status_variables: &[StatusVariable]) -> String {
+        let mut result = String::new();
+        let session = &mut self.session;
+
+        let mut auto_increment = (1, 1);
+        let mut lc_time_names = 0;
+        let mut charset_database = None;
+
+        for status_variable in status_variables {
+            match status_variable {
+                StatusVariable::Flags2(flags2) if session.flags2 != Some(*flags2) => {
+                    session.flags2 = Some(*flags2);
+                    let on_off = |v: bool| if v { 1 } else { 0 };
+                    result.push_str(&format!(
+                        "SET @@session.foreign_key_checks={}, @@session.sql_auto_is_null={}, @@session.unique_checks={}, @@session.autocommit={}/*!*/;\n",
+                        on_off(flags2 & OPTION_NO_FOREIGN_KEY_CHECKS == 0),
+                        on_off(flags2 & OPTION_AUTO_IS_NULL != 0),
+                        on_off(flags2 & OPTION_RELAXED_UNIQUE_CHECKS == 0),
+                        on_off(flags2 & OPTION_NOT_AUTOCOMMIT == 0)
+                    ));
+                }
+                StatusVariable::SqlMode(sql_mode) if session.sql_mode != Some(*sql_mode) => {
+                    session.sql_mode = Some(*sql_mode);
+                    result.push_str(&format!("SET @@session.sql_mode={}/*!*/;\n", sql_mode));
+                }
+                StatusVariable::AutoIncrement { increment, offset } => {
+                    auto_increment = (*increment, *offset);
+                }
+                StatusVariable::Charset {
+                    client,
+                    connection,
+                    server,
+                } if session.charset != Some((*client, *connection, *server)) => {
+                    session.charset = Some((*client, *connection, *server));
+                    // mysql客户端的\C命令切换客户端的字符集
+                    if let Some(collation) = collation::lookup(*client as u64) {
+                        result.push_str(&format!("/*!\\C {} *//*!*/;\n", collation.charset));
+                    }
+                    result.push_str(&format!(
+                        "SET @@session.character_set_client={},@@session.collation_connection={},@@session.collation_server={}/*!*/;\n",
+                        client, connection, server
+                    ));
+                }
+                StatusVariable::TimeZone(time_zone)
+                    if session.time_zone.as_ref() != Some(time_zone) =>
+                {
+                    session.time_zone = Some(time_zone.clone());
+                    result.push_str(&format!(
+                        "SET @@session.time_zone='{}'/*!*/;\n",
+                        time_zone.replace('\'', "''")
+                    ));
+                }
+                StatusVariable::LcTimeNames { code, .. } => lc_time_names = *code,
+                StatusVariable::CharsetDatabase(id) => charset_database = Some(*id),
+                _ => {}
+            }
+        }
+
+        if session.auto_increment != Some(auto_increment) {
+            session.auto_increment = Some(auto_increment);
+            result.push_str(&format!(
+                "SET @@session.auto_increment_increment={}, @@session.auto_increment_offset={}/*!*/;\n",
+                auto_increment.0, auto_increment.1
+            ));
+        }
+        if session.lc_time_names != Some(lc_time_names) {
+            session.lc_time_names = Some(lc_time_names);
+            result.push_str(&format!(
+                "SET @@session.lc_time_names={}/*!*/;\n",
+                lc_time_names
+            ));
+        }
+        if session.charset_database != Some(charset_database) {
+            session.charset_database = Some(charset_database);
+            match charset_database {
+                Some(id) => {
+                    result.push_str(&format!("SET @@session.collation_database={}/*!*/;\n", id))
+                }
+                None => result.push_str("SET @@session.collation_database=DEFAULT/*!*/;\n"),
+            }
+        }
+
+        result
+    }
+
+    /// DDL和mysqlbinlog一样以SQL文本输出
+    pub fn ddl(&self, statement: &DdlStatement) -> String {
+        let mut result = format!("# at {}\n", statement.position);
+
+        if !statement.database_name.is_empty() {
+            result.push_str(&format!("use `{}`/*!*/;\n", statement.database_name));
+        }
+
+        result.push_str(&format!(
+            "SET TIMESTAMP={}/*!*/;\n{}\n/*!*/;\n",
+            statement.timestamp, statement.sql
+        ));
+
+        result
+    }
+}
+
+// 非事务引擎的事务以COMMIT或者ROLLBACK（不是ROLLBACK TO SAVEPOINT）结束
+fn is_transaction_end(sql: &str) -> bool {
+    sql.eq_ignore_ascii_case("COMMIT") || sql.eq_ignore_ascii_case("ROLLBACK")
+}
+
+/// user var事件的值，解析后的value是有损的字符串，按原始字节重新取出
+/// 字符串和mysqlbinlog一样输出为带字符集的十六进制，NULL和不认识的类型为None
+fn user_variable_value(user_var: &EventBodyTypeCode14, raw_event: &[u8]) -> Option<String> {
+    let length = user_var.length_of_value? as usize;
+    // header之后是名字的长度、名字、null标志、类型、collation和值的长度
+    let offset = 19 + 4 + user_var.length_of_user_variable_name as usize + 1 + 1 + 4 + 4;
+    let value = raw_event.get(offset..offset + length)?;
+    let unsigned = user_var.flags.unwrap_or(0) & 0x01 != 0;
+
+    match user_var.variable_type? {
+        0 => {
+            let collation = collation::lookup(user_var.collation_number? as u64)?;
+            let hex = value
+                .iter()
+                .map(|v| format!("{:02x}", v))
+                .collect::<String>();
+            Some(match hex.is_empty() {
+                true => format!("_{} '' COLLATE `{}`", collation.charset, collation.name),
+                false => format!(
+                    "_{} 0x{} COLLATE `{}`",
+                    collation.charset, hex, collation.name
+                ),
+            })
+        }
+        1 => Some(f64::from_le_bytes(value.try_into().ok()?).to_string()),
+        2 if unsigned => Some(u64::from_le_bytes(value.try_into().ok()?).to_string()),
+        2 => Some(i64::from_le_bytes(value.try_into().ok()?).to_string()),
+        4 => {
+            let (precision, decimals) = (*value.first()? as usize, *value.get(1)? as usize);
+            let mut buffer = value.get(2..)?.to_vec();
+            bin_to_decimal(&mut buffer, precision, decimals)
+                .ok()
+                .map(|v| v.0)
+        }
+        _ => None,
+    }
+}
+
+/// 每个事件单独编码（带有=补齐），服务端可以据此把多段base64拆开
+fn encode_event(raw_event: &[u8]) -> String {
+    let encoded = BASE64_STANDARD.encode(raw_event);
+
+    let mut result = String::new();
+    for line in encoded.as_bytes().chunks(BASE64_LINE_LENGTH) {
+        result.push_str(std::str::from_utf8(line).unwrap());
+        result.push('\n');
+    }
+
+    result
+}
+
+/// 从mysqlbinlog的文本输出中取出`BINLOG '...'`语句中的事件，按顺序返回每个事件的完整字节（包括header和校验和）
+/// 按DELIMITER切分语句，`/*!*/;`和`;`都可以；一个语句中的多个事件是分别编码的，按`=`补齐的位置拆开再解码
+/// MariaDB把大的事件拆成`SET @binlog_fragment_0='...'`，再用`BINLOG @binlog_fragment_0, @binlog_fragment_1`执行，也会拼接起来
+/// 第一个事件一般是FDE，之后是table map和行事件，其他的SQL语句和注释都忽略
+pub struct BinlogBase64Reader<R: BufRead> {
+    reader: R,
+    delimiter: String,
+    fragments: HashMap<String, String>,
+    events: VecDeque<Vec<u8>>,
+    line_number: usize,
+}
+
+impl<R: BufRead> BinlogBase64Reader<R> {
+    pub fn new(reader: R) -> BinlogBase64Reader<R> {
+        BinlogBase64Reader {
+            reader,
+            delimiter: ";".to_string(),
+            fragments: HashMap::new(),
+            events: VecDeque::new(),
+            line_number: 0,
+        }
+    }
+
+    /// 读取下一个完整的语句，去掉最后的分隔符，返回语句和开始的行号，读完时为None
+    fn next_statement(&mut self) -> Result<Option<(String, usize)>, BoxedError> {
+        let mut statement = String::new();
+        let mut start_line = 0;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                // 最后一个语句可能没有分隔符
+                return match statement.trim().is_empty() {
+                    true => Ok(None),
+                    false => Ok(Some((statement, start_line))),
+                };
+            }
+            self.line_number += 1;
+
+            let trimmed = line.trim();
+            if statement.is_empty() {
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("--") {
+                    continue;
+                }
+
+                let mut words = trimmed.splitn(2, char::is_whitespace);
+                if words
+                    .next()
+                    .is_some_and(|v| v.eq_ignore_ascii_case("DELIMITER"))
+                {
+                    if let Some(delimiter) = words.next().map(str::trim).filter(|v| !v.is_empty()) {
+                        self.delimiter = delimiter.to_string();
+                    }
+                    continue;
+                }
+
+                start_line = self.line_number;
+            }
+
+            match trimmed.strip_suffix(self.delimiter.as_str()) {
+                Some(rest) => {
+                    statement.push_str(rest);
+                    return Ok(Some((statement, start_line)));
+                }
+                None => {
+                    statement.push_str(trimmed);
+                    statement.push('\n');
+                }
+            }
+        }
+    }
+
+    /// 只处理BINLOG语句和设置binlog_fragment的语句，返回其中的base64
+    fn handle_statement(&mut self, statement: &str) -> Result<Option<String>, BoxedError> {
+        let statement = statement.trim();
+
+        if let Some(rest) = strip_keyword(statement, "SET") {
+            if let Some((name, value)) = rest.split_once('=') {
+                let name = name.trim();
+                if name.to_ascii_lowercase().starts_with("@binlog_fragment_") {
+                    self.fragments
+                        .insert(name.to_ascii_lowercase(), quoted(value)?);
+                }
+            }
+            return Ok(None);
+        }
+
+        let rest = match strip_keyword(statement, "BINLOG") {
+            Some(rest) => rest.trim(),
+            None => return Ok(None),
+        };
+
+        match rest.starts_with('@') {
+            true => {
+                let mut payload = String::new();
+                for name in rest.split(',') {
+                    let name = name.trim().to_ascii_lowercase();
+                    let fragment = self.fragments.remove(&name).ok_or_else(|| {
+                        MyError(format!(
+                            "the BINLOG statement uses {} which is not set",
+                            name
+                        ))
+                    })?;
+                    payload.push_str(&fragment);
+                }
+                Ok(Some(payload))
+            }
+            false => Ok(Some(quoted(rest)?)),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for BinlogBase64Reader<R> {
+    type Item = Result<Vec<u8>, BoxedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.events.is_empty() {
+            let (statement, line_number) = match self.next_statement() {
+                Ok(Some(statement)) => statement,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let result = self
+                .handle_statement(&statement)
+                .and_then(|payload| match payload {
+                    Some(payload) => split_events(&decode_payload(&payload)?),
+                    None => Ok(Vec::new()),
+                })
+                .map_err(|e| MyError(format!("{} in the statement at line {}", e, line_number)));
+
+            match result {
+                Ok(events) => self.events.extend(events),
+                Err(e) => return Some(Err(Box::new(e))),
+            }
+        }
+
+        self.events.pop_front().map(Ok)
+    }
+}
+
+/// `from-base64`子命令，按顺序解析mysqlbinlog文本输出中的事件，输出和解析binlog文件时一样写到out中
+/// 事件的位置是header中next_event_position减去事件长度，没有时按解码出的字节累加
+pub fn write_base64_events(
+    options: &FromBase64Options,
+    out: &mut dyn Write,
+    diagnostics: &mut dyn FnMut(Diagnostic),
+) -> Result<(), BoxedError> {
+    let reader: Box<dyn BufRead> = match options.text_file_path.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        path => Box::new(BufReader::new(File::open(path)?)),
+    };
+
+    let mut pipeline = EventPipeline::new(ValueOptions::default(), false);
+    let mut event_writer = EventWriter::new(options.output, ColorChoice::Auto);
+
+    let mut position = 4;
+    for event in BinlogBase64Reader::new(reader) {
+        let event = event?;
+        let mut cursor = Cursor::new(&event);
+
+        let header = get_event_header(&mut cursor, 0)?;
+        let event_position = (header.next_event_position as u64)
+            .checked_sub(header.event_length as u64)
+            .filter(|_| header.next_event_position != 0)
+            .unwrap_or(position);
+
+        let body = pipeline.decode(&mut cursor, 0, &header)?;
+        pipeline.update(body.as_ref(), diagnostics);
+
+        event_writer.write(
+            out,
+            &header,
+            event_position,
+            body.as_ref(),
+            &pipeline.table_structs,
+        )?;
+
+        position = event_position + header.event_length as u64;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+// 不区分大小写的关键字，后面必须是空白或者结束
+fn strip_keyword<'a>(statement: &'a str, keyword: &str) -> Option<&'a str> {
+    let head = statement.get(..keyword.len())?;
+    let rest = &statement[keyword.len()..];
+
+    match head.eq_ignore_ascii_case(keyword) && rest.chars().next().is_none_or(char::is_whitespace)
+    {
+        true => Some(rest),
+        false => None,
+    }
+}
+
+// 单引号中的内容，base64中没有引号和反斜杠，不需要处理转义
+fn quoted(value: &str) -> Result<String, BoxedError> {
+    let value = value.trim();
+
+    match value
+        .strip_prefix('\'')
+        .and_then(|v| v.rfind('\'').map(|end| &v[..end]))
+    {
+        Some(content) => Ok(content.to_string()),
+        None => Err(Box::new(MyError(format!(
+            "expect a quoted base64 string but got `{}`",
+            value.chars().take(32).collect::<String>()
+        )))),
+    }
+}
+
+/// 去掉空白之后按`=`补齐的位置拆成几段分别解码，没有补齐的事件正好是完整的4个字符一组，可以和后面的一起解码
+fn decode_payload(payload: &str) -> Result<Vec<u8>, BoxedError> {
+    let payload = payload
+        .chars()
+        .filter(|v| !v.is_whitespace())
+        .collect::<String>();
+
+    let mut result = Vec::new();
+    let mut rest = payload.as_str();
+    while !rest.is_empty() {
+        let end = match rest.find('=') {
+            Some(index) => {
+                index
+                    + rest[index..]
+                        .find(|v| v != '=')
+                        .unwrap_or(rest.len() - index)
+            }
+            None => rest.len(),
+        };
+
+        result.extend(BASE64_STANDARD.decode(&rest[..end])?);
+        rest = &rest[end..];
+    }
+
+    Ok(result)
+}
+
+/// 按header中的event_length把解码后的字节拆成事件
+fn split_events(bytes: &[u8]) -> Result<Vec<Vec<u8>>, BoxedError> {
+    let mut events = Vec::new();
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let event_length = match rest.get(9..13) {
+            Some(v) => u32::from_le_bytes(v.try_into()?) as usize,
+            None => 0,
+        };
+
+        if event_length < 19 || event_length > rest.len() {
+            return Err(Box::new(MyError(format!(
+                "the event at byte {} of the decoded BINLOG statement is truncated",
+                offset
+            ))));
+        }
+
+        events.push(rest[..event_length].to_vec());
+        offset += event_length;
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::cli::OutputFormat;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, CHECKSUM_LENGTH};
+
+    fn orders() -> TableMapBuilder {
+        TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+    }
+
+    fn row(id: i64) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(id), ColumnValue::String("x".repeat(100))]
+    }
+
+    // 解析整个binlog，交给writer，返回输出和每个事件的原始字节
+    fn write(binlog: &[u8]) -> (String, Vec<(u8, Vec<u8>)>) {
+        let mut writer = BinlogBase64Writer::new();
+        let mut output = writer.header();
+        let mut events = Vec::new();
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+
+            let next_position = header.next_event_position as u64;
+            let raw_event = binlog[position as usize..next_position as usize].to_vec();
+            if let Some(text) = writer.process(&header, position, body.as_ref(), &raw_event) {
+                output.push_str(&text);
+            }
+            events.push((header.type_code, raw_event));
+
+            position = next_position;
+        }
+        output.push_str(&writer.footer());
+
+        (output, events)
+    }
+
+    fn read(output: &str) -> Vec<Vec<u8>> {
+        BinlogBase64Reader::new(output.as_bytes())
+            .collect::<Result<Vec<Vec<u8>>, BoxedError>>()
+            .unwrap()
+    }
+
+    // FDE、table map和行事件解码之后和原来的字节相同，包括校验和
+    fn assert_round_trip(binlog: &[u8], rows_type_codes: &[u8]) {
+        let (output, events) = write(binlog);
+
+        let expected = events
+            .into_iter()
+            .filter(|(type_code, _)| {
+                *type_code == 15 || *type_code == 19 || rows_type_codes.contains(type_code)
+            })
+            .map(|(_, raw_event)| raw_event)
+            .collect::<Vec<Vec<u8>>>();
+        assert_eq!(read(&output), expected);
+
+        // base64每行最多76个字符
+        let mut in_statement = false;
+        for line in output.lines() {
+            match line {
+                "BINLOG '" => in_statement = true,
+                "'/*!*/;" => in_statement = false,
+                _ if in_statement => assert!(line.len() <= BASE64_LINE_LENGTH, "{}", line),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn mariadb_events_round_trip() {
+        let table = orders();
+        let binlog = BinlogBuilder::new()
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(row(1)).row(row(2)))
+            .event(
+                &RowsEventBuilder::update(&table)
+                    .update_row(row(2), row(3))
+                    .flags(STMT_END_F),
+            )
+            .event(&table)
+            .event(
+                &RowsEventBuilder::delete(&table)
+                    .row(row(3))
+                    .flags(STMT_END_F),
+            )
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+
+        assert_round_trip(&binlog, &[23, 24, 25]);
+    }
+
+    #[test]
+    fn mysql_events_round_trip() {
+        let table = orders();
+        let binlog = BinlogBuilder::with_server_version("8.0.36")
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .v2(true)
+                    .row(row(1))
+                    .flags(STMT_END_F),
+            )
+            .event(&table)
+            .event(
+                &RowsEventBuilder::update(&table)
+                    .v2(true)
+                    .update_row(row(1), row(2))
+                    .flags(STMT_END_F),
+            )
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+
+        assert_round_trip(&binlog, &[30, 31]);
+    }
+
+    // table map和语句中的行事件在同一个BINLOG语句中，语句结束之前不输出
+    #[test]
+    fn one_statement_per_rows_statement() {
+        let table = orders();
+        let binlog = BinlogBuilder::new()
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(row(1)))
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .row(row(2))
+                    .flags(STMT_END_F),
+            )
+            .build();
+
+        let (output, events) = write(&binlog);
+        assert_eq!(output.matches("BINLOG '").count(), 2);
+
+        let statement = output.split("BINLOG '").nth(2).unwrap();
+        let statement = statement.split("'/*!*/;").next().unwrap();
+        let raw_events = events
+            .into_iter()
+            .skip(1)
+            .map(|(_, v)| v)
+            .collect::<Vec<Vec<u8>>>();
+        assert_eq!(
+            split_events(&decode_payload(statement).unwrap()).unwrap(),
+            raw_events
+        );
+    }
+
+    #[test]
+    fn from_base64_writes_each_event() {
+        let table = orders();
+        let binlog = BinlogBuilder::new()
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .row(row(1))
+                    .flags(STMT_END_F),
+            )
+            .build();
+        let (text, _) = write(&binlog);
+
+        let path = std::env::temp_dir().join(format!("from-base64-{}.sql", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let options = FromBase64Options {
+            text_file_path: path.to_string_lossy().to_string(),
+            output: OutputFormat::Json,
+        };
+
+        let mut out = Vec::new();
+        let mut diagnostics = Vec::new();
+        write_base64_events(&options, &mut out, &mut |v| diagnostics.push(v.to_string())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let events = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|v| serde_json::from_str::<serde_json::Value>(v).unwrap())
+            .collect::<Vec<_>>();
+        let positions = events
+            .iter()
+            .map(|v| {
+                (
+                    v["position"].as_u64().unwrap(),
+                    v["header"]["type_code"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(positions[0], (4, 15));
+        assert_eq!(
+            positions.iter().map(|v| v.1).collect::<Vec<_>>(),
+            [15, 19, 23]
+        );
+        assert_eq!(events[2]["body"]["rows"][0]["after"][0], 1);
+        assert_eq!(diagnostics, ["the binlog is written by MariaDB 10.11.6"]);
+    }
+
+    fn gtid(sequence: u64, flags: u8) -> EventBuilder {
+        EventBuilder::from_body(&GtidEventBuilder::new(sequence).flags(flags)).timestamp(1700000000)
+    }
+
+    fn query(sql: &str, status_variables: &[u8]) -> EventBuilder {
+        EventBuilder::from_body(
+            &QueryEventBuilder::new("shop", sql)
+                .thread_id(42)
+                .status_variables(status_variables),
+        )
+        .timestamp(1700000000)
+    }
+
+    // mysqlbinlog写的会话变量：flags2、sql_mode、auto_increment、字符集、lc_time_names、collation_database
+    fn session_status_variables() -> Vec<u8> {
+        let mut status_variables = vec![0];
+        status_variables.extend(0u32.to_le_bytes());
+        status_variables.push(1);
+        status_variables.extend(1411383296u64.to_le_bytes());
+        status_variables.push(3);
+        status_variables.extend([2, 0, 1, 0]);
+        status_variables.push(4);
+        status_variables.extend([33, 0, 33, 0, 8, 0]);
+        status_variables.push(7);
+        status_variables.extend([0, 0]);
+        status_variables.push(8);
+        status_variables.extend([45, 0]);
+        status_variables
+    }
+
+    fn user_var(name: &str, value: &str) -> EventBuilder {
+        let mut body = (name.len() as u32).to_le_bytes().to_vec();
+        body.extend(name.as_bytes());
+        body.extend([0, 0]);
+        body.extend(45u32.to_le_bytes());
+        body.extend((value.len() as u32).to_le_bytes());
+        body.extend(value.as_bytes());
+        body.push(0);
+        EventBuilder::new(14, body)
+    }
+
+    fn replay(binlog: &[u8]) -> String {
+        let mut writer = BinlogBase64Writer::new();
+        writer.replayable(true);
+        let mut output = writer.header();
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+
+            let next_position = header.next_event_position as u64;
+            let raw_event = &binlog[position as usize..next_position as usize];
+            if let Some(text) = writer.process(&header, position, body.as_ref(), raw_event) {
+                output.push_str(&text);
+            }
+            position = next_position;
+        }
+        output.push_str(&writer.footer());
+
+        // 和mysqlbinlog的输出比较时不管注释和BINLOG语句中的base64
+        let mut in_statement = false;
+        output
+            .lines()
+            .filter(|line| {
+                match *line {
+                    "BINLOG '" => in_statement = true,
+                    "'/*!*/;" => in_statement = false,
+                    _ if in_statement => return false,
+                    _ => {}
+                }
+                !line.starts_with('#')
+            })
+            .map(|v| format!("{}\n", v))
+            .collect()
+    }
+
+    #[test]
+    fn replayable_like_mysqlbinlog() {
+        let table = orders();
+        let binlog = BinlogBuilder::new()
+            .push(gtid(5, 0))
+            .push(query("BEGIN", &session_status_variables()))
+            .push(EventBuilder::new(
+                5,
+                [&[2u8][..], &7u64.to_le_bytes()].concat(),
+            ))
+            .push(user_var("who", "ann"))
+            .push(query(
+                "INSERT INTO orders (name) VALUES (@who)",
+                &session_status_variables(),
+            ))
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .row(row(1))
+                    .flags(STMT_END_F),
+            )
+            .push(EventBuilder::new(16, 9u64.to_le_bytes().to_vec()))
+            .push(gtid(6, FL_STANDALONE))
+            .push(query(
+                "CREATE TABLE t (id INT)",
+                &session_status_variables(),
+            ))
+            .build();
+
+        // mysqlbinlog对同样的事件的输出，BEGIN由GTID开始的事务代替，没有变化的会话变量不再输出
+        let expected = concat!(
+            "/*!50530 SET @@SESSION.PSEUDO_SLAVE_MODE=1*/;\n",
+            "/*!40019 SET @@session.max_insert_delayed_threads=0*/;\n",
+            "/*!50003 SET @OLD_COMPLETION_TYPE=@@COMPLETION_TYPE,COMPLETION_TYPE=0*/;\n",
+            "DELIMITER /*!*/;\n",
+            "BINLOG '\n",
+            "'/*!*/;\n",
+            "/*!100001 SET @@session.gtid_domain_id=0*//*!*/;\n",
+            "/*!100001 SET @@session.server_id=1*//*!*/;\n",
+            "/*!100001 SET @@session.gtid_seq_no=5*//*!*/;\n",
+            "START TRANSACTION\n",
+            "/*!*/;\n",
+            "SET INSERT_ID=7/*!*/;\n",
+            "SET @`who`:=_utf8mb4 0x616e6e COLLATE `utf8mb4_general_ci`/*!*/;\n",
+            "use `shop`/*!*/;\n",
+            "SET TIMESTAMP=1700000000/*!*/;\n",
+            "SET @@session.pseudo_thread_id=42/*!*/;\n",
+            "SET @@session.foreign_key_checks=1, @@session.sql_auto_is_null=0, @@session.unique_checks=1, @@session.autocommit=1/*!*/;\n",
+            "SET @@session.sql_mode=1411383296/*!*/;\n",
+            "/*!\\C utf8mb3 *//*!*/;\n",
+            "SET @@session.character_set_client=33,@@session.collation_connection=33,@@session.collation_server=8/*!*/;\n",
+            "SET @@session.auto_increment_increment=2, @@session.auto_increment_offset=1/*!*/;\n",
+            "SET @@session.lc_time_names=0/*!*/;\n",
+            "SET @@session.collation_database=45/*!*/;\n",
+            "INSERT INTO orders (name) VALUES (@who)\n",
+            "/*!*/;\n",
+            "BINLOG '\n",
+            "'/*!*/;\n",
+            "COMMIT/* xid=9 *//*!*/;\n",
+            "/*!100001 SET @@session.gtid_seq_no=6*//*!*/;\n",
+            "SET TIMESTAMP=1700000000/*!*/;\n",
+            "SET @@session.pseudo_thread_id=42/*!*/;\n",
+            "CREATE TABLE t (id INT)\n",
+            "/*!*/;\n",
+            "DELIMITER ;\n",
+            "ROLLBACK /* added by mysqlbinlog */;\n",
+            "/*!50003 SET COMPLETION_TYPE=@OLD_COMPLETION_TYPE*/;\n",
+            "/*!50530 SET @@SESSION.PSEUDO_SLAVE_MODE=0*/;\n",
+        );
+        assert_eq!(replay(&binlog), expected);
+    }
+}
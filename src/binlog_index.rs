@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use crate::model::MyError;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 读取服务端的binlog索引文件（例如mysql-bin.index），按其中的顺序返回binlog文件的路径
+///
+/// 每行一个文件，可以是绝对路径，也可以是相对于索引文件所在目录的路径（服务端写的是`./mysql-bin.000001`），
+/// 允许CRLF换行和空行。有文件不存在时报错，列出它们是索引中的第几个
+pub fn read_binlog_index(index_path: &str) -> Result<Vec<String>, BoxedError> {
+    let content = std::fs::read_to_string(index_path).map_err(|e| {
+        MyError(format!(
+            "can not read the index file `{}`: {}",
+            index_path, e
+        ))
+    })?;
+    let dir = Path::new(index_path).parent().unwrap_or(Path::new(""));
+
+    let entries: Vec<&str> = content
+        .lines()
+        .map(|v| v.trim_end_matches('\r'))
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    let mut file_paths = Vec::new();
+    let mut missing = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let entry_path = Path::new(entry);
+        let path = match entry_path.is_absolute() {
+            true => entry_path.to_path_buf(),
+            false => dir.join(entry_path.strip_prefix("./").unwrap_or(entry_path)),
+        };
+
+        if !path.is_file() {
+            missing.push(format!("#{} `{}` ({})", i + 1, entry, path.display()));
+        }
+
+        file_paths.push(path.to_string_lossy().to_string());
+    }
+
+    if !missing.is_empty() {
+        return Err(Box::new(MyError(format!(
+            "{} of {} binlog files in `{}` do not exist: {}",
+            missing.len(),
+            entries.len(),
+            index_path,
+            missing.join(", ")
+        ))));
+    }
+
+    if file_paths.is_empty() {
+        return Err(Box::new(MyError(format!(
+            "`{}` does not list any binlog file",
+            index_path
+        ))));
+    }
+
+    Ok(file_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // 每个测试一个目录，其中有索引文件和binlog
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("binlog-index-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path) {
+        std::fs::write(path, b"\xfebin").unwrap();
+    }
+
+    #[test]
+    fn relative_and_absolute_entries() {
+        let dir = temp_dir("entries");
+        let other_dir = temp_dir("entries-other");
+        touch(&dir.join("mysql-bin.000001"));
+        touch(&dir.join("mysql-bin.000002"));
+        touch(&other_dir.join("renamed.000003"));
+
+        let absolute = other_dir.join("renamed.000003");
+        let index_path = dir.join("mysql-bin.index");
+        // 服务端写的./前缀、CRLF、空行和绝对路径
+        std::fs::write(
+            &index_path,
+            format!(
+                "./mysql-bin.000001\r\nmysql-bin.000002\n\n{}\n",
+                absolute.display()
+            ),
+        )
+        .unwrap();
+
+        let file_paths = read_binlog_index(index_path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            file_paths,
+            [
+                dir.join("mysql-bin.000001").to_string_lossy().to_string(),
+                dir.join("mysql-bin.000002").to_string_lossy().to_string(),
+                absolute.to_string_lossy().to_string(),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&other_dir).unwrap();
+    }
+
+    #[test]
+    fn missing_entries_are_reported_with_their_position() {
+        let dir = temp_dir("missing");
+        touch(&dir.join("mysql-bin.000002"));
+
+        let index_path = dir.join("mysql-bin.index");
+        std::fs::write(
+            &index_path,
+            "./mysql-bin.000001\n./mysql-bin.000002\n./mysql-bin.000003\n",
+        )
+        .unwrap();
+
+        let error = read_binlog_index(index_path.to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("2 of 3 binlog files"));
+        assert!(error.contains("#1 `./mysql-bin.000001`"));
+        assert!(error.contains("#3 `./mysql-bin.000003`"));
+        assert!(!error.contains("#2"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_or_unreadable_index() {
+        let dir = temp_dir("empty");
+        let index_path = dir.join("mysql-bin.index");
+        std::fs::write(&index_path, "\r\n\n").unwrap();
+
+        let error = read_binlog_index(index_path.to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("does not list any binlog file"));
+
+        let error = read_binlog_index(dir.join("other.index").to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("can not read the index file"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1033 @@
+//! 合成事件的构造器，用于测试和复现线上很难得到的情况（旧的字段格式、损坏的长度等）
+//!
+//! 每个构造器生成不带header和校验和的body，由BinlogBuilder加上header和CRC32，
+//! 生成的字节和服务端写的一样，可以直接交给BinlogParser或者命令行解析：
+//!
+//! ```ignore
+//! let table = TableMapBuilder::new("db", "t")
+//!     .table_id(70)
+//!     .column(FieldType::Long, &[])
+//!     .column(FieldType::Varchar, &64u16.to_le_bytes())
+//!     .nullable(true);
+//! let insert = RowsEventBuilder::insert(&table)
+//!     .row(vec![ColumnValue::Int(1), ColumnValue::String("a".to_string())]);
+//! let binlog = BinlogBuilder::new()
+//!     .event(&QueryEventBuilder::new("db", "BEGIN"))
+//!     .event(&table)
+//!     .event(&insert)
+//!     .build();
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::field_type::FieldType;
+use crate::model::*;
+use crate::service::deal_type_code_19;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+const BINLOG_MAGIC_NUMBER: [u8; 4] = [0xfe, 0x62, 0x69, 0x6e];
+
+const EVENT_HEADER_LENGTH: u32 = 19;
+
+const CHECKSUM_LENGTH: u32 = 4;
+
+/// 一种事件的body，不包括header和最后的校验和
+pub trait EventBodyBuilder {
+    fn type_code(&self) -> u8;
+
+    fn body(&self) -> Vec<u8>;
+}
+
+/// 一个事件的header中可以修改的部分，event_length和checksum用来构造损坏的事件
+#[derive(Debug, Clone)]
+pub struct EventBuilder {
+    type_code: u8,
+    body: Vec<u8>,
+    timestamp: u32,
+    server_id: u32,
+    flags: u16,
+    event_length: Option<u32>,
+    checksum: Option<u32>,
+    without_checksum: bool,
+}
+
+impl EventBuilder {
+    pub fn new(type_code: u8, body: Vec<u8>) -> EventBuilder {
+        EventBuilder {
+            type_code,
+            body,
+            timestamp: 0,
+            server_id: 1,
+            flags: 0,
+            event_length: None,
+            checksum: None,
+            without_checksum: false,
+        }
+    }
+
+    pub fn from_body(builder: &dyn EventBodyBuilder) -> EventBuilder {
+        EventBuilder::new(builder.type_code(), builder.body())
+    }
+
+    pub fn timestamp(mut self, timestamp: u32) -> EventBuilder {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn server_id(mut self, server_id: u32) -> EventBuilder {
+        self.server_id = server_id;
+        self
+    }
+
+    pub fn flags(mut self, flags: u16) -> EventBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// header中写入的事件长度，默认是实际的长度，next_event_position也按这个长度计算
+    pub fn event_length(mut self, event_length: u32) -> EventBuilder {
+        self.event_length = Some(event_length);
+        self
+    }
+
+    /// 写入的校验和，默认是整个事件的CRC32
+    pub fn checksum(mut self, checksum: u32) -> EventBuilder {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// 不写校验和，对应binlog_checksum=NONE时format description之后的事件
+    pub fn without_checksum(mut self) -> EventBuilder {
+        self.without_checksum = true;
+        self
+    }
+
+    /// 放在文件中position处的整个事件：header、body和4个字节的校验和
+    pub fn build(&self, position: u32) -> Vec<u8> {
+        let checksum_length = if self.without_checksum {
+            0
+        } else {
+            CHECKSUM_LENGTH
+        };
+        let event_length = self
+            .event_length
+            .unwrap_or(EVENT_HEADER_LENGTH + self.body.len() as u32 + checksum_length);
+
+        let mut event = Vec::with_capacity(EVENT_HEADER_LENGTH as usize + self.body.len() + 4);
+        event.extend(self.timestamp.to_le_bytes());
+        event.push(self.type_code);
+        event.extend(self.server_id.to_le_bytes());
+        event.extend(event_length.to_le_bytes());
+        event.extend(position.wrapping_add(event_length).to_le_bytes());
+        event.extend(self.flags.to_le_bytes());
+        event.extend(&self.body);
+        if self.without_checksum {
+            return event;
+        }
+
+        let checksum = self.checksum.unwrap_or_else(|| crc32fast::hash(&event));
+        event.extend(checksum.to_le_bytes());
+
+        event
+    }
+}
+
+/// 整个binlog文件，开头是magic number和format description事件，事件的位置按追加的顺序计算
+#[derive(Debug, Clone)]
+pub struct BinlogBuilder {
+    file: Vec<u8>,
+    without_checksum: bool,
+}
+
+impl Default for BinlogBuilder {
+    fn default() -> Self {
+        BinlogBuilder::new()
+    }
+}
+
+impl BinlogBuilder {
+    pub fn new() -> BinlogBuilder {
+        BinlogBuilder::with_server_version("10.11.6-MariaDB-log")
+    }
+
+    /// format description事件中的服务端版本，例如MySQL的`8.0.36`
+    pub fn with_server_version(server_version: &str) -> BinlogBuilder {
+        BinlogBuilder::empty().push(EventBuilder::from_body(&FormatDescriptionBuilder::new(
+            server_version,
+        )))
+    }
+
+    /// binlog_checksum=NONE的文件：format description自己仍然带校验和，之后的事件都没有
+    pub fn without_checksum() -> BinlogBuilder {
+        let mut builder = BinlogBuilder::empty().push(EventBuilder::from_body(
+            &FormatDescriptionBuilder::new("10.11.6-MariaDB-log").checksum_algorithm(0),
+        ));
+        builder.without_checksum = true;
+        builder
+    }
+
+    /// 不带format description事件，只有magic number
+    pub fn empty() -> BinlogBuilder {
+        BinlogBuilder {
+            file: BINLOG_MAGIC_NUMBER.to_vec(),
+            without_checksum: false,
+        }
+    }
+
+    pub fn event(self, builder: &dyn EventBodyBuilder) -> BinlogBuilder {
+        self.push(EventBuilder::from_body(builder))
+    }
+
+    /// 需要修改header或者构造损坏的事件时用这个
+    pub fn push(mut self, event: EventBuilder) -> BinlogBuilder {
+        let event = if self.without_checksum {
+            event.without_checksum()
+        } else {
+            event
+        };
+        let event = event.build(self.file.len() as u32);
+        self.file.extend(event);
+        self
+    }
+
+    /// 原样追加的字节，例如截断的事件
+    pub fn raw(mut self, bytes: &[u8]) -> BinlogBuilder {
+        self.file.extend(bytes);
+        self
+    }
+
+    /// 下一个事件的位置
+    pub fn position(&self) -> u64 {
+        self.file.len() as u64
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.file
+    }
+}
+
+/// format description事件，校验算法默认是CRC32
+#[derive(Debug, Clone)]
+pub struct FormatDescriptionBuilder {
+    server_version: String,
+    create_timestamp: u32,
+    checksum_algorithm: u8,
+}
+
+impl FormatDescriptionBuilder {
+    pub fn new(server_version: &str) -> FormatDescriptionBuilder {
+        FormatDescriptionBuilder {
+            server_version: server_version.to_string(),
+            create_timestamp: 0,
+            checksum_algorithm: 1,
+        }
+    }
+
+    pub fn create_timestamp(mut self, create_timestamp: u32) -> FormatDescriptionBuilder {
+        self.create_timestamp = create_timestamp;
+        self
+    }
+
+    /// 0是NONE，1是CRC32
+    pub fn checksum_algorithm(mut self, checksum_algorithm: u8) -> FormatDescriptionBuilder {
+        self.checksum_algorithm = checksum_algorithm;
+        self
+    }
+}
+
+impl EventBodyBuilder for FormatDescriptionBuilder {
+    fn type_code(&self) -> u8 {
+        15
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut server_version = self.server_version.as_bytes().to_vec();
+        server_version.resize(50, 0);
+
+        // 每种事件的post header长度，下标是类型码减1，这里只填常用的几种
+        let mut post_header_lengths = vec![0u8; 164];
+        for (type_code, length) in [
+            (2, 13),
+            (4, 8),
+            (15, 84),
+            (19, 8),
+            (23, 8),
+            (24, 8),
+            (25, 8),
+            (30, 10),
+            (31, 10),
+            (32, 10),
+            (162, 19),
+            (163, 4),
+        ] {
+            post_header_lengths[type_code - 1] = length;
+        }
+
+        let mut body = Vec::new();
+        body.extend(4u16.to_le_bytes());
+        body.extend(server_version);
+        body.extend(self.create_timestamp.to_le_bytes());
+        body.push(EVENT_HEADER_LENGTH as u8);
+        body.extend(post_header_lengths);
+        body.push(self.checksum_algorithm);
+
+        body
+    }
+}
+
+/// query事件，状态变量是原始字节，默认为空
+#[derive(Debug, Clone)]
+pub struct QueryEventBuilder {
+    database: String,
+    sql: String,
+    thread_id: u32,
+    execute_time: u32,
+    error_code: u16,
+    status_variables: Vec<u8>,
+}
+
+impl QueryEventBuilder {
+    pub fn new(database: &str, sql: &str) -> QueryEventBuilder {
+        QueryEventBuilder {
+            database: database.to_string(),
+            sql: sql.to_string(),
+            thread_id: 1,
+            execute_time: 0,
+            error_code: 0,
+            status_variables: Vec::new(),
+        }
+    }
+
+    pub fn thread_id(mut self, thread_id: u32) -> QueryEventBuilder {
+        self.thread_id = thread_id;
+        self
+    }
+
+    pub fn execute_time(mut self, execute_time: u32) -> QueryEventBuilder {
+        self.execute_time = execute_time;
+        self
+    }
+
+    pub fn error_code(mut self, error_code: u16) -> QueryEventBuilder {
+        self.error_code = error_code;
+        self
+    }
+
+    /// 编码好的状态变量，例如`[0x80, 0x01, 0x00, 0x00]`是Q_HRNOW（3个字节的微秒）
+    pub fn status_variables(mut self, status_variables: &[u8]) -> QueryEventBuilder {
+        self.status_variables = status_variables.to_vec();
+        self
+    }
+}
+
+impl EventBodyBuilder for QueryEventBuilder {
+    fn type_code(&self) -> u8 {
+        2
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend(self.thread_id.to_le_bytes());
+        body.extend(self.execute_time.to_le_bytes());
+        body.push(self.database.len() as u8);
+        body.extend(self.error_code.to_le_bytes());
+        body.extend((self.status_variables.len() as u16).to_le_bytes());
+        body.extend(&self.status_variables);
+        body.extend(self.database.as_bytes());
+        body.push(0);
+        body.extend(self.sql.as_bytes());
+
+        body
+    }
+}
+
+/// table map事件，column的元数据是table map中这个字段的原始字节（例如VARCHAR是2个字节的最大长度），
+/// nullable修改最后加入的字段，默认不能为NULL
+#[derive(Debug, Clone)]
+pub struct TableMapBuilder {
+    table_id: u64,
+    database: String,
+    table: String,
+    columns: Vec<(FieldType, Vec<u8>, bool)>,
+    optional_metadata: Vec<u8>,
+}
+
+impl TableMapBuilder {
+    pub fn new(database: &str, table: &str) -> TableMapBuilder {
+        TableMapBuilder {
+            table_id: 1,
+            database: database.to_string(),
+            table: table.to_string(),
+            columns: Vec::new(),
+            optional_metadata: Vec::new(),
+        }
+    }
+
+    /// 只写入低6个字节
+    pub fn table_id(mut self, table_id: u64) -> TableMapBuilder {
+        self.table_id = table_id;
+        self
+    }
+
+    pub fn column(mut self, field_type: FieldType, metadata: &[u8]) -> TableMapBuilder {
+        self.columns.push((field_type, metadata.to_vec(), false));
+        self
+    }
+
+    pub fn nullable(mut self, nullable: bool) -> TableMapBuilder {
+        if let Some(column) = self.columns.last_mut() {
+            column.2 = nullable;
+        }
+        self
+    }
+
+    /// 编码好的可选元数据（binlog_row_metadata），放在null bitmap之后
+    pub fn optional_metadata(mut self, optional_metadata: &[u8]) -> TableMapBuilder {
+        self.optional_metadata = optional_metadata.to_vec();
+        self
+    }
+
+    pub fn get_table_id(&self) -> u64 {
+        self.table_id
+    }
+
+    /// 和解析这个事件得到的表结构相同，行事件按它编码字段的值
+    pub fn table_info(&self) -> Result<Arc<EventBodyTypeCode19>, BoxedError> {
+        let body = deal_type_code_19(self.body(), &mut HashMap::new())?;
+        body.downcast_ref::<Arc<EventBodyTypeCode19>>()
+            .cloned()
+            .ok_or_else(|| Box::new(MyError("the table map can not be parsed".to_string())).into())
+    }
+}
+
+impl EventBodyBuilder for TableMapBuilder {
+    fn type_code(&self) -> u8 {
+        19
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let metadata: Vec<u8> = self.columns.iter().flat_map(|v| v.1.clone()).collect();
+        let nullable = Bitmap::from_iter(self.columns.iter().map(|v| v.2));
+
+        let mut body = Vec::new();
+        body.extend(&self.table_id.to_le_bytes()[..6]);
+        body.extend(1u16.to_le_bytes());
+        body.push(self.database.len() as u8);
+        body.extend(self.database.as_bytes());
+        body.push(0);
+        body.push(self.table.len() as u8);
+        body.extend(self.table.as_bytes());
+        body.push(0);
+        push_lenenc(&mut body, self.columns.len() as u64);
+        body.extend(self.columns.iter().map(|v| v.0.code()));
+        push_lenenc(&mut body, metadata.len() as u64);
+        body.extend(metadata);
+        body.extend(nullable.to_bytes());
+        body.extend(&self.optional_metadata);
+
+        body
+    }
+}
+
+/// 行事件，默认所有字段都在columns_used中（binlog_row_image=FULL），值按table map中的类型编码：
+/// 整数、FLOAT、DOUBLE用Int、Float、Double，VARCHAR、BLOB等变长类型用String或Bytes，
+/// 其他类型（DECIMAL、时间类型等）用Bytes，里面是行镜像中这个字段的原始字节
+/// columns_used、columns_used_for_update可以只选择部分字段，模拟MINIMAL、NOBLOB，
+/// 这时每一行仍然给出所有字段的值，没有选择的字段不写入镜像
+#[derive(Debug, Clone)]
+pub struct RowsEventBuilder {
+    type_code: u8,
+    table_id: u64,
+    table_info: Option<Arc<EventBodyTypeCode19>>,
+    flags: u16,
+    v2: bool,
+    columns_used: Option<Vec<bool>>,
+    columns_used_for_update: Option<Vec<bool>>,
+    rows: Vec<RowsEventRow>,
+}
+
+impl RowsEventBuilder {
+    pub fn insert(table: &TableMapBuilder) -> RowsEventBuilder {
+        RowsEventBuilder::new(23, table)
+    }
+
+    pub fn update(table: &TableMapBuilder) -> RowsEventBuilder {
+        RowsEventBuilder::new(24, table)
+    }
+
+    pub fn delete(table: &TableMapBuilder) -> RowsEventBuilder {
+        RowsEventBuilder::new(25, table)
+    }
+
+    fn new(type_code: u8, table: &TableMapBuilder) -> RowsEventBuilder {
+        RowsEventBuilder {
+            type_code,
+            table_id: table.table_id,
+            table_info: table.table_info().ok(),
+            flags: 0,
+            v2: false,
+            columns_used: None,
+            columns_used_for_update: None,
+            rows: Vec::new(),
+        }
+    }
+
+    /// 行事件中的table id，用来构造没有对应table map的行事件
+    pub fn table_id(mut self, table_id: u64) -> RowsEventBuilder {
+        self.table_id = table_id;
+        self
+    }
+
+    pub fn flags(mut self, flags: u16) -> RowsEventBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// 写成v2的行事件（类型码30到32），extra data为空
+    pub fn v2(mut self, v2: bool) -> RowsEventBuilder {
+        self.v2 = v2;
+        self
+    }
+
+    /// 镜像（update时是修改前的镜像）中有哪些字段，默认是所有字段
+    pub fn columns_used(mut self, columns_used: &[bool]) -> RowsEventBuilder {
+        self.columns_used = Some(columns_used.to_vec());
+        self
+    }
+
+    /// update修改后的镜像中有哪些字段，默认和columns_used相同
+    pub fn columns_used_for_update(mut self, columns_used: &[bool]) -> RowsEventBuilder {
+        self.columns_used_for_update = Some(columns_used.to_vec());
+        self
+    }
+
+    /// insert是插入的行，delete是删除的行，update时用update_row
+    pub fn row(mut self, values: Vec<ColumnValue>) -> RowsEventBuilder {
+        let row = match self.type_code {
+            25 => RowsEventRow {
+                before: Some(values),
+                after: None,
+            },
+            _ => RowsEventRow {
+                before: None,
+                after: Some(values),
+            },
+        };
+        self.rows.push(row);
+        self
+    }
+
+    pub fn update_row(
+        mut self,
+        before: Vec<ColumnValue>,
+        after: Vec<ColumnValue>,
+    ) -> RowsEventBuilder {
+        self.rows.push(RowsEventRow {
+            before: Some(before),
+            after: Some(after),
+        });
+        self
+    }
+
+    // 只写入present中的字段，null bitmap也只有这些字段
+    fn push_row_image(&self, body: &mut Vec<u8>, values: &[ColumnValue], present: &Bitmap) {
+        let is_present = |i: &usize| present.get(*i).unwrap_or(false);
+        let values = values
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| is_present(i))
+            .collect::<Vec<(usize, &ColumnValue)>>();
+
+        let nulls = Bitmap::from_iter(values.iter().map(|(_, v)| **v == ColumnValue::Null));
+        body.extend(nulls.to_bytes());
+
+        let descriptors = match &self.table_info {
+            Some(table_info) => table_info.descriptors(),
+            None => panic!("the table map of the rows event can not be parsed"),
+        };
+
+        for (i, value) in values {
+            let descriptor = &descriptors[i];
+            match (value, descriptor.length) {
+                (ColumnValue::Null, _) => {}
+                (ColumnValue::Int(v), ColumnLength::Fixed(length)) => {
+                    body.extend(&v.to_le_bytes()[..length])
+                }
+                (ColumnValue::UInt(v), ColumnLength::Fixed(length)) => {
+                    body.extend(&v.to_le_bytes()[..length])
+                }
+                (ColumnValue::Float(v), ColumnLength::Fixed(4)) => body.extend(v.to_le_bytes()),
+                (ColumnValue::Double(v), ColumnLength::Fixed(8)) => body.extend(v.to_le_bytes()),
+                (ColumnValue::String(v), ColumnLength::Prefixed(n)) => {
+                    body.extend(&(v.len() as u32).to_le_bytes()[..n]);
+                    body.extend(v.as_bytes());
+                }
+                (ColumnValue::Bytes(v), ColumnLength::Prefixed(n)) => {
+                    body.extend(&(v.len() as u32).to_le_bytes()[..n]);
+                    body.extend(v);
+                }
+                (ColumnValue::Bytes(v), _) => body.extend(v),
+                (value, _) => panic!(
+                    "column #{} is {}, {:?} can not be encoded, use ColumnValue::Bytes",
+                    i + 1,
+                    descriptor.field_type,
+                    value
+                ),
+            }
+        }
+    }
+}
+
+impl EventBodyBuilder for RowsEventBuilder {
+    fn type_code(&self) -> u8 {
+        match self.v2 {
+            true => self.type_code + 7,
+            false => self.type_code,
+        }
+    }
+
+    /// 字段的值和table map中的类型不匹配时panic
+    fn body(&self) -> Vec<u8> {
+        let number_of_columns = self
+            .table_info
+            .as_ref()
+            .map(|v| v.columns.len())
+            .unwrap_or_default();
+        let columns_used = match &self.columns_used {
+            Some(columns_used) => Bitmap::from_iter(columns_used.iter().copied()),
+            None => Bitmap::repeat(true, number_of_columns),
+        };
+        let columns_used_for_update = match &self.columns_used_for_update {
+            Some(columns_used) => Bitmap::from_iter(columns_used.iter().copied()),
+            None => columns_used.clone(),
+        };
+
+        let mut body = Vec::new();
+        body.extend(&self.table_id.to_le_bytes()[..6]);
+        body.extend(self.flags.to_le_bytes());
+        if self.v2 {
+            // extra data的长度包括这两个字节
+            body.extend(2u16.to_le_bytes());
+        }
+        push_lenenc(&mut body, number_of_columns as u64);
+        body.extend(columns_used.to_bytes());
+        if self.type_code == 24 {
+            body.extend(columns_used_for_update.to_bytes());
+        }
+
+        for row in &self.rows {
+            if let Some(before) = &row.before {
+                self.push_row_image(&mut body, before, &columns_used);
+            }
+            if let Some(after) = &row.after {
+                let present = match self.type_code {
+                    24 => &columns_used_for_update,
+                    _ => &columns_used,
+                };
+                self.push_row_image(&mut body, after, present);
+            }
+        }
+
+        body
+    }
+}
+
+// length encoded integer
+/// MariaDB的GTID事件，默认domain id为0，没有flags
+#[derive(Debug, Clone)]
+pub struct GtidEventBuilder {
+    sequence: u64,
+    domain_id: u32,
+    flags: u8,
+    commit_id: Option<u64>,
+    xa: Option<(u32, Vec<u8>, Vec<u8>)>,
+}
+
+impl GtidEventBuilder {
+    pub fn new(sequence: u64) -> GtidEventBuilder {
+        GtidEventBuilder {
+            sequence,
+            domain_id: 0,
+            flags: 0,
+            commit_id: None,
+            xa: None,
+        }
+    }
+
+    pub fn domain_id(mut self, domain_id: u32) -> GtidEventBuilder {
+        self.domain_id = domain_id;
+        self
+    }
+
+    pub fn flags(mut self, flags: u8) -> GtidEventBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// 同时加上FL_GROUP_COMMIT_ID
+    pub fn commit_id(mut self, commit_id: u64) -> GtidEventBuilder {
+        self.commit_id = Some(commit_id);
+        self
+    }
+
+    /// XA事务的xid，flags需要另外加上FL_PREPARED_XA或者FL_COMPLETED_XA
+    pub fn xa(mut self, format_id: u32, gtrid: &[u8], bqual: &[u8]) -> GtidEventBuilder {
+        self.xa = Some((format_id, gtrid.to_vec(), bqual.to_vec()));
+        self
+    }
+}
+
+impl EventBodyBuilder for GtidEventBuilder {
+    fn type_code(&self) -> u8 {
+        162
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut body = self.sequence.to_le_bytes().to_vec();
+        body.extend(self.domain_id.to_le_bytes());
+        match self.commit_id {
+            Some(commit_id) => {
+                body.push(self.flags | 2);
+                body.extend(commit_id.to_le_bytes());
+            }
+            None => body.push(self.flags),
+        }
+        if let Some((format_id, gtrid, bqual)) = &self.xa {
+            body.extend(format_id.to_le_bytes());
+            body.extend([gtrid.len() as u8, bqual.len() as u8]);
+            body.extend(gtrid);
+            body.extend(bqual);
+        }
+        body.extend([0; 6]);
+
+        body
+    }
+}
+
+// 单元测试中常用的事件和表，需要其他的内容时在返回的构造器上修改
+
+/// domain id为0的GTID事件
+pub fn gtid(sequence: u64) -> EventBuilder {
+    EventBuilder::from_body(&GtidEventBuilder::new(sequence))
+}
+
+/// 提交事务的XID事件
+pub fn xid(xid: u64) -> EventBuilder {
+    EventBuilder::new(16, xid.to_le_bytes().to_vec())
+}
+
+/// `shop.orders (id INT NOT NULL)`，table id为7
+pub fn orders() -> TableMapBuilder {
+    TableMapBuilder::new("shop", "orders")
+        .table_id(7)
+        .column(FieldType::Long, &[])
+}
+
+/// `shop.users (id INT NOT NULL, name VARCHAR(64))`，table id为7
+pub fn users() -> TableMapBuilder {
+    TableMapBuilder::new("shop", "users")
+        .table_id(7)
+        .column(FieldType::Long, &[])
+        .column(FieldType::Varchar, &64u16.to_le_bytes())
+        .nullable(true)
+}
+
+fn push_lenenc(buffer: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=250 => buffer.push(value as u8),
+        251..=0xffff => {
+            buffer.push(0xfc);
+            buffer.extend(&value.to_le_bytes()[..2]);
+        }
+        0x10000..=0xffffff => {
+            buffer.push(0xfd);
+            buffer.extend(&value.to_le_bytes()[..3]);
+        }
+        _ => {
+            buffer.push(0xfe);
+            buffer.extend(value.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::parser::{BinlogParser, ParsedEvent};
+
+    // 从头解析整个文件，返回magic number之后的所有事件
+    fn parse(binlog: &[u8]) -> Vec<ParsedEvent> {
+        assert_eq!(binlog[..4], BINLOG_MAGIC_NUMBER);
+
+        let mut parser = BinlogParser::new();
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut events = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let event = parser.read_event(&mut file, position).unwrap();
+            position += event.header.event_length as u64;
+            events.push(event);
+        }
+        assert_eq!(position, binlog.len() as u64);
+
+        events
+    }
+
+    fn users_table() -> TableMapBuilder {
+        // COLUMN_NAME：类型4，长度，之后每个字段名带1个字节的长度
+        let mut optional_metadata = vec![4u8, 0];
+        for name in ["id", "name", "score"] {
+            optional_metadata.push(name.len() as u8);
+            optional_metadata.extend(name.as_bytes());
+        }
+        optional_metadata[1] = (optional_metadata.len() - 2) as u8;
+
+        TableMapBuilder::new("shop", "users")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Double, &[8])
+            .nullable(true)
+            .optional_metadata(&optional_metadata)
+    }
+
+    fn string(value: &str) -> ColumnValue {
+        ColumnValue::String(value.to_string())
+    }
+
+    #[test]
+    fn binlog_builder_writes_positions_and_checksums() {
+        let binlog = BinlogBuilder::with_server_version("8.0.36")
+            .event(&QueryEventBuilder::new("db", "BEGIN"))
+            .push(EventBuilder::new(16, 7u64.to_le_bytes().to_vec()).timestamp(1700000000))
+            .build();
+
+        let events = parse(&binlog);
+        assert_eq!(
+            events
+                .iter()
+                .map(|v| v.header.type_code)
+                .collect::<Vec<u8>>(),
+            vec![15, 2, 16]
+        );
+
+        for event in &events {
+            let start = event.position as usize;
+            let end = start + event.header.event_length as usize;
+            assert_eq!(event.header.next_event_position as usize, end);
+
+            let checksum = u32::from_le_bytes(binlog[end - 4..end].try_into().unwrap());
+            assert_eq!(checksum, crc32fast::hash(&binlog[start..end - 4]));
+        }
+
+        let format_description = events[0]
+            .body
+            .downcast_ref::<EventBodyTypeCode15>()
+            .unwrap();
+        assert_eq!(format_description.server_version, "8.0.36");
+        assert_eq!(format_description.header_length, EVENT_HEADER_LENGTH as u8);
+        assert_eq!(format_description.checksum_algorithm, 1);
+
+        let xid = events[2]
+            .body
+            .downcast_ref::<EventBodyTypeCode16>()
+            .unwrap();
+        assert_eq!(xid.xid_transaction_number, 7);
+        assert_eq!(events[2].header.timestamp, 1700000000);
+    }
+
+    #[test]
+    fn event_builder_overrides_header() {
+        let event = EventBuilder::new(16, 1u64.to_le_bytes().to_vec())
+            .server_id(9)
+            .flags(0x40)
+            .event_length(100)
+            .checksum(0xdeadbeef)
+            .build(4);
+
+        assert_eq!(event[5..9], 9u32.to_le_bytes());
+        assert_eq!(event[9..13], 100u32.to_le_bytes());
+        assert_eq!(event[13..17], 104u32.to_le_bytes());
+        assert_eq!(event[17..19], 0x40u16.to_le_bytes());
+        assert_eq!(event[event.len() - 4..], 0xdeadbeefu32.to_le_bytes());
+
+        let binlog = BinlogBuilder::empty().raw(&[1, 2, 3]);
+        assert_eq!(binlog.position(), 7);
+    }
+
+    #[test]
+    fn query_event_builder_round_trip() {
+        let query = QueryEventBuilder::new("shop", "UPDATE t SET a = 1")
+            .thread_id(42)
+            .execute_time(3)
+            .error_code(1062)
+            .status_variables(&[0x80, 0x01, 0x02, 0x03]);
+        let events = parse(&BinlogBuilder::new().event(&query).build());
+
+        let body = events[1].body.downcast_ref::<EventBodyTypeCode2>().unwrap();
+        assert_eq!(body.id_of_thread, 42);
+        assert_eq!(body.execute_time, 3);
+        assert_eq!(body.error_code, 1062);
+        assert_eq!(body.status_variables, vec![0x80, 0x01, 0x02, 0x03]);
+        assert_eq!(body.hrnow, Some(0x030201));
+        assert_eq!(body.database_name, "shop");
+        assert_eq!(body.sql, "UPDATE t SET a = 1");
+    }
+
+    #[test]
+    fn gtid_event_builder_round_trip() {
+        let events = parse(
+            &BinlogBuilder::new()
+                .push(gtid(5))
+                .event(&GtidEventBuilder::new(6).domain_id(2).commit_id(42))
+                .event(&GtidEventBuilder::new(7).flags(64).xa(1, b"abc", b"de"))
+                .push(xid(9))
+                .build(),
+        );
+
+        let gtids = events[1..4]
+            .iter()
+            .map(|v| v.body.downcast_ref::<EventBodyTypeCode162>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(gtids[0].gtid_sequence, 5);
+        assert_eq!(gtids[0].replication_domain_id, 0);
+        assert_eq!(gtids[0].flags, 0);
+        assert_eq!(gtids[0].commit_id, None);
+        assert_eq!(gtids[1].replication_domain_id, 2);
+        assert_eq!(gtids[1].flags, 2);
+        assert_eq!(gtids[1].commit_id, Some(42));
+        assert_eq!(gtids[2].flags, 64);
+        assert_eq!(gtids[2].format_id, Some(1));
+        assert_eq!(gtids[2].xid, Some(b"abcde".to_vec()));
+
+        let xid = events[4]
+            .body
+            .downcast_ref::<EventBodyTypeCode16>()
+            .unwrap();
+        assert_eq!(xid.xid_transaction_number, 9);
+    }
+
+    #[test]
+    fn table_map_builder_round_trip() {
+        let table = users_table();
+        let events = parse(&BinlogBuilder::new().event(&table).build());
+
+        let body = events[1]
+            .body
+            .downcast_ref::<Arc<EventBodyTypeCode19>>()
+            .unwrap();
+        assert_eq!(body.table_id, 70);
+        assert_eq!(body.database_name, "shop");
+        assert_eq!(body.table_name, "users");
+        assert_eq!(
+            body.columns
+                .iter()
+                .map(|v| v.field_type)
+                .collect::<Vec<_>>(),
+            vec![FieldType::Long, FieldType::Varchar, FieldType::Double]
+        );
+        assert_eq!(
+            body.columns.iter().map(|v| v.nullable).collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+        assert_eq!(body.columns[1].metadata_raw, 64u16.to_le_bytes());
+        assert_eq!(
+            body.column_names,
+            Some(vec![
+                "id".to_string(),
+                "name".to_string(),
+                "score".to_string()
+            ])
+        );
+
+        // table_info和解析事件得到的字段定义相同
+        let table_info = table.table_info().unwrap();
+        assert_eq!(
+            serde_json::to_value(&table_info.columns).unwrap(),
+            serde_json::to_value(&body.columns).unwrap()
+        );
+    }
+
+    #[test]
+    fn rows_event_builder_round_trip() {
+        let table = users_table();
+        let rows = vec![
+            vec![
+                ColumnValue::Int(1),
+                string("alice"),
+                ColumnValue::Double(1.5),
+            ],
+            vec![ColumnValue::Int(-2), ColumnValue::Null, ColumnValue::Null],
+        ];
+        let before = vec![ColumnValue::Int(1), string("alice"), ColumnValue::Null];
+        let after = vec![ColumnValue::Int(1), string("bob"), ColumnValue::Double(2.0)];
+
+        let mut insert = RowsEventBuilder::insert(&table);
+        for row in &rows {
+            insert = insert.row(row.clone());
+        }
+        let binlog = BinlogBuilder::new()
+            .event(&table)
+            .event(&insert)
+            .event(&RowsEventBuilder::update(&table).update_row(before.clone(), after.clone()))
+            .event(&RowsEventBuilder::delete(&table).row(rows[0].clone()))
+            .build();
+        let events = parse(&binlog);
+
+        let rows_event = |index: usize| {
+            events[index]
+                .body
+                .downcast_ref::<EventBodyTypeCode23To25>()
+                .unwrap()
+        };
+
+        let inserted = rows_event(2);
+        assert_eq!(events[2].header.type_code, 23);
+        assert_eq!(inserted.table_id, 70);
+        assert_eq!(inserted.number_of_rows, 2);
+        let values = inserted.rows.iter().map(|v| v.after.clone().unwrap());
+        assert_eq!(values.collect::<Vec<_>>(), rows);
+        assert!(inserted.rows.iter().all(|v| v.before.is_none()));
+
+        let updated = rows_event(3);
+        assert_eq!(events[3].header.type_code, 24);
+        assert_eq!(updated.rows[0].before, Some(before));
+        assert_eq!(updated.rows[0].after, Some(after));
+
+        let deleted = rows_event(4);
+        assert_eq!(events[4].header.type_code, 25);
+        assert_eq!(deleted.rows[0].before, Some(rows[0].clone()));
+        assert_eq!(deleted.rows[0].after, None);
+    }
+
+    #[test]
+    fn rows_event_builder_v2_and_minimal_image() {
+        let table = users_table();
+        let update = RowsEventBuilder::update(&table)
+            .v2(true)
+            .columns_used(&[true, false, false])
+            .columns_used_for_update(&[false, true, false])
+            .update_row(
+                vec![ColumnValue::Int(5), string("old"), ColumnValue::Null],
+                vec![ColumnValue::Int(5), string("new"), ColumnValue::Null],
+            );
+        let events = parse(&BinlogBuilder::new().event(&table).event(&update).build());
+
+        assert_eq!(events[2].header.type_code, 31);
+        let body = events[2]
+            .body
+            .downcast_ref::<EventBodyTypeCode23To25>()
+            .unwrap();
+        assert_eq!(body.number_of_rows, 1);
+        assert_eq!(
+            body.rows[0].before.as_ref().unwrap()[0],
+            ColumnValue::Int(5)
+        );
+        assert_eq!(body.rows[0].after.as_ref().unwrap()[1], string("new"));
+    }
+}
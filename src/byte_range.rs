@@ -0,0 +1,411 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+#[cfg(feature = "s3")]
+use std::time::Duration;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 可以按范围读取的数据，BinlogReader::from_source在它上面读取binlog
+///
+/// 对象存储这类每次请求都有延迟的数据源，BinlogReader顺序读取时每次请求一大段，
+/// seek只记录位置，从checkpoint继续、`summary`读取文件末尾这些只会请求需要的范围
+pub trait ByteRangeSource: Send {
+    /// 数据的总长度，打开时就确定，之后追加的内容读不到
+    fn length(&self) -> u64;
+
+    /// 从offset开始的最多length个字节，只有到结尾时才比length短
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>>;
+}
+
+/// 本地文件，主要用于和远程的数据源比较
+pub struct LocalFileSource {
+    file: File,
+    length: u64,
+}
+
+impl LocalFileSource {
+    pub fn open(file_path: &str) -> io::Result<LocalFileSource> {
+        let file = File::open(file_path)?;
+        let length = file.metadata()?.len();
+
+        Ok(LocalFileSource { file, length })
+    }
+}
+
+impl ByteRangeSource for LocalFileSource {
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        let length = length.min(self.length.saturating_sub(offset) as usize);
+        let mut buffer = vec![0u8; length];
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+/// 是否是`http://`、`https://`开头的URL
+pub fn is_url(file_path: &str) -> bool {
+    file_path.starts_with("http://") || file_path.starts_with("https://")
+}
+
+// 预签名URL的query中有签名，错误信息中只保留前面的部分
+#[cfg(feature = "s3")]
+fn redact_url(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+// 一次请求的超时时间，8MB的范围在很慢的网络上也足够
+#[cfg(feature = "s3")]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// 用HTTP的Range请求读取，S3等对象存储使用预签名的URL（`aws s3 presign`），需要编译时打开s3特性
+///
+/// 预签名的URL只能用于GET，所以长度也用`Range: bytes=0-0`的GET从Content-Range中得到
+#[cfg(feature = "s3")]
+pub struct HttpRangeSource {
+    agent: ureq::Agent,
+    url: String,
+    length: u64,
+}
+
+#[cfg(feature = "s3")]
+impl HttpRangeSource {
+    pub fn open(url: &str) -> Result<HttpRangeSource, BoxedError> {
+        let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+
+        let response = get_range(&agent, url, "bytes=0-0")?;
+        let length = response
+            .header("Content-Range")
+            .and_then(|v| v.rsplit('/').next())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "`{}` has no total length in Content-Range",
+                    redact_url(url)
+                ))
+            })?;
+
+        Ok(HttpRangeSource {
+            agent,
+            url: url.to_string(),
+            length,
+        })
+    }
+}
+
+/// 服务端不支持Range时会返回整个文件，这时报错，而不是下载整个文件
+#[cfg(feature = "s3")]
+fn get_range(agent: &ureq::Agent, url: &str, range: &str) -> io::Result<ureq::Response> {
+    let response = agent
+        .get(url)
+        .set("Range", range)
+        .call()
+        .map_err(|e| match e {
+            ureq::Error::Status(status, response) => io::Error::other(format!(
+                "GET `{}` failed with {} {}",
+                redact_url(url),
+                status,
+                response.status_text()
+            )),
+            ureq::Error::Transport(transport) => {
+                io::Error::other(format!("GET `{}` failed: {}", redact_url(url), transport))
+            }
+        })?;
+
+    if response.status() != 206 {
+        return Err(io::Error::other(format!(
+            "`{}` does not support range requests, the server returned {}",
+            redact_url(url),
+            response.status()
+        )));
+    }
+
+    Ok(response)
+}
+
+#[cfg(feature = "s3")]
+impl ByteRangeSource for HttpRangeSource {
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        let length = length.min(self.length.saturating_sub(offset) as usize);
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+
+        let range = format!("bytes={}-{}", offset, offset + length as u64 - 1);
+        let response = get_range(&self.agent, &self.url, &range)?;
+
+        let mut buffer = Vec::with_capacity(length);
+        response
+            .into_reader()
+            .take(length as u64)
+            .read_to_end(&mut buffer)?;
+
+        if buffer.len() != length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "`{}` returned {} bytes for {}",
+                    redact_url(&self.url),
+                    buffer.len(),
+                    range
+                ),
+            ));
+        }
+
+        Ok(buffer)
+    }
+}
+
+/// 按路径打开：URL用HttpRangeSource，其他的是本地文件
+pub fn open_source(file_path: &str) -> Result<Box<dyn ByteRangeSource>, BoxedError> {
+    if !is_url(file_path) {
+        return Ok(Box::new(LocalFileSource::open(file_path)?));
+    }
+
+    #[cfg(feature = "s3")]
+    return Ok(Box::new(HttpRangeSource::open(file_path)?));
+
+    #[cfg(not(feature = "s3"))]
+    Err(Box::new(crate::model::MyError(
+        "reading binlogs from urls is not compiled in, please build with `--features s3`"
+            .to_string(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("byte-range-{}-{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn local_file_source_reads_ranges() {
+        let bytes = (0..100u8).collect::<Vec<_>>();
+        let path = write_temp("local", &bytes);
+        let mut source = LocalFileSource::open(&path).unwrap();
+
+        assert_eq!(source.length(), 100);
+        assert_eq!(source.read_at(10, 5).unwrap(), bytes[10..15]);
+        // 到结尾时比length短，超过结尾时为空
+        assert_eq!(source.read_at(95, 10).unwrap(), bytes[95..]);
+        assert!(source.read_at(100, 10).unwrap().is_empty());
+        assert!(source.read_at(200, 10).unwrap().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn urls_are_detected_by_scheme() {
+        assert!(is_url("http://localhost/binlog.000001"));
+        assert!(is_url(
+            "https://bucket.s3.amazonaws.com/binlog.000001?X-Amz-Signature=x"
+        ));
+        assert!(!is_url("/var/lib/mysql/binlog.000001"));
+        assert!(!is_url("ftp://localhost/binlog.000001"));
+    }
+
+    #[cfg(feature = "s3")]
+    mod http {
+        use std::{
+            collections::HashMap,
+            io::{BufRead, BufReader, Cursor, Write},
+            net::TcpListener,
+            sync::{Arc, Mutex},
+            thread,
+        };
+
+        use super::super::*;
+        use crate::builders::*;
+        use crate::reader::BinlogReader;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+        #[derive(Clone, Copy)]
+        enum Server {
+            // 按Range返回206
+            Ranges,
+            // 不支持Range，总是返回200和整个文件
+            IgnoresRanges,
+            // 返回206，但是内容只有请求的一半
+            ShortReads,
+        }
+
+        // 本地的HTTP服务，返回URL和收到的每个Range，URL中的签名不应该出现在错误信息中
+        fn serve(content: Vec<u8>, server: Server) -> (String, Arc<Mutex<Vec<String>>>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!(
+                "http://{}/binlog.000001?X-Amz-Signature=secret",
+                listener.local_addr().unwrap()
+            );
+            let ranges = Arc::new(Mutex::new(Vec::new()));
+
+            let received = ranges.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut headers = HashMap::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            headers.insert(name.to_lowercase(), value.trim().to_string());
+                        }
+                    }
+
+                    let range = headers.remove("range").unwrap();
+                    received.lock().unwrap().push(range.clone());
+                    let (start, end) = range
+                        .trim_start_matches("bytes=")
+                        .split_once('-')
+                        .map(|(start, end)| {
+                            (
+                                start.parse::<usize>().unwrap(),
+                                end.parse::<usize>().unwrap(),
+                            )
+                        })
+                        .unwrap();
+                    let end = end.min(content.len() - 1);
+
+                    let (status, body) = match server {
+                        Server::Ranges => ("206 Partial Content", &content[start..=end]),
+                        Server::IgnoresRanges => ("200 OK", &content[..]),
+                        Server::ShortReads => {
+                            let length = ((end + 1 - start) / 2).max(1);
+                            ("206 Partial Content", &content[start..start + length])
+                        }
+                    };
+                    write!(
+                        stream,
+                        "HTTP/1.1 {}\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        start,
+                        end,
+                        content.len(),
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(body).unwrap();
+                }
+            });
+
+            (url, ranges)
+        }
+
+        // 每个事件的输出
+        fn events<R: Read + Seek>(file: &mut R) -> Vec<String> {
+            let file_length = file.seek(SeekFrom::End(0)).unwrap();
+            let mut table_structs = HashMap::new();
+            let mut events = Vec::new();
+
+            let mut position = 4;
+            while position < file_length {
+                let header = get_event_header(file, position).unwrap();
+                let body =
+                    get_event_body(file, position, &header, &mut table_structs, CHECKSUM_LENGTH)
+                        .unwrap();
+                events.push(format!("{:?}", body));
+                position = header.next_event_position as u64;
+            }
+
+            events
+        }
+
+        #[test]
+        fn partial_content_is_read_by_range() {
+            let bytes = (0..100u8).collect::<Vec<_>>();
+            let (url, ranges) = serve(bytes.clone(), Server::Ranges);
+            let mut source = HttpRangeSource::open(&url).unwrap();
+
+            assert_eq!(source.length(), 100);
+            assert_eq!(source.read_at(10, 5).unwrap(), bytes[10..15]);
+            assert_eq!(source.read_at(95, 10).unwrap(), bytes[95..]);
+            // 超过结尾时不请求
+            assert!(source.read_at(100, 10).unwrap().is_empty());
+
+            // 长度来自bytes=0-0的Content-Range
+            assert_eq!(
+                *ranges.lock().unwrap(),
+                vec!["bytes=0-0", "bytes=10-14", "bytes=95-99"]
+            );
+        }
+
+        #[test]
+        fn server_ignoring_ranges_is_an_error() {
+            let (url, ranges) = serve(vec![0; 100], Server::IgnoresRanges);
+
+            let error = HttpRangeSource::open(&url).err().unwrap().to_string();
+            assert!(
+                error.contains("does not support range requests, the server returned 200"),
+                "{}",
+                error
+            );
+            assert!(!error.contains("secret"), "{}", error);
+            assert_eq!(ranges.lock().unwrap().len(), 1);
+        }
+
+        #[test]
+        fn short_read_is_an_error() {
+            let (url, _) = serve(vec![0; 100], Server::ShortReads);
+            let mut source = HttpRangeSource::open(&url).unwrap();
+            assert_eq!(source.length(), 100);
+
+            let error = source.read_at(0, 100).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+            assert!(
+                error
+                    .to_string()
+                    .ends_with("returned 50 bytes for bytes=0-99"),
+                "{}",
+                error
+            );
+            assert!(!error.to_string().contains("secret"), "{}", error);
+        }
+
+        #[test]
+        fn binlog_over_http_is_parsed_like_a_local_file() {
+            let mut binlog = BinlogBuilder::new();
+            for i in 0..100 {
+                binlog = binlog.event(&QueryEventBuilder::new(
+                    "shop",
+                    &format!("DELETE FROM t{}", i),
+                ));
+            }
+            let binlog = binlog.build();
+            let (url, ranges) = serve(binlog.clone(), Server::Ranges);
+
+            let mut file = BinlogReader::from_source(open_source(&url).unwrap());
+            assert_eq!(file.file_length().unwrap(), binlog.len() as u64);
+            let expected = events(&mut Cursor::new(&binlog));
+            assert_eq!(expected.len(), 101);
+            assert_eq!(events(&mut file), expected);
+
+            // 顺序读取时一次请求就读完了整个文件
+            assert_eq!(
+                *ranges.lock().unwrap(),
+                vec![
+                    "bytes=0-0".to_string(),
+                    format!("bytes=4-{}", binlog.len() - 1)
+                ]
+            );
+        }
+    }
+}
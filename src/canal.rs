@@ -0,0 +1,375 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::ddl::{ddl_target, DdlStatement};
+use crate::model::*;
+use crate::table_structure::{column_names, column_type_definitions, primary_key_columns};
+
+/// 将行事件转换成Canal的FlatMessage，每个行事件一个，事件中的所有行都在data里
+pub struct CanalConverter {
+    next_id: u64,
+    current_gtid: Option<String>,
+}
+
+impl Default for CanalConverter {
+    fn default() -> Self {
+        CanalConverter {
+            next_id: 1,
+            current_gtid: None,
+        }
+    }
+}
+
+impl CanalConverter {
+    pub fn new() -> CanalConverter {
+        CanalConverter::default()
+    }
+
+    /// 非行事件只会更新上下文，返回None
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Option<Value> {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+            return None;
+        }
+        // MySQL的anonymous GTID事件没有GTID
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            self.current_gtid =
+                (gtid_event.gno > 0).then(|| format!("{}:{}", gtid_event.uuid, gtid_event.gno));
+            return None;
+        }
+
+        let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>()?;
+        let table_info = table_structs.get(&rows_event.table_id)?;
+
+        let message_type = match header.type_code {
+            23 | 30 => "INSERT",
+            24 | 31 => "UPDATE",
+            _ => "DELETE",
+        };
+
+        let names = column_names(table_info);
+        let definitions = column_type_definitions(table_info);
+
+        // delete是删除的行，insert和update是修改后的行，update的old是改变了的字段修改前的值
+        let rows = rows_event.rows(table_info).ok()?;
+        let data: Vec<Value> = rows
+            .iter()
+            .map(|row| {
+                let mut object = Map::new();
+                for (name, value) in row.columns() {
+                    object.insert(name.to_string(), column_value_to_string(value));
+                }
+                Value::Object(object)
+            })
+            .collect();
+
+        let mut old = Vec::new();
+        for (before, after) in rows_event.pairs(table_info).ok()? {
+            let mut object = Map::new();
+            for index in before.changed_columns(&after) {
+                // MINIMAL时before中没有这个字段，不知道原来的值
+                if let (Some(name), Some(value)) = (names.get(index), before.get(index)) {
+                    object.insert(name.clone(), column_value_to_string(value));
+                }
+            }
+            old.push(Value::Object(object));
+        }
+
+        let mut mysql_type = Map::new();
+        let mut sql_type = Map::new();
+        for (name, definition) in names.iter().zip(&definitions) {
+            mysql_type.insert(name.clone(), json!(definition.to_lowercase()));
+            sql_type.insert(name.clone(), json!(java_sql_type(definition)));
+        }
+
+        let pk_names = primary_key_columns(table_info).map(|columns| {
+            columns
+                .iter()
+                .filter_map(|v| names.get(*v).cloned())
+                .collect::<Vec<String>>()
+        });
+
+        // 和Canal一样按字段名排序
+        Some(json!({
+            "data": data,
+            "database": table_info.database_name,
+            "es": header.timestamp as u64 * 1000,
+            "gtid": self.current_gtid.clone().unwrap_or_default(),
+            "id": self.take_id(),
+            "isDdl": false,
+            "mysqlType": mysql_type,
+            "old": if matches!(header.type_code, 24 | 31) { Some(old) } else { None },
+            "pkNames": pk_names,
+            "sql": "",
+            "sqlType": sql_type,
+            "table": table_info.table_name,
+            "ts": now_millis(),
+            "type": message_type,
+        }))
+    }
+
+    /// DDL输出为isDdl为true的FlatMessage
+    pub fn schema_change(&mut self, statement: &DdlStatement) -> Value {
+        let target = ddl_target(&statement.sql);
+
+        let message_type = match (target.action.as_str(), target.object.as_str()) {
+            ("CREATE", "INDEX") => "CINDEX",
+            ("DROP", "INDEX") => "DINDEX",
+            ("CREATE", _) => "CREATE",
+            ("ALTER", _) => "ALTER",
+            ("DROP", _) => "ERASE",
+            ("RENAME", _) => "RENAME",
+            ("TRUNCATE", _) => "TRUNCATE",
+            _ => "QUERY",
+        };
+
+        let table = match target.object.as_str() {
+            "DATABASE" | "SCHEMA" => None,
+            _ => target.name.clone(),
+        };
+
+        json!({
+            "data": null,
+            "database": target.database_name.unwrap_or_else(|| statement.database_name.clone()),
+            "es": statement.timestamp as u64 * 1000,
+            "gtid": statement.gtid.clone().unwrap_or_default(),
+            "id": self.take_id(),
+            "isDdl": true,
+            "mysqlType": null,
+            "old": null,
+            "pkNames": null,
+            "sql": statement.sql,
+            "sqlType": null,
+            "table": table.unwrap_or_default(),
+            "ts": now_millis(),
+            "type": message_type,
+        })
+    }
+
+    fn take_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+/// Canal的值都是字符串，二进制数据按ISO-8859-1转换成字符串
+fn column_value_to_string(value: &ColumnValue) -> Value {
+    match value {
+        ColumnValue::Null | ColumnValue::Unsupported(_) => Value::Null,
+        ColumnValue::Int(v) => json!(v.to_string()),
+        ColumnValue::UInt(v) => json!(v.to_string()),
+        ColumnValue::Float(v) => json!(v.to_string()),
+        ColumnValue::Double(v) => json!(v.to_string()),
+        ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => json!(v),
+        ColumnValue::RawTemporal(v) => json!(v.to_string()),
+        ColumnValue::RawDecimal(v) => json!(v.value),
+        ColumnValue::Bytes(v) => json!(v.iter().map(|v| *v as char).collect::<String>()),
+    }
+}
+
+/// 按类型定义得到java.sql.Types中的值，和Canal一样无符号整数使用更大的类型
+fn java_sql_type(definition: &str) -> i32 {
+    let unsigned = definition.contains(" UNSIGNED");
+    let name = definition.split(['(', ' ']).next().unwrap_or_default();
+
+    match (name, unsigned) {
+        ("TINYINT", false) => -6,
+        ("TINYINT", true) | ("SMALLINT", false) => 5,
+        ("SMALLINT", true) | ("MEDIUMINT", _) | ("INT", false) => 4,
+        ("INT", true) | ("BIGINT", false) => -5,
+        ("BIGINT", true) | ("DECIMAL", _) => 3,
+        ("FLOAT", _) => 7,
+        ("DOUBLE", _) => 8,
+        ("DATE", _) => 91,
+        ("TIME", _) => 92,
+        ("DATETIME", _) | ("TIMESTAMP", _) => 93,
+        ("CHAR", _) => 1,
+        ("VARCHAR", _) | ("YEAR", _) | ("JSON", _) | ("ENUM", _) | ("SET", _) => 12,
+        ("BINARY", _) | ("GEOMETRY", _) => -2,
+        ("VARBINARY", _) => -3,
+        ("BIT", _) => -7,
+        ("TINYBLOB", _) | ("BLOB", _) | ("MEDIUMBLOB", _) | ("LONGBLOB", _) => 2004,
+        ("TINYTEXT", _) | ("TEXT", _) | ("MEDIUMTEXT", _) | ("LONGTEXT", _) => 2005,
+        _ => 1111,
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    // test.t，主键是id，有字段名
+    fn table() -> TableMapBuilder {
+        let mut optional_metadata = vec![4u8, 0];
+        for name in ["id", "name"] {
+            optional_metadata.push(name.len() as u8);
+            optional_metadata.extend(name.as_bytes());
+        }
+        optional_metadata[1] = (optional_metadata.len() - 2) as u8;
+        optional_metadata.extend([8, 1, 0]);
+
+        TableMapBuilder::new("test", "t")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .optional_metadata(&optional_metadata)
+    }
+
+    fn row(id: i64, name: &str) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(id), ColumnValue::String(name.to_string())]
+    }
+
+    // ts是转换的时间，不参与比较
+    fn convert(binlog: &[u8]) -> Vec<Value> {
+        let mut converter = CanalConverter::new();
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut table_structs = HashMap::new();
+        let mut messages = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            if let Some(mut message) = converter.process(&header, body.as_ref(), &table_structs) {
+                message.as_object_mut().unwrap().remove("ts");
+                messages.push(message);
+            }
+            position = header.next_event_position as u64;
+        }
+
+        messages
+    }
+
+    // Canal文档中的FlatMessage，gtid是MariaDB的GTID
+    #[test]
+    fn golden_canal_messages() {
+        let table = table();
+        let mut gtid = 5u64.to_le_bytes().to_vec();
+        gtid.extend(0u32.to_le_bytes());
+        gtid.push(0);
+        gtid.extend([0; 6]);
+
+        let binlog = BinlogBuilder::new()
+            .push(EventBuilder::new(162, gtid))
+            .event(&table)
+            .push(
+                EventBuilder::from_body(
+                    &RowsEventBuilder::insert(&table)
+                        .row(row(1, "a"))
+                        .row(row(2, "b")),
+                )
+                .timestamp(1589373515),
+            )
+            .push(
+                EventBuilder::from_body(
+                    &RowsEventBuilder::update(&table).update_row(row(1, "a"), row(1, "c")),
+                )
+                .timestamp(1589373516),
+            )
+            .build();
+
+        let mysql_type = json!({"id": "int", "name": "varchar(255)"});
+        let sql_type = json!({"id": 4, "name": 12});
+        assert_eq!(
+            convert(&binlog),
+            vec![
+                json!({
+                    "data": [{"id": "1", "name": "a"}, {"id": "2", "name": "b"}],
+                    "database": "test",
+                    "es": 1589373515000u64,
+                    "gtid": "0-1-5",
+                    "id": 1,
+                    "isDdl": false,
+                    "mysqlType": mysql_type,
+                    "old": null,
+                    "pkNames": ["id"],
+                    "sql": "",
+                    "sqlType": sql_type,
+                    "table": "t",
+                    "type": "INSERT"
+                }),
+                json!({
+                    "data": [{"id": "1", "name": "c"}],
+                    "database": "test",
+                    "es": 1589373516000u64,
+                    "gtid": "0-1-5",
+                    "id": 2,
+                    "isDdl": false,
+                    "mysqlType": mysql_type,
+                    "old": [{"name": "a"}],
+                    "pkNames": ["id"],
+                    "sql": "",
+                    "sqlType": sql_type,
+                    "table": "t",
+                    "type": "UPDATE"
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn java_sql_types() {
+        assert_eq!(java_sql_type("TINYINT"), -6);
+        assert_eq!(java_sql_type("TINYINT UNSIGNED"), 5);
+        assert_eq!(java_sql_type("INT UNSIGNED"), -5);
+        assert_eq!(java_sql_type("BIGINT UNSIGNED"), 3);
+        assert_eq!(java_sql_type("DECIMAL(10,2)"), 3);
+        assert_eq!(java_sql_type("DATETIME(6)"), 93);
+        assert_eq!(java_sql_type("VARBINARY(16)"), -3);
+        assert_eq!(java_sql_type("MEDIUMTEXT"), 2005);
+        assert_eq!(java_sql_type("POINT"), 1111);
+    }
+
+    #[test]
+    fn ddl_messages() {
+        let mut converter = CanalConverter::new();
+        let message = converter.schema_change(&DdlStatement {
+            position: 120,
+            timestamp: 1589373517,
+            server_id: 1,
+            gtid: Some("0-1-6".to_string()),
+            database_name: "test".to_string(),
+            sql: "CREATE INDEX idx_name ON t (name)".to_string(),
+        });
+
+        assert_eq!(message["isDdl"], true);
+        assert_eq!(message["type"], "CINDEX");
+        assert_eq!(message["database"], "test");
+        assert_eq!(message["gtid"], "0-1-6");
+        assert_eq!(message["es"], 1589373517000u64);
+        assert_eq!(message["data"], Value::Null);
+    }
+}
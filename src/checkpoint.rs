@@ -0,0 +1,95 @@
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::model::MyError;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 已经处理完成的位置，重启之后从这里继续
+/// position总是某个事务的起始位置（GTID事件的位置），这样继续解析时不会缺少table map
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub file_name: String,
+    pub position: u64,
+}
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 控制checkpoint的写入频率，每个事务都fsync一次代价太大
+pub struct CheckpointWriter {
+    checkpoint_file_path: String,
+    saved_at: Option<Instant>,
+}
+
+impl CheckpointWriter {
+    pub fn new(checkpoint_file_path: &str) -> CheckpointWriter {
+        CheckpointWriter {
+            checkpoint_file_path: checkpoint_file_path.to_string(),
+            saved_at: None,
+        }
+    }
+
+    /// 距离上次写入不到1秒时直接忽略
+    pub fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), BoxedError> {
+        if self.saved_at.is_some_and(|v| v.elapsed() < SAVE_INTERVAL) {
+            return Ok(());
+        }
+
+        self.save_now(checkpoint)
+    }
+
+    pub fn save_now(&mut self, checkpoint: &Checkpoint) -> Result<(), BoxedError> {
+        save_checkpoint(&self.checkpoint_file_path, checkpoint)?;
+        self.saved_at = Some(Instant::now());
+
+        Ok(())
+    }
+}
+
+/// 文件不存在时返回None
+pub fn load_checkpoint(checkpoint_file_path: &str) -> Result<Option<Checkpoint>, BoxedError> {
+    let content = match fs::read_to_string(checkpoint_file_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    // 格式为`文件名 位置`
+    let (file_name, position) = content.trim().rsplit_once(' ').ok_or_else(|| {
+        MyError(format!(
+            "checkpoint file `{}` is broken",
+            checkpoint_file_path
+        ))
+    })?;
+
+    Ok(Some(Checkpoint {
+        file_name: file_name.to_string(),
+        position: position.parse::<u64>()?,
+    }))
+}
+
+/// 先写临时文件并fsync，再rename覆盖，避免写到一半时崩溃留下损坏的checkpoint
+pub fn save_checkpoint(
+    checkpoint_file_path: &str,
+    checkpoint: &Checkpoint,
+) -> Result<(), BoxedError> {
+    let temp_file_path = format!("{}.tmp", checkpoint_file_path);
+
+    let mut f = File::create(&temp_file_path)?;
+    writeln!(f, "{} {}", checkpoint.file_name, checkpoint.position)?;
+    f.sync_all()?;
+
+    fs::rename(&temp_file_path, checkpoint_file_path)?;
+
+    if let Some(parent) = Path::new(checkpoint_file_path).parent() {
+        if let Ok(dir) = File::open(parent) {
+            dir.sync_all().ok();
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::time::{Duration, Instant};
+
+use crate::error::{ErrorCategory, ParseError};
+use crate::flavor::ServerVersion;
+use crate::model::EventHeader;
+use crate::reader::BinlogReader;
+
+// FDE body的最后是1个字节的校验算法和4个字节的校验和
+const BINLOG_CHECKSUM_ALG_CRC32: u8 = 1;
+
+// FDE中server_version的位置，header之后是2字节的binlog版本
+const SERVER_VERSION_RANGE: std::ops::Range<usize> = 21..71;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// `--verify-checksums`，在解析body之前检查每个事件最后4个字节的CRC32
+///
+/// header刚读取过，事件的字节一般都还在BinlogReader的缓冲区里，所以header和body一起借用出来计算一次，
+/// 之后解析body时也是从缓冲区复制，不会再读文件。是否有校验和按文件中的FDE决定，FDE之前默认有
+pub struct ChecksumVerifier {
+    enabled: bool,
+    events: u64,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Default for ChecksumVerifier {
+    fn default() -> Self {
+        ChecksumVerifier {
+            enabled: true,
+            events: 0,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl ChecksumVerifier {
+    pub fn new() -> ChecksumVerifier {
+        ChecksumVerifier::default()
+    }
+
+    /// offset是事件的位置，header已经检查过长度，校验和不一致时返回Corruption
+    pub fn verify(
+        &mut self,
+        file: &mut BinlogReader,
+        offset: u64,
+        header: &EventHeader,
+    ) -> Result<(), BoxedError> {
+        let started_at = Instant::now();
+
+        let event = file.peek(offset, header.event_length as usize)?;
+        let length = event.len();
+
+        // FDE中的校验算法对它自己和之后的事件都有效，没有校验算法的旧版本的binlog不检查
+        if header.type_code == 15 && length >= 5 {
+            let has_checksum_algorithm = event.get(SERVER_VERSION_RANGE).is_some_and(|v| {
+                let server_version = String::from_utf8_lossy(v);
+                ServerVersion::parse(server_version.trim_end_matches('\0')).has_checksum_algorithm()
+            });
+
+            self.enabled = has_checksum_algorithm && event[length - 5] == BINLOG_CHECKSUM_ALG_CRC32;
+        }
+
+        if !self.enabled || length < 4 {
+            return Ok(());
+        }
+
+        let expected = u32::from_le_bytes(event[length - 4..].try_into()?);
+        let computed = crc32fast::hash(&event[..length - 4]);
+
+        self.events += 1;
+        self.bytes += length as u64;
+        self.elapsed += started_at.elapsed();
+
+        if expected != computed {
+            return Err(Box::new(
+                ParseError::new(
+                    ErrorCategory::Corruption,
+                    format!(
+                        "checksum mismatch, the event has {:#010x} but the computed one is {:#010x}",
+                        expected, computed
+                    ),
+                )
+                .at(offset, Some(header.type_code)),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// `--jobs`时合并每个文件的统计，耗时是各个线程的和，所以速度是单个线程的速度
+    pub fn merge(&mut self, other: &ChecksumVerifier) {
+        self.events += other.events;
+        self.bytes += other.bytes;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// 校验的事件数、字节数和速度，和`--analyze`的解析速度一起输出到stderr
+impl std::fmt::Display for ChecksumVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        write!(
+            f,
+            "verified {} checksums over {} bytes in {:.2}s ({:.1} MB/s)",
+            self.events,
+            self.bytes,
+            seconds,
+            self.bytes as f64 / 1_000_000.0 / seconds.max(f64::EPSILON)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::get_event_header;
+
+    // XID事件，最后的校验和用zlib的crc32计算，和服务端写binlog时一样
+    const XID_EVENT: [u8; 31] = [
+        0x00, 0xf1, 0x53, 0x65, 0x10, 0x01, 0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x1f, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x07, 0xf2,
+        0x81,
+    ];
+
+    // 校验文件中的每个事件，返回第一个错误
+    fn verify(binlog: &[u8], name: &str) -> (ChecksumVerifier, Result<(), String>) {
+        let path = std::env::temp_dir().join(format!("checksum-{}-{}", std::process::id(), name));
+        std::fs::write(&path, binlog).unwrap();
+        let mut file = BinlogReader::open(path.to_str().unwrap()).unwrap();
+
+        let mut verifier = ChecksumVerifier::new();
+        let mut result = Ok(());
+        let mut position = 4;
+        while position < binlog.len() as u64 && result.is_ok() {
+            let header = get_event_header(&mut file, position).unwrap();
+            result = verifier
+                .verify(&mut file, position, &header)
+                .map_err(|e| e.to_string());
+            position += header.event_length as u64;
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        (verifier, result)
+    }
+
+    #[test]
+    fn checksums_computed_by_the_server() {
+        assert_eq!(crc32fast::hash(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32fast::hash(&XID_EVENT[..27]), 0x81f20740);
+
+        let binlog = BinlogBuilder::new().raw(&XID_EVENT).build();
+        let (verifier, result) = verify(&binlog, "server");
+        assert_eq!(result, Ok(()));
+        assert_eq!(verifier.events, 2);
+        assert_eq!(verifier.bytes, binlog.len() as u64 - 4);
+    }
+
+    #[test]
+    fn mismatch_is_corruption() {
+        let mut event = XID_EVENT;
+        event[19] = 8;
+        let builder = BinlogBuilder::new();
+        let offset = builder.position();
+        let binlog = builder.raw(&event).build();
+
+        let (_, result) = verify(&binlog, "mismatch");
+        let error = result.unwrap_err();
+        assert!(error.contains(&format!("at {}", offset)), "{}", error);
+        assert!(error.contains("the event has 0x81f20740"), "{}", error);
+    }
+
+    #[test]
+    fn checksum_none_is_not_verified() {
+        let binlog = BinlogBuilder::without_checksum()
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+
+        let (verifier, result) = verify(&binlog, "none");
+        assert_eq!(result, Ok(()));
+        assert_eq!(verifier.events, 0);
+    }
+
+    #[test]
+    fn merged_statistics() {
+        let binlog = BinlogBuilder::new().raw(&XID_EVENT).build();
+        let (mut total, _) = verify(&binlog, "merge");
+        let (other, _) = verify(&binlog, "merge");
+        total.merge(&other);
+
+        assert_eq!(total.events, 4);
+        assert!(total.to_string().starts_with(&format!(
+            "verified 4 checksums over {} bytes",
+            2 * (binlog.len() - 4)
+        )));
+    }
+}
@@ -0,0 +1,387 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::mask::like;
+use crate::model::*;
+use crate::table_structure::{metadata_column_names, primary_key_columns};
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// `--columns`中的一个字段，按名字或者按位置（`#3`是第3个字段，和`col_3`一致）
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnRef {
+    Name(String),
+    Position(usize),
+}
+
+/// `库名.表名:字段1,字段2`，库名和表名可以使用%通配符，不区分大小写
+#[derive(Debug, Clone)]
+pub struct ColumnSelection {
+    pub database: String,
+    pub table: String,
+    pub columns: Vec<ColumnRef>,
+}
+
+impl ColumnSelection {
+    fn matches_table(&self, table_map: &EventBodyTypeCode19) -> bool {
+        like(&self.database, &table_map.database_name) && like(&self.table, &table_map.table_name)
+    }
+}
+
+// 一张表要保留的字段，update和delete还要保留主键，用来定位修改的行
+struct SelectionPlan {
+    selected: Vec<bool>,
+    with_primary_key: Vec<bool>,
+}
+
+/// 按`--columns`只保留行事件中选择的字段，和binlog_row_image=MINIMAL时一样，
+/// 没有选择的字段从columns_used中去掉，值为NULL，所有的输出格式都按columns_used只输出镜像中的字段
+/// 按原来的库名匹配（在--rewrite-db之前），字段名来自table map中的元数据
+#[derive(Default)]
+pub struct ColumnSelector {
+    selections: Vec<ColumnSelection>,
+    // table_id -> 要保留的字段，在table map事件时确定，没有匹配的表为None
+    plans: HashMap<u64, Option<SelectionPlan>>,
+}
+
+impl ColumnSelector {
+    pub fn new(selections: &[ColumnSelection]) -> ColumnSelector {
+        ColumnSelector {
+            selections: selections.to_vec(),
+            plans: HashMap::new(),
+        }
+    }
+
+    /// 每个事件都要经过这里，以便记录每张表要保留的字段
+    /// 按字段名选择但table map中没有字段名、或者没有这个字段时返回错误
+    pub fn select_body(
+        &mut self,
+        body: Box<dyn EventBody>,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Result<Box<dyn EventBody>, BoxedError> {
+        if self.selections.is_empty() {
+            return Ok(body);
+        }
+
+        if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+            let plan = self.plan(table_map)?;
+            self.plans.insert(table_map.table_id, plan);
+            return Ok(body);
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Ok(body),
+        };
+
+        let plan = match self.plans.get(&rows_event.table_id) {
+            Some(Some(plan)) => plan,
+            _ => return Ok(body),
+        };
+
+        let keep = match rows_event.type_string_for_human.as_str() {
+            "insert" => &plan.selected,
+            _ => &plan.with_primary_key,
+        };
+        let is_kept = |index: usize| keep.get(index).copied().unwrap_or(false);
+
+        let mut rows_event = rows_event.clone();
+
+        // 只解析了结构的事件先解码，之后原始的行镜像和去掉字段之后的columns_used不再对应
+        if !rows_event.is_decoded() {
+            if let Some(table_map) = table_structs.get(&rows_event.table_id) {
+                rows_event.rows = rows_event.decode_rows(table_map)?;
+            }
+        }
+        rows_event.row_images = Vec::new();
+
+        rows_event.columns_used = rows_event
+            .columns_used
+            .iter()
+            .enumerate()
+            .map(|(index, used)| used && is_kept(index))
+            .collect();
+        rows_event.columns_used_for_update = rows_event.columns_used_for_update.map(|bitmap| {
+            bitmap
+                .iter()
+                .enumerate()
+                .map(|(index, used)| used && is_kept(index))
+                .collect()
+        });
+
+        for row in rows_event.rows.iter_mut() {
+            for image in row.before.iter_mut().chain(row.after.iter_mut()) {
+                for (index, value) in image.iter_mut().enumerate() {
+                    if !is_kept(index) {
+                        *value = ColumnValue::Null;
+                    }
+                }
+            }
+        }
+
+        // 原有的column_data是第一行的镜像，也要换成保留的字段
+        if let Some(row) = rows_event.rows.first() {
+            if let Some(image) = row.before.as_ref().or(row.after.as_ref()) {
+                rows_event.column_data = column_data_for_human(image);
+            }
+
+            if let (Some(_), Some(after)) = (&row.before, &row.after) {
+                rows_event.column_data_for_update = Some(column_data_for_human(after));
+            }
+        }
+
+        Ok(Box::new(rows_event))
+    }
+
+    /// 这张表所有行事件中可能输出的字段（选择的字段和主键），没有匹配的表为None
+    /// 用于CSV这类固定表头的输出
+    pub fn output_columns(
+        &self,
+        table_map: &EventBodyTypeCode19,
+    ) -> Result<Option<Vec<bool>>, BoxedError> {
+        Ok(self.plan(table_map)?.map(|v| v.with_primary_key))
+    }
+
+    // 同一张表匹配多条规则时保留所有规则中的字段
+    fn plan(&self, table_map: &EventBodyTypeCode19) -> Result<Option<SelectionPlan>, BoxedError> {
+        let column_count = table_map.columns.len();
+        let table = format!("`{}`.`{}`", table_map.database_name, table_map.table_name);
+
+        let selections = self
+            .selections
+            .iter()
+            .filter(|v| v.matches_table(table_map))
+            .collect::<Vec<&ColumnSelection>>();
+        if selections.is_empty() {
+            return Ok(None);
+        }
+
+        let names = metadata_column_names(table_map);
+
+        let mut selected = vec![false; column_count];
+        for column in selections.iter().flat_map(|v| v.columns.iter()) {
+            let index = match column {
+                ColumnRef::Position(position) if (1..=column_count).contains(position) => {
+                    position - 1
+                }
+                ColumnRef::Position(position) => {
+                    return Err(Box::new(MyError(format!(
+                        "`--columns` selects column #{} but {} has {} columns",
+                        position, table, column_count
+                    ))));
+                }
+                ColumnRef::Name(name) => {
+                    let names = names.as_ref().ok_or_else(|| {
+                        MyError(format!(
+                            "`--columns` needs the column names of {}, please set binlog_row_metadata=FULL on the server or use `#N`",
+                            table
+                        ))
+                    })?;
+
+                    names
+                        .iter()
+                        .position(|v| v.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| MyError(format!("{} has no column `{}`", table, name)))?
+                }
+            };
+
+            selected[index] = true;
+        }
+
+        let mut with_primary_key = selected.clone();
+        for index in primary_key_columns(table_map).unwrap_or_default() {
+            if let Some(keep) = with_primary_key.get_mut(index) {
+                *keep = true;
+            }
+        }
+
+        Ok(Some(SelectionPlan {
+            selected,
+            with_primary_key,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    // shop.users(id, name, email, age)，主键是id
+    fn users(with_metadata: bool) -> TableMapBuilder {
+        let mut optional_metadata = Vec::new();
+        if with_metadata {
+            let names = ["id", "name", "email", "age"]
+                .iter()
+                .flat_map(|v| [&[v.len() as u8], v.as_bytes()].concat())
+                .collect::<Vec<u8>>();
+            optional_metadata.extend([4, names.len() as u8]);
+            optional_metadata.extend(names);
+            optional_metadata.extend([8, 1, 0]);
+        }
+
+        TableMapBuilder::new("shop", "users")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .column(FieldType::Long, &[])
+            .nullable(true)
+            .optional_metadata(&optional_metadata)
+    }
+
+    fn user(id: i64, name: &str) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(name.to_string()),
+            ColumnValue::String(format!("{}@example.com", name)),
+            ColumnValue::Int(30),
+        ]
+    }
+
+    fn selection(target: &str, columns: &[ColumnRef]) -> ColumnSelection {
+        let (database, table) = target.split_once('.').unwrap();
+        ColumnSelection {
+            database: database.to_string(),
+            table: table.to_string(),
+            columns: columns.to_vec(),
+        }
+    }
+
+    fn name(name: &str) -> ColumnRef {
+        ColumnRef::Name(name.to_string())
+    }
+
+    type SelectedRow = (Vec<bool>, Vec<ColumnValue>);
+
+    // 每个行事件选择之后的columns_used和第一行
+    fn select(
+        selections: &[ColumnSelection],
+        table: &TableMapBuilder,
+    ) -> Result<Vec<SelectedRow>, BoxedError> {
+        let binlog = BinlogBuilder::new()
+            .event(table)
+            .event(&RowsEventBuilder::insert(table).row(user(1, "ann")))
+            .event(&RowsEventBuilder::update(table).update_row(user(1, "ann"), user(1, "bob")))
+            .event(&RowsEventBuilder::delete(table).row(user(1, "bob")))
+            .build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut selector = ColumnSelector::new(selections);
+        let mut result = Vec::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            let body = selector.select_body(body, &table_structs)?;
+
+            if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+                let row = &rows_event.rows[0];
+                result.push((
+                    rows_event.columns_used.iter().collect(),
+                    row.after.clone().or(row.before.clone()).unwrap(),
+                ));
+            }
+            position = header.next_event_position as u64;
+        }
+
+        Ok(result)
+    }
+
+    #[test]
+    fn primary_key_is_kept_for_updates_and_deletes() {
+        let selected = select(&[selection("shop.users", &[name("NAME")])], &users(true)).unwrap();
+
+        let null = ColumnValue::Null;
+        let bob = ColumnValue::String("bob".to_string());
+        assert_eq!(
+            selected,
+            [
+                // insert只有选择的字段
+                (
+                    vec![false, true, false, false],
+                    vec![
+                        null.clone(),
+                        ColumnValue::String("ann".to_string()),
+                        null.clone(),
+                        null.clone()
+                    ]
+                ),
+                (
+                    vec![true, true, false, false],
+                    vec![ColumnValue::Int(1), bob.clone(), null.clone(), null.clone()]
+                ),
+                (
+                    vec![true, true, false, false],
+                    vec![ColumnValue::Int(1), bob, null.clone(), null]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn positions_without_column_names() {
+        let selected = select(
+            &[selection("shop.user%", &[ColumnRef::Position(3)])],
+            &users(false),
+        )
+        .unwrap();
+        // 没有元数据时不知道主键
+        assert!(selected
+            .iter()
+            .all(|(columns_used, _)| *columns_used == [false, false, true, false]));
+
+        let error = select(&[selection("shop.users", &[name("email")])], &users(false))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("binlog_row_metadata=FULL"));
+
+        let error = select(
+            &[selection("shop.users", &[ColumnRef::Position(5)])],
+            &users(false),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("selects column #5 but `shop`.`users` has 4 columns"));
+    }
+
+    #[test]
+    fn other_tables_and_merged_rules() {
+        // 没有匹配的表不变
+        let selected = select(&[selection("shop.orders", &[name("id")])], &users(true)).unwrap();
+        assert!(selected
+            .iter()
+            .all(|(columns_used, _)| !columns_used.contains(&false)));
+        assert_eq!(selected[0].1, user(1, "ann"));
+
+        // 同一张表的多条规则合并
+        let selections = [
+            selection("shop.users", &[name("email")]),
+            selection("%.users", &[ColumnRef::Position(4)]),
+        ];
+        let selected = select(&selections, &users(true)).unwrap();
+        assert_eq!(selected[0].0, [false, false, true, true]);
+
+        let selector = ColumnSelector::new(&selections);
+        let table_map = users(true).table_info().unwrap();
+        assert_eq!(
+            selector.output_columns(&table_map).unwrap(),
+            Some(vec![true, false, true, true])
+        );
+        let error = select(&[selection("shop.users", &[name("phone")])], &users(true))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("`shop`.`users` has no column `phone`"));
+    }
+}
@@ -0,0 +1,439 @@
+use crate::error::{ErrorCategory, ParseError};
+use crate::gtid::GtidSet;
+use crate::model::*;
+
+// 一个文件结束时的状态，在下一个文件开始时检查
+struct FileState {
+    file_name: String,
+    server_version: Option<String>,
+    // 最后一个事件的类型，正常切换文件时是rotate，服务端关闭时是stop
+    last_type_code: Option<u8>,
+    rotate_to: Option<String>,
+    // 已经检查过这个文件开头的GTID_LIST
+    gtid_list_checked: bool,
+}
+
+impl FileState {
+    fn new(file_name: &str) -> FileState {
+        FileState {
+            file_name: file_name.to_string(),
+            server_version: None,
+            last_type_code: None,
+            rotate_to: None,
+            gtid_list_checked: false,
+        }
+    }
+
+    // 以rotate结束时是同一个服务端继续写下一个文件
+    fn rotated(&self) -> bool {
+        self.last_type_code == Some(4)
+    }
+}
+
+/// `--verify-continuity`，检查`--index`、`--watch`中相邻的两个文件是不是连续的：
+/// 前一个文件最后的rotate指向后一个文件，后一个文件开头的GTID_LIST和前一个文件结束时的GTID一致，
+/// 两个文件的FDE中的服务端版本相同（服务端重启时可以不同）
+///
+/// 发现的问题按Discontinuity返回，带上相关的文件名和GTID。MySQL的binlog没有GTID_LIST，只检查文件名和版本
+#[derive(Default)]
+pub struct ContinuityChecker {
+    previous: Option<FileState>,
+    current: Option<FileState>,
+    // 到目前为止所有文件中的GTID
+    gtids: GtidSet,
+    // start_file发现的问题，和这个文件的第一个事件一起返回
+    pending: Vec<ParseError>,
+}
+
+impl ContinuityChecker {
+    pub fn new() -> ContinuityChecker {
+        ContinuityChecker::default()
+    }
+
+    /// 开始处理下一个文件，检查上一个文件是不是正常切换到这个文件，发现的问题由下一次process返回
+    pub fn start_file(&mut self, file_name: &str) {
+        self.previous = self.current.take();
+        self.current = Some(FileState::new(file_name));
+
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return,
+        };
+
+        let mut issues = Vec::new();
+
+        match (&previous.rotate_to, previous.last_type_code) {
+            (Some(rotate_to), Some(4)) if rotate_to != file_name => issues.push(format!(
+                "`{}` rotates to `{}`, but the next file is `{}`, the files between them are missing",
+                previous.file_name, rotate_to, file_name
+            )),
+            (_, Some(4)) => {}
+            (_, Some(3)) => issues.push(format!(
+                "`{}` ends with a stop event, the server was restarted before `{}`",
+                previous.file_name, file_name
+            )),
+            _ => issues.push(format!(
+                "`{}` does not end with a rotate event, the server crashed or the file is truncated before `{}`",
+                previous.file_name, file_name
+            )),
+        }
+
+        // 没有rotate时按序号判断中间是否缺少文件
+        if !previous.rotated() {
+            if let Some(missing) = missing_file_count(&previous.file_name, file_name) {
+                issues.push(format!(
+                    "{} files between `{}` and `{}` are missing",
+                    missing, previous.file_name, file_name
+                ));
+            }
+        }
+
+        self.pending = issues
+            .into_iter()
+            .map(|v| ParseError::new(ErrorCategory::Discontinuity, v))
+            .collect();
+    }
+
+    /// 按顺序处理每个事件，offset是事件的起始位置
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        offset: u64,
+        body: &dyn EventBody,
+    ) -> Vec<ParseError> {
+        let mut issues = Vec::new();
+
+        let current = match self.current.as_mut() {
+            Some(current) => current,
+            None => return Vec::new(),
+        };
+
+        if let Some(format_description) = body.downcast_ref::<EventBodyTypeCode15>() {
+            let server_version = format_description.server_version.clone();
+
+            // 重启时已经在start_file中报告过了，版本可以不同
+            match &self.previous {
+                Some(previous) if current.server_version.is_none() && previous.rotated() => {
+                    if let Some(previous_version) = &previous.server_version {
+                        if *previous_version != server_version {
+                            issues.push(format!(
+                                "the server version changes from {} in `{}` to {} in `{}` without a restart",
+                                previous_version, previous.file_name, server_version, current.file_name
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            current.server_version = Some(server_version);
+        }
+
+        if let Some(gtid_list) = body.downcast_ref::<EventBodyTypeCode163>() {
+            if let (false, Some(previous)) = (current.gtid_list_checked, &self.previous) {
+                issues.extend(compare_gtid_list(
+                    &self.gtids,
+                    &GtidSet::from(gtid_list),
+                    &previous.file_name,
+                    &current.file_name,
+                ));
+            }
+            current.gtid_list_checked = true;
+        }
+
+        if let Some(rotate) = body.downcast_ref::<EventBodyTypeCode4>() {
+            current.rotate_to = Some(rotate.file_name_of_next_binary_log.clone());
+        }
+
+        current.last_type_code = Some(header.type_code);
+        self.gtids.observe(header, body);
+
+        let issues = issues.into_iter().map(|v| {
+            ParseError::new(ErrorCategory::Discontinuity, v).at(offset, Some(header.type_code))
+        });
+
+        self.pending.drain(..).chain(issues).collect()
+    }
+}
+
+// 下一个文件开头的GTID_LIST是它之前所有事务的位置，应该和上一个文件结束时相同
+fn compare_gtid_list(
+    seen: &GtidSet,
+    gtid_list: &GtidSet,
+    previous_file: &str,
+    file_name: &str,
+) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    for gtid in gtid_list.gtids() {
+        let domain_id = gtid.replication_domain_id;
+
+        match seen.get(domain_id) {
+            Some(last) if last.gtid_sequence < gtid.gtid_sequence => issues.push(format!(
+                "the GTID list of `{}` is at {} but `{}` ends at {}, sequence {} to {} of domain {} are missing",
+                file_name,
+                gtid,
+                previous_file,
+                last,
+                last.gtid_sequence + 1,
+                gtid.gtid_sequence,
+                domain_id
+            )),
+            Some(last) if last.gtid_sequence > gtid.gtid_sequence => issues.push(format!(
+                "the GTID list of `{}` is at {} but `{}` already ends at {}",
+                file_name, gtid, previous_file, last
+            )),
+            Some(_) => {}
+            None => issues.push(format!(
+                "the GTID list of `{}` has {} but domain {} is not in `{}` or the files before it",
+                file_name, gtid, domain_id, previous_file
+            )),
+        }
+    }
+
+    for last in seen.gtids() {
+        if gtid_list.get(last.replication_domain_id).is_none() {
+            issues.push(format!(
+                "the GTID list of `{}` has no domain {}, `{}` ends at {}",
+                file_name, last.replication_domain_id, previous_file, last
+            ));
+        }
+    }
+
+    issues
+}
+
+// `mysql-bin.000001`和`mysql-bin.000004`之间缺少的文件数，前缀不同或者不是序号时为None
+fn missing_file_count(previous: &str, next: &str) -> Option<u64> {
+    let (previous_base, previous_number) = previous.rsplit_once('.')?;
+    let (next_base, next_number) = next.rsplit_once('.')?;
+
+    if previous_base != next_base {
+        return None;
+    }
+
+    let previous_number = previous_number.parse::<u64>().ok()?;
+    let next_number = next_number.parse::<u64>().ok()?;
+
+    next_number
+        .checked_sub(previous_number + 1)
+        .filter(|v| *v > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    fn gtid_list(sequence: u64) -> EventBuilder {
+        let mut body = 1u32.to_le_bytes().to_vec();
+        body.extend(0u32.to_le_bytes());
+        body.extend(1u32.to_le_bytes());
+        body.extend(sequence.to_le_bytes());
+        EventBuilder::new(163, body)
+    }
+
+    fn rotate(file_name: &str) -> EventBuilder {
+        let mut body = 4u64.to_le_bytes().to_vec();
+        body.extend(file_name.as_bytes());
+        EventBuilder::new(4, body)
+    }
+
+    // 开头的GTID_LIST是previous，之后每个事务是GTID和XID，最后是last（rotate或者stop）
+    fn binlog_file(
+        builder: BinlogBuilder,
+        previous: u64,
+        sequences: &[u64],
+        last: Option<EventBuilder>,
+    ) -> Vec<u8> {
+        let mut builder = builder.push(gtid_list(previous));
+        for sequence in sequences {
+            builder = builder.push(gtid(*sequence)).push(xid(*sequence));
+        }
+        match last {
+            Some(last) => builder.push(last).build(),
+            None => builder.build(),
+        }
+    }
+
+    // mysql-bin.000001：11-13，mysql-bin.000002：14、15，mysql-bin.000003：16
+    fn chain() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            (
+                "mysql-bin.000001",
+                binlog_file(
+                    BinlogBuilder::new(),
+                    10,
+                    &[11, 12, 13],
+                    Some(rotate("mysql-bin.000002")),
+                ),
+            ),
+            (
+                "mysql-bin.000002",
+                binlog_file(
+                    BinlogBuilder::new(),
+                    13,
+                    &[14, 15],
+                    Some(rotate("mysql-bin.000003")),
+                ),
+            ),
+            (
+                "mysql-bin.000003",
+                binlog_file(BinlogBuilder::new(), 15, &[16], None),
+            ),
+        ]
+    }
+
+    // 依次处理每个文件，返回发现的问题
+    fn verify(files: &[(&str, Vec<u8>)]) -> Vec<ParseError> {
+        let mut checker = ContinuityChecker::new();
+        let mut issues = Vec::new();
+
+        for (file_name, binlog) in files {
+            checker.start_file(file_name);
+
+            let mut file = Cursor::new(binlog);
+            let mut table_structs = HashMap::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                issues.extend(checker.process(&header, position, body.as_ref()));
+                position = header.next_event_position as u64;
+            }
+        }
+
+        issues
+    }
+
+    fn messages(issues: &[ParseError]) -> Vec<&str> {
+        issues.iter().map(|v| v.message.as_str()).collect()
+    }
+
+    #[test]
+    fn continuous_files() {
+        assert!(verify(&chain()).is_empty());
+    }
+
+    #[test]
+    fn missing_file_in_the_middle() {
+        let mut files = chain();
+        files.remove(1);
+        let issues = verify(&files);
+
+        assert_eq!(
+            messages(&issues),
+            [
+                "`mysql-bin.000001` rotates to `mysql-bin.000002`, but the next file is `mysql-bin.000003`, the files between them are missing",
+                "the GTID list of `mysql-bin.000003` is at 0-1-15 but `mysql-bin.000001` ends at 0-1-13, sequence 14 to 15 of domain 0 are missing",
+            ]
+        );
+        assert!(issues
+            .iter()
+            .all(|v| v.category == ErrorCategory::Discontinuity));
+        // GTID的问题在GTID_LIST事件的位置
+        assert_eq!(issues[0].offset, None);
+        assert_eq!(issues[1].offset, Some(BinlogBuilder::new().position()));
+    }
+
+    #[test]
+    fn restart_and_server_version() {
+        // 服务端关闭后升级，版本不同不算问题
+        let files = [
+            (
+                "mysql-bin.000001",
+                binlog_file(
+                    BinlogBuilder::new(),
+                    10,
+                    &[11],
+                    Some(EventBuilder::new(3, Vec::new())),
+                ),
+            ),
+            (
+                "mysql-bin.000002",
+                binlog_file(
+                    BinlogBuilder::with_server_version("11.4.2-MariaDB-log"),
+                    11,
+                    &[12],
+                    None,
+                ),
+            ),
+        ];
+        assert_eq!(
+            messages(&verify(&files)),
+            ["`mysql-bin.000001` ends with a stop event, the server was restarted before `mysql-bin.000002`"]
+        );
+
+        // 没有重启时版本变化
+        let files = [
+            (
+                "mysql-bin.000001",
+                binlog_file(
+                    BinlogBuilder::new(),
+                    10,
+                    &[11],
+                    Some(rotate("mysql-bin.000002")),
+                ),
+            ),
+            (
+                "mysql-bin.000002",
+                binlog_file(
+                    BinlogBuilder::with_server_version("11.4.2-MariaDB-log"),
+                    11,
+                    &[12],
+                    None,
+                ),
+            ),
+        ];
+        assert_eq!(
+            messages(&verify(&files)),
+            ["the server version changes from 10.11.6-MariaDB-log in `mysql-bin.000001` to 11.4.2-MariaDB-log in `mysql-bin.000002` without a restart"]
+        );
+    }
+
+    #[test]
+    fn truncated_file_and_purge_gap() {
+        // 没有rotate，按序号判断缺少的文件；GTID_LIST落后于上一个文件
+        let files = [
+            (
+                "mysql-bin.000001",
+                binlog_file(BinlogBuilder::new(), 10, &[11, 12], None),
+            ),
+            (
+                "mysql-bin.000004",
+                binlog_file(BinlogBuilder::new(), 11, &[12], None),
+            ),
+        ];
+        assert_eq!(
+            messages(&verify(&files)),
+            [
+                "`mysql-bin.000001` does not end with a rotate event, the server crashed or the file is truncated before `mysql-bin.000004`",
+                "2 files between `mysql-bin.000001` and `mysql-bin.000004` are missing",
+                "the GTID list of `mysql-bin.000004` is at 0-1-11 but `mysql-bin.000001` already ends at 0-1-12",
+            ]
+        );
+
+        assert_eq!(
+            missing_file_count("mysql-bin.000001", "mysql-bin.000002"),
+            None
+        );
+        assert_eq!(
+            missing_file_count("mysql-bin.000001", "relay-bin.000003"),
+            None
+        );
+        assert_eq!(
+            missing_file_count("mysql-bin.000009", "mysql-bin.000012"),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,404 @@
+use serde::Serialize;
+
+use crate::model::*;
+
+const DDL_KEYWORDS: [&str; 5] = ["CREATE", "ALTER", "DROP", "RENAME", "TRUNCATE"];
+
+const GRANT_KEYWORDS: [&str; 2] = ["GRANT", "REVOKE"];
+
+const DDL_OBJECTS: [&str; 10] = [
+    "TABLE",
+    "DATABASE",
+    "SCHEMA",
+    "INDEX",
+    "VIEW",
+    "TRIGGER",
+    "PROCEDURE",
+    "FUNCTION",
+    "EVENT",
+    "SEQUENCE",
+];
+
+/// 一条DDL语句以及它所在的位置和事务
+#[derive(Debug, Serialize)]
+pub struct DdlStatement {
+    pub position: u64,
+    pub timestamp: u32,
+    pub server_id: u32,
+    pub gtid: Option<String>,
+    pub database_name: String,
+    pub sql: String,
+}
+
+/// 从query事件中挑出DDL，需要每个事件都经过process以便记录当前的GTID
+pub struct DdlExtractor {
+    include_grants: bool,
+    current_gtid: Option<String>,
+}
+
+impl DdlExtractor {
+    pub fn new(include_grants: bool) -> DdlExtractor {
+        DdlExtractor {
+            include_grants,
+            current_gtid: None,
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+    ) -> Option<DdlStatement> {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+            return None;
+        }
+        // MySQL的anonymous GTID事件没有GTID
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            self.current_gtid =
+                (gtid_event.gno > 0).then(|| format!("{}:{}", gtid_event.uuid, gtid_event.gno));
+            return None;
+        }
+
+        let query_event = body.downcast_ref::<EventBodyTypeCode2>()?;
+
+        if !is_ddl(&query_event.sql, self.include_grants) {
+            return None;
+        }
+
+        Some(DdlStatement {
+            position,
+            timestamp: header.timestamp,
+            server_id: header.server_id,
+            gtid: self.current_gtid.clone(),
+            database_name: query_event.database_name.clone(),
+            sql: query_event.sql.clone(),
+        })
+    }
+}
+
+/// 按第一个关键字判断是否是DDL，忽略开头的空白和注释
+/// `/*!40101 ... */`这种带版本号的注释会被服务端执行，如果语句只有这样的注释，就按注释里的内容判断
+pub fn is_ddl(sql: &str, include_grants: bool) -> bool {
+    let (rest, executable_comment) = skip_leading_comments(sql);
+
+    let statement = if rest.is_empty() {
+        executable_comment.unwrap_or_default()
+    } else {
+        rest
+    };
+
+    let keyword = statement
+        .split(|v: char| !v.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default()
+        .to_uppercase();
+
+    DDL_KEYWORDS.contains(&keyword.as_str())
+        || (include_grants && GRANT_KEYWORDS.contains(&keyword.as_str()))
+}
+
+/// DDL操作的对象，例如`ALTER TABLE db.t1 ...`是ALTER、TABLE、db、t1
+#[derive(Debug, Clone, PartialEq)]
+pub struct DdlTarget {
+    pub action: String,
+    /// TABLE、DATABASE、INDEX等，没有识别出来时为空
+    pub object: String,
+    pub database_name: Option<String>,
+    pub name: Option<String>,
+}
+
+/// 只按关键字粗略地找出操作和对象的名字，不是完整的SQL解析
+pub fn ddl_target(sql: &str) -> DdlTarget {
+    let (rest, executable_comment) = skip_leading_comments(sql);
+
+    let statement = if rest.is_empty() {
+        executable_comment.unwrap_or_default()
+    } else {
+        rest
+    };
+
+    let words = statement.split_whitespace().collect::<Vec<&str>>();
+    let action = words.first().map(|v| v.to_uppercase()).unwrap_or_default();
+
+    // TRUNCATE可以省略TABLE
+    let object_index = words
+        .iter()
+        .position(|v| DDL_OBJECTS.contains(&v.to_uppercase().as_str()));
+    let (object, mut name_index) = match object_index {
+        Some(index) => (words[index].to_uppercase(), index + 1),
+        None if action == "TRUNCATE" => ("TABLE".to_string(), 1),
+        None => (String::new(), words.len()),
+    };
+
+    while let Some(word) = words.get(name_index) {
+        if ["IF", "NOT", "EXISTS"].contains(&word.to_uppercase().as_str()) {
+            name_index += 1;
+        } else {
+            break;
+        }
+    }
+
+    let full_name = words
+        .get(name_index)
+        .map(|v| v.split(['(', ';']).next().unwrap_or_default())
+        .filter(|v| !v.is_empty());
+
+    let (database_name, name) = match full_name.map(|v| v.split_once('.')) {
+        Some(Some((database_name, name))) => (
+            Some(unquote_identifier(database_name)),
+            Some(unquote_identifier(name)),
+        ),
+        Some(None) => (None, full_name.map(unquote_identifier)),
+        None => (None, None),
+    };
+
+    DdlTarget {
+        action,
+        object,
+        database_name,
+        name,
+    }
+}
+
+fn unquote_identifier(name: &str) -> String {
+    name.trim_matches('`').replace("``", "`")
+}
+
+/// 返回去掉开头注释后的语句，以及第一个带版本号的注释的内容
+fn skip_leading_comments(sql: &str) -> (&str, Option<&str>) {
+    let mut rest = sql.trim_start();
+    let mut executable_comment = None;
+
+    loop {
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let end = match comment.find("*/") {
+                Some(end) => end,
+                None => return ("", executable_comment),
+            };
+
+            if let Some(content) = comment[..end].strip_prefix('!') {
+                executable_comment.get_or_insert(
+                    content
+                        .trim_start_matches(|v: char| v.is_ascii_digit())
+                        .trim(),
+                );
+            }
+
+            rest = comment[end + 2..].trim_start();
+        } else if rest.starts_with("-- ") || rest.starts_with('#') {
+            rest = match rest.find('\n') {
+                Some(end) => rest[end + 1..].trim_start(),
+                None => "",
+            };
+        } else {
+            return (rest, executable_comment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ddl_keywords() {
+        let ddl = [
+            "CREATE TABLE t (id INT)",
+            "create table t (id int)",
+            "  \n\tAlter table t add column c int",
+            "drop database if exists shop",
+            "RENAME TABLE a TO b",
+            "truncate t",
+            "/* app=orders */ ALTER TABLE t ADD INDEX i (c)",
+            "/*!40101 SET NAMES utf8 */ create index i on t (c)",
+            "-- migration 42\ndrop table t",
+            "# comment\nCREATE VIEW v AS SELECT 1",
+            // 只有带版本号的注释时按注释中的语句判断
+            "/*!40000 ALTER TABLE `t` DISABLE KEYS */",
+            "/*!50001 drop view if exists v */",
+        ];
+        for sql in ddl {
+            assert!(is_ddl(sql, false), "{}", sql);
+        }
+
+        let not_ddl = [
+            "BEGIN",
+            "insert into t values (1)",
+            "/*!40101 SET character_set_client = utf8 */",
+            "/* CREATE TABLE t (id INT) */ UPDATE t SET c = 1",
+            "-- drop table t\ndelete from t",
+            "select 'create table'",
+            "GRANT SELECT ON *.* TO 'u'@'%'",
+            "",
+            "/* not closed",
+        ];
+        for sql in not_ddl {
+            assert!(!is_ddl(sql, false), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn grants_are_optional() {
+        for sql in [
+            "GRANT SELECT ON shop.* TO 'u'@'%'",
+            "/*!50000 revoke all privileges on *.* from 'u'@'%' */",
+        ] {
+            assert!(!is_ddl(sql, false), "{}", sql);
+            assert!(is_ddl(sql, true), "{}", sql);
+        }
+        assert!(is_ddl("create table t (id int)", true));
+    }
+
+    #[test]
+    fn targets() {
+        let target =
+            |action: &str, object: &str, database_name: Option<&str>, name: Option<&str>| {
+                DdlTarget {
+                    action: action.to_string(),
+                    object: object.to_string(),
+                    database_name: database_name.map(str::to_string),
+                    name: name.map(str::to_string),
+                }
+            };
+
+        assert_eq!(
+            ddl_target("/*!40101 x */ alter table `shop`.`orders` add c int"),
+            target("ALTER", "TABLE", Some("shop"), Some("orders"))
+        );
+        assert_eq!(
+            ddl_target("CREATE TABLE IF NOT EXISTS orders(id INT)"),
+            target("CREATE", "TABLE", None, Some("orders"))
+        );
+        assert_eq!(
+            ddl_target("truncate shop.orders;"),
+            target("TRUNCATE", "TABLE", Some("shop"), Some("orders"))
+        );
+        assert_eq!(
+            ddl_target("drop database `a``b`"),
+            target("DROP", "DATABASE", None, Some("a`b"))
+        );
+        assert_eq!(
+            ddl_target("RENAME USER a TO b"),
+            target("RENAME", "", None, None)
+        );
+    }
+
+    mod events {
+        use std::collections::HashMap;
+        use std::io::Cursor;
+
+        use super::*;
+        use crate::builders::*;
+        use crate::field_type::FieldType;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+        fn extract(binlog: &[u8]) -> Vec<DdlStatement> {
+            let mut extractor = DdlExtractor::new(false);
+
+            let mut file = Cursor::new(binlog);
+            let mut table_structs = HashMap::new();
+            let mut result = Vec::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                result.extend(extractor.process(&header, position, body.as_ref()));
+
+                position = header.next_event_position as u64;
+            }
+
+            result
+        }
+
+        fn mariadb_gtid(sequence: u64) -> EventBuilder {
+            let mut body = sequence.to_le_bytes().to_vec();
+            body.extend(0u32.to_le_bytes());
+            body.push(1);
+            body.extend([0; 6]);
+
+            EventBuilder::new(162, body).server_id(2)
+        }
+
+        fn mysql_gtid(gno: i64) -> EventBuilder {
+            let mut body = vec![1];
+            body.extend([0x3e; 16]);
+            body.extend(gno.to_le_bytes());
+
+            EventBuilder::new(33, body)
+        }
+
+        // 行事件、table map和DML都不输出
+        fn fixture(binlog: BinlogBuilder, gtid: impl Fn(u64) -> EventBuilder) -> Vec<u8> {
+            let table = TableMapBuilder::new("shop", "orders")
+                .table_id(70)
+                .column(FieldType::Long, &[]);
+
+            binlog
+                .push(gtid(1))
+                .push(
+                    EventBuilder::from_body(&QueryEventBuilder::new(
+                        "shop",
+                        "/*!40101 SET NAMES utf8 */ create table orders (id int)",
+                    ))
+                    .timestamp(1700000000),
+                )
+                .push(gtid(2))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&QueryEventBuilder::new(
+                    "shop",
+                    "insert into orders values (1)",
+                ))
+                .event(&table)
+                .event(&RowsEventBuilder::insert(&table).row(vec![ColumnValue::Int(1)]))
+                .push(EventBuilder::new(16, 2u64.to_le_bytes().to_vec()))
+                .push(gtid(3))
+                .event(&QueryEventBuilder::new(
+                    "",
+                    "ALTER TABLE shop.orders ADD c INT",
+                ))
+                .build()
+        }
+
+        #[test]
+        fn mariadb_ddl() {
+            let statements = extract(&fixture(BinlogBuilder::new(), mariadb_gtid));
+            let summary = statements
+                .iter()
+                .map(|v| (v.gtid.as_deref(), v.database_name.as_str(), v.timestamp))
+                .collect::<Vec<(Option<&str>, &str, u32)>>();
+            assert_eq!(
+                summary,
+                [(Some("0-2-1"), "shop", 1700000000), (Some("0-2-3"), "", 0)]
+            );
+            assert_eq!(statements[1].sql, "ALTER TABLE shop.orders ADD c INT");
+        }
+
+        #[test]
+        fn mysql_ddl() {
+            let binlog = fixture(BinlogBuilder::with_server_version("8.0.36"), |v| {
+                mysql_gtid(v as i64)
+            });
+            let gtids = extract(&binlog)
+                .into_iter()
+                .map(|v| v.gtid)
+                .collect::<Vec<Option<String>>>();
+            let uuid = "3e3e3e3e-3e3e-3e3e-3e3e-3e3e3e3e3e3e";
+            assert_eq!(
+                gtids,
+                [Some(format!("{}:1", uuid)), Some(format!("{}:3", uuid))]
+            );
+        }
+    }
+}
@@ -0,0 +1,562 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::{json, Map, Value};
+
+use crate::ddl::DdlStatement;
+use crate::error::{ErrorCategory, ParseError};
+use crate::model::*;
+use crate::service::get_rows_event_table_info;
+use crate::table_structure::primary_key_columns;
+use crate::util::get_file_name;
+
+/// 将行事件转换成Debezium格式的变更事件（schemas disabled模式，只有payload）
+/// 行事件的key是主键字段组成的对象，和Debezium的消息key一样，没有binlog_row_metadata=FULL时不知道主键，为null
+/// 转换需要依赖前面的GTID事件和ANNOTATE_ROWS事件，所以每个事件都要经过process
+pub struct DebeziumConverter {
+    file_name: String,
+    current_gtid: Option<String>,
+    current_query: Option<String>,
+}
+
+impl DebeziumConverter {
+    pub fn new(binlog_file_path: &str) -> DebeziumConverter {
+        DebeziumConverter {
+            file_name: get_file_name(binlog_file_path),
+            current_gtid: None,
+            current_query: None,
+        }
+    }
+
+    /// 处理多个文件时切换到下一个文件，GTID等上下文保持不变
+    pub fn set_file(&mut self, binlog_file_path: &str) {
+        self.file_name = get_file_name(binlog_file_path);
+    }
+
+    /// position是这个事件在文件中的起始位置
+    /// 非行事件只会更新上下文，返回空的vec
+    /// 行事件没有table map或者行无法解码时返回错误，不会少输出其中的行
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Result<Vec<Value>, ParseError> {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+            self.current_query = None;
+            return Ok(Vec::new());
+        }
+        // MySQL的anonymous GTID事件没有GTID
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            self.current_gtid =
+                (gtid_event.gno > 0).then(|| format!("{}:{}", gtid_event.uuid, gtid_event.gno));
+            self.current_query = None;
+            return Ok(Vec::new());
+        }
+
+        if let Some(annotate_event) = body.downcast_ref::<EventBodyTypeCode160>() {
+            self.current_query = Some(annotate_event.sql.clone());
+            return Ok(Vec::new());
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Ok(Vec::new()),
+        };
+
+        let op = match header.type_code {
+            23 | 30 => "c",
+            24 | 31 => "u",
+            _ => "d",
+        };
+
+        let table_info = get_rows_event_table_info(rows_event.table_id, table_structs)
+            .map_err(|e| ParseError::from_error(e.as_ref()).at(position, Some(header.type_code)))?;
+
+        // delete只有修改前的行，insert只有修改后的行
+        let changes: Vec<(Option<Row>, Option<Row>)> = match header.type_code {
+            24 | 31 => rows_event.pairs(table_info).map(|pairs| {
+                pairs
+                    .map(|(before, after)| (Some(before), Some(after)))
+                    .collect()
+            }),
+            type_code => rows_event.rows(table_info).map(|rows| {
+                rows.into_iter()
+                    .map(|row| match type_code {
+                        23 | 30 => (None, Some(row)),
+                        _ => (Some(row), None),
+                    })
+                    .collect()
+            }),
+        }
+        .map_err(|e| {
+            ParseError::new(ErrorCategory::Corruption, e.to_string())
+                .at(position, Some(header.type_code))
+        })?;
+
+        let primary_key = primary_key_columns(table_info);
+
+        Ok(changes
+            .into_iter()
+            .enumerate()
+            .map(|(row_index, (before, after))| {
+                let source = json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "connector": "mysql",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "ts_ms": header.timestamp as u64 * 1000,
+                    "snapshot": "false",
+                    "db": table_info.database_name,
+                    "table": table_info.table_name,
+                    "server_id": header.server_id,
+                    "gtid": self.current_gtid,
+                    "file": self.file_name,
+                    "pos": position,
+                    "row": row_index,
+                    "query": self.current_query,
+                });
+
+                // delete时用修改前的行，其他时候用修改后的行
+                let key = primary_key.as_ref().and_then(|columns| {
+                    after
+                        .as_ref()
+                        .or(before.as_ref())
+                        .map(|row| key_to_json(row, columns))
+                });
+
+                json!({
+                    "key": key,
+                    "payload": {
+                        "before": before.as_ref().map(row_to_json),
+                        "after": after.as_ref().map(row_to_json),
+                        "source": source,
+                        "op": op,
+                        "ts_ms": header.timestamp as u64 * 1000,
+                    }
+                })
+            })
+            .collect())
+    }
+
+    /// DDL输出为Debezium的schema change事件
+    pub fn schema_change(&self, statement: &DdlStatement) -> Value {
+        let source = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "connector": "mysql",
+            "name": env!("CARGO_PKG_NAME"),
+            "ts_ms": statement.timestamp as u64 * 1000,
+            "snapshot": "false",
+            "db": statement.database_name,
+            "table": null,
+            "server_id": statement.server_id,
+            "gtid": statement.gtid,
+            "file": self.file_name,
+            "pos": statement.position,
+            "row": 0,
+            "query": null,
+        });
+
+        json!({
+            "payload": {
+                "source": source,
+                "ts_ms": statement.timestamp as u64 * 1000,
+                "databaseName": statement.database_name,
+                "schemaName": null,
+                "ddl": statement.sql,
+                "tableChanges": [],
+            }
+        })
+    }
+}
+
+/// 主键字段的名字和值，镜像中没有的主键字段为null
+fn key_to_json(row: &Row, columns: &[usize]) -> Value {
+    let mut object = Map::new();
+
+    for index in columns {
+        let name = match row.column_index().name(*index) {
+            Some(name) => name.to_string(),
+            None => format!("col_{}", index + 1),
+        };
+        object.insert(
+            name,
+            row.get(*index).map(|v| v.to_json()).unwrap_or(Value::Null),
+        );
+    }
+
+    Value::Object(object)
+}
+
+/// 只有镜像中的字段，没有字段名时是col_1、col_2这样的名字
+fn row_to_json(row: &Row) -> Value {
+    let mut object = Map::new();
+
+    for (name, value) in row.columns() {
+        object.insert(name.to_string(), value.to_json());
+    }
+
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{
+        get_event_body, get_event_body_without_row_values, get_event_header, CHECKSUM_LENGTH,
+    };
+
+    // Debezium MySQL connector文档中的create事件（schemas disabled时的payload）
+    fn debezium_create_event() -> Value {
+        json!({
+            "before": null,
+            "after": {
+                "id": 1004,
+                "first_name": "Anne",
+                "last_name": "Kretchmar",
+                "email": "annek@noanswer.org"
+            },
+            "source": {
+                "version": "2.7.0.Final",
+                "connector": "mysql",
+                "name": "mysql-server-1",
+                "ts_ms": 1465491411000u64,
+                "snapshot": "false",
+                "db": "inventory",
+                "sequence": null,
+                "table": "customers",
+                "server_id": 223344,
+                "gtid": null,
+                "file": "mysql-bin.000003",
+                "pos": 484,
+                "row": 0,
+                "thread": 7,
+                "query": "INSERT INTO customers (first_name, last_name, email) VALUES ('Anne', 'Kretchmar', 'annek@noanswer.org')"
+            },
+            "op": "c",
+            "ts_ms": 1465491411815u64,
+            "transaction": null
+        })
+    }
+
+    // inventory.customers，主键是id，有字段名
+    fn customers() -> TableMapBuilder {
+        let mut optional_metadata = vec![4u8, 0];
+        for name in ["id", "first_name", "last_name", "email"] {
+            optional_metadata.push(name.len() as u8);
+            optional_metadata.extend(name.as_bytes());
+        }
+        optional_metadata[1] = (optional_metadata.len() - 2) as u8;
+        // SIMPLE_PRIMARY_KEY：类型8，长度，字段序号
+        optional_metadata.extend([8, 1, 0]);
+
+        TableMapBuilder::new("inventory", "customers")
+            .table_id(108)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .optional_metadata(&optional_metadata)
+    }
+
+    fn customer(id: i64, first_name: &str, email: &str) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(first_name.to_string()),
+            ColumnValue::String("Kretchmar".to_string()),
+            ColumnValue::String(email.to_string()),
+        ]
+    }
+
+    fn convert(binlog: &[u8]) -> Vec<Value> {
+        let mut converter = DebeziumConverter::new("/var/lib/mysql/mysql-bin.000003");
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut table_structs = HashMap::new();
+        let mut envelopes = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            envelopes.extend(
+                converter
+                    .process(&header, position, body.as_ref(), &table_structs)
+                    .unwrap(),
+            );
+            position = header.next_event_position as u64;
+        }
+
+        envelopes
+    }
+
+    fn workload() -> Vec<u8> {
+        let table = customers();
+        let mut gtid = 7u64.to_le_bytes().to_vec();
+        gtid.extend(0u32.to_le_bytes());
+        gtid.push(0);
+        gtid.extend([0u8; 6]);
+        let query = "INSERT INTO customers (first_name, last_name, email) VALUES ('Anne', 'Kretchmar', 'annek@noanswer.org')";
+
+        BinlogBuilder::new()
+            .push(EventBuilder::new(162, gtid).server_id(223344))
+            .push(EventBuilder::new(160, query.as_bytes().to_vec()).server_id(223344))
+            .event(&table)
+            .push(
+                EventBuilder::from_body(&RowsEventBuilder::insert(&table).row(customer(
+                    1004,
+                    "Anne",
+                    "annek@noanswer.org",
+                )))
+                .timestamp(1465491411)
+                .server_id(223344),
+            )
+            .event(
+                &RowsEventBuilder::update(&table)
+                    .update_row(
+                        customer(1004, "Anne", "annek@noanswer.org"),
+                        customer(1004, "Anne Marie", "annek@noanswer.org"),
+                    )
+                    .update_row(
+                        customer(1005, "Bob", "bob@example.com"),
+                        customer(1005, "Bob", "bob@example.org"),
+                    ),
+            )
+            .event(&RowsEventBuilder::delete(&table).row(customer(1005, "Bob", "bob@example.org")))
+            .build()
+    }
+
+    // 字段名都和Debezium的一样，类型相同（Debezium中为null的字段除外）
+    fn assert_same_fields(ours: &Value, debezium: &Value, path: &str) {
+        let (Value::Object(ours), Value::Object(debezium)) = (ours, debezium) else {
+            return;
+        };
+
+        for (name, value) in ours {
+            let path = format!("{}.{}", path, name);
+            let expected = debezium
+                .get(name)
+                .unwrap_or_else(|| panic!("Debezium has no field `{}`", path));
+            match (value, expected) {
+                (_, Value::Null) | (Value::Null, _) => {}
+                (Value::Object(_), Value::Object(_)) => assert_same_fields(value, expected, &path),
+                _ => assert_eq!(
+                    std::mem::discriminant(value),
+                    std::mem::discriminant(expected),
+                    "{}",
+                    path
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn envelope_fields_match_debezium() {
+        let envelopes = convert(&workload());
+        let debezium = debezium_create_event();
+
+        let create = &envelopes[0]["payload"];
+        assert_same_fields(create, &debezium, "payload");
+        for name in ["before", "after", "source", "op", "ts_ms"] {
+            assert!(create.get(name).is_some(), "{}", name);
+        }
+        for name in [
+            "connector",
+            "db",
+            "table",
+            "server_id",
+            "gtid",
+            "file",
+            "pos",
+            "row",
+            "query",
+        ] {
+            assert!(create["source"].get(name).is_some(), "source.{}", name);
+        }
+
+        // 和Debezium的create事件相同的部分
+        for name in ["before", "after", "op"] {
+            assert_eq!(create[name], debezium[name], "{}", name);
+        }
+        let source = &create["source"];
+        for name in [
+            "connector",
+            "snapshot",
+            "db",
+            "table",
+            "server_id",
+            "file",
+            "row",
+            "query",
+        ] {
+            assert_eq!(source[name], debezium["source"][name], "source.{}", name);
+        }
+        assert_eq!(source["gtid"], "0-223344-7");
+        assert_eq!(create["ts_ms"], 1465491411000u64);
+        assert_eq!(envelopes[0]["key"], json!({"id": 1004}));
+    }
+
+    #[test]
+    fn one_envelope_per_row() {
+        let envelopes = convert(&workload());
+        let payloads = envelopes
+            .iter()
+            .map(|v| {
+                (
+                    v["payload"]["op"].clone(),
+                    v["payload"]["source"]["row"].clone(),
+                    v["key"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            payloads,
+            vec![
+                (json!("c"), json!(0), json!({"id": 1004})),
+                (json!("u"), json!(0), json!({"id": 1004})),
+                (json!("u"), json!(1), json!({"id": 1005})),
+                (json!("d"), json!(0), json!({"id": 1005})),
+            ]
+        );
+
+        let update = &envelopes[2]["payload"];
+        assert_eq!(update["before"]["email"], "bob@example.com");
+        assert_eq!(update["after"]["email"], "bob@example.org");
+        // 同一个事件的行有相同的位置
+        assert_eq!(
+            envelopes[1]["payload"]["source"]["pos"],
+            update["source"]["pos"]
+        );
+        let delete = &envelopes[3]["payload"];
+        assert_eq!(delete["after"], Value::Null);
+        assert_eq!(delete["before"]["first_name"], "Bob");
+    }
+
+    // 没有解码字段值的insert事件，在process中才解码
+    fn undecoded_insert() -> (EventHeader, u64, Box<dyn EventBody>) {
+        let binlog = workload();
+        let mut file = Cursor::new(binlog.clone());
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        loop {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body_without_row_values(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            if header.type_code == 23 {
+                return (header, position, body);
+            }
+            position = header.next_event_position as u64;
+        }
+    }
+
+    #[test]
+    fn rows_that_can_not_be_converted_are_errors() {
+        let (header, position, body) = undecoded_insert();
+        let mut converter = DebeziumConverter::new("mysql-bin.000003");
+
+        // 从文件中间开始时没有table map
+        let error = converter
+            .process(&header, position, body.as_ref(), &HashMap::new())
+            .unwrap_err();
+        assert_eq!(error.category, ErrorCategory::Other);
+        assert_eq!(error.offset, Some(position));
+        assert!(error.message.contains("table id 108 is missing"));
+
+        // table map和行的内容不一致
+        let mut table = TableMapBuilder::new("inventory", "customers").table_id(108);
+        for _ in 0..4 {
+            table = table.column(FieldType::LongLong, &[]);
+        }
+        let table_structs = HashMap::from([(108, table.table_info().unwrap())]);
+        let error = converter
+            .process(&header, position, body.as_ref(), &table_structs)
+            .unwrap_err();
+        assert_eq!(error.category, ErrorCategory::Corruption);
+        assert_eq!(error.offset, Some(position));
+    }
+
+    // 字段名和主键的可选元数据，primary_key是类型和值
+    fn keyed_table(
+        name: &str,
+        columns: &[&str],
+        primary_key: Option<(u8, &[u8])>,
+    ) -> TableMapBuilder {
+        let mut names = Vec::new();
+        for column in columns {
+            names.push(column.len() as u8);
+            names.extend(column.as_bytes());
+        }
+        let mut optional_metadata = vec![4u8, names.len() as u8];
+        optional_metadata.extend(names);
+        if let Some((field_type, value)) = primary_key {
+            optional_metadata.extend([field_type, value.len() as u8]);
+            optional_metadata.extend(value);
+        }
+
+        let mut table = TableMapBuilder::new("shop", name).table_id(200);
+        for _ in columns {
+            table = table.column(FieldType::Long, &[]);
+        }
+        table.optional_metadata(&optional_metadata)
+    }
+
+    fn key_of_insert(table: &TableMapBuilder, values: &[i64]) -> Value {
+        let insert = RowsEventBuilder::insert(table)
+            .row(values.iter().map(|v| ColumnValue::Int(*v)).collect());
+        let binlog = BinlogBuilder::new().event(table).event(&insert).build();
+        convert(&binlog)[0]["key"].clone()
+    }
+
+    #[test]
+    fn keys_of_each_primary_key_kind() {
+        // 单个字段的主键
+        let table = keyed_table("users", &["id", "age"], Some((8, &[0])));
+        assert_eq!(table.table_info().unwrap().primary_key, Some(vec![0]));
+        assert_eq!(key_of_insert(&table, &[1, 30]), json!({"id": 1}));
+
+        // 多个字段的主键，按主键中的顺序
+        let table = keyed_table(
+            "order_items",
+            &["qty", "order_id", "line"],
+            Some((8, &[1, 2])),
+        );
+        let table_info = table.table_info().unwrap();
+        assert_eq!(table_info.primary_key, Some(vec![1, 2]));
+        assert_eq!(table_info.primary_key_prefixes, None);
+        assert_eq!(
+            key_of_insert(&table, &[5, 100, 2]),
+            json!({"order_id": 100, "line": 2})
+        );
+
+        // 有前缀索引的主键，key中是整个字段的值
+        let table = keyed_table("notes", &["id", "code"], Some((9, &[0, 0, 1, 10])));
+        let table_info = table.table_info().unwrap();
+        assert_eq!(table_info.primary_key, Some(vec![0, 1]));
+        assert_eq!(table_info.primary_key_prefixes, Some(vec![0, 10]));
+        assert_eq!(key_of_insert(&table, &[7, 8]), json!({"id": 7, "code": 8}));
+
+        // 没有主键时key为null
+        let table = keyed_table("logs", &["id", "message"], None);
+        assert_eq!(table.table_info().unwrap().primary_key, None);
+        assert_eq!(key_of_insert(&table, &[1, 2]), Value::Null);
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt::Display;
+
+use crate::flavor::ServerVersion;
+use crate::schema_drift::SchemaChange;
+
+/// 解析过程中给使用者的提示，库中不直接输出，交给调用方处理，命令行中输出到stderr
+#[derive(Debug, Clone)]
+pub enum Diagnostic {
+    /// FDE中写binlog的服务端，变化时才有
+    ServerVersion(ServerVersion),
+    /// 同一张表的定义在中途变化
+    SchemaChange(SchemaChange),
+    /// 可能有问题但是可以继续处理的情况
+    Warning(String),
+    /// 进度，例如重连、回滚没有完成的事务
+    Note(String),
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::ServerVersion(server_version) => {
+                write!(f, "the binlog is written by {}", server_version)
+            }
+            Diagnostic::SchemaChange(change) => write!(f, "{}", change),
+            Diagnostic::Warning(message) => write!(f, "warning: {}", message),
+            Diagnostic::Note(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// 不关心提示时使用
+pub fn ignore_diagnostic(_: Diagnostic) {}
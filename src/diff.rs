@@ -0,0 +1,380 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::Serialize;
+
+use crate::cli::{BinlogRange, RangeBound};
+use crate::error::ParseError;
+use crate::model::*;
+use crate::reader::BinlogReader;
+use crate::service::*;
+use crate::transaction::{TransactionHasher, TransactionSummary, TransactionTracker};
+use crate::util::*;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 一个事务以及它的内容哈希
+#[derive(Debug, Clone, Serialize)]
+pub struct HashedTransaction {
+    pub gtid: String,
+    pub start_position: u64,
+    pub end_position: u64,
+    pub content_hash: String,
+}
+
+/// 同一个GTID在两边的内容不同
+#[derive(Debug, Serialize)]
+pub struct DifferentTransaction {
+    pub gtid: String,
+    pub left: HashedTransaction,
+    pub right: HashedTransaction,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub left_file: String,
+    pub right_file: String,
+    pub left_transactions: usize,
+    pub right_transactions: usize,
+    pub identical: usize,
+    pub only_in_left: Vec<HashedTransaction>,
+    pub only_in_right: Vec<HashedTransaction>,
+    pub different: Vec<DifferentTransaction>,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.only_in_left.is_empty() && self.only_in_right.is_empty() && self.different.is_empty()
+    }
+}
+
+impl std::fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "left:  {} ({} transactions)",
+            self.left_file, self.left_transactions
+        )?;
+        writeln!(
+            f,
+            "right: {} ({} transactions)",
+            self.right_file, self.right_transactions
+        )?;
+        writeln!(f, "identical: {}", self.identical)?;
+
+        writeln!(f, "only in left: {}", self.only_in_left.len())?;
+        for transaction in &self.only_in_left {
+            writeln!(
+                f,
+                "  gtid={} start={} end={}",
+                transaction.gtid, transaction.start_position, transaction.end_position
+            )?;
+        }
+
+        writeln!(f, "only in right: {}", self.only_in_right.len())?;
+        for transaction in &self.only_in_right {
+            writeln!(
+                f,
+                "  gtid={} start={} end={}",
+                transaction.gtid, transaction.start_position, transaction.end_position
+            )?;
+        }
+
+        write!(f, "different content: {}", self.different.len())?;
+        for transaction in &self.different {
+            write!(
+                f,
+                "\n  gtid={} left start={} hash={} right start={} hash={}",
+                transaction.gtid,
+                transaction.left.start_position,
+                transaction.left.content_hash,
+                transaction.right.start_position,
+                transaction.right.content_hash
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 按GTID比较两个文件中的事务，GTID相同时比较内容哈希
+pub fn diff_binlogs(left: &BinlogRange, right: &BinlogRange) -> Result<DiffReport, BoxedError> {
+    let left_transactions = hash_transactions(left)?;
+    let right_transactions = hash_transactions(right)?;
+
+    let left_by_gtid = index_by_gtid(&left_transactions);
+    let right_by_gtid = index_by_gtid(&right_transactions);
+
+    let mut identical = 0;
+    let mut only_in_left = Vec::new();
+    let mut different = Vec::new();
+
+    for transaction in &left_transactions {
+        match right_by_gtid.get(transaction.gtid.as_str()) {
+            Some(other) if other.content_hash == transaction.content_hash => identical += 1,
+            Some(other) => different.push(DifferentTransaction {
+                gtid: transaction.gtid.clone(),
+                left: transaction.clone(),
+                right: (*other).clone(),
+            }),
+            None => only_in_left.push(transaction.clone()),
+        }
+    }
+
+    let only_in_right = right_transactions
+        .iter()
+        .filter(|v| !left_by_gtid.contains_key(v.gtid.as_str()))
+        .cloned()
+        .collect();
+
+    Ok(DiffReport {
+        left_file: left.file_path.clone(),
+        right_file: right.file_path.clone(),
+        left_transactions: left_transactions.len(),
+        right_transactions: right_transactions.len(),
+        identical,
+        only_in_left,
+        only_in_right,
+        different,
+    })
+}
+
+// 同一个GTID出现多次时使用第一次出现的事务
+fn index_by_gtid(transactions: &[HashedTransaction]) -> HashMap<&str, &HashedTransaction> {
+    let mut result = HashMap::new();
+
+    for transaction in transactions {
+        result
+            .entry(transaction.gtid.as_str())
+            .or_insert(transaction);
+    }
+
+    result
+}
+
+/// 读取范围内的所有事务并计算内容哈希
+/// 按位置时包含从start开始、在stop之前开始的事务，按GTID时start和stop两个事务都包含在内
+pub fn hash_transactions(range: &BinlogRange) -> Result<Vec<HashedTransaction>, BoxedError> {
+    let mut file = BinlogReader::open(&range.file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(&range.file_path)));
+    }
+
+    let file_length = file.file_length()?;
+
+    let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+    let mut transaction_tracker = TransactionTracker::new();
+    let mut transaction_hasher = TransactionHasher::new();
+    let mut transactions: Vec<(TransactionSummary, String)> = Vec::new();
+
+    let mut offset = 4;
+    while offset < file_length {
+        let event_position = offset;
+
+        let header = get_event_header(&mut file, offset)?;
+
+        let body = get_event_body(
+            &mut file,
+            offset,
+            &header,
+            &mut table_structs,
+            checksum_length,
+        )?;
+        update_checksum_length(&mut checksum_length, body.as_ref());
+        offset = header.next_event_position as u64;
+
+        let is_gtid = matches!(header.type_code, 162 | 33);
+        if !is_gtid {
+            transaction_hasher.update(&header, body.as_ref(), &table_structs);
+        }
+
+        // 遇到GTID时返回的是上一个没有提交的事务，哈希里还是它的内容
+        if let Some(summary) =
+            transaction_tracker.process(&header, event_position, body.as_ref(), &table_structs)
+        {
+            transactions.push((summary, transaction_hasher.finish()));
+        }
+
+        if is_gtid {
+            transaction_hasher.reset();
+        }
+    }
+
+    if let Some(summary) = transaction_tracker.finish() {
+        transactions.push((summary, transaction_hasher.finish()));
+    }
+
+    let start_index = match &range.start {
+        None => 0,
+        Some(RangeBound::Position(position)) => transactions
+            .iter()
+            .position(|(v, _)| v.start_position >= *position)
+            .unwrap_or(transactions.len()),
+        Some(RangeBound::Gtid(gtid)) => find_gtid(&transactions, gtid, &range.file_path)?,
+    };
+
+    let stop_index = match &range.stop {
+        None => transactions.len(),
+        Some(RangeBound::Position(position)) => transactions
+            .iter()
+            .position(|(v, _)| v.start_position >= *position)
+            .unwrap_or(transactions.len()),
+        Some(RangeBound::Gtid(gtid)) => find_gtid(&transactions, gtid, &range.file_path)? + 1,
+    };
+
+    Ok(transactions
+        .into_iter()
+        .take(stop_index)
+        .skip(start_index)
+        .map(|(summary, content_hash)| HashedTransaction {
+            gtid: summary.gtid,
+            start_position: summary.start_position,
+            end_position: summary.end_position,
+            content_hash,
+        })
+        .collect())
+}
+
+fn find_gtid(
+    transactions: &[(TransactionSummary, String)],
+    gtid: &str,
+    file_path: &str,
+) -> Result<usize, BoxedError> {
+    transactions
+        .iter()
+        .position(|(v, _)| v.gtid == gtid)
+        .ok_or_else(|| {
+            Box::new(MyError(format!(
+                "gtid `{}` is not found in `{}`",
+                gtid, file_path
+            ))) as BoxedError
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+
+    // 每个事务插入一行，timestamp和写事件的server_id不参与比较
+    fn write_binlog(name: &str, transactions: &[(u64, i64)], timestamp: u32) -> String {
+        let table_map = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+
+        let mut binlog = BinlogBuilder::new();
+        for (sequence, id) in transactions {
+            binlog = binlog
+                .push(gtid(*sequence).timestamp(timestamp))
+                .push(EventBuilder::from_body(&table_map).timestamp(timestamp))
+                .push(
+                    EventBuilder::from_body(
+                        &RowsEventBuilder::insert(&table_map).row(vec![ColumnValue::Int(*id)]),
+                    )
+                    .timestamp(timestamp)
+                    .server_id(timestamp),
+                )
+                .push(EventBuilder::new(16, sequence.to_le_bytes().to_vec()).timestamp(timestamp));
+        }
+
+        let path = std::env::temp_dir().join(format!("diff-{}-{}", std::process::id(), name));
+        std::fs::write(&path, binlog.build()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn range(file_path: &str) -> BinlogRange {
+        BinlogRange {
+            file_path: file_path.to_string(),
+            start: None,
+            stop: None,
+        }
+    }
+
+    #[test]
+    fn copy_missing_one_transaction() {
+        let left = write_binlog("left", &[(1, 10), (2, 20), (3, 30)], 100);
+        let right = write_binlog("right", &[(1, 10), (3, 30)], 200);
+
+        let report = diff_binlogs(&range(&left), &range(&right)).unwrap();
+        assert!(!report.is_identical());
+        assert_eq!(report.left_transactions, 3);
+        assert_eq!(report.right_transactions, 2);
+        assert_eq!(report.identical, 2);
+        assert_eq!(report.only_in_left.len(), 1);
+        assert_eq!(report.only_in_left[0].gtid, "0-1-2");
+        assert!(report.only_in_right.is_empty());
+        assert!(report.different.is_empty());
+        assert!(report.to_string().contains("only in left: 1\n  gtid=0-1-2"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["only_in_left"][0]["gtid"], "0-1-2");
+
+        let report = diff_binlogs(&range(&right), &range(&left)).unwrap();
+        assert_eq!(report.only_in_right.len(), 1);
+
+        std::fs::remove_file(left).unwrap();
+        std::fs::remove_file(right).unwrap();
+    }
+
+    #[test]
+    fn same_gtid_with_different_rows() {
+        let left = write_binlog("same-left", &[(1, 10), (2, 20)], 100);
+        let right = write_binlog("same-right", &[(1, 10), (2, 21)], 100);
+
+        let report = diff_binlogs(&range(&left), &range(&right)).unwrap();
+        assert_eq!(report.identical, 1);
+        assert_eq!(report.different.len(), 1);
+        assert_eq!(report.different[0].gtid, "0-1-2");
+        assert_ne!(
+            report.different[0].left.content_hash,
+            report.different[0].right.content_hash
+        );
+
+        // 哈希在多次运行之间不变
+        let first = hash_transactions(&range(&left)).unwrap();
+        let second = hash_transactions(&range(&left)).unwrap();
+        assert_eq!(
+            first.iter().map(|v| &v.content_hash).collect::<Vec<_>>(),
+            second.iter().map(|v| &v.content_hash).collect::<Vec<_>>()
+        );
+
+        std::fs::remove_file(left).unwrap();
+        std::fs::remove_file(right).unwrap();
+    }
+
+    #[test]
+    fn ranges_by_gtid_and_position() {
+        let file = write_binlog("ranges", &[(1, 10), (2, 20), (3, 30)], 100);
+
+        let all = hash_transactions(&range(&file)).unwrap();
+        let by_gtid = hash_transactions(&BinlogRange {
+            start: Some(RangeBound::Gtid("0-1-2".to_string())),
+            stop: Some(RangeBound::Gtid("0-1-3".to_string())),
+            ..range(&file)
+        })
+        .unwrap();
+        assert_eq!(
+            by_gtid.iter().map(|v| v.gtid.as_str()).collect::<Vec<_>>(),
+            ["0-1-2", "0-1-3"]
+        );
+
+        let by_position = hash_transactions(&BinlogRange {
+            start: Some(RangeBound::Position(all[1].start_position)),
+            stop: Some(RangeBound::Position(all[2].start_position)),
+            ..range(&file)
+        })
+        .unwrap();
+        assert_eq!(by_position.len(), 1);
+        assert_eq!(by_position[0].gtid, "0-1-2");
+
+        assert!(hash_transactions(&BinlogRange {
+            start: Some(RangeBound::Gtid("0-1-9".to_string())),
+            ..range(&file)
+        })
+        .is_err());
+
+        std::fs::remove_file(file).unwrap();
+    }
+}
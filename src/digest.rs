@@ -0,0 +1,235 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Serialize;
+
+use crate::fingerprint::{fingerprint, fingerprint_checksum};
+use crate::model::*;
+use crate::util::format_timestamp;
+
+// 例子中的SQL在human输出中最多显示的字符数
+const MAX_SQL_CHARS: usize = 200;
+
+/// 指纹相同的语句
+#[derive(Debug, Clone, Serialize)]
+pub struct DigestGroup {
+    /// 指纹的sha256的前16个十六进制字符
+    pub checksum: String,
+    pub fingerprint: String,
+    pub count: u64,
+    /// 第一次出现的语句
+    pub example: String,
+    pub first_seen: u32,
+    pub last_seen: u32,
+    /// 语句的当前库，按第一次出现的顺序
+    pub databases: Vec<String>,
+    pub total_execute_time: u64,
+}
+
+impl Display for DigestGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} count={} exec={}s first={} last={} db={}",
+            self.checksum,
+            self.count,
+            self.total_execute_time,
+            format_timestamp(self.first_seen),
+            format_timestamp(self.last_seen),
+            self.databases.join(",")
+        )?;
+        writeln!(f, "    {}", self.fingerprint)?;
+
+        let example = self
+            .example
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let example = match example.char_indices().nth(MAX_SQL_CHARS) {
+            Some((index, _)) => format!("{}...", &example[..index]),
+            None => example,
+        };
+        writeln!(f, "    e.g. {}", example)
+    }
+}
+
+/// `--digest`，query事件和annotate事件中的语句按指纹分组，和pt-query-digest类似
+///
+/// 行格式的binlog中DML只在annotate事件（binlog_annotate_row_events）中，它没有库，用事务中BEGIN的库。
+/// BEGIN、COMMIT和ROLLBACK不计入，execute_time只有query事件有
+#[derive(Default)]
+pub struct DigestReport {
+    // 最近一个query事件的库
+    database: String,
+    groups: HashMap<String, DigestGroup>,
+}
+
+impl DigestReport {
+    pub fn new() -> DigestReport {
+        DigestReport::default()
+    }
+
+    pub fn process(&mut self, header: &EventHeader, body: &dyn EventBody) {
+        let (sql, execute_time) = match body.downcast_ref::<EventBodyTypeCode2>() {
+            Some(query_event) => {
+                self.database = query_event.database_name.clone();
+                (&query_event.sql, query_event.execute_time)
+            }
+            None => match body.downcast_ref::<EventBodyTypeCode160>() {
+                Some(annotate) => (&annotate.sql, 0),
+                None => return,
+            },
+        };
+
+        if ["BEGIN", "COMMIT", "ROLLBACK"]
+            .iter()
+            .any(|v| sql.trim().eq_ignore_ascii_case(v))
+        {
+            return;
+        }
+
+        let fingerprint = fingerprint(sql);
+        let group = self
+            .groups
+            .entry(fingerprint.clone())
+            .or_insert_with(|| DigestGroup {
+                checksum: fingerprint_checksum(&fingerprint),
+                fingerprint,
+                count: 0,
+                example: sql.clone(),
+                first_seen: header.timestamp,
+                last_seen: header.timestamp,
+                databases: Vec::new(),
+                total_execute_time: 0,
+            });
+
+        group.count += 1;
+        group.total_execute_time += execute_time as u64;
+        group.first_seen = group.first_seen.min(header.timestamp);
+        group.last_seen = group.last_seen.max(header.timestamp);
+        if !self.database.is_empty() && !group.databases.contains(&self.database) {
+            group.databases.push(self.database.clone());
+        }
+    }
+
+    /// 按次数从多到少排列，相同时按指纹
+    pub fn groups(&self) -> Vec<&DigestGroup> {
+        let mut groups = self.groups.values().collect::<Vec<&DigestGroup>>();
+        groups.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(a.fingerprint.cmp(&b.fingerprint))
+        });
+        groups
+    }
+}
+
+impl Display for DigestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let groups = self.groups();
+        writeln!(
+            f,
+            "{} statement(s) in {} fingerprint(s)",
+            groups.iter().map(|v| v.count).sum::<u64>(),
+            groups.len()
+        )?;
+
+        for (rank, group) in groups.into_iter().enumerate() {
+            write!(f, "#{} {}", rank + 1, group)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    fn digest(binlog: &[u8]) -> DigestReport {
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut table_structs = HashMap::new();
+        let mut report = DigestReport::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            report.process(&header, body.as_ref());
+            position = header.next_event_position as u64;
+        }
+
+        report
+    }
+
+    fn query(database: &str, sql: &str, timestamp: u32, execute_time: u32) -> EventBuilder {
+        EventBuilder::from_body(&QueryEventBuilder::new(database, sql).execute_time(execute_time))
+            .timestamp(timestamp)
+    }
+
+    // annotate事件没有库，用事务中BEGIN的库
+    fn annotate(sql: &str, timestamp: u32) -> EventBuilder {
+        EventBuilder::new(160, sql.as_bytes().to_vec()).timestamp(timestamp)
+    }
+
+    #[test]
+    fn group_statements_by_fingerprint() {
+        let binlog = BinlogBuilder::new()
+            .push(query("shop", "BEGIN", 100, 0))
+            .push(annotate(
+                "UPDATE orders SET status = 'paid' WHERE id = 1",
+                100,
+            ))
+            .push(query("shop", "COMMIT", 100, 0))
+            .push(query("crm", "BEGIN", 90, 0))
+            .push(annotate("update orders set status='new' where id=22", 90))
+            .push(query("crm", "COMMIT", 90, 0))
+            .push(query("shop", "CREATE TABLE t1 (id INT)", 120, 3))
+            .push(query("shop", "BEGIN", 130, 0))
+            .push(annotate(
+                "UPDATE orders SET status = \"done\" WHERE id = -5",
+                130,
+            ))
+            .push(query("shop", "ROLLBACK", 130, 0))
+            .build();
+
+        let report = digest(&binlog);
+        let groups = report.groups();
+        assert_eq!(groups.len(), 2);
+
+        let update = groups[0];
+        assert_eq!(
+            update.fingerprint,
+            "update orders set status = ? where id = ?"
+        );
+        assert_eq!(update.checksum, fingerprint_checksum(&update.fingerprint));
+        assert_eq!(update.count, 3);
+        assert_eq!(
+            update.example,
+            "UPDATE orders SET status = 'paid' WHERE id = 1"
+        );
+        assert_eq!((update.first_seen, update.last_seen), (90, 130));
+        assert_eq!(update.databases, vec!["shop", "crm"]);
+        assert_eq!(update.total_execute_time, 0);
+
+        let create = groups[1];
+        assert_eq!(create.fingerprint, "create table t1 (id int)");
+        assert_eq!(create.count, 1);
+        assert_eq!(create.total_execute_time, 3);
+        assert_eq!(create.databases, vec!["shop"]);
+
+        let text = report.to_string();
+        assert!(text.starts_with("4 statement(s) in 2 fingerprint(s)\n#1 "));
+        assert!(text.contains("first=1970-01-01 00:01:30 last=1970-01-01 00:02:10 db=shop,crm"));
+    }
+}
@@ -0,0 +1,620 @@
+use std::{
+    fmt::Display,
+    io::{Read, Seek},
+};
+
+use serde::{Serialize, Serializer};
+use serde_json::json;
+
+use crate::cli::ErrorFormat;
+use crate::model::*;
+use crate::service::get_event_header;
+use crate::util::get_event_type_name;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+// RowImageLengthMismatch的Display中最多显示的剩余字节数
+const MAX_REMAINING_BYTES: usize = 32;
+
+// FDE中的flag，服务端打开binlog时设置，正常关闭时清除
+const LOG_EVENT_BINLOG_IN_USE_F: u16 = 0x1;
+
+// `--skip-errors`时跳过了解码失败的事件，但其他事件都处理完了
+pub const COMPLETED_WITH_ERRORS_EXIT_CODE: u8 = 7;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 错误的分类，决定进程的退出码
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// 参数错误、IO错误等其他错误
+    Other,
+    NotBinlog,
+    /// 事件的长度、位置不对或者内容无法解析
+    Corruption,
+    /// 文件以不完整的事件结束，并且in use flag还没有清除，通常是服务器还在写或者崩溃了
+    TruncatedInUse,
+    /// `--strict`时遇到还不支持解析的事件或者字段类型
+    UnsupportedEvent,
+    /// `--strict`时遇到和FDE中的服务端不一致的事件，比如MySQL的binlog中有MariaDB的GTID事件
+    FlavorMismatch,
+    /// `--verify-continuity`时相邻的两个文件不连续，比如缺少中间的文件、GTID不一致
+    Discontinuity,
+}
+
+impl ErrorCategory {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCategory::Other => 1,
+            ErrorCategory::NotBinlog => 2,
+            ErrorCategory::Corruption => 3,
+            ErrorCategory::TruncatedInUse => 4,
+            ErrorCategory::UnsupportedEvent => 5,
+            ErrorCategory::FlavorMismatch => 6,
+            ErrorCategory::Discontinuity => 8,
+        }
+    }
+
+    /// 和JSON中的名字一致
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Other => "other",
+            ErrorCategory::NotBinlog => "not_binlog",
+            ErrorCategory::Corruption => "corruption",
+            ErrorCategory::TruncatedInUse => "truncated_in_use",
+            ErrorCategory::UnsupportedEvent => "unsupported_event",
+            ErrorCategory::FlavorMismatch => "flavor_mismatch",
+            ErrorCategory::Discontinuity => "discontinuity",
+        }
+    }
+}
+
+/// 带分类的错误，能确定位置时会带上事件的位置和类型
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseError {
+    pub category: ErrorCategory,
+    pub offset: Option<u64>,
+    pub event_type: Option<&'static str>,
+    pub message: String,
+}
+
+impl std::error::Error for ParseError {}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        match (self.offset, self.event_type) {
+            (Some(offset), Some(event_type)) => write!(f, " (at {}, {})", offset, event_type),
+            (Some(offset), None) => write!(f, " (at {})", offset),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ParseError {
+    pub fn new(category: ErrorCategory, message: String) -> ParseError {
+        ParseError {
+            category,
+            offset: None,
+            event_type: None,
+            message,
+        }
+    }
+
+    /// 设置出错的事件的位置和类型
+    pub fn at(mut self, offset: u64, type_code: Option<u8>) -> ParseError {
+        self.offset = Some(offset);
+        self.event_type = type_code.map(get_event_type_name);
+        self
+    }
+
+    pub fn not_binlog(file_path: &str) -> ParseError {
+        ParseError::new(
+            ErrorCategory::NotBinlog,
+            format!("`{}` is not a binlog file", file_path),
+        )
+    }
+
+    /// 没有分类的错误都归为Other
+    pub fn from_error(error: &(dyn std::error::Error + 'static)) -> ParseError {
+        match error.downcast_ref::<ParseError>() {
+            Some(error) => error.clone(),
+            None => ParseError::new(ErrorCategory::Other, error.to_string()),
+        }
+    }
+}
+
+/// 解码器用掉的字节数和body中checksum之前的长度不一致，说明解析的位置不对或者有还不认识的字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumedLengthMismatch {
+    pub type_code: u8,
+    /// body中checksum之前的长度
+    pub body_length: usize,
+    pub consumed: usize,
+}
+
+impl ConsumedLengthMismatch {
+    /// 正数是多读了的字节数，负数是剩下没有读的字节数
+    pub fn delta(&self) -> i64 {
+        self.consumed as i64 - self.body_length as i64
+    }
+}
+
+impl std::error::Error for ConsumedLengthMismatch {}
+
+impl Display for ConsumedLengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} consumed {} of {} body bytes ({:+})",
+            get_event_type_name(self.type_code),
+            self.consumed,
+            self.body_length,
+            self.delta()
+        )
+    }
+}
+
+/// 行事件的镜像按table map解码之后没有正好用完checksum之前的字节，
+/// 通常是table map中的字段数、类型或者元数据和写行镜像时的表结构不一致，也可能是解码的bug
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct RowImageLengthMismatch {
+    /// `库名.表名`
+    pub table: String,
+    pub column_count: u64,
+    /// 完整解码的镜像用掉的字节数，和expected一样从body的开头算起
+    pub consumed: usize,
+    /// body中checksum之前的长度
+    pub expected: usize,
+    /// consumed之后没有解码的字节
+    #[serde(serialize_with = "serialize_hex")]
+    pub remaining: Vec<u8>,
+    /// 解码停止的原因
+    pub reason: String,
+}
+
+impl RowImageLengthMismatch {
+    /// parse_row_images中的位置是相对于行镜像的，加上行镜像在body中的起始位置
+    pub fn offset_by(mut self, start: usize) -> RowImageLengthMismatch {
+        self.consumed += start;
+        self.expected += start;
+        self
+    }
+}
+
+impl std::error::Error for RowImageLengthMismatch {}
+
+impl Display for RowImageLengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the row images of `{}` ({} columns) do not match the table map: decoded {} of {} body bytes, {}; remaining {}",
+            self.table,
+            self.column_count,
+            self.consumed,
+            self.expected,
+            self.reason,
+            to_hex(&self.remaining[..self.remaining.len().min(MAX_REMAINING_BYTES)])
+        )?;
+
+        if self.remaining.len() > MAX_REMAINING_BYTES {
+            write!(f, "... ({} bytes)", self.remaining.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(bytes))
+}
+
+/// FDE中的in use flag是否还没有清除
+pub fn is_binlog_in_use<R: Read + Seek>(file: &mut R, file_length: u64) -> Result<bool, BoxedError> {
+    if file_length < 4 + EVENT_HEADER_LENGTH {
+        return Ok(false);
+    }
+
+    let header = get_event_header(file, 4)?;
+
+    Ok(header.type_code == 15 && header.flags & LOG_EVENT_BINLOG_IN_USE_F > 0)
+}
+
+/// 读取header并检查事件是否完整地在文件中，不完整时按in use flag区分是还在写的文件还是损坏的文件
+pub fn get_checked_event_header<R: Read + Seek>(
+    file: &mut R,
+    offset: u64,
+    file_length: u64,
+    in_use: bool,
+) -> Result<EventHeader, BoxedError> {
+    check_event_header(file, offset, file_length, in_use, true)
+}
+
+/// 和get_checked_event_header一样，但relay log中的next_event_position是主库文件中的位置，不检查
+pub fn get_relay_log_event_header<R: Read + Seek>(
+    file: &mut R,
+    offset: u64,
+    file_length: u64,
+    in_use: bool,
+) -> Result<EventHeader, BoxedError> {
+    check_event_header(file, offset, file_length, in_use, false)
+}
+
+fn check_event_header<R: Read + Seek>(
+    file: &mut R,
+    offset: u64,
+    file_length: u64,
+    in_use: bool,
+    check_next_event_position: bool,
+) -> Result<EventHeader, BoxedError> {
+    let truncated = |type_code: Option<u8>, needed: u64| {
+        let category = match in_use {
+            true => ErrorCategory::TruncatedInUse,
+            false => ErrorCategory::Corruption,
+        };
+
+        ParseError::new(
+            category,
+            format!(
+                "the event needs {} bytes but the file ends at {}",
+                needed, file_length
+            ),
+        )
+        .at(offset, type_code)
+    };
+
+    if offset + EVENT_HEADER_LENGTH > file_length {
+        return Err(Box::new(truncated(None, EVENT_HEADER_LENGTH)));
+    }
+
+    let header = get_event_header(file, offset)?;
+    let event_length = header.event_length as u64;
+
+    if event_length < EVENT_HEADER_LENGTH
+        || (check_next_event_position && header.next_event_position as u64 != offset + event_length)
+    {
+        return Err(Box::new(
+            ParseError::new(
+                ErrorCategory::Corruption,
+                format!(
+                    "invalid event length {} or next event position {}",
+                    header.event_length, header.next_event_position
+                ),
+            )
+            .at(offset, Some(header.type_code)),
+        ));
+    }
+
+    if offset + event_length > file_length {
+        return Err(Box::new(truncated(Some(header.type_code), event_length)));
+    }
+
+    Ok(header)
+}
+
+/// 跳过了没有解析的事件、行镜像和table map不一致，或者行中有还不支持的字段类型时返回一个问题
+/// STOP事件没有内容，不算在内
+pub fn find_unsupported(
+    header: &EventHeader,
+    offset: u64,
+    body: &dyn EventBody,
+) -> Option<ParseError> {
+    if body.downcast_ref::<EventBodyTypeSkip>().is_some() && header.type_code != 3 {
+        return Some(
+            ParseError::new(
+                ErrorCategory::UnsupportedEvent,
+                "the event type is not supported".to_string(),
+            )
+            .at(offset, Some(header.type_code)),
+        );
+    }
+
+    let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>()?;
+
+    // 只有`--lenient-row-images`时事件中才有不一致的情况，和其他问题一样汇总
+    if let Some(mismatch) = &rows_event.row_image_mismatch {
+        return Some(
+            ParseError::new(ErrorCategory::Corruption, mismatch.to_string())
+                .at(offset, Some(header.type_code)),
+        );
+    }
+
+    let type_name = rows_event
+        .rows
+        .iter()
+        .flat_map(|v| v.before.iter().chain(v.after.iter()).flatten())
+        .find_map(|v| match v {
+            ColumnValue::Unsupported(type_name) => Some(type_name),
+            _ => None,
+        })?;
+
+    Some(
+        ParseError::new(
+            ErrorCategory::UnsupportedEvent,
+            format!(
+                "column type `{}` is not supported, the rest of the rows are not decoded",
+                type_name
+            ),
+        )
+        .at(offset, Some(header.type_code)),
+    )
+}
+
+/// 相同事件类型和信息的问题合并成一条
+#[derive(Debug, Serialize)]
+pub struct IssueSummary {
+    pub category: ErrorCategory,
+    pub event_type: Option<&'static str>,
+    pub message: String,
+    pub first_offset: Option<u64>,
+    pub count: u64,
+}
+
+/// `--skip-errors`跳过的事件按错误的分类和事件类型汇总
+#[derive(Debug, Serialize)]
+pub struct SkippedSummary {
+    pub category: ErrorCategory,
+    /// header损坏时可能不是真正的事件类型
+    pub event_type: Option<&'static str>,
+    pub count: u64,
+    pub first_offset: Option<u64>,
+}
+
+/// 收集运行中遇到的非致命问题，结束时按格式输出错误并返回退出码
+#[derive(Default)]
+pub struct ErrorReport {
+    pub format: ErrorFormat,
+    issues: Vec<IssueSummary>,
+    // `--skip-errors`时跳过的每个事件的错误
+    skipped: Vec<ParseError>,
+}
+
+impl ErrorReport {
+    pub fn new() -> ErrorReport {
+        ErrorReport::default()
+    }
+
+    pub fn push(&mut self, issue: ParseError) {
+        let existing = self
+            .issues
+            .iter_mut()
+            .find(|v| v.event_type == issue.event_type && v.message == issue.message);
+
+        match existing {
+            Some(summary) => summary.count += 1,
+            None => self.issues.push(IssueSummary {
+                category: issue.category,
+                event_type: issue.event_type,
+                message: issue.message,
+                first_offset: issue.offset,
+                count: 1,
+            }),
+        }
+    }
+
+    /// `--skip-errors`时记录解码失败而跳过的事件
+    pub fn skip(&mut self, error: ParseError) {
+        self.skipped.push(error);
+    }
+
+    /// 按第一次出现的顺序排列
+    pub fn skipped_summary(&self) -> Vec<SkippedSummary> {
+        let mut summary: Vec<SkippedSummary> = Vec::new();
+
+        for error in &self.skipped {
+            let existing = summary
+                .iter_mut()
+                .find(|v| v.category == error.category && v.event_type == error.event_type);
+
+            match existing {
+                Some(existing) => existing.count += 1,
+                None => summary.push(SkippedSummary {
+                    category: error.category,
+                    event_type: error.event_type,
+                    count: 1,
+                    first_offset: error.offset,
+                }),
+            }
+        }
+
+        summary
+    }
+
+    pub fn finish(&self, result: Result<(), BoxedError>) -> u8 {
+        let error = result.err().map(|v| ParseError::from_error(v.as_ref()));
+        let exit_code = match &error {
+            Some(error) => error.category.exit_code(),
+            None if !self.skipped.is_empty() => COMPLETED_WITH_ERRORS_EXIT_CODE,
+            None => 0,
+        };
+
+        match self.format {
+            ErrorFormat::Text => {
+                if let Some(error) = &error {
+                    eprintln!("Error: {}", error);
+                }
+
+                // 不连续的问题在发现时已经输出过了
+                let count: u64 = self
+                    .issues
+                    .iter()
+                    .filter(|v| v.category != ErrorCategory::Discontinuity)
+                    .map(|v| v.count)
+                    .sum();
+                if count > 0 {
+                    eprintln!(
+                        "warning: {} events are not fully decoded, use `--errors json` for details",
+                        count
+                    );
+                }
+
+                if !self.skipped.is_empty() {
+                    eprintln!(
+                        "warning: {} events are skipped because of errors",
+                        self.skipped.len()
+                    );
+                    eprintln!(
+                        "{:<20} {:<24} {:>8} {:>16}",
+                        "category", "event type", "count", "first offset"
+                    );
+                    for summary in self.skipped_summary() {
+                        eprintln!(
+                            "{:<20} {:<24} {:>8} {:>16}",
+                            summary.category.name(),
+                            summary.event_type.unwrap_or("-"),
+                            summary.count,
+                            summary
+                                .first_offset
+                                .map_or_else(|| "-".to_string(), |v| v.to_string())
+                        );
+                    }
+                }
+            }
+            ErrorFormat::Json => {
+                let mut summary = match error {
+                    Some(error) => json!({
+                        "status": "error",
+                        "exit_code": exit_code,
+                        "category": error.category,
+                        "offset": error.offset,
+                        "event_type": error.event_type,
+                        "message": error.message,
+                        "issues": self.issues,
+                    }),
+                    None if !self.skipped.is_empty() => json!({
+                        "status": "completed_with_errors",
+                        "exit_code": exit_code,
+                        "issues": self.issues,
+                    }),
+                    None => json!({
+                        "status": "ok",
+                        "exit_code": exit_code,
+                        "issues": self.issues,
+                    }),
+                };
+
+                if !self.skipped.is_empty() {
+                    summary["skipped"] = json!(self.skipped_summary());
+                    summary["skipped_events"] = json!(self.skipped);
+                }
+
+                eprintln!("{}", summary);
+            }
+        }
+
+        exit_code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::{BinlogBuilder, EventBuilder, FormatDescriptionBuilder, QueryEventBuilder};
+    use crate::service::{get_event_body, update_checksum_length, CHECKSUM_LENGTH};
+    use crate::util::check_file_magic_number;
+
+    // USER_VAR_EVENT，variable_type是值的类型，0到4是合法的
+    fn user_var(variable_type: u8) -> Vec<u8> {
+        let mut body = 1u32.to_le_bytes().to_vec();
+        body.extend([b'a', 0, variable_type]);
+        body.extend(33u32.to_le_bytes());
+        body.extend(1u32.to_le_bytes());
+        body.push(b'x');
+        body
+    }
+
+    // 和`--skip-errors`一样，解码失败的事件记录在report中，继续下一个事件
+    #[test]
+    fn skip_errors_reports_every_corrupted_event() {
+        let binlog = BinlogBuilder::new()
+            .event(&QueryEventBuilder::new("db", "BEGIN"))
+            // 9个字节的XID
+            .push(EventBuilder::new(16, vec![7; 9]))
+            .push(EventBuilder::new(14, user_var(2)))
+            .push(EventBuilder::new(14, user_var(9)))
+            .push(EventBuilder::new(16, 7u64.to_le_bytes().to_vec()))
+            .build();
+
+        let mut report = ErrorReport::new();
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut checksum_length = CHECKSUM_LENGTH;
+        let mut decoded = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            match get_event_body(&mut file, position, &header, &mut table_structs, checksum_length) {
+                Ok(body) => {
+                    update_checksum_length(&mut checksum_length, body.as_ref());
+                    decoded.push(header.type_code);
+                }
+                Err(error) => report.skip(
+                    ParseError::new(ErrorCategory::Corruption, error.to_string())
+                        .at(position, Some(header.type_code)),
+                ),
+            }
+            position += header.event_length as u64;
+        }
+
+        assert_eq!(decoded, [15, 2, 14, 16]);
+
+        let summary = report.skipped_summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].event_type, Some("XID_EVENT"));
+        assert_eq!(summary[1].event_type, Some("USER_VAR_EVENT"));
+        assert!(summary.iter().all(|v| v.count == 1 && v.category == ErrorCategory::Corruption));
+
+        assert_eq!(report.finish(Ok(())), COMPLETED_WITH_ERRORS_EXIT_CODE);
+    }
+
+    // 空文件、不到4个字节的文件和magic number不对的文件都不是binlog，退出码2
+    #[test]
+    fn not_binlog_exit_code() {
+        for content in [&b""[..], b"\xfe", b"\xfebi", b"not a binlog"] {
+            assert!(!check_file_magic_number(&mut Cursor::new(content)).unwrap());
+        }
+        assert!(check_file_magic_number(&mut Cursor::new(BinlogBuilder::new().build())).unwrap());
+
+        let result: Result<(), BoxedError> = Err(Box::new(ParseError::not_binlog("a.txt")));
+        assert_eq!(ErrorReport::new().finish(result), 2);
+    }
+
+    // 以不完整的事件结束时，in use flag已经清除是文件损坏（3），没有清除是还在写（4）
+    #[test]
+    fn truncated_file_exit_code() {
+        let exit_code = |fde_flags: u16| {
+            let format_description = FormatDescriptionBuilder::new("10.11.6-MariaDB-log");
+            let binlog = BinlogBuilder::empty()
+                .push(EventBuilder::from_body(&format_description).flags(fde_flags))
+                .event(&QueryEventBuilder::new("db", "BEGIN"))
+                .build();
+            let binlog = &binlog[..binlog.len() - 3];
+            let file_length = binlog.len() as u64;
+
+            let mut file = Cursor::new(binlog);
+            let in_use = is_binlog_in_use(&mut file, file_length).unwrap();
+            let mut position = 4;
+            let result = loop {
+                match get_checked_event_header(&mut file, position, file_length, in_use) {
+                    Ok(header) => position = header.next_event_position as u64,
+                    Err(error) => break Err(error),
+                }
+            };
+
+            ErrorReport::new().finish(result)
+        };
+
+        assert_eq!(exit_code(0), 3);
+        assert_eq!(exit_code(LOG_EVENT_BINLOG_IN_USE_F), 4);
+    }
+}
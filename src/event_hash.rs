@@ -0,0 +1,199 @@
+use sha2::{Digest, Sha256};
+
+use crate::cli::HashAlgorithm;
+
+const EVENT_HEADER_LENGTH: usize = 19;
+const CHECKSUM_LENGTH: usize = 4;
+
+/// `--hash`，事件的原始字节的哈希，raw_event是整个事件（header和body）
+///
+/// 不包括最后的校验和，header中的event_length按不含校验和的长度、next_event_position按0计算，
+/// 同一个事件在改名、重新导出或者校验算法不同（NONE和CRC32）的文件中哈希相同
+pub fn event_hash(algorithm: HashAlgorithm, raw_event: &[u8], has_checksum: bool) -> Vec<u8> {
+    let length = match has_checksum {
+        true => raw_event.len().saturating_sub(CHECKSUM_LENGTH),
+        false => raw_event.len(),
+    };
+    let mut event = raw_event[..length].to_vec();
+
+    if event.len() >= EVENT_HEADER_LENGTH {
+        event[9..13].copy_from_slice(&(length as u32).to_le_bytes());
+        event[13..17].fill(0);
+    }
+
+    match algorithm {
+        HashAlgorithm::Sha256 => Sha256::digest(&event).to_vec(),
+    }
+}
+
+/// 事务的哈希，是事务中所有事件的哈希按顺序拼起来之后的哈希
+#[derive(Debug, Clone)]
+pub struct TransactionHash {
+    hasher: Sha256,
+}
+
+impl TransactionHash {
+    pub fn new(algorithm: HashAlgorithm) -> TransactionHash {
+        match algorithm {
+            HashAlgorithm::Sha256 => TransactionHash {
+                hasher: Sha256::new(),
+            },
+        }
+    }
+
+    pub fn update(&mut self, event_hash: &[u8]) {
+        self.hasher.update(event_hash);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.hasher.finalize().to_vec()
+    }
+}
+
+/// 输出用的十六进制
+pub fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::model::ColumnValue;
+    use crate::service::{get_event_body, get_event_header, update_checksum_length};
+    use crate::transaction::TransactionTracker;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    fn orders() -> TableMapBuilder {
+        TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+    }
+
+    // 一个事务，name是插入的值
+    fn transaction(builder: BinlogBuilder, name: &str) -> BinlogBuilder {
+        builder
+            .push(gtid(7))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&orders())
+            .event(&RowsEventBuilder::insert(&orders()).row(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String(name.to_string()),
+            ]))
+            .push(EventBuilder::new(16, 7u64.to_le_bytes().to_vec()))
+    }
+
+    // FDE之后每个事件的哈希，以及事务的哈希
+    fn hashes(binlog: &[u8], has_checksum: bool) -> (Vec<String>, Option<String>) {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut transaction_tracker = TransactionTracker::new();
+        let mut event_hashes = Vec::new();
+        let mut transaction_hash = None;
+
+        let mut position = 4;
+        let mut checksum_length = CHECKSUM_LENGTH;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                checksum_length,
+            )
+            .unwrap();
+            let next = header.next_event_position as usize;
+
+            if header.type_code != 15 {
+                let raw_event = &binlog[position as usize..next];
+                let hash = event_hash(HashAlgorithm::Sha256, raw_event, has_checksum);
+                event_hashes.push(to_hex(&hash));
+
+                if let Some(transaction) = transaction_tracker.process_with_hash(
+                    &header,
+                    position,
+                    body.as_ref(),
+                    &table_structs,
+                    HashAlgorithm::Sha256,
+                    &hash,
+                ) {
+                    transaction_hash = transaction.transaction_hash;
+                }
+            }
+            update_checksum_length(&mut checksum_length, body.as_ref());
+            position = next as u64;
+        }
+        if let Some(transaction) = transaction_tracker.finish() {
+            transaction_hash = transaction_hash.or(transaction.transaction_hash);
+        }
+
+        (event_hashes, transaction_hash)
+    }
+
+    #[test]
+    fn stable_across_files_and_checksums() {
+        let (event_hashes, transaction_hash) =
+            hashes(&transaction(BinlogBuilder::new(), "ann").build(), true);
+        assert_eq!(event_hashes.len(), 5);
+        assert!(event_hashes.iter().all(|v| v.len() == 64));
+        assert!(transaction_hash.is_some());
+
+        // 同样的内容再构造一次
+        let again = hashes(&transaction(BinlogBuilder::new(), "ann").build(), true);
+        assert_eq!(again, (event_hashes.clone(), transaction_hash.clone()));
+
+        // 在另一个文件中的位置不同
+        let moved = BinlogBuilder::new().event(&QueryEventBuilder::new("shop", "FLUSH LOGS"));
+        let (moved_hashes, moved_transaction_hash) =
+            hashes(&transaction(moved, "ann").build(), true);
+        assert_eq!(moved_hashes[1..], event_hashes);
+        assert_eq!(moved_transaction_hash, transaction_hash);
+
+        // binlog_checksum=NONE
+        let without_checksum = hashes(
+            &transaction(BinlogBuilder::without_checksum(), "ann").build(),
+            false,
+        );
+        assert_eq!(without_checksum, (event_hashes, transaction_hash));
+    }
+
+    #[test]
+    fn one_value_byte_differs() {
+        let (ann, ann_transaction) =
+            hashes(&transaction(BinlogBuilder::new(), "ann").build(), true);
+        let (amy, amy_transaction) =
+            hashes(&transaction(BinlogBuilder::new(), "amy").build(), true);
+
+        // 只有行事件不同
+        let differs = ann
+            .iter()
+            .zip(&amy)
+            .map(|(a, b)| a != b)
+            .collect::<Vec<_>>();
+        assert_eq!(differs, [false, false, false, true, false]);
+        assert_ne!(ann_transaction, amy_transaction);
+    }
+
+    #[test]
+    fn checksum_is_excluded() {
+        let event = gtid(7).build(4);
+        let mut corrupted = event.clone();
+        let length = corrupted.len();
+        corrupted[length - 1] ^= 0xff;
+
+        assert_eq!(
+            event_hash(HashAlgorithm::Sha256, &event, true),
+            event_hash(HashAlgorithm::Sha256, &corrupted, true)
+        );
+        assert_ne!(
+            event_hash(HashAlgorithm::Sha256, &event, false),
+            event_hash(HashAlgorithm::Sha256, &corrupted, false)
+        );
+        // 太短的事件不会panic
+        assert_eq!(event_hash(HashAlgorithm::Sha256, &[1, 2], true).len(), 32);
+    }
+}
@@ -0,0 +1,546 @@
+use std::{collections::HashMap, io::Cursor, sync::Arc};
+
+use crate::model::*;
+use crate::service::{
+    deal_type_code_19, decode_rows_event, get_event_body, get_rows_event_table_id,
+    get_rows_event_table_info, CHECKSUM_LENGTH,
+};
+use crate::util::{decode_status_variables, get_status_variable_hrnow, parse_lenenc};
+
+const EVENT_HEADER_LENGTH: usize = 19;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 借用事件缓冲区的事件，解析时除了事件本身不分配内存，需要长期保存时用to_owned转换成model中的结构
+///
+/// 只有常见的高频事件有借用的版本，其他事件是Other，to_owned时按原来的方式解析
+#[derive(Debug)]
+pub struct EventRef<'a> {
+    pub header: EventHeader,
+    pub body: EventBodyRef<'a>,
+    /// 完整的事件，包括header和校验和
+    pub raw: &'a [u8],
+}
+
+#[derive(Debug)]
+pub enum EventBodyRef<'a> {
+    Query(QueryEventRef<'a>),
+    Rotate(RotateEventRef<'a>),
+    Xid(XidEventRef),
+    Gtid(GtidEventRef<'a>),
+    TableMap(TableMapEventRef<'a>),
+    Rows(RowsEventRef<'a>),
+    Other { type_code: u8, body: &'a [u8] },
+}
+
+/// query，status_variables是原始字节
+#[derive(Debug)]
+pub struct QueryEventRef<'a> {
+    pub id_of_thread: u32,
+    pub execute_time: u32,
+    pub error_code: u16,
+    pub status_variables: &'a [u8],
+    pub database_name: &'a str,
+    pub sql: &'a str,
+}
+
+#[derive(Debug)]
+pub struct RotateEventRef<'a> {
+    pub position_of_the_first_event_in_next_log_file: u64,
+    pub file_name_of_next_binary_log: &'a str,
+}
+
+#[derive(Debug)]
+pub struct XidEventRef {
+    pub xid_transaction_number: u64,
+}
+
+#[derive(Debug)]
+pub struct GtidEventRef<'a> {
+    pub gtid_sequence: u64,
+    pub replication_domain_id: u32,
+    pub flags: u8,
+    pub commit_id: Option<u64>,
+    pub format_id: Option<u32>,
+    /// XA事务的gtrid和bqual
+    pub xid: Option<(&'a [u8], &'a [u8])>,
+}
+
+/// table map，字段类型和元数据都是原始字节
+#[derive(Debug)]
+pub struct TableMapEventRef<'a> {
+    pub table_id: u64,
+    pub database_name: &'a str,
+    pub table_name: &'a str,
+    pub number_of_columns: u64,
+    pub column_types: &'a [u8],
+    pub metadata_block: &'a [u8],
+    pub null_bitmap: &'a [u8],
+    // 去掉校验和之后的body，deal_type_code_19需要
+    data: &'a [u8],
+}
+
+/// 行事件，bitmap和行镜像都是原始字节，字段值在to_owned时按table map解码
+#[derive(Debug)]
+pub struct RowsEventRef<'a> {
+    pub type_code: u8,
+    pub table_id: u64,
+    pub flags: u16,
+    pub number_of_columns: u64,
+    pub columns_used: &'a [u8],
+    pub columns_used_for_update: Option<&'a [u8]>,
+    pub row_images: &'a [u8],
+    // 去掉校验和之后的body，decode_rows_event需要
+    data: &'a [u8],
+}
+
+/// 从buffer（一个完整的事件，比如内存映射中的一段）解析借用的事件
+/// 事件的最后必须是4个字节的CRC32（binlog_checksum=CRC32，MariaDB的默认值）
+pub fn parse_event_ref(buffer: &[u8]) -> Result<EventRef<'_>, BoxedError> {
+    if buffer.len() < EVENT_HEADER_LENGTH + CHECKSUM_LENGTH {
+        return Err(Box::new(MyError(format!(
+            "event of {} bytes is shorter than its header and checksum",
+            buffer.len()
+        ))));
+    }
+
+    let header = EventHeader {
+        timestamp: u32::from_le_bytes(buffer[0..4].try_into()?),
+        type_code: buffer[4],
+        server_id: u32::from_le_bytes(buffer[5..9].try_into()?),
+        event_length: u32::from_le_bytes(buffer[9..13].try_into()?),
+        next_event_position: u32::from_le_bytes(buffer[13..17].try_into()?),
+        flags: u16::from_le_bytes(buffer[17..19].try_into()?),
+    };
+
+    if header.event_length as usize != buffer.len() {
+        return Err(Box::new(MyError(format!(
+            "event length {} does not match the buffer of {} bytes",
+            header.event_length,
+            buffer.len()
+        ))));
+    }
+
+    let body = &buffer[EVENT_HEADER_LENGTH..];
+    let data = &body[..body.len() - CHECKSUM_LENGTH];
+
+    let body_ref = match header.type_code {
+        2 => EventBodyRef::Query(parse_query_ref(data)?),
+        4 => EventBodyRef::Rotate(RotateEventRef {
+            position_of_the_first_event_in_next_log_file: read_u64(data, 0)?,
+            file_name_of_next_binary_log: std::str::from_utf8(slice(data, 8, data.len() - 8)?)?
+                .trim_end_matches(char::from(0)),
+        }),
+        16 => EventBodyRef::Xid(XidEventRef {
+            xid_transaction_number: read_u64(data, 0)?,
+        }),
+        162 => EventBodyRef::Gtid(parse_gtid_ref(data)?),
+        19 => EventBodyRef::TableMap(parse_table_map_ref(data)?),
+        23..=25 => EventBodyRef::Rows(parse_rows_ref(data, header.type_code)?),
+        type_code => EventBodyRef::Other { type_code, body },
+    };
+
+    Ok(EventRef {
+        header,
+        body: body_ref,
+        raw: buffer,
+    })
+}
+
+impl EventRef<'_> {
+    /// 转换成get_event_body得到的结构，table map会和get_event_body一样加入table_structs，行事件从table_structs中取表结构
+    pub fn to_owned(
+        &self,
+        table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Result<Box<dyn EventBody>, BoxedError> {
+        match &self.body {
+            EventBodyRef::Query(query) => Ok(Box::new(query.to_owned()?)),
+            EventBodyRef::Rotate(rotate) => Ok(Box::new(rotate.to_owned())),
+            EventBodyRef::Xid(xid) => Ok(Box::new(xid.to_owned())),
+            EventBodyRef::Gtid(gtid) => Ok(Box::new(gtid.to_owned())),
+            EventBodyRef::TableMap(table_map) => Ok(Box::new(table_map.to_owned(table_structs)?)),
+            EventBodyRef::Rows(rows) => {
+                let table_info = get_rows_event_table_info(rows.table_id, table_structs)?;
+                Ok(Box::new(rows.to_owned(table_info)?))
+            }
+            EventBodyRef::Other { .. } => get_event_body(
+                &mut Cursor::new(self.raw),
+                0,
+                &self.header,
+                table_structs,
+                CHECKSUM_LENGTH,
+            ),
+        }
+    }
+}
+
+impl QueryEventRef<'_> {
+    pub fn to_owned(&self) -> Result<EventBodyTypeCode2, BoxedError> {
+        let typed_status_variables = decode_status_variables(self.status_variables)?;
+
+        #[allow(deprecated)]
+        Ok(EventBodyTypeCode2 {
+            id_of_thread: self.id_of_thread,
+            execute_time: self.execute_time,
+            length_of_database_name: self.database_name.len() as u8,
+            error_code: self.error_code,
+            length_of_status_variable_block: self.status_variables.len() as u16,
+            status_variables: self.status_variables.to_vec(),
+            status_variables_string_vec_for_human: typed_status_variables
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+            hrnow: get_status_variable_hrnow(&typed_status_variables),
+            typed_status_variables,
+            database_name: self.database_name.to_string(),
+            sql: self.sql.to_string(),
+        })
+    }
+}
+
+impl RotateEventRef<'_> {
+    pub fn to_owned(&self) -> EventBodyTypeCode4 {
+        EventBodyTypeCode4 {
+            position_of_the_first_event_in_next_log_file: self
+                .position_of_the_first_event_in_next_log_file,
+            file_name_of_next_binary_log: self.file_name_of_next_binary_log.to_string(),
+        }
+    }
+}
+
+impl XidEventRef {
+    pub fn to_owned(&self) -> EventBodyTypeCode16 {
+        EventBodyTypeCode16 {
+            xid_transaction_number: self.xid_transaction_number,
+        }
+    }
+}
+
+impl GtidEventRef<'_> {
+    pub fn to_owned(&self) -> EventBodyTypeCode162 {
+        EventBodyTypeCode162 {
+            gtid_sequence: self.gtid_sequence,
+            replication_domain_id: self.replication_domain_id,
+            flags: self.flags,
+            commit_id: self.commit_id,
+            format_id: self.format_id,
+            gtid_length: self.xid.map(|v| v.0.len() as u8),
+            bqual_length: self.xid.map(|v| v.1.len() as u8),
+            xid: self.xid.map(|v| [v.0, v.1].concat()),
+        }
+    }
+}
+
+impl TableMapEventRef<'_> {
+    /// 字段类型的名字和元数据的解析需要查表，所以和get_event_body一样解析，同样会加入table_structs
+    pub fn to_owned(
+        &self,
+        table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Result<Arc<EventBodyTypeCode19>, BoxedError> {
+        let body = deal_type_code_19(self.data.to_vec(), table_structs)?;
+
+        body.downcast_ref::<Arc<EventBodyTypeCode19>>()
+            .cloned()
+            .ok_or_else(|| Box::new(MyError("invalid table map".to_string())) as BoxedError)
+    }
+}
+
+impl RowsEventRef<'_> {
+    /// 按table map解码所有字段的值
+    pub fn to_owned(
+        &self,
+        table_info: &EventBodyTypeCode19,
+    ) -> Result<EventBodyTypeCode23To25, BoxedError> {
+        decode_rows_event(
+            self.data,
+            self.type_code,
+            table_info,
+            ValueOptions::default(),
+        )
+    }
+}
+
+fn parse_query_ref(data: &[u8]) -> Result<QueryEventRef<'_>, BoxedError> {
+    let length_of_database_name = *slice(data, 8, 1)?.first().unwrap_or(&0) as usize;
+    let length_of_status_variable_block = u16::from_le_bytes(slice(data, 11, 2)?.try_into()?);
+
+    let mut offset = 13;
+    let status_variables = slice(data, offset, length_of_status_variable_block as usize)?;
+    offset += length_of_status_variable_block as usize;
+
+    // 库名后面有一个\0
+    let database_name = std::str::from_utf8(slice(data, offset, length_of_database_name + 1)?)?
+        .trim_end_matches(char::from(0));
+    offset += length_of_database_name + 1;
+
+    Ok(QueryEventRef {
+        id_of_thread: read_u32(data, 0)?,
+        execute_time: read_u32(data, 4)?,
+        error_code: u16::from_le_bytes(slice(data, 9, 2)?.try_into()?),
+        status_variables,
+        database_name,
+        sql: std::str::from_utf8(slice(data, offset, data.len().saturating_sub(offset))?)?,
+    })
+}
+
+fn parse_gtid_ref(data: &[u8]) -> Result<GtidEventRef<'_>, BoxedError> {
+    // FL_GROUP_COMMIT_ID、FL_PREPARED_XA和FL_COMPLETED_XA
+    const FL_GROUP_COMMIT_ID: u8 = 2;
+    const FL_XA: u8 = 64 | 128;
+
+    let flags = *slice(data, 12, 1)?.first().unwrap_or(&0);
+
+    let mut gtid = GtidEventRef {
+        gtid_sequence: read_u64(data, 0)?,
+        replication_domain_id: read_u32(data, 8)?,
+        flags,
+        commit_id: None,
+        format_id: None,
+        xid: None,
+    };
+
+    if flags & FL_GROUP_COMMIT_ID > 0 {
+        gtid.commit_id = Some(read_u64(data, 13)?);
+    } else if flags & FL_XA > 0 {
+        gtid.format_id = Some(read_u32(data, 13)?);
+
+        let gtid_length = slice(data, 17, 1)?[0] as usize;
+        let bqual_length = slice(data, 18, 1)?[0] as usize;
+        gtid.xid = Some((
+            slice(data, 19, gtid_length)?,
+            slice(data, 19 + gtid_length, bqual_length)?,
+        ));
+    }
+
+    Ok(gtid)
+}
+
+fn parse_table_map_ref(data: &[u8]) -> Result<TableMapEventRef<'_>, BoxedError> {
+    let table_id = get_rows_event_table_id(&mut Cursor::new(data), 0)?;
+
+    // table_id和2个字节的保留字段
+    let mut offset = 8;
+
+    let database_name_length = slice(data, offset, 1)?[0] as usize;
+    offset += 1;
+    let database_name = std::str::from_utf8(slice(data, offset, database_name_length)?)?;
+    // 名字后面有一个\0
+    offset += database_name_length + 1;
+
+    let table_name_length = slice(data, offset, 1)?[0] as usize;
+    offset += 1;
+    let table_name = std::str::from_utf8(slice(data, offset, table_name_length)?)?;
+    offset += table_name_length + 1;
+
+    let (number_of_columns, skip) = read_lenenc(data, offset)?;
+    offset += skip;
+
+    let column_types = slice(data, offset, number_of_columns as usize)?;
+    offset += number_of_columns as usize;
+
+    let (metadata_length, skip) = read_lenenc(data, offset)?;
+    offset += skip;
+    let metadata_block = slice(data, offset, metadata_length as usize)?;
+    offset += metadata_length as usize;
+
+    let null_bitmap = slice(data, offset, number_of_columns.div_ceil(8) as usize)?;
+
+    Ok(TableMapEventRef {
+        table_id,
+        database_name,
+        table_name,
+        number_of_columns,
+        column_types,
+        metadata_block,
+        null_bitmap,
+        data,
+    })
+}
+
+fn parse_rows_ref(data: &[u8], type_code: u8) -> Result<RowsEventRef<'_>, BoxedError> {
+    let table_id = get_rows_event_table_id(&mut Cursor::new(data), 0)?;
+    let flags = u16::from_le_bytes(slice(data, 6, 2)?.try_into()?);
+
+    let mut offset = 8;
+    let (number_of_columns, skip) = read_lenenc(data, offset)?;
+    offset += skip;
+
+    let bitmap_length = number_of_columns.div_ceil(8) as usize;
+    let columns_used = slice(data, offset, bitmap_length)?;
+    offset += bitmap_length;
+
+    let columns_used_for_update = match type_code {
+        24 => {
+            let bitmap = slice(data, offset, bitmap_length)?;
+            offset += bitmap_length;
+            Some(bitmap)
+        }
+        _ => None,
+    };
+
+    Ok(RowsEventRef {
+        type_code,
+        table_id,
+        flags,
+        number_of_columns,
+        columns_used,
+        columns_used_for_update,
+        row_images: &data[offset..],
+        data,
+    })
+}
+
+// 越界时返回错误，而不是panic
+fn slice(data: &[u8], offset: usize, length: usize) -> Result<&[u8], BoxedError> {
+    data.get(offset..offset + length).ok_or_else(|| {
+        Box::new(MyError(format!(
+            "event body of {} bytes is too short, needs {} bytes at {}",
+            data.len(),
+            length,
+            offset
+        ))) as BoxedError
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, BoxedError> {
+    Ok(u32::from_le_bytes(slice(data, offset, 4)?.try_into()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, BoxedError> {
+    Ok(u64::from_le_bytes(slice(data, offset, 8)?.try_into()?))
+}
+
+fn read_lenenc(data: &[u8], offset: usize) -> Result<(u64, usize), BoxedError> {
+    // parse_lenenc不检查长度，最长是9个字节
+    let length = match slice(data, offset, 1)?[0] {
+        252 => 3,
+        253 => 4,
+        254 => 9,
+        _ => 1,
+    };
+    let (value, skip) = parse_lenenc(slice(data, offset, length)?)?;
+
+    Ok((value, skip as usize))
+}
+
+// table map和行事件的body都以6个字节的table_id开头
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::get_event_header;
+
+    fn gtid(sequence: u64) -> GtidEventBuilder {
+        GtidEventBuilder::new(sequence).domain_id(3)
+    }
+
+    fn fixture() -> Vec<u8> {
+        let table_map = orders()
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true);
+        let mut status_variables = vec![0u8];
+        status_variables.extend(0u32.to_le_bytes());
+        let mut rotate = 4u64.to_le_bytes().to_vec();
+        rotate.extend(b"mysql-bin.000002");
+
+        BinlogBuilder::new()
+            .event(&gtid(1))
+            .event(
+                &QueryEventBuilder::new("shop", "BEGIN")
+                    .thread_id(9)
+                    .status_variables(&status_variables),
+            )
+            .event(&table_map)
+            .event(&RowsEventBuilder::insert(&table_map).row(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String("a".to_string()),
+            ]))
+            .event(&RowsEventBuilder::update(&table_map).update_row(
+                vec![ColumnValue::Int(1), ColumnValue::String("a".to_string())],
+                vec![ColumnValue::Int(1), ColumnValue::Null],
+            ))
+            .event(
+                &RowsEventBuilder::delete(&table_map)
+                    .row(vec![ColumnValue::Int(1), ColumnValue::Null]),
+            )
+            .push(xid(5))
+            .event(&gtid(2).commit_id(42))
+            .event(&gtid(3).flags(64).xa(1, b"abc", b"de"))
+            .event(&QueryEventBuilder::new("", "XA END 'abc'"))
+            .push(EventBuilder::new(4, rotate))
+            .build()
+    }
+
+    #[test]
+    fn borrowed_and_owned_decoding_agree() {
+        let binlog = fixture();
+        let mut file = Cursor::new(&binlog);
+        let mut owned_table_structs = HashMap::new();
+        let mut table_structs = HashMap::new();
+
+        let mut type_codes = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let owned = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut owned_table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+
+            let raw = &binlog[position as usize..header.next_event_position as usize];
+            let event = parse_event_ref(raw).unwrap();
+            assert_eq!(format!("{:?}", event.header), format!("{:?}", header));
+            assert_eq!(event.raw.as_ptr(), raw.as_ptr());
+            let borrowed = event.to_owned(&mut table_structs).unwrap();
+            assert_eq!(format!("{:?}", borrowed), format!("{:?}", owned));
+
+            type_codes.push(header.type_code);
+            position = header.next_event_position as u64;
+        }
+
+        assert_eq!(type_codes, [15, 162, 2, 19, 23, 24, 25, 16, 162, 162, 2, 4]);
+        assert_eq!(table_structs.len(), 1);
+    }
+
+    #[test]
+    fn fields_borrow_from_the_buffer() {
+        let binlog = fixture();
+        let mut file = Cursor::new(&binlog);
+        let fde = get_event_header(&mut file, 4).unwrap();
+        let gtid = get_event_header(&mut file, fde.next_event_position as u64).unwrap();
+        let start = gtid.next_event_position as usize;
+        let query = get_event_header(&mut file, start as u64).unwrap();
+        let raw = &binlog[start..query.next_event_position as usize];
+
+        let event = parse_event_ref(raw).unwrap();
+        let query = match &event.body {
+            EventBodyRef::Query(query) => query,
+            others => panic!("unexpected event {:?}", others),
+        };
+        assert_eq!((query.database_name, query.sql), ("shop", "BEGIN"));
+        assert_eq!(query.id_of_thread, 9);
+        let range = raw.as_ptr_range();
+        assert!(range.contains(&query.sql.as_ptr()));
+        assert!(range.contains(&query.status_variables.as_ptr()));
+
+        // 第一个事件是FDE，没有借用的版本
+        let raw = &binlog[4..fde.next_event_position as usize];
+        assert!(matches!(
+            parse_event_ref(raw).unwrap().body,
+            EventBodyRef::Other { type_code: 15, .. }
+        ));
+    }
+
+    #[test]
+    fn buffer_must_be_one_event() {
+        let binlog = fixture();
+        assert!(parse_event_ref(&binlog[4..20]).is_err());
+        let error = parse_event_ref(&binlog[4..200]).unwrap_err();
+        assert!(error.to_string().contains("does not match the buffer"));
+    }
+}
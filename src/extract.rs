@@ -0,0 +1,531 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Cursor, Write},
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::error::ParseError;
+use crate::field_type::FieldType;
+use crate::model::*;
+use crate::reader::BinlogReader;
+use crate::service::*;
+use crate::util::*;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+const BINLOG_MAGIC_NUMBER: [u8; 4] = [0xfe, 0x62, 0x69, 0x6e];
+
+// FDE中的flag，服务端打开binlog时设置，正常关闭时清除
+const LOG_EVENT_BINLOG_IN_USE_F: u16 = 0x1;
+
+// FDE body的最后是1个字节的校验算法和4个字节的校验和
+const BINLOG_CHECKSUM_ALG_CRC32: u8 = 1;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 写出的事件
+#[derive(Debug, Serialize)]
+pub struct ExtractReport {
+    pub file_path: String,
+    pub offset: u64,
+    pub type_code: u8,
+    pub event_type: String,
+    pub event_length: u32,
+    pub output_path: String,
+    /// 写入output_path的字节数，standalone时包括magic number、FDE和table map
+    pub bytes_written: u64,
+    pub standalone: bool,
+    pub redacted: bool,
+}
+
+impl std::fmt::Display for ExtractReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} event at {} ({} bytes) written to {} ({} bytes{}{})",
+            self.event_type,
+            self.offset,
+            self.event_length,
+            self.output_path,
+            self.bytes_written,
+            if self.standalone { ", standalone" } else { "" },
+            if self.redacted { ", redacted" } else { "" }
+        )
+    }
+}
+
+/// 把offset处的一个事件（header加上body和校验和）写入output_path，offset必须是事件的边界
+/// standalone时在前面加上magic number、原文件的FDE和行事件用到的table map，写出的文件可以单独解析
+/// redact_values时把行事件中字段的值清零，保留null bitmap和变长字段的长度，所以结构不变
+pub fn extract_event(
+    binlog_file_path: &str,
+    offset: u64,
+    output_path: &str,
+    standalone: bool,
+    redact_values: bool,
+) -> Result<ExtractReport, BoxedError> {
+    let mut file = BinlogReader::open(binlog_file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(binlog_file_path)));
+    }
+
+    let file_length = file.file_length()?;
+
+    let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+    // 每个table_id最近一次的table map
+    let mut table_maps: HashMap<u64, Vec<u8>> = HashMap::new();
+    let mut format_description = Vec::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+
+    // 只读取事件头，直到offset，这样才能确定offset是事件的边界
+    let mut position = 4;
+    while position < offset {
+        let header = get_event_header(&mut file, position)?;
+
+        match header.type_code {
+            15 => {
+                format_description = get_event_raw(&mut file, position, header.event_length)?;
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                )?;
+                update_checksum_length(&mut checksum_length, body.as_ref());
+            }
+            19 => {
+                let raw_event = get_event_raw(&mut file, position, header.event_length)?;
+                get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                )?;
+                let table_id =
+                    get_rows_event_table_id(&mut Cursor::new(&raw_event), EVENT_HEADER_LENGTH)?;
+                table_maps.insert(table_id, raw_event);
+            }
+            _ => {}
+        }
+
+        if (header.next_event_position as u64) <= position {
+            return Err(Box::new(MyError(format!(
+                "invalid next event position {} at {}",
+                header.next_event_position, position
+            ))));
+        }
+        position = header.next_event_position as u64;
+    }
+
+    if position != offset || offset + EVENT_HEADER_LENGTH > file_length {
+        return Err(Box::new(MyError(format!(
+            "{} is not an event boundary in `{}`, the nearest event is at {}",
+            offset, binlog_file_path, position
+        ))));
+    }
+
+    let header = get_event_header(&mut file, offset)?;
+    if offset + header.event_length as u64 > file_length {
+        return Err(Box::new(MyError(format!(
+            "the event at {} is incomplete, it needs {} bytes but the file ends at {}",
+            offset, header.event_length, file_length
+        ))));
+    }
+    let mut raw_event = get_event_raw(&mut file, offset, header.event_length)?;
+
+    // offset是FDE时它自己就是FDE
+    if header.type_code == 15 {
+        format_description = raw_event.clone();
+    }
+    let has_checksum = match format_description.len() {
+        length if length >= EVENT_HEADER_LENGTH as usize + 5 => {
+            format_description[length - 5] == BINLOG_CHECKSUM_ALG_CRC32
+        }
+        _ => true,
+    };
+
+    let is_rows_event = matches!(header.type_code, 23..=25 | 30..=32);
+    let table_id = match is_rows_event {
+        true => Some(get_rows_event_table_id(
+            &mut Cursor::new(&raw_event),
+            EVENT_HEADER_LENGTH,
+        )?),
+        false => None,
+    };
+    let table_map = table_id.and_then(|v| table_maps.get(&v));
+    if is_rows_event && (standalone || redact_values) && table_map.is_none() {
+        return Err(Box::new(MyError(format!(
+            "no table map for the rows event at {}",
+            offset
+        ))));
+    }
+
+    let redacted = redact_values && is_rows_event;
+    if redacted {
+        let table_info = table_id
+            .and_then(|v| table_structs.get(&v))
+            .ok_or_else(|| MyError(format!("no table map for the rows event at {}", offset)))?;
+        redact_rows_event(&mut raw_event, header.type_code, table_info, has_checksum)?;
+    }
+
+    let mut output = Vec::new();
+    if standalone {
+        output.extend_from_slice(&BINLOG_MAGIC_NUMBER);
+
+        if header.type_code != 15 {
+            if format_description.is_empty() {
+                return Err(Box::new(MyError(format!(
+                    "`{}` has no format description event before {}",
+                    binlog_file_path, offset
+                ))));
+            }
+
+            // 片段是完整的文件，不需要in use flag
+            let mut format_description = format_description.clone();
+            let flags = u16::from_le_bytes([format_description[17], format_description[18]])
+                & !LOG_EVENT_BINLOG_IN_USE_F;
+            format_description[17..19].copy_from_slice(&flags.to_le_bytes());
+            append_event(&mut output, format_description, has_checksum);
+        }
+
+        if let Some(table_map) = table_map {
+            append_event(&mut output, table_map.clone(), has_checksum);
+        }
+
+        append_event(&mut output, raw_event, has_checksum);
+    } else {
+        output = raw_event;
+    }
+
+    let mut output_file = File::create(output_path)?;
+    output_file.write_all(&output)?;
+    output_file.flush()?;
+
+    Ok(ExtractReport {
+        file_path: binlog_file_path.to_string(),
+        offset,
+        type_code: header.type_code,
+        event_type: get_event_type_name(header.type_code).to_string(),
+        event_length: header.event_length,
+        output_path: output_path.to_string(),
+        bytes_written: output.len() as u64,
+        standalone,
+        redacted,
+    })
+}
+
+/// 追加到output的末尾，next_event_position改成在新文件中的位置，并重新计算校验和
+fn append_event(output: &mut Vec<u8>, mut raw_event: Vec<u8>, has_checksum: bool) {
+    let length = raw_event.len();
+
+    let next_event_position = (output.len() + length) as u32;
+    raw_event[13..17].copy_from_slice(&next_event_position.to_le_bytes());
+
+    if has_checksum {
+        update_checksum(&mut raw_event);
+    }
+
+    output.extend_from_slice(&raw_event);
+}
+
+fn update_checksum(raw_event: &mut [u8]) {
+    let length = raw_event.len();
+    let checksum = crc32fast::hash(&raw_event[..length - 4]);
+    raw_event[length - 4..].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// 行事件中每个镜像的字段逐个清零，null bitmap不变
+/// varchar和blob只清零内容，保留前面的长度；decimal、time和datetime写成0对应的编码，否则解析会出错
+fn redact_rows_event(
+    raw_event: &mut [u8],
+    type_code: u8,
+    table_info: &EventBodyTypeCode19,
+    has_checksum: bool,
+) -> Result<(), BoxedError> {
+    let rows_end = raw_event.len() - if has_checksum { 4 } else { 0 };
+    let body = &mut raw_event[EVENT_HEADER_LENGTH as usize..rows_end];
+
+    // table_id和flags，v2在后面还有extra data，长度包括它自己的2个字节
+    let mut offset = 8;
+    if matches!(type_code, 30..=32) {
+        let extra_data_length = body
+            .get(offset..offset + 2)
+            .ok_or_else(|| MyError("the rows event is too short".to_string()))?;
+        offset += u16::from_le_bytes(extra_data_length.try_into()?) as usize;
+    }
+
+    let (number_of_columns, skip) = parse_lenenc(&body[offset..])?;
+    offset += skip as usize;
+
+    let bitmap_length = number_of_columns.div_ceil(8) as usize;
+    offset += bitmap_length;
+    if matches!(type_code, 24 | 31) {
+        offset += bitmap_length;
+    }
+
+    let columns = &table_info.columns;
+    while offset < body.len() {
+        if bitmap_length == 0 || offset + bitmap_length > body.len() {
+            return Err(Box::new(MyError(format!(
+                "invalid row image at {} of the rows event",
+                offset
+            ))));
+        }
+
+        let null_bitmap = parse_bitmap(&body[offset..offset + bitmap_length], number_of_columns);
+        offset += bitmap_length;
+
+        // 依次把后面的字段当作null解析，得到每个字段结束的位置
+        let mut column_start = offset;
+        for i in 0..null_bitmap.len() {
+            if null_bitmap[i] {
+                continue;
+            }
+
+            let partial_bitmap: Bitmap = null_bitmap
+                .iter()
+                .enumerate()
+                .map(|(j, is_null)| is_null || j > i)
+                .collect();
+
+            let (values, skip) = parse_column_data_for_row_event(
+                &body[offset..],
+                table_info,
+                &partial_bitmap,
+                ValueOptions::default(),
+            )?;
+            if let Some(ColumnValue::Unsupported(column_type)) = values.get(i) {
+                return Err(Box::new(MyError(format!(
+                    "can not redact column {} of type {}",
+                    i + 1,
+                    column_type
+                ))));
+            }
+
+            let column_end = offset + skip;
+            let column = &mut body[column_start..column_end];
+            match columns.get(i).map(|v| v.field_type) {
+                Some(FieldType::Varchar) | Some(FieldType::Blob) => {
+                    let prefix_length = length_prefix(column);
+                    column[prefix_length..].fill(0);
+                }
+                Some(FieldType::NewDecimal)
+                | Some(FieldType::Time2)
+                | Some(FieldType::DateTime2) => {
+                    column.fill(0);
+                    if let Some(first) = column.first_mut() {
+                        *first = 0x80;
+                    }
+                }
+                _ => column.fill(0),
+            }
+
+            column_start = column_end;
+        }
+
+        offset = column_start;
+    }
+
+    if has_checksum {
+        update_checksum(raw_event);
+    }
+
+    Ok(())
+}
+
+/// 变长字段前面长度的字节数，是让剩下的字节数正好等于长度的最小值
+fn length_prefix(column: &[u8]) -> usize {
+    for prefix_length in 1..=4.min(column.len()) {
+        let mut buffer = [0u8; 4];
+        buffer[..prefix_length].copy_from_slice(&column[..prefix_length]);
+
+        if u32::from_le_bytes(buffer) as usize == column.len() - prefix_length {
+            return prefix_length;
+        }
+    }
+
+    column.len()
+}
+
+// table map和行事件的body都以6个字节的table_id开头
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+
+    struct Fixture {
+        binlog: Vec<u8>,
+        rows_position: u64,
+    }
+
+    fn fixture() -> Fixture {
+        let table_map = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true);
+        let builder = BinlogBuilder::new()
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&table_map);
+        let rows_position = builder.position();
+        let binlog = builder
+            .event(
+                &RowsEventBuilder::insert(&table_map)
+                    .row(vec![
+                        ColumnValue::Int(42),
+                        ColumnValue::String("secret".to_string()),
+                    ])
+                    .row(vec![ColumnValue::Int(7), ColumnValue::Null]),
+            )
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+
+        Fixture {
+            binlog,
+            rows_position,
+        }
+    }
+
+    // 写入原文件，从offset处提取，返回报告和写出的字节
+    fn extract(
+        fixture: &Fixture,
+        offset: u64,
+        standalone: bool,
+        redact_values: bool,
+        name: &str,
+    ) -> Result<(ExtractReport, Vec<u8>), BoxedError> {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("extract-{}-{}.bin", std::process::id(), name));
+        let output = dir.join(format!("extract-{}-{}.out", std::process::id(), name));
+        std::fs::write(&input, &fixture.binlog)?;
+
+        let result = extract_event(
+            input.to_str().unwrap(),
+            offset,
+            output.to_str().unwrap(),
+            standalone,
+            redact_values,
+        );
+        let _ = std::fs::remove_file(&input);
+        let bytes = std::fs::read(&output).unwrap_or_default();
+        let _ = std::fs::remove_file(&output);
+
+        Ok((result?, bytes))
+    }
+
+    // 解析提取出来的文件中的所有事件
+    fn parse(fragment: &[u8]) -> Vec<(EventHeader, Box<dyn EventBody>)> {
+        let mut file = Cursor::new(fragment);
+        assert!(check_file_magic_number(&mut file).unwrap());
+
+        let mut table_structs = HashMap::new();
+        let mut checksum_length = CHECKSUM_LENGTH;
+        let mut events = Vec::new();
+        let mut position = 4;
+        while position < fragment.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                checksum_length,
+            )
+            .unwrap();
+            update_checksum_length(&mut checksum_length, body.as_ref());
+            position = header.next_event_position as u64;
+            events.push((header, body));
+        }
+        events
+    }
+
+    fn rows(events: &[(EventHeader, Box<dyn EventBody>)]) -> Vec<Option<Vec<ColumnValue>>> {
+        let rows_event = events
+            .last()
+            .unwrap()
+            .1
+            .downcast_ref::<EventBodyTypeCode23To25>()
+            .unwrap();
+        rows_event.rows.iter().map(|v| v.after.clone()).collect()
+    }
+
+    #[test]
+    fn standalone_fragment_is_parseable() {
+        let fixture = fixture();
+        let (report, fragment) =
+            extract(&fixture, fixture.rows_position, true, false, "standalone").unwrap();
+        assert_eq!(report.type_code, 23);
+        assert_eq!(report.event_type, "WRITE_ROWS_EVENT_V1");
+        assert_eq!(report.bytes_written, fragment.len() as u64);
+
+        let events = parse(&fragment);
+        let type_codes = events.iter().map(|v| v.0.type_code).collect::<Vec<_>>();
+        assert_eq!(type_codes, [15, 19, 23]);
+        assert_eq!(events[0].0.flags & LOG_EVENT_BINLOG_IN_USE_F, 0);
+        assert_eq!(
+            rows(&events),
+            [
+                Some(vec![
+                    ColumnValue::Int(42),
+                    ColumnValue::String("secret".to_string())
+                ]),
+                Some(vec![ColumnValue::Int(7), ColumnValue::Null]),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_event_without_standalone() {
+        let fixture = fixture();
+        let (report, event) =
+            extract(&fixture, fixture.rows_position, false, false, "raw").unwrap();
+        let start = fixture.rows_position as usize;
+        assert_eq!(
+            event,
+            &fixture.binlog[start..start + report.event_length as usize]
+        );
+        assert!(!report.standalone);
+    }
+
+    #[test]
+    fn redacted_values_keep_the_structure() {
+        let fixture = fixture();
+        let (report, fragment) =
+            extract(&fixture, fixture.rows_position, true, true, "redacted").unwrap();
+        assert!(report.redacted);
+        assert!(!fragment.windows(6).any(|v| v == b"secret"));
+
+        // 值清零，null和字符串的长度不变
+        assert_eq!(
+            rows(&parse(&fragment)),
+            [
+                Some(vec![
+                    ColumnValue::Int(0),
+                    ColumnValue::String("\0".repeat(6))
+                ]),
+                Some(vec![ColumnValue::Int(0), ColumnValue::Null]),
+            ]
+        );
+    }
+
+    #[test]
+    fn offset_must_be_an_event_boundary() {
+        let fixture = fixture();
+        let error = extract(
+            &fixture,
+            fixture.rows_position + 1,
+            false,
+            false,
+            "boundary",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("is not an event boundary"), "{}", error);
+    }
+}
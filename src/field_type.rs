@@ -0,0 +1,351 @@
+use serde::Serialize;
+
+use crate::model::MyError;
+
+/// table map中的字段类型
+// 参考 https://github.com/mysql/mysql-server/blob/mysql-cluster-8.0.22/include/field_types.h#L52
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldType {
+    Decimal,
+    Tiny,
+    Short,
+    Long,
+    Float,
+    Double,
+    Null,
+    Timestamp,
+    LongLong,
+    Int24,
+    Date,
+    Time,
+    DateTime,
+    Year,
+    NewDate,
+    Varchar,
+    Bit,
+    Timestamp2,
+    DateTime2,
+    Time2,
+    TypedArray,
+    Invalid,
+    Bool,
+    Json,
+    NewDecimal,
+    Enum,
+    Set,
+    TinyBlob,
+    MediumBlob,
+    LongBlob,
+    Blob,
+    VarString,
+    String,
+    Geometry,
+}
+
+impl FieldType {
+    /// 所有的类型，按类型码排列
+    pub const ALL: [FieldType; 34] = [
+        FieldType::Decimal,
+        FieldType::Tiny,
+        FieldType::Short,
+        FieldType::Long,
+        FieldType::Float,
+        FieldType::Double,
+        FieldType::Null,
+        FieldType::Timestamp,
+        FieldType::LongLong,
+        FieldType::Int24,
+        FieldType::Date,
+        FieldType::Time,
+        FieldType::DateTime,
+        FieldType::Year,
+        FieldType::NewDate,
+        FieldType::Varchar,
+        FieldType::Bit,
+        FieldType::Timestamp2,
+        FieldType::DateTime2,
+        FieldType::Time2,
+        FieldType::TypedArray,
+        FieldType::Invalid,
+        FieldType::Bool,
+        FieldType::Json,
+        FieldType::NewDecimal,
+        FieldType::Enum,
+        FieldType::Set,
+        FieldType::TinyBlob,
+        FieldType::MediumBlob,
+        FieldType::LongBlob,
+        FieldType::Blob,
+        FieldType::VarString,
+        FieldType::String,
+        FieldType::Geometry,
+    ];
+
+    /// binlog中的类型码
+    pub fn code(&self) -> u8 {
+        match self {
+            FieldType::Decimal => 0,
+            FieldType::Tiny => 1,
+            FieldType::Short => 2,
+            FieldType::Long => 3,
+            FieldType::Float => 4,
+            FieldType::Double => 5,
+            FieldType::Null => 6,
+            FieldType::Timestamp => 7,
+            FieldType::LongLong => 8,
+            FieldType::Int24 => 9,
+            FieldType::Date => 10,
+            FieldType::Time => 11,
+            FieldType::DateTime => 12,
+            FieldType::Year => 13,
+            FieldType::NewDate => 14,
+            FieldType::Varchar => 15,
+            FieldType::Bit => 16,
+            FieldType::Timestamp2 => 17,
+            FieldType::DateTime2 => 18,
+            FieldType::Time2 => 19,
+            FieldType::TypedArray => 20,
+            FieldType::Invalid => 243,
+            FieldType::Bool => 244,
+            FieldType::Json => 245,
+            FieldType::NewDecimal => 246,
+            FieldType::Enum => 247,
+            FieldType::Set => 248,
+            FieldType::TinyBlob => 249,
+            FieldType::MediumBlob => 250,
+            FieldType::LongBlob => 251,
+            FieldType::Blob => 252,
+            FieldType::VarString => 253,
+            FieldType::String => 254,
+            FieldType::Geometry => 255,
+        }
+    }
+
+    /// 服务端源码中的名字，例如`MYSQL_TYPE_VARCHAR`
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldType::Decimal => "MYSQL_TYPE_DECIMAL",
+            FieldType::Tiny => "MYSQL_TYPE_TINY",
+            FieldType::Short => "MYSQL_TYPE_SHORT",
+            FieldType::Long => "MYSQL_TYPE_LONG",
+            FieldType::Float => "MYSQL_TYPE_FLOAT",
+            FieldType::Double => "MYSQL_TYPE_DOUBLE",
+            FieldType::Null => "MYSQL_TYPE_NULL",
+            FieldType::Timestamp => "MYSQL_TYPE_TIMESTAMP",
+            FieldType::LongLong => "MYSQL_TYPE_LONGLONG",
+            FieldType::Int24 => "MYSQL_TYPE_INT24",
+            FieldType::Date => "MYSQL_TYPE_DATE",
+            FieldType::Time => "MYSQL_TYPE_TIME",
+            FieldType::DateTime => "MYSQL_TYPE_DATETIME",
+            FieldType::Year => "MYSQL_TYPE_YEAR",
+            FieldType::NewDate => "MYSQL_TYPE_NEWDATE",
+            FieldType::Varchar => "MYSQL_TYPE_VARCHAR",
+            FieldType::Bit => "MYSQL_TYPE_BIT",
+            FieldType::Timestamp2 => "MYSQL_TYPE_TIMESTAMP2",
+            FieldType::DateTime2 => "MYSQL_TYPE_DATETIME2",
+            FieldType::Time2 => "MYSQL_TYPE_TIME2",
+            FieldType::TypedArray => "MYSQL_TYPE_TYPED_ARRAY",
+            FieldType::Invalid => "MYSQL_TYPE_INVALID",
+            FieldType::Bool => "MYSQL_TYPE_BOOL",
+            FieldType::Json => "MYSQL_TYPE_JSON",
+            FieldType::NewDecimal => "MYSQL_TYPE_NEWDECIMAL",
+            FieldType::Enum => "MYSQL_TYPE_ENUM",
+            FieldType::Set => "MYSQL_TYPE_SET",
+            FieldType::TinyBlob => "MYSQL_TYPE_TINY_BLOB",
+            FieldType::MediumBlob => "MYSQL_TYPE_MEDIUM_BLOB",
+            FieldType::LongBlob => "MYSQL_TYPE_LONG_BLOB",
+            FieldType::Blob => "MYSQL_TYPE_BLOB",
+            FieldType::VarString => "MYSQL_TYPE_VAR_STRING",
+            FieldType::String => "MYSQL_TYPE_STRING",
+            FieldType::Geometry => "MYSQL_TYPE_GEOMETRY",
+        }
+    }
+
+    /// 行镜像中长度固定、和元数据无关的类型的字节数，其他类型为None
+    pub fn fixed_length(&self) -> Option<usize> {
+        match self {
+            FieldType::Null => Some(0),
+            FieldType::Tiny | FieldType::Year => Some(1),
+            FieldType::Short => Some(2),
+            FieldType::Int24 | FieldType::Date | FieldType::NewDate | FieldType::Time => Some(3),
+            FieldType::Long | FieldType::Float | FieldType::Timestamp => Some(4),
+            FieldType::LongLong | FieldType::Double | FieldType::DateTime => Some(8),
+            _ => None,
+        }
+    }
+
+    /// table map的metadata block中这个类型的元数据占用的字节数
+    pub fn metadata_len(&self) -> usize {
+        match self {
+            FieldType::Float
+            | FieldType::Double
+            | FieldType::Timestamp2
+            | FieldType::DateTime2
+            | FieldType::Time2
+            | FieldType::Json
+            | FieldType::TinyBlob
+            | FieldType::MediumBlob
+            | FieldType::LongBlob
+            | FieldType::Blob
+            | FieldType::Geometry => 1,
+            FieldType::Varchar
+            | FieldType::Bit
+            | FieldType::NewDecimal
+            | FieldType::VarString
+            | FieldType::String => 2,
+            _ => 0,
+        }
+    }
+
+    /// 日期和时间类型，包括旧的TIMESTAMP、DATETIME、TIME
+    pub fn is_temporal(&self) -> bool {
+        matches!(
+            self,
+            FieldType::Timestamp
+                | FieldType::Date
+                | FieldType::Time
+                | FieldType::DateTime
+                | FieldType::Year
+                | FieldType::NewDate
+                | FieldType::Timestamp2
+                | FieldType::DateTime2
+                | FieldType::Time2
+        )
+    }
+
+    /// binlog_row_image=NOBLOB时可以不写入镜像的类型：BLOB、TEXT、JSON和GEOMETRY
+    pub fn is_blob(&self) -> bool {
+        matches!(
+            self,
+            FieldType::TinyBlob
+                | FieldType::MediumBlob
+                | FieldType::LongBlob
+                | FieldType::Blob
+                | FieldType::Json
+                | FieldType::Geometry
+        )
+    }
+
+    /// 内容是字节串的类型，包括BLOB和TEXT、ENUM和SET
+    pub fn is_string_like(&self) -> bool {
+        matches!(
+            self,
+            FieldType::Varchar
+                | FieldType::VarString
+                | FieldType::String
+                | FieldType::Enum
+                | FieldType::Set
+                | FieldType::TinyBlob
+                | FieldType::MediumBlob
+                | FieldType::LongBlob
+                | FieldType::Blob
+        )
+    }
+}
+
+impl TryFrom<u8> for FieldType {
+    type Error = MyError;
+
+    fn try_from(code: u8) -> Result<FieldType, MyError> {
+        FieldType::ALL
+            .into_iter()
+            .find(|v| v.code() == code)
+            .ok_or_else(|| MyError(format!("unknown field type `{}`", code)))
+    }
+}
+
+impl std::fmt::Display for FieldType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// 输出类型名，和以前的column_types_string_for_human一样
+impl Serialize for FieldType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// json中是类型名，只有ALL中的这些
+#[cfg(feature = "schemars")]
+impl schemars::JsonSchema for FieldType {
+    fn schema_name() -> String {
+        "FieldType".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{InstanceType, SchemaObject};
+
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(FieldType::ALL.iter().map(|v| v.name().into()).collect()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn convert_all_codes() {
+        let mut names = HashSet::new();
+        for code in 0..=255u8 {
+            match FieldType::try_from(code) {
+                Ok(field_type) => {
+                    assert_eq!(field_type.code(), code);
+                    assert!(field_type.name().starts_with("MYSQL_TYPE_"));
+                    assert!(names.insert(field_type.name()), "{}", field_type);
+                    assert_eq!(field_type.to_string(), field_type.name());
+                    assert_eq!(
+                        serde_json::to_value(field_type).unwrap(),
+                        serde_json::json!(field_type.name())
+                    );
+                }
+                // 21到242没有定义
+                Err(error) => {
+                    assert!((21..=242).contains(&code), "{}", code);
+                    assert_eq!(error.0, format!("unknown field type `{}`", code));
+                }
+            }
+        }
+
+        assert_eq!(names.len(), FieldType::ALL.len());
+    }
+
+    #[test]
+    fn all_types_ordered_by_code() {
+        let codes = FieldType::ALL.iter().map(|v| v.code()).collect::<Vec<u8>>();
+        let mut sorted = codes.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(codes, sorted);
+
+        for field_type in FieldType::ALL {
+            assert_eq!(FieldType::try_from(field_type.code()).unwrap(), field_type);
+        }
+    }
+
+    #[test]
+    fn helpers() {
+        for field_type in FieldType::ALL {
+            assert!(!(field_type.is_temporal() && field_type.is_string_like()));
+        }
+
+        assert_eq!(FieldType::LongLong.fixed_length(), Some(8));
+        assert_eq!(FieldType::Varchar.fixed_length(), None);
+        assert_eq!(FieldType::NewDecimal.metadata_len(), 2);
+        assert_eq!(FieldType::Blob.metadata_len(), 1);
+        // FLOAT和DOUBLE的长度固定，但元数据中也有1个字节的长度
+        assert_eq!(FieldType::Double.metadata_len(), 1);
+        assert_eq!(FieldType::Long.metadata_len(), 0);
+        assert!(FieldType::Time2.is_temporal());
+        assert!(FieldType::Json.is_blob() && !FieldType::Json.is_string_like());
+        assert!(FieldType::Enum.is_string_like() && !FieldType::Enum.is_blob());
+    }
+}
@@ -0,0 +1,713 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::model::*;
+
+/// 按复制域过滤事务
+/// 事务从GTID事件开始，直到下一个GTID事件之前的事件都属于这个事务
+/// format description、rotate这类和事务无关的事件总是保留
+pub struct DomainFilter {
+    domain_ids: Vec<u32>,
+    in_selected_transaction: bool,
+}
+
+impl DomainFilter {
+    /// domain_ids为空时不做过滤
+    pub fn new(domain_ids: &[u32]) -> DomainFilter {
+        DomainFilter {
+            domain_ids: domain_ids.to_vec(),
+            in_selected_transaction: true,
+        }
+    }
+
+    fn is_selected(&self, domain_id: u32) -> bool {
+        self.domain_ids.is_empty() || self.domain_ids.contains(&domain_id)
+    }
+
+    /// 每个事件都要经过这里，以便跟踪当前所在的事务
+    pub fn accept(&mut self, header: &EventHeader, body: &dyn EventBody) -> bool {
+        match header.type_code {
+            // format description、rotate、binlog checkpoint、gtid list、start encryption
+            4 | 15 | 161 | 163 | 164 => true,
+            162 => {
+                self.in_selected_transaction = body
+                    .downcast_ref::<EventBodyTypeCode162>()
+                    .is_none_or(|v| self.is_selected(v.replication_domain_id));
+
+                self.in_selected_transaction
+            }
+            _ => self.in_selected_transaction,
+        }
+    }
+
+    /// gtid list只保留选中的复制域
+    pub fn filter_gtid_list(&self, body: Box<dyn EventBody>) -> Box<dyn EventBody> {
+        if self.domain_ids.is_empty() {
+            return body;
+        }
+
+        let gtids = match body.downcast_ref::<EventBodyTypeCode163>() {
+            Some(gtid_list) => gtid_list
+                .gtids
+                .iter()
+                .filter(|v| self.is_selected(v.replication_domain_id))
+                .map(|v| GTID {
+                    replication_domain_id: v.replication_domain_id,
+                    server_id: v.server_id,
+                    gtid_sequence: v.gtid_sequence,
+                })
+                .collect::<Vec<GTID>>(),
+            None => return body,
+        };
+
+        Box::new(EventBodyTypeCode163 {
+            number_of_gtids: gtids.len() as u32,
+            gtids,
+        })
+    }
+}
+
+/// `--events query,rows`，只输出这些类型的事件
+/// 其他事件只读取事件头，body不读取也不解析，直接跳到下一个事件
+pub struct EventTypeFilter {
+    type_codes: Vec<u8>,
+}
+
+impl EventTypeFilter {
+    /// type_codes为空时不做过滤
+    pub fn new(type_codes: &[u8]) -> EventTypeFilter {
+        EventTypeFilter {
+            type_codes: type_codes.to_vec(),
+        }
+    }
+
+    pub fn accept(&self, type_code: u8) -> bool {
+        self.type_codes.is_empty() || self.type_codes.contains(&type_code)
+    }
+
+    /// 不输出的事件中，format description、table map和GTID记录了后面的事件需要的状态，仍然要解析
+    pub fn needs_body(&self, type_code: u8) -> bool {
+        self.accept(type_code) || matches!(type_code, 15 | 19 | 162)
+    }
+}
+
+/// `--stop-position`、`--stop-datetime`、`--stop-gtid`，和mysqlbinlog一样遇到第一个满足条件的事件时停止
+#[derive(Debug, Clone, Default)]
+pub struct StopCondition {
+    /// 文件中的位置，从这个位置开始的事件不再处理
+    pub position: Option<u64>,
+    /// 时间戳大于等于它的事件不再处理
+    pub timestamp: Option<u32>,
+    /// 这个GTID的事务处理完之后停止
+    pub gtid: Option<String>,
+}
+
+impl StopCondition {
+    pub fn is_empty(&self) -> bool {
+        self.position.is_none() && self.timestamp.is_none() && self.gtid.is_none()
+    }
+}
+
+/// 每个事件都要经过这里，以便知道--stop-gtid的事务是否已经结束
+pub struct StopFilter {
+    condition: StopCondition,
+    passed_gtid: bool,
+}
+
+impl StopFilter {
+    pub fn new(condition: &StopCondition) -> StopFilter {
+        StopFilter {
+            condition: condition.clone(),
+            passed_gtid: false,
+        }
+    }
+
+    /// 从这个事件开始都不再处理时返回true
+    pub fn is_reached(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+    ) -> bool {
+        if self.condition.position.is_some_and(|v| position >= v) {
+            return true;
+        }
+
+        // rotate这类人为生成的事件时间戳为0
+        if header.timestamp > 0
+            && self
+                .condition
+                .timestamp
+                .is_some_and(|v| header.timestamp >= v)
+        {
+            return true;
+        }
+
+        if let (Some(stop_gtid), Some(gtid_event)) = (
+            &self.condition.gtid,
+            body.downcast_ref::<EventBodyTypeCode162>(),
+        ) {
+            // 下一个事务开始时，指定的事务已经结束了
+            if self.passed_gtid {
+                return true;
+            }
+
+            let gtid = format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            );
+            self.passed_gtid = &gtid == stop_gtid;
+        }
+
+        false
+    }
+}
+
+/// `--limit`、`--limit-rows`、`--sample-rows`，按过滤之后实际输出的内容计数
+/// 事件数只计算输出方式实际输出了内容的事件，比如`--rows-only`时只有行事件
+#[derive(Debug, Clone, Default)]
+pub struct OutputLimit {
+    /// 输出这么多个事件之后停止
+    pub events: Option<u64>,
+    /// 输出这么多行之后停止，最后一个行事件可能只输出一部分行
+    pub rows: Option<u64>,
+    /// 每张表只输出前这么多行，其余的行只计数
+    pub sample_rows: Option<u64>,
+}
+
+impl OutputLimit {
+    pub fn is_empty(&self) -> bool {
+        self.events.is_none() && self.rows.is_none() && self.sample_rows.is_none()
+    }
+}
+
+/// `--sample-rows`时一张表输出的行数和总行数
+#[derive(Debug, Clone)]
+pub struct SampledTable {
+    /// `库名.表名`
+    pub table: String,
+    pub kept_rows: u64,
+    pub total_rows: u64,
+}
+
+/// 每个要输出的事件都要经过这里，达到限制之后不再输出任何事件
+pub struct OutputLimiter {
+    limit: OutputLimit,
+    events: u64,
+    rows: u64,
+    // 按第一次出现的顺序
+    sampled_tables: Vec<SampledTable>,
+}
+
+impl OutputLimiter {
+    pub fn new(limit: &OutputLimit) -> OutputLimiter {
+        OutputLimiter {
+            limit: limit.clone(),
+            events: 0,
+            rows: 0,
+            sampled_tables: Vec::new(),
+        }
+    }
+
+    /// 返回None时这个事件不输出，行事件可能只保留前面的一部分行
+    /// 保留的行在这里计数，事件要在实际输出之后调用count_event
+    pub fn limit_body(
+        &mut self,
+        body: Box<dyn EventBody>,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Option<Box<dyn EventBody>> {
+        if self.is_exhausted() {
+            return None;
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Some(body),
+        };
+
+        let total_rows = rows_event.rows.len() as u64;
+        let mut keep_rows = total_rows;
+
+        if let Some(limit) = self.limit.rows {
+            keep_rows = keep_rows.min(limit - self.rows);
+        }
+
+        if let Some(sample_rows) = self.limit.sample_rows {
+            let table = table_structs
+                .get(&rows_event.table_id)
+                .map(|v| format!("{}.{}", v.database_name, v.table_name))
+                .unwrap_or_else(|| format!("table_id {}", rows_event.table_id));
+            let sampled = self.sampled_table(table);
+
+            keep_rows = keep_rows.min(sample_rows - sampled.kept_rows);
+            sampled.kept_rows += keep_rows;
+            sampled.total_rows += total_rows;
+        }
+
+        // 行镜像和table map不一致时可能一行都没有解码，这样的事件仍然输出
+        if keep_rows == 0 && total_rows > 0 {
+            return None;
+        }
+
+        self.rows += keep_rows;
+
+        if keep_rows == total_rows {
+            return Some(body);
+        }
+
+        // 保留的是前面的行，column_data对应第一行，不需要改变
+        let mut rows_event = rows_event.clone();
+        rows_event.rows.truncate(keep_rows as usize);
+        rows_event.number_of_rows = keep_rows;
+
+        Some(Box::new(rows_event))
+    }
+
+    /// limit_body返回的事件输出了内容时调用
+    pub fn count_event(&mut self) {
+        self.events += 1;
+    }
+
+    /// 已经达到`--limit`或`--limit-rows`，`--sample-rows`不会停止
+    pub fn is_exhausted(&self) -> bool {
+        self.limit.events.is_some_and(|v| self.events >= v)
+            || self.limit.rows.is_some_and(|v| self.rows >= v)
+    }
+
+    pub fn sampled_tables(&self) -> &[SampledTable] {
+        &self.sampled_tables
+    }
+
+    fn sampled_table(&mut self, table: String) -> &mut SampledTable {
+        let index = match self.sampled_tables.iter().position(|v| v.table == table) {
+            Some(index) => index,
+            None => {
+                self.sampled_tables.push(SampledTable {
+                    table,
+                    kept_rows: 0,
+                    total_rows: 0,
+                });
+                self.sampled_tables.len() - 1
+            }
+        };
+
+        &mut self.sampled_tables[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{
+        deal_type_code_23_to_25, get_event_body, get_event_header, CHECKSUM_LENGTH,
+    };
+
+    fn gtid(domain_id: u32, sequence: u64) -> EventBuilder {
+        EventBuilder::from_body(&GtidEventBuilder::new(sequence).domain_id(domain_id))
+    }
+
+    fn gtid_list(gtids: &[(u32, u64)]) -> EventBuilder {
+        let mut body = (gtids.len() as u32).to_le_bytes().to_vec();
+        for (domain_id, sequence) in gtids {
+            body.extend(domain_id.to_le_bytes());
+            body.extend(1u32.to_le_bytes());
+            body.extend(sequence.to_le_bytes());
+        }
+
+        EventBuilder::new(163, body)
+    }
+
+    fn rotate() -> EventBuilder {
+        let mut body = 4u64.to_le_bytes().to_vec();
+        body.extend(b"mysql-bin.000002");
+
+        EventBuilder::new(4, body)
+    }
+
+    // 两个复制域的事务交替出现，每个域写自己的表
+    fn fixture() -> Vec<u8> {
+        let mut builder = BinlogBuilder::new().push(gtid_list(&[(1, 10), (2, 20), (3, 30)]));
+        for (domain_id, sequence) in [(1, 11), (2, 21), (1, 12), (2, 22)] {
+            let table = TableMapBuilder::new(&format!("dc{}", domain_id), "orders")
+                .table_id(70 + domain_id as u64)
+                .column(FieldType::LongLong, &[]);
+            builder = builder
+                .push(gtid(domain_id, sequence))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&table)
+                .event(
+                    &RowsEventBuilder::insert(&table)
+                        .row(vec![ColumnValue::Int(sequence as i64)])
+                        .flags(1),
+                )
+                .push(EventBuilder::new(16, sequence.to_le_bytes().to_vec()));
+        }
+
+        builder.push(rotate()).build()
+    }
+
+    // 留下的事件：(类型, 所在事务的GTID序号)，事务之外为0
+    fn accepted_events(domain_ids: &[u32]) -> Vec<(u8, u64)> {
+        let binlog = fixture();
+        let mut filter = DomainFilter::new(domain_ids);
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut result = Vec::new();
+        let mut sequence = 0;
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+
+            if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+                sequence = gtid_event.gtid_sequence;
+            }
+            if filter.accept(&header, body.as_ref()) {
+                let sequence = match header.type_code {
+                    4 | 15 | 163 => 0,
+                    _ => sequence,
+                };
+                result.push((header.type_code, sequence));
+            }
+
+            position = header.next_event_position as u64;
+        }
+
+        result
+    }
+
+    fn transaction(sequence: u64) -> Vec<(u8, u64)> {
+        [162, 2, 19, 23, 16]
+            .iter()
+            .map(|v| (*v, sequence))
+            .collect()
+    }
+
+    #[test]
+    fn separate_domains() {
+        let head = vec![(15, 0), (163, 0)];
+        let tail = vec![(4, 0)];
+
+        let domain_1 = [head.clone(), transaction(11), transaction(12), tail.clone()].concat();
+        assert_eq!(accepted_events(&[1]), domain_1);
+
+        let domain_2 = [head.clone(), transaction(21), transaction(22), tail.clone()].concat();
+        assert_eq!(accepted_events(&[2]), domain_2);
+
+        // 两个域都选中和不过滤一样
+        let all = [
+            head.clone(),
+            transaction(11),
+            transaction(21),
+            transaction(12),
+            transaction(22),
+            tail.clone(),
+        ]
+        .concat();
+        assert_eq!(accepted_events(&[1, 2]), all);
+        assert_eq!(accepted_events(&[]), all);
+
+        assert_eq!(accepted_events(&[3]), [head, tail].concat());
+    }
+
+    #[test]
+    fn filter_gtid_list() {
+        let gtids = [(1, 10), (2, 20), (3, 30)]
+            .iter()
+            .map(|(domain_id, sequence)| GTID {
+                replication_domain_id: *domain_id,
+                server_id: 1,
+                gtid_sequence: *sequence,
+            })
+            .collect::<Vec<GTID>>();
+        let gtid_list = || -> Box<dyn EventBody> {
+            Box::new(EventBodyTypeCode163 {
+                number_of_gtids: 3,
+                gtids: gtids.clone(),
+            })
+        };
+
+        let body = DomainFilter::new(&[1, 3]).filter_gtid_list(gtid_list());
+        let body = body.downcast_ref::<EventBodyTypeCode163>().unwrap();
+        assert_eq!(body.number_of_gtids, 2);
+        assert_eq!(body.gtids, [gtids[0].clone(), gtids[2].clone()]);
+
+        let body = DomainFilter::new(&[]).filter_gtid_list(gtid_list());
+        assert_eq!(
+            body.downcast_ref::<EventBodyTypeCode163>().unwrap().gtids,
+            gtids
+        );
+    }
+
+    // 和main中一样，先按复制域过滤，再经过limiter，达到限制之后停止
+    // rows_only时只有行事件算作输出，否则每个事件都输出，返回输出的事件类型和行中的值
+    fn limited_output(
+        limit: OutputLimit,
+        domain_ids: &[u32],
+        rows_only: bool,
+    ) -> Vec<(u8, Vec<i64>)> {
+        let binlog = fixture();
+        let mut filter = DomainFilter::new(domain_ids);
+        let mut limiter = OutputLimiter::new(&limit);
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut result = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 && !limiter.is_exhausted() {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            position = header.next_event_position as u64;
+
+            if !filter.accept(&header, body.as_ref()) {
+                continue;
+            }
+            let body = match limiter.limit_body(body, &table_structs) {
+                Some(body) => body,
+                None => continue,
+            };
+
+            let values = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+                Some(rows_event) => rows_event
+                    .rows
+                    .iter()
+                    .flat_map(|v| v.after.iter().flatten())
+                    .map(|v| match v {
+                        ColumnValue::Int(v) => *v,
+                        others => panic!("unexpected value {:?}", others),
+                    })
+                    .collect(),
+                None if rows_only => continue,
+                None => Vec::new(),
+            };
+            limiter.count_event();
+            result.push((header.type_code, values));
+        }
+
+        result
+    }
+
+    #[test]
+    fn limit_counts_only_emitted_events() {
+        let limit = OutputLimit {
+            events: Some(2),
+            ..Default::default()
+        };
+
+        // FDE、GTID list、GTID、table map这些不是行的事件不占`--rows-only`的数量
+        assert_eq!(
+            limited_output(limit.clone(), &[], true),
+            [(23, vec![11]), (23, vec![21])]
+        );
+
+        // 事件输出时每个事件都算
+        assert_eq!(
+            limited_output(limit, &[], false),
+            [(15, vec![]), (163, vec![])]
+        );
+    }
+
+    #[test]
+    fn limit_applies_after_filters() {
+        let limit = OutputLimit {
+            events: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            limited_output(limit, &[2], true),
+            [(23, vec![21]), (23, vec![22])]
+        );
+
+        let limit = OutputLimit {
+            rows: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(limited_output(limit, &[1], true), [(23, vec![11])]);
+
+        // 没有被过滤掉的行只有2行，达不到限制，处理完整个文件
+        let limit = OutputLimit {
+            rows: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            limited_output(limit, &[2], true),
+            [(23, vec![21]), (23, vec![22])]
+        );
+    }
+
+    #[test]
+    fn limit_rows_truncates_the_last_rows_event() {
+        let table = TableMapBuilder::new("shop", "orders").column(FieldType::LongLong, &[]);
+        let rows = RowsEventBuilder::insert(&table)
+            .row(vec![ColumnValue::Int(1)])
+            .row(vec![ColumnValue::Int(2)])
+            .row(vec![ColumnValue::Int(3)]);
+        let mut table_structs = HashMap::new();
+        table_structs.insert(table.get_table_id(), table.table_info().unwrap());
+        let body = || deal_type_code_23_to_25(rows.body(), 23, &table_structs).unwrap();
+
+        let mut limiter = OutputLimiter::new(&OutputLimit {
+            rows: Some(4),
+            ..Default::default()
+        });
+        let first = limiter.limit_body(body(), &table_structs).unwrap();
+        assert_eq!(
+            first
+                .downcast_ref::<EventBodyTypeCode23To25>()
+                .unwrap()
+                .rows
+                .len(),
+            3
+        );
+        assert!(!limiter.is_exhausted());
+
+        let second = limiter.limit_body(body(), &table_structs).unwrap();
+        let second = second.downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+        assert_eq!(second.rows.len(), 1);
+        assert_eq!(second.number_of_rows, 1);
+        assert!(limiter.is_exhausted());
+        assert!(limiter.limit_body(body(), &table_structs).is_none());
+    }
+
+    #[test]
+    fn sample_rows_keeps_counting_the_rest() {
+        let limit = OutputLimit {
+            sample_rows: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            limited_output(limit.clone(), &[], true),
+            [(23, vec![11]), (23, vec![21])]
+        );
+
+        let binlog = fixture();
+        let mut limiter = OutputLimiter::new(&limit);
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            limiter.limit_body(body, &table_structs);
+            position = header.next_event_position as u64;
+        }
+
+        // `--sample-rows`不会停止，每张表的总行数都计入
+        assert!(!limiter.is_exhausted());
+        let sampled = limiter
+            .sampled_tables()
+            .iter()
+            .map(|v| (v.table.as_str(), v.kept_rows, v.total_rows))
+            .collect::<Vec<_>>();
+        assert_eq!(sampled, [("dc1.orders", 1, 2), ("dc2.orders", 1, 2)]);
+    }
+
+    // 记录读取的字节数，确认跳过的事件没有读取body
+    struct CountingReader<'a> {
+        inner: Cursor<&'a [u8]>,
+        bytes_read: u64,
+    }
+
+    impl std::io::Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.inner.read(buf)?;
+            self.bytes_read += length as u64;
+            Ok(length)
+        }
+    }
+
+    impl std::io::Seek for CountingReader<'_> {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn skipped_events_are_never_decoded() {
+        let binlog = fixture();
+        let filter = EventTypeFilter::new(&[2]);
+        let mut file = CountingReader {
+            inner: Cursor::new(&binlog),
+            bytes_read: 0,
+        };
+
+        // 和main中一样，不需要body的事件只读取事件头
+        let mut table_structs = HashMap::new();
+        let mut decoded = Vec::new();
+        let mut accepted = Vec::new();
+        let mut expected_bytes = 0;
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            expected_bytes += 19;
+            if filter.needs_body(header.type_code) {
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                decoded.push(header.type_code);
+                expected_bytes += header.event_length as u64 - 19;
+                if filter.accept(header.type_code) {
+                    accepted.push(
+                        body.downcast_ref::<EventBodyTypeCode2>()
+                            .unwrap()
+                            .sql
+                            .clone(),
+                    );
+                }
+            }
+            position = header.next_event_position as u64;
+        }
+
+        assert_eq!(accepted, ["BEGIN"; 4]);
+        // 行事件、XID、GTID list和rotate都没有解析
+        decoded.sort();
+        decoded.dedup();
+        assert_eq!(decoded, [2, 15, 19, 162]);
+        assert_eq!(file.bytes_read, expected_bytes);
+    }
+
+    #[test]
+    fn needs_body() {
+        let filter = EventTypeFilter::new(&[23, 24, 25]);
+        assert!(filter.accept(24));
+        assert!(!filter.accept(19));
+        assert!(filter.needs_body(19));
+        assert!(filter.needs_body(15));
+        assert!(filter.needs_body(162));
+        assert!(!filter.needs_body(16));
+        assert!(!filter.needs_body(2));
+
+        let all = EventTypeFilter::new(&[]);
+        assert!(all.accept(16) && all.needs_body(16));
+    }
+}
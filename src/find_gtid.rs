@@ -0,0 +1,525 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use serde::Serialize;
+
+use crate::binlog_index::read_binlog_index;
+use crate::error::ParseError;
+use crate::gtid::GtidSet;
+use crate::model::*;
+use crate::reader::BinlogReader;
+use crate::service::{get_event_body, get_event_header, update_checksum_length, CHECKSUM_LENGTH};
+use crate::transaction::TransactionTracker;
+use crate::util::{check_file_magic_number, get_binlog_files_in_dir};
+
+// 文件开头FDE、GTID_LIST、BINLOG_CHECKPOINT之后就是第一个事务，最多看这么多个事件
+const HEAD_EVENTS: usize = 8;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// `find-gtid`的结果，按domain比较sequence，和服务端按GTID位置查找binlog的方式相同
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum GtidLocation {
+    /// commit_position是XID或者COMMIT事件的位置，end_position是事务结束的位置，从这里继续复制
+    Found {
+        gtid: String,
+        file_path: String,
+        gtid_position: u64,
+        commit_position: Option<u64>,
+        end_position: u64,
+    },
+    /// 第一个文件的GTID_LIST已经包括这个GTID，它所在的文件已经被purge
+    Purged {
+        gtid: String,
+        first_file_path: String,
+        gtid_list: String,
+    },
+    /// 按GTID_LIST应该在这个文件中，但是文件中没有，previous和next是同一个domain中前后的GTID
+    Gap {
+        gtid: String,
+        file_path: String,
+        previous: Option<String>,
+        next: Option<String>,
+    },
+    /// 比最后一个文件中所有的GTID都新，还没有写入
+    NotYetWritten {
+        gtid: String,
+        last_file_path: String,
+        last_gtid: Option<String>,
+    },
+}
+
+impl std::fmt::Display for GtidLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GtidLocation::Found {
+                gtid,
+                file_path,
+                gtid_position,
+                commit_position,
+                end_position,
+            } => write!(
+                f,
+                "{} is in {} at {}, commit at {}, end at {}",
+                gtid,
+                file_path,
+                gtid_position,
+                commit_position
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "- (not committed)".to_string()),
+                end_position
+            ),
+            GtidLocation::Purged {
+                gtid,
+                first_file_path,
+                gtid_list,
+            } => write!(
+                f,
+                "{} has been purged, the first file {} starts after {}",
+                gtid, first_file_path, gtid_list
+            ),
+            GtidLocation::Gap {
+                gtid,
+                file_path,
+                previous,
+                next,
+            } => write!(
+                f,
+                "{} is not in {}, which has {} before it and {} after it",
+                gtid,
+                file_path,
+                previous.as_deref().unwrap_or("-"),
+                next.as_deref().unwrap_or("-")
+            ),
+            GtidLocation::NotYetWritten {
+                gtid,
+                last_file_path,
+                last_gtid,
+            } => write!(
+                f,
+                "{} is not written yet, the last file {} ends with {}",
+                gtid,
+                last_file_path,
+                last_gtid.as_deref().unwrap_or("-")
+            ),
+        }
+    }
+}
+
+/// path可以是目录、索引文件或者一个binlog文件
+/// 每个文件开头的GTID_LIST是这个文件之前的位置，按它二分查找GTID所在的文件，只扫描这一个文件
+pub fn find_gtid(path: &str, gtid: &GTID) -> Result<GtidLocation, BoxedError> {
+    let file_paths = list_binlog_files(path)?;
+    let gtid_string = gtid.to_string();
+
+    // 第一个GTID_LIST已经包括这个GTID的文件，之前的文件都不包括
+    let (mut low, mut high) = (0, file_paths.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        match read_gtid_list(&file_paths[middle])?.contains(gtid) {
+            true => high = middle,
+            false => low = middle + 1,
+        }
+    }
+
+    if low == 0 {
+        return Ok(GtidLocation::Purged {
+            gtid: gtid_string,
+            first_file_path: file_paths[0].clone(),
+            gtid_list: read_gtid_list(&file_paths[0])?.to_string(),
+        });
+    }
+
+    let file_path = &file_paths[low - 1];
+    let is_last_file = low == file_paths.len();
+
+    scan_file(file_path, gtid, is_last_file)
+}
+
+/// path是目录时按文件名排序，索引文件按其中的顺序，binlog文件就是它本身
+pub fn list_binlog_files(path: &str) -> Result<Vec<String>, BoxedError> {
+    let file_paths = match Path::new(path).is_dir() {
+        true => get_binlog_files_in_dir(path)?,
+        false => {
+            let mut file = BinlogReader::open(path)?;
+            match check_file_magic_number(&mut file)? {
+                true => vec![path.to_string()],
+                false => read_binlog_index(path)?,
+            }
+        }
+    };
+
+    if file_paths.is_empty() {
+        return Err(Box::new(MyError(format!(
+            "there is no binlog file in `{}`",
+            path
+        ))));
+    }
+
+    Ok(file_paths)
+}
+
+/// 文件开头的GTID_LIST事件，没有时报错（MySQL或者没有打开GTID的binlog）
+fn read_gtid_list(file_path: &str) -> Result<GtidSet, BoxedError> {
+    let mut file = BinlogReader::open(file_path)?;
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(file_path)));
+    }
+
+    let file_length = file.file_length()?;
+    let mut table_structs = HashMap::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+
+    let mut offset = 4;
+    for _ in 0..HEAD_EVENTS {
+        if offset >= file_length {
+            break;
+        }
+
+        let header = get_event_header(&mut file, offset)?;
+        match header.type_code {
+            15 => {
+                let body = get_event_body(
+                    &mut file,
+                    offset,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                )?;
+                update_checksum_length(&mut checksum_length, body.as_ref());
+            }
+            163 => {
+                let body = get_event_body(
+                    &mut file,
+                    offset,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                )?;
+                if let Some(gtid_list) = body.downcast_ref::<EventBodyTypeCode163>() {
+                    return Ok(GtidSet::from(gtid_list));
+                }
+            }
+            // 已经到了第一个事务
+            162 => break,
+            _ => {}
+        }
+
+        offset = header.next_event_position as u64;
+    }
+
+    Err(Box::new(MyError(format!(
+        "`{}` has no GTID_LIST event at the beginning",
+        file_path
+    ))))
+}
+
+// 只解码GTID、query、XID和XA_PREPARE事件，用TransactionTracker找到事务的提交
+fn scan_file(file_path: &str, gtid: &GTID, is_last_file: bool) -> Result<GtidLocation, BoxedError> {
+    let mut file = BinlogReader::open(file_path)?;
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(file_path)));
+    }
+
+    let file_length = file.file_length()?;
+    let gtid_string = gtid.to_string();
+
+    let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+    let mut transaction_tracker = TransactionTracker::new();
+    let mut found: Option<u64> = None;
+
+    // 同一个domain中在这个GTID前后最近的GTID
+    let mut previous: Option<GTID> = None;
+    let mut next: Option<GTID> = None;
+
+    let mut offset = 4;
+    while offset < file_length {
+        let position = offset;
+        let header = get_event_header(&mut file, position)?;
+        offset = header.next_event_position as u64;
+
+        let body = match header.type_code {
+            2 | 15 | 16 | 38 | 162 => get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                checksum_length,
+            )?,
+            type_code => Box::new(EventBodyTypeSkip(type_code)),
+        };
+        update_checksum_length(&mut checksum_length, body.as_ref());
+
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            let current = GTID {
+                replication_domain_id: gtid_event.replication_domain_id,
+                server_id: header.server_id,
+                gtid_sequence: gtid_event.gtid_sequence,
+            };
+
+            // 上一个事务没有提交就开始了新的事务
+            if let Some(gtid_position) = found {
+                return Ok(GtidLocation::Found {
+                    gtid: gtid_string,
+                    file_path: file_path.to_string(),
+                    gtid_position,
+                    commit_position: None,
+                    end_position: position,
+                });
+            }
+
+            if current == *gtid {
+                found = Some(position);
+            } else if current.replication_domain_id == gtid.replication_domain_id {
+                match current.gtid_sequence <= gtid.gtid_sequence {
+                    true => previous = Some(current),
+                    false if next.is_none() => next = Some(current),
+                    false => {}
+                }
+            }
+        }
+
+        let finished =
+            transaction_tracker.process(&header, position, body.as_ref(), &table_structs);
+
+        if let (Some(gtid_position), Some(transaction)) = (found, finished) {
+            if transaction.start_position == gtid_position {
+                return Ok(GtidLocation::Found {
+                    gtid: gtid_string,
+                    file_path: file_path.to_string(),
+                    gtid_position,
+                    commit_position: transaction.committed.then_some(position),
+                    end_position: transaction.end_position,
+                });
+            }
+        }
+    }
+
+    if let Some(gtid_position) = found {
+        return Ok(GtidLocation::Found {
+            gtid: gtid_string,
+            file_path: file_path.to_string(),
+            gtid_position,
+            commit_position: None,
+            end_position: offset,
+        });
+    }
+
+    match (is_last_file, &next) {
+        (true, None) => Ok(GtidLocation::NotYetWritten {
+            gtid: gtid_string,
+            last_file_path: file_path.to_string(),
+            last_gtid: previous.as_ref().map(|v| v.to_string()),
+        }),
+        _ => Ok(GtidLocation::Gap {
+            gtid: gtid_string,
+            file_path: file_path.to_string(),
+            previous: previous.as_ref().map(|v| v.to_string()),
+            next: next.as_ref().map(|v| v.to_string()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use std::path::PathBuf;
+
+    // 每个事务的GTID和XID的位置
+    type Positions = HashMap<u64, (u64, u64)>;
+
+    fn gtid_list(sequence: u64) -> EventBuilder {
+        let mut body = 1u32.to_le_bytes().to_vec();
+        body.extend(0u32.to_le_bytes());
+        body.extend(1u32.to_le_bytes());
+        body.extend(sequence.to_le_bytes());
+        EventBuilder::new(163, body)
+    }
+
+    fn of(sequence: u64) -> GTID {
+        GTID {
+            replication_domain_id: 0,
+            server_id: 1,
+            gtid_sequence: sequence,
+        }
+    }
+
+    /// 一个文件：开头的GTID_LIST，之后每个事务是GTID、BEGIN、INSERT、XID
+    fn binlog_file(
+        previous: u64,
+        sequences: &[u64],
+        uncommitted: Option<u64>,
+    ) -> (Vec<u8>, Positions) {
+        let mut builder = BinlogBuilder::new().push(gtid_list(previous));
+        let mut positions = HashMap::new();
+
+        for sequence in sequences {
+            let gtid_position = builder.position();
+            builder = builder
+                .push(gtid(*sequence))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&QueryEventBuilder::new("shop", "INSERT INTO t VALUES (1)"));
+            positions.insert(*sequence, (gtid_position, builder.position()));
+            builder = builder.push(xid(*sequence));
+        }
+
+        if let Some(sequence) = uncommitted {
+            positions.insert(sequence, (builder.position(), 0));
+            builder = builder
+                .push(gtid(sequence))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"));
+        }
+
+        (builder.build(), positions)
+    }
+
+    // mysql-bin.000001：11-13，mysql-bin.000002：14、15、17，16不在其中，mysql-bin.000003：18和没有提交的19
+    fn chained_files(name: &str) -> (PathBuf, Vec<(Vec<u8>, Positions)>) {
+        let dir = std::env::temp_dir().join(format!("find-gtid-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let files = vec![
+            binlog_file(10, &[11, 12, 13], None),
+            binlog_file(13, &[14, 15, 17], None),
+            binlog_file(17, &[18], Some(19)),
+        ];
+        for (i, (binlog, _)) in files.iter().enumerate() {
+            std::fs::write(dir.join(format!("mysql-bin.00000{}", i + 1)), binlog).unwrap();
+        }
+
+        (dir, files)
+    }
+
+    fn path(dir: &Path, number: usize) -> String {
+        dir.join(format!("mysql-bin.00000{}", number))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn found_in_each_file() {
+        let (dir, files) = chained_files("found");
+        let dir_path = dir.to_str().unwrap();
+
+        for (sequence, number) in [(11, 1), (12, 1), (13, 1), (14, 2), (17, 2), (18, 3)] {
+            let (binlog, positions) = &files[number - 1];
+            let (gtid_position, commit_position) = positions[&sequence];
+            let xid_length = xid(sequence).build(0).len() as u64;
+
+            match find_gtid(dir_path, &of(sequence)).unwrap() {
+                GtidLocation::Found {
+                    gtid,
+                    file_path,
+                    gtid_position: found_gtid_position,
+                    commit_position: found_commit_position,
+                    end_position,
+                } => {
+                    assert_eq!(gtid, format!("0-1-{}", sequence));
+                    assert_eq!(file_path, path(&dir, number));
+                    assert_eq!(found_gtid_position, gtid_position);
+                    assert_eq!(found_commit_position, Some(commit_position));
+                    assert_eq!(end_position, commit_position + xid_length);
+                    assert!(end_position <= binlog.len() as u64);
+                }
+                location => panic!("0-1-{}: {}", sequence, location),
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn uncommitted_transaction_at_the_end() {
+        let (dir, files) = chained_files("uncommitted");
+
+        let location = find_gtid(dir.to_str().unwrap(), &of(19)).unwrap();
+        assert_eq!(
+            location.to_string(),
+            format!(
+                "0-1-19 is in {} at {}, commit at - (not committed), end at {}",
+                path(&dir, 3),
+                files[2].1[&19].0,
+                files[2].0.len()
+            )
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn purged_gap_and_not_yet_written() {
+        let (dir, _) = chained_files("missing");
+        let dir_path = dir.to_str().unwrap();
+
+        // 第一个文件的GTID_LIST已经包括
+        for sequence in [5, 10] {
+            assert_eq!(
+                find_gtid(dir_path, &of(sequence)).unwrap().to_string(),
+                format!(
+                    "0-1-{} has been purged, the first file {} starts after 0-1-10",
+                    sequence,
+                    path(&dir, 1)
+                )
+            );
+        }
+
+        assert_eq!(
+            find_gtid(dir_path, &of(16)).unwrap().to_string(),
+            format!(
+                "0-1-16 is not in {}, which has 0-1-15 before it and 0-1-17 after it",
+                path(&dir, 2)
+            )
+        );
+
+        assert_eq!(
+            find_gtid(dir_path, &of(25)).unwrap().to_string(),
+            format!(
+                "0-1-25 is not written yet, the last file {} ends with 0-1-19",
+                path(&dir, 3)
+            )
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn index_file_and_single_file() {
+        let (dir, _) = chained_files("index");
+        let index_path = dir.join("mysql-bin.index");
+        std::fs::write(
+            &index_path,
+            "./mysql-bin.000001\n./mysql-bin.000002\n./mysql-bin.000003\n",
+        )
+        .unwrap();
+        let index_path = index_path.to_str().unwrap();
+
+        assert_eq!(
+            list_binlog_files(index_path).unwrap(),
+            [path(&dir, 1), path(&dir, 2), path(&dir, 3)]
+        );
+        assert!(matches!(
+            find_gtid(index_path, &of(15)).unwrap(),
+            GtidLocation::Found { file_path, .. } if file_path == path(&dir, 2)
+        ));
+
+        // 只有一个文件时GTID_LIST之前的都是purge
+        let single = path(&dir, 2);
+        assert_eq!(
+            list_binlog_files(&single).unwrap(),
+            std::slice::from_ref(&single)
+        );
+        assert!(matches!(
+            find_gtid(&single, &of(15)).unwrap(),
+            GtidLocation::Found { .. }
+        ));
+        assert!(matches!(
+            find_gtid(&single, &of(12)).unwrap(),
+            GtidLocation::Purged { .. }
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
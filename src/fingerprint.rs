@@ -0,0 +1,240 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+// 比较运算符和逗号前后的空白统一成`a = ?`、`a, b`
+static OPERATOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*([=<>!]+)\s*").unwrap());
+static COMMA: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*,\s*").unwrap());
+
+// NULL也是值，和pt-query-digest一样
+static NULL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\bnull\b").unwrap());
+
+// `(?, ?, ?)`这样只有占位符的列表
+static VALUE_LIST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\(\s*\?(\s*,\s*\?)*\s*\)").unwrap());
+
+// VALUES中重复的多行
+static REPEATED_LISTS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\(\?\+\)(\s*,\s*\(\?\+\))+").unwrap());
+
+// 这些字符之后的`-`和`+`是数字的符号，不是运算符
+const BEFORE_SIGN: &[char] = &['=', '<', '>', '!', '(', ',', '*', '/', '+', '-'];
+
+/// 语句的指纹：去掉注释，字符串和数字换成`?`，小写，合并空白（运算符和逗号前后的空白也统一），
+/// `IN (1, 2, 3)`和VALUES中的多行合并成`(?+)`
+///
+/// 和pt-query-digest类似：NULL、`X'..'`、`_utf8mb4'..'`这样带前缀的字符串和带符号的数字也是`?`，
+/// 标识符中的数字（`t1`、反引号中的内容）保持原样
+pub fn fingerprint(sql: &str) -> String {
+    let chars = sql.chars().collect::<Vec<char>>();
+    let mut result = String::with_capacity(sql.len());
+
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let next = chars.get(index + 1).copied();
+
+        match c {
+            // 注释当作空白
+            '/' if next == Some('*') => {
+                index += 2;
+                while index < chars.len()
+                    && !(chars[index] == '*' && chars.get(index + 1) == Some(&'/'))
+                {
+                    index += 1;
+                }
+                index += 2;
+                result.push(' ');
+            }
+            '-' if next == Some('-') && chars.get(index + 2).is_none_or(|v| v.is_whitespace()) => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+                result.push(' ');
+            }
+            '#' => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+                result.push(' ');
+            }
+            '\'' | '"' => {
+                index += 1;
+                while index < chars.len() {
+                    match chars[index] {
+                        '\\' => index += 2,
+                        // 两个引号是引号本身
+                        v if v == c && chars.get(index + 1) == Some(&c) => index += 2,
+                        v if v == c => break,
+                        _ => index += 1,
+                    }
+                }
+                index += 1;
+                strip_introducer(&mut result);
+                result.push('?');
+            }
+            // 标识符中的内容保持原样
+            '`' => {
+                result.push(c);
+                index += 1;
+                while index < chars.len() {
+                    result.push(chars[index]);
+                    index += 1;
+                    if chars[index - 1] == '`' {
+                        break;
+                    }
+                }
+            }
+            // 数字不能是标识符的一部分，例如t1
+            c if (c.is_ascii_digit() || c == '.' && next.is_some_and(|v| v.is_ascii_digit()))
+                && !result.chars().last().is_some_and(is_identifier_char) =>
+            {
+                let hex = c == '0' && matches!(next, Some('x' | 'X'));
+                while index < chars.len() {
+                    match chars[index] {
+                        v if v.is_ascii_alphanumeric() || v == '.' => index += 1,
+                        // 1e-5中的符号
+                        '+' | '-' if !hex && matches!(chars[index - 1], 'e' | 'E') => index += 1,
+                        _ => break,
+                    }
+                }
+                strip_sign(&mut result);
+                result.push('?');
+            }
+            c if c.is_whitespace() => {
+                result.push(' ');
+                index += 1;
+            }
+            c => {
+                result.extend(c.to_lowercase());
+                index += 1;
+            }
+        }
+    }
+
+    let result = result.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let result = OPERATOR.replace_all(&result, " $1 ");
+    let result = COMMA.replace_all(&result, ", ");
+    let result = NULL.replace_all(&result, "?");
+    let result = VALUE_LIST.replace_all(&result, "(?+)");
+    REPEATED_LISTS.replace_all(&result, "(?+)").to_string()
+}
+
+/// 指纹的sha256的前16个十六进制字符，用来在输出中引用一组语句
+pub fn fingerprint_checksum(fingerprint: &str) -> String {
+    Sha256::digest(fingerprint.as_bytes())
+        .iter()
+        .take(8)
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+// 字符串之前紧挨着的x、b、n或者`_字符集`是字符串的一部分
+fn strip_introducer(result: &mut String) {
+    let start = result
+        .char_indices()
+        .rev()
+        .take_while(|(_, v)| is_identifier_char(*v))
+        .last()
+        .map(|(index, _)| index)
+        .unwrap_or(result.len());
+    let introducer = &result[start..];
+
+    if matches!(introducer, "x" | "b" | "n") || introducer.starts_with('_') {
+        result.truncate(start);
+    }
+}
+
+// 运算符、括号和逗号之后的`-1`、`+1`是一个数字
+fn strip_sign(result: &mut String) {
+    let trimmed = result.trim_end();
+    let sign_index = match trimmed.char_indices().last() {
+        Some((index, '-' | '+')) => index,
+        _ => return,
+    };
+
+    let before = trimmed[..sign_index].trim_end();
+    let is_sign = match before.chars().last() {
+        None => true,
+        Some(v) if BEFORE_SIGN.contains(&v) => true,
+        // 关键字之后，例如`LIMIT -1`、`THEN -1`
+        Some(v) if is_identifier_char(v) => {
+            let word = before
+                .rsplit(|v: char| !is_identifier_char(v))
+                .next()
+                .unwrap_or_default();
+            [
+                "select", "where", "and", "or", "not", "then", "else", "when", "values", "set",
+                "limit", "by", "in", "between", "return",
+            ]
+            .contains(&word)
+        }
+        _ => false,
+    };
+
+    if is_sign {
+        result.truncate(sign_index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints() {
+        let cases = [
+            // 大小写、空白和运算符前后的空白
+            ("SELECT * FROM t WHERE id = 42", "select * from t where id = ?"),
+            ("select  *  from   T where ID=42", "select * from t where id = ?"),
+            ("SELECT * FROM t WHERE a IS NULL AND b <> 3 AND c != 4 AND d>=5", "select * from t where a is ? and b <> ? and c != ? and d >= ?"),
+
+            // 字符串中的转义和引号
+            ("SELECT * FROM t WHERE name = 'O\\'Brien'", "select * from t where name = ?"),
+            ("SELECT * FROM t WHERE name = 'a\\\\' AND id = 1", "select * from t where name = ? and id = ?"),
+            ("SELECT * FROM t WHERE name = 'it''s' AND note = \"say \"\"hi\"\"\"", "select * from t where name = ? and note = ?"),
+            ("SELECT '-- not a comment', \"#not\"", "select ?, ?"),
+
+            // 带前缀的字符串和十六进制
+            ("SELECT * FROM t WHERE name = _utf8'x' AND b = _utf8mb4'y' COLLATE utf8mb4_bin", "select * from t where name = ? and b = ? collate utf8mb4_bin"),
+            ("SELECT * FROM t WHERE data = X'0aff' OR data = x'00' OR flag = b'101' OR n = N'abc'", "select * from t where data = ? or data = ? or flag = ? or n = ?"),
+            ("SELECT * FROM t WHERE data = 0xDEADBEEF", "select * from t where data = ?"),
+
+            // 带符号的数字，运算符之后的不是符号
+            ("UPDATE t SET a = -1, b = +2.5, c = 1e-5, d = -.5 WHERE id = 3", "update t set a = ?, b = ?, c = ?, d = ? where id = ?"),
+            ("SELECT a - 1, a + 1 FROM t LIMIT -1", "select a - ?, a + ? from t limit ?"),
+
+            // 标识符中的数字
+            ("SELECT * FROM t1 JOIN t2 ON t1.id = t2.id WHERE t2.c3 = 5", "select * from t1 join t2 on t1.id = t2.id where t2.c3 = ?"),
+            ("SELECT * FROM `t 123` WHERE `col1` = 1", "select * from `t 123` where `col1` = ?"),
+
+            // IN列表和VALUES中的多行
+            ("SELECT * FROM t WHERE id IN (1, 2, 3)", "select * from t where id in (?+)"),
+            ("SELECT * FROM t WHERE id IN ( 1 )", "select * from t where id in (?+)"),
+            ("SELECT * FROM t WHERE id IN ('a','b') AND x IN (1, a)", "select * from t where id in (?+) and x in (?, a)"),
+            ("INSERT INTO t (a, b) VALUES (1, 'x'), (2, 'y'), (3, NULL)", "insert into t (a, b) values (?+)"),
+            ("INSERT INTO t VALUES (1)", "insert into t values (?+)"),
+
+            // 注释
+            ("/* comment */ SELECT 1 -- trailing", "select ?"),
+            ("SELECT 1 # hash comment", "select ?"),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(fingerprint(sql), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn checksum_of_fingerprint() {
+        assert_eq!(fingerprint_checksum("select ?"), "e1c71d1661ae46e0");
+        assert_eq!(
+            fingerprint_checksum(&fingerprint("SELECT 1")),
+            fingerprint_checksum(&fingerprint("select  2 -- other"))
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use serde::Serialize;
+
+use crate::error::{ErrorCategory, ParseError};
+use crate::model::{
+    EventBody, EventBodyTypeCode15, EventBodyTypeCode19, EventBodyTypeCode2, EventHeader,
+};
+use crate::util::{get_status_variable_codes, parse_optional_metadata};
+
+/// 写binlog的服务端，只能从FDE中的server_version区分
+///
+/// Percona Server写的binlog和MySQL相同，Unknown是server_version无法解析，这时不按服务端做任何判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Flavor {
+    MariaDB,
+    MySQL,
+    Percona,
+    Unknown,
+}
+
+impl Flavor {
+    /// 这种服务端是否会写other才会写的内容，Percona会写MySQL的内容，Unknown都可能
+    pub fn writes(&self, other: Flavor) -> bool {
+        match (self, other) {
+            (Flavor::Unknown, _) | (Flavor::Percona, Flavor::MySQL) => true,
+            _ => *self == other,
+        }
+    }
+}
+
+impl std::fmt::Display for Flavor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Flavor::MariaDB => write!(f, "MariaDB"),
+            Flavor::MySQL => write!(f, "MySQL"),
+            Flavor::Percona => write!(f, "Percona"),
+            Flavor::Unknown => write!(f, "unknown server"),
+        }
+    }
+}
+
+// MariaDB在复制协议中为了兼容旧的MySQL客户端，版本号前面会加上`5.5.5-`
+const MARIADB_VERSION_PREFIX: &str = "5.5.5-";
+
+/// FDE中的server_version，例如`10.11.6-MariaDB-log`、`8.0.36`
+///
+/// suffix是版本号之后的部分，例如`-MariaDB-log`、`-28`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerVersion {
+    pub flavor: Flavor,
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub suffix: String,
+}
+
+impl ServerVersion {
+    /// 带MariaDB字样的是MariaDB，带Percona字样的是Percona，其他的按MySQL处理
+    ///
+    /// 不以`主版本.次版本`开头的是Unknown，这时版本号记为0，不会返回错误
+    pub fn parse(server_version: &str) -> ServerVersion {
+        let version = match server_version.contains("MariaDB") {
+            true => server_version
+                .strip_prefix(MARIADB_VERSION_PREFIX)
+                .unwrap_or(server_version),
+            false => server_version,
+        };
+
+        let end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+        let (numbers, suffix) = version.split_at(end);
+
+        let numbers = numbers
+            .split('.')
+            .map(|v| v.parse::<u32>().ok())
+            .collect::<Vec<_>>();
+
+        let (major, minor, patch) = match numbers[..] {
+            [Some(major), Some(minor)] => (major, minor, 0),
+            [Some(major), Some(minor), Some(patch)] => (major, minor, patch),
+            _ => {
+                return ServerVersion {
+                    flavor: Flavor::Unknown,
+                    major: 0,
+                    minor: 0,
+                    patch: 0,
+                    suffix: server_version.to_string(),
+                }
+            }
+        };
+
+        let flavor = if version.contains("MariaDB") {
+            Flavor::MariaDB
+        } else if version.contains("Percona") {
+            Flavor::Percona
+        } else {
+            Flavor::MySQL
+        };
+
+        ServerVersion {
+            flavor,
+            major,
+            minor,
+            patch,
+            suffix: suffix.to_string(),
+        }
+    }
+
+    /// 版本号不小于major.minor.patch，不区分服务端
+    pub fn at_least(&self, major: u32, minor: u32, patch: u32) -> bool {
+        (self.major, self.minor, self.patch) >= (major, minor, patch)
+    }
+
+    /// MySQL 5.6.1、MariaDB 5.3开始FDE中有校验算法，之前的binlog没有校验和
+    ///
+    /// 不知道服务端时按有校验算法处理，和没有FDE时的默认值相同
+    pub fn has_checksum_algorithm(&self) -> bool {
+        match self.flavor {
+            Flavor::MariaDB => self.at_least(5, 3, 0),
+            Flavor::MySQL | Flavor::Percona => self.at_least(5, 6, 1),
+            Flavor::Unknown => true,
+        }
+    }
+
+    /// 是否可能写v2的行事件（事件类型30到32），MySQL 5.6开始默认写v2，MariaDB只写v1
+    pub fn writes_rows_event_v2(&self) -> bool {
+        match self.flavor {
+            Flavor::MySQL | Flavor::Percona => self.at_least(5, 6, 0),
+            Flavor::MariaDB => false,
+            Flavor::Unknown => true,
+        }
+    }
+}
+
+/// `MariaDB 10.11.6`，不知道服务端时是原来的server_version
+impl std::fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.flavor {
+            Flavor::Unknown => write!(f, "{} `{}`", self.flavor, self.suffix),
+            _ => write!(
+                f,
+                "{} {}.{}.{}",
+                self.flavor, self.major, self.minor, self.patch
+            ),
+        }
+    }
+}
+
+/// 只有一种服务端会写的事件类型，两种都会写的返回None
+pub fn event_flavor(type_code: u8) -> Option<Flavor> {
+    match type_code {
+        // rows query、v2的行事件、GTID、previous GTIDs等
+        29..=37 | 39..=41 => Some(Flavor::MySQL),
+        // annotate rows、binlog checkpoint、GTID、GTID list、加密和压缩的事件
+        160..=171 => Some(Flavor::MariaDB),
+        _ => None,
+    }
+}
+
+/// query事件中只有一种服务端会写的状态变量
+pub fn status_variable_flavor(code: u8) -> Option<Flavor> {
+    match code {
+        // Q_UPDATED_DB_NAMES到Q_DEFAULT_TABLE_ENCRYPTION
+        12..=20 => Some(Flavor::MySQL),
+        // Q_HRNOW、Q_XID
+        128..=129 => Some(Flavor::MariaDB),
+        _ => None,
+    }
+}
+
+/// table map的可选元数据中只有MySQL会写的类型，COLUMN_VISIBILITY和VECTOR_DIMENSIONALITY
+pub fn optional_metadata_flavor(field_type: u8) -> Option<Flavor> {
+    match field_type {
+        12..=13 => Some(Flavor::MySQL),
+        _ => None,
+    }
+}
+
+/// 按FDE记录写binlog的服务端，检查之后的事件、状态变量和可选元数据是不是这种服务端会写的
+///
+/// 第一个FDE之前不知道服务端，不做检查。`--watch`时每个文件都有FDE，服务端变化时重新记录
+#[derive(Default)]
+pub struct FlavorTracker {
+    server_version: Option<ServerVersion>,
+}
+
+impl FlavorTracker {
+    pub fn new() -> FlavorTracker {
+        FlavorTracker::default()
+    }
+
+    pub fn server_version(&self) -> Option<&ServerVersion> {
+        self.server_version.as_ref()
+    }
+
+    /// FDE中的服务端和之前不同时返回它，用于输出检测的结果
+    pub fn update(&mut self, body: &dyn EventBody) -> Option<&ServerVersion> {
+        let format_description = body.downcast_ref::<EventBodyTypeCode15>()?;
+        let server_version = ServerVersion::parse(&format_description.server_version);
+
+        if self.server_version.as_ref() == Some(&server_version) {
+            return None;
+        }
+
+        self.server_version = Some(server_version);
+        self.server_version.as_ref()
+    }
+
+    /// 不是这种服务端会写的内容返回一个FlavorMismatch的问题
+    pub fn check(
+        &self,
+        header: &EventHeader,
+        offset: u64,
+        body: &dyn EventBody,
+    ) -> Option<ParseError> {
+        let server_version = self.server_version.as_ref()?;
+
+        let (writer, what) = match header.type_code {
+            // v2的行事件还要看版本，MySQL 5.6之前只写v1
+            30..=32 if server_version.writes_rows_event_v2() => return None,
+            30..=32 => ("MySQL 5.6 and later".to_string(), "the event".to_string()),
+            type_code => {
+                let (flavor, what) = match event_flavor(type_code) {
+                    Some(flavor) => (flavor, "the event".to_string()),
+                    None => self.find_in_body(body)?,
+                };
+
+                if server_version.flavor.writes(flavor) {
+                    return None;
+                }
+
+                (flavor.to_string(), what)
+            }
+        };
+
+        Some(
+            ParseError::new(
+                ErrorCategory::FlavorMismatch,
+                format!(
+                    "{} is only written by {}, but the format description event says the binlog is written by {}",
+                    what, writer, server_version
+                ),
+            )
+            .at(offset, Some(header.type_code)),
+        )
+    }
+
+    // 找到第一个只有一种服务端会写的状态变量或者可选元数据
+    fn find_in_body(&self, body: &dyn EventBody) -> Option<(Flavor, String)> {
+        if let Some(query) = body.downcast_ref::<EventBodyTypeCode2>() {
+            return get_status_variable_codes(&query.status_variables)
+                .ok()?
+                .into_iter()
+                .find_map(|code| {
+                    status_variable_flavor(code)
+                        .map(|flavor| (flavor, format!("status variable {}", code)))
+                });
+        }
+
+        let table_map = body.downcast_ref::<EventBodyTypeCode19>()?;
+        let start = table_map.number_of_columns.div_ceil(8) as usize;
+        let block = &table_map.optional_metadata_block;
+        if block.len() <= start {
+            return None;
+        }
+
+        parse_optional_metadata(&block[start..])
+            .ok()?
+            .into_iter()
+            .find_map(|(field_type, _)| {
+                optional_metadata_flavor(field_type)
+                    .map(|flavor| (flavor, format!("optional metadata type {}", field_type)))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(flavor: Flavor, major: u32, minor: u32, patch: u32, suffix: &str) -> ServerVersion {
+        ServerVersion {
+            flavor,
+            major,
+            minor,
+            patch,
+            suffix: suffix.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_server_versions() {
+        let cases = [
+            (
+                "10.6.16-MariaDB-log",
+                version(Flavor::MariaDB, 10, 6, 16, "-MariaDB-log"),
+            ),
+            (
+                "5.5.5-10.6.16-MariaDB",
+                version(Flavor::MariaDB, 10, 6, 16, "-MariaDB"),
+            ),
+            (
+                "10.11.6-MariaDB-1:10.11.6+maria~ubu2204-log",
+                version(
+                    Flavor::MariaDB,
+                    10,
+                    11,
+                    6,
+                    "-MariaDB-1:10.11.6+maria~ubu2204-log",
+                ),
+            ),
+            (
+                "11.4.2-MariaDB",
+                version(Flavor::MariaDB, 11, 4, 2, "-MariaDB"),
+            ),
+            (
+                "5.5.68-MariaDB",
+                version(Flavor::MariaDB, 5, 5, 68, "-MariaDB"),
+            ),
+            ("8.0.36", version(Flavor::MySQL, 8, 0, 36, "")),
+            ("8.4.0", version(Flavor::MySQL, 8, 4, 0, "")),
+            ("5.7.44-log", version(Flavor::MySQL, 5, 7, 44, "-log")),
+            ("5.1.73-log", version(Flavor::MySQL, 5, 1, 73, "-log")),
+            // Percona Server的server_version中一般只有构建号，和MySQL没有区别
+            ("8.0.36-28", version(Flavor::MySQL, 8, 0, 36, "-28")),
+            ("5.7.44-48-log", version(Flavor::MySQL, 5, 7, 44, "-48-log")),
+            (
+                "8.0.36-28-Percona",
+                version(Flavor::Percona, 8, 0, 36, "-28-Percona"),
+            ),
+            // MySQL的版本号不去掉5.5.5-前缀
+            ("5.5.5-8.0.36", version(Flavor::MySQL, 5, 5, 5, "-8.0.36")),
+            ("8.0", version(Flavor::MySQL, 8, 0, 0, "")),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(ServerVersion::parse(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_versions_are_unknown() {
+        for text in [
+            "", "MariaDB", "garbage", "10", "v8.0.36", "10.x.1", "1.2.3.4",
+        ] {
+            let version = ServerVersion::parse(text);
+            assert_eq!(version.flavor, Flavor::Unknown, "{}", text);
+            assert_eq!((version.major, version.minor, version.patch), (0, 0, 0));
+            assert_eq!(version.suffix, text);
+        }
+
+        assert_eq!(
+            ServerVersion::parse("garbage").to_string(),
+            "unknown server `garbage`"
+        );
+        assert_eq!(
+            ServerVersion::parse("5.5.5-10.6.16-MariaDB").to_string(),
+            "MariaDB 10.6.16"
+        );
+    }
+
+    #[test]
+    fn compare_versions() {
+        let mariadb = ServerVersion::parse("10.6.16-MariaDB-log");
+        assert!(mariadb.at_least(10, 5, 0));
+        assert!(mariadb.at_least(10, 6, 16));
+        assert!(!mariadb.at_least(10, 6, 17));
+        assert!(!mariadb.at_least(11, 0, 0));
+
+        let mysql = ServerVersion::parse("8.0.36");
+        assert!(mysql.at_least(5, 7, 100));
+        assert!(!mysql.at_least(8, 1, 0));
+    }
+
+    #[test]
+    fn capabilities() {
+        let checksum = |text: &str| ServerVersion::parse(text).has_checksum_algorithm();
+        assert!(checksum("10.6.16-MariaDB-log"));
+        assert!(checksum("5.3.12-MariaDB"));
+        assert!(!checksum("5.2.14-MariaDB"));
+        assert!(checksum("5.6.1-log"));
+        assert!(!checksum("5.6.0-log"));
+        assert!(!checksum("5.1.73-log"));
+        assert!(checksum("garbage"));
+
+        let rows_v2 = |text: &str| ServerVersion::parse(text).writes_rows_event_v2();
+        assert!(rows_v2("8.0.36"));
+        assert!(rows_v2("8.0.36-28-Percona"));
+        assert!(!rows_v2("5.5.62-log"));
+        assert!(!rows_v2("10.11.6-MariaDB-log"));
+        assert!(rows_v2(""));
+    }
+
+    #[test]
+    fn flavors_written() {
+        assert!(Flavor::Percona.writes(Flavor::MySQL));
+        assert!(!Flavor::MySQL.writes(Flavor::MariaDB));
+        assert!(!Flavor::MariaDB.writes(Flavor::MySQL));
+        assert!(Flavor::Unknown.writes(Flavor::MariaDB));
+
+        assert_eq!(event_flavor(30), Some(Flavor::MySQL));
+        assert_eq!(event_flavor(162), Some(Flavor::MariaDB));
+        assert_eq!(event_flavor(2), None);
+    }
+}
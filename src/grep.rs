@@ -0,0 +1,346 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use regex::Regex;
+
+use crate::model::*;
+use crate::table_structure::column_names;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 用正则匹配query、annotate事件中的SQL和行事件中的字段值
+/// 匹配的事件输出前需要先输出它所在事务的GTID事件和用到的table map，每个事件都要经过process
+pub struct GrepFilter {
+    regex: Regex,
+    // `库名.表名.字段名`，不为空时只匹配这些字段，不再匹配SQL
+    columns: HashSet<String>,
+    // 二进制值默认不参与匹配，打开后按小写十六进制匹配
+    binary_hex: bool,
+    gtid_position: Option<u64>,
+    // table_id -> table map事件的位置
+    table_map_positions: HashMap<u64, u64>,
+    // 当前事务中已经输出过的上下文事件
+    emitted: HashSet<u64>,
+}
+
+impl GrepFilter {
+    pub fn new(
+        pattern: &str,
+        columns: &[String],
+        binary_hex: bool,
+    ) -> Result<GrepFilter, BoxedError> {
+        for column in columns {
+            if column.split('.').count() != 3 {
+                return Err(Box::new(MyError(format!(
+                    "`{}` is not in the form of db.tbl.col",
+                    column
+                ))));
+            }
+        }
+
+        Ok(GrepFilter {
+            regex: Regex::new(pattern)?,
+            columns: columns.iter().cloned().collect(),
+            binary_hex,
+            gtid_position: None,
+            table_map_positions: HashMap::new(),
+            emitted: HashSet::new(),
+        })
+    }
+
+    /// 事件匹配时返回需要先输出的上下文事件的位置（可能为空），不匹配时返回None
+    /// format description事件总是会输出
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Option<Vec<u64>> {
+        if body.downcast_ref::<EventBodyTypeCode162>().is_some()
+            || body.downcast_ref::<EventBodyTypeCode33>().is_some()
+        {
+            self.gtid_position = Some(position);
+            self.table_map_positions.clear();
+            self.emitted.clear();
+            return None;
+        }
+
+        if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+            self.table_map_positions
+                .insert(table_map.table_id, position);
+            return None;
+        }
+
+        let mut context = Vec::new();
+
+        let is_match = match header.type_code {
+            15 => return Some(context),
+            2 => body
+                .downcast_ref::<EventBodyTypeCode2>()
+                .is_some_and(|v| self.columns.is_empty() && self.regex.is_match(&v.sql)),
+            160 => body
+                .downcast_ref::<EventBodyTypeCode160>()
+                .is_some_and(|v| self.columns.is_empty() && self.regex.is_match(&v.sql)),
+            _ => match body.downcast_ref::<EventBodyTypeCode23To25>() {
+                Some(rows_event) => {
+                    let is_match = self.is_rows_match(rows_event, table_structs);
+                    if is_match {
+                        context.extend(self.table_map_positions.get(&rows_event.table_id));
+                    }
+                    is_match
+                }
+                None => false,
+            },
+        };
+
+        if !is_match {
+            return None;
+        }
+
+        context.extend(self.gtid_position);
+        context.sort();
+        context.retain(|v| self.emitted.insert(*v));
+
+        Some(context)
+    }
+
+    fn is_rows_match(
+        &self,
+        rows_event: &EventBodyTypeCode23To25,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> bool {
+        let table_info = match table_structs.get(&rows_event.table_id) {
+            Some(table_info) => table_info,
+            None => return false,
+        };
+
+        // 指定了字段时只把这些字段转成字符串
+        let selected = if self.columns.is_empty() {
+            None
+        } else {
+            let prefix = format!("{}.{}.", table_info.database_name, table_info.table_name);
+            if !self.columns.iter().any(|v| v.starts_with(&prefix)) {
+                return false;
+            }
+
+            Some(
+                column_names(table_info)
+                    .iter()
+                    .map(|v| self.columns.contains(&format!("{}{}", prefix, v)))
+                    .collect::<Vec<bool>>(),
+            )
+        };
+
+        rows_event
+            .rows
+            .iter()
+            .flat_map(|row| row.before.iter().chain(row.after.iter()))
+            .any(|image| {
+                image.iter().enumerate().any(|(i, value)| {
+                    if selected
+                        .as_ref()
+                        .is_some_and(|v| !v.get(i).copied().unwrap_or(false))
+                    {
+                        return false;
+                    }
+
+                    self.value_text(value)
+                        .is_some_and(|text| self.regex.is_match(&text))
+                })
+            })
+    }
+
+    fn value_text(&self, value: &ColumnValue) -> Option<String> {
+        match value {
+            ColumnValue::Null | ColumnValue::Unsupported(_) => None,
+            ColumnValue::Int(v) => Some(v.to_string()),
+            ColumnValue::UInt(v) => Some(v.to_string()),
+            ColumnValue::Float(v) => Some(v.to_string()),
+            ColumnValue::Double(v) => Some(v.to_string()),
+            ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => {
+                Some(v.clone())
+            }
+            ColumnValue::RawTemporal(v) => Some(v.to_string()),
+            ColumnValue::RawDecimal(v) => Some(v.value.clone()),
+            ColumnValue::Bytes(v) if self.binary_hex => {
+                Some(v.iter().map(|b| format!("{:02x}", b)).collect())
+            }
+            ColumnValue::Bytes(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    fn mariadb_gtid(sequence: u64) -> EventBuilder {
+        let mut body = sequence.to_le_bytes().to_vec();
+        body.extend(0u32.to_le_bytes());
+        body.push(0);
+        body.extend([0; 6]);
+
+        EventBuilder::new(162, body)
+    }
+
+    fn mysql_gtid(gno: u64) -> EventBuilder {
+        let mut body = vec![1];
+        body.extend([0x3e; 16]);
+        body.extend((gno as i64).to_le_bytes());
+
+        EventBuilder::new(33, body)
+    }
+
+    fn customers() -> TableMapBuilder {
+        TableMapBuilder::new("shop", "customers")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .column(FieldType::Blob, &[2])
+            .optional_metadata(&[
+                4, 13, 2, b'i', b'd', 4, b'n', b'o', b't', b'e', 4, b'd', b'a', b't', b'a',
+            ])
+    }
+
+    fn row(id: i64, note: &str, data: &[u8]) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(note.to_string()),
+            ColumnValue::Bytes(data.to_vec()),
+        ]
+    }
+
+    // 12345只出现在第二个事务的行中和第三个事务的query事件中
+    fn fixture(binlog: BinlogBuilder, gtid: fn(u64) -> EventBuilder, v2: bool) -> Vec<u8> {
+        let table = customers();
+        let xid = || EventBuilder::new(16, 1u64.to_le_bytes().to_vec());
+
+        binlog
+            .push(gtid(1))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .v2(v2)
+                    .row(row(1, "new", b"")),
+            )
+            .push(xid())
+            .push(gtid(2))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .v2(v2)
+                    .row(row(2, "new", b""))
+                    .row(row(12345, "vip", b"")),
+            )
+            .push(xid())
+            .push(gtid(3))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&QueryEventBuilder::new(
+                "shop",
+                "UPDATE customers SET note = 'gold' WHERE id = 12345",
+            ))
+            .push(xid())
+            .build()
+    }
+
+    // 匹配的事件的类型，以及在它之前输出的上下文事件的类型
+    fn matches(binlog: &[u8], filter: &mut GrepFilter) -> Vec<(u8, Vec<u8>)> {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut type_codes = HashMap::new();
+        let mut result = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            type_codes.insert(position, header.type_code);
+
+            if let Some(context) = filter.process(&header, position, body.as_ref(), &table_structs)
+            {
+                let context = context.iter().map(|v| type_codes[v]).collect();
+                result.push((header.type_code, context));
+            }
+
+            position = header.next_event_position as u64;
+        }
+
+        result
+    }
+
+    #[test]
+    fn match_query_and_row() {
+        let mut filter = GrepFilter::new(r"\b12345\b", &[], false).unwrap();
+        let binlog = fixture(BinlogBuilder::new(), mariadb_gtid, false);
+        assert_eq!(
+            matches(&binlog, &mut filter),
+            [(15, vec![]), (23, vec![162, 19]), (2, vec![162])]
+        );
+
+        let mut filter = GrepFilter::new(r"\b12345\b", &[], false).unwrap();
+        let binlog = fixture(
+            BinlogBuilder::with_server_version("8.0.36"),
+            mysql_gtid,
+            true,
+        );
+        assert_eq!(
+            matches(&binlog, &mut filter),
+            [(15, vec![]), (30, vec![33, 19]), (2, vec![33])]
+        );
+    }
+
+    // 指定字段时只匹配这些字段的值，不再匹配SQL
+    #[test]
+    fn match_selected_columns() {
+        let binlog = fixture(BinlogBuilder::new(), mariadb_gtid, false);
+
+        let columns = ["shop.customers.id".to_string()];
+        let mut filter = GrepFilter::new("12345", &columns, false).unwrap();
+        assert_eq!(
+            matches(&binlog, &mut filter),
+            [(15, vec![]), (23, vec![162, 19])]
+        );
+
+        let columns = ["shop.customers.note".to_string()];
+        let mut filter = GrepFilter::new("12345", &columns, false).unwrap();
+        assert_eq!(matches(&binlog, &mut filter), [(15, vec![])]);
+
+        let columns = ["shop.customers".to_string()];
+        assert!(GrepFilter::new("12345", &columns, false).is_err());
+    }
+
+    // 二进制值只在打开binary_hex时按十六进制匹配
+    #[test]
+    fn match_binary_as_hex() {
+        let table = customers();
+        let binlog = BinlogBuilder::new()
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(row(1, "new", &[0xca, 0xfe])))
+            .build();
+
+        let mut filter = GrepFilter::new("cafe", &[], false).unwrap();
+        assert_eq!(matches(&binlog, &mut filter), [(15, vec![])]);
+
+        let mut filter = GrepFilter::new("cafe", &[], true).unwrap();
+        assert_eq!(
+            matches(&binlog, &mut filter),
+            [(15, vec![]), (23, vec![19])]
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use serde::Serialize;
+
+use crate::model::*;
+use crate::transaction::{TransactionSummary, TransactionTracker};
+
+/// 输出的最大的组的个数
+pub const LARGEST_GROUPS: usize = 10;
+
+/// 一个提交组，binlog中连续的、GTID中commit_id相同的事务，没有commit_id的事务单独一组
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitGroup {
+    pub commit_id: Option<u64>,
+    pub transactions: u64,
+    pub first_gtid: String,
+    pub last_gtid: String,
+    pub file_name: String,
+    /// 第一个事务的GTID事件的位置
+    pub start_position: u64,
+}
+
+/// 大小在min_size到max_size之间（包括两端）的组
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupSizeBucket {
+    pub min_size: u64,
+    pub max_size: u64,
+    pub groups: u64,
+    pub transactions: u64,
+}
+
+/// `--group-commit-report`的结果
+#[derive(Debug, Serialize)]
+pub struct GroupCommitSummary {
+    pub transactions: u64,
+    pub groups: u64,
+    /// 单独一组的事务数，它们在乐观的并行复制中不能和其他事务一起执行
+    pub singleton_transactions: u64,
+    pub singleton_fraction: f64,
+    pub average_group_size: f64,
+    /// 按1、2、3-4、5-8这样2的幂的区间
+    pub size_distribution: Vec<GroupSizeBucket>,
+    /// 从大到小，大小相同时按出现的顺序
+    pub largest_groups: Vec<CommitGroup>,
+}
+
+/// `--group-commit-report`，按GTID中的commit_id（FL_GROUP_COMMIT_ID）统计组提交，
+/// 同一组的事务可以在从库上并行执行，单独一组的事务越多，并行复制的作用越小
+///
+/// 事务按TransactionTracker划分，只认MariaDB的GTID
+#[derive(Default)]
+pub struct GroupCommitReport {
+    file_name: String,
+    transaction_tracker: TransactionTracker,
+    // 当前事务的GTID中的commit_id
+    commit_id: Option<u64>,
+    current_group: Option<CommitGroup>,
+    transactions: u64,
+    groups: u64,
+    singleton_transactions: u64,
+    // 区间的下标（组的大小以2为底的对数向上取整） -> (组数, 事务数)
+    buckets: Vec<(u64, u64)>,
+    largest_groups: Vec<CommitGroup>,
+}
+
+impl GroupCommitReport {
+    pub fn new() -> GroupCommitReport {
+        GroupCommitReport::default()
+    }
+
+    /// 每个文件开始时调用，结果中记录组所在的文件
+    pub fn start_file(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+    }
+
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        // 新的GTID会结束上一个事务，这时还是上一个事务的commit_id
+        let finished = self
+            .transaction_tracker
+            .process(header, position, body, table_structs);
+        if let Some(transaction) = finished {
+            self.add(&transaction);
+        }
+
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.commit_id = gtid_event.commit_id;
+        }
+    }
+
+    /// 文件结束时还没有提交的事务也计入
+    pub fn finish(&mut self) -> GroupCommitSummary {
+        if let Some(transaction) = self.transaction_tracker.finish() {
+            self.add(&transaction);
+        }
+        self.close_group();
+
+        let size_distribution = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, (groups, _))| *groups > 0)
+            .map(|(index, (groups, transactions))| GroupSizeBucket {
+                min_size: match index {
+                    0 => 1,
+                    _ => (1 << (index - 1)) + 1,
+                },
+                max_size: 1 << index,
+                groups: *groups,
+                transactions: *transactions,
+            })
+            .collect();
+
+        GroupCommitSummary {
+            transactions: self.transactions,
+            groups: self.groups,
+            singleton_transactions: self.singleton_transactions,
+            singleton_fraction: ratio(self.singleton_transactions, self.transactions),
+            average_group_size: ratio(self.transactions, self.groups),
+            size_distribution,
+            largest_groups: self.largest_groups.clone(),
+        }
+    }
+
+    fn add(&mut self, transaction: &TransactionSummary) {
+        self.transactions += 1;
+
+        match self.current_group.as_mut() {
+            Some(group) if group.commit_id.is_some() && group.commit_id == self.commit_id => {
+                group.transactions += 1;
+                group.last_gtid = transaction.gtid.clone();
+            }
+            _ => {
+                self.close_group();
+                self.current_group = Some(CommitGroup {
+                    commit_id: self.commit_id,
+                    transactions: 1,
+                    first_gtid: transaction.gtid.clone(),
+                    last_gtid: transaction.gtid.clone(),
+                    file_name: self.file_name.clone(),
+                    start_position: transaction.start_position,
+                });
+            }
+        }
+    }
+
+    fn close_group(&mut self) {
+        let group = match self.current_group.take() {
+            Some(group) => group,
+            None => return,
+        };
+
+        self.groups += 1;
+        if group.transactions == 1 {
+            self.singleton_transactions += 1;
+        }
+
+        let index = group.transactions.next_power_of_two().trailing_zeros() as usize;
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, (0, 0));
+        }
+        self.buckets[index].0 += 1;
+        self.buckets[index].1 += group.transactions;
+
+        // 只保留最大的几个，大小相同时先出现的在前面
+        let position = self
+            .largest_groups
+            .iter()
+            .position(|v| v.transactions < group.transactions)
+            .unwrap_or(self.largest_groups.len());
+        if position < LARGEST_GROUPS {
+            self.largest_groups.insert(position, group);
+            self.largest_groups.truncate(LARGEST_GROUPS);
+        }
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    match denominator {
+        0 => 0.0,
+        _ => numerator as f64 / denominator as f64,
+    }
+}
+
+impl Display for GroupCommitSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} transactions in {} commit groups, {:.2} transactions per group on average",
+            self.transactions, self.groups, self.average_group_size
+        )?;
+        writeln!(
+            f,
+            "{} transactions ({:.1}%) are committed alone and cannot run in parallel with others",
+            self.singleton_transactions,
+            self.singleton_fraction * 100.0
+        )?;
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>11}  {:>10}  {:>12}  {:>7}",
+            "group size", "groups", "transactions", "percent"
+        )?;
+        for bucket in &self.size_distribution {
+            let size = match bucket.min_size == bucket.max_size {
+                true => bucket.min_size.to_string(),
+                false => format!("{}-{}", bucket.min_size, bucket.max_size),
+            };
+            writeln!(
+                f,
+                "{:>11}  {:>10}  {:>12}  {:>6.1}%",
+                size,
+                bucket.groups,
+                bucket.transactions,
+                ratio(bucket.transactions, self.transactions) * 100.0
+            )?;
+        }
+
+        if !self.largest_groups.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "largest groups:")?;
+            for group in &self.largest_groups {
+                writeln!(
+                    f,
+                    "{:>6} transactions  commit_id={}  {}..{}  {}:{}",
+                    group.transactions,
+                    group
+                        .commit_id
+                        .map(|v| v.to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    group.first_gtid,
+                    group.last_gtid,
+                    group.file_name,
+                    group.start_position
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    // 有commit_id时带FL_GROUP_COMMIT_ID
+    fn gtid(sequence: u64, commit_id: Option<u64>) -> EventBuilder {
+        let gtid = GtidEventBuilder::new(sequence);
+        match commit_id {
+            Some(commit_id) => EventBuilder::from_body(&gtid.commit_id(commit_id)),
+            None => EventBuilder::from_body(&gtid),
+        }
+    }
+
+    // 按顺序每个事务的commit_id，最后一个事务没有提交，返回文件和每个事务的GTID事件的位置
+    fn fixture(commit_ids: &[Option<u64>]) -> (Vec<u8>, Vec<u64>) {
+        let mut builder = BinlogBuilder::new();
+        let mut positions = Vec::new();
+        for (i, commit_id) in commit_ids.iter().enumerate() {
+            positions.push(builder.position());
+            builder = builder
+                .push(gtid(i as u64 + 1, *commit_id))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"));
+            if i + 1 < commit_ids.len() {
+                builder = builder.push(EventBuilder::new(16, (i as u64).to_le_bytes().to_vec()));
+            }
+        }
+        (builder.build(), positions)
+    }
+
+    fn report(binlog: &[u8]) -> GroupCommitSummary {
+        let mut report = GroupCommitReport::new();
+        report.start_file("mysql-bin.000001");
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            report.process(&header, position, body.as_ref(), &table_structs);
+            position = header.next_event_position as u64;
+        }
+        report.finish()
+    }
+
+    // 3个100、没有commit_id的、2个101、102、5个103、104，最后又是100，不连续所以是新的一组
+    fn commit_ids() -> Vec<Option<u64>> {
+        let mut commit_ids = vec![Some(100); 3];
+        commit_ids.push(None);
+        commit_ids.extend([Some(101), Some(101), Some(102)]);
+        commit_ids.extend([Some(103); 5]);
+        commit_ids.extend([Some(104), Some(100)]);
+        commit_ids
+    }
+
+    #[test]
+    fn groups_and_distribution() {
+        let (binlog, positions) = fixture(&commit_ids());
+        let summary = report(&binlog);
+
+        assert_eq!(summary.transactions, 14);
+        assert_eq!(summary.groups, 7);
+        assert_eq!(summary.singleton_transactions, 4);
+        assert!((summary.singleton_fraction - 4.0 / 14.0).abs() < 1e-9);
+        assert!((summary.average_group_size - 2.0).abs() < 1e-9);
+        assert_eq!(
+            summary
+                .size_distribution
+                .iter()
+                .map(|v| (v.min_size, v.max_size, v.groups, v.transactions))
+                .collect::<Vec<_>>(),
+            [(1, 1, 4, 4), (2, 2, 1, 2), (3, 4, 1, 3), (5, 8, 1, 5)]
+        );
+
+        // 从大到小，大小相同时按出现的顺序
+        assert_eq!(
+            summary
+                .largest_groups
+                .iter()
+                .map(|v| (v.commit_id, v.transactions))
+                .collect::<Vec<_>>(),
+            [
+                (Some(103), 5),
+                (Some(100), 3),
+                (Some(101), 2),
+                (None, 1),
+                (Some(102), 1),
+                (Some(104), 1),
+                (Some(100), 1)
+            ]
+        );
+        let largest = &summary.largest_groups[0];
+        assert_eq!(largest.first_gtid, "0-1-8");
+        assert_eq!(largest.last_gtid, "0-1-12");
+        assert_eq!(largest.start_position, positions[7]);
+
+        assert_eq!(
+            summary.to_string(),
+            format!(
+                concat!(
+                    "14 transactions in 7 commit groups, 2.00 transactions per group on average\n",
+                    "4 transactions (28.6%) are committed alone and cannot run in parallel with others\n",
+                    "\n",
+                    " group size      groups  transactions  percent\n",
+                    "          1           4             4    28.6%\n",
+                    "          2           1             2    14.3%\n",
+                    "        3-4           1             3    21.4%\n",
+                    "        5-8           1             5    35.7%\n",
+                    "\n",
+                    "largest groups:\n",
+                    "     5 transactions  commit_id=103  0-1-8..0-1-12  mysql-bin.000001:{}\n",
+                    "     3 transactions  commit_id=100  0-1-1..0-1-3  mysql-bin.000001:{}\n",
+                    "     2 transactions  commit_id=101  0-1-5..0-1-6  mysql-bin.000001:{}\n",
+                    "     1 transactions  commit_id=-  0-1-4..0-1-4  mysql-bin.000001:{}\n",
+                    "     1 transactions  commit_id=102  0-1-7..0-1-7  mysql-bin.000001:{}\n",
+                    "     1 transactions  commit_id=104  0-1-13..0-1-13  mysql-bin.000001:{}\n",
+                    "     1 transactions  commit_id=100  0-1-14..0-1-14  mysql-bin.000001:{}\n",
+                ),
+                positions[7],
+                positions[0],
+                positions[4],
+                positions[3],
+                positions[6],
+                positions[12],
+                positions[13]
+            )
+        );
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["groups"], 7);
+        assert_eq!(json["size_distribution"][2]["min_size"], 3);
+        assert_eq!(json["largest_groups"][0]["commit_id"], 103);
+        assert_eq!(
+            json["largest_groups"][3]["commit_id"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn only_the_largest_groups_are_kept() {
+        let mut commit_ids = (0..12).map(Some).collect::<Vec<_>>();
+        commit_ids.extend([Some(12), Some(12)]);
+        let summary = report(&fixture(&commit_ids).0);
+
+        assert_eq!(summary.groups, 13);
+        assert_eq!(summary.largest_groups.len(), LARGEST_GROUPS);
+        assert_eq!(summary.largest_groups[0].commit_id, Some(12));
+        assert_eq!(
+            summary.largest_groups[1..]
+                .iter()
+                .map(|v| v.commit_id.unwrap())
+                .collect::<Vec<_>>(),
+            (0..9).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn without_commit_ids() {
+        // 没有FL_GROUP_COMMIT_ID时每个事务单独一组
+        let summary = report(&fixture(&[None, None, None]).0);
+        assert_eq!(summary.groups, 3);
+        assert_eq!(summary.singleton_transactions, 3);
+        assert!((summary.singleton_fraction - 1.0).abs() < 1e-9);
+
+        let summary = report(&BinlogBuilder::new().build());
+        assert_eq!(summary.transactions, 0);
+        assert_eq!(summary.singleton_fraction, 0.0);
+        assert!(summary.size_distribution.is_empty());
+        assert!(!summary.to_string().contains("largest groups"));
+    }
+}
@@ -0,0 +1,615 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use serde::{Serialize, Serializer};
+
+use crate::model::*;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// MariaDB的GTID位置，每个domain一个最后的GTID，和`gtid_slave_pos`一样
+///
+/// 同一个domain中sequence是递增的，所以一个GTID在集合中就是它的sequence不大于这个domain的sequence，
+/// server_id只用于输出，不参与比较
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GtidSet {
+    // domain -> (server_id, sequence)，按domain排序
+    domains: BTreeMap<u32, (u32, u64)>,
+}
+
+impl GtidSet {
+    pub fn new() -> GtidSet {
+        GtidSet::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// domain的个数
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// 这个domain最后的GTID
+    pub fn get(&self, domain_id: u32) -> Option<GTID> {
+        self.domains
+            .get(&domain_id)
+            .map(|(server_id, sequence)| GTID {
+                replication_domain_id: domain_id,
+                server_id: *server_id,
+                gtid_sequence: *sequence,
+            })
+    }
+
+    /// 按domain排序的每个domain最后的GTID
+    pub fn gtids(&self) -> Vec<GTID> {
+        self.domains
+            .iter()
+            .map(|(domain_id, (server_id, sequence))| GTID {
+                replication_domain_id: *domain_id,
+                server_id: *server_id,
+                gtid_sequence: *sequence,
+            })
+            .collect()
+    }
+
+    /// GTID所在的事务是否已经在这个位置之前
+    pub fn contains(&self, gtid: &GTID) -> bool {
+        self.domains
+            .get(&gtid.replication_domain_id)
+            .is_some_and(|(_, sequence)| gtid.gtid_sequence <= *sequence)
+    }
+
+    /// 把GTID所在的domain推进到这个GTID，已经在集合中的GTID不会让位置后退，sequence相同时保留原来的server_id
+    pub fn advance(&mut self, gtid: &GTID) {
+        if !self.contains(gtid) {
+            self.domains.insert(
+                gtid.replication_domain_id,
+                (gtid.server_id, gtid.gtid_sequence),
+            );
+        }
+    }
+
+    /// 合并两个位置，每个domain取较新的GTID
+    pub fn merge(&mut self, other: &GtidSet) {
+        for gtid in other.gtids() {
+            self.advance(&gtid);
+        }
+    }
+
+    /// self中比other新的domain，也就是从other继续时还需要的部分
+    pub fn difference(&self, other: &GtidSet) -> GtidSet {
+        GtidSet {
+            domains: self
+                .gtids()
+                .into_iter()
+                .filter(|v| !other.contains(v))
+                .map(|v| (v.replication_domain_id, (v.server_id, v.gtid_sequence)))
+                .collect(),
+        }
+    }
+
+    /// 是否每个domain都不比other新
+    pub fn is_subset(&self, other: &GtidSet) -> bool {
+        self.gtids().iter().all(|v| other.contains(v))
+    }
+
+    /// 按顺序处理事件时调用：GTID事件推进它的domain，GTID_LIST合并其中的位置
+    /// GTID事件在事务的开头，所以事务处理完之前集合中已经包括这个事务
+    pub fn observe(&mut self, header: &EventHeader, body: &dyn EventBody) {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.advance(&GTID {
+                replication_domain_id: gtid_event.replication_domain_id,
+                server_id: header.server_id,
+                gtid_sequence: gtid_event.gtid_sequence,
+            });
+        } else if let Some(gtid_list) = body.downcast_ref::<EventBodyTypeCode163>() {
+            self.merge(&GtidSet::from(gtid_list));
+        }
+    }
+}
+
+impl From<&EventBodyTypeCode163> for GtidSet {
+    fn from(gtid_list: &EventBodyTypeCode163) -> GtidSet {
+        gtid_list.gtids.iter().collect()
+    }
+}
+
+impl<'a> FromIterator<&'a GTID> for GtidSet {
+    fn from_iter<T: IntoIterator<Item = &'a GTID>>(iter: T) -> GtidSet {
+        let mut gtid_set = GtidSet::new();
+        for gtid in iter {
+            gtid_set.advance(gtid);
+        }
+        gtid_set
+    }
+}
+
+/// `0-1-100,1-2-50`，按domain排序，空集合为空字符串
+impl std::fmt::Display for GtidSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gtids: Vec<String> = self.gtids().iter().map(|v| v.to_string()).collect();
+        write!(f, "{}", gtids.join(","))
+    }
+}
+
+/// 逗号分隔的domain-server_id-sequence，同一个domain只能有一个，空字符串是空集合
+impl FromStr for GtidSet {
+    type Err = BoxedError;
+
+    fn from_str(value: &str) -> Result<GtidSet, BoxedError> {
+        let mut gtid_set = GtidSet::new();
+
+        for item in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let gtid = item.parse::<GTID>()?;
+
+            if gtid_set.get(gtid.replication_domain_id).is_some() {
+                return Err(Box::new(MyError(format!(
+                    "more than one GTID for domain {} in `{}`",
+                    gtid.replication_domain_id, value
+                ))));
+            }
+
+            gtid_set.advance(&gtid);
+        }
+
+        Ok(gtid_set)
+    }
+}
+
+impl Serialize for GtidSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// MySQL的GTID集合，每个server uuid若干个不相交的区间，和`gtid_executed`一样
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MysqlGtidSet {
+    // uuid -> 按顺序排列、不相交也不相邻的闭区间
+    intervals: BTreeMap<String, Vec<(u64, u64)>>,
+}
+
+impl MysqlGtidSet {
+    pub fn new() -> MysqlGtidSet {
+        MysqlGtidSet::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn contains(&self, uuid: &str, gno: u64) -> bool {
+        self.intervals
+            .get(&uuid.to_lowercase())
+            .is_some_and(|v| v.iter().any(|(start, end)| *start <= gno && gno <= *end))
+    }
+
+    /// 加入一个GTID
+    pub fn add(&mut self, uuid: &str, gno: u64) {
+        self.add_interval(uuid, gno, gno);
+    }
+
+    /// 合并两个集合
+    pub fn merge(&mut self, other: &MysqlGtidSet) {
+        for (uuid, intervals) in &other.intervals {
+            for (start, end) in intervals {
+                self.add_interval(uuid, *start, *end);
+            }
+        }
+    }
+
+    /// self中不在other中的GTID
+    pub fn difference(&self, other: &MysqlGtidSet) -> MysqlGtidSet {
+        let mut result = MysqlGtidSet::new();
+
+        for (uuid, intervals) in &self.intervals {
+            let removed = other.intervals.get(uuid).map(Vec::as_slice).unwrap_or(&[]);
+
+            for (start, end) in intervals {
+                // 从前往后去掉other中的区间，next是还没有处理的第一个gno
+                let mut next = Some(*start);
+                for (removed_start, removed_end) in removed {
+                    let Some(current) = next.filter(|v| v <= end) else {
+                        break;
+                    };
+                    if *removed_end < current {
+                        continue;
+                    }
+                    if removed_start > end {
+                        break;
+                    }
+                    if *removed_start > current {
+                        result.add_interval(uuid, current, removed_start - 1);
+                    }
+                    next = removed_end.checked_add(1);
+                }
+
+                if let Some(current) = next.filter(|v| v <= end) {
+                    result.add_interval(uuid, current, *end);
+                }
+            }
+        }
+
+        result
+    }
+
+    pub fn is_subset(&self, other: &MysqlGtidSet) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    /// 按顺序处理事件时调用：GTID事件加入它的GTID，previous gtids合并其中的集合
+    pub fn observe(&mut self, body: &dyn EventBody) {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            // anonymous gtid的gno为0
+            if gtid_event.gno > 0 {
+                self.add(&gtid_event.uuid, gtid_event.gno as u64);
+            }
+        } else if let Some(previous_gtids) = body.downcast_ref::<EventBodyTypeCode35>() {
+            if let Ok(gtid_set) = previous_gtids.gtid_set.parse::<MysqlGtidSet>() {
+                self.merge(&gtid_set);
+            }
+        }
+    }
+
+    fn add_interval(&mut self, uuid: &str, start: u64, end: u64) {
+        let intervals = self.intervals.entry(uuid.to_lowercase()).or_default();
+        intervals.push((start, end));
+        intervals.sort_unstable();
+
+        // 重叠和相邻的区间合并成一个
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+        for (start, end) in intervals.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+}
+
+impl From<&EventBodyTypeCode35> for MysqlGtidSet {
+    fn from(previous_gtids: &EventBodyTypeCode35) -> MysqlGtidSet {
+        previous_gtids.gtid_set.parse().unwrap_or_default()
+    }
+}
+
+/// `uuid:1-5:7,uuid2:1-3`，按uuid排序，只有一个GTID的区间写成一个数
+impl std::fmt::Display for MysqlGtidSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sids: Vec<String> = self
+            .intervals
+            .iter()
+            .map(|(uuid, intervals)| {
+                let mut sid = uuid.clone();
+                for (start, end) in intervals {
+                    match start == end {
+                        true => sid.push_str(&format!(":{}", start)),
+                        false => sid.push_str(&format!(":{}-{}", start, end)),
+                    }
+                }
+                sid
+            })
+            .collect();
+
+        write!(f, "{}", sids.join(","))
+    }
+}
+
+/// 和`gtid_executed`一样的格式，允许换行和空格
+impl FromStr for MysqlGtidSet {
+    type Err = BoxedError;
+
+    fn from_str(value: &str) -> Result<MysqlGtidSet, BoxedError> {
+        let mut gtid_set = MysqlGtidSet::new();
+
+        for item in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let invalid = || {
+                MyError(format!(
+                    "invalid GTID set `{}`, use uuid:interval like 3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5",
+                    item
+                ))
+            };
+
+            let mut parts = item.split(':');
+            let uuid = parts.next().filter(|v| v.len() == 36).ok_or_else(invalid)?;
+
+            let mut intervals = 0;
+            for interval in parts {
+                let (start, end) = match interval.split_once('-') {
+                    Some((start, end)) => (start.trim().parse(), end.trim().parse()),
+                    None => (interval.trim().parse(), interval.trim().parse()),
+                };
+                let (Ok(start), Ok(end)) = (start, end) else {
+                    return Err(Box::new(invalid()));
+                };
+                if start == 0 || start > end {
+                    return Err(Box::new(invalid()));
+                }
+
+                gtid_set.add_interval(uuid, start, end);
+                intervals += 1;
+            }
+
+            if intervals == 0 {
+                return Err(Box::new(invalid()));
+            }
+        }
+
+        Ok(gtid_set)
+    }
+}
+
+impl Serialize for MysqlGtidSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn gtid(domain: u32, server_id: u32, sequence: u64) -> GTID {
+        GTID {
+            replication_domain_id: domain,
+            server_id,
+            gtid_sequence: sequence,
+        }
+    }
+
+    fn mysql(value: &str) -> MysqlGtidSet {
+        value.parse().unwrap()
+    }
+
+    const UUID_A: &str = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+    const UUID_B: &str = "8bc4a5d0-0000-11ee-8000-000000000001";
+
+    // 固定种子的xorshift，每次运行生成同样的随机集合
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+    }
+
+    #[test]
+    fn gtid_set_round_trip() {
+        for value in [
+            "",
+            "0-1-100",
+            "0-1-100,1-2-50",
+            "0-1-0,4294967295-4294967295-18446744073709551615",
+        ] {
+            assert_eq!(value.parse::<GtidSet>().unwrap().to_string(), value);
+        }
+
+        // 按domain排序，忽略空格和空的项
+        let gtid_set = " 1-2-50 , 0-1-100,".parse::<GtidSet>().unwrap();
+        assert_eq!(gtid_set.to_string(), "0-1-100,1-2-50");
+        assert_eq!(gtid_set.len(), 2);
+        assert_eq!(gtid_set.get(1), Some(gtid(1, 2, 50)));
+        assert_eq!(serde_json::to_value(&gtid_set).unwrap(), "0-1-100,1-2-50");
+
+        assert!("0-1-100,0-2-200".parse::<GtidSet>().is_err());
+        assert!("0-1".parse::<GtidSet>().is_err());
+        assert!("a-b-c".parse::<GtidSet>().is_err());
+    }
+
+    #[test]
+    fn gtid_set_contains_and_advance() {
+        let mut gtid_set = "0-1-100,1-2-50".parse::<GtidSet>().unwrap();
+        assert!(gtid_set.contains(&gtid(0, 9, 100)));
+        assert!(gtid_set.contains(&gtid(0, 1, 1)));
+        assert!(!gtid_set.contains(&gtid(0, 1, 101)));
+        assert!(!gtid_set.contains(&gtid(2, 1, 1)));
+
+        // 不会后退，sequence相同时保留原来的server_id
+        gtid_set.advance(&gtid(0, 3, 90));
+        gtid_set.advance(&gtid(1, 3, 50));
+        assert_eq!(gtid_set.to_string(), "0-1-100,1-2-50");
+        gtid_set.advance(&gtid(0, 3, 101));
+        gtid_set.advance(&gtid(5, 1, 1));
+        assert_eq!(gtid_set.to_string(), "0-3-101,1-2-50,5-1-1");
+    }
+
+    #[test]
+    fn gtid_set_merge_and_difference() {
+        let a = "0-1-100,1-2-50,3-1-7".parse::<GtidSet>().unwrap();
+        let b = "0-2-120,1-2-40,2-1-5".parse::<GtidSet>().unwrap();
+
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert_eq!(merged.to_string(), "0-2-120,1-2-50,2-1-5,3-1-7");
+        assert_eq!(a.difference(&b).to_string(), "1-2-50,3-1-7");
+        assert_eq!(b.difference(&a).to_string(), "0-2-120,2-1-5");
+        assert!(a.is_subset(&merged) && b.is_subset(&merged));
+        assert!(!a.is_subset(&b));
+        assert!(GtidSet::new().is_subset(&a));
+        assert!(a.difference(&a).is_empty());
+    }
+
+    // 随机的GtidSet和按domain记录最大sequence的模型比较
+    #[test]
+    fn gtid_set_properties() {
+        let mut random = Random(0x2545f4914f6cdd1d);
+        let random_set = |random: &mut Random| {
+            (0..random.next(5))
+                .map(|_| {
+                    gtid(
+                        random.next(4) as u32,
+                        random.next(3) as u32,
+                        random.next(20),
+                    )
+                })
+                .collect::<Vec<GTID>>()
+        };
+
+        for _ in 0..500 {
+            let (a, b) = (random_set(&mut random), random_set(&mut random));
+            let set_a = a.iter().collect::<GtidSet>();
+            let set_b = b.iter().collect::<GtidSet>();
+
+            let mut merged = set_a.clone();
+            merged.merge(&set_b);
+            let mut swapped = set_b.clone();
+            swapped.merge(&set_a);
+
+            // 合并和顺序无关（server_id除外）
+            let sequences = |v: &GtidSet| {
+                v.gtids()
+                    .iter()
+                    .map(|v| (v.replication_domain_id, v.gtid_sequence))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(sequences(&merged), sequences(&swapped));
+            assert_eq!(merged.to_string().parse::<GtidSet>().unwrap(), merged);
+
+            for domain in 0..4 {
+                for sequence in 0..21 {
+                    let value = gtid(domain, 0, sequence);
+                    let max = |gtids: &[GTID]| {
+                        gtids
+                            .iter()
+                            .filter(|v| v.replication_domain_id == domain)
+                            .map(|v| v.gtid_sequence)
+                            .max()
+                    };
+                    let in_a = max(&a).is_some_and(|v| sequence <= v);
+                    let in_b = max(&b).is_some_and(|v| sequence <= v);
+
+                    assert_eq!(set_a.contains(&value), in_a);
+                    assert_eq!(merged.contains(&value), in_a || in_b);
+                }
+            }
+
+            assert!(set_a.is_subset(&merged) && set_b.is_subset(&merged));
+            // 从b继续时加上difference就是合并的结果
+            let mut resumed = set_b.clone();
+            resumed.merge(&set_a.difference(&set_b));
+            assert_eq!(sequences(&resumed), sequences(&merged));
+        }
+    }
+
+    #[test]
+    fn mysql_gtid_set_round_trip() {
+        for value in [
+            "",
+            "3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5",
+            "3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5:7:9-10,8bc4a5d0-0000-11ee-8000-000000000001:3",
+        ] {
+            assert_eq!(mysql(value).to_string(), value);
+        }
+
+        // 大写的uuid、换行、重叠和相邻的区间
+        let gtid_set = mysql(
+            "8BC4A5D0-0000-11EE-8000-000000000001:3-4,\n3e11fa47-71ca-11e1-9e33-c80aa9429562:6-9:1-5:8-12:14",
+        );
+        assert_eq!(
+            gtid_set.to_string(),
+            format!("{}:1-12:14,{}:3-4", UUID_A, UUID_B)
+        );
+        // 同一个uuid出现多次
+        assert_eq!(
+            mysql(&format!("{}:1-2,{}:3", UUID_A, UUID_A)).to_string(),
+            format!("{}:1-3", UUID_A)
+        );
+
+        for invalid in [
+            "abc:1",
+            "3e11fa47-71ca-11e1-9e33-c80aa9429562",
+            "3e11fa47-71ca-11e1-9e33-c80aa9429562:0",
+            "3e11fa47-71ca-11e1-9e33-c80aa9429562:5-3",
+            "3e11fa47-71ca-11e1-9e33-c80aa9429562:a-3",
+        ] {
+            assert!(invalid.parse::<MysqlGtidSet>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn mysql_gtid_set_operations() {
+        let a = mysql(&format!("{}:1-10:20-30,{}:5", UUID_A, UUID_B));
+        let b = mysql(&format!("{}:3-4:8-21:25", UUID_A));
+
+        assert!(a.contains(UUID_A, 10) && a.contains(UUID_A, 20));
+        assert!(!a.contains(UUID_A, 11) && !a.contains(UUID_A, 0));
+        assert!(a.contains(&UUID_B.to_uppercase(), 5));
+
+        assert_eq!(
+            a.difference(&b).to_string(),
+            format!("{}:1-2:5-7:22-24:26-30,{}:5", UUID_A, UUID_B)
+        );
+        assert_eq!(b.difference(&a).to_string(), format!("{}:11-19", UUID_A));
+
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert_eq!(merged.to_string(), format!("{}:1-30,{}:5", UUID_A, UUID_B));
+
+        let mut added = MysqlGtidSet::new();
+        for gno in [3, 1, 2, 5] {
+            added.add(UUID_A, gno);
+        }
+        assert_eq!(added.to_string(), format!("{}:1-3:5", UUID_A));
+        assert!(added.is_subset(&merged) && !merged.is_subset(&added));
+        assert!(a.difference(&a).is_empty());
+
+        // 区间的边界是u64的最大值时也不会溢出
+        let max = mysql(&format!("{}:1-18446744073709551615", UUID_A));
+        assert!(max.contains(UUID_A, u64::MAX));
+        assert!(max.difference(&a).contains(UUID_A, u64::MAX));
+        assert_eq!(a.difference(&max).to_string(), format!("{}:5", UUID_B));
+    }
+
+    // 随机的MysqlGtidSet和GTID的集合比较
+    #[test]
+    fn mysql_gtid_set_properties() {
+        let mut random = Random(0x9e3779b97f4a7c15);
+        let random_set = |random: &mut Random| {
+            let mut gtids = BTreeSet::new();
+            let mut gtid_set = MysqlGtidSet::new();
+            for _ in 0..random.next(4) {
+                let uuid = [UUID_A, UUID_B][random.next(2) as usize];
+                let start = random.next(30) + 1;
+                let end = start + random.next(5);
+                gtid_set.add_interval(uuid, start, end);
+                gtids.extend((start..=end).map(|v| (uuid, v)));
+            }
+            (gtid_set, gtids)
+        };
+
+        for _ in 0..500 {
+            let (set_a, a) = random_set(&mut random);
+            let (set_b, b) = random_set(&mut random);
+
+            assert_eq!(set_a.to_string().parse::<MysqlGtidSet>().unwrap(), set_a);
+
+            let mut merged = set_a.clone();
+            merged.merge(&set_b);
+            let difference = set_a.difference(&set_b);
+
+            for uuid in [UUID_A, UUID_B] {
+                for gno in 0..40 {
+                    let in_a = a.contains(&(uuid, gno));
+                    let in_b = b.contains(&(uuid, gno));
+
+                    assert_eq!(set_a.contains(uuid, gno), in_a);
+                    assert_eq!(merged.contains(uuid, gno), in_a || in_b);
+                    assert_eq!(difference.contains(uuid, gno), in_a && !in_b);
+                }
+            }
+
+            assert_eq!(set_a.is_subset(&set_b), a.is_subset(&b));
+            // 区间是规范的，相同的集合有相同的字符串
+            let mut swapped = set_b.clone();
+            swapped.merge(&set_a);
+            assert_eq!(swapped, merged);
+        }
+    }
+}
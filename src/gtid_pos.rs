@@ -0,0 +1,271 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Serialize;
+
+use crate::error::ParseError;
+use crate::find_gtid::list_binlog_files;
+use crate::gtid::GtidSet;
+use crate::model::*;
+use crate::reader::BinlogReader;
+use crate::service::{get_event_body, get_event_header, update_checksum_length, CHECKSUM_LENGTH};
+use crate::util::check_file_magic_number;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// `gtid-pos`的结果，和服务端`BINLOG_GTID_POS()`在文件末尾的结果相同
+#[derive(Debug, Serialize)]
+pub struct GtidPosition {
+    /// 第一个文件开头的GTID_LIST，没有时为空
+    pub start: GtidSet,
+    /// 最后一个文件结束时的位置，`gtid_slave_pos`的格式
+    pub end: GtidSet,
+    /// 开头的GTID_LIST中没有、在文件中间才出现的domain
+    pub new_domains: Vec<u32>,
+    pub files: Vec<String>,
+}
+
+impl Display for GtidPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "start: {}", self.start)?;
+        writeln!(f, "end: {}", self.end)?;
+        match self.new_domains.is_empty() {
+            true => write!(f, "new domains: -"),
+            false => write!(
+                f,
+                "new domains: {}",
+                self.new_domains
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+/// 按顺序扫描文件，从第一个文件的GTID_LIST开始，依次加上每个GTID事件和之后文件的GTID_LIST
+///
+/// 每个path可以是目录、索引文件或者binlog文件。只解码GTID和GTID_LIST事件，其他事件按事件头跳过；
+/// 还在写的文件以不完整的事件结束，到这里为止
+pub fn gtid_pos(paths: &[String]) -> Result<GtidPosition, BoxedError> {
+    let mut files = Vec::new();
+    for path in paths {
+        files.extend(list_binlog_files(path)?);
+    }
+
+    let mut start = None;
+    let mut end = GtidSet::new();
+    for file_path in &files {
+        let gtid_list = scan_file(file_path, &mut end)?;
+        start.get_or_insert(gtid_list.unwrap_or_default());
+    }
+    let start = start.unwrap_or_default();
+
+    let new_domains = end
+        .gtids()
+        .iter()
+        .map(|v| v.replication_domain_id)
+        .filter(|v| start.get(*v).is_none())
+        .collect();
+
+    Ok(GtidPosition {
+        start,
+        end,
+        new_domains,
+        files,
+    })
+}
+
+// 把文件中的GTID加到gtid_set，返回文件开头（第一个GTID事件之前）的GTID_LIST
+fn scan_file(file_path: &str, gtid_set: &mut GtidSet) -> Result<Option<GtidSet>, BoxedError> {
+    let mut file = BinlogReader::open(file_path)?;
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(file_path)));
+    }
+
+    let file_length = file.file_length()?;
+    let mut table_structs = HashMap::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+    let mut gtid_list = None;
+    let mut seen_gtid = false;
+
+    let mut offset = 4;
+    while offset + EVENT_HEADER_LENGTH <= file_length {
+        let header = get_event_header(&mut file, offset)?;
+        let next_offset = header.next_event_position as u64;
+        if next_offset <= offset || next_offset > file_length {
+            break;
+        }
+
+        if let 15 | 162 | 163 = header.type_code {
+            let body = get_event_body(
+                &mut file,
+                offset,
+                &header,
+                &mut table_structs,
+                checksum_length,
+            )?;
+            update_checksum_length(&mut checksum_length, body.as_ref());
+            match body.downcast_ref::<EventBodyTypeCode163>() {
+                Some(event) if !seen_gtid && gtid_list.is_none() => {
+                    gtid_list = Some(GtidSet::from(event));
+                }
+                _ => seen_gtid |= header.type_code == 162,
+            }
+            gtid_set.observe(&header, body.as_ref());
+        }
+
+        offset = next_offset;
+    }
+
+    Ok(gtid_list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use std::path::PathBuf;
+
+    fn gtid(domain_id: u32, server_id: u32, sequence: u64) -> EventBuilder {
+        EventBuilder::from_body(&GtidEventBuilder::new(sequence).domain_id(domain_id))
+            .server_id(server_id)
+    }
+
+    fn gtid_list(gtids: &[(u32, u32, u64)]) -> EventBuilder {
+        let mut body = (gtids.len() as u32).to_le_bytes().to_vec();
+        for (domain_id, server_id, sequence) in gtids {
+            body.extend(domain_id.to_le_bytes());
+            body.extend(server_id.to_le_bytes());
+            body.extend(sequence.to_le_bytes());
+        }
+        EventBuilder::new(163, body)
+    }
+
+    // 每个事务是GTID、BEGIN、行事件和XID，行事件的table map不在文件中，解码时会失败，
+    // 所以只有按事件头跳过才能处理
+    fn binlog_file(start: &[(u32, u32, u64)], gtids: &[(u32, u32, u64)]) -> BinlogBuilder {
+        let orders = TableMapBuilder::new("shop", "orders")
+            .table_id(99)
+            .column(FieldType::Long, &[]);
+        let mut builder = BinlogBuilder::new().push(gtid_list(start));
+        for (domain_id, server_id, sequence) in gtids {
+            builder = builder
+                .push(gtid(*domain_id, *server_id, *sequence))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&RowsEventBuilder::insert(&orders).row(vec![ColumnValue::Int(1)]))
+                .push(EventBuilder::new(16, sequence.to_le_bytes().to_vec()));
+        }
+        builder
+    }
+
+    // 服务端切换文件时把当时的BINLOG_GTID_POS写到新文件的GTID_LIST中，
+    // 所以每个文件结束的位置就是下一个文件的GTID_LIST
+    const GTID_LISTS: [&[(u32, u32, u64)]; 4] = [
+        &[(0, 1, 10)],
+        &[(0, 1, 12)],
+        &[(0, 1, 14), (1, 2, 5)],
+        &[(0, 3, 15), (1, 2, 6)],
+    ];
+
+    fn chained_files(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gtid-pos-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let files = [
+            binlog_file(GTID_LISTS[0], &[(0, 1, 11), (0, 1, 12)]),
+            // domain 1在文件中间才出现
+            binlog_file(GTID_LISTS[1], &[(0, 1, 13), (1, 2, 5), (0, 1, 14)]),
+            // 切换主库后server_id变了
+            binlog_file(GTID_LISTS[2], &[(1, 2, 6), (0, 3, 15)]),
+        ];
+        for (i, builder) in files.into_iter().enumerate() {
+            std::fs::write(
+                dir.join(format!("mysql-bin.00000{}", i + 1)),
+                builder.build(),
+            )
+            .unwrap();
+        }
+
+        dir
+    }
+
+    fn path(dir: &std::path::Path, number: usize) -> String {
+        dir.join(format!("mysql-bin.00000{}", number))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn gtid_set(gtids: &[(u32, u32, u64)]) -> GtidSet {
+        let mut gtid_set = GtidSet::new();
+        for (domain_id, server_id, sequence) in gtids {
+            gtid_set.advance(&GTID {
+                replication_domain_id: *domain_id,
+                server_id: *server_id,
+                gtid_sequence: *sequence,
+            });
+        }
+        gtid_set
+    }
+
+    #[test]
+    fn end_of_each_file_is_the_next_gtid_list() {
+        let dir = chained_files("each");
+
+        for number in 1..=3 {
+            let position = gtid_pos(&[path(&dir, number)]).unwrap();
+            assert_eq!(position.start, gtid_set(GTID_LISTS[number - 1]));
+            assert_eq!(position.end, gtid_set(GTID_LISTS[number]));
+        }
+
+        let position = gtid_pos(&[dir.to_string_lossy().to_string()]).unwrap();
+        assert_eq!(position.files.len(), 3);
+        assert_eq!(position.start.to_string(), "0-1-10");
+        assert_eq!(position.end.to_string(), "0-3-15,1-2-6");
+        assert_eq!(position.new_domains, [1]);
+        assert_eq!(
+            position.to_string(),
+            "start: 0-1-10\nend: 0-3-15,1-2-6\nnew domains: 1"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_list_and_new_domains() {
+        let dir = chained_files("list");
+
+        let position = gtid_pos(&[path(&dir, 2), path(&dir, 3)]).unwrap();
+        assert_eq!(position.start.to_string(), "0-1-12");
+        assert_eq!(position.end.to_string(), "0-3-15,1-2-6");
+        assert_eq!(position.new_domains, [1]);
+
+        // domain 1已经在开头的GTID_LIST中
+        let position = gtid_pos(&[path(&dir, 3)]).unwrap();
+        assert!(position.new_domains.is_empty());
+        assert!(position.to_string().ends_with("new domains: -"));
+
+        let json = serde_json::to_value(&position).unwrap();
+        assert_eq!(json["end"], "0-3-15,1-2-6");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_being_written() {
+        // 最后一个事件不完整，到这里为止
+        let dir = chained_files("partial");
+        let mut binlog = binlog_file(GTID_LISTS[3], &[(0, 3, 16)]).build();
+        binlog.extend(&gtid(0, 3, 17).build(binlog.len() as u32)[..20]);
+        std::fs::write(dir.join("mysql-bin.000004"), binlog).unwrap();
+
+        let position = gtid_pos(&[path(&dir, 4)]).unwrap();
+        assert_eq!(position.end.to_string(), "0-3-16,1-2-6");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt::Write;
+
+const EVENT_HEADER_LENGTH: usize = 19;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// 事件中一个字段占用的字节范围，start和end是相对事件起始位置的偏移
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldRange {
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+}
+
+impl FieldRange {
+    pub fn new(start: usize, end: usize, name: &str) -> FieldRange {
+        FieldRange {
+            start,
+            end,
+            name: name.to_string(),
+        }
+    }
+}
+
+/// 事件的基本结构：header的各个字段、body以及最后checksum_length个字节的CRC32（binlog_checksum=NONE时没有）
+/// 对于body开头是固定长度字段的事件，再把这些字段单独标出来
+pub fn event_layout(raw_event: &[u8], checksum_length: usize) -> Vec<FieldRange> {
+    let mut ranges = vec![
+        FieldRange::new(0, 4, "timestamp"),
+        FieldRange::new(4, 5, "type_code"),
+        FieldRange::new(5, 9, "server_id"),
+        FieldRange::new(9, 13, "event_length"),
+        FieldRange::new(13, 17, "next_event_position"),
+        FieldRange::new(17, 19, "flags"),
+    ];
+
+    if raw_event.len() >= EVENT_HEADER_LENGTH + checksum_length {
+        let body_end = raw_event.len() - checksum_length;
+        ranges.push(FieldRange::new(EVENT_HEADER_LENGTH, body_end, "body"));
+
+        let mut offset = EVENT_HEADER_LENGTH;
+        for (name, length) in body_fixed_fields(raw_event[4]) {
+            if offset + length > body_end {
+                break;
+            }
+            ranges.push(FieldRange::new(offset, offset + length, name));
+            offset += length;
+        }
+
+        if checksum_length > 0 {
+            ranges.push(FieldRange::new(body_end, raw_event.len(), "crc32"));
+        }
+    }
+
+    ranges
+}
+
+/// `--trace`时的结构：header和CRC32同event_layout，body中是解码器实际读取的字段（见trace模块），
+/// 没有记录字段的事件（还没有加上记录的解码器）和event_layout一样
+pub fn traced_layout(
+    raw_event: &[u8],
+    checksum_length: usize,
+    traced: &[FieldRange],
+) -> Vec<FieldRange> {
+    let layout = event_layout(raw_event, checksum_length);
+    if traced.is_empty() {
+        return layout;
+    }
+
+    let (header, rest): (Vec<FieldRange>, Vec<FieldRange>) = layout
+        .into_iter()
+        .partition(|v| v.end <= EVENT_HEADER_LENGTH);
+
+    header
+        .into_iter()
+        .chain(traced.iter().cloned())
+        .chain(rest.into_iter().filter(|v| v.name == "crc32"))
+        .collect()
+}
+
+/// body开头的固定长度字段，(字段名, 字节数)
+fn body_fixed_fields(type_code: u8) -> Vec<(&'static str, usize)> {
+    match type_code {
+        2 => vec![
+            ("id_of_thread", 4),
+            ("execute_time", 4),
+            ("length_of_database_name", 1),
+            ("error_code", 2),
+            ("length_of_status_variable_block", 2),
+        ],
+        4 => vec![("position_of_the_first_event_in_next_log_file", 8)],
+        5 => vec![("data_type", 1), ("value", 8)],
+        13 => vec![("first_seed", 8), ("second_seed", 8)],
+        15 => vec![
+            ("binlog_version", 2),
+            ("server_version", 50),
+            ("create_timestamp", 4),
+            ("header_length", 1),
+        ],
+        16 => vec![("xid_transaction_number", 8)],
+        19 => vec![("table_id", 6), ("reserved_for_future_use", 2)],
+        23..=25 => vec![("table_id", 6), ("flags", 2)],
+        30..=32 => vec![("table_id", 6), ("flags", 2), ("extra_data_length", 2)],
+        161 => vec![("log_filename_length", 4)],
+        162 => vec![
+            ("gtid_sequence", 8),
+            ("replication_domain_id", 4),
+            ("flags", 1),
+        ],
+        163 => vec![("number_of_gtids", 4)],
+        164 => vec![
+            ("encryption_scheme", 1),
+            ("encryption_key_version", 4),
+            ("nonce", 12),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// 输出和`xxd`类似的offset/hex/ASCII格式，左边一列是在文件中的绝对位置
+/// 最右边一列标出这一行里开始的字段及其在事件中的字节范围
+pub fn hexdump(position: u64, raw_event: &[u8], ranges: &[FieldRange]) -> String {
+    let mut result = String::new();
+
+    for (line_index, line) in raw_event.chunks(BYTES_PER_LINE).enumerate() {
+        let line_start = line_index * BYTES_PER_LINE;
+        let line_end = line_start + line.len();
+
+        let mut hex = String::new();
+        for (index, byte) in line.iter().enumerate() {
+            if index == BYTES_PER_LINE / 2 {
+                hex.push(' ');
+            }
+            write!(hex, "{:02x} ", byte).unwrap();
+        }
+
+        let ascii = line
+            .iter()
+            .map(|v| match v {
+                0x20..=0x7e => *v as char,
+                _ => '.',
+            })
+            .collect::<String>();
+
+        let fields = ranges
+            .iter()
+            .filter(|v| v.start >= line_start && v.start < line_end)
+            .map(|v| format!("{}[{}..{}]", v.name, v.start, v.end))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let line = format!(
+            "{:08x}  {:<50}|{:<16}|  {}",
+            position + line_start as u64,
+            hex,
+            ascii,
+            fields
+        );
+        writeln!(result, "{}", line.trim_end()).unwrap();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 位置256处的query事件：BEGIN，数据库shop
+    const QUERY_EVENT: [u8; 46] = [
+        0xb3, 0xa2, 0xf1, 0x65, 0x02, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x00, 0x00, 0x00, 0x2e, 0x01,
+        0x00, 0x00, 0x08, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00,
+        0x00, 0x00, 0x73, 0x68, 0x6f, 0x70, 0x00, 0x42, 0x45, 0x47, 0x49, 0x4e, 0x30, 0xd7, 0xe0,
+        0x2a,
+    ];
+
+    #[test]
+    fn dump_query_event() {
+        let dump = hexdump(256, &QUERY_EVENT, &event_layout(&QUERY_EVENT, 4));
+        assert_eq!(
+            dump,
+            concat!(
+                "00000100  b3 a2 f1 65 02 01 00 00  00 2e 00 00 00 2e 01 00  |...e............|  ",
+                "timestamp[0..4] type_code[4..5] server_id[5..9] event_length[9..13] next_event_position[13..17]\n",
+                "00000110  00 08 00 07 00 00 00 00  00 00 00 04 00 00 00 00  |................|  ",
+                "flags[17..19] body[19..42] id_of_thread[19..23] execute_time[23..27] length_of_database_name[27..28] ",
+                "error_code[28..30] length_of_status_variable_block[30..32]\n",
+                "00000120  73 68 6f 70 00 42 45 47  49 4e 30 d7 e0 2a        |shop.BEGIN0..*  |  crc32[42..46]\n",
+            )
+        );
+    }
+
+    // 没有字段标注时只有hex和ASCII两列
+    #[test]
+    fn dump_without_ranges() {
+        assert_eq!(
+            hexdump(0x1000, &QUERY_EVENT[32..42], &[]),
+            "00001000  73 68 6f 70 00 42 45 47  49 4e                    |shop.BEGIN      |\n"
+        );
+    }
+
+    #[test]
+    fn traced_fields_replace_body() {
+        let traced = vec![
+            FieldRange::new(19, 23, "thread_id"),
+            FieldRange::new(37, 42, "sql"),
+        ];
+        let names = traced_layout(&QUERY_EVENT, 4, &traced)
+            .into_iter()
+            .map(|v| v.name)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            [
+                "timestamp",
+                "type_code",
+                "server_id",
+                "event_length",
+                "next_event_position",
+                "flags",
+                "thread_id",
+                "sql",
+                "crc32"
+            ]
+        );
+
+        assert_eq!(
+            traced_layout(&QUERY_EVENT, 4, &[]),
+            event_layout(&QUERY_EVENT, 4)
+        );
+    }
+
+    // v2的行事件在flags之后还有extra data的长度
+    #[test]
+    fn rows_v2_fixed_fields() {
+        let mut event = vec![0; 19 + 10 + 4];
+        event[4] = 30;
+        let ranges = event_layout(&event, 4);
+        assert_eq!(
+            ranges[ranges.len() - 2],
+            FieldRange::new(27, 29, "extra_data_length")
+        );
+    }
+
+    // binlog_checksum=NONE时body一直到事件的结尾
+    #[test]
+    fn layout_without_checksum() {
+        let event = &QUERY_EVENT[..42];
+        let ranges = event_layout(event, 0);
+
+        assert!(ranges.contains(&FieldRange::new(19, 42, "body")));
+        assert!(ranges.iter().all(|v| v.name != "crc32"));
+    }
+}
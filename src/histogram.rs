@@ -0,0 +1,340 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::cli::HistogramBy;
+use crate::model::*;
+use crate::schema_drift::SchemaTracker;
+use crate::util::{format_timestamp, get_event_type_name};
+
+// 柱状图最长的长度
+const BAR_WIDTH: u64 = 40;
+
+// 按表分组时不属于任何表的事件（GTID、XID、query等）
+const NO_TABLE: &str = "(no table)";
+
+/// 一个时间区间内的事件数、行数和字节数
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HistogramBucket {
+    /// 区间开始的时间戳，时间戳为0的事件（rotate这类人为生成的事件）为None
+    pub start: Option<u32>,
+    /// `--histogram-by`的分组，没有分组时为None
+    pub group: Option<String>,
+    pub events: u64,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+/// `--histogram`，按事件头中的时间戳把事件放进固定宽度的区间
+pub struct Histogram {
+    width: u32,
+    by: HistogramBy,
+    // (分组, 区间开始的时间戳) -> 区间
+    buckets: BTreeMap<(String, Option<u32>), HistogramBucket>,
+    // 按表分组时表结构变化之后的事件分到`库名.表名#2`这样的组中
+    schema_tracker: SchemaTracker,
+}
+
+impl Histogram {
+    /// width是区间的秒数
+    pub fn new(width: u32, by: HistogramBy) -> Histogram {
+        Histogram {
+            width: width.max(1),
+            by,
+            buckets: BTreeMap::new(),
+            schema_tracker: SchemaTracker::new(),
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        let group = match self.by {
+            HistogramBy::Total => None,
+            HistogramBy::EventType => Some(get_event_type_name(header.type_code).to_string()),
+            HistogramBy::Table => {
+                self.schema_tracker.process(0, body);
+                Some(event_table(body, table_structs, &self.schema_tracker))
+            }
+        };
+
+        let start = match header.timestamp {
+            0 => None,
+            timestamp => Some(timestamp - timestamp % self.width),
+        };
+
+        let bucket = self
+            .buckets
+            .entry((group.clone().unwrap_or_default(), start))
+            .or_insert_with(|| HistogramBucket {
+                start,
+                group,
+                ..Default::default()
+            });
+
+        bucket.events += 1;
+        bucket.bytes += header.event_length as u64;
+        if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+            bucket.rows += rows_event.number_of_rows;
+        }
+    }
+
+    /// `--jobs`时合并另一个文件的区间，两个文件中同一个区间的事件相加
+    pub fn merge(&mut self, other: Histogram) {
+        for (key, bucket) in other.buckets {
+            match self.buckets.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(bucket);
+                }
+                Entry::Occupied(mut entry) => {
+                    let total = entry.get_mut();
+                    total.events += bucket.events;
+                    total.rows += bucket.rows;
+                    total.bytes += bucket.bytes;
+                }
+            }
+        }
+    }
+
+    /// 按分组和时间排序，只有有事件的区间
+    pub fn buckets(&self) -> Vec<&HistogramBucket> {
+        self.buckets.values().collect()
+    }
+}
+
+/// 每个分组一段，同一分组中没有事件的区间也会输出，柱的长度按所有区间中最多的事件数缩放
+impl Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_events = self.buckets.values().map(|v| v.events).max().unwrap_or(0);
+
+        let mut groups: BTreeMap<&str, Vec<&HistogramBucket>> = BTreeMap::new();
+        for ((group, _), bucket) in &self.buckets {
+            groups.entry(group.as_str()).or_default().push(bucket);
+        }
+
+        for (group, buckets) in groups {
+            if self.by != HistogramBy::Total {
+                writeln!(f, "== {} ==", group)?;
+            }
+
+            let mut next_start = None;
+            for bucket in buckets {
+                // 补上两个有事件的区间之间的空区间
+                if let (Some(next), Some(start)) = (next_start, bucket.start) {
+                    for empty_start in (next..start).step_by(self.width as usize) {
+                        write_line(
+                            f,
+                            Some(empty_start),
+                            &HistogramBucket::default(),
+                            max_events,
+                        )?;
+                    }
+                }
+
+                write_line(f, bucket.start, bucket, max_events)?;
+                next_start = bucket.start.map(|v| v.saturating_add(self.width));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_line(
+    f: &mut std::fmt::Formatter<'_>,
+    start: Option<u32>,
+    bucket: &HistogramBucket,
+    max_events: u64,
+) -> std::fmt::Result {
+    let time = match start {
+        Some(start) => format_timestamp(start),
+        None => "artificial".to_string(),
+    };
+
+    // 有事件时至少有一个#
+    let bar_length = match (bucket.events, max_events) {
+        (0, _) | (_, 0) => 0,
+        (events, max_events) => (events * BAR_WIDTH / max_events).max(1),
+    };
+
+    writeln!(
+        f,
+        "{:<19}  events {:>8}  rows {:>10}  bytes {:>12}  {}",
+        time,
+        bucket.events,
+        bucket.rows,
+        bucket.bytes,
+        "#".repeat(bar_length as usize)
+    )
+}
+
+/// table map和行事件所属的表，表结构变化过时带上版本
+fn event_table(
+    body: &dyn EventBody,
+    table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    schema_tracker: &SchemaTracker,
+) -> String {
+    let table_map = match (
+        body.downcast_ref::<EventBodyTypeCode19>(),
+        body.downcast_ref::<EventBodyTypeCode23To25>(),
+    ) {
+        (Some(table_map), _) => Some(table_map),
+        (None, Some(rows_event)) => table_structs.get(&rows_event.table_id).map(|v| v.as_ref()),
+        (None, None) => return NO_TABLE.to_string(),
+    };
+
+    match table_map {
+        Some(v) => match schema_tracker.version(&v.database_name, &v.table_name) {
+            1 => format!("{}.{}", v.database_name, v.table_name),
+            version => format!("{}.{}#{}", v.database_name, v.table_name, version),
+        },
+        None => NO_TABLE.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    // 1699999980是60的整数倍
+    const BASE: u32 = 1699999980;
+
+    // 第一个区间一个事务两行，第二个区间没有事件，第三个区间一个事务一行，FDE的时间戳为0
+    fn fixture() -> Vec<u8> {
+        let table_map = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+        let transaction = |binlog: BinlogBuilder, sequence: u64, timestamp: u32, rows: i32| {
+            let mut insert = RowsEventBuilder::insert(&table_map);
+            for id in 0..rows {
+                insert = insert.row(vec![ColumnValue::Int(id as i64)]);
+            }
+            binlog
+                .push(gtid(sequence).timestamp(timestamp))
+                .push(EventBuilder::from_body(&table_map).timestamp(timestamp))
+                .push(EventBuilder::from_body(&insert).timestamp(timestamp + 1))
+                .push(
+                    EventBuilder::new(16, sequence.to_le_bytes().to_vec()).timestamp(timestamp + 2),
+                )
+        };
+
+        let binlog = transaction(BinlogBuilder::new(), 1, BASE + 1, 2);
+        transaction(binlog, 2, BASE + 125, 1).build()
+    }
+
+    // 从第start个事件开始，处理end之前的事件
+    fn histogram(binlog: &[u8], by: HistogramBy, start: usize, end: usize) -> Histogram {
+        let mut histogram = Histogram::new(60, by);
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        let mut index = 0;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            if (start..end).contains(&index) {
+                histogram.process(&header, body.as_ref(), &table_structs);
+            }
+            position = header.next_event_position as u64;
+            index += 1;
+        }
+        histogram
+    }
+
+    fn counts(histogram: &Histogram) -> Vec<(Option<&str>, Option<u32>, u64, u64)> {
+        histogram
+            .buckets()
+            .into_iter()
+            .map(|v| (v.group.as_deref(), v.start, v.events, v.rows))
+            .collect()
+    }
+
+    #[test]
+    fn total_buckets() {
+        let binlog = fixture();
+        let histogram = histogram(&binlog, HistogramBy::Total, 0, usize::MAX);
+
+        assert_eq!(
+            counts(&histogram),
+            [
+                (None, None, 1, 0),
+                (None, Some(BASE), 4, 2),
+                (None, Some(BASE + 120), 4, 1),
+            ]
+        );
+        let bytes: u64 = histogram.buckets().iter().map(|v| v.bytes).sum();
+        assert_eq!(bytes, binlog.len() as u64 - 4);
+
+        // 没有事件的区间也输出一行
+        let chart = histogram.to_string();
+        let lines = chart.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("artificial"));
+        assert!(lines[1].starts_with("2023-11-14 22:13:00  events        4"));
+        assert!(lines[1].ends_with(&"#".repeat(BAR_WIDTH as usize)));
+        assert!(lines[2].starts_with("2023-11-14 22:14:00  events        0"));
+        assert!(!lines[2].contains('#'));
+        assert!(lines[3].starts_with("2023-11-14 22:15:00  events        4"));
+    }
+
+    #[test]
+    fn buckets_by_table_and_event_type() {
+        let binlog = fixture();
+
+        let by_table = histogram(&binlog, HistogramBy::Table, 0, usize::MAX);
+        assert_eq!(
+            counts(&by_table),
+            [
+                (Some(NO_TABLE), None, 1, 0),
+                (Some(NO_TABLE), Some(BASE), 2, 0),
+                (Some(NO_TABLE), Some(BASE + 120), 2, 0),
+                (Some("shop.orders"), Some(BASE), 2, 2),
+                (Some("shop.orders"), Some(BASE + 120), 2, 1),
+            ]
+        );
+        assert!(by_table.to_string().contains("== shop.orders =="));
+
+        let by_type = histogram(&binlog, HistogramBy::EventType, 0, usize::MAX);
+        let rows_events = counts(&by_type)
+            .into_iter()
+            .filter(|v| v.0 == Some("WRITE_ROWS_EVENT_V1"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows_events,
+            [
+                (Some("WRITE_ROWS_EVENT_V1"), Some(BASE), 1, 2),
+                (Some("WRITE_ROWS_EVENT_V1"), Some(BASE + 120), 1, 1),
+            ]
+        );
+        assert_eq!(by_type.buckets().len(), 9);
+    }
+
+    #[test]
+    fn merged_histograms_match_a_single_pass() {
+        let binlog = fixture();
+        let single = histogram(&binlog, HistogramBy::Table, 0, usize::MAX);
+
+        let mut merged = histogram(&binlog, HistogramBy::Table, 0, 3);
+        merged.merge(histogram(&binlog, HistogramBy::Table, 3, usize::MAX));
+        assert_eq!(counts(&merged), counts(&single));
+        assert_eq!(merged.to_string(), single.to_string());
+    }
+}
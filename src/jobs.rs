@@ -0,0 +1,538 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use serde_json::json;
+
+use crate::analyze::{ImpactAnalyzer, ImpactReport, TransactionImpact};
+use crate::binlog_index::read_binlog_index;
+use crate::checksum::ChecksumVerifier;
+use crate::cli::{CliOptions, OutputFormat};
+use crate::diagnostic::Diagnostic;
+use crate::error::{get_checked_event_header, is_binlog_in_use, ErrorReport, ParseError};
+use crate::filter::EventTypeFilter;
+use crate::grep::GrepFilter;
+use crate::histogram::Histogram;
+use crate::model::*;
+use crate::pipeline::{check_event_size, EventPipeline, EventWriter};
+use crate::reader::BinlogReader;
+use crate::service::*;
+use crate::util::{check_file_magic_number, get_binlog_files_in_dir, get_file_name};
+
+// 每个文件的输出攒到这么多字节再交给主线程
+const OUTPUT_CHUNK_BYTES: usize = 64 * 1024;
+
+// 每个文件最多积压的输出块，主线程还没有输出到这个文件时处理它的线程会在这里等待
+const MAX_PENDING_CHUNKS: usize = 16;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 一个文件处理完之后需要合并的结果
+struct FileResult {
+    impact_report: Option<ImpactReport>,
+    histogram: Option<Histogram>,
+    checksum_verifier: Option<ChecksumVerifier>,
+    parsed_bytes: u64,
+    // 跳过的还不支持的内容，按文件的顺序计入ErrorReport
+    issues: Vec<ParseError>,
+    // 服务端、表结构的变化这些提示，按文件的顺序交给调用方
+    diagnostics: Vec<Diagnostic>,
+}
+
+enum Message {
+    Output(Vec<u8>),
+    // FileResult比较大，放在堆上
+    Finished(Result<Box<FileResult>, ParseError>),
+}
+
+/// 一个文件的输出，满一块就发给主线程，主线程按文件名的顺序输出
+struct FileOutput {
+    sender: SyncSender<Message>,
+    buffer: Vec<u8>,
+}
+
+impl Write for FileOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= OUTPUT_CHUNK_BYTES {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    // 主线程已经因为之前的文件出错退出时发送失败
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.sender
+            .send(Message::Output(std::mem::take(&mut self.buffer)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the output is closed"))
+    }
+}
+
+/// `--jobs N`，path是目录时其中的binlog文件分给N个线程同时处理，也可以是一个文件，`--index`时是索引中的文件
+///
+/// 每个文件从头开始解析，table map等状态不跨文件。事件的输出按文件名（索引中）的顺序，和依次处理这些文件时一样；
+/// `--analyze`、`--histogram`和`--verify-checksums`的汇总按文件的顺序合并之后输出。
+/// 某个文件出错时输出它之前的文件和它已经输出的部分，然后返回这个错误
+///
+/// 事件和汇总写到out中，提示和解析的速度交给diagnostics
+pub fn process_files(
+    path: &str,
+    jobs: usize,
+    options: &CliOptions,
+    out: &mut dyn Write,
+    error_report: &mut ErrorReport,
+    diagnostics: &mut dyn FnMut(Diagnostic),
+) -> Result<(), BoxedError> {
+    let file_paths = match &options.index_file_path {
+        Some(index_file_path) => read_binlog_index(index_file_path)?,
+        None if Path::new(path).is_dir() => get_binlog_files_in_dir(path)?,
+        None => vec![path.to_string()],
+    };
+
+    let started_at = Instant::now();
+
+    // 按文件名的顺序分配，主线程等待的文件总是已经有线程在处理
+    let next_file = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+
+    let mut senders = Vec::new();
+    let mut receivers: Vec<Receiver<Message>> = Vec::new();
+    for _ in &file_paths {
+        let (sender, receiver) = sync_channel(MAX_PENDING_CHUNKS);
+        senders.push(Mutex::new(Some(sender)));
+        receivers.push(receiver);
+    }
+
+    let mut impact_report: Option<ImpactReport> = None;
+    let mut histogram: Option<Histogram> = None;
+    let mut checksum_verifier = options.verify_checksums.then(ChecksumVerifier::new);
+    let mut parsed_bytes = 0;
+
+    thread::scope(|scope| -> Result<(), BoxedError> {
+        for _ in 0..jobs.min(file_paths.len()) {
+            scope.spawn(|| loop {
+                let index = next_file.fetch_add(1, Ordering::Relaxed);
+                let (file_path, sender) = match (file_paths.get(index), senders.get(index)) {
+                    (Some(file_path), Some(sender)) if !stopped.load(Ordering::Relaxed) => {
+                        (file_path, sender.lock().unwrap().take())
+                    }
+                    _ => break,
+                };
+
+                let sender = match sender {
+                    Some(sender) => sender,
+                    None => break,
+                };
+
+                let mut output = FileOutput {
+                    sender: sender.clone(),
+                    buffer: Vec::new(),
+                };
+                let result = process_file(file_path, options, &mut output, &stopped)
+                    .and_then(|result| output.flush().map(|_| result).map_err(|e| e.into()))
+                    .map_err(|e| {
+                        // 同时处理多个文件，位置需要带上文件名
+                        let mut error = ParseError::from_error(e.as_ref());
+                        error.message =
+                            format!("{} in `{}`", error.message, get_file_name(file_path));
+                        error
+                    });
+
+                if sender
+                    .send(Message::Finished(result.map(Box::new)))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+
+        for receiver in receivers {
+            let result = loop {
+                match receiver.recv() {
+                    Ok(Message::Output(buffer)) => out.write_all(&buffer)?,
+                    Ok(Message::Finished(result)) => break result,
+                    Err(_) => {
+                        stopped.store(true, Ordering::Relaxed);
+                        return Err(Box::new(MyError("a worker of `--jobs` exited".to_string())));
+                    }
+                }
+            };
+
+            // 之后的接收端随着返回一起释放，其他线程发送失败后退出
+            let result = match result {
+                Ok(result) => result,
+                Err(error) => {
+                    stopped.store(true, Ordering::Relaxed);
+                    return Err(Box::new(error));
+                }
+            };
+
+            match (impact_report.as_mut(), result.impact_report) {
+                (Some(total), Some(report)) => total.merge(&report),
+                (None, report) => impact_report = report,
+                _ => {}
+            }
+
+            match (histogram.as_mut(), result.histogram) {
+                (Some(total), Some(buckets)) => total.merge(buckets),
+                (None, buckets) => histogram = buckets,
+                _ => {}
+            }
+
+            if let (Some(total), Some(verifier)) =
+                (checksum_verifier.as_mut(), &result.checksum_verifier)
+            {
+                total.merge(verifier);
+            }
+
+            parsed_bytes += result.parsed_bytes;
+
+            for issue in result.issues {
+                error_report.push(issue);
+            }
+
+            for diagnostic in result.diagnostics {
+                diagnostics(diagnostic);
+            }
+        }
+
+        out.flush()?;
+        Ok(())
+    })?;
+
+    // 和顺序处理时一样，汇总在所有文件之后输出
+    if let Some(checksum_verifier) = &checksum_verifier {
+        diagnostics(Diagnostic::Note(checksum_verifier.to_string()));
+    }
+
+    if let Some(impact_report) = &impact_report {
+        match options.output {
+            OutputFormat::Json => writeln!(out, "{}", json!({ "aggregate": impact_report }))?,
+            _ => writeln!(out, "{}", impact_report)?,
+        }
+
+        let seconds = started_at.elapsed().as_secs_f64();
+        diagnostics(Diagnostic::Note(format!(
+            "parsed {} bytes in {:.2}s ({:.1} MB/s) with {} jobs",
+            parsed_bytes,
+            seconds,
+            parsed_bytes as f64 / 1_000_000.0 / seconds.max(f64::EPSILON),
+            jobs
+        )));
+    }
+
+    if let Some(histogram) = &histogram {
+        match options.output {
+            OutputFormat::Json => {
+                for bucket in histogram.buckets() {
+                    writeln!(out, "{}", serde_json::to_string(bucket)?)?;
+                }
+            }
+            _ => write!(out, "{}", histogram)?,
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// 在一个线程中处理一个文件，和main中的循环一样，只支持`--jobs`允许的输出
+fn process_file(
+    file_path: &str,
+    options: &CliOptions,
+    output: &mut FileOutput,
+    stopped: &AtomicBool,
+) -> Result<FileResult, BoxedError> {
+    let mut file = match options.mmap {
+        #[cfg(feature = "mmap")]
+        true => BinlogReader::open_mmap(file_path)?,
+        _ => BinlogReader::open(file_path)?,
+    };
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(file_path)));
+    }
+
+    let file_length = file.file_length()?;
+    let in_use = is_binlog_in_use(&mut file, file_length)?;
+
+    let event_type_filter = EventTypeFilter::new(&options.event_types);
+
+    let mut grep_filter = match &options.grep {
+        Some(pattern) => Some(GrepFilter::new(
+            pattern,
+            &options.grep_columns,
+            options.grep_binary_hex,
+        )?),
+        None => None,
+    };
+
+    let mut impact_analyzer = options.analyze.then(ImpactAnalyzer::new);
+    let mut histogram = options
+        .histogram
+        .map(|width| Histogram::new(width, options.histogram_by));
+    let mut checksum_verifier = options.verify_checksums.then(ChecksumVerifier::new);
+
+    // 汇总类的输出只需要表和行数
+    let mut pipeline = EventPipeline::from_options(options)
+        .decode_row_values(impact_analyzer.is_none() && histogram.is_none());
+    let mut event_writer = EventWriter::from_options(options);
+
+    let mut parsed_bytes = 0;
+    let mut issues = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    let mut offset = 4;
+    while offset < file_length {
+        diagnostics.extend(file.take_diagnostics());
+
+        // 之前的文件出错时不需要再处理
+        if stopped.load(Ordering::Relaxed) {
+            return Err(Box::new(MyError("stopped by another file".to_string())));
+        }
+
+        let event_position = offset;
+
+        let header = get_checked_event_header(&mut file, offset, file_length, in_use)?;
+        check_event_size(&header, event_position, options.max_event_size)?;
+
+        if let Some(checksum_verifier) = checksum_verifier.as_mut() {
+            checksum_verifier.verify(&mut file, event_position, &header)?;
+        }
+        parsed_bytes += header.event_length as u64;
+
+        offset = header.next_event_position as u64;
+
+        // FDE中有写binlog的服务端，总是读取
+        if !event_type_filter.needs_body(header.type_code) && header.type_code != 15 {
+            continue;
+        }
+
+        let body = pipeline.decode(&mut file, event_position, &header)?;
+
+        pipeline.update(body.as_ref(), &mut |v| diagnostics.push(v));
+        issues.extend(
+            pipeline.check(&header, event_position, body.as_ref(), &mut |v| {
+                diagnostics.push(v)
+            })?,
+        );
+
+        if !event_type_filter.accept(header.type_code) {
+            continue;
+        }
+
+        let table_structs = &pipeline.table_structs;
+
+        if let Some(impact_analyzer) = impact_analyzer.as_mut() {
+            if let Some(impact) =
+                impact_analyzer.process(&header, event_position, body.as_ref(), table_structs)
+            {
+                write_impact(output, options.output, &impact)?;
+            }
+        }
+
+        // 和main中一样，`--analyze`和`--histogram`可以同时使用
+        if let Some(histogram) = histogram.as_mut() {
+            histogram.process(&header, body.as_ref(), table_structs);
+        }
+
+        if impact_analyzer.is_none() && histogram.is_none() {
+            let mut events = Vec::new();
+
+            // 和main中一样先补上匹配的事件所在事务的GTID和table map
+            if let Some(grep_filter) = grep_filter.as_mut() {
+                let context = match grep_filter.process(
+                    &header,
+                    event_position,
+                    body.as_ref(),
+                    table_structs,
+                ) {
+                    Some(context) => context,
+                    None => continue,
+                };
+
+                for context_position in context {
+                    let context_header = get_event_header(&mut file, context_position)?;
+                    let context_body = get_event_body(
+                        &mut file,
+                        context_position,
+                        &context_header,
+                        &mut pipeline.table_structs,
+                        pipeline.checksum_length,
+                    )?;
+                    events.push((context_position, context_header, context_body));
+                }
+            }
+
+            events.push((event_position, header, body));
+
+            for (event_position, header, body) in events {
+                event_writer.write(
+                    output,
+                    &header,
+                    event_position,
+                    body.as_ref(),
+                    &pipeline.table_structs,
+                )?;
+            }
+        }
+    }
+
+    diagnostics.extend(file.take_diagnostics());
+
+    // 事务不会跨文件，文件结束时还没有提交的事务直接结束
+    let impact_report = match impact_analyzer.as_mut() {
+        Some(impact_analyzer) => {
+            if let Some(impact) = impact_analyzer.finish() {
+                write_impact(output, options.output, &impact)?;
+            }
+            Some(impact_analyzer.report().clone())
+        }
+        None => None,
+    };
+
+    if options.output == OutputFormat::Human {
+        writeln!(output, "It's the end of file")?;
+    }
+
+    Ok(FileResult {
+        impact_report,
+        histogram,
+        checksum_verifier,
+        parsed_bytes,
+        issues,
+        diagnostics,
+    })
+}
+
+fn write_impact(
+    output: &mut FileOutput,
+    format: OutputFormat,
+    impact: &TransactionImpact,
+) -> Result<(), BoxedError> {
+    match format {
+        OutputFormat::Json => writeln!(output, "{}", serde_json::to_string(impact)?)?,
+        _ => writeln!(output, "{}", impact)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::cli::parse_args;
+    use crate::field_type::FieldType;
+
+    // 每个事务一个insert，rows是每个事务的行数
+    fn binlog(first_sequence: u64, rows: &[i64]) -> Vec<u8> {
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[]);
+
+        let mut builder = BinlogBuilder::new();
+        for (i, count) in rows.iter().enumerate() {
+            let insert = (0..*count).fold(RowsEventBuilder::insert(&table), |insert, id| {
+                insert.row(vec![ColumnValue::Int(id)])
+            });
+            builder = builder
+                .push(gtid(first_sequence + i as u64))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&table)
+                .event(&insert)
+                .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()));
+        }
+
+        builder.build()
+    }
+
+    // 三个文件，事务数和行数都不同
+    fn binlog_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("jobs-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(dir.join("mysql-bin.000001"), binlog(1, &[1, 2])).unwrap();
+        std::fs::write(dir.join("mysql-bin.000002"), binlog(3, &[3])).unwrap();
+        std::fs::write(dir.join("mysql-bin.000003"), binlog(4, &[4, 5, 6])).unwrap();
+
+        dir.to_string_lossy().to_string()
+    }
+
+    fn run(dir: &str, jobs: usize, args: &[&str]) -> (String, ErrorReport) {
+        let args = [&[dir], args]
+            .concat()
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>();
+        let options = parse_args(&args).unwrap();
+
+        let mut out = Vec::new();
+        let mut error_report = ErrorReport::new();
+        process_files(
+            dir,
+            jobs,
+            &options,
+            &mut out,
+            &mut error_report,
+            &mut |_| {},
+        )
+        .unwrap();
+
+        (String::from_utf8(out).unwrap(), error_report)
+    }
+
+    #[test]
+    fn aggregates_match_the_serial_run() {
+        let dir = binlog_dir("aggregates");
+
+        for args in [
+            &["--analyze", "--output", "json"][..],
+            &["--histogram", "60", "--output", "json"][..],
+            &["--analyze", "--histogram", "60"][..],
+        ] {
+            let (serial, _) = run(&dir, 1, args);
+            let (parallel, _) = run(&dir, 3, args);
+            assert_eq!(serial, parallel);
+        }
+
+        let (out, _) = run(&dir, 3, &["--analyze", "--output", "json"]);
+        let aggregate = out
+            .lines()
+            .map(|v| serde_json::from_str::<serde_json::Value>(v).unwrap())
+            .find_map(|v| v.get("aggregate").cloned())
+            .unwrap();
+        assert_eq!(aggregate["transactions"], 6);
+    }
+
+    #[test]
+    fn events_are_written_in_file_order() {
+        let dir = binlog_dir("order");
+
+        let (serial, _) = run(&dir, 1, &["--output", "json"]);
+        let (parallel, _) = run(&dir, 3, &["--output", "json"]);
+        assert_eq!(serial, parallel);
+
+        // 每个文件从FDE开始
+        let starts = parallel
+            .lines()
+            .map(|v| serde_json::from_str::<serde_json::Value>(v).unwrap())
+            .filter(|v| v["header"]["type_code"] == 15)
+            .count();
+        assert_eq!(starts, 3);
+    }
+}
@@ -0,0 +1,239 @@
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+
+use crate::model::*;
+use crate::relay_log::MasterPosition;
+
+/// `--output json`中每一行的结构，只用来生成JSON Schema，输出时是用json!拼起来的
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct EventEnvelope {
+    /// 事件在文件中的起始位置
+    position: u64,
+    header: EventHeader,
+    body: EventBodySchema,
+    /// 只有带Q_HRNOW的query事件有，UTC时间带微秒
+    event_time: Option<String>,
+    /// `--rewrite-db`改写了库名时原来的库名
+    original_database_name: Option<String>,
+    /// 行事件的表的字段名，只有binlog_row_metadata=FULL时才有
+    column_names: Option<Vec<String>>,
+    /// `--relay-log`时才有，不知道主库的位置时为null
+    master_position: Option<MasterPosition>,
+    /// `--hash`时事件的原始字节的摘要，十六进制
+    event_hash: Option<String>,
+    /// `--keep-raw`时整个事件的base64
+    raw: Option<String>,
+}
+
+/// 按header中的type_code是其中的一种，json中没有标记是哪一种
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum EventBodySchema {
+    /// 2
+    Query(EventBodyTypeCode2),
+    /// 4
+    Rotate(EventBodyTypeCode4),
+    /// 5
+    Intvar(EventBodyTypeCode5),
+    /// 13
+    Rand(EventBodyTypeCode13),
+    /// 14
+    UserVar(EventBodyTypeCode14),
+    /// 15
+    FormatDescription(EventBodyTypeCode15),
+    /// 16
+    Xid(EventBodyTypeCode16),
+    /// 19
+    TableMap(EventBodyTypeCode19),
+    /// 23到25、30到32
+    Rows(EventBodyTypeCode23To25),
+    /// 33、34
+    MysqlGtid(EventBodyTypeCode33),
+    /// 35
+    PreviousGtids(EventBodyTypeCode35),
+    /// 38
+    XaPrepare(EventBodyTypeCode38),
+    /// 160
+    Annotate(EventBodyTypeCode160),
+    /// 161
+    BinlogCheckpoint(EventBodyTypeCode161),
+    /// 162
+    Gtid(EventBodyTypeCode162),
+    /// 163
+    GtidList(EventBodyTypeCode163),
+    /// 164
+    StartEncryption(EventBodyTypeCode164),
+    /// `--report-missing-table-maps`时缺少table map的行事件
+    UndecodableRows(EventBodyUndecodableRows),
+    /// `--skip-errors`时解码失败的事件
+    Undecoded(EventBodyUndecoded),
+    /// 其他不解析的事件，里面是类型码
+    Skip(EventBodyTypeSkip),
+}
+
+/// `--dump-json-schema`，`--output json`中每一行的JSON Schema，可以用来生成其他语言的类型或者校验输出
+pub fn event_schema() -> RootSchema {
+    schema_for!(EventEnvelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::cli::OutputFormat;
+    use crate::pipeline::{EventPipeline, EventWriter};
+    use crate::service::get_event_header;
+    use crate::style::ColorChoice;
+    use serde_json::Value;
+    use std::io::Cursor;
+
+    fn user_var(name: &str, value: Option<&str>) -> EventBuilder {
+        let mut body = (name.len() as u32).to_le_bytes().to_vec();
+        body.extend(name.as_bytes());
+        match value {
+            Some(value) => {
+                body.extend([0, 0]);
+                body.extend(45u32.to_le_bytes());
+                body.extend((value.len() as u32).to_le_bytes());
+                body.extend(value.as_bytes());
+                body.push(0);
+            }
+            None => body.push(1),
+        }
+        EventBuilder::new(14, body)
+    }
+
+    // 各种事件都有的文件，包括Option字段有值和没有值的情况
+    fn fixture() -> Vec<u8> {
+        let users = users().optional_metadata(&[4, 8, 2, b'i', b'd', 4, b'n', b'a', b'm', b'e']);
+
+        let mut xa_prepare = vec![0];
+        xa_prepare.extend(1u32.to_le_bytes());
+        xa_prepare.extend(4u32.to_le_bytes());
+        xa_prepare.extend(1u32.to_le_bytes());
+        xa_prepare.extend(b"trx1b");
+
+        let mut gtid_list = 1u32.to_le_bytes().to_vec();
+        gtid_list.extend(0u32.to_le_bytes());
+        gtid_list.extend(1u32.to_le_bytes());
+        gtid_list.extend(9u64.to_le_bytes());
+
+        let mut rotate = 4u64.to_le_bytes().to_vec();
+        rotate.extend(b"mysql-bin.000002");
+
+        BinlogBuilder::new()
+            .push(EventBuilder::new(163, gtid_list))
+            .push(gtid(10))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .push(user_var("a", Some("x")))
+            .push(user_var("b", None))
+            .event(&users)
+            .event(
+                &RowsEventBuilder::insert(&users).row(vec![ColumnValue::Int(1), ColumnValue::Null]),
+            )
+            .event(&RowsEventBuilder::update(&users).update_row(
+                vec![ColumnValue::Int(1), ColumnValue::Null],
+                vec![ColumnValue::Int(1), ColumnValue::String("bob".to_string())],
+            ))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .event(
+                &GtidEventBuilder::new(11)
+                    .flags(64)
+                    .commit_id(12)
+                    .xa(1, b"trx1", b"b"),
+            )
+            .event(&RowsEventBuilder::delete(&users).row(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String("bob".to_string()),
+            ]))
+            .push(EventBuilder::new(38, xa_prepare))
+            .event(&GtidEventBuilder::new(12).flags(128).xa(1, b"trx1", b"b"))
+            .event(&QueryEventBuilder::new(
+                "shop",
+                "XA COMMIT X'74727831',X'62',1",
+            ))
+            .push(EventBuilder::new(170, vec![0; 8]))
+            .push(EventBuilder::new(4, rotate))
+            .build()
+    }
+
+    fn output(binlog: &[u8]) -> Vec<Value> {
+        let mut pipeline = EventPipeline::new(ValueOptions::default(), false);
+        let mut writer = EventWriter::new(OutputFormat::Json, ColorChoice::Never);
+        let mut file = Cursor::new(binlog);
+        let mut out = Vec::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = pipeline.decode(&mut file, position, &header).unwrap();
+            pipeline.update(body.as_ref(), &mut |_| {});
+            writer
+                .write(
+                    &mut out,
+                    &header,
+                    position,
+                    body.as_ref(),
+                    &pipeline.table_structs,
+                )
+                .unwrap();
+            position = header.next_event_position as u64;
+        }
+
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|v| serde_json::from_str(v).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn output_is_valid_against_the_schema() {
+        let schema = serde_json::to_value(event_schema()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+
+        let events = output(&fixture());
+        assert_eq!(events.len(), 17);
+        for event in &events {
+            let errors = validator
+                .iter_errors(event)
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            assert!(errors.is_empty(), "{}: {:?}", event, errors);
+        }
+
+        // 缺少必需的字段时校验失败
+        let mut event = events[1].clone();
+        event["header"].as_object_mut().unwrap().remove("type_code");
+        assert!(!validator.is_valid(&event));
+    }
+
+    #[test]
+    fn optional_fields_are_not_required() {
+        let schema = serde_json::to_value(event_schema()).unwrap();
+        // required按名字排序
+        let required = |schema: &Value| {
+            schema["required"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            required(&schema["definitions"]["EventBodyTypeCode162"]),
+            ["flags", "gtid_sequence", "replication_domain_id"]
+        );
+        assert_eq!(
+            required(&schema["definitions"]["EventBodyTypeCode14"]),
+            [
+                "length_of_user_variable_name",
+                "name_of_user_variable",
+                "null_indicator"
+            ]
+        );
+        assert_eq!(required(&schema), ["body", "header", "position"]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use rdkafka::{
+    config::ClientConfig,
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext},
+    ClientContext,
+};
+use serde_json::Value;
+
+use crate::checkpoint::Checkpoint;
+use crate::cli::KafkaKey;
+use crate::model::MyError;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Default)]
+struct DeliveryState {
+    // 已经发送但还没有收到确认的消息序号
+    in_flight: BTreeSet<u64>,
+    fatal_error: Option<String>,
+}
+
+struct DeliveryContext {
+    state: Mutex<DeliveryState>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = Box<u64>;
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, sequence: Self::DeliveryOpaque) {
+        let mut state = self.state.lock().unwrap();
+
+        state.in_flight.remove(&sequence);
+
+        // librdkafka内部已经按retry.backoff.ms重试过，到这里的失败都是最终失败
+        if let Err((e, _message)) = delivery_result {
+            state
+                .fatal_error
+                .get_or_insert(format!("kafka delivery failed: {}", e));
+        }
+    }
+}
+
+/// 把变更事件发送到kafka
+/// 只有当某个checkpoint之前的消息全部得到确认，这个checkpoint才会被交给调用方保存
+pub struct KafkaSink {
+    producer: BaseProducer<DeliveryContext>,
+    topic: String,
+    key: KafkaKey,
+    max_in_flight: usize,
+    next_sequence: u64,
+    // (在此之前发送的消息序号都需要确认, 对应的checkpoint)
+    pending_checkpoints: VecDeque<(u64, Checkpoint)>,
+}
+
+impl KafkaSink {
+    pub fn new(
+        brokers: &str,
+        topic: &str,
+        key: KafkaKey,
+        max_in_flight: usize,
+    ) -> Result<KafkaSink, BoxedError> {
+        let context = DeliveryContext {
+            state: Mutex::new(DeliveryState::default()),
+        };
+
+        let producer: BaseProducer<DeliveryContext> = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            // 幂等生产者在重试时也能保证分区内的顺序
+            .set("enable.idempotence", "true")
+            .set("retry.backoff.ms", "200")
+            .set("retry.backoff.max.ms", "10000")
+            .set("message.timeout.ms", "300000")
+            .create_with_context(context)?;
+
+        Ok(KafkaSink {
+            producer,
+            topic: topic.to_string(),
+            key,
+            max_in_flight: max_in_flight.max(1),
+            next_sequence: 0,
+            pending_checkpoints: VecDeque::new(),
+        })
+    }
+
+    /// envelope是Debezium格式的变更事件
+    /// 在途消息达到上限时会阻塞等待确认，从而让解析速度跟随broker的速度
+    pub fn send(&mut self, envelope: &Value) -> Result<(), BoxedError> {
+        while self.in_flight_count() >= self.max_in_flight {
+            self.producer.poll(POLL_INTERVAL);
+            self.check_fatal_error()?;
+        }
+
+        let key = message_key(self.key, envelope);
+        let payload = envelope.to_string();
+
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.producer
+            .context()
+            .state
+            .lock()
+            .unwrap()
+            .in_flight
+            .insert(sequence);
+
+        let mut record = BaseRecord::with_opaque_to(&self.topic, Box::new(sequence))
+            .key(&key)
+            .payload(&payload);
+
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => break,
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    record = returned;
+                    self.producer.poll(POLL_INTERVAL);
+                }
+                Err((e, _record)) => return Err(Box::new(e)),
+            }
+        }
+
+        self.producer.poll(Duration::ZERO);
+
+        self.check_fatal_error()
+    }
+
+    /// 记录一个checkpoint，它之前发送的消息全部确认后才算完成
+    pub fn mark_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.pending_checkpoints
+            .push_back((self.next_sequence, checkpoint));
+    }
+
+    /// 返回最新的一个已经完成确认的checkpoint
+    pub fn take_acknowledged_checkpoint(&mut self) -> Result<Option<Checkpoint>, BoxedError> {
+        self.producer.poll(Duration::ZERO);
+        self.check_fatal_error()?;
+
+        let lowest_in_flight = self
+            .producer
+            .context()
+            .state
+            .lock()
+            .unwrap()
+            .in_flight
+            .first()
+            .copied();
+
+        let mut result = None;
+        while let Some((boundary, _)) = self.pending_checkpoints.front() {
+            if lowest_in_flight.is_some_and(|v| v < *boundary) {
+                break;
+            }
+            result = self.pending_checkpoints.pop_front().map(|v| v.1);
+        }
+
+        Ok(result)
+    }
+
+    /// 等待所有消息确认
+    pub fn flush(&mut self) -> Result<Option<Checkpoint>, BoxedError> {
+        self.producer.flush(FLUSH_TIMEOUT)?;
+
+        self.take_acknowledged_checkpoint()
+    }
+
+    fn in_flight_count(&self) -> usize {
+        self.producer
+            .context()
+            .state
+            .lock()
+            .unwrap()
+            .in_flight
+            .len()
+    }
+
+    fn check_fatal_error(&self) -> Result<(), BoxedError> {
+        match &self.producer.context().state.lock().unwrap().fatal_error {
+            Some(e) => Err(Box::new(MyError(e.clone()))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 按表名作为key时，kafka默认的分区器会把同一张表的变更放到同一个分区，保证表内有序
+fn message_key(key: KafkaKey, envelope: &Value) -> String {
+    let source = &envelope["payload"]["source"];
+
+    match key {
+        KafkaKey::Table => format!(
+            "{}.{}",
+            source["db"].as_str().unwrap_or_default(),
+            source["table"].as_str().unwrap_or_default()
+        ),
+        KafkaKey::Gtid => source["gtid"].as_str().unwrap_or_default().to_string(),
+        // 加上表名，不同的表主键值相同时不会被当成同一行
+        KafkaKey::PrimaryKey => match &envelope["key"] {
+            key @ Value::Object(_) => format!(
+                "{}.{}:{}",
+                source["db"].as_str().unwrap_or_default(),
+                source["table"].as_str().unwrap_or_default(),
+                key
+            ),
+            _ => message_key(KafkaKey::Table, envelope),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn envelope(table: &str, gtid: Option<&str>, key: Value) -> Value {
+        json!({
+            "key": key,
+            "payload": {
+                "source": {"db": "shop", "table": table, "gtid": gtid},
+            }
+        })
+    }
+
+    fn checkpoint(position: u64) -> Checkpoint {
+        Checkpoint {
+            file_name: "mysql-bin.000001".to_string(),
+            position,
+        }
+    }
+
+    // 创建producer时不会连接broker
+    fn sink(max_in_flight: usize) -> KafkaSink {
+        KafkaSink::new("127.0.0.1:1", "changes", KafkaKey::Table, max_in_flight).unwrap()
+    }
+
+    // 模拟已经发送、还没有确认的消息
+    fn send_unacknowledged(sink: &mut KafkaSink) -> u64 {
+        let sequence = sink.next_sequence;
+        sink.next_sequence += 1;
+        sink.producer
+            .context()
+            .state
+            .lock()
+            .unwrap()
+            .in_flight
+            .insert(sequence);
+        sequence
+    }
+
+    fn acknowledge(sink: &KafkaSink, sequence: u64) {
+        sink.producer
+            .context()
+            .state
+            .lock()
+            .unwrap()
+            .in_flight
+            .remove(&sequence);
+    }
+
+    #[test]
+    fn message_keys() {
+        let orders = envelope("orders", Some("0-1-10"), json!({"id": 7}));
+        assert_eq!(message_key(KafkaKey::Table, &orders), "shop.orders");
+        assert_eq!(message_key(KafkaKey::Gtid, &orders), "0-1-10");
+        assert_eq!(
+            message_key(KafkaKey::PrimaryKey, &orders),
+            r#"shop.orders:{"id":7}"#
+        );
+
+        // 同一张表的变更有相同的key，进入同一个分区
+        let other_row = envelope("orders", Some("0-1-11"), json!({"id": 8}));
+        assert_eq!(
+            message_key(KafkaKey::Table, &orders),
+            message_key(KafkaKey::Table, &other_row)
+        );
+        // 不同的表主键值相同时key不同
+        let users = envelope("users", Some("0-1-10"), json!({"id": 7}));
+        assert_ne!(
+            message_key(KafkaKey::PrimaryKey, &orders),
+            message_key(KafkaKey::PrimaryKey, &users)
+        );
+
+        // 不知道主键时按表名，没有GTID时为空
+        let no_key = envelope("orders", None, Value::Null);
+        assert_eq!(message_key(KafkaKey::PrimaryKey, &no_key), "shop.orders");
+        assert_eq!(message_key(KafkaKey::Gtid, &no_key), "");
+    }
+
+    #[test]
+    fn checkpoint_waits_for_earlier_messages() {
+        let mut sink = sink(10);
+
+        // 之前没有消息时马上完成
+        sink.mark_checkpoint(checkpoint(100));
+        assert_eq!(
+            sink.take_acknowledged_checkpoint().unwrap(),
+            Some(checkpoint(100))
+        );
+        assert_eq!(sink.take_acknowledged_checkpoint().unwrap(), None);
+
+        let first = send_unacknowledged(&mut sink);
+        sink.mark_checkpoint(checkpoint(200));
+        let second = send_unacknowledged(&mut sink);
+        sink.mark_checkpoint(checkpoint(300));
+        assert_eq!(sink.take_acknowledged_checkpoint().unwrap(), None);
+
+        // 后面的消息先确认，前面的checkpoint还不能保存
+        acknowledge(&sink, second);
+        assert_eq!(sink.take_acknowledged_checkpoint().unwrap(), None);
+
+        // 全部确认后返回最新的一个
+        acknowledge(&sink, first);
+        assert_eq!(
+            sink.take_acknowledged_checkpoint().unwrap(),
+            Some(checkpoint(300))
+        );
+    }
+
+    #[test]
+    fn checkpoint_before_unacknowledged_message() {
+        let mut sink = sink(10);
+
+        let first = send_unacknowledged(&mut sink);
+        sink.mark_checkpoint(checkpoint(200));
+        send_unacknowledged(&mut sink);
+        sink.mark_checkpoint(checkpoint(300));
+
+        acknowledge(&sink, first);
+        assert_eq!(
+            sink.take_acknowledged_checkpoint().unwrap(),
+            Some(checkpoint(200))
+        );
+        assert_eq!(sink.in_flight_count(), 1);
+    }
+
+    #[test]
+    fn delivery_failure_is_fatal() {
+        let mut sink = sink(10);
+        sink.mark_checkpoint(checkpoint(100));
+        sink.producer.context().state.lock().unwrap().fatal_error =
+            Some("kafka delivery failed: Message timed out".to_string());
+
+        let error = sink.take_acknowledged_checkpoint().unwrap_err();
+        assert!(error.to_string().contains("Message timed out"));
+        assert!(sink.send(&envelope("orders", None, Value::Null)).is_err());
+    }
+
+    // 需要本地的broker：KAFKA_TEST_BROKERS=localhost:9092 cargo test --features kafka
+    #[test]
+    fn send_to_broker() {
+        use rdkafka::consumer::{BaseConsumer, Consumer};
+        use rdkafka::Message;
+
+        let Ok(brokers) = std::env::var("KAFKA_TEST_BROKERS") else {
+            return;
+        };
+        let topic = format!("binlog-test-{}", std::process::id());
+
+        let mut sink = KafkaSink::new(&brokers, &topic, KafkaKey::Table, 2).unwrap();
+        for (index, table) in ["orders", "users", "orders"].iter().enumerate() {
+            sink.send(&envelope(
+                table,
+                Some(&format!("0-1-{}", index)),
+                Value::Null,
+            ))
+            .unwrap();
+            sink.mark_checkpoint(checkpoint(index as u64));
+        }
+        assert_eq!(sink.flush().unwrap(), Some(checkpoint(2)));
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", &topic)
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&[&topic]).unwrap();
+
+        let mut keys = Vec::new();
+        while keys.len() < 3 {
+            let message = consumer
+                .poll(Duration::from_secs(30))
+                .expect("no message from the broker")
+                .unwrap();
+            keys.push(String::from_utf8(message.key().unwrap().to_vec()).unwrap());
+        }
+        keys.sort();
+        assert_eq!(keys, vec!["shop.orders", "shop.orders", "shop.users"]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fmt::Display, path::Path, sync::Arc};
+
+use serde::Serialize;
+
+use crate::error::ParseError;
+use crate::model::EventBodyTypeCode19;
+use crate::reader::BinlogReader;
+use crate::service::*;
+use crate::transaction::{TransactionSummary, TransactionTracker};
+use crate::util::*;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 超过任意一个阈值的事务都会被报告
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargeTransactionThreshold {
+    pub bytes: Option<u64>,
+    pub rows: Option<u64>,
+}
+
+impl LargeTransactionThreshold {
+    fn is_exceeded(&self, transaction: &TransactionSummary) -> bool {
+        self.bytes.is_some_and(|v| transaction.bytes > v)
+            || self.rows.is_some_and(|v| transaction.total_rows() > v)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LargeTransaction {
+    pub file_name: String,
+    #[serde(flatten)]
+    pub transaction: TransactionSummary,
+}
+
+impl Display for LargeTransaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file={} {}", self.file_name, self.transaction)
+    }
+}
+
+/// path可以是一个binlog文件，也可以是包含多个binlog文件的目录
+/// 结果按字节数从大到小排列，只设置了行数阈值时按行数排列
+pub fn find_large_transactions(
+    path: &str,
+    threshold: LargeTransactionThreshold,
+) -> Result<Vec<LargeTransaction>, BoxedError> {
+    let file_paths = if Path::new(path).is_dir() {
+        get_binlog_files_in_dir(path)?
+    } else {
+        vec![path.to_string()]
+    };
+
+    let mut result = Vec::new();
+    for file_path in file_paths {
+        let file_name = get_file_name(&file_path);
+
+        result.extend(
+            scan_file(&file_path)?
+                .into_iter()
+                .filter(|v| threshold.is_exceeded(v))
+                .map(|transaction| LargeTransaction {
+                    file_name: file_name.clone(),
+                    transaction,
+                }),
+        );
+    }
+
+    if threshold.bytes.is_some() {
+        result.sort_by_key(|v| std::cmp::Reverse(v.transaction.bytes));
+    } else {
+        result.sort_by_key(|v| std::cmp::Reverse(v.transaction.total_rows()));
+    }
+
+    Ok(result)
+}
+
+fn scan_file(file_path: &str) -> Result<Vec<TransactionSummary>, BoxedError> {
+    let mut file = BinlogReader::open(file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(file_path)));
+    }
+
+    let file_length = file.file_length()?;
+
+    let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+    let mut transaction_tracker = TransactionTracker::new();
+    let mut result = Vec::new();
+
+    let mut offset = 4;
+    while offset < file_length {
+        let event_position = offset;
+
+        let header = get_event_header(&mut file, offset)?;
+
+        // 只需要表和行数
+        let body = get_event_body_without_row_values(
+            &mut file,
+            offset,
+            &header,
+            &mut table_structs,
+            checksum_length,
+        )?;
+        update_checksum_length(&mut checksum_length, body.as_ref());
+        offset = header.next_event_position as u64;
+
+        result.extend(transaction_tracker.process(
+            &header,
+            event_position,
+            body.as_ref(),
+            &table_structs,
+        ));
+    }
+
+    result.extend(transaction_tracker.finish());
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::model::ColumnValue;
+
+    // 每个事务一个insert，rows是每个事务的行数
+    fn binlog(first_sequence: u64, rows: &[i64]) -> Vec<u8> {
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &200u16.to_le_bytes());
+
+        let mut builder = BinlogBuilder::new();
+        for (i, count) in rows.iter().enumerate() {
+            let insert = (0..*count).fold(RowsEventBuilder::insert(&table), |insert, id| {
+                insert.row(vec![
+                    ColumnValue::Int(id),
+                    ColumnValue::String("x".repeat(100)),
+                ])
+            });
+            builder = builder
+                .push(gtid(first_sequence + i as u64))
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&table)
+                .event(&insert)
+                .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()));
+        }
+
+        builder.build()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("large-transaction-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn gtids(transactions: &[LargeTransaction]) -> Vec<(&str, &str)> {
+        transactions
+            .iter()
+            .map(|v| (v.file_name.as_str(), v.transaction.gtid.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn flag_the_large_insert() {
+        let dir = temp_dir("file");
+        let path = dir.join("mysql-bin.000001");
+        std::fs::write(&path, binlog(1, &[1, 1000, 2, 1])).unwrap();
+        let path = path.to_str().unwrap();
+
+        let by_bytes = LargeTransactionThreshold {
+            bytes: Some(100 * 1024),
+            rows: None,
+        };
+        let result = find_large_transactions(path, by_bytes).unwrap();
+        assert_eq!(gtids(&result), [("mysql-bin.000001", "0-1-2")]);
+
+        let transaction = &result[0].transaction;
+        assert_eq!(transaction.total_rows(), 1000);
+        assert_eq!(transaction.tables, ["shop.orders"]);
+        assert!(transaction.bytes > 100 * 1024);
+        assert_eq!(
+            transaction.bytes,
+            transaction.end_position - transaction.start_position
+        );
+
+        let by_rows = LargeTransactionThreshold {
+            bytes: None,
+            rows: Some(100),
+        };
+        let result = find_large_transactions(path, by_rows).unwrap();
+        assert_eq!(gtids(&result), [("mysql-bin.000001", "0-1-2")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 目录中所有binlog文件的结果放在一起从大到小排列，其他文件忽略
+    #[test]
+    fn sort_across_files() {
+        let dir = temp_dir("dir");
+        std::fs::write(dir.join("mysql-bin.000001"), binlog(1, &[1, 200, 1])).unwrap();
+        std::fs::write(dir.join("mysql-bin.000002"), binlog(4, &[1000, 1, 500])).unwrap();
+        std::fs::write(dir.join("mysql-bin.index"), "mysql-bin.000001\n").unwrap();
+
+        let threshold = LargeTransactionThreshold {
+            bytes: None,
+            rows: Some(100),
+        };
+        let result = find_large_transactions(dir.to_str().unwrap(), threshold).unwrap();
+        assert_eq!(
+            gtids(&result),
+            [
+                ("mysql-bin.000002", "0-1-4"),
+                ("mysql-bin.000002", "0-1-6"),
+                ("mysql-bin.000001", "0-1-2"),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
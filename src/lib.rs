@@ -1,3 +1,87 @@
+pub mod analyze;
+#[cfg(feature = "apply")]
+pub mod apply;
+pub mod binlog_base64;
+pub mod binlog_index;
+#[cfg(any(test, feature = "builders"))]
+pub mod builders;
+pub mod byte_range;
+pub mod canal;
+pub mod checkpoint;
+pub mod checksum;
+pub mod cli;
+pub mod collation;
+pub mod column_select;
+pub mod continuity;
+pub mod ddl;
+pub mod debezium;
+pub mod diff;
+pub mod diagnostic;
+pub mod digest;
+pub mod error;
+pub mod event_hash;
+pub mod event_ref;
+pub mod extract;
+pub mod field_type;
+pub mod filter;
+pub mod find_gtid;
+pub mod fingerprint;
+pub mod flavor;
+pub mod grep;
+pub mod group_commit;
+pub mod gtid;
+pub mod gtid_pos;
+pub mod hexdump;
+pub mod histogram;
+pub mod jobs;
+#[cfg(feature = "schemars")]
+pub mod json_schema;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod large_transaction;
+pub mod locale;
+pub mod mask;
+pub mod maxwell;
+pub mod metrics;
+#[cfg(feature = "metrics")]
+pub mod metrics_server;
+pub mod missing_table_map;
 pub mod model;
+pub mod mysql_json;
+pub mod parallel;
+pub mod parser;
+pub mod per_table;
+pub mod pipeline;
+pub mod raw_archive;
+pub mod reader;
+pub mod relay_log;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod repair;
+pub mod rewrite;
+pub mod row_filter;
+pub mod rows_only;
+pub mod schema_drift;
 pub mod service;
-pub mod util;
\ No newline at end of file
+pub mod session;
+pub mod slow_report;
+pub mod split;
+pub mod sql;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod statement_tables;
+pub mod style;
+pub mod summary;
+pub mod table_checksum;
+pub mod table_structure;
+pub mod table_view;
+pub mod template;
+pub mod timestamp_check;
+pub mod trace;
+pub mod transaction;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod util;
+pub mod watch;
+pub mod writer;
+pub mod xa_report;
\ No newline at end of file
@@ -0,0 +1,94 @@
+/// lc_time_names的locale，下标就是Q_LC_TIME_NAMES_CODE中的id，和MariaDB的`sql/sql_locale.cc`相同
+/// MySQL 5.7、8.0的前111个也是这些，sr_YU改名为sr_RS之后id没有变
+const LOCALES: [&str; 111] = [
+    "en_US", "en_GB", "ja_JP", "sv_SE", "de_DE", "fr_FR", "ar_AE", "ar_BH", "ar_JO", "ar_SA",
+    "ar_SY", "be_BY", "bg_BG", "ca_ES", "cs_CZ", "da_DK", "de_AT", "es_ES", "et_EE", "eu_ES",
+    "fi_FI", "fo_FO", "gl_ES", "gu_IN", "he_IL", "hi_IN", "hr_HR", "hu_HU", "id_ID", "is_IS",
+    "it_CH", "ko_KR", "lt_LT", "lv_LV", "mk_MK", "mn_MN", "ms_MY", "nb_NO", "nl_NL", "pl_PL",
+    "pt_BR", "pt_PT", "ro_RO", "ru_RU", "ru_UA", "sk_SK", "sl_SI", "sq_AL", "sr_RS", "ta_IN",
+    "te_IN", "th_TH", "tr_TR", "uk_UA", "ur_PK", "vi_VN", "zh_CN", "zh_TW", "ar_DZ", "ar_EG",
+    "ar_IN", "ar_IQ", "ar_KW", "ar_LB", "ar_LY", "ar_MA", "ar_OM", "ar_QA", "ar_SD", "ar_TN",
+    "ar_YE", "de_BE", "de_CH", "de_LU", "en_AU", "en_CA", "en_IN", "en_NZ", "en_PH", "en_ZA",
+    "en_ZW", "es_AR", "es_BO", "es_CL", "es_CO", "es_CR", "es_DO", "es_EC", "es_GT", "es_HN",
+    "es_MX", "es_NI", "es_PA", "es_PE", "es_PR", "es_PY", "es_SV", "es_US", "es_UY", "es_VE",
+    "fr_BE", "fr_CA", "fr_CH", "fr_LU", "it_IT", "nl_BE", "no_NO", "sv_FI", "zh_HK", "el_GR",
+    "rm_CH",
+];
+
+/// 按id查找，不认识的id返回None
+pub fn lookup(id: u64) -> Option<&'static str> {
+    LOCALES.get(usize::try_from(id).ok()?).copied()
+}
+
+/// 输出用的名字，例如`zh_CN (56)`，不认识的id是`unknown (200)`
+pub fn locale_name(id: u64) -> String {
+    match lookup(id) {
+        Some(name) => format!("{} ({})", name, id),
+        None => format!("unknown ({})", id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::StatusVariable;
+    use crate::util::decode_status_variables;
+
+    #[test]
+    fn known_ids() {
+        let known = [
+            (0, "en_US"),
+            (1, "en_GB"),
+            (2, "ja_JP"),
+            (4, "de_DE"),
+            (5, "fr_FR"),
+            (31, "ko_KR"),
+            (48, "sr_RS"),
+            (56, "zh_CN"),
+            (57, "zh_TW"),
+            (108, "zh_HK"),
+            (110, "rm_CH"),
+        ];
+
+        for (id, name) in known {
+            assert_eq!(lookup(id), Some(name), "locale {}", id);
+        }
+        assert_eq!(locale_name(56), "zh_CN (56)");
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(lookup(111), None);
+        assert_eq!(lookup(u64::MAX), None);
+        assert_eq!(locale_name(200), "unknown (200)");
+    }
+
+    #[test]
+    fn lc_time_names_status_variable() {
+        // Q_LC_TIME_NAMES_CODE是7，后面是2个字节的id
+        let status_variables = decode_status_variables(&[7, 56, 0, 7, 200, 0]).unwrap();
+        assert_eq!(
+            status_variables,
+            [
+                StatusVariable::LcTimeNames {
+                    code: 56,
+                    locale: Some("zh_CN")
+                },
+                StatusVariable::LcTimeNames {
+                    code: 200,
+                    locale: None
+                },
+            ]
+        );
+        assert_eq!(
+            status_variables
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "lc time names is zh_CN (56)",
+                "lc time names is unknown (200)"
+            ]
+        );
+    }
+}
@@ -1,153 +1,1577 @@
 use std::{
-    collections::HashMap,
-    env,
+    env, io,
+    process::ExitCode,
+    sync::Arc,
+    time::Instant,
 };
 
-use mariadb_binlog_parse::model::EventBodyTypeCode19;
+use serde_json::json;
+
+use mariadb_binlog_parse::analyze::ImpactAnalyzer;
+#[cfg(feature = "apply")]
+use mariadb_binlog_parse::apply::Applier;
+use mariadb_binlog_parse::binlog_base64::{write_base64_events, BinlogBase64Writer};
+use mariadb_binlog_parse::binlog_index::read_binlog_index;
+use mariadb_binlog_parse::canal::CanalConverter;
+use mariadb_binlog_parse::checkpoint::{load_checkpoint, Checkpoint, CheckpointWriter};
+use mariadb_binlog_parse::checksum::ChecksumVerifier;
+use mariadb_binlog_parse::cli::{parse_command, Command, OutputFormat, USAGE};
+use mariadb_binlog_parse::column_select::ColumnSelector;
+use mariadb_binlog_parse::continuity::ContinuityChecker;
+use mariadb_binlog_parse::ddl::DdlExtractor;
+use mariadb_binlog_parse::debezium::DebeziumConverter;
+use mariadb_binlog_parse::diagnostic::Diagnostic;
+use mariadb_binlog_parse::diff::diff_binlogs;
+use mariadb_binlog_parse::digest::DigestReport;
+use mariadb_binlog_parse::error::{
+    get_checked_event_header, get_relay_log_event_header, is_binlog_in_use, ErrorCategory,
+    ErrorReport, ParseError,
+};
+use mariadb_binlog_parse::event_hash::{event_hash, to_hex};
+use mariadb_binlog_parse::extract::extract_event;
+use mariadb_binlog_parse::filter::{DomainFilter, EventTypeFilter, OutputLimiter, StopFilter};
+use mariadb_binlog_parse::find_gtid::find_gtid;
+use mariadb_binlog_parse::grep::GrepFilter;
+use mariadb_binlog_parse::group_commit::GroupCommitReport;
+use mariadb_binlog_parse::gtid_pos::gtid_pos;
+use mariadb_binlog_parse::hexdump::{event_layout, hexdump, traced_layout};
+use mariadb_binlog_parse::histogram::Histogram;
+use mariadb_binlog_parse::jobs::process_files;
+#[cfg(feature = "kafka")]
+use mariadb_binlog_parse::kafka::KafkaSink;
+use mariadb_binlog_parse::large_transaction::{find_large_transactions, LargeTransactionThreshold};
+use mariadb_binlog_parse::mask::ColumnMasker;
+use mariadb_binlog_parse::maxwell::MaxwellConverter;
+use mariadb_binlog_parse::metrics::MetricsRegistry;
+#[cfg(feature = "metrics")]
+use mariadb_binlog_parse::metrics_server::serve_metrics;
+use mariadb_binlog_parse::missing_table_map::MissingTableMapReport;
+use mariadb_binlog_parse::model::{
+    EventBody, EventBodyTypeSkip,
+    EventBodyUndecodableRows, EventBodyUndecoded, EventTime, MyError,
+};
+use mariadb_binlog_parse::parallel::ParallelDecoder;
+use mariadb_binlog_parse::parser::RawEvent;
+use mariadb_binlog_parse::per_table::PerTableWriter;
+use mariadb_binlog_parse::pipeline::{check_event_size, EventPipeline};
+use mariadb_binlog_parse::reader::BinlogReader;
+use mariadb_binlog_parse::relay_log::RelayLogTracker;
+#[cfg(feature = "remote")]
+use mariadb_binlog_parse::remote::dump_from_server;
+use mariadb_binlog_parse::repair::{find_next_valid_event, repair_binlog};
+use mariadb_binlog_parse::rewrite::DatabaseRewriter;
+use mariadb_binlog_parse::row_filter::RowFilter;
+use mariadb_binlog_parse::rows_only::RowsOnlyFormatter;
 use mariadb_binlog_parse::service::*;
-use mariadb_binlog_parse::util::{check_file_magic_number, get_file};
+use mariadb_binlog_parse::session::SessionReport;
+use mariadb_binlog_parse::slow_report::SlowReport;
+use mariadb_binlog_parse::split::split_binlog;
+#[cfg(feature = "sqlite")]
+use mariadb_binlog_parse::sqlite::SqliteWriter;
+use mariadb_binlog_parse::style::Styler;
+use mariadb_binlog_parse::summary::summarize_binlog;
+use mariadb_binlog_parse::table_checksum::TableChecksums;
+use mariadb_binlog_parse::table_structure::{rows_event_column_names, TableStructureTracker};
+use mariadb_binlog_parse::table_view::{TableRenderer, DEFAULT_MAX_COLUMN_WIDTH};
+use mariadb_binlog_parse::template::EventFormatter;
+use mariadb_binlog_parse::timestamp_check::{
+    TimestampChecker, DEFAULT_MAX_JUMP, DEFAULT_TOLERANCE,
+};
+use mariadb_binlog_parse::trace;
+use mariadb_binlog_parse::transaction::{sort_transactions, TransactionTracker};
+use mariadb_binlog_parse::util::{check_file_magic_number, get_file_name};
+use mariadb_binlog_parse::watch::BinlogWatcher;
+use mariadb_binlog_parse::xa_report::XaReport;
 
 const EVENT_HEADER_LENGTH: usize = 19;
 
 type BoxedError = Box<dyn std::error::Error>;
 
-fn main() -> Result<(), BoxedError> {
+fn main() -> ExitCode {
     let args = env::args().collect::<Vec<String>>();
 
-    if args.len() < 2 {
-        panic!("have no enough arguments. please input the binlog file path");
-    }
-
-    let binlog_file_path = args[1].clone();
-
-    // cargo run --bin mariadb_binlog_parse --features="test"
-    // 上述指令用于进行测试，即运行下面if中的代码块
-    // 用于测试单条日志
-    if cfg!(feature = "test") {
-        let mut offset = 75227;
-
-        let mut file = get_file(&binlog_file_path)?;
-
-        let mut table_structs: HashMap<u64, EventBodyTypeCode19> = HashMap::new();
-
-        let event_body = EventBodyTypeCode19 {
-            table_id: 230,
-            reserved_for_future_use: 1,
-            database_name_length: 8,
-            database_name: "test1223".to_string(),
-            table_name_length: 2,
-            table_name: "t4".to_string(),
-            number_of_columns: 4,
-            column_types: vec![
-                3,
-                252,
-                252,
-                15,
-            ],
-            column_types_string_for_human: vec![
-                "MYSQL_TYPE_LONG".to_string(),
-                "MYSQL_TYPE_BLOB".to_string(),
-                "MYSQL_TYPE_BLOB".to_string(),
-                "MYSQL_TYPE_VARCHAR".to_string(),
-            ],
-            number_of_metadata_block: 4,
-            metadata_block: vec![
-                2,
-                2,
-                16,
-                39,
-            ],
-            metadata_block_string_for_human: vec![
-                "field type id is: 252, field type name is: MYSQL_TYPE_BLOB, infomation is [field size is 2 bytes]".to_string(),
-                "field type id is: 252, field type name is: MYSQL_TYPE_BLOB, infomation is [field size is 2 bytes]".to_string(),
-                "field type id is: 15, field type name is: MYSQL_TYPE_VARCHAR, infomation is [the maximum length of the string is 10000 byte]".to_string(),
-            ],
-            metadata_block_data_raw: vec![
-                vec![
-                    2,
-                ],
-                vec![
-                    2,
-                ],
-                vec![
-                    16,
-                    39,
-                ],
-            ],
-            columns_can_be_null: vec![
-                false,
-                true,
-                true,
-                true,
-            ],
-            optional_metadata_block: vec![
-                14,
-                108,
-                66,
-                131,
-                64,
-            ],
+    // 退出码和`--errors json`的输出见error.rs
+    let mut error_report = ErrorReport::new();
+    let result = run(&args[1..], &mut error_report);
+
+    ExitCode::from(error_report.finish(result))
+}
+
+fn run(args: &[String], error_report: &mut ErrorReport) -> Result<(), BoxedError> {
+    let options = match parse_command(args)? {
+        Command::Parse(options) => *options,
+        Command::Diff(diff_options) => {
+            let report = diff_binlogs(&diff_options.left, &diff_options.right)?;
+
+            match diff_options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                _ => println!("{}", report),
+            }
+
+            return Ok(());
+        }
+        Command::Split(split_options) => {
+            for part in split_binlog(
+                &split_options.binlog_file_path,
+                &split_options.output_dir,
+                &split_options.split_points,
+            )? {
+                match split_options.output {
+                    OutputFormat::Json => println!("{}", serde_json::to_string(&part)?),
+                    _ => println!("{}", part),
+                }
+            }
+
+            return Ok(());
+        }
+        Command::Repair(repair_options) => {
+            let report = repair_binlog(
+                &repair_options.binlog_file_path,
+                repair_options.truncate,
+                repair_options.force,
+            )?;
+
+            match repair_options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                _ => println!("{}", report),
+            }
+
+            return Ok(());
+        }
+        Command::Extract(extract_options) => {
+            let report = extract_event(
+                &extract_options.binlog_file_path,
+                extract_options.offset,
+                &extract_options.out,
+                extract_options.standalone,
+                extract_options.redact_values,
+            )?;
+
+            match extract_options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                _ => println!("{}", report),
+            }
+
+            return Ok(());
+        }
+        Command::Summary(summary_options) => {
+            let summary = summarize_binlog(&summary_options.binlog_file_path)?;
+
+            match summary_options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&summary)?),
+                _ => println!("{}", summary),
+            }
+
+            return Ok(());
+        }
+        Command::FromBase64(from_base64_options) => {
+            return write_base64_events(
+                &from_base64_options,
+                &mut io::stdout().lock(),
+                &mut print_diagnostic,
+            );
+        }
+        Command::Help => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        Command::DumpJsonSchema => {
+            #[cfg(feature = "schemars")]
+            {
+                let schema = mariadb_binlog_parse::json_schema::event_schema();
+                println!("{}", serde_json::to_string_pretty(&schema)?);
+                return Ok(());
+            }
+
+            #[cfg(not(feature = "schemars"))]
+            return Err(Box::new(MyError(
+                "json schema support is not compiled in, please build with `--features schemars`"
+                    .to_string(),
+            )));
+        }
+        Command::FindGtid(find_gtid_options) => {
+            let location = find_gtid(&find_gtid_options.path, &find_gtid_options.gtid)?;
+
+            match find_gtid_options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&location)?),
+                _ => println!("{}", location),
+            }
+
+            return Ok(());
+        }
+        Command::GtidPos(gtid_pos_options) => {
+            let position = gtid_pos(&gtid_pos_options.paths)?;
+
+            match (gtid_pos_options.output, gtid_pos_options.verbose) {
+                (OutputFormat::Json, _) => println!("{}", serde_json::to_string(&position)?),
+                (_, true) => println!("{}", position),
+                (_, false) => println!("{}", position.end),
+            }
+
+            return Ok(());
+        }
+    };
+
+    error_report.format = options.errors;
+
+    let binlog_file_path = options.binlog_file_path.clone();
+
+    if options.find_large_transactions {
+        let threshold = LargeTransactionThreshold {
+            bytes: options.threshold_bytes,
+            rows: options.threshold_rows,
+        };
+
+        for transaction in find_large_transactions(&binlog_file_path, threshold)? {
+            match options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&transaction)?),
+                _ => println!("{}", transaction),
+            }
+        }
+
+        return Ok(());
+    }
+
+    #[cfg(feature = "tui")]
+    if options.tui {
+        return mariadb_binlog_parse::tui::run(&binlog_file_path);
+    }
+
+    #[cfg(not(feature = "tui"))]
+    if options.tui {
+        return Err(Box::new(MyError(
+            "tui support is not compiled in, please build with `--features tui`".to_string(),
+        )));
+    }
+
+    // 如果有checkpoint，就从上次完成的事务之后继续
+    let mut resume_checkpoint = None;
+    let mut checkpoint_writer = None;
+    if let Some(checkpoint_file_path) = &options.checkpoint_file_path {
+        resume_checkpoint = load_checkpoint(checkpoint_file_path)?;
+        checkpoint_writer = Some(CheckpointWriter::new(checkpoint_file_path));
+    }
+
+    #[cfg(feature = "kafka")]
+    let mut kafka_sink = match (&options.kafka_brokers, &options.kafka_topic) {
+        (Some(brokers), Some(topic)) => Some(KafkaSink::new(
+            brokers,
+            topic,
+            options.kafka_key,
+            options.kafka_max_in_flight,
+        )?),
+        _ => None,
+    };
+
+    #[cfg(not(feature = "kafka"))]
+    if options.kafka_brokers.is_some() {
+        return Err(Box::new(MyError(
+            "kafka support is not compiled in, please build with `--features kafka`"
+                .to_string(),
+        )));
+    }
+
+    // dry run时不连接目标库，只输出SQL
+    #[cfg(feature = "apply")]
+    let mut applier = match (options.apply, options.dry_run, options.target.as_deref()) {
+        (true, true, _) => Some(Applier::dry_run(
+            Box::new(io::stdout()),
+            options.on_error,
+            options.insert_mode,
+        )),
+        (true, false, Some(target)) => Some(Applier::new(
+            target,
+            options.on_error,
+            options.insert_mode,
+        )?),
+        _ => None,
+    };
+
+    #[cfg(not(feature = "apply"))]
+    if options.apply {
+        return Err(Box::new(MyError(
+            "apply support is not compiled in, please build with `--features apply`"
+                .to_string(),
+        )));
+    }
+
+    let metrics = options
+        .metrics_listen
+        .as_ref()
+        .map(|_| Arc::new(MetricsRegistry::new()));
+
+    #[cfg(feature = "metrics")]
+    if let (Some(address), Some(metrics)) = (&options.metrics_listen, &metrics) {
+        serve_metrics(address, metrics.clone())?;
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    if options.metrics_listen.is_some() {
+        return Err(Box::new(MyError(
+            "metrics support is not compiled in, please build with `--features metrics`"
+                .to_string(),
+        )));
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    if options.mmap {
+        return Err(Box::new(MyError(
+            "mmap support is not compiled in, please build with `--features mmap`".to_string(),
+        )));
+    }
+
+    #[cfg(not(feature = "trace"))]
+    if options.trace {
+        return Err(Box::new(MyError(
+            "trace support is not compiled in, please build with `--features trace`"
+                .to_string(),
+        )));
+    }
+
+    #[cfg(feature = "sqlite")]
+    let mut sqlite_writer = match &options.result_file {
+        Some(path) => Some(SqliteWriter::new(path, options.ddl_include_grants)?),
+        None => None,
+    };
+
+    #[cfg(not(feature = "sqlite"))]
+    if options.output == OutputFormat::Sqlite {
+        return Err(Box::new(MyError(
+            "sqlite support is not compiled in, please build with `--features sqlite`"
+                .to_string(),
+        )));
+    }
+
+    #[cfg(feature = "remote")]
+    if let Some(remote_server) = &options.remote_server {
+        return dump_from_server(
+            remote_server,
+            &binlog_file_path,
+            &options,
+            &mut io::stdout().lock(),
+            error_report,
+            metrics.as_deref(),
+            &mut print_diagnostic,
+        );
+    }
+
+    #[cfg(not(feature = "remote"))]
+    if options.remote_server.is_some() {
+        return Err(Box::new(MyError(
+            "remote server support is not compiled in, please build with `--features remote`"
+                .to_string(),
+        )));
+    }
+
+    // 每个文件在自己的线程中处理，不经过下面的循环
+    if let Some(jobs) = options.jobs {
+        return process_files(
+            &binlog_file_path,
+            jobs,
+            &options,
+            &mut io::stdout().lock(),
+            error_report,
+            &mut print_diagnostic,
+        );
+    }
+
+    // table map、校验和长度、写binlog的服务端和表结构在文件之间延续
+    let mut pipeline = EventPipeline::from_options(&options);
+
+    let mut debezium_converter = DebeziumConverter::new(&binlog_file_path);
+
+    let mut maxwell_converter = MaxwellConverter::new(&binlog_file_path);
+
+    let mut canal_converter = CanalConverter::new();
+
+    let styler = Styler::new(options.color);
+
+    let mut event_formatter = options.format.as_ref().map(EventFormatter::new);
+    let table_renderer =
+        TableRenderer::new(options.max_column_width.unwrap_or(DEFAULT_MAX_COLUMN_WIDTH));
+    let mut rows_only_formatter = options.rows_only.then(RowsOnlyFormatter::new);
+
+    let mut per_table_writer = match &options.per_table_output {
+        Some(dir) => Some(PerTableWriter::new(
+            dir,
+            options.per_table_format,
+            options.insert_mode,
+            options.ddl_include_grants,
+            &options.columns,
+        )?),
+        None => None,
+    };
+
+    let mut domain_filter = DomainFilter::new(&options.domain_ids);
+
+    let mut column_masker = ColumnMasker::new(&options.mask);
+
+    let mut column_selector = ColumnSelector::new(&options.columns);
+    let mut row_filter = RowFilter::new(&options.row_predicates, options.where_image);
+
+    let mut database_rewriter = DatabaseRewriter::new(&options.rewrite_db);
+
+    let mut stop_filter = StopFilter::new(&options.stop);
+
+    let event_type_filter = EventTypeFilter::new(&options.event_types);
+
+    // 汇总类的输出只需要表和行数，行事件不解码字段的值
+    // `--lenient-row-images`时不一致的行事件也要完整解码，保留已经解码的行
+    let decode_row_values = options.kafka_brokers.is_some()
+        || options.apply
+        || options.lenient_row_images
+        || options.table_checksums
+        || !(options.analyze
+            || options.histogram.is_some()
+            || options.transactions
+            || options.slow_report
+            || options.sessions
+            || options.xa_report
+            || options.group_commit_report
+            || options.digest
+            || options.show_table_structure
+            || options.ddl_only);
+
+    // 汇总、DDL和表结构的输出不再输出事件本身，`--check-timestamps`在过滤之前检查事件头
+    let event_output = !(options.analyze
+        || options.histogram.is_some()
+        || options.transactions
+        || options.slow_report
+        || options.sessions
+        || options.xa_report
+        || options.group_commit_report
+        || options.digest
+        || options.show_table_structure
+        || options.ddl_only
+        || options.table_checksums
+        || options.check_timestamps);
+
+    let mut output_limiter = OutputLimiter::new(&options.limit);
+    let mut missing_table_maps = MissingTableMapReport::new();
+
+    let mut ddl_extractor = DdlExtractor::new(options.ddl_include_grants);
+
+    let mut grep_filter = match &options.grep {
+        Some(pattern) => Some(GrepFilter::new(
+            pattern,
+            &options.grep_columns,
+            options.grep_binary_hex,
+        )?),
+        None => None,
+    };
+
+    let mut table_structure_tracker = TableStructureTracker::new();
+
+    let mut transaction_tracker = TransactionTracker::new();
+    let mut transactions = Vec::new();
+
+    let mut impact_analyzer = ImpactAnalyzer::new();
+
+    let mut histogram = options
+        .histogram
+        .map(|width| Histogram::new(width, options.histogram_by));
+
+    // 没有指定时报告执行时间至少1秒的语句
+    let mut slow_report = options
+        .slow_report
+        .then(|| SlowReport::new(options.min_exec_time.unwrap_or(1)));
+
+    let mut table_checksums = options.table_checksums.then(TableChecksums::new);
+
+    let mut session_report = options.sessions.then(SessionReport::new);
+
+    let mut xa_report = options.xa_report.then(XaReport::new);
+
+    let mut group_commit_report = options.group_commit_report.then(GroupCommitReport::new);
+
+    let mut digest_report = options.digest.then(DigestReport::new);
+
+    let mut timestamp_checker = TimestampChecker::new(
+        options.timestamp_tolerance.unwrap_or(DEFAULT_TOLERANCE),
+        options.max_timestamp_jump.unwrap_or(DEFAULT_MAX_JUMP),
+    );
+
+    let mut binlog_base64_writer = BinlogBase64Writer::new();
+    binlog_base64_writer.replayable(options.replayable);
+    if options.output == OutputFormat::BinlogBase64 {
+        print!("{}", binlog_base64_writer.header());
+    }
+
+    // --watch时按序号、--index时按索引文件中的顺序依次处理文件，GTID、table map等状态在文件之间延续
+    let binlog_files: Box<dyn Iterator<Item = Result<String, BoxedError>>> =
+        match (&options.watch_dir, &options.index_file_path) {
+            (Some(dir), _) => Box::new(
+                BinlogWatcher::new(
+                    dir,
+                    resume_checkpoint.as_ref().map(|v| v.file_name.as_str()),
+                )?
+                .on_diagnostic(print_diagnostic),
+            ),
+            (None, Some(index_file_path)) => {
+                let mut file_paths = read_binlog_index(index_file_path)?;
+
+                // 从checkpoint继续时，它之前的文件已经处理过了
+                if let Some(checkpoint) = &resume_checkpoint {
+                    let start = file_paths
+                        .iter()
+                        .position(|v| get_file_name(v) == checkpoint.file_name)
+                        .ok_or_else(|| {
+                            MyError(format!(
+                                "`{}` in the checkpoint is not listed in `{}`",
+                                checkpoint.file_name, index_file_path
+                            ))
+                        })?;
+                    file_paths.drain(..start);
+                }
+
+                Box::new(file_paths.into_iter().map(Ok))
+            }
+            (None, None) => Box::new(std::iter::once(Ok(binlog_file_path.clone()))),
         };
 
-        table_structs
-            .entry(event_body.table_id)
-            .or_insert(event_body);
-
-        let header = get_event_header(&mut file, offset)?;
-        println!("{:#?}", header);
-        offset += EVENT_HEADER_LENGTH as u64;
-
-        let body = get_event_body(
-            &mut file,
-            offset,
-            header.event_length,
-            header.type_code,
-            &mut table_structs,
-        )?;
-        println!("{:#?}", body);
-
-        Ok(())
-    } else {
+    // --analyze结束时输出解析的速度
+    let started_at = Instant::now();
+    let mut parsed_bytes: u64 = 0;
+
+    let mut checksum_verifier = options.verify_checksums.then(ChecksumVerifier::new);
+
+    let mut relay_log_tracker = options.relay_log.then(RelayLogTracker::new);
+    let mut continuity_checker = options.verify_continuity.then(ContinuityChecker::new);
+
+    for binlog_file_path in binlog_files {
+        let binlog_file_path = binlog_file_path?;
+
         let mut offset: u64 = 0;
 
-        let mut file = get_file(&binlog_file_path)?;
+        // 顺序读取，只有跳过或者回头读取事件时才真正seek
+        let mut file = match options.mmap {
+            #[cfg(feature = "mmap")]
+            true => BinlogReader::open_mmap(&binlog_file_path)?,
+            _ => BinlogReader::open(&binlog_file_path)?,
+        };
 
         let is_binlog_file = check_file_magic_number(&mut file)?;
 
         if !is_binlog_file {
-            panic!("this is not a binglog file");
+            return Err(Box::new(ParseError::not_binlog(&binlog_file_path)));
+        }
+
+        let file_length = file.file_length()?;
+
+        // 以不完整的事件结束时，按它区分是还在写的文件还是损坏的文件
+        let in_use = is_binlog_in_use(&mut file, file_length)?;
+
+        let file_name = get_file_name(&binlog_file_path);
+
+        if let Some(continuity_checker) = continuity_checker.as_mut() {
+            continuity_checker.start_file(&file_name);
+        }
+
+        if let Some(xa_report) = xa_report.as_mut() {
+            xa_report.start_file(&file_name);
         }
 
-        let file_length = (&file).metadata()?.len();
+        if let Some(group_commit_report) = group_commit_report.as_mut() {
+            group_commit_report.start_file(&file_name);
+        }
+
+        timestamp_checker.start_file(&file_name);
 
         offset += 4;
 
-        let mut table_structs: HashMap<u64, EventBodyTypeCode19> = HashMap::new();
+        if let Some(checkpoint) = &resume_checkpoint {
+            if checkpoint.file_name == file_name && checkpoint.position > offset {
+                offset = checkpoint.position;
+            }
+        }
+
+        debezium_converter.set_file(&binlog_file_path);
+        maxwell_converter.set_file(&binlog_file_path);
+
+        // 到达stop条件时最后一个完整事务的边界
+        let mut last_checkpoint = None;
+        let mut stopped = false;
+
+        pipeline.start_file();
+
+        // 预读的结果按文件中的位置保存，所以每个文件单独一个
+        // 压缩的文件预读之后回到原来的位置需要重新解压，所以顺序解码
+        let mut parallel_decoder = match options.decode_threads {
+            1 => None,
+            _ if file.compression().is_some() => {
+                print_diagnostic(Diagnostic::Warning(format!(
+                    "`{}` is compressed, `--decode-threads` is ignored for it",
+                    binlog_file_path
+                )));
+                None
+            }
+            threads => Some(ParallelDecoder::new(
+                threads,
+                &options.event_types,
+                options.value_options(),
+            )),
+        };
 
-        loop {
-            let header = get_event_header(&mut file, offset)?;
-            println!("{:#?}", header);
+        while offset < file_length {
+            // 压缩的文件回头读取时从头重新解压的警告
+            file.take_diagnostics().into_iter().for_each(print_diagnostic);
+
+            let event_position = offset;
+
+            let header = match options.relay_log {
+                true => get_relay_log_event_header(&mut file, offset, file_length, in_use),
+                false => get_checked_event_header(&mut file, offset, file_length, in_use),
+            };
+            let header = match header {
+                Ok(header) => header,
+                // `--skip-errors`时从下一个字节开始查找完整的事件，找不到时这个文件结束
+                Err(error)
+                    if options.skip_errors
+                        && ParseError::from_error(error.as_ref()).category
+                            == ErrorCategory::Corruption =>
+                {
+                    let error = ParseError::from_error(error.as_ref());
+                    print_diagnostic(Diagnostic::Warning(format!("skipped the event: {}", error)));
+                    error_report.skip(error);
+
+                    match find_next_valid_event(
+                        &mut file,
+                        event_position + 1,
+                        file_length,
+                        pipeline.checksum_length > 0,
+                    )? {
+                        Some(position) => {
+                            offset = position;
+                            continue;
+                        }
+                        None => break,
+                    }
+                }
+                Err(error) => return Err(error),
+            };
             offset += EVENT_HEADER_LENGTH as u64;
 
-            let body = get_event_body(
-                &mut file,
-                offset,
-                header.event_length,
-                header.type_code,
-                &mut table_structs,
-            )?;
-            println!("{:#?}", body);
-            offset = header.next_event_position as u64;
+            check_event_size(&header, event_position, options.max_event_size)?;
+
+            let checksum_error = match checksum_verifier.as_mut() {
+                Some(checksum_verifier) => checksum_verifier
+                    .verify(&mut file, event_position, &header)
+                    .err(),
+                None => None,
+            };
+            // `--skip-errors`时校验和错误的事件不解码
+            let mut checksum_error = match checksum_error {
+                Some(error) if !options.skip_errors => return Err(error),
+                error => error.map(|v| ParseError::from_error(v.as_ref())),
+            };
+            parsed_bytes += header.event_length as u64;
+
+            // `--events`过滤掉的事件不读取body，直接跳到下一个事件，`--check-timestamps`只需要事件头
+            // relay log中主库的文件名只在rotate中，FDE中有写binlog的服务端，所以总是读取
+            let skip_body = !(event_type_filter.needs_body(header.type_code)
+                && !options.check_timestamps
+                || options.relay_log && header.type_code == 4
+                || options.verify_continuity && matches!(header.type_code, 4 | 162 | 163)
+                || header.type_code == 15);
+
+            // 对应的table map还没有出现过时，用EventBodyUndecodableRows代替
+            let missing_table_id = match (options.report_missing_table_maps, header.type_code) {
+                (true, 23..=25 | 30..=32) if !skip_body => {
+                    Some(get_rows_event_table_id(&mut file, offset)?)
+                        .filter(|v| !pipeline.table_structs.contains_key(v))
+                }
+                _ => None,
+            };
+
+            if options.trace {
+                trace::start();
+            }
+
+            let body: Result<Box<dyn EventBody>, ParseError> = match missing_table_id {
+                _ if checksum_error.is_some() => Err(checksum_error.take().unwrap()),
+                None if skip_body => Ok(Box::new(EventBodyTypeSkip(header.type_code))),
+                Some(table_id) => {
+                    missing_table_maps.record(
+                        &file_name,
+                        table_id,
+                        event_position,
+                        header.event_length,
+                    );
+                    Ok(Box::new(EventBodyUndecodableRows {
+                        table_id,
+                        bytes: header.event_length,
+                    }))
+                }
+                None => match decode_row_values {
+                    true => match parallel_decoder.as_mut() {
+                        Some(parallel_decoder) => parallel_decoder.get_event_body(
+                            &mut file,
+                            file_length,
+                            event_position,
+                            &header,
+                            &mut pipeline.table_structs,
+                            pipeline.checksum_length,
+                        ),
+                        None => get_event_body_with_value_options(
+                            &mut file,
+                            event_position,
+                            &header,
+                            &mut pipeline.table_structs,
+                            pipeline.checksum_length,
+                            options.value_options(),
+                        ),
+                    },
+                    false => get_event_body_without_row_values(
+                        &mut file,
+                        event_position,
+                        &header,
+                        &mut pipeline.table_structs,
+                        pipeline.checksum_length,
+                    ),
+                }
+                .map_err(|e| {
+                    ParseError::new(ErrorCategory::Corruption, e.to_string())
+                        .at(event_position, Some(header.type_code))
+                }),
+            };
+            let traced_fields = match options.trace {
+                true => trace::finish(),
+                false => Vec::new(),
+            };
 
-            println!();
-            println!();
+            let body = match body {
+                Ok(body) => body,
+                // `--skip-errors`时记录错误，用EventBodyUndecoded代替，继续下一个事件
+                Err(error) if options.skip_errors => {
+                    print_diagnostic(Diagnostic::Warning(format!("skipped the event: {}", error)));
+                    let reason = error.message.clone();
+                    error_report.skip(error);
 
-            if offset >= file_length {
-                println!("It's the end of file");
+                    Box::new(EventBodyUndecoded {
+                        bytes: header.event_length,
+                        reason,
+                    })
+                }
+                Err(error) => return Err(Box::new(error)),
+            };
+            // relay log中的next_event_position是主库文件中的位置
+            offset = match options.relay_log {
+                true => event_position + header.event_length as u64,
+                false => header.next_event_position as u64,
+            };
+
+            let master_position = relay_log_tracker
+                .as_mut()
+                .and_then(|v| v.process(&header, body.as_ref()));
+
+            // 这个事件自己的校验和长度，FDE改变的是之后的事件
+            let event_checksum_length = pipeline.update(body.as_ref(), &mut print_diagnostic);
+
+            // 和上一个文件不连续时提示，`--strict`时报错退出
+            if let Some(continuity_checker) = continuity_checker.as_mut() {
+                let issues = continuity_checker.process(&header, event_position, body.as_ref());
+                for issue in issues {
+                    if options.strict {
+                        return Err(Box::new(issue));
+                    }
+
+                    print_diagnostic(Diagnostic::Warning(issue.to_string()));
+                    error_report.push(issue);
+                }
+            }
+
+            // GTID事件的起始位置是事务的边界，在它之前的事件都已经处理完了
+            let checkpoint = match header.type_code {
+                162 => Some(Checkpoint {
+                    file_name: file_name.clone(),
+                    position: event_position,
+                }),
+                _ => None,
+            };
+
+            if stop_filter.is_reached(&header, event_position, body.as_ref()) {
+                last_checkpoint = checkpoint.or(last_checkpoint);
+                stopped = true;
                 break;
             }
+
+            if checkpoint.is_some() {
+                last_checkpoint = checkpoint.clone();
+            }
+
+            // 过滤之前的所有事件都计入
+            if let Some(metrics) = &metrics {
+                metrics.record_event(
+                    &header,
+                    &file_name,
+                    offset,
+                    body.as_ref(),
+                    &pipeline.table_structs,
+                );
+            }
+
+            // 和metrics一样，过滤之前的所有事件都检查
+            timestamp_checker.process(&header, event_position);
+
+            // 跳过的事件不是GTID，不影响domain过滤和checkpoint
+            if skip_body {
+                continue;
+            }
+
+            // 同一张表的定义在中途变化时（ALTER TABLE）提示，按原来的库名，所有的输出方式都有
+            // 默认跳过还不支持的内容和不是FDE中的服务端会写的内容，结束时汇总，`--strict`时直接报错退出
+            let issue =
+                pipeline.check(&header, event_position, body.as_ref(), &mut print_diagnostic);
+            if let (Some(metrics), Ok(Some(issue)) | Err(issue)) = (&metrics, &issue) {
+                metrics.record_error(issue);
+            }
+            if let Some(issue) = issue? {
+                error_report.push(issue);
+            }
+
+            // table map即使被过滤掉也已经记录在table_structs里了
+            let accepted = domain_filter.accept(&header, body.as_ref())
+                && event_type_filter.accept(header.type_code);
+            let body = domain_filter.filter_gtid_list(body);
+            // 按原来的值匹配，所以在遮盖字段之前，没有剩下的行的事件不输出
+            let (body, rows_matched) = row_filter.filter_body(body, &pipeline.table_structs)?;
+            let accepted = accepted && rows_matched;
+            // 按原来的库名匹配，所以在改写库名之前
+            let body = column_masker.mask_body(body)?;
+            let body = column_selector.select_body(body, &pipeline.table_structs)?;
+            let body = database_rewriter.rewrite_body(body);
+
+            let ddl_statement = ddl_extractor.process(&header, event_position, body.as_ref());
+
+            #[cfg(feature = "kafka")]
+            if let Some(kafka_sink) = kafka_sink.as_mut() {
+                // 先转换，转换失败时直接返回错误，不会记录这个事件的checkpoint，也不会只发送一部分行
+                let envelopes = match (accepted, options.ddl_only) {
+                    (false, _) => Vec::new(),
+                    (true, true) => ddl_statement
+                        .iter()
+                        .map(|v| debezium_converter.schema_change(v))
+                        .collect(),
+                    (true, false) => debezium_converter.process(
+                        &header,
+                        event_position,
+                        body.as_ref(),
+                        database_rewriter.table_structs(&pipeline.table_structs),
+                    )?,
+                };
+
+                if let Some(checkpoint) = checkpoint {
+                    kafka_sink.mark_checkpoint(checkpoint);
+                }
+
+                for envelope in &envelopes {
+                    kafka_sink.send(envelope)?;
+                }
+
+                if let (Some(checkpoint_writer), Some(checkpoint)) = (
+                    checkpoint_writer.as_mut(),
+                    kafka_sink.take_acknowledged_checkpoint()?,
+                ) {
+                    checkpoint_writer.save(&checkpoint)?;
+                }
+
+                continue;
+            }
+
+            #[cfg(feature = "apply")]
+            if let Some(applier) = applier.as_mut() {
+                if accepted {
+                    let result = applier.process(
+                        &header,
+                        event_position,
+                        body.as_ref(),
+                        database_rewriter.table_structs(&pipeline.table_structs),
+                    );
+                    applier
+                        .take_diagnostics()
+                        .into_iter()
+                        .for_each(print_diagnostic);
+                    result?;
+                }
+
+                // 之前的事务已经提交，不立即保存的话重启后会重复执行
+                if let (Some(checkpoint_writer), Some(checkpoint)) =
+                    (checkpoint_writer.as_mut(), checkpoint)
+                {
+                    checkpoint_writer.save_now(&checkpoint)?;
+                }
+
+                continue;
+            }
+
+            if let (Some(checkpoint_writer), Some(checkpoint)) =
+                (checkpoint_writer.as_mut(), checkpoint)
+            {
+                checkpoint_writer.save(&checkpoint)?;
+            }
+
+            // 可以同时使用多个汇总，每个都处理所有的事件，结束时依次输出
+            if accepted && options.analyze {
+                if let Some(impact) = impact_analyzer.process(
+                    &header,
+                    event_position,
+                    body.as_ref(),
+                    database_rewriter.table_structs(&pipeline.table_structs),
+                ) {
+                    match options.output {
+                        OutputFormat::Json => println!("{}", serde_json::to_string(&impact)?),
+                        _ => println!("{}", impact),
+                    }
+                }
+            }
+
+            if let (true, Some(histogram)) = (accepted, histogram.as_mut()) {
+                histogram.process(
+                    &header,
+                    body.as_ref(),
+                    database_rewriter.table_structs(&pipeline.table_structs),
+                );
+            }
+
+            if let (true, Some(slow_report)) = (accepted, slow_report.as_mut()) {
+                slow_report.process(&header, event_position, body.as_ref());
+            }
+
+            if let (true, Some(table_checksums)) = (accepted, table_checksums.as_mut()) {
+                table_checksums.process(
+                    body.as_ref(),
+                    database_rewriter.table_structs(&pipeline.table_structs),
+                );
+            }
+
+            if let (true, Some(session_report)) = (accepted, session_report.as_mut()) {
+                session_report.process(
+                    &header,
+                    event_position,
+                    body.as_ref(),
+                    database_rewriter.table_structs(&pipeline.table_structs),
+                );
+            }
+
+            if let (true, Some(xa_report)) = (accepted, xa_report.as_mut()) {
+                xa_report.process(&header, event_position, body.as_ref());
+            }
+
+            if let (true, Some(group_commit_report)) = (accepted, group_commit_report.as_mut()) {
+                group_commit_report.process(
+                    &header,
+                    event_position,
+                    body.as_ref(),
+                    database_rewriter.table_structs(&pipeline.table_structs),
+                );
+            }
+
+            if let (true, Some(digest_report)) = (accepted, digest_report.as_mut()) {
+                digest_report.process(&header, body.as_ref());
+            }
+
+            if accepted && options.transactions {
+                let table_structs = database_rewriter.table_structs(&pipeline.table_structs);
+                let finished = match options.hash {
+                    Some(algorithm) => {
+                        let raw_event =
+                            get_event_raw(&mut file, event_position, header.event_length)?;
+                        transaction_tracker.process_with_hash(
+                            &header,
+                            event_position,
+                            body.as_ref(),
+                            table_structs,
+                            algorithm,
+                            &event_hash(algorithm, &raw_event, event_checksum_length > 0),
+                        )
+                    }
+                    None => transaction_tracker.process(
+                        &header,
+                        event_position,
+                        body.as_ref(),
+                        table_structs,
+                    ),
+                };
+                transactions.extend(finished);
+            }
+
+            if accepted && options.show_table_structure {
+                if let Some(statement) = table_structure_tracker.process(body.as_ref())? {
+                    match options.output {
+                        OutputFormat::Json => {
+                            println!(
+                                "{}",
+                                json!({"position": event_position, "statement": statement})
+                            );
+                        }
+                        _ => {
+                            println!("-- at {}", event_position);
+                            println!("{}", statement);
+                            println!();
+                        }
+                    }
+                }
+            }
+
+            if accepted && options.ddl_only {
+                if let Some(statement) = &ddl_statement {
+                    match options.output {
+                        OutputFormat::Human | OutputFormat::Table => {
+                            println!("{:#?}", statement);
+
+                            println!();
+                            println!();
+                        }
+                        OutputFormat::Debezium => {
+                            println!("{}", debezium_converter.schema_change(statement));
+                        }
+                        OutputFormat::Maxwell => {
+                            println!("{}", maxwell_converter.schema_change(statement));
+                        }
+                        OutputFormat::Canal => {
+                            println!("{}", canal_converter.schema_change(statement));
+                        }
+                        OutputFormat::BinlogBase64 => {
+                            print!("{}", binlog_base64_writer.ddl(statement));
+                        }
+                        OutputFormat::Json => {
+                            println!("{}", serde_json::to_string(statement)?);
+                        }
+                        OutputFormat::Sqlite => {
+                            #[cfg(feature = "sqlite")]
+                            if let Some(sqlite_writer) = sqlite_writer.as_mut() {
+                                sqlite_writer.ddl(statement)?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if accepted && event_output {
+                let mut events = Vec::new();
+
+                // grep只输出匹配的事件，之前先补上它所在事务的GTID和table map
+                if let Some(grep_filter) = grep_filter.as_mut() {
+                    let context = match grep_filter.process(
+                        &header,
+                        event_position,
+                        body.as_ref(),
+                        &pipeline.table_structs,
+                    ) {
+                        Some(context) => context,
+                        None => continue,
+                    };
+
+                    for context_position in context {
+                        let context_header = get_event_header(&mut file, context_position)?;
+                        let context_body = get_event_body(
+                            &mut file,
+                            context_position,
+                            &context_header,
+                            &mut pipeline.table_structs,
+                            pipeline.checksum_length,
+                        )?;
+
+                        let context_body = column_masker.mask_body(context_body)?;
+                        let context_body =
+                            column_selector.select_body(context_body, &pipeline.table_structs)?;
+                        let context_master_position = relay_log_tracker
+                            .as_ref()
+                            .and_then(|v| v.position_of(&context_header));
+                        events.push((
+                            context_position,
+                            context_header,
+                            database_rewriter.rewrite_body(context_body),
+                            context_master_position,
+                        ));
+                    }
+                }
+
+                events.push((event_position, header, body, master_position));
+
+                for (event_position, header, body, master_position) in events {
+                    let body = match output_limiter
+                        .limit_body(body, database_rewriter.table_structs(&pipeline.table_structs))
+                    {
+                        Some(body) => body,
+                        None => continue,
+                    };
+
+                    let event_hash = match options.hash {
+                        Some(algorithm) => {
+                            let raw_event =
+                                get_event_raw(&mut file, event_position, header.event_length)?;
+                            Some(to_hex(&event_hash(algorithm, &raw_event, event_checksum_length > 0)))
+                        }
+                        None => None,
+                    };
+
+                    if let Some(per_table_writer) = per_table_writer.as_mut() {
+                        if per_table_writer.process(
+                            &header,
+                            event_position,
+                            body.as_ref(),
+                            database_rewriter.table_structs(&pipeline.table_structs),
+                            event_hash.as_deref(),
+                        )? {
+                            output_limiter.count_event();
+                        }
+                        continue;
+                    }
+
+                    if let Some(rows_only_formatter) = rows_only_formatter.as_mut() {
+                        let lines = rows_only_formatter.process(
+                            &header,
+                            body.as_ref(),
+                            database_rewriter.table_structs(&pipeline.table_structs),
+                        );
+                        if !lines.is_empty() {
+                            output_limiter.count_event();
+                        }
+                        for line in lines {
+                            println!("{}", line);
+                        }
+                        continue;
+                    }
+
+                    // `--limit`只计算实际输出了内容的事件，table map、GTID这些只更新上下文的不算
+                    let emitted = match options.output {
+                        OutputFormat::Human => {
+                            if let Some(event_formatter) = event_formatter.as_mut() {
+                                println!(
+                                    "{}",
+                                    event_formatter.process(
+                                        &header,
+                                        event_position,
+                                        body.as_ref(),
+                                        database_rewriter.table_structs(&pipeline.table_structs),
+                                    )
+                                );
+                                output_limiter.count_event();
+                                continue;
+                            }
+
+                            print!("{}", styler.event(&header, body.as_ref()));
+
+                            if let Some(original) = database_rewriter
+                                .original_database_name(body.as_ref(), &pipeline.table_structs)
+                            {
+                                println!();
+                                print!("rewritten from database `{}`", original);
+                            }
+
+                            if let Some(column_names) = rows_event_column_names(
+                                body.as_ref(),
+                                database_rewriter.table_structs(&pipeline.table_structs),
+                            ) {
+                                println!();
+                                print!("column names: {}", column_names.join(", "));
+                            }
+
+                            if let Some(master_position) = &master_position {
+                                println!();
+                                print!("master position: {}", master_position);
+                            }
+
+                            if options.hexdump {
+                                let raw_event = get_event_raw(
+                                    &mut file,
+                                    event_position,
+                                    header.event_length,
+                                )?;
+
+                                // `--trace`时标出解码器实际读取的字段
+                                let layout = match options.trace {
+                                    true => traced_layout(
+                                        &raw_event,
+                                        event_checksum_length,
+                                        &traced_fields,
+                                    ),
+                                    false => event_layout(&raw_event, event_checksum_length),
+                                };
+
+                                println!();
+                                print!("{}", hexdump(event_position, &raw_event, &layout));
+                            }
+
+                            println!();
+                            println!();
+                            true
+                        }
+                        OutputFormat::Debezium => {
+                            let envelopes = debezium_converter.process(
+                                &header,
+                                event_position,
+                                body.as_ref(),
+                                database_rewriter.table_structs(&pipeline.table_structs),
+                            )?;
+                            let emitted = !envelopes.is_empty();
+                            for mut envelope in envelopes {
+                                if let Some(event_hash) = &event_hash {
+                                    envelope["payload"]["source"]["event_hash"] =
+                                        json!(event_hash);
+                                }
+
+                                println!("{}", envelope);
+                            }
+                            emitted
+                        }
+                        OutputFormat::Maxwell => {
+                            let rows = maxwell_converter.process(
+                                &header,
+                                event_position,
+                                body.as_ref(),
+                                database_rewriter.table_structs(&pipeline.table_structs),
+                            );
+                            let emitted = !rows.is_empty();
+                            for row in rows {
+                                println!("{}", row);
+                            }
+                            emitted
+                        }
+                        OutputFormat::Table => {
+                            print!(
+                                "{}",
+                                table_renderer.process(
+                                    &header,
+                                    event_position,
+                                    body.as_ref(),
+                                    database_rewriter.table_structs(&pipeline.table_structs),
+                                )
+                            );
+                            true
+                        }
+                        OutputFormat::Canal => {
+                            match canal_converter.process(
+                                &header,
+                                body.as_ref(),
+                                database_rewriter.table_structs(&pipeline.table_structs),
+                            ) {
+                                Some(message) => {
+                                    println!("{}", message);
+                                    true
+                                }
+                                None => false,
+                            }
+                        }
+                        OutputFormat::BinlogBase64 => {
+                            let raw_event =
+                                get_event_raw(&mut file, event_position, header.event_length)?;
+
+                            match binlog_base64_writer.process(
+                                &header,
+                                event_position,
+                                body.as_ref(),
+                                &raw_event,
+                            ) {
+                                Some(statement) => {
+                                    print!("{}", statement);
+                                    true
+                                }
+                                None => false,
+                            }
+                        }
+                        OutputFormat::Json => {
+                            let mut event = json!({
+                                "position": event_position,
+                                "header": header,
+                                "body": body,
+                            });
+
+                            // 只有带Q_HRNOW的query事件有微秒
+                            let event_time = EventTime::new(&header, body.as_ref());
+                            if event_time.microseconds.is_some() {
+                                event["event_time"] = json!(event_time.to_string());
+                            }
+
+                            if let Some(original) = database_rewriter
+                                .original_database_name(body.as_ref(), &pipeline.table_structs)
+                            {
+                                event["original_database_name"] = json!(original);
+                            }
+
+                            if let Some(column_names) = rows_event_column_names(
+                                body.as_ref(),
+                                database_rewriter.table_structs(&pipeline.table_structs),
+                            ) {
+                                event["column_names"] = json!(column_names);
+                            }
+
+                            if options.relay_log {
+                                event["master_position"] = json!(master_position);
+                            }
+
+                            if let Some(event_hash) = &event_hash {
+                                event["event_hash"] = json!(event_hash);
+                            }
+
+                            if options.keep_raw {
+                                event["raw"] = json!(RawEvent::read(
+                                    &mut file,
+                                    event_position,
+                                    header.event_length
+                                )?);
+                            }
+
+                            println!("{}", event);
+                            true
+                        }
+                        OutputFormat::Sqlite => {
+                            #[cfg(feature = "sqlite")]
+                            if let Some(sqlite_writer) = sqlite_writer.as_mut() {
+                                sqlite_writer.process(
+                                    &header,
+                                    event_position,
+                                    body.as_ref(),
+                                    database_rewriter.table_structs(&pipeline.table_structs),
+                                )?;
+                            }
+                            true
+                        }
+                    };
+
+                    if emitted {
+                        output_limiter.count_event();
+                    }
+                }
+
+                // 和stop条件一样正常结束，不再处理之后的事件和文件
+                if output_limiter.is_exhausted() {
+                    stopped = true;
+                    break;
+                }
+            }
         }
+        file.take_diagnostics().into_iter().for_each(print_diagnostic);
+
+        // 停止时只保存到最后一个完整事务的边界，之后的文件也不再处理
+        if stopped {
+            #[cfg(feature = "kafka")]
+            if let Some(kafka_sink) = kafka_sink.as_mut() {
+                kafka_sink.flush()?;
+            }
 
-        Ok(())
+            if let (Some(checkpoint_writer), Some(checkpoint)) =
+                (checkpoint_writer.as_mut(), &last_checkpoint)
+            {
+                checkpoint_writer.save_now(checkpoint)?;
+            }
+
+            break;
+        }
+
+        // --format和--rows-only时只有每个事件或者每一行的一行
+        if options.output == OutputFormat::Human
+            && !options.apply
+            && options.format.is_none()
+            && !options.rows_only
+            && options.per_table_output.is_none()
+            && offset >= file_length
+        {
+            println!("It's the end of file");
+        }
+
+        let end_of_file = Checkpoint {
+            file_name,
+            position: file_length,
+        };
+
+        #[cfg(feature = "kafka")]
+        if let Some(kafka_sink) = kafka_sink.as_mut() {
+            kafka_sink.mark_checkpoint(end_of_file.clone());
+            kafka_sink.flush()?;
+        }
+
+        if let Some(checkpoint_writer) = checkpoint_writer.as_mut() {
+            checkpoint_writer.save_now(&end_of_file)?;
+        }
+    }
+
+    // 和解析速度一样输出到stderr
+    if let Some(checksum_verifier) = &checksum_verifier {
+        eprintln!("{}", checksum_verifier);
+    }
+
+    #[cfg(feature = "apply")]
+    if let Some(applier) = applier.as_mut() {
+        let result = applier.finish();
+        applier
+            .take_diagnostics()
+            .into_iter()
+            .for_each(print_diagnostic);
+        return result;
+    }
+
+    if options.analyze {
+        if let Some(impact) = impact_analyzer.finish() {
+            match options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&impact)?),
+                _ => println!("{}", impact),
+            }
+        }
+
+        // 最后是所有事务的汇总
+        match options.output {
+            OutputFormat::Json => {
+                println!("{}", json!({"aggregate": impact_analyzer.report()}))
+            }
+            _ => println!("{}", impact_analyzer.report()),
+        }
+
+        // 输出到stderr，stdout的内容不受运行环境影响
+        let seconds = started_at.elapsed().as_secs_f64();
+        eprintln!(
+            "parsed {} bytes in {:.2}s ({:.1} MB/s)",
+            parsed_bytes,
+            seconds,
+            parsed_bytes as f64 / 1_000_000.0 / seconds.max(f64::EPSILON)
+        );
+    }
+
+    if let Some(histogram) = &histogram {
+        match options.output {
+            OutputFormat::Json => {
+                for bucket in histogram.buckets() {
+                    println!("{}", serde_json::to_string(bucket)?);
+                }
+            }
+            _ => print!("{}", histogram),
+        }
+    }
+
+    if let Some(slow_report) = &slow_report {
+        match options.output {
+            OutputFormat::Json => {
+                for group in slow_report.groups() {
+                    println!("{}", serde_json::to_string(group)?);
+                }
+            }
+            _ => print!("{}", slow_report),
+        }
+    }
+
+    if let Some(table_checksums) = &table_checksums {
+        match options.output {
+            OutputFormat::Json => {
+                for table in table_checksums.tables() {
+                    println!("{}", serde_json::to_string(table)?);
+                }
+            }
+            _ => print!("{}", table_checksums),
+        }
+    }
+
+    if let Some(session_report) = session_report.as_mut() {
+        session_report.finish();
+
+        match options.output {
+            OutputFormat::Json => {
+                for session in session_report.sessions() {
+                    println!("{}", serde_json::to_string(session)?);
+                }
+            }
+            _ => print!("{}", session_report),
+        }
     }
+
+    if let Some(xa_report) = &xa_report {
+        match options.output {
+            OutputFormat::Json => {
+                for entry in xa_report.dangling().chain(xa_report.unmatched()) {
+                    println!("{}", serde_json::to_string(entry)?);
+                }
+            }
+            _ => print!("{}", xa_report),
+        }
+    }
+
+    if options.transactions {
+        transactions.extend(transaction_tracker.finish());
+
+        sort_transactions(&mut transactions, options.transaction_sort);
+
+        for transaction in &transactions {
+            match options.output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(transaction)?),
+                _ => println!("{}", transaction),
+            }
+        }
+    }
+
+    if let Some(group_commit_report) = group_commit_report.as_mut() {
+        let summary = group_commit_report.finish();
+
+        match options.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string(&summary)?),
+            _ => print!("{}", summary),
+        }
+    }
+
+    if let Some(digest_report) = &digest_report {
+        match options.output {
+            OutputFormat::Json => {
+                for group in digest_report.groups() {
+                    println!("{}", serde_json::to_string(group)?);
+                }
+            }
+            _ => print!("{}", digest_report),
+        }
+    }
+
+    if options.check_timestamps {
+        match options.output {
+            OutputFormat::Json => {
+                for anomaly in timestamp_checker.anomalies() {
+                    println!("{}", serde_json::to_string(anomaly)?);
+                }
+            }
+            _ => print!("{}", timestamp_checker),
+        }
+    } else if !timestamp_checker.anomalies().is_empty() {
+        print_diagnostic(Diagnostic::Warning(format!(
+            "the timestamps of {} events go backwards by more than {}s or jump forward by more than {}s, see `--check-timestamps`",
+            timestamp_checker.anomalies().len(),
+            timestamp_checker.tolerance(),
+            timestamp_checker.max_jump()
+        )));
+    }
+
+    // 输出到stderr，不影响json等格式的输出
+    if !missing_table_maps.is_empty() {
+        eprint!("{}", missing_table_maps);
+    }
+
+    for table in output_limiter.sampled_tables() {
+        eprintln!(
+            "sampled {} of {} rows from {}",
+            table.kept_rows, table.total_rows, table.table
+        );
+    }
+
+    if let Some(per_table_writer) = per_table_writer.as_mut() {
+        per_table_writer.finish()?;
+    }
+
+    // 提交最后一批写入
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_writer) = sqlite_writer.as_mut() {
+        sqlite_writer.finish()?;
+    }
+
+    if options.output == OutputFormat::Maxwell {
+        for row in maxwell_converter.finish() {
+            println!("{}", row);
+        }
+    }
+
+    if options.output == OutputFormat::BinlogBase64 {
+        print!("{}", binlog_base64_writer.footer());
+    }
+
+    Ok(())
+}
+
+/// 库中的提示都输出到stderr
+fn print_diagnostic(diagnostic: Diagnostic) {
+    eprintln!("{}", diagnostic);
 }
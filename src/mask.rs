@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::model::*;
+use crate::table_structure::metadata_column_names;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+const REDACTED: &str = "REDACTED";
+
+// annotate事件中的SQL可能带有原始的值，打开--mask时整个替换掉
+const MASKED_SQL: &str = "/* masked */";
+
+/// `--mask`中字段值的处理方式，值为null时保持null
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaskStrategy {
+    Null,
+    /// SHA-256的十六进制，相同的值得到相同的结果
+    Hash,
+    /// 固定的字符串
+    Redact,
+    /// 和原来的值长度相同的星号，二进制数据按字节数
+    KeepLength,
+}
+
+impl MaskStrategy {
+    pub fn apply(self, value: &ColumnValue) -> ColumnValue {
+        let text = match value {
+            ColumnValue::Null | ColumnValue::Unsupported(_) => return value.clone(),
+            ColumnValue::Int(v) => v.to_string(),
+            ColumnValue::UInt(v) => v.to_string(),
+            ColumnValue::Float(v) => v.to_string(),
+            ColumnValue::Double(v) => v.to_string(),
+            ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => {
+                v.clone()
+            }
+            ColumnValue::RawTemporal(v) => v.to_string(),
+            ColumnValue::RawDecimal(v) => v.value.clone(),
+            ColumnValue::Bytes(v) => match self {
+                MaskStrategy::Hash => return ColumnValue::String(sha256_hex(v)),
+                MaskStrategy::KeepLength => return ColumnValue::String("*".repeat(v.len())),
+                _ => String::new(),
+            },
+        };
+
+        match self {
+            MaskStrategy::Null => ColumnValue::Null,
+            MaskStrategy::Hash => ColumnValue::String(sha256_hex(text.as_bytes())),
+            MaskStrategy::Redact => ColumnValue::String(REDACTED.to_string()),
+            MaskStrategy::KeepLength => ColumnValue::String("*".repeat(text.chars().count())),
+        }
+    }
+}
+
+/// `库名.表名.字段名:处理方式`，每一部分都可以使用%通配符，不区分大小写
+#[derive(Debug, Clone)]
+pub struct MaskRule {
+    pub database: String,
+    pub table: String,
+    pub column: String,
+    pub strategy: MaskStrategy,
+}
+
+impl MaskRule {
+    fn matches_table(&self, table_map: &EventBodyTypeCode19) -> bool {
+        like(&self.database, &table_map.database_name) && like(&self.table, &table_map.table_name)
+    }
+}
+
+/// 按`--mask`的规则替换行事件中解码后的值，修改前后的镜像都会处理
+/// 按原来的库名匹配（在--rewrite-db之前），字段名来自table map中的元数据
+#[derive(Default)]
+pub struct ColumnMasker {
+    rules: Vec<MaskRule>,
+    // table_id -> 每个字段的处理方式，在table map事件时确定
+    plans: HashMap<u64, Vec<Option<MaskStrategy>>>,
+}
+
+impl ColumnMasker {
+    pub fn new(rules: &[MaskRule]) -> ColumnMasker {
+        ColumnMasker {
+            rules: rules.to_vec(),
+            plans: HashMap::new(),
+        }
+    }
+
+    /// 每个事件都要经过这里，以便记录每张表要处理的字段
+    /// 需要按字段名匹配但table map中没有字段名时返回错误
+    pub fn mask_body(
+        &mut self,
+        body: Box<dyn EventBody>,
+    ) -> Result<Box<dyn EventBody>, BoxedError> {
+        if self.rules.is_empty() {
+            return Ok(body);
+        }
+
+        if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+            let plan = self.plan(table_map)?;
+            self.plans.insert(table_map.table_id, plan);
+            return Ok(body);
+        }
+
+        if body.downcast_ref::<EventBodyTypeCode160>().is_some() {
+            return Ok(Box::new(EventBodyTypeCode160 {
+                sql: MASKED_SQL.to_string(),
+            }));
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Ok(body),
+        };
+
+        let plan = match self.plans.get(&rows_event.table_id) {
+            Some(plan) if plan.iter().any(|v| v.is_some()) => plan,
+            _ => return Ok(body),
+        };
+
+        let mut rows_event = rows_event.clone();
+        for row in rows_event.rows.iter_mut() {
+            for image in row.before.iter_mut().chain(row.after.iter_mut()) {
+                for (value, strategy) in image.iter_mut().zip(plan) {
+                    if let Some(strategy) = strategy {
+                        *value = strategy.apply(value);
+                    }
+                }
+            }
+        }
+
+        // 原有的column_data是第一行的镜像，也要换成处理后的值
+        if let Some(row) = rows_event.rows.first() {
+            if let Some(image) = row.before.as_ref().or(row.after.as_ref()) {
+                rows_event.column_data = column_data_for_human(image);
+            }
+
+            if let (Some(_), Some(after)) = (&row.before, &row.after) {
+                rows_event.column_data_for_update = Some(column_data_for_human(after));
+            }
+        }
+
+        Ok(Box::new(rows_event))
+    }
+
+    // 同一个字段匹配多条规则时使用靠前的
+    fn plan(
+        &self,
+        table_map: &EventBodyTypeCode19,
+    ) -> Result<Vec<Option<MaskStrategy>>, BoxedError> {
+        let column_count = table_map.columns.len();
+
+        let rules = self
+            .rules
+            .iter()
+            .filter(|v| v.matches_table(table_map))
+            .collect::<Vec<&MaskRule>>();
+        if rules.is_empty() {
+            return Ok(vec![None; column_count]);
+        }
+
+        let names = metadata_column_names(table_map);
+        if names.is_none() && rules.iter().any(|v| v.column != "%") {
+            return Err(Box::new(MyError(format!(
+                "`--mask` needs the column names of `{}`.`{}`, please set binlog_row_metadata=FULL on the server",
+                table_map.database_name, table_map.table_name
+            ))));
+        }
+
+        Ok((0..column_count)
+            .map(|index| {
+                let name = names.as_ref().and_then(|v| v.get(index));
+                rules
+                    .iter()
+                    .find(|rule| rule.column == "%" || name.is_some_and(|v| like(&rule.column, v)))
+                    .map(|rule| rule.strategy)
+            })
+            .collect())
+    }
+}
+
+/// SQL中LIKE的%通配符，不支持_
+pub(crate) fn like(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+
+    let parts = pattern.split('%').collect::<Vec<&str>>();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+
+    // 第一段必须在开头，最后一段必须在结尾，中间的依次向后查找
+    let mut rest = match text.strip_prefix(parts[0]) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(parts[parts.len() - 1])
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::deal_type_code_23_to_25;
+
+    fn rule(target: &str, strategy: MaskStrategy) -> MaskRule {
+        let parts = target.split('.').collect::<Vec<&str>>();
+        MaskRule {
+            database: parts[0].to_string(),
+            table: parts[1].to_string(),
+            column: parts[2].to_string(),
+            strategy,
+        }
+    }
+
+    // shop.users(id, email, phone)，有字段名
+    fn users(with_names: bool) -> TableMapBuilder {
+        let mut optional_metadata = vec![4u8, 0];
+        for name in ["id", "email", "phone"] {
+            optional_metadata.push(name.len() as u8);
+            optional_metadata.extend(name.as_bytes());
+        }
+        optional_metadata[1] = (optional_metadata.len() - 2) as u8;
+
+        TableMapBuilder::new("shop", "users")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .optional_metadata(if with_names { &optional_metadata } else { &[] })
+    }
+
+    fn user(id: i64, email: &str, phone: Option<&str>) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(email.to_string()),
+            phone.map_or(ColumnValue::Null, |v| ColumnValue::String(v.to_string())),
+        ]
+    }
+
+    fn mask(
+        masker: &mut ColumnMasker,
+        table: &TableMapBuilder,
+        rows: &RowsEventBuilder,
+    ) -> Vec<RowsEventRow> {
+        let table_info = table.table_info().unwrap();
+        masker.mask_body(Box::new(table_info.clone())).unwrap();
+
+        let table_structs = HashMap::from([(7, table_info)]);
+        let body = deal_type_code_23_to_25(rows.body(), rows.type_code(), &table_structs).unwrap();
+        let body = masker.mask_body(body).unwrap();
+        body.downcast_ref::<EventBodyTypeCode23To25>()
+            .unwrap()
+            .rows
+            .clone()
+    }
+
+    #[test]
+    fn each_strategy() {
+        let email = ColumnValue::String("anne@example.com".to_string());
+
+        assert_eq!(MaskStrategy::Null.apply(&email), ColumnValue::Null);
+        assert_eq!(
+            MaskStrategy::Redact.apply(&email),
+            ColumnValue::String("REDACTED".to_string())
+        );
+        assert_eq!(
+            MaskStrategy::KeepLength.apply(&email),
+            ColumnValue::String("****************".to_string())
+        );
+        assert_eq!(
+            MaskStrategy::KeepLength.apply(&ColumnValue::String("名字".to_string())),
+            ColumnValue::String("**".to_string())
+        );
+        assert_eq!(
+            MaskStrategy::Hash.apply(&ColumnValue::String("abc".to_string())),
+            ColumnValue::String(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+            )
+        );
+        assert_eq!(
+            MaskStrategy::Hash.apply(&ColumnValue::Bytes(b"abc".to_vec())),
+            MaskStrategy::Hash.apply(&ColumnValue::String("abc".to_string()))
+        );
+        assert_eq!(
+            MaskStrategy::Hash.apply(&ColumnValue::Int(42)),
+            MaskStrategy::Hash.apply(&ColumnValue::String("42".to_string()))
+        );
+
+        // null仍然是null
+        for strategy in [
+            MaskStrategy::Null,
+            MaskStrategy::Hash,
+            MaskStrategy::Redact,
+            MaskStrategy::KeepLength,
+        ] {
+            assert_eq!(strategy.apply(&ColumnValue::Null), ColumnValue::Null);
+        }
+    }
+
+    #[test]
+    fn both_images_are_masked_and_others_untouched() {
+        let table = users(true);
+        let mut masker = ColumnMasker::new(&[
+            rule("shop.users.email", MaskStrategy::Redact),
+            rule("SHOP.user%.ph%", MaskStrategy::KeepLength),
+        ]);
+
+        let rows = mask(
+            &mut masker,
+            &table,
+            &RowsEventBuilder::update(&table).update_row(
+                user(1, "anne@example.com", Some("555-0100")),
+                user(1, "anne@example.org", None),
+            ),
+        );
+
+        assert_eq!(
+            rows[0].before,
+            Some(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String("REDACTED".to_string()),
+                ColumnValue::String("********".to_string()),
+            ])
+        );
+        assert_eq!(
+            rows[0].after,
+            Some(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String("REDACTED".to_string()),
+                ColumnValue::Null,
+            ])
+        );
+    }
+
+    #[test]
+    fn other_tables_are_untouched() {
+        let table = users(false);
+        let mut masker = ColumnMasker::new(&[rule("shop.orders.%", MaskStrategy::Null)]);
+
+        let rows = mask(
+            &mut masker,
+            &table,
+            &RowsEventBuilder::insert(&table).row(user(1, "anne@example.com", None)),
+        );
+        assert_eq!(rows[0].after, Some(user(1, "anne@example.com", None)));
+    }
+
+    #[test]
+    fn column_names_are_required() {
+        let table_info = users(false).table_info().unwrap();
+
+        // 所有字段都处理时不需要字段名
+        let mut masker = ColumnMasker::new(&[rule("shop.users.%", MaskStrategy::Null)]);
+        assert!(masker.mask_body(Box::new(table_info.clone())).is_ok());
+
+        let mut masker = ColumnMasker::new(&[rule("shop.users.email", MaskStrategy::Null)]);
+        let error = masker.mask_body(Box::new(table_info)).unwrap_err();
+        assert!(error.to_string().contains("binlog_row_metadata=FULL"));
+    }
+
+    #[test]
+    fn annotate_rows_sql_is_replaced() {
+        let mut masker = ColumnMasker::new(&[rule("shop.users.email", MaskStrategy::Null)]);
+        let body = masker
+            .mask_body(Box::new(EventBodyTypeCode160 {
+                sql: "INSERT INTO users VALUES (1, 'anne@example.com', NULL)".to_string(),
+            }))
+            .unwrap();
+        assert_eq!(
+            body.downcast_ref::<EventBodyTypeCode160>().unwrap().sql,
+            MASKED_SQL
+        );
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert!(like("shop", "SHOP"));
+        assert!(like("%", ""));
+        assert!(like("us%rs", "users"));
+        assert!(like("%_id", "user_id"));
+        assert!(like("a%b%c", "a-b-b-c"));
+        assert!(!like("a%b%c", "a-c-b"));
+        assert!(!like("user", "users"));
+    }
+}
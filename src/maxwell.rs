@@ -0,0 +1,449 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::{json, Map, Value};
+
+use crate::ddl::{ddl_target, DdlStatement};
+use crate::model::*;
+use crate::table_structure::primary_key_columns;
+use crate::util::get_file_name;
+
+/// 等待XID的一行变更，xid、xoffset和commit在提交时才能确定
+struct PendingRow {
+    database: String,
+    table: String,
+    row_type: &'static str,
+    ts: u32,
+    position: String,
+    server_id: u32,
+    thread_id: Option<u32>,
+    primary_key: Option<Vec<Value>>,
+    data: Value,
+    old: Option<Value>,
+}
+
+/// 将行事件转换成Maxwell格式的json，每行变更一个
+/// Maxwell的每一行都带有事务的xid，而XID事件在事务的最后，所以事务中的行会先缓存起来，提交时一起返回
+pub struct MaxwellConverter {
+    file_name: String,
+    current_thread_id: Option<u32>,
+    pending: Vec<PendingRow>,
+}
+
+impl MaxwellConverter {
+    pub fn new(binlog_file_path: &str) -> MaxwellConverter {
+        MaxwellConverter {
+            file_name: get_file_name(binlog_file_path),
+            current_thread_id: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// 处理多个文件时切换到下一个文件
+    pub fn set_file(&mut self, binlog_file_path: &str) {
+        self.file_name = get_file_name(binlog_file_path);
+    }
+
+    /// position是这个事件在文件中的起始位置
+    /// 只有事务提交时才会返回这个事务中的所有行，其他时候返回空的vec
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Vec<Value> {
+        // 上一个事务没有看到提交（例如被--grep过滤掉了），在新事务开始前返回，xid为null
+        if body.downcast_ref::<EventBodyTypeCode162>().is_some()
+            || body.downcast_ref::<EventBodyTypeCode33>().is_some()
+        {
+            self.current_thread_id = None;
+            return self.commit(None);
+        }
+
+        if let Some(xid_event) = body.downcast_ref::<EventBodyTypeCode16>() {
+            return self.commit(Some(xid_event.xid_transaction_number));
+        }
+
+        // 非事务引擎的事务以COMMIT语句结束，没有xid
+        if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+            self.current_thread_id = Some(query_event.id_of_thread);
+            if query_event.sql == "COMMIT" {
+                return self.commit(None);
+            }
+            return Vec::new();
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Vec::new(),
+        };
+
+        let table_info = match table_structs.get(&rows_event.table_id) {
+            Some(table_info) => table_info,
+            None => return Vec::new(),
+        };
+
+        let row_type = match header.type_code {
+            23 | 30 => "insert",
+            24 | 31 => "update",
+            _ => "delete",
+        };
+
+        let primary_key = primary_key_columns(table_info);
+
+        // delete只有修改前的值，insert和update使用修改后的值，update的old是改变了的字段修改前的值
+        let changes: Vec<(Row, Option<Value>)> = match header.type_code {
+            24 | 31 => match rows_event.pairs(table_info) {
+                Ok(pairs) => pairs
+                    .map(|(before, after)| {
+                        let mut old = Map::new();
+                        for index in before.changed_columns(&after) {
+                            // MINIMAL时before中没有这个字段，不知道原来的值
+                            if let (Some(name), Some(value)) =
+                                (after.column_index().name(index), before.get(index))
+                            {
+                                old.insert(name.to_string(), value.to_json());
+                            }
+                        }
+                        (after, Some(Value::Object(old)))
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            _ => rows_event
+                .rows(table_info)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|row| (row, None))
+                .collect(),
+        };
+
+        for (row, old) in changes {
+            self.pending.push(PendingRow {
+                database: table_info.database_name.clone(),
+                table: table_info.table_name.clone(),
+                row_type,
+                ts: header.timestamp,
+                position: format!("{}:{}", self.file_name, position),
+                server_id: header.server_id,
+                thread_id: self.current_thread_id,
+                primary_key: primary_key.as_ref().map(|columns| {
+                    columns
+                        .iter()
+                        .map(|v| row.get(*v).map(|v| v.to_json()).unwrap_or(Value::Null))
+                        .collect()
+                }),
+                data: row_to_json(&row),
+                old,
+            });
+        }
+
+        Vec::new()
+    }
+
+    /// 所有文件都处理完之后返回还没有看到提交的行
+    pub fn finish(&mut self) -> Vec<Value> {
+        self.commit(None)
+    }
+
+    /// DDL输出为Maxwell的schema change事件，没有表结构的定义
+    pub fn schema_change(&self, statement: &DdlStatement) -> Value {
+        let target = ddl_target(&statement.sql);
+
+        let object = match target.object.as_str() {
+            "DATABASE" | "SCHEMA" => "database",
+            _ => "table",
+        };
+        let change_type = match target.action.as_str() {
+            "CREATE" => format!("{}-create", object),
+            "DROP" => format!("{}-drop", object),
+            "ALTER" | "RENAME" | "TRUNCATE" => format!("{}-alter", object),
+            _ => "ddl".to_string(),
+        };
+
+        let database = match object {
+            "database" => target.name.clone(),
+            _ => target.database_name.clone(),
+        }
+        .unwrap_or_else(|| statement.database_name.clone());
+
+        json!({
+            "type": change_type,
+            "database": database,
+            "table": if object == "table" { target.name } else { None },
+            "ts": statement.timestamp,
+            "sql": statement.sql,
+            "position": format!("{}:{}", self.file_name, statement.position),
+        })
+    }
+
+    // 最后一行带commit，其他行带xoffset
+    fn commit(&mut self, xid: Option<u64>) -> Vec<Value> {
+        let count = self.pending.len();
+
+        self.pending
+            .drain(..)
+            .enumerate()
+            .map(|(index, row)| {
+                let mut object = Map::new();
+                object.insert("database".to_string(), json!(row.database));
+                object.insert("table".to_string(), json!(row.table));
+                object.insert("type".to_string(), json!(row.row_type));
+                object.insert("ts".to_string(), json!(row.ts));
+                object.insert("xid".to_string(), json!(xid));
+                if index + 1 == count {
+                    object.insert("commit".to_string(), json!(true));
+                } else {
+                    object.insert("xoffset".to_string(), json!(index));
+                }
+                object.insert("position".to_string(), json!(row.position));
+                object.insert("server_id".to_string(), json!(row.server_id));
+                if let Some(thread_id) = row.thread_id {
+                    object.insert("thread_id".to_string(), json!(thread_id));
+                }
+                if let Some(primary_key) = row.primary_key {
+                    object.insert("primary_key".to_string(), Value::Array(primary_key));
+                }
+                object.insert("data".to_string(), row.data);
+                if let Some(old) = row.old {
+                    object.insert("old".to_string(), old);
+                }
+
+                Value::Object(object)
+            })
+            .collect()
+    }
+}
+
+// 只有镜像中的字段
+fn row_to_json(row: &Row) -> Value {
+    let mut object = Map::new();
+
+    for (name, value) in row.columns() {
+        object.insert(name.to_string(), value.to_json());
+    }
+
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    // test.maxwell，主键是id，有字段名
+    fn maxwell_table() -> TableMapBuilder {
+        let mut optional_metadata = vec![4u8, 0];
+        for name in ["id", "daemon"] {
+            optional_metadata.push(name.len() as u8);
+            optional_metadata.extend(name.as_bytes());
+        }
+        optional_metadata[1] = (optional_metadata.len() - 2) as u8;
+        optional_metadata.extend([8, 1, 0]);
+
+        TableMapBuilder::new("test", "maxwell")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .optional_metadata(&optional_metadata)
+    }
+
+    fn daemon(id: i64, name: &str) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(id), ColumnValue::String(name.to_string())]
+    }
+
+    // 每个事务：GTID、BEGIN、table map、行事件、XID
+    fn transaction(
+        binlog: BinlogBuilder,
+        sequence: u64,
+        timestamp: u32,
+        rows: RowsEventBuilder,
+        xid: u64,
+    ) -> BinlogBuilder {
+        binlog
+            .push(gtid(sequence).server_id(23042).timestamp(timestamp))
+            .push(
+                EventBuilder::from_body(&QueryEventBuilder::new("test", "BEGIN").thread_id(108))
+                    .timestamp(timestamp)
+                    .server_id(23042),
+            )
+            .push(
+                EventBuilder::from_body(&maxwell_table())
+                    .timestamp(timestamp)
+                    .server_id(23042),
+            )
+            .push(
+                EventBuilder::from_body(&rows)
+                    .timestamp(timestamp)
+                    .server_id(23042),
+            )
+            .push(
+                EventBuilder::new(16, xid.to_le_bytes().to_vec())
+                    .timestamp(timestamp)
+                    .server_id(23042),
+            )
+    }
+
+    fn convert(binlog: &[u8]) -> (Vec<Value>, Vec<u64>) {
+        let mut converter = MaxwellConverter::new("/var/lib/mysql/master.000006");
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut table_structs = HashMap::new();
+        let mut rows = Vec::new();
+        let mut rows_positions = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            if matches!(header.type_code, 23..=25) {
+                rows_positions.push(position);
+            }
+            rows.extend(converter.process(&header, position, body.as_ref(), &table_structs));
+            position = header.next_event_position as u64;
+        }
+        rows.extend(converter.finish());
+
+        (rows, rows_positions)
+    }
+
+    // Maxwell文档中的insert和update，打开了output_binlog_position、output_server_id、
+    // output_thread_id和output_primary_keys
+    #[test]
+    fn golden_maxwell_rows() {
+        let table = maxwell_table();
+        let mut binlog = BinlogBuilder::new();
+        binlog = transaction(
+            binlog,
+            1,
+            1449786310,
+            RowsEventBuilder::insert(&table).row(daemon(1, "Stanislaw Lem")),
+            940752,
+        );
+        binlog = transaction(
+            binlog,
+            2,
+            1449786341,
+            RowsEventBuilder::update(&table)
+                .update_row(daemon(1, "Stanislaw Lem"), daemon(1, "Firebus!  Firebus!")),
+            940786,
+        );
+        binlog = transaction(
+            binlog,
+            3,
+            1449786400,
+            RowsEventBuilder::delete(&table)
+                .row(daemon(1, "Firebus!  Firebus!"))
+                .row(daemon(2, "Ursula K. Le Guin")),
+            940800,
+        );
+
+        let (rows, positions) = convert(&binlog.build());
+        let expected = vec![
+            json!({
+                "database": "test",
+                "table": "maxwell",
+                "type": "insert",
+                "ts": 1449786310,
+                "xid": 940752,
+                "commit": true,
+                "position": format!("master.000006:{}", positions[0]),
+                "server_id": 23042,
+                "thread_id": 108,
+                "primary_key": [1],
+                "data": {"id": 1, "daemon": "Stanislaw Lem"}
+            }),
+            json!({
+                "database": "test",
+                "table": "maxwell",
+                "type": "update",
+                "ts": 1449786341,
+                "xid": 940786,
+                "commit": true,
+                "position": format!("master.000006:{}", positions[1]),
+                "server_id": 23042,
+                "thread_id": 108,
+                "primary_key": [1],
+                "data": {"id": 1, "daemon": "Firebus!  Firebus!"},
+                "old": {"daemon": "Stanislaw Lem"}
+            }),
+            json!({
+                "database": "test",
+                "table": "maxwell",
+                "type": "delete",
+                "ts": 1449786400,
+                "xid": 940800,
+                "xoffset": 0,
+                "position": format!("master.000006:{}", positions[2]),
+                "server_id": 23042,
+                "thread_id": 108,
+                "primary_key": [1],
+                "data": {"id": 1, "daemon": "Firebus!  Firebus!"}
+            }),
+            json!({
+                "database": "test",
+                "table": "maxwell",
+                "type": "delete",
+                "ts": 1449786400,
+                "xid": 940800,
+                "commit": true,
+                "position": format!("master.000006:{}", positions[2]),
+                "server_id": 23042,
+                "thread_id": 108,
+                "primary_key": [2],
+                "data": {"id": 2, "daemon": "Ursula K. Le Guin"}
+            }),
+        ];
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn uncommitted_rows_have_no_xid() {
+        let table = maxwell_table();
+        let binlog = BinlogBuilder::new()
+            .push(gtid(1).server_id(23042))
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(daemon(1, "Stanislaw Lem")))
+            .build();
+
+        let (rows, _) = convert(&binlog);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["xid"], Value::Null);
+        assert_eq!(rows[0]["commit"], true);
+        assert!(rows[0].get("thread_id").is_none());
+    }
+
+    #[test]
+    fn schema_change_types() {
+        let converter = MaxwellConverter::new("master.000006");
+        let statement = |sql: &str| DdlStatement {
+            position: 120,
+            timestamp: 1449786500,
+            server_id: 23042,
+            database_name: "test".to_string(),
+            sql: sql.to_string(),
+            gtid: None,
+        };
+
+        let change = converter.schema_change(&statement("ALTER TABLE maxwell ADD COLUMN x INT"));
+        assert_eq!(change["type"], "table-alter");
+        assert_eq!(change["database"], "test");
+        assert_eq!(change["table"], "maxwell");
+        assert_eq!(change["position"], "master.000006:120");
+
+        let change = converter.schema_change(&statement("DROP DATABASE other"));
+        assert_eq!(change["type"], "database-drop");
+        assert_eq!(change["database"], "other");
+        assert_eq!(change["table"], Value::Null);
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::error::ParseError;
+use crate::model::*;
+use crate::util::get_event_type_name;
+
+#[derive(Default)]
+struct Metrics {
+    // 事件类型 -> 事件数
+    events: BTreeMap<String, u64>,
+    // (操作, `库名.表名`) -> 行数
+    rows: BTreeMap<(String, String), u64>,
+    // 推断的binlog_row_image -> 行事件数
+    row_images: BTreeMap<String, u64>,
+    bytes: u64,
+    // 错误分类 -> 次数
+    errors: BTreeMap<String, u64>,
+    current_file: Option<String>,
+    current_position: u64,
+    last_event: Option<Instant>,
+    // `--read-from-remote-server`时连接断开之后重连成功的次数
+    reconnects: u64,
+}
+
+/// 解析过程中的计数，和HTTP服务无关，库的使用者也可以自己调用render输出
+/// 可以在多个线程之间共享（比如放在Arc中）
+#[derive(Default)]
+pub struct MetricsRegistry {
+    metrics: Mutex<Metrics>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        MetricsRegistry::default()
+    }
+
+    /// 每个解析出来的事件都要经过这里（过滤之前），position是事件结束的位置
+    pub fn record_event(
+        &self,
+        header: &EventHeader,
+        file_name: &str,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        let mut metrics = self.metrics.lock().unwrap();
+
+        *metrics
+            .events
+            .entry(get_event_type_name(header.type_code).to_string())
+            .or_default() += 1;
+        metrics.bytes += header.event_length as u64;
+
+        if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+            let op = match header.type_code {
+                23 | 30 => "insert",
+                24 | 31 => "update",
+                _ => "delete",
+            };
+            let table = table_structs
+                .get(&rows_event.table_id)
+                .map(|v| format!("{}.{}", v.database_name, v.table_name))
+                .unwrap_or_default();
+
+            *metrics.rows.entry((op.to_string(), table)).or_default() += rows_event.number_of_rows;
+            *metrics
+                .row_images
+                .entry(rows_event.row_image.value.to_string())
+                .or_default() += 1;
+        }
+
+        if metrics.current_file.as_deref() != Some(file_name) {
+            metrics.current_file = Some(file_name.to_string());
+        }
+        metrics.current_position = position;
+        metrics.last_event = Some(Instant::now());
+    }
+
+    pub fn record_error(&self, error: &ParseError) {
+        let category = serde_json::to_value(error.category)
+            .ok()
+            .and_then(|v| v.as_str().map(|v| v.to_string()))
+            .unwrap_or_default();
+
+        *self
+            .metrics
+            .lock()
+            .unwrap()
+            .errors
+            .entry(category)
+            .or_default() += 1;
+    }
+
+    pub fn record_reconnect(&self) {
+        self.metrics.lock().unwrap().reconnects += 1;
+    }
+
+    /// Prometheus的文本格式
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().unwrap();
+        let mut result = String::new();
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_events_total",
+            "counter",
+            "Events parsed by type.",
+        );
+        for (event_type, count) in &metrics.events {
+            let _ = writeln!(
+                result,
+                "mariadb_binlog_events_total{{type=\"{}\"}} {}",
+                escape_label(event_type),
+                count
+            );
+        }
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_rows_total",
+            "counter",
+            "Rows decoded by operation and table.",
+        );
+        for ((op, table), count) in &metrics.rows {
+            let _ = writeln!(
+                result,
+                "mariadb_binlog_rows_total{{op=\"{}\",table=\"{}\"}} {}",
+                op,
+                escape_label(table),
+                count
+            );
+        }
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_rows_events_by_row_image_total",
+            "counter",
+            "Rows events by the binlog_row_image inferred from their column bitmaps.",
+        );
+        for (row_image, count) in &metrics.row_images {
+            let _ = writeln!(
+                result,
+                "mariadb_binlog_rows_events_by_row_image_total{{row_image=\"{}\"}} {}",
+                row_image, count
+            );
+        }
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_bytes_total",
+            "counter",
+            "Bytes of events processed.",
+        );
+        let _ = writeln!(result, "mariadb_binlog_bytes_total {}", metrics.bytes);
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_parse_errors_total",
+            "counter",
+            "Parse errors and skipped issues by category.",
+        );
+        for (category, count) in &metrics.errors {
+            let _ = writeln!(
+                result,
+                "mariadb_binlog_parse_errors_total{{category=\"{}\"}} {}",
+                category, count
+            );
+        }
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_reconnects_total",
+            "counter",
+            "Reconnects to the server after the dump connection was lost.",
+        );
+        let _ = writeln!(
+            result,
+            "mariadb_binlog_reconnects_total {}",
+            metrics.reconnects
+        );
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_current_position",
+            "gauge",
+            "End position of the last parsed event in the current file.",
+        );
+        if let Some(file_name) = &metrics.current_file {
+            let _ = writeln!(
+                result,
+                "mariadb_binlog_current_position{{file=\"{}\"}} {}",
+                escape_label(file_name),
+                metrics.current_position
+            );
+        }
+
+        write_header(
+            &mut result,
+            "mariadb_binlog_seconds_since_last_event",
+            "gauge",
+            "Seconds since the last event was parsed.",
+        );
+        if let Some(last_event) = metrics.last_event {
+            let _ = writeln!(
+                result,
+                "mariadb_binlog_seconds_since_last_event {:.3}",
+                last_event.elapsed().as_secs_f64()
+            );
+        }
+
+        result
+    }
+}
+
+fn write_header(result: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(result, "# HELP {} {}", name, help);
+    let _ = writeln!(result, "# TYPE {} {}", name, metric_type);
+}
+
+// label的值中反斜杠、双引号和换行需要转义
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::error::ErrorCategory;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    // 处理一个有两张表的文件中的所有事件
+    fn record_fixture(registry: &MetricsRegistry) {
+        let orders = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+        let users = TableMapBuilder::new("app", "users")
+            .table_id(8)
+            .column(FieldType::Long, &[]);
+        let binlog = BinlogBuilder::new()
+            .event(&orders)
+            .event(
+                &RowsEventBuilder::insert(&orders)
+                    .row(vec![ColumnValue::Int(1)])
+                    .row(vec![ColumnValue::Int(2)]),
+            )
+            .event(&users)
+            .event(&RowsEventBuilder::delete(&users).row(vec![ColumnValue::Int(3)]))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            position = header.next_event_position as u64;
+            registry.record_event(
+                &header,
+                "mysql-bin.000001",
+                position,
+                body.as_ref(),
+                &table_structs,
+            );
+        }
+    }
+
+    #[test]
+    fn series_over_a_fixture() {
+        let registry = MetricsRegistry::new();
+        assert!(!registry
+            .render()
+            .contains("mariadb_binlog_current_position{"));
+
+        record_fixture(&registry);
+        registry.record_error(&ParseError::new(
+            ErrorCategory::Corruption,
+            "bad".to_string(),
+        ));
+        registry.record_reconnect();
+
+        let text = registry.render();
+        for line in [
+            "# TYPE mariadb_binlog_events_total counter",
+            "mariadb_binlog_events_total{type=\"TABLE_MAP_EVENT\"} 2",
+            "mariadb_binlog_events_total{type=\"XID_EVENT\"} 1",
+            "mariadb_binlog_rows_total{op=\"insert\",table=\"shop.orders\"} 2",
+            "mariadb_binlog_rows_total{op=\"delete\",table=\"app.users\"} 1",
+            "mariadb_binlog_parse_errors_total{category=\"corruption\"} 1",
+            "mariadb_binlog_reconnects_total 1",
+            "# TYPE mariadb_binlog_current_position gauge",
+        ] {
+            assert!(text.lines().any(|v| v == line), "{}", line);
+        }
+        assert!(text.contains("mariadb_binlog_current_position{file=\"mysql-bin.000001\"} "));
+        assert!(text.contains("mariadb_binlog_seconds_since_last_event "));
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
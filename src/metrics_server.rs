@@ -0,0 +1,123 @@
+use std::{sync::Arc, thread};
+
+use tiny_http::{Header, Response, Server};
+
+use crate::metrics::MetricsRegistry;
+use crate::model::MyError;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 在后台线程中通过HTTP提供`/metrics`，其他路径返回404
+pub fn serve_metrics(address: &str, registry: Arc<MetricsRegistry>) -> Result<(), BoxedError> {
+    let server = Server::http(address)
+        .map_err(|e| MyError(format!("failed to listen on `{}`: {}", address, e)))?;
+
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+        .map_err(|_| MyError("invalid content type".to_string()))?;
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" => {
+                    Response::from_string(registry.render()).with_header(content_type.clone())
+                }
+                _ => Response::from_string("not found").with_status_code(404),
+            };
+
+            // 客户端提前断开时忽略
+            let _ = request.respond(response);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::model::*;
+    use crate::service::*;
+    use std::{
+        collections::HashMap,
+        io::{Cursor, Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    fn record_fixture(registry: &MetricsRegistry) {
+        let orders = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+        let binlog = BinlogBuilder::new()
+            .event(&orders)
+            .event(
+                &RowsEventBuilder::insert(&orders)
+                    .row(vec![ColumnValue::Int(1)])
+                    .row(vec![ColumnValue::Int(2)]),
+            )
+            .build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            position = header.next_event_position as u64;
+            registry.record_event(
+                &header,
+                "mysql-bin.000001",
+                position,
+                body.as_ref(),
+                &table_structs,
+            );
+        }
+    }
+
+    fn get(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn scrape_the_endpoint() {
+        // 先找一个空闲的端口
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let registry = Arc::new(MetricsRegistry::new());
+        serve_metrics(&format!("127.0.0.1:{}", port), registry.clone()).unwrap();
+
+        record_fixture(&registry);
+        let response = get(port, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.contains("mariadb_binlog_events_total{type=\"WRITE_ROWS_EVENT_V1\"} 1"));
+        assert!(
+            response.contains("mariadb_binlog_rows_total{op=\"insert\",table=\"shop.orders\"} 2")
+        );
+
+        assert!(get(port, "/other").starts_with("HTTP/1.1 404"));
+
+        // 端口已经被占用
+        assert!(serve_metrics(&format!("127.0.0.1:{}", port), registry).is_err());
+    }
+}
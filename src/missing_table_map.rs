@@ -0,0 +1,266 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Serialize;
+
+// 每个table id最多列出的位置数
+const MAX_LISTED_OFFSETS: usize = 10;
+
+/// 一个table id的行事件
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingTableMap {
+    pub table_id: u64,
+    pub file_name: String,
+    /// 行事件的起始位置，按出现的顺序
+    pub offsets: Vec<u64>,
+    pub events: u64,
+    pub bytes: u64,
+}
+
+/// `--report-missing-table-maps`，从文件中间开始解析（或者事务跨过了FLUSH LOGS）时
+/// 开头的行事件对应的table map在之前的文件中，这些行事件无法解码，跳过并在结束时汇总
+#[derive(Default)]
+pub struct MissingTableMapReport {
+    // (文件名, table id) -> 行事件
+    tables: BTreeMap<(String, u64), MissingTableMap>,
+}
+
+impl MissingTableMapReport {
+    pub fn new() -> MissingTableMapReport {
+        MissingTableMapReport::default()
+    }
+
+    pub fn record(&mut self, file_name: &str, table_id: u64, offset: u64, event_length: u32) {
+        let table = self
+            .tables
+            .entry((file_name.to_string(), table_id))
+            .or_insert_with(|| MissingTableMap {
+                table_id,
+                file_name: file_name.to_string(),
+                offsets: Vec::new(),
+                events: 0,
+                bytes: 0,
+            });
+
+        table.offsets.push(offset);
+        table.events += 1;
+        table.bytes += event_length as u64;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn tables(&self) -> Vec<&MissingTableMap> {
+        self.tables.values().collect()
+    }
+}
+
+impl Display for MissingTableMapReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "rows events of {} table ids are not decoded because their table maps are missing:",
+            self.tables.len()
+        )?;
+
+        for table in self.tables.values() {
+            let mut offsets = table
+                .offsets
+                .iter()
+                .take(MAX_LISTED_OFFSETS)
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>();
+            if table.offsets.len() > MAX_LISTED_OFFSETS {
+                offsets.push("...".to_string());
+            }
+
+            writeln!(
+                f,
+                "  table id {} in {}: {} events, {} bytes, at {}",
+                table.table_id,
+                table.file_name,
+                table.events,
+                table.bytes,
+                offsets.join(",")
+            )?;
+        }
+
+        let mut file_names = self
+            .tables
+            .keys()
+            .map(|(file_name, _)| file_name.as_str())
+            .collect::<Vec<&str>>();
+        file_names.dedup();
+
+        for file_name in file_names {
+            match previous_file_name(file_name) {
+                Some(previous) => writeln!(
+                    f,
+                    "the table maps of {} are probably in {}, parse it first or start from an earlier position",
+                    file_name, previous
+                )?,
+                None => writeln!(
+                    f,
+                    "the table maps of {} are probably in the previous binlog file, start from an earlier position",
+                    file_name
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `mysql-bin.000002`的前一个文件是`mysql-bin.000001`，序号保持同样的宽度
+fn previous_file_name(file_name: &str) -> Option<String> {
+    let (base_name, sequence) = file_name.rsplit_once('.')?;
+    let number = sequence.parse::<u64>().ok()?.checked_sub(1)?;
+    if number == 0 {
+        return None;
+    }
+
+    Some(format!(
+        "{}.{:0width$}",
+        base_name,
+        number,
+        width = sequence.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::model::*;
+    use crate::service::*;
+    use std::{collections::HashMap, io::Cursor};
+
+    const EVENT_HEADER_LENGTH: u64 = 19;
+
+    fn table(name: &str, table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new("shop", name)
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+    }
+
+    #[test]
+    fn start_right_after_the_table_maps() {
+        let orders = table("orders", 7);
+        let users = table("users", 8);
+        let builder = BinlogBuilder::new().event(&orders).event(&users);
+        let start = builder.position();
+        let builder =
+            builder.event(&RowsEventBuilder::insert(&orders).row(vec![ColumnValue::Int(1)]));
+        let update_position = builder.position();
+        let builder = builder.event(
+            &RowsEventBuilder::update(&users)
+                .update_row(vec![ColumnValue::Int(1)], vec![ColumnValue::Int(2)]),
+        );
+        let delete_position = builder.position();
+        let binlog = builder
+            .event(&RowsEventBuilder::delete(&orders).row(vec![ColumnValue::Int(1)]))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            // 下一个事务有table map，可以正常解码
+            .event(&orders)
+            .event(&RowsEventBuilder::insert(&orders).row(vec![ColumnValue::Int(3)]))
+            .build();
+
+        // 没有table map时返回错误而不是panic
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let header = get_event_header(&mut file, start).unwrap();
+        assert!(get_event_body(
+            &mut file,
+            start,
+            &header,
+            &mut table_structs,
+            CHECKSUM_LENGTH
+        )
+        .is_err());
+
+        let mut report = MissingTableMapReport::new();
+        let mut decoded_rows = 0;
+        let mut position = start;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let missing_table_id = match header.type_code {
+                23..=25 => Some(
+                    get_rows_event_table_id(&mut file, position + EVENT_HEADER_LENGTH).unwrap(),
+                )
+                .filter(|v| !table_structs.contains_key(v)),
+                _ => None,
+            };
+
+            match missing_table_id {
+                Some(table_id) => {
+                    report.record("mysql-bin.000002", table_id, position, header.event_length)
+                }
+                None => {
+                    let body = get_event_body(
+                        &mut file,
+                        position,
+                        &header,
+                        &mut table_structs,
+                        CHECKSUM_LENGTH,
+                    )
+                    .unwrap();
+                    if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+                        decoded_rows += rows_event.number_of_rows;
+                    }
+                }
+            }
+            position = header.next_event_position as u64;
+        }
+
+        assert_eq!(decoded_rows, 1);
+        let tables = report
+            .tables()
+            .into_iter()
+            .map(|v| (v.table_id, v.offsets.clone(), v.events))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tables,
+            [
+                (7, vec![start, delete_position], 2),
+                (8, vec![update_position], 1),
+            ]
+        );
+        assert_eq!(report.tables()[1].bytes, delete_position - update_position);
+
+        let text = report.to_string();
+        assert!(text.starts_with("rows events of 2 table ids are not decoded"));
+        assert!(text.contains(&format!(
+            "  table id 7 in mysql-bin.000002: 2 events, {} bytes, at {},{}\n",
+            report.tables()[0].bytes,
+            start,
+            delete_position
+        )));
+        assert!(text.contains("are probably in mysql-bin.000001, parse it first"));
+    }
+
+    #[test]
+    fn offsets_are_truncated() {
+        let mut report = MissingTableMapReport::new();
+        assert!(report.is_empty());
+        for offset in 0..12 {
+            report.record("relay.log", 9, 100 + offset * 10, 10);
+        }
+
+        let text = report.to_string();
+        assert!(
+            text.contains("12 events, 120 bytes, at 100,110,120,130,140,150,160,170,180,190,...\n")
+        );
+        assert!(text.contains("of relay.log are probably in the previous binlog file"));
+    }
+
+    #[test]
+    fn previous_file_names() {
+        assert_eq!(
+            previous_file_name("mysql-bin.000010").as_deref(),
+            Some("mysql-bin.000009")
+        );
+        assert_eq!(previous_file_name("mysql-bin.000001"), None);
+        assert_eq!(previous_file_name("binlog"), None);
+    }
+}
@@ -1,9 +1,35 @@
-use std::fmt::Display;
+use std::{any::Any, borrow::Cow, collections::HashMap, fmt::Display, sync::Arc};
 
-pub trait EventBody: std::fmt::Debug {}
+use base64::prelude::*;
+use bitvec::prelude::*;
+use serde::{Serialize, Serializer};
+
+use crate::error::RowImageLengthMismatch;
+use crate::field_type::FieldType;
+use crate::flavor::ServerVersion;
+use crate::table_structure::column_descriptors;
+use crate::util::{format_timestamp, parse_row_images};
+
+pub trait EventBody: std::fmt::Debug + Any + erased_serde::Serialize {}
+
+erased_serde::serialize_trait_object!(EventBody);
+
+impl dyn EventBody {
+    /// 将事件体还原成具体的结构，用于需要读取字段的输出格式
+    /// table map保存在Arc中，也可以按EventBodyTypeCode19还原
+    pub fn downcast_ref<T: EventBody>(&self) -> Option<&T> {
+        let any = self as &dyn Any;
+
+        any.downcast_ref::<T>().or_else(|| {
+            any.downcast_ref::<Arc<EventBodyTypeCode19>>()
+                .and_then(|v| (v.as_ref() as &dyn Any).downcast_ref::<T>())
+        })
+    }
+}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct EventHeader {
     pub timestamp: u32,
     pub type_code: u8,
@@ -13,20 +39,91 @@ pub struct EventHeader {
     pub flags: u16,
 }
 
+/// 事件的时间，header中的时间戳只有秒，query事件有Q_HRNOW时加上其中的微秒
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EventTime {
+    pub seconds: u32,
+    pub microseconds: Option<u32>,
+}
+
+impl EventTime {
+    pub fn new(header: &EventHeader, body: &dyn EventBody) -> EventTime {
+        EventTime {
+            seconds: header.timestamp,
+            microseconds: body
+                .downcast_ref::<EventBodyTypeCode2>()
+                .and_then(|v| v.hrnow),
+        }
+    }
+
+    /// 没有微秒时按整秒
+    pub fn as_micros(&self) -> u64 {
+        self.seconds as u64 * 1_000_000 + self.microseconds.unwrap_or(0) as u64
+    }
+}
+
+/// UTC时间，有微秒时是`2023-11-14 22:13:20.123456`，没有时到秒
+impl Display for EventTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let datetime = format_timestamp(self.seconds);
+
+        match self.microseconds {
+            Some(microseconds) => write!(f, "{}.{:06}", datetime, microseconds),
+            None => write!(f, "{}", datetime),
+        }
+    }
+}
+
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// format description
 pub struct EventBodyTypeCode15 {
     pub binlog_version: u16,
     pub server_version: String,
     pub create_timestamp: u32,
     pub header_length: u8,
+    /// 每种事件post header的长度，按类型码排列，写回文件时使用
+    #[serde(skip)]
+    pub post_header_lengths: Vec<u8>,
+    /// 之后的事件的校验算法，1是CRC32
+    #[serde(skip)]
+    pub checksum_algorithm: u8,
 }
 
 impl EventBody for EventBodyTypeCode15 {}
 
+// checksum_algorithm中的CRC32
+const BINLOG_CHECKSUM_ALG_CRC32: u8 = 1;
+
+impl EventBodyTypeCode15 {
+    /// 之后的事件最后的checksum长度，CRC32时是4；binlog_checksum=NONE或者FDE中还没有校验算法的旧版本时是0
+    pub fn checksum_length(&self) -> usize {
+        let has_checksum_algorithm =
+            ServerVersion::parse(&self.server_version).has_checksum_algorithm();
+
+        match has_checksum_algorithm && self.checksum_algorithm == BINLOG_CHECKSUM_ALG_CRC32 {
+            true => 4,
+            false => 0,
+        }
+    }
+}
+
+// 人类可读的输出中不包括只用于写回文件的字段
+impl std::fmt::Debug for EventBodyTypeCode15 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBodyTypeCode15")
+            .field("binlog_version", &self.binlog_version)
+            .field("server_version", &self.server_version)
+            .field("create_timestamp", &self.create_timestamp)
+            .field("header_length", &self.header_length)
+            .finish()
+    }
+}
+
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// annotate row
 /// sql text
 pub struct EventBodyTypeCode160 {
@@ -36,7 +133,8 @@ pub struct EventBodyTypeCode160 {
 impl EventBody for EventBodyTypeCode160 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// gtid list
 pub struct EventBodyTypeCode163 {
     pub number_of_gtids: u32,
@@ -44,17 +142,56 @@ pub struct EventBodyTypeCode163 {
 }
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct GTID {
     pub replication_domain_id: u32,
     pub server_id: u32,
     pub gtid_sequence: u64,
 }
 
+/// domain-server_id-sequence，例如`0-1-100`
+impl Display for GTID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}",
+            self.replication_domain_id, self.server_id, self.gtid_sequence
+        )
+    }
+}
+
+impl std::str::FromStr for GTID {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(value: &str) -> Result<GTID, Self::Err> {
+        let invalid = || {
+            MyError(format!(
+                "invalid GTID `{}`, use domain-server_id-sequence like 0-1-100",
+                value
+            ))
+        };
+
+        let mut parts = value.split('-');
+        let (Some(domain), Some(server_id), Some(sequence), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Box::new(invalid()));
+        };
+
+        Ok(GTID {
+            replication_domain_id: domain.parse().map_err(|_| invalid())?,
+            server_id: server_id.parse().map_err(|_| invalid())?,
+            gtid_sequence: sequence.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
 impl EventBody for EventBodyTypeCode163 {}
 
 #[allow(unused)]
-#[derive(Debug, Clone)]
+#[derive(Clone, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// table map
 pub struct EventBodyTypeCode19 {
     // 这里只要6字节，只能向上取到u64
@@ -66,29 +203,251 @@ pub struct EventBodyTypeCode19 {
     pub table_name_length: u8,
     pub table_name: String,
     pub number_of_columns: u64,
+    #[deprecated(note = "use `columns[i].field_type`")]
     pub column_types: Vec<u8>,
+    #[deprecated(note = "use `columns[i].type_name()`")]
     pub column_types_string_for_human: Vec<String>,
     pub number_of_metadata_block: u64,
     pub metadata_block: Vec<u8>,
+    /// 没有元数据的字段不在其中，下标和字段的序号不对应
+    #[deprecated(note = "use `columns[i].metadata_description`")]
     pub metadata_block_string_for_human: Vec<String>,
+    /// 没有元数据的字段不在其中，下标和字段的序号不对应
+    #[deprecated(note = "use `columns[i].metadata_raw`")]
     pub metadata_block_data_raw: Vec<Vec<u8>>,
-    pub columns_can_be_null: Vec<bool>,
+    #[deprecated(note = "use `columns[i].nullable`")]
+    pub columns_can_be_null: Bitmap,
     pub optional_metadata_block: Vec<u8>,
+    /// 按字段顺序排列的每个字段的定义，代替上面几个按下标对应的数组
+    pub columns: Vec<ColumnDef>,
+    /// optional metadata中COLUMN_NAME的字段名，只有binlog_row_metadata=FULL时才有
+    pub column_names: Option<Vec<String>>,
+    /// optional metadata中主键字段的序号，按主键中的顺序排列，只有binlog_row_metadata=FULL时才有
+    pub primary_key: Option<Vec<usize>>,
+    /// 和primary_key一一对应的前缀长度，0表示整个字段，只有主键中有前缀索引（PRIMARY_KEY_WITH_PREFIX）时才有
+    pub primary_key_prefixes: Option<Vec<u64>>,
+    /// 按字段顺序排列，deal_type_code_19中根据上面的字段计算一次，行事件解码时直接使用
+    #[serde(skip)]
+    pub column_descriptors: Vec<ColumnDescriptor>,
+    /// 字段名到序号的索引，deal_type_code_19中计算一次，这张表的所有Row共用
+    #[serde(skip)]
+    pub column_index: Arc<ColumnIndex>,
 }
 
 impl EventBody for EventBodyTypeCode19 {}
 
+impl EventBodyTypeCode19 {
+    /// 预先计算的column_descriptors，直接构造的table map中没有时现在计算
+    pub fn descriptors(&self) -> Cow<'_, [ColumnDescriptor]> {
+        match self.column_descriptors.len() == self.columns.len() {
+            true => Cow::Borrowed(&self.column_descriptors),
+            false => Cow::Owned(column_descriptors(self)),
+        }
+    }
+
+    /// 预先计算的column_index，直接构造的table map中没有时现在计算
+    pub fn column_index(&self) -> Arc<ColumnIndex> {
+        match self.column_index.len() == self.columns.len() {
+            true => self.column_index.clone(),
+            false => Arc::new(ColumnIndex::new(self)),
+        }
+    }
+}
+
+/// 一张表的字段名和字段名到序号的索引，没有binlog_row_metadata=FULL时使用col_1这样的名字
+#[derive(Debug, Default)]
+pub struct ColumnIndex {
+    names: Vec<String>,
+    by_name: HashMap<String, usize>,
+}
+
+impl ColumnIndex {
+    pub fn new(table_map: &EventBodyTypeCode19) -> ColumnIndex {
+        let names: Vec<String> = table_map
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                column
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("col_{}", i + 1))
+            })
+            .collect();
+
+        // 同名时（比如真实的字段名和其他字段的col_2重复）取第一个
+        let mut by_name = HashMap::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            by_name.entry(name.clone()).or_insert(i);
+        }
+
+        ColumnIndex { names, by_name }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// 第index个字段的名字
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.names.get(index).map(|v| v.as_str())
+    }
+
+    /// 字段名对应的序号
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+}
+
+// 人类可读的输出中不包括column_descriptors
+#[allow(deprecated)]
+impl std::fmt::Debug for EventBodyTypeCode19 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBodyTypeCode19")
+            .field("table_id", &self.table_id)
+            .field("reserved_for_future_use", &self.reserved_for_future_use)
+            .field("database_name_length", &self.database_name_length)
+            .field("database_name", &self.database_name)
+            .field("table_name_length", &self.table_name_length)
+            .field("table_name", &self.table_name)
+            .field("number_of_columns", &self.number_of_columns)
+            .field("column_types", &self.column_types)
+            .field(
+                "column_types_string_for_human",
+                &self.column_types_string_for_human,
+            )
+            .field("number_of_metadata_block", &self.number_of_metadata_block)
+            .field("metadata_block", &self.metadata_block)
+            .field(
+                "metadata_block_string_for_human",
+                &self.metadata_block_string_for_human,
+            )
+            .field("metadata_block_data_raw", &self.metadata_block_data_raw)
+            .field("columns_can_be_null", &self.columns_can_be_null)
+            .field("optional_metadata_block", &self.optional_metadata_block)
+            .field("columns", &self.columns)
+            .field("column_names", &self.column_names)
+            .field("primary_key", &self.primary_key)
+            .field("primary_key_prefixes", &self.primary_key_prefixes)
+            .finish()
+    }
+}
+
+/// table map中一个字段的定义，deal_type_code_19中一次得到
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ColumnDef {
+    /// json中是类型名，例如`MYSQL_TYPE_VARCHAR`
+    pub field_type: FieldType,
+    /// 这个字段在metadata block中的部分，没有元数据的类型为空
+    pub metadata_raw: Vec<u8>,
+    /// 元数据的含义，没有元数据时为空
+    pub metadata_description: String,
+    pub nullable: bool,
+    /// 字符串类型的collation id，只有binlog_row_metadata=FULL时才有
+    pub charset: Option<u64>,
+    /// 数值类型是否unsigned，只有binlog_row_metadata=FULL时才有
+    pub unsigned: Option<bool>,
+    /// 只有binlog_row_metadata=FULL时才有
+    pub name: Option<String>,
+}
+
+impl ColumnDef {
+    pub fn type_code(&self) -> u8 {
+        self.field_type.code()
+    }
+
+    /// 类型名，例如`MYSQL_TYPE_VARCHAR`
+    pub fn type_name(&self) -> &'static str {
+        self.field_type.name()
+    }
+
+    /// 旧的metadata_block_string_for_human中的格式，没有元数据时为空
+    pub fn metadata_for_human(&self) -> String {
+        if self.metadata_raw.is_empty() {
+            return String::new();
+        }
+
+        format!(
+            "field type id is: {}, field type name is: {}, infomation is [{}]",
+            self.type_code(),
+            self.type_name(),
+            self.metadata_description
+        )
+    }
+}
+
+/// 行事件解码时字段的类型，还不支持解码的类型都是Unsupported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Tiny,
+    Short,
+    /// MEDIUMINT，3个字节
+    Int24,
+    Long,
+    LongLong,
+    Float,
+    Double,
+    NewDecimal,
+    Varchar,
+    Date,
+    Time2,
+    DateTime2,
+    Timestamp2,
+    Blob,
+    /// MySQL的JSON，二进制格式，见mysql_json
+    Json,
+    Unsupported,
+}
+
+/// 字段在行镜像中占用的长度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnLength {
+    /// 固定的字节数
+    Fixed(usize),
+    /// 先是这么多字节的长度，然后是数据
+    Prefixed(usize),
+    /// 还不支持的类型，不知道长度
+    Unknown,
+}
+
+/// 一个字段解码需要的所有信息，由类型码、元数据、nullable bitmap和可选元数据得到
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDescriptor {
+    pub field_type: FieldType,
+    pub kind: ColumnKind,
+    pub length: ColumnLength,
+    /// NEWDECIMAL的总位数，其他类型为0
+    pub precision: u8,
+    /// NEWDECIMAL的小数位数，TIME2、DATETIME2、TIMESTAMP2的秒的小数位数，其他类型为0
+    pub decimals: u8,
+    /// 字符串类型的collation id，只有binlog_row_metadata=FULL时才有
+    pub charset: Option<u64>,
+    pub nullable: bool,
+    /// 数值类型是否unsigned，只有binlog_row_metadata=FULL时才有
+    pub unsigned: Option<bool>,
+}
+
+// deal_type_code_19返回的table map，和table_structs共用
+impl EventBody for Arc<EventBodyTypeCode19> {}
+
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// xid
 pub struct EventBodyTypeCode16 {
-    pub xid_transaction_number: u8,
+    pub xid_transaction_number: u64,
 }
 
 impl EventBody for EventBodyTypeCode16 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// query
 pub struct EventBodyTypeCode2 {
     pub id_of_thread: u32,
@@ -97,15 +456,96 @@ pub struct EventBodyTypeCode2 {
     pub error_code: u16,
     pub length_of_status_variable_block: u16,
     pub status_variables: Vec<u8>,
+    #[deprecated(note = "use `typed_status_variables`, each one displays as the same string")]
     pub status_variables_string_vec_for_human: Vec<String>,
+    /// 按出现的顺序解析出的状态变量
+    pub typed_status_variables: Vec<StatusVariable>,
+    /// Q_HRNOW，执行时间的微秒部分，只有MariaDB会写，秒在header的timestamp中
+    pub hrnow: Option<u32>,
     pub database_name: String,
     pub sql: String,
 }
 
 impl EventBody for EventBodyTypeCode2 {}
 
+/// query事件中的一个状态变量，Display是人类可读的说明，例如`SQL_MODE is [MODE_ANSI_QUOTES]`
+/// 参考 https://dev.mysql.com/doc/dev/mysql-server/latest/classmysql_1_1binlog_1_1event_1_1Query__event.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum StatusVariable {
+    Flags2(u32),
+    SqlMode(u64),
+    Catalog(String),
+    AutoIncrement {
+        increment: u16,
+        offset: u16,
+    },
+    /// 都是collation id
+    Charset {
+        client: u16,
+        connection: u16,
+        server: u16,
+    },
+    TimeZone(String),
+    /// locale是按`locale::lookup`得到的名字，例如`zh_CN`，不认识的id是None
+    LcTimeNames {
+        code: u16,
+        locale: Option<&'static str>,
+    },
+    CharsetDatabase(u16),
+    TableMapForUpdate(u64),
+    Invoker {
+        user: String,
+        host: String,
+    },
+    /// 库的数量超过限制时为None，只有MySQL会写
+    UpdatedDbNames(Option<Vec<String>>),
+    Microseconds(u32),
+    ExplicitDefaultsForTimestamp(u8),
+    DdlLoggedWithXid(u64),
+    DefaultCollationForUtf8mb4(u16),
+    SqlRequirePrimaryKey(u8),
+    DefaultTableEncryption(u8),
+    MariadbHrNow(u32),
+    Xid(u64),
+    /// 不认识的代码不知道长度，raw是之后所有的字节
+    Unknown {
+        code: u8,
+        raw: Vec<u8>,
+    },
+}
+
+impl StatusVariable {
+    /// binlog中的代码，例如Q_SQL_MODE_CODE是1
+    pub fn code(&self) -> u8 {
+        match self {
+            StatusVariable::Flags2(_) => 0,
+            StatusVariable::SqlMode(_) => 1,
+            StatusVariable::AutoIncrement { .. } => 3,
+            StatusVariable::Charset { .. } => 4,
+            StatusVariable::TimeZone(_) => 5,
+            StatusVariable::Catalog(_) => 6,
+            StatusVariable::LcTimeNames { .. } => 7,
+            StatusVariable::CharsetDatabase(_) => 8,
+            StatusVariable::TableMapForUpdate(_) => 9,
+            StatusVariable::Invoker { .. } => 11,
+            StatusVariable::UpdatedDbNames(_) => 12,
+            StatusVariable::Microseconds(_) => 13,
+            StatusVariable::ExplicitDefaultsForTimestamp(_) => 16,
+            StatusVariable::DdlLoggedWithXid(_) => 17,
+            StatusVariable::DefaultCollationForUtf8mb4(_) => 18,
+            StatusVariable::SqlRequirePrimaryKey(_) => 19,
+            StatusVariable::DefaultTableEncryption(_) => 20,
+            StatusVariable::MariadbHrNow(_) => 128,
+            StatusVariable::Xid(_) => 129,
+            StatusVariable::Unknown { code, .. } => *code,
+        }
+    }
+}
+
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// binlog_checkpoint
 pub struct EventBodyTypeCode161 {
     pub log_filename_length: u32,
@@ -115,7 +555,8 @@ pub struct EventBodyTypeCode161 {
 impl EventBody for EventBodyTypeCode161 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// gtid event
 pub struct EventBodyTypeCode162 {
     pub gtid_sequence: u64,
@@ -131,7 +572,33 @@ pub struct EventBodyTypeCode162 {
 impl EventBody for EventBodyTypeCode162 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+/// MySQL的gtid和anonymous gtid
+/// MySQL 5.7之前没有last_committed和sequence_number
+pub struct EventBodyTypeCode33 {
+    pub flags: u8,
+    pub uuid: String,
+    pub gno: i64,
+    pub last_committed: Option<i64>,
+    pub sequence_number: Option<i64>,
+}
+
+impl EventBody for EventBodyTypeCode33 {}
+
+#[allow(unused)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+/// MySQL的previous gtids，之前的文件中所有的GTID，格式和`gtid_executed`一样
+pub struct EventBodyTypeCode35 {
+    pub gtid_set: String,
+}
+
+impl EventBody for EventBodyTypeCode35 {}
+
+#[allow(unused)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// intvar event
 pub struct EventBodyTypeCode5 {
     pub data_type: u8,
@@ -141,7 +608,8 @@ pub struct EventBodyTypeCode5 {
 impl EventBody for EventBodyTypeCode5 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// rotate event
 pub struct EventBodyTypeCode4 {
     pub position_of_the_first_event_in_next_log_file: u64,
@@ -151,25 +619,717 @@ pub struct EventBodyTypeCode4 {
 impl EventBody for EventBodyTypeCode4 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+/// 服务端的binlog_row_image设置，决定行镜像中有哪些字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RowImageMode {
+    /// 所有字段
+    Full,
+    /// 修改前只有定位行需要的字段（主键），修改后只有修改过的字段
+    Minimal,
+    /// 除了没有修改过的BLOB、TEXT以外的所有字段
+    Noblob,
+}
+
+impl Display for RowImageMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowImageMode::Full => write!(f, "FULL"),
+            RowImageMode::Minimal => write!(f, "MINIMAL"),
+            RowImageMode::Noblob => write!(f, "NOBLOB"),
+        }
+    }
+}
+
+/// 事件中没有直接记录、从其他字段推断出来的值，certain为false时也可能是别的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Inferred<T> {
+    pub value: T,
+    pub certain: bool,
+}
+
+impl<T: Display> Display for Inferred<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.certain {
+            true => write!(f, "{}", self.value),
+            false => write!(f, "probably {}", self.value),
+        }
+    }
+}
+
+impl Inferred<RowImageMode> {
+    /// 镜像中有所有的字段
+    pub fn is_full(&self) -> bool {
+        self.value == RowImageMode::Full
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// insert update delete event
 pub struct EventBodyTypeCode23To25 {
     pub type_string_for_human: String,
     pub table_id: u64,
     pub flags: u16,
     pub number_of_columns: u64,
-    pub columns_used: Vec<bool>,
-    pub columns_used_for_update: Option<Vec<bool>>,
-    pub null_bitmap: Vec<bool>,
+    pub columns_used: Bitmap,
+    pub columns_used_for_update: Option<Bitmap>,
+    pub null_bitmap: Bitmap,
     pub column_data: Vec<String>,
-    pub null_bitmap_for_update: Option<Vec<bool>>,
+    pub null_bitmap_for_update: Option<Bitmap>,
     pub column_data_for_update: Option<Vec<String>>,
+    pub rows: Vec<RowsEventRow>,
+    /// 只解析了结构、没有解码字段值时rows为空，行数仍然是准确的；
+    /// BinlogParser::decode_only排除的表的行事件没有计算行数，为0
+    #[serde(skip)]
+    pub number_of_rows: u64,
+    /// 所有行镜像的原始字节（不包括校验和），decode_rows从这里解码
+    #[serde(skip)]
+    pub row_images: Vec<u8>,
+    /// 按table map解码行镜像之后没有正好用完body，rows中只有完整解码的行
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_image_mismatch: Option<RowImageLengthMismatch>,
+    /// 按镜像中的字段推断的binlog_row_image，见infer_row_image，FULL时不输出
+    #[serde(skip_serializing_if = "Inferred::is_full")]
+    #[cfg_attr(feature = "schemars", schemars(default = "full_row_image"))]
+    pub row_image: Inferred<RowImageMode>,
+}
+
+// json中没有row_image时是FULL，JSON Schema中因此不是必需的
+#[cfg(feature = "schemars")]
+fn full_row_image() -> Inferred<RowImageMode> {
+    Inferred {
+        value: RowImageMode::Full,
+        certain: true,
+    }
 }
 
 impl EventBody for EventBodyTypeCode23To25 {}
 
+// 人类可读的输出中不包括行数和原始字节，行镜像一致时也不包括row_image_mismatch，FULL时不包括row_image
+impl std::fmt::Debug for EventBodyTypeCode23To25 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("EventBodyTypeCode23To25");
+        debug
+            .field("type_string_for_human", &self.type_string_for_human)
+            .field("table_id", &self.table_id)
+            .field("flags", &self.flags)
+            .field("number_of_columns", &self.number_of_columns)
+            .field("columns_used", &self.columns_used)
+            .field("columns_used_for_update", &self.columns_used_for_update)
+            .field("null_bitmap", &self.null_bitmap)
+            .field("column_data", &self.column_data)
+            .field("null_bitmap_for_update", &self.null_bitmap_for_update)
+            .field("column_data_for_update", &self.column_data_for_update)
+            .field("rows", &self.rows);
+
+        if let Some(mismatch) = &self.row_image_mismatch {
+            debug.field("row_image_mismatch", mismatch);
+        }
+
+        if !self.row_image.is_full() {
+            debug.field("row_image", &self.row_image);
+        }
+
+        debug.finish()
+    }
+}
+
+impl EventBodyTypeCode23To25 {
+    /// rows中是否已经有解码的字段值
+    pub fn is_decoded(&self) -> bool {
+        self.rows.len() as u64 == self.number_of_rows
+            && (!self.rows.is_empty() || self.row_images.is_empty())
+    }
+
+    /// 镜像中是否有所有的字段，binlog_row_image为MINIMAL、NOBLOB时只有部分字段
+    pub fn is_full_image(&self) -> bool {
+        self.columns_used.all()
+            && self
+                .columns_used_for_update
+                .as_ref()
+                .is_none_or(|v| v.all())
+    }
+
+    /// 按镜像中有哪些字段推断binlog_row_image，事件中没有直接记录这个设置
+    ///
+    /// 缺少BLOB、TEXT以外的字段时一定是MINIMAL；只缺少BLOB这类字段时可能是NOBLOB，
+    /// 也可能是MINIMAL时语句正好写了其他所有字段；所有的字段都在时一般是FULL，
+    /// 但MINIMAL时没有主键的表的delete、写了所有字段的insert也是这样，只有update修改前后都完整时才确定。
+    /// table_info为None时不知道哪些是BLOB字段，缺少字段时按MINIMAL，不确定
+    pub fn infer_row_image(
+        &self,
+        table_info: Option<&EventBodyTypeCode19>,
+    ) -> Inferred<RowImageMode> {
+        let images = std::iter::once(&self.columns_used).chain(&self.columns_used_for_update);
+        let missing = images
+            .flat_map(|bitmap| bitmap.iter().enumerate().filter(|(_, v)| !v))
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+
+        let is_blob = |index: &usize| {
+            table_info
+                .and_then(|v| v.columns.get(*index))
+                .is_some_and(|v| v.field_type.is_blob())
+        };
+
+        match (missing.is_empty(), table_info) {
+            (true, _) => Inferred {
+                value: RowImageMode::Full,
+                certain: self.columns_used_for_update.is_some(),
+            },
+            (false, Some(_)) if missing.iter().all(is_blob) => Inferred {
+                value: RowImageMode::Noblob,
+                certain: false,
+            },
+            (false, table_info) => Inferred {
+                value: RowImageMode::Minimal,
+                certain: table_info.is_some(),
+            },
+        }
+    }
+
+    /// 每一行的镜像，可以按字段名取值：insert是插入的行，delete是删除的行，update是修改后的行
+    /// 还没有解码字段值的事件在这里解码
+    pub fn rows(
+        &self,
+        table_info: &EventBodyTypeCode19,
+    ) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
+        let rows = self.decoded_rows(table_info)?;
+        let column_index = table_info.column_index();
+        let present = match self.type_string_for_human.as_str() {
+            "update" => self
+                .columns_used_for_update
+                .as_ref()
+                .unwrap_or(&self.columns_used),
+            _ => &self.columns_used,
+        };
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| match self.type_string_for_human.as_str() {
+                "delete" => row.before.as_ref(),
+                _ => row.after.as_ref(),
+            })
+            .map(|values| Row::new(values.clone(), present.clone(), column_index.clone()))
+            .collect())
+    }
+
+    /// update的每一行修改前后的镜像(before, after)，insert和delete没有
+    /// MINIMAL时before中一般只有主键，after中只有修改过的字段，用Row::changed_columns比较
+    pub fn pairs(
+        &self,
+        table_info: &EventBodyTypeCode19,
+    ) -> Result<impl Iterator<Item = (Row, Row)> + '_, Box<dyn std::error::Error>> {
+        let rows = match self.type_string_for_human.as_str() {
+            "update" => self.decoded_rows(table_info)?,
+            _ => Cow::Owned(Vec::new()),
+        };
+        let column_index = table_info.column_index();
+        let present_for_update = self
+            .columns_used_for_update
+            .as_ref()
+            .unwrap_or(&self.columns_used);
+
+        let pairs: Vec<(Row, Row)> = rows
+            .iter()
+            .filter_map(|row| match (&row.before, &row.after) {
+                (Some(before), Some(after)) => Some((
+                    Row::new(
+                        before.clone(),
+                        self.columns_used.clone(),
+                        column_index.clone(),
+                    ),
+                    Row::new(
+                        after.clone(),
+                        present_for_update.clone(),
+                        column_index.clone(),
+                    ),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        Ok(pairs.into_iter())
+    }
+
+    // 已经解码的行，没有解码时现在解码
+    fn decoded_rows(
+        &self,
+        table_info: &EventBodyTypeCode19,
+    ) -> Result<Cow<'_, [RowsEventRow]>, Box<dyn std::error::Error>> {
+        match self.is_decoded() {
+            true => Ok(Cow::Borrowed(&self.rows)),
+            false => Ok(Cow::Owned(self.decode_rows(table_info)?)),
+        }
+    }
+
+    /// 解码row_images中的所有行，只解析了结构的事件在需要字段值时调用
+    pub fn decode_rows(
+        &self,
+        table_info: &EventBodyTypeCode19,
+    ) -> Result<Vec<RowsEventRow>, Box<dyn std::error::Error>> {
+        let images = parse_row_images(
+            &self.row_images,
+            &self.columns_used,
+            self.columns_used_for_update.as_ref(),
+            table_info,
+            ValueOptions::default(),
+        )?;
+
+        let type_code = match self.type_string_for_human.as_str() {
+            "update" => 24,
+            "delete" => 25,
+            _ => 23,
+        };
+
+        Ok(RowsEventRow::from_images(images, type_code))
+    }
+}
+
+/// table map和行事件中每个字段一位的bitmap，和binlog中一样每个字节从最低位开始
+/// 宽表的每个行事件都有几个，所以按位保存；输出和原来的Vec<bool>一样是bool的列表
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Bitmap(BitVec<u8, Lsb0>);
+
+impl Bitmap {
+    /// 直接使用binlog中的字节，只保留前length位
+    pub fn from_bytes(bytes: &[u8], length: usize) -> Bitmap {
+        let mut bits = BitVec::from_slice(bytes);
+        bits.truncate(length);
+        Bitmap(bits)
+    }
+
+    /// length个相同的位
+    pub fn repeat(bit: bool, length: usize) -> Bitmap {
+        Bitmap(BitVec::repeat(bit, length))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// 第index个字段的位，超出字段数时为None
+    pub fn get(&self, index: usize) -> Option<bool> {
+        self.0.get(index).map(|v| *v)
+    }
+
+    /// 按字段顺序
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        self.0.iter().by_vals()
+    }
+
+    /// 所有的位都是1，比如FULL的行镜像中columns_used
+    pub fn all(&self) -> bool {
+        self.0.all()
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.0.count_ones()
+    }
+
+    pub fn as_bitslice(&self) -> &BitSlice<u8, Lsb0> {
+        &self.0
+    }
+
+    /// 和binlog中一样的字节，最后一个字节中多出的位为0
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.len().div_ceil(8)];
+        for index in self.0.iter_ones() {
+            bytes[index / 8] |= 1 << (index % 8);
+        }
+        bytes
+    }
+}
+
+impl std::ops::Index<usize> for Bitmap {
+    type Output = bool;
+
+    fn index(&self, index: usize) -> &bool {
+        &self.0[index]
+    }
+}
+
+impl FromIterator<bool> for Bitmap {
+    fn from_iter<T: IntoIterator<Item = bool>>(iter: T) -> Bitmap {
+        Bitmap(iter.into_iter().collect())
+    }
+}
+
+impl std::fmt::Debug for Bitmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for Bitmap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "schemars")]
+impl schemars::JsonSchema for Bitmap {
+    fn schema_name() -> String {
+        "Bitmap".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        <Vec<bool>>::json_schema(gen)
+    }
+}
+
+/// 行事件中的一个镜像：null bitmap和按字段顺序排列的值（null字段为ColumnValue::Null）
+pub type RowImage = (Bitmap, Vec<ColumnValue>);
+
+/// 一个行镜像，可以按序号或者字段名取值，区分不在镜像中的字段和值为NULL的字段
+#[derive(Debug, Clone)]
+pub struct Row {
+    values: Vec<ColumnValue>,
+    present: Bitmap,
+    column_index: Arc<ColumnIndex>,
+}
+
+impl Row {
+    /// values按表的字段顺序排列，present是镜像中有哪些字段
+    pub fn new(values: Vec<ColumnValue>, present: Bitmap, column_index: Arc<ColumnIndex>) -> Row {
+        Row {
+            values,
+            present,
+            column_index,
+        }
+    }
+
+    /// 第index个字段的值，不在镜像中的字段为None，NULL为Some(ColumnValue::Null)
+    pub fn get(&self, index: usize) -> Option<&ColumnValue> {
+        match self.is_present(index) {
+            true => self.values.get(index),
+            false => None,
+        }
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&ColumnValue> {
+        self.get(self.column_index.position(name)?)
+    }
+
+    /// 第index个字段是否在镜像中，FULL的镜像中所有字段都在
+    pub fn is_present(&self, index: usize) -> bool {
+        self.present.get(index).unwrap_or(false) && index < self.values.len()
+    }
+
+    /// 镜像中的字段，按字段顺序返回(字段名, 值)
+    pub fn columns(&self) -> impl Iterator<Item = (&str, &ColumnValue)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.is_present(*i))
+            .map(|(i, value)| (self.column_index.name(i).unwrap_or_default(), value))
+    }
+
+    /// 按字段顺序排列的所有值，不在镜像中的字段为ColumnValue::Null
+    pub fn values(&self) -> &[ColumnValue] {
+        &self.values
+    }
+
+    pub fn present(&self) -> &Bitmap {
+        &self.present
+    }
+
+    pub fn column_index(&self) -> &Arc<ColumnIndex> {
+        &self.column_index
+    }
+
+    /// self是update修改前的镜像，返回和修改后的镜像after相比改变了的字段序号
+    /// 不在after中的字段没有被修改（MINIMAL时after中只有修改过的字段），不算改变；
+    /// 在after中但不在before中的字段不知道原来的值，算作改变
+    pub fn changed_columns(&self, after: &Row) -> Vec<usize> {
+        (0..after.values.len())
+            .filter(|i| after.is_present(*i))
+            .filter(|i| match self.get(*i) {
+                Some(before) => Some(before) != after.get(*i),
+                None => true,
+            })
+            .collect()
+    }
+}
+
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+/// one row of insert update delete event
+/// insert only has after, delete only has before, update has both
+pub struct RowsEventRow {
+    pub before: Option<Vec<ColumnValue>>,
+    pub after: Option<Vec<ColumnValue>>,
+}
+
+impl RowsEventRow {
+    /// 按事件类型把镜像组合成行，update的每一行有前后两个镜像
+    pub fn from_images(images: Vec<RowImage>, type_code: u8) -> Vec<RowsEventRow> {
+        let mut values = images.into_iter().map(|image| image.1);
+
+        match type_code {
+            24 => {
+                let mut rows = Vec::new();
+                while let Some(before) = values.next() {
+                    rows.push(RowsEventRow {
+                        before: Some(before),
+                        after: values.next(),
+                    });
+                }
+                rows
+            }
+            25 => values
+                .map(|values| RowsEventRow {
+                    before: Some(values),
+                    after: None,
+                })
+                .collect(),
+            _ => values
+                .map(|values| RowsEventRow {
+                    before: None,
+                    after: Some(values),
+                })
+                .collect(),
+        }
+    }
+
+    /// update前后值不同的字段序号，insert和delete没有
+    /// 不区分不在镜像中的字段和NULL，MINIMAL、NOBLOB的行镜像用Row::changed_columns
+    pub fn changed_columns(&self) -> Vec<usize> {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => before
+                .iter()
+                .zip(after)
+                .enumerate()
+                .filter(|(_, (before, after))| before != after)
+                .map(|(index, _)| index)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// decoded value of a single column in a row image
+pub enum ColumnValue {
+    Null,
+    Int(i64),
+    /// 超过i64范围的BIGINT UNSIGNED，只有binlog_row_metadata=FULL时才知道是unsigned，其他整数都是Int
+    UInt(u64),
+    Float(f32),
+    Double(f64),
+    Decimal(String),
+    String(String),
+    Bytes(Vec<u8>),
+    Temporal(String),
+    /// `--raw-temporals`时的DATE、TIME2、DATETIME2、TIMESTAMP2，保留binlog中的各个部分
+    RawTemporal(RawTemporal),
+    /// `--raw-temporals`时的NEWDECIMAL，带上精度和小数位数
+    RawDecimal(RawDecimal),
+    /// 尚未实现解析的字段类型，里面是类型名
+    Unsupported(String),
+}
+
+/// 行事件中字段值的解码方式，默认和原来一样
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ValueOptions {
+    /// `--max-value-length`，超过这个长度的VARCHAR和BLOB只保留前面的部分，见truncate_binary
+    pub max_value_length: Option<usize>,
+    /// `--raw-temporals`，时间类型和NEWDECIMAL不格式化成字符串，见RawTemporal和RawDecimal
+    pub raw_temporals: bool,
+    /// `--lenient-row-images`，行镜像和table map不一致时不返回错误，记录在事件的row_image_mismatch中
+    pub lenient_row_images: bool,
+}
+
+/// 时间类型在binlog中的各个部分，由使用方自己处理时区和精度
+/// TIMESTAMP是UTC的秒数，不转换时区；其他类型没有时区，是写入时的值
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum RawTemporal {
+    Timestamp {
+        epoch: u32,
+        micros: u32,
+    },
+    DateTime {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+        micros: u32,
+    },
+    /// TIME可以是负数，hour最大838
+    Time {
+        negative: bool,
+        hour: u16,
+        minute: u8,
+        second: u8,
+        micros: u32,
+    },
+    Date {
+        year: u16,
+        month: u8,
+        day: u8,
+    },
+}
+
+/// TIMESTAMP是`1700000000.123456`这样的秒数，其他类型按MySQL的格式，微秒为0时不输出
+impl Display for RawTemporal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let micros = |micros: u32| match micros {
+            0 => String::new(),
+            micros => format!(".{:06}", micros),
+        };
+
+        match *self {
+            RawTemporal::Timestamp { epoch, micros: m } => write!(f, "{}{}", epoch, micros(m)),
+            RawTemporal::DateTime {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                micros: m,
+            } => write!(
+                f,
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}",
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                micros(m)
+            ),
+            RawTemporal::Time {
+                negative,
+                hour,
+                minute,
+                second,
+                micros: m,
+            } => write!(
+                f,
+                "{}{:02}:{:02}:{:02}{}",
+                if negative { "-" } else { "" },
+                hour,
+                minute,
+                second,
+                micros(m)
+            ),
+            RawTemporal::Date { year, month, day } => {
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)
+            }
+        }
+    }
+}
+
+/// NEWDECIMAL的精确值和table map中的精度
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct RawDecimal {
+    pub value: String,
+    pub precision: u8,
+    pub scale: u8,
+}
+
+impl ColumnValue {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ColumnValue::Null | ColumnValue::Unsupported(_) => serde_json::Value::Null,
+            ColumnValue::Int(v) => serde_json::Value::from(*v),
+            ColumnValue::UInt(v) => serde_json::Value::from(*v),
+            ColumnValue::Float(v) => serde_json::Value::from(*v),
+            ColumnValue::Double(v) => serde_json::Value::from(*v),
+            ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => {
+                serde_json::Value::from(v.as_str())
+            }
+            ColumnValue::Bytes(v) => serde_json::Value::from(BASE64_STANDARD.encode(v)),
+            ColumnValue::RawTemporal(v) => serde_json::to_value(v).unwrap_or_default(),
+            ColumnValue::RawDecimal(v) => serde_json::to_value(v).unwrap_or_default(),
+        }
+    }
+}
+
+// 和json输出的格式保持一致，二进制数据使用base64
+impl Serialize for ColumnValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+/// 和to_json一致：null、整数、浮点数、字符串（二进制数据是base64），`--raw-temporals`时还有两种对象
+#[cfg(feature = "schemars")]
+impl schemars::JsonSchema for ColumnValue {
+    fn schema_name() -> String {
+        "ColumnValue".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{SchemaObject, SubschemaValidation};
+
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![
+                    gen.subschema_for::<()>(),
+                    gen.subschema_for::<i64>(),
+                    gen.subschema_for::<u64>(),
+                    gen.subschema_for::<f64>(),
+                    gen.subschema_for::<String>(),
+                    gen.subschema_for::<RawTemporal>(),
+                    gen.subschema_for::<RawDecimal>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// 行事件中原有的column_data字段，不包含值为null的字段
+pub fn column_data_for_human(image: &[ColumnValue]) -> Vec<String> {
+    image
+        .iter()
+        .filter(|v| **v != ColumnValue::Null)
+        .map(|v| v.to_string())
+        .collect()
+}
+
+impl Display for ColumnValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnValue::Null => write!(f, "NULL"),
+            ColumnValue::Int(v) => write!(f, "{}", v),
+            ColumnValue::UInt(v) => write!(f, "{}", v),
+            ColumnValue::Float(v) => write!(f, "{}", v),
+            ColumnValue::Double(v) => write!(f, "{}", v),
+            ColumnValue::Decimal(v) | ColumnValue::Temporal(v) => write!(f, "{}", v),
+            ColumnValue::RawTemporal(v) => write!(f, "{}", v),
+            ColumnValue::RawDecimal(v) => write!(f, "{}", v.value),
+            ColumnValue::String(v) => write!(f, "this is a String, value is `{}`", v),
+            ColumnValue::Bytes(v) => write!(
+                f,
+                "this is not a String, value with base64 is {}",
+                BASE64_STANDARD.encode(v)
+            ),
+            ColumnValue::Unsupported(v) => write!(f, "type `{}` is not implement", v),
+        }
+    }
+}
+
+#[allow(unused)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// rand event
 pub struct EventBodyTypeCode13 {
     pub first_seed: u64,
@@ -179,7 +1339,8 @@ pub struct EventBodyTypeCode13 {
 impl EventBody for EventBodyTypeCode13 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// start encryption event
 pub struct EventBodyTypeCode164 {
     pub encryption_scheme: u8,
@@ -190,20 +1351,22 @@ pub struct EventBodyTypeCode164 {
 impl EventBody for EventBodyTypeCode164 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// XA prepare log event
 pub struct EventBodyTypeCode38 {
     pub one_phase_commit: u8,
     pub format_id: u32,
     pub length_of_gtrid: u32,
-    pub length_of_bqual: u8,
+    pub length_of_bqual: u32,
     pub xid: Vec<u8>,
 }
 
 impl EventBody for EventBodyTypeCode38 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 /// user var event
 pub struct EventBodyTypeCode14 {
     pub length_of_user_variable_name: u32,
@@ -212,6 +1375,8 @@ pub struct EventBodyTypeCode14 {
     pub variable_type: Option<u8>,
     pub variable_type_string_for_human: Option<String>,
     pub collation_number: Option<u32>,
+    /// 例如`utf8mb4_general_ci (45)`
+    pub collation_string_for_human: Option<String>,
     pub length_of_value: Option<u32>,
     pub value: Option<String>,
     pub flags: Option<u8>,
@@ -220,11 +1385,32 @@ pub struct EventBodyTypeCode14 {
 impl EventBody for EventBodyTypeCode14 {}
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct EventBodyTypeSkip(pub u8);
 
 impl EventBody for EventBodyTypeSkip {}
 
+/// `--report-missing-table-maps`时代替table map缺失的行事件，bytes是事件的长度
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct EventBodyUndecodableRows {
+    pub table_id: u64,
+    pub bytes: u32,
+}
+
+impl EventBody for EventBodyUndecodableRows {}
+
+/// `--skip-errors`时代替解码失败的事件，bytes是事件的长度，reason是失败的原因
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct EventBodyUndecoded {
+    pub bytes: u32,
+    pub reason: String,
+}
+
+impl EventBody for EventBodyUndecoded {}
+
 #[derive(Debug)]
 pub struct MyError(pub String);
 
@@ -235,3 +1421,528 @@ impl Display for MyError {
         write!(f, "some error occoured: {}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    // COLUMN_NAME可选元数据，可以比字段少
+    fn column_names(names: &[&str]) -> Vec<u8> {
+        let value = names
+            .iter()
+            .flat_map(|v| [&[v.len() as u8], v.as_bytes()].concat())
+            .collect::<Vec<u8>>();
+
+        [vec![4, value.len() as u8], value].concat()
+    }
+
+    fn users(names: &[&str]) -> TableMapBuilder {
+        TableMapBuilder::new("shop", "users")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true)
+            .optional_metadata(&column_names(names))
+    }
+
+    fn row(id: i64, name: Option<&str>, email: Option<&str>) -> Vec<ColumnValue> {
+        let text = |v: Option<&str>| match v {
+            Some(v) => ColumnValue::String(v.to_string()),
+            None => ColumnValue::Null,
+        };
+
+        vec![ColumnValue::Int(id), text(name), text(email)]
+    }
+
+    // 解析table map和它后面的行事件
+    fn parse(
+        table: &TableMapBuilder,
+        rows_event: &RowsEventBuilder,
+    ) -> (Arc<EventBodyTypeCode19>, EventBodyTypeCode23To25) {
+        let binlog = BinlogBuilder::new().event(table).event(rows_event).build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        let mut rows_events = Vec::new();
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            rows_events.extend(body.downcast_ref::<EventBodyTypeCode23To25>().cloned());
+
+            position = header.next_event_position as u64;
+        }
+
+        (table_structs[&70].clone(), rows_events.remove(0))
+    }
+
+    fn columns(row: &Row) -> Vec<(&str, &ColumnValue)> {
+        row.columns().collect()
+    }
+
+    #[test]
+    fn access_by_name_and_index() {
+        let table = users(&["id", "name", "email"]);
+        let insert = RowsEventBuilder::insert(&table)
+            .row(row(1, Some("ann"), None))
+            .row(row(2, Some("bob"), Some("bob@example.com")));
+        let (table_info, rows_event) = parse(&table, &insert);
+
+        let rows = rows_event.rows(&table_info).unwrap();
+        assert_eq!(rows.len(), 2);
+
+        let ann = &rows[0];
+        assert_eq!(ann.get(0), Some(&ColumnValue::Int(1)));
+        assert_eq!(ann.get_by_name("name"), ann.get(1));
+        // NULL和不存在的字段不同
+        assert_eq!(ann.get_by_name("email"), Some(&ColumnValue::Null));
+        assert_eq!(ann.get_by_name("phone"), None);
+        assert_eq!(ann.get(3), None);
+        assert_eq!(
+            columns(ann),
+            [
+                ("id", &ColumnValue::Int(1)),
+                ("name", &ColumnValue::String("ann".to_string())),
+                ("email", &ColumnValue::Null),
+            ]
+        );
+
+        // 同一个table map的行共用一个索引
+        assert!(Arc::ptr_eq(ann.column_index(), rows[1].column_index()));
+        assert!(Arc::ptr_eq(ann.column_index(), &table_info.column_index()));
+    }
+
+    // MINIMAL的delete只有主键，update修改后的镜像只有修改过的字段
+    #[test]
+    fn missing_columns_in_minimal_image() {
+        let table = users(&["id", "name", "email"]);
+
+        let delete = RowsEventBuilder::delete(&table)
+            .columns_used(&[true, false, false])
+            .row(row(1, None, None));
+        let (table_info, rows_event) = parse(&table, &delete);
+        let rows = rows_event.rows(&table_info).unwrap();
+        assert_eq!(columns(&rows[0]), [("id", &ColumnValue::Int(1))]);
+        assert!(!rows[0].is_present(1));
+        assert_eq!(rows[0].get_by_name("name"), None);
+        assert_eq!(rows[0].get_by_name("email"), None);
+
+        let update = RowsEventBuilder::update(&table)
+            .columns_used(&[true, false, false])
+            .columns_used_for_update(&[false, false, true])
+            .update_row(row(1, None, None), row(1, None, None));
+        let (table_info, rows_event) = parse(&table, &update);
+        let rows = rows_event.rows(&table_info).unwrap();
+        assert_eq!(columns(&rows[0]), [("email", &ColumnValue::Null)]);
+        assert_eq!(rows[0].get_by_name("id"), None);
+        assert_eq!(rows[0].get_by_name("email"), Some(&ColumnValue::Null));
+    }
+
+    // 没有字段名时是col_N，和真实的字段名重复时按名字取第一个
+    #[test]
+    fn duplicate_placeholder_names() {
+        let table = users(&[]).optional_metadata(&[]);
+        let insert = RowsEventBuilder::insert(&table).row(row(1, Some("ann"), None));
+        let (table_info, rows_event) = parse(&table, &insert);
+        let rows = rows_event.rows(&table_info).unwrap();
+        assert_eq!(
+            columns(&rows[0]).iter().map(|v| v.0).collect::<Vec<&str>>(),
+            ["col_1", "col_2", "col_3"]
+        );
+        assert_eq!(rows[0].get_by_name("col_2"), rows[0].get(1));
+
+        // 只有前两个字段有名字，第一个字段叫col_3
+        let table = users(&["col_3", "name"]);
+        let insert = RowsEventBuilder::insert(&table).row(row(1, Some("ann"), Some("a@b")));
+        let (table_info, rows_event) = parse(&table, &insert);
+        let rows = rows_event.rows(&table_info).unwrap();
+        assert_eq!(
+            columns(&rows[0]).iter().map(|v| v.0).collect::<Vec<&str>>(),
+            ["col_3", "name", "col_3"]
+        );
+        assert_eq!(rows[0].get_by_name("col_3"), Some(&ColumnValue::Int(1)));
+        assert_eq!(table_info.column_index().position("col_3"), Some(0));
+    }
+
+    fn pairs(update: &RowsEventBuilder) -> Vec<(Row, Row)> {
+        let table = users(&["id", "name", "email"]);
+        let (table_info, rows_event) = parse(&table, update);
+
+        rows_event.pairs(&table_info).unwrap().collect()
+    }
+
+    #[test]
+    fn changed_columns_in_full_image() {
+        let table = users(&["id", "name", "email"]);
+        let update = RowsEventBuilder::update(&table)
+            // NULL改为值，值改为NULL
+            .update_row(row(1, None, Some("a@b")), row(1, Some("ann"), None))
+            // 没有改变
+            .update_row(row(2, Some("bob"), None), row(2, Some("bob"), None));
+
+        let pairs = pairs(&update);
+        assert_eq!(pairs.len(), 2);
+
+        let (before, after) = &pairs[0];
+        assert_eq!(before.changed_columns(after), [1, 2]);
+        assert_eq!(before.get_by_name("name"), Some(&ColumnValue::Null));
+        assert_eq!(after.get_by_name("email"), Some(&ColumnValue::Null));
+
+        let (before, after) = &pairs[1];
+        assert!(before.changed_columns(after).is_empty());
+    }
+
+    // 修改前只有主键，修改后只有写了的字段，不在修改后镜像中的字段没有改变
+    #[test]
+    fn changed_columns_in_minimal_image() {
+        let table = users(&["id", "name", "email"]);
+
+        let update = RowsEventBuilder::update(&table)
+            .columns_used(&[true, false, false])
+            .columns_used_for_update(&[false, true, false])
+            .update_row(row(1, None, None), row(1, Some("ann"), None));
+        let (before, after) = &pairs(&update)[0];
+        assert_eq!(before.changed_columns(after), [1]);
+        assert_eq!(
+            after.get_by_name("name"),
+            Some(&ColumnValue::String("ann".to_string()))
+        );
+        // 修改前的值不知道
+        assert_eq!(before.get_by_name("name"), None);
+        assert_eq!(after.get_by_name("email"), None);
+
+        let update = RowsEventBuilder::update(&table)
+            .columns_used(&[true, false, false])
+            .columns_used_for_update(&[false, false, true])
+            .update_row(row(1, None, None), row(1, None, None));
+        let (before, after) = &pairs(&update)[0];
+        assert_eq!(before.changed_columns(after), [2]);
+        assert_eq!(after.get_by_name("email"), Some(&ColumnValue::Null));
+
+        // 两个镜像中都有并且值相同的字段没有改变
+        let update = RowsEventBuilder::update(&table)
+            .columns_used(&[true, true, false])
+            .columns_used_for_update(&[false, true, true])
+            .update_row(row(1, Some("ann"), None), row(1, Some("ann"), Some("a@b")));
+        let (before, after) = &pairs(&update)[0];
+        assert_eq!(before.changed_columns(after), [2]);
+    }
+
+    #[test]
+    fn no_pairs_for_insert_and_delete() {
+        let table = users(&["id", "name", "email"]);
+        let insert = RowsEventBuilder::insert(&table).row(row(1, None, None));
+        let delete = RowsEventBuilder::delete(&table).row(row(1, None, None));
+
+        assert!(pairs(&insert).is_empty());
+        assert!(pairs(&delete).is_empty());
+    }
+
+    #[test]
+    fn column_defs_of_a_mixed_table() {
+        // id INT UNSIGNED, price DECIMAL(10,2), name VARCHAR(255) utf8mb4, created DATETIME(3), body BLOB
+        let mut optional_metadata = vec![1, 1, 0b1000_0000, 3, 2, 45, 63];
+        optional_metadata.extend(column_names(&["id", "price", "name", "created", "body"]));
+        let table = TableMapBuilder::new("shop", "users")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::NewDecimal, &[10, 2])
+            .column(FieldType::Varchar, &1020u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::DateTime2, &[3])
+            .nullable(true)
+            .column(FieldType::Blob, &[2])
+            .nullable(true)
+            .optional_metadata(&optional_metadata);
+        // 12.34
+        let price = vec![0x80, 0, 0, 12, 34];
+        let rows_event = RowsEventBuilder::insert(&table).row(vec![
+            ColumnValue::Int(1),
+            ColumnValue::Bytes(price),
+            ColumnValue::String("alice".to_string()),
+            ColumnValue::Null,
+            ColumnValue::Null,
+        ]);
+        let (table_map, rows_event) = parse(&table, &rows_event);
+
+        let column = |field_type: FieldType, metadata_raw: &[u8], description: &str| ColumnDef {
+            field_type,
+            metadata_raw: metadata_raw.to_vec(),
+            metadata_description: description.to_string(),
+            nullable: true,
+            charset: None,
+            unsigned: None,
+            name: None,
+        };
+        assert_eq!(
+            table_map.columns,
+            [
+                ColumnDef {
+                    nullable: false,
+                    unsigned: Some(true),
+                    name: Some("id".to_string()),
+                    ..column(FieldType::Long, &[], "")
+                },
+                ColumnDef {
+                    nullable: false,
+                    unsigned: Some(false),
+                    name: Some("price".to_string()),
+                    ..column(
+                        FieldType::NewDecimal,
+                        &[10, 2],
+                        "the length of precision is 10, the length of decimals is 2"
+                    )
+                },
+                ColumnDef {
+                    charset: Some(45),
+                    name: Some("name".to_string()),
+                    ..column(
+                        FieldType::Varchar,
+                        &[252, 3],
+                        "the maximum length of the string is 1020 byte"
+                    )
+                },
+                ColumnDef {
+                    name: Some("created".to_string()),
+                    ..column(
+                        FieldType::DateTime2,
+                        &[3],
+                        "the number of decimals for the fractional part is 3"
+                    )
+                },
+                ColumnDef {
+                    charset: Some(63),
+                    name: Some("body".to_string()),
+                    ..column(FieldType::Blob, &[2], "field size is 2 bytes")
+                },
+            ]
+        );
+        assert_eq!(table_map.columns[2].type_code(), 15);
+        assert_eq!(table_map.columns[2].type_name(), "MYSQL_TYPE_VARCHAR");
+        assert_eq!(table_map.columns[0].metadata_for_human(), "");
+
+        // 旧的按下标对应的数组和columns一致，没有元数据的字段不在其中
+        #[allow(deprecated)]
+        {
+            assert_eq!(table_map.column_types, [3, 246, 15, 18, 252]);
+            assert_eq!(table_map.metadata_block_data_raw.len(), 4);
+            assert_eq!(
+                table_map.metadata_block_string_for_human[0],
+                table_map.columns[1].metadata_for_human()
+            );
+            assert_eq!(
+                table_map.columns_can_be_null.iter().collect::<Vec<_>>(),
+                [false, false, true, true, true]
+            );
+        }
+
+        // 行事件按columns解码
+        let rows = rows_event.rows(&table_map).unwrap();
+        assert_eq!(
+            columns(&rows[0]),
+            [
+                ("id", &ColumnValue::Int(1)),
+                ("price", &ColumnValue::Decimal("12.34".to_string())),
+                ("name", &ColumnValue::String("alice".to_string())),
+                ("created", &ColumnValue::Null),
+                ("body", &ColumnValue::Null),
+            ]
+        );
+    }
+
+    // 解析一个query事件，返回header和body
+    fn query_event(query: QueryEventBuilder) -> (EventHeader, Box<dyn EventBody>) {
+        let binlog = BinlogBuilder::new()
+            .push(EventBuilder::from_body(&query).timestamp(1_700_000_000))
+            .build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        loop {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            if header.type_code == 2 {
+                return (header, body);
+            }
+            position = header.next_event_position as u64;
+        }
+    }
+
+    #[test]
+    fn event_time_with_hrnow() {
+        // Q_HRNOW是123456微秒
+        let query =
+            QueryEventBuilder::new("shop", "BEGIN").status_variables(&[0x80, 0x40, 0xe2, 0x01]);
+        let (header, body) = query_event(query);
+
+        let event_time = EventTime::new(&header, body.as_ref());
+        assert_eq!(event_time.to_string(), "2023-11-14 22:13:20.123456");
+        assert_eq!(event_time.as_micros(), 1_700_000_000_123_456);
+        assert_eq!(
+            serde_json::to_value(event_time).unwrap(),
+            serde_json::json!({"seconds": 1_700_000_000u32, "microseconds": 123456})
+        );
+
+        let output = crate::style::Styler::new(crate::style::ColorChoice::Never)
+            .event(&header, body.as_ref());
+        assert!(output.contains("timestamp: 1700000000, // 2023-11-14 22:13:20.123456 UTC\n"));
+
+        // 没有Q_HRNOW时只到秒
+        let (header, body) = query_event(QueryEventBuilder::new("shop", "BEGIN"));
+        let event_time = EventTime::new(&header, body.as_ref());
+        assert_eq!(event_time.to_string(), "2023-11-14 22:13:20");
+        assert_eq!(event_time.as_micros(), 1_700_000_000_000_000);
+        let output = crate::style::Styler::new(crate::style::ColorChoice::Never)
+            .event(&header, body.as_ref());
+        assert!(!output.contains("UTC"));
+    }
+
+    // shop.docs(id, title, body)的主键是id，body是BLOB
+    fn docs() -> TableMapBuilder {
+        TableMapBuilder::new("shop", "docs")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Blob, &[2])
+            .nullable(true)
+            .optional_metadata(&[column_names(&["id", "title", "body"]), vec![8, 1, 0]].concat())
+    }
+
+    fn doc(id: i64, title: &str, body: &str) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(title.to_string()),
+            ColumnValue::String(body.to_string()),
+        ]
+    }
+
+    fn row_image(rows_event: &RowsEventBuilder) -> Inferred<RowImageMode> {
+        parse(&docs(), rows_event).1.row_image
+    }
+
+    #[test]
+    fn row_image_of_each_setting() {
+        let table = docs();
+        let certain = |value| Inferred {
+            value,
+            certain: true,
+        };
+        let probably = |value| Inferred {
+            value,
+            certain: false,
+        };
+
+        // FULL：只有update修改前后都完整时才确定
+        let update =
+            RowsEventBuilder::update(&table).update_row(doc(1, "a", "x"), doc(1, "b", "x"));
+        assert_eq!(row_image(&update), certain(RowImageMode::Full));
+        let insert = RowsEventBuilder::insert(&table).row(doc(1, "a", "x"));
+        assert_eq!(row_image(&insert), probably(RowImageMode::Full));
+
+        // MINIMAL：修改前只有主键，修改后只有修改过的字段
+        let update = RowsEventBuilder::update(&table)
+            .columns_used(&[true, false, false])
+            .columns_used_for_update(&[false, true, false])
+            .update_row(doc(1, "a", "x"), doc(1, "b", "x"));
+        assert_eq!(row_image(&update), certain(RowImageMode::Minimal));
+        let delete = RowsEventBuilder::delete(&table)
+            .columns_used(&[true, false, false])
+            .row(doc(1, "a", "x"));
+        assert_eq!(row_image(&delete), certain(RowImageMode::Minimal));
+
+        // NOBLOB：只缺少没有修改的BLOB
+        let update = RowsEventBuilder::update(&table)
+            .columns_used(&[true, true, false])
+            .columns_used_for_update(&[true, true, false])
+            .update_row(doc(1, "a", "x"), doc(1, "b", "x"));
+        assert_eq!(row_image(&update), probably(RowImageMode::Noblob));
+        let insert = RowsEventBuilder::insert(&table)
+            .columns_used(&[true, true, false])
+            .row(doc(1, "a", "x"));
+        assert_eq!(row_image(&insert), probably(RowImageMode::Noblob));
+
+        // 不知道表结构时缺少字段按MINIMAL，不确定
+        let (_, rows_event) = parse(&table, &update);
+        assert_eq!(
+            rows_event.infer_row_image(None),
+            probably(RowImageMode::Minimal)
+        );
+    }
+
+    #[test]
+    fn row_image_in_the_output() {
+        let table = docs();
+        let full = parse(
+            &table,
+            &RowsEventBuilder::insert(&table).row(doc(1, "a", "x")),
+        )
+        .1;
+        let json = serde_json::to_value(&full).unwrap();
+        assert!(json.get("row_image").is_none());
+        assert!(!format!("{:?}", full).contains("row_image"));
+
+        let noblob = parse(
+            &table,
+            &RowsEventBuilder::update(&table)
+                .columns_used(&[true, true, false])
+                .columns_used_for_update(&[true, true, false])
+                .update_row(doc(1, "a", "x"), doc(1, "b", "x")),
+        )
+        .1;
+        assert_eq!(
+            serde_json::to_value(&noblob).unwrap()["row_image"],
+            serde_json::json!({"value": "NOBLOB", "certain": false})
+        );
+        assert_eq!(noblob.row_image.to_string(), "probably NOBLOB");
+    }
+
+    #[test]
+    fn sql_fails_early_without_columns_to_locate_the_row() {
+        let table = docs();
+        let (table_map, delete) = parse(
+            &table,
+            &RowsEventBuilder::delete(&table)
+                .columns_used(&[false, false, false])
+                .row(doc(1, "a", "x")),
+        );
+        assert_eq!(delete.row_image.value, RowImageMode::Minimal);
+
+        let column_names = ["id", "title", "body"].map(String::from);
+        let error = crate::sql::row_statements(
+            25,
+            &delete,
+            &table_map,
+            &column_names,
+            false,
+            crate::cli::InsertMode::Insert,
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().ends_with(
+            "the row image of `shop`.`docs` is MINIMAL and has no column to locate the row"
+        ));
+    }
+}
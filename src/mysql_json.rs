@@ -0,0 +1,590 @@
+use base64::prelude::*;
+use serde_json::{Map, Number, Value};
+
+use crate::model::MyError;
+use crate::util::bin_to_decimal;
+
+// MySQL的JSON字段在行事件中是二进制格式
+// 参考 https://github.com/mysql/mysql-server/blob/8.0/sql-common/json_binary.h
+const SMALL_OBJECT: u8 = 0x00;
+const LARGE_OBJECT: u8 = 0x01;
+const SMALL_ARRAY: u8 = 0x02;
+const LARGE_ARRAY: u8 = 0x03;
+const LITERAL: u8 = 0x04;
+const INT16: u8 = 0x05;
+const UINT16: u8 = 0x06;
+const INT32: u8 = 0x07;
+const UINT32: u8 = 0x08;
+const INT64: u8 = 0x09;
+const UINT64: u8 = 0x0a;
+const DOUBLE: u8 = 0x0b;
+const STRING: u8 = 0x0c;
+const OPAQUE: u8 = 0x0f;
+
+const LITERAL_NULL: u8 = 0x00;
+const LITERAL_TRUE: u8 = 0x01;
+const LITERAL_FALSE: u8 = 0x02;
+
+// opaque中可以解码的字段类型
+const FIELD_TYPE_TIMESTAMP: u8 = 7;
+const FIELD_TYPE_DATE: u8 = 10;
+const FIELD_TYPE_TIME: u8 = 11;
+const FIELD_TYPE_DATETIME: u8 = 12;
+const FIELD_TYPE_NEWDECIMAL: u8 = 246;
+
+// 和MySQL的JSON_DOCUMENT_MAX_DEPTH一样，更深的对象和数组MySQL不会写入，解码时不再递归
+const MAX_DEPTH: usize = 100;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 把JSON字段的值转换成serde_json::Value，长度为0的值是JSON的null
+pub fn parse_json_binary(buffer: &[u8]) -> Result<Value, BoxedError> {
+    match buffer.split_first() {
+        None => Ok(Value::Null),
+        Some((type_code, data)) => parse_value(*type_code, data, 0),
+    }
+}
+
+// depth是外面的对象和数组的层数
+fn parse_value(type_code: u8, data: &[u8], depth: usize) -> Result<Value, BoxedError> {
+    let value = match type_code {
+        SMALL_OBJECT => parse_container(data, 2, true, depth + 1)?,
+        LARGE_OBJECT => parse_container(data, 4, true, depth + 1)?,
+        SMALL_ARRAY => parse_container(data, 2, false, depth + 1)?,
+        LARGE_ARRAY => parse_container(data, 4, false, depth + 1)?,
+        LITERAL => match read(data, 0, 1)?[0] {
+            LITERAL_NULL => Value::Null,
+            LITERAL_TRUE => Value::Bool(true),
+            LITERAL_FALSE => Value::Bool(false),
+            others => {
+                return Err(Box::new(MyError(format!(
+                    "unknown json literal {:#04x}",
+                    others
+                ))))
+            }
+        },
+        INT16 => Value::from(i16::from_le_bytes(read(data, 0, 2)?.try_into()?)),
+        UINT16 => Value::from(u16::from_le_bytes(read(data, 0, 2)?.try_into()?)),
+        INT32 => Value::from(i32::from_le_bytes(read(data, 0, 4)?.try_into()?)),
+        UINT32 => Value::from(u32::from_le_bytes(read(data, 0, 4)?.try_into()?)),
+        INT64 => Value::from(i64::from_le_bytes(read(data, 0, 8)?.try_into()?)),
+        UINT64 => Value::from(u64::from_le_bytes(read(data, 0, 8)?.try_into()?)),
+        DOUBLE => {
+            let value = f64::from_le_bytes(read(data, 0, 8)?.try_into()?);
+            Number::from_f64(value)
+                .map(Value::Number)
+                .ok_or_else(|| MyError(format!("json double {} is not a number", value)))?
+        }
+        STRING => {
+            let (length, skip) = parse_variable_length(data)?;
+            Value::from(String::from_utf8(read(data, skip, length)?.to_vec())?)
+        }
+        OPAQUE => {
+            let field_type = read(data, 0, 1)?[0];
+            let (length, skip) = parse_variable_length(&data[1..])?;
+            parse_opaque(field_type, read(data, 1 + skip, length)?)?
+        }
+        others => {
+            return Err(Box::new(MyError(format!(
+                "unknown json value type {:#04x}",
+                others
+            ))))
+        }
+    };
+
+    Ok(value)
+}
+
+/// 对象和数组的开头是元素个数和总字节数，然后是key的位置和长度（只有对象有）、值的类型和位置，
+/// 位置都是相对于对象或者数组开头的。offset_size在small中是2，large中是4
+fn parse_container(
+    data: &[u8],
+    offset_size: usize,
+    is_object: bool,
+    depth: usize,
+) -> Result<Value, BoxedError> {
+    if depth > MAX_DEPTH {
+        return Err(Box::new(MyError(format!(
+            "json value is nested deeper than {} levels",
+            MAX_DEPTH
+        ))));
+    }
+
+    let count = read_uint(data, 0, offset_size)?;
+    let header_length = 2 * offset_size;
+    let key_entry_length = offset_size + 2;
+    let value_entry_length = 1 + offset_size;
+
+    let value_entries = match is_object {
+        true => header_length + count * key_entry_length,
+        false => header_length,
+    };
+
+    let mut values = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = value_entries + i * value_entry_length;
+        let type_code = read(data, entry, 1)?[0];
+
+        // 能放进位置里的值直接写在这里
+        let inlined = match type_code {
+            LITERAL | INT16 | UINT16 => true,
+            INT32 | UINT32 => offset_size == 4,
+            _ => false,
+        };
+
+        let value = match inlined {
+            true => parse_value(type_code, read(data, entry + 1, offset_size)?, depth)?,
+            false => {
+                let value_offset = read_uint(data, entry + 1, offset_size)?;
+                let value = data
+                    .get(value_offset..)
+                    .ok_or_else(|| MyError("json value is truncated".to_string()))?;
+                parse_value(type_code, value, depth)?
+            }
+        };
+
+        values.push(value);
+    }
+
+    if !is_object {
+        return Ok(Value::Array(values));
+    }
+
+    let mut object = Map::new();
+    for (i, value) in values.into_iter().enumerate() {
+        let entry = header_length + i * key_entry_length;
+        let key_offset = read_uint(data, entry, offset_size)?;
+        let key_length = read_uint(data, entry + offset_size, 2)?;
+        let key = String::from_utf8(read(data, key_offset, key_length)?.to_vec())?;
+
+        object.insert(key, value);
+    }
+
+    Ok(Value::Object(object))
+}
+
+/// 其他类型的值，decimal和时间类型解码，其余的按MySQL的方式输出成`base64:type15:...`
+fn parse_opaque(field_type: u8, value: &[u8]) -> Result<Value, BoxedError> {
+    let result = match field_type {
+        // 开头是精度和小数位数
+        FIELD_TYPE_NEWDECIMAL if value.len() > 2 => {
+            let (decimal, _) = bin_to_decimal(
+                &mut value[2..].to_vec(),
+                value[0] as usize,
+                value[1] as usize,
+            )?;
+            Value::from(decimal)
+        }
+        FIELD_TYPE_DATE | FIELD_TYPE_DATETIME | FIELD_TYPE_TIMESTAMP | FIELD_TYPE_TIME
+            if value.len() >= 8 =>
+        {
+            let packed = i64::from_le_bytes(value[..8].try_into()?);
+            Value::from(format_packed_temporal(field_type, packed))
+        }
+        _ => Value::from(format!(
+            "base64:type{}:{}",
+            field_type,
+            BASE64_STANDARD.encode(value)
+        )),
+    };
+
+    Ok(result)
+}
+
+/// 时间类型在JSON中是MySQL内部的packed格式，低24位是微秒
+fn format_packed_temporal(field_type: u8, packed: i64) -> String {
+    let sign = if packed < 0 { "-" } else { "" };
+    let packed = packed.unsigned_abs();
+
+    let microsecond = packed % (1 << 24);
+    let value = packed >> 24;
+
+    // TIME的小时可以超过24，不分日期和时间
+    if field_type == FIELD_TYPE_TIME {
+        return format!(
+            "{}{:02}:{:02}:{:02}.{:06}",
+            sign,
+            value >> 12,
+            (value >> 6) % (1 << 6),
+            value % (1 << 6),
+            microsecond
+        );
+    }
+
+    let ymd = value >> 17;
+    let hms = value % (1 << 17);
+    let day = ymd % (1 << 5);
+    let month = (ymd >> 5) % 13;
+    let year = (ymd >> 5) / 13;
+
+    if field_type == FIELD_TYPE_DATE {
+        return format!("{:04}-{:02}-{:02}", year, month, day);
+    }
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+        year,
+        month,
+        day,
+        hms >> 12,
+        (hms >> 6) % (1 << 6),
+        hms % (1 << 6),
+        microsecond
+    )
+}
+
+/// 字符串和opaque的长度，每个字节的低7位是数据，最高位表示后面还有
+fn parse_variable_length(data: &[u8]) -> Result<(usize, usize), BoxedError> {
+    let mut length = 0;
+
+    for (i, byte) in data.iter().take(5).enumerate() {
+        length |= ((byte & 0x7f) as usize) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok((length, i + 1));
+        }
+    }
+
+    Err(Box::new(MyError(
+        "json variable length is truncated".to_string(),
+    )))
+}
+
+fn read(data: &[u8], offset: usize, length: usize) -> Result<&[u8], BoxedError> {
+    data.get(offset..offset + length)
+        .ok_or_else(|| Box::new(MyError("json value is truncated".to_string())) as BoxedError)
+}
+
+// 小端的1到4个字节
+fn read_uint(data: &[u8], offset: usize, length: usize) -> Result<usize, BoxedError> {
+    let mut buffer = [0u8; 8];
+    buffer[..length].copy_from_slice(read(data, offset, length)?);
+
+    Ok(u64::from_le_bytes(buffer) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // 按json_binary.cc的格式编码，对象和数组在large时用4个字节的位置
+    fn encode(value: &Value, large: bool) -> (u8, Vec<u8>) {
+        match value {
+            Value::Null => (LITERAL, vec![LITERAL_NULL]),
+            Value::Bool(true) => (LITERAL, vec![LITERAL_TRUE]),
+            Value::Bool(false) => (LITERAL, vec![LITERAL_FALSE]),
+            Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+                (Some(v), _) if i16::try_from(v).is_ok() => {
+                    (INT16, (v as i16).to_le_bytes().to_vec())
+                }
+                (_, Some(v)) if u16::try_from(v).is_ok() => {
+                    (UINT16, (v as u16).to_le_bytes().to_vec())
+                }
+                (Some(v), _) if i32::try_from(v).is_ok() => {
+                    (INT32, (v as i32).to_le_bytes().to_vec())
+                }
+                (_, Some(v)) if u32::try_from(v).is_ok() => {
+                    (UINT32, (v as u32).to_le_bytes().to_vec())
+                }
+                (Some(v), _) => (INT64, v.to_le_bytes().to_vec()),
+                (_, Some(v)) => (UINT64, v.to_le_bytes().to_vec()),
+                _ => (DOUBLE, number.as_f64().unwrap().to_le_bytes().to_vec()),
+            },
+            Value::String(string) => {
+                let mut data = variable_length(string.len());
+                data.extend(string.as_bytes());
+                (STRING, data)
+            }
+            Value::Array(array) => {
+                let data = encode_container(array.iter().map(|v| (None, v)).collect(), large);
+                (if large { LARGE_ARRAY } else { SMALL_ARRAY }, data)
+            }
+            Value::Object(object) => {
+                let data = encode_container(
+                    object.iter().map(|(k, v)| (Some(k.as_str()), v)).collect(),
+                    large,
+                );
+                (if large { LARGE_OBJECT } else { SMALL_OBJECT }, data)
+            }
+        }
+    }
+
+    fn encode_container(entries: Vec<(Option<&str>, &Value)>, large: bool) -> Vec<u8> {
+        let offset_size = if large { 4 } else { 2 };
+        let uint = |v: usize| v.to_le_bytes()[..offset_size].to_vec();
+        let key_count = entries.iter().filter(|v| v.0.is_some()).count();
+        let header_length =
+            2 * offset_size + key_count * (offset_size + 2) + entries.len() * (1 + offset_size);
+
+        let mut key_entries = Vec::new();
+        let mut keys: Vec<u8> = Vec::new();
+        for key in entries.iter().filter_map(|v| v.0) {
+            key_entries.extend(uint(header_length + keys.len()));
+            key_entries.extend((key.len() as u16).to_le_bytes());
+            keys.extend(key.as_bytes());
+        }
+
+        let mut value_entries = Vec::new();
+        let mut values = Vec::new();
+        for (_, value) in &entries {
+            let (type_code, mut data) = encode(value, large);
+            value_entries.push(type_code);
+            let inlined = match type_code {
+                LITERAL | INT16 | UINT16 => true,
+                INT32 | UINT32 => large,
+                _ => false,
+            };
+            if inlined {
+                data.resize(offset_size, 0);
+                value_entries.extend(data);
+            } else {
+                value_entries.extend(uint(header_length + keys.len() + values.len()));
+                values.extend(data);
+            }
+        }
+
+        let mut data = uint(entries.len());
+        data.extend(uint(header_length + keys.len() + values.len()));
+        data.extend(key_entries);
+        data.extend(value_entries);
+        data.extend(keys);
+        data.extend(values);
+        data
+    }
+
+    fn variable_length(mut length: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        loop {
+            let byte = (length & 0x7f) as u8;
+            length >>= 7;
+            if length == 0 {
+                data.push(byte);
+                return data;
+            }
+            data.push(byte | 0x80);
+        }
+    }
+
+    fn parse(type_code: u8, data: &[u8]) -> Result<Value, BoxedError> {
+        let mut buffer = vec![type_code];
+        buffer.extend(data);
+        parse_json_binary(&buffer)
+    }
+
+    fn round_trip(value: &Value, large: bool) -> Value {
+        let (type_code, data) = encode(value, large);
+        parse(type_code, &data).unwrap()
+    }
+
+    #[test]
+    fn scalars_of_each_type() {
+        // 长度为0是null，和字面量的null一样
+        assert_eq!(parse_json_binary(&[]).unwrap(), Value::Null);
+        assert_eq!(parse(LITERAL, &[LITERAL_NULL]).unwrap(), Value::Null);
+        assert_eq!(parse(LITERAL, &[LITERAL_TRUE]).unwrap(), json!(true));
+        assert_eq!(parse(LITERAL, &[LITERAL_FALSE]).unwrap(), json!(false));
+
+        assert_eq!(parse(INT16, &(-2i16).to_le_bytes()).unwrap(), json!(-2));
+        assert_eq!(
+            parse(UINT16, &u16::MAX.to_le_bytes()).unwrap(),
+            json!(65535)
+        );
+        assert_eq!(
+            parse(INT32, &i32::MIN.to_le_bytes()).unwrap(),
+            json!(i32::MIN)
+        );
+        assert_eq!(
+            parse(UINT32, &u32::MAX.to_le_bytes()).unwrap(),
+            json!(u32::MAX)
+        );
+        assert_eq!(
+            parse(INT64, &i64::MIN.to_le_bytes()).unwrap(),
+            json!(i64::MIN)
+        );
+        assert_eq!(
+            parse(UINT64, &u64::MAX.to_le_bytes()).unwrap(),
+            json!(u64::MAX)
+        );
+        assert_eq!(parse(DOUBLE, &1.5f64.to_le_bytes()).unwrap(), json!(1.5));
+
+        assert_eq!(parse(STRING, b"\x03abc").unwrap(), json!("abc"));
+        // 超过127个字节的长度用两个字节
+        let long = "x".repeat(200);
+        let mut data = vec![0xc8, 0x01];
+        data.extend(long.as_bytes());
+        assert_eq!(parse(STRING, &data).unwrap(), json!(long));
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        let message = |type_code: u8, data: &[u8]| parse(type_code, data).unwrap_err().to_string();
+
+        assert_eq!(
+            message(0x0d, &[]),
+            "some error occoured: unknown json value type 0x0d"
+        );
+        assert_eq!(
+            message(LITERAL, &[0x03]),
+            "some error occoured: unknown json literal 0x03"
+        );
+        assert_eq!(
+            message(INT32, &[1, 2]),
+            "some error occoured: json value is truncated"
+        );
+        assert_eq!(
+            message(STRING, b"\x05abc"),
+            "some error occoured: json value is truncated"
+        );
+        assert_eq!(
+            message(STRING, &[0x80, 0x80]),
+            "some error occoured: json variable length is truncated"
+        );
+        assert!(parse(DOUBLE, &f64::NAN.to_le_bytes()).is_err());
+
+        // 值的位置超出了数组
+        assert_eq!(
+            message(SMALL_ARRAY, &[1, 0, 7, 0, INT32, 100, 0]),
+            "some error occoured: json value is truncated"
+        );
+    }
+
+    #[test]
+    fn opaque_values() {
+        let opaque = |field_type: u8, value: &[u8]| {
+            let mut data = vec![field_type, value.len() as u8];
+            data.extend(value);
+            parse(OPAQUE, &data).unwrap()
+        };
+
+        // DECIMAL(4,2)，开头是精度和小数位数
+        assert_eq!(
+            opaque(FIELD_TYPE_NEWDECIMAL, &[4, 2, 0x8c, 0x22]),
+            json!("12.34")
+        );
+        assert_eq!(
+            opaque(FIELD_TYPE_NEWDECIMAL, &[4, 2, 0x73, 0xdd]),
+            json!("-12.34")
+        );
+
+        let ymd = (2024i64 * 13 + 2) << 5 | 29;
+        let hms = 13 << 12 | 45 << 6 | 30;
+        let date = (ymd << 17) << 24;
+        let datetime = ((ymd << 17 | hms) << 24) | 123456;
+        assert_eq!(
+            opaque(FIELD_TYPE_DATE, &date.to_le_bytes()),
+            json!("2024-02-29")
+        );
+        assert_eq!(
+            opaque(FIELD_TYPE_DATETIME, &datetime.to_le_bytes()),
+            json!("2024-02-29 13:45:30.123456")
+        );
+        assert_eq!(
+            opaque(FIELD_TYPE_TIMESTAMP, &datetime.to_le_bytes()),
+            json!("2024-02-29 13:45:30.123456")
+        );
+        // TIME的小时可以超过24，也可以是负数
+        let time = -((838i64 << 12 | 59 << 6 | 59) << 24);
+        assert_eq!(
+            opaque(FIELD_TYPE_TIME, &time.to_le_bytes()),
+            json!("-838:59:59.000000")
+        );
+
+        // 其他类型和MySQL的输出一样
+        assert_eq!(opaque(252, &[1, 2, 3]), json!("base64:type252:AQID"));
+        // 长度不够时也按其他类型输出
+        assert_eq!(
+            opaque(FIELD_TYPE_DATE, &[1, 2]),
+            json!("base64:type10:AQI=")
+        );
+    }
+
+    #[test]
+    fn small_and_large_containers() {
+        // {"a": 1}：1个元素，12个字节，key在第11个字节，值直接写在值的位置里
+        assert_eq!(
+            parse(SMALL_OBJECT, &[1, 0, 12, 0, 11, 0, 1, 0, INT16, 1, 0, b'a']).unwrap(),
+            json!({"a": 1})
+        );
+        // [100000]：small中INT32放不进2个字节的位置，在第7个字节
+        let mut data = vec![1, 0, 11, 0, INT32, 7, 0];
+        data.extend(100000i32.to_le_bytes());
+        assert_eq!(parse(SMALL_ARRAY, &data).unwrap(), json!([100000]));
+        // large中放得进4个字节的位置
+        let mut data = vec![1, 0, 0, 0, 13, 0, 0, 0, INT32];
+        data.extend(100000i32.to_le_bytes());
+        assert_eq!(parse(LARGE_ARRAY, &data).unwrap(), json!([100000]));
+
+        let document = json!({
+            "id": 1,
+            "name": "Anne",
+            "tags": ["a", "b", null, true, false],
+            "scores": [1.5, -70000, 4000000000u64, u64::MAX, i64::MIN],
+            "address": {"city": "Beijing", "zip": 100000, "empty": {}},
+            "list": [],
+        });
+        for large in [false, true] {
+            assert_eq!(round_trip(&document, large), document);
+        }
+        assert_eq!(encode(&document, false).0, SMALL_OBJECT);
+        assert_eq!(encode(&document, true).0, LARGE_OBJECT);
+    }
+
+    #[test]
+    fn inlined_values() {
+        // 字面量、16位的整数总是直接写在位置里，32位的整数只在large中
+        let values = json!([null, true, false, -1, 65535, -70000, 4000000000u64]);
+        for large in [false, true] {
+            let (_, data) = encode(&values, large);
+            let offset_size = if large { 4 } else { 2 };
+            let inlined_length = 2 * offset_size + 7 * (1 + offset_size);
+            let expected_length = match large {
+                true => inlined_length,
+                false => inlined_length + 2 * 4,
+            };
+            assert_eq!(data.len(), expected_length);
+            assert_eq!(round_trip(&values, large), values);
+        }
+
+        // UINT16直接写在位置里
+        assert_eq!(
+            parse(SMALL_ARRAY, &[1, 0, 7, 0, UINT16, 0xff, 0xff]).unwrap(),
+            json!([65535])
+        );
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nested = |depth: usize| {
+            let mut value = json!(1);
+            for _ in 0..depth {
+                value = json!([value]);
+            }
+            value
+        };
+
+        for large in [false, true] {
+            let value = nested(MAX_DEPTH);
+            assert_eq!(round_trip(&value, large), value);
+
+            let (type_code, data) = encode(&nested(MAX_DEPTH + 1), large);
+            assert_eq!(
+                parse(type_code, &data).unwrap_err().to_string(),
+                "some error occoured: json value is nested deeper than 100 levels"
+            );
+        }
+
+        // 对象和数组都计入层数
+        let mut value = json!("leaf");
+        for i in 0..MAX_DEPTH {
+            value = match i % 2 {
+                0 => json!({ "k": value }),
+                _ => json!([value]),
+            };
+        }
+        assert_eq!(round_trip(&value, false), value);
+        let (type_code, data) = encode(&json!({ "k": value }), false);
+        assert!(parse(type_code, &data).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    sync::Arc,
+    thread,
+};
+
+use crate::filter::EventTypeFilter;
+use crate::model::*;
+use crate::service::*;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+// 一批最多预读的事件数和行事件的字节数
+const MAX_BATCH_EVENTS: usize = 4096;
+const MAX_BATCH_BYTES: u64 = 16 * 1024 * 1024;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 交给解码线程的一个行事件，表结构是文件中它之前最近的table map
+struct RowsJob {
+    position: u64,
+    type_code: u8,
+    buffer: Vec<u8>,
+    table_info: Arc<EventBodyTypeCode19>,
+}
+
+/// `--decode-threads N`，行事件字段值的解码分给多个线程
+///
+/// 主线程遇到还没有解码的table map或者行事件时，从它开始往后预读一批事件：table map按顺序在主线程解析，
+/// 行事件带上它之前最近的table map交给解码线程，所以同一批中table id被重新定义时每个行事件用的仍然是正确的表结构。
+/// 之后主线程按文件顺序调用get_event_body时直接取出解码的结果，table map这时才加入table_structs，
+/// 所以其他事件看到的表结构和顺序解析时一样。解码出错的事件会在主线程重新解码，错误信息和顺序解析时一样
+pub struct ParallelDecoder {
+    threads: usize,
+    // `--max-value-length`和`--raw-temporals`，和get_event_body_with_value_options一样解码
+    value_options: ValueOptions,
+    event_type_filter: EventTypeFilter,
+    // 最近一批预读的范围，其中没有结果的事件（比如缺少table map）不需要再预读
+    batch: Range<u64>,
+    // 事件的位置 -> 预读时解析的table map
+    table_maps: HashMap<u64, Box<dyn EventBody>>,
+    // 事件的位置 -> 解码的行事件，错误只保留信息
+    rows_events: HashMap<u64, Result<EventBodyTypeCode23To25, String>>,
+}
+
+impl ParallelDecoder {
+    /// 每个文件使用一个新的ParallelDecoder，因为预读的结果按文件中的位置保存
+    pub fn new(threads: usize, event_types: &[u8], value_options: ValueOptions) -> ParallelDecoder {
+        ParallelDecoder {
+            threads: threads.max(1),
+            value_options,
+            event_type_filter: EventTypeFilter::new(event_types),
+            batch: 0..0,
+            table_maps: HashMap::new(),
+            rows_events: HashMap::new(),
+        }
+    }
+
+    /// 和service::get_event_body一样，position是事件的起始位置
+    pub fn get_event_body<R: Read + Seek>(
+        &mut self,
+        file: &mut R,
+        file_length: u64,
+        position: u64,
+        header: &EventHeader,
+        table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+        checksum_length: usize,
+    ) -> Result<Box<dyn EventBody>, BoxedError> {
+        if !matches!(header.type_code, 19 | 23..=25 | 30..=32) {
+            return get_event_body_with_value_options(
+                file,
+                position,
+                header,
+                table_structs,
+                checksum_length,
+                self.value_options,
+            );
+        }
+
+        if !self.batch.contains(&position) {
+            self.prefetch(file, position, file_length, table_structs, checksum_length)?;
+        }
+
+        if let Some(body) = self.table_maps.remove(&position) {
+            if let Some(table_info) = body.downcast_ref::<Arc<EventBodyTypeCode19>>() {
+                table_structs.insert(table_info.table_id, table_info.clone());
+            }
+            return Ok(body);
+        }
+
+        match self.rows_events.remove(&position) {
+            Some(Ok(rows_event)) => Ok(Box::new(rows_event)),
+            _ => get_event_body_with_value_options(
+                file,
+                position,
+                header,
+                table_structs,
+                checksum_length,
+                self.value_options,
+            ),
+        }
+    }
+
+    /// 从position开始预读一批事件，遇到不完整或者不正常的事件时停止，留给主线程按原来的方式处理
+    /// 预读在FDE处停止，之后的事件的checksum长度可能不同
+    fn prefetch<R: Read + Seek>(
+        &mut self,
+        file: &mut R,
+        position: u64,
+        file_length: u64,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+        checksum_length: usize,
+    ) -> Result<(), BoxedError> {
+        // 之前一批中没有取出的是被跳过的事件
+        self.table_maps.clear();
+        self.rows_events.clear();
+
+        // 这一批中定义的表结构，没有的从table_structs中取
+        let mut registry: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+        let mut scratch = HashMap::new();
+        let mut jobs = Vec::new();
+
+        let mut offset = position;
+        let mut bytes = 0;
+        let mut events = 0;
+        while offset + EVENT_HEADER_LENGTH <= file_length
+            && events < MAX_BATCH_EVENTS
+            && bytes < MAX_BATCH_BYTES
+        {
+            let header = match get_event_header(file, offset) {
+                Ok(header) => header,
+                Err(_) => break,
+            };
+
+            let next_event_position = header.next_event_position as u64;
+            if (header.event_length as u64) < EVENT_HEADER_LENGTH
+                || next_event_position <= offset
+                || offset + header.event_length as u64 > file_length
+            {
+                break;
+            }
+
+            let body_offset = offset + EVENT_HEADER_LENGTH;
+            match header.type_code {
+                15 => break,
+                19 => {
+                    let body = match get_event_body(
+                        file,
+                        offset,
+                        &header,
+                        &mut scratch,
+                        checksum_length,
+                    ) {
+                        Ok(body) => body,
+                        Err(_) => break,
+                    };
+
+                    if let Some(table_info) = body.downcast_ref::<Arc<EventBodyTypeCode19>>() {
+                        registry.insert(table_info.table_id, table_info.clone());
+                    }
+                    self.table_maps.insert(offset, body);
+                }
+                23..=25 | 30..=32 if self.event_type_filter.needs_body(header.type_code) => {
+                    let table_id = match get_rows_event_table_id(file, body_offset) {
+                        Ok(table_id) => table_id,
+                        Err(_) => break,
+                    };
+
+                    // table map缺失的行事件由主线程处理
+                    let table_info = registry
+                        .get(&table_id)
+                        .or_else(|| table_structs.get(&table_id))
+                        .cloned();
+
+                    if let Some(table_info) = table_info {
+                        let mut buffer =
+                            vec![0u8; (header.event_length as u64 - EVENT_HEADER_LENGTH) as usize];
+                        file.seek(SeekFrom::Start(body_offset))?;
+                        file.read_exact(&mut buffer)?;
+
+                        // MySQL的v2行事件去掉extra data之后按v1解码
+                        let (buffer, type_code) = match strip_checksum(buffer, checksum_length)
+                            .and_then(|v| rows_event_v2_to_v1(v, header.type_code))
+                        {
+                            Ok(v) => v,
+                            Err(_) => break,
+                        };
+
+                        bytes += header.event_length as u64;
+                        jobs.push(RowsJob {
+                            position: offset,
+                            type_code,
+                            buffer,
+                            table_info,
+                        });
+                    }
+                }
+                _ => {}
+            }
+
+            events += 1;
+            offset = next_event_position;
+        }
+
+        self.batch = position..offset;
+        self.rows_events = decode_in_parallel(jobs, self.threads, self.value_options);
+
+        Ok(())
+    }
+}
+
+/// 按顺序分成threads段，每个线程解码其中一段
+fn decode_in_parallel(
+    jobs: Vec<RowsJob>,
+    threads: usize,
+    value_options: ValueOptions,
+) -> HashMap<u64, Result<EventBodyTypeCode23To25, String>> {
+    if jobs.is_empty() {
+        return HashMap::new();
+    }
+
+    let chunk_size = jobs.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let handles = jobs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|job| {
+                            let result = decode_rows_event(
+                                &job.buffer,
+                                job.type_code,
+                                &job.table_info,
+                                value_options,
+                            )
+                            .map_err(|e| e.to_string());
+                            (job.position, result)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            // 解码线程panic时这一段全部留给主线程重新解码，这样panic的信息和顺序解析时一样
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use std::io::Cursor;
+
+    // 每个事件的输出，解码失败时是错误信息
+    fn decode(binlog: &[u8], threads: Option<usize>) -> Vec<String> {
+        let mut parallel_decoder =
+            threads.map(|v| ParallelDecoder::new(v, &[], ValueOptions::default()));
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut checksum_length = CHECKSUM_LENGTH;
+        let mut result = Vec::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = match parallel_decoder.as_mut() {
+                Some(parallel_decoder) => parallel_decoder.get_event_body(
+                    &mut file,
+                    binlog.len() as u64,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                ),
+                None => get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                ),
+            };
+            match body {
+                Ok(body) => {
+                    update_checksum_length(&mut checksum_length, body.as_ref());
+                    result.push(format!("{:?}", body));
+                }
+                Err(e) => result.push(format!("error: {}", e)),
+            }
+            position = header.next_event_position as u64;
+        }
+
+        result
+    }
+
+    #[test]
+    fn same_output_as_the_serial_path() {
+        // 同一个table id在一批中被重新定义为不同的表结构
+        let narrow = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+        let wide = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes());
+
+        // 没有table map的行事件在一开始，超过一批的事件数
+        let mut binlog = BinlogBuilder::new().event(
+            &RowsEventBuilder::insert(&narrow)
+                .table_id(8)
+                .row(vec![ColumnValue::Int(0)]),
+        );
+        for i in 0..MAX_BATCH_EVENTS as i64 {
+            binlog = match i % 2 {
+                0 => binlog
+                    .event(&narrow)
+                    .event(&RowsEventBuilder::insert(&narrow).row(vec![ColumnValue::Int(i)])),
+                _ => binlog
+                    .event(&wide)
+                    .event(&RowsEventBuilder::update(&wide).update_row(
+                        vec![ColumnValue::Int(i), ColumnValue::String("a".to_string())],
+                        vec![ColumnValue::Int(i), ColumnValue::String(i.to_string())],
+                    )),
+            };
+        }
+        let binlog = binlog.build();
+
+        let serial = decode(&binlog, None);
+        assert_eq!(serial.len(), 2 + 2 * MAX_BATCH_EVENTS);
+        assert!(serial[1].starts_with("error: "));
+        for threads in [1, 4] {
+            assert_eq!(decode(&binlog, Some(threads)), serial);
+        }
+    }
+
+    #[test]
+    fn decode_errors_match_the_serial_path() {
+        // 行镜像按两个LONG写的，table map中只有一个字段
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+        let written = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Long, &[]);
+        let binlog = BinlogBuilder::new()
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(vec![ColumnValue::Int(1)]))
+            .event(
+                &RowsEventBuilder::insert(&written)
+                    .row(vec![ColumnValue::Int(1), ColumnValue::Int(2)]),
+            )
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+
+        let serial = decode(&binlog, None);
+        assert!(serial[3].starts_with("error: "));
+        assert_eq!(decode(&binlog, Some(2)), serial);
+    }
+}
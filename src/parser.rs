@@ -0,0 +1,521 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek},
+    sync::Arc,
+};
+
+use base64::prelude::*;
+use serde::{Serialize, Serializer};
+
+use crate::flavor::ServerVersion;
+use crate::model::*;
+use crate::service::*;
+use crate::statement_tables::StatementTableExtractor;
+
+const EVENT_HEADER_LENGTH: u32 = 19;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+type TableFilter = Box<dyn Fn(&str, &str) -> bool + Send>;
+
+/// 事件在文件中的原始字节，用于审计或者原样写回
+/// body包括最后4个字节的checksum（有checksum时），header和body拼起来就是文件中的这个事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEvent {
+    pub header: [u8; EVENT_HEADER_LENGTH as usize],
+    pub body: Vec<u8>,
+}
+
+impl RawEvent {
+    /// position是事件的起始位置
+    pub fn read<R: Read + Seek>(
+        file: &mut R,
+        position: u64,
+        event_length: u32,
+    ) -> Result<RawEvent, BoxedError> {
+        let raw_event = get_event_raw(file, position, event_length)?;
+        if raw_event.len() < EVENT_HEADER_LENGTH as usize {
+            return Err(Box::new(MyError(format!(
+                "the event at {} is shorter than the event header",
+                position
+            ))));
+        }
+
+        let (header, body) = raw_event.split_at(EVENT_HEADER_LENGTH as usize);
+
+        Ok(RawEvent {
+            header: header.try_into()?,
+            body: body.to_vec(),
+        })
+    }
+
+    /// 整个事件的字节
+    pub fn to_vec(&self) -> Vec<u8> {
+        [&self.header[..], &self.body].concat()
+    }
+}
+
+/// json中是整个事件的base64
+impl Serialize for RawEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64_STANDARD.encode(self.to_vec()))
+    }
+}
+
+/// read_event返回的事件，keep_raw(true)时raw中是原始字节，否则为None
+#[derive(Debug)]
+pub struct ParsedEvent {
+    pub position: u64,
+    pub header: EventHeader,
+    pub body: Box<dyn EventBody>,
+    pub raw: Option<RawEvent>,
+}
+
+/// 保存table map的解析器，作为库使用时代替自己维护table_structs
+///
+/// `decode_only(|db, table| ...)`在table map加入时判断这张表是否需要解码：不需要的表的table map照常保存，
+/// 行事件只解析结构，行镜像保留原始字节、不解码字段的值（之后可以用decode_rows解码），
+/// `skip_excluded(true)`时连结构也不解析，返回EventBodyTypeSkip。
+/// 修改过滤条件时已经保存的table map按新的条件重新判断，对之后的事件生效
+///
+/// 打开sqlparser特性时，decode_only也用于query事件和annotate事件：解析语句中引用的表，
+/// 都不需要时statement_excluded()为true。无法解析的语句不排除，个数在unparsed_statements()中
+///
+/// `keep_raw(true)`时read_event返回的事件附带原始字节，默认不读取
+///
+/// 最近一个FDE中的服务端版本保存在server_version()中，用于判断之后的事件的格式，
+/// 其中的校验算法决定之后的事件最后有没有checksum
+pub struct BinlogParser {
+    table_structs: HashMap<u64, Arc<EventBodyTypeCode19>>,
+    table_filter: Option<TableFilter>,
+    // 不需要解码的表的table id
+    excluded: HashSet<u64>,
+    skip_excluded: bool,
+    keep_raw: bool,
+    server_version: Option<ServerVersion>,
+    // 之后的事件最后的checksum长度，第一个FDE之前按有checksum处理
+    checksum_length: usize,
+    statement_tables: StatementTableExtractor,
+    // 最近一个语句引用的表都不需要
+    statement_excluded: bool,
+}
+
+impl Default for BinlogParser {
+    fn default() -> Self {
+        BinlogParser::new()
+    }
+}
+
+impl BinlogParser {
+    pub fn new() -> BinlogParser {
+        BinlogParser {
+            table_structs: HashMap::new(),
+            table_filter: None,
+            excluded: HashSet::new(),
+            skip_excluded: false,
+            keep_raw: false,
+            server_version: None,
+            checksum_length: CHECKSUM_LENGTH,
+            statement_tables: StatementTableExtractor::new(),
+            statement_excluded: false,
+        }
+    }
+
+    /// 只解码filter(库名, 表名)为true的表的行事件
+    pub fn decode_only<F>(&mut self, filter: F) -> &mut BinlogParser
+    where
+        F: Fn(&str, &str) -> bool + Send + 'static,
+    {
+        self.table_filter = Some(Box::new(filter));
+        self.refresh_excluded();
+        self
+    }
+
+    /// 去掉decode_only的条件，所有的表都解码
+    pub fn decode_all(&mut self) -> &mut BinlogParser {
+        self.table_filter = None;
+        self.excluded.clear();
+        self.statement_excluded = false;
+        self
+    }
+
+    /// 不需要解码的表的行事件是否完全跳过，默认只解析结构
+    pub fn skip_excluded(&mut self, skip: bool) -> &mut BinlogParser {
+        self.skip_excluded = skip;
+        self
+    }
+
+    /// read_event是否附带事件的原始字节
+    pub fn keep_raw(&mut self, keep_raw: bool) -> &mut BinlogParser {
+        self.keep_raw = keep_raw;
+        self
+    }
+
+    /// 到目前为止的table map
+    pub fn table_structs(&self) -> &HashMap<u64, Arc<EventBodyTypeCode19>> {
+        &self.table_structs
+    }
+
+    /// 最近一个FDE中的服务端版本，第一个FDE之前为None
+    pub fn server_version(&self) -> Option<&ServerVersion> {
+        self.server_version.as_ref()
+    }
+
+    /// 最近一个query事件或annotate事件引用的表是否都被decode_only排除，没有条件时总是false
+    pub fn statement_excluded(&self) -> bool {
+        self.statement_excluded
+    }
+
+    /// decode_only时无法解析的语句数
+    pub fn unparsed_statements(&self) -> u64 {
+        self.statement_tables.unparsed()
+    }
+
+    /// 这个table id的行事件是否解码，没有table map时为true，这时和get_event_body一样报错
+    pub fn decodes(&self, table_id: u64) -> bool {
+        !self.excluded.contains(&table_id)
+    }
+
+    /// 和service::get_event_body一样，position是事件的起始位置
+    pub fn get_event_body<R: Read + Seek>(
+        &mut self,
+        file: &mut R,
+        position: u64,
+        header: &EventHeader,
+    ) -> Result<Box<dyn EventBody>, BoxedError> {
+        match header.type_code {
+            15 => {
+                let body = get_event_body(
+                    file,
+                    position,
+                    header,
+                    &mut self.table_structs,
+                    self.checksum_length,
+                )?;
+
+                if let Some(format_description) = body.downcast_ref::<EventBodyTypeCode15>() {
+                    self.server_version =
+                        Some(ServerVersion::parse(&format_description.server_version));
+                    self.checksum_length = format_description.checksum_length();
+                }
+
+                Ok(body)
+            }
+            19 => {
+                let body = get_event_body(
+                    file,
+                    position,
+                    header,
+                    &mut self.table_structs,
+                    self.checksum_length,
+                )?;
+
+                if let Some(table_info) = body.downcast_ref::<Arc<EventBodyTypeCode19>>() {
+                    let table_info = table_info.clone();
+                    self.register(&table_info);
+                }
+
+                Ok(body)
+            }
+            23..=25 | 30..=32 if !self.excluded.is_empty() => {
+                let table_id =
+                    get_rows_event_table_id(file, position + EVENT_HEADER_LENGTH as u64)?;
+                if self.decodes(table_id) {
+                    return get_event_body(
+                        file,
+                        position,
+                        header,
+                        &mut self.table_structs,
+                        self.checksum_length,
+                    );
+                }
+
+                if self.skip_excluded {
+                    return Ok(Box::new(EventBodyTypeSkip(header.type_code)));
+                }
+
+                let buffer = read_event_data(file, position, header, self.checksum_length)?;
+
+                let (buffer, type_code) = rows_event_v2_to_v1(buffer, header.type_code)?;
+
+                Ok(Box::new(parse_rows_event(&buffer, type_code)?))
+            }
+            2 | 160 if self.table_filter.is_some() => {
+                let body = get_event_body(
+                    file,
+                    position,
+                    header,
+                    &mut self.table_structs,
+                    self.checksum_length,
+                )?;
+
+                self.statement_excluded = match (
+                    self.statement_tables.process(body.as_ref()),
+                    &self.table_filter,
+                ) {
+                    (Some(tables), Some(filter)) => {
+                        !tables.is_empty() && !tables.iter().any(|v| filter(&v.database, &v.table))
+                    }
+                    _ => false,
+                };
+
+                Ok(body)
+            }
+            _ => get_event_body(
+                file,
+                position,
+                header,
+                &mut self.table_structs,
+                self.checksum_length,
+            ),
+        }
+    }
+
+    /// 读取position处的整个事件，下一个事件的位置是position + header.event_length
+    pub fn read_event<R: Read + Seek>(
+        &mut self,
+        file: &mut R,
+        position: u64,
+    ) -> Result<ParsedEvent, BoxedError> {
+        let header = get_event_header(file, position)?;
+        let body = self.get_event_body(file, position, &header)?;
+
+        let raw = match self.keep_raw {
+            true => Some(RawEvent::read(file, position, header.event_length)?),
+            false => None,
+        };
+
+        Ok(ParsedEvent {
+            position,
+            header,
+            body,
+            raw,
+        })
+    }
+
+    // table id被重新定义时按新的表名判断
+    fn register(&mut self, table_info: &EventBodyTypeCode19) {
+        let decode = match &self.table_filter {
+            Some(filter) => filter(&table_info.database_name, &table_info.table_name),
+            None => true,
+        };
+
+        match decode {
+            true => self.excluded.remove(&table_info.table_id),
+            false => self.excluded.insert(table_info.table_id),
+        };
+    }
+
+    fn refresh_excluded(&mut self) {
+        self.excluded.clear();
+
+        let table_structs: Vec<Arc<EventBodyTypeCode19>> =
+            self.table_structs.values().cloned().collect();
+        for table_info in table_structs {
+            self.register(&table_info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use std::io::Cursor;
+
+    fn table(name: &str, table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new("shop", name)
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+    }
+
+    fn insert(table: &TableMapBuilder, id: i64) -> RowsEventBuilder {
+        RowsEventBuilder::insert(table).row(vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(format!("row {}", id)),
+        ])
+    }
+
+    // orders和logs的table map，之后每张表一个insert
+    fn two_tables() -> Vec<u8> {
+        let orders = table("orders", 7);
+        let logs = table("logs", 8);
+        BinlogBuilder::new()
+            .event(&orders)
+            .event(&logs)
+            .event(&insert(&orders, 1))
+            .event(&insert(&logs, 2))
+            .build()
+    }
+
+    fn read_all(parser: &mut BinlogParser, binlog: &[u8]) -> Vec<ParsedEvent> {
+        let mut file = Cursor::new(binlog);
+        let mut events = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let event = parser.read_event(&mut file, position).unwrap();
+            position += event.header.event_length as u64;
+            events.push(event);
+        }
+        events
+    }
+
+    fn rows_event(event: &ParsedEvent) -> &EventBodyTypeCode23To25 {
+        event
+            .body
+            .downcast_ref::<EventBodyTypeCode23To25>()
+            .unwrap()
+    }
+
+    fn values(id: i64) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            ColumnValue::String(format!("row {}", id)),
+        ]
+    }
+
+    #[test]
+    fn excluded_tables_keep_the_raw_rows() {
+        let binlog = two_tables();
+        let mut parser = BinlogParser::new();
+        parser.decode_only(|_, table| table == "orders");
+        let events = read_all(&mut parser, &binlog);
+
+        // 需要的表和没有条件时一样完整解码
+        let orders = rows_event(&events[3]);
+        assert_eq!(orders.rows.len(), 1);
+        assert_eq!(orders.rows[0].after.as_deref(), Some(&values(1)[..]));
+
+        // 排除的表只解析结构，行镜像保留原始字节
+        let logs = rows_event(&events[4]);
+        assert_eq!(logs.table_id, 8);
+        assert!(logs.rows.is_empty());
+        assert!(!logs.row_images.is_empty());
+        let rows = logs.decode_rows(&parser.table_structs()[&8]).unwrap();
+        assert_eq!(rows[0].after.as_deref(), Some(&values(2)[..]));
+
+        // table map照常保存
+        assert_eq!(parser.table_structs().len(), 2);
+        assert!(parser.decodes(7));
+        assert!(!parser.decodes(8));
+    }
+
+    #[test]
+    fn excluded_tables_can_be_skipped() {
+        let binlog = two_tables();
+        let mut parser = BinlogParser::new();
+        parser
+            .decode_only(|_, table| table == "orders")
+            .skip_excluded(true);
+        let events = read_all(&mut parser, &binlog);
+
+        assert_eq!(rows_event(&events[3]).rows.len(), 1);
+        let skipped = events[4].body.downcast_ref::<EventBodyTypeSkip>().unwrap();
+        assert_eq!(skipped.0, 23);
+    }
+
+    #[test]
+    fn filter_changes_take_effect_for_later_events() {
+        let orders = table("orders", 7);
+        let logs = table("logs", 8);
+        let binlog = BinlogBuilder::new()
+            .event(&orders)
+            .event(&logs)
+            .event(&insert(&orders, 1))
+            .event(&insert(&logs, 2))
+            .event(&insert(&orders, 3))
+            .event(&insert(&logs, 4))
+            .event(&insert(&orders, 5))
+            .build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut parser = BinlogParser::new();
+        parser.decode_only(|_, table| table == "orders");
+
+        let mut decoded = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let event = parser.read_event(&mut file, position).unwrap();
+            position += event.header.event_length as u64;
+
+            if let Some(rows_event) = event.body.downcast_ref::<EventBodyTypeCode23To25>() {
+                decoded.push((rows_event.table_id, !rows_event.rows.is_empty()));
+
+                // 已经保存的table map按新的条件重新判断
+                match decoded.len() {
+                    2 => parser.decode_only(|_, table| table == "logs"),
+                    4 => parser.decode_all(),
+                    _ => &mut parser,
+                };
+            }
+        }
+
+        assert_eq!(
+            decoded,
+            [(7, true), (8, false), (7, false), (8, true), (7, true)]
+        );
+    }
+
+    #[test]
+    fn redefined_table_id_uses_the_new_table_name() {
+        let logs = table("logs", 7);
+        let orders = table("orders", 7);
+        let binlog = BinlogBuilder::new()
+            .event(&logs)
+            .event(&insert(&logs, 1))
+            .event(&orders)
+            .event(&insert(&orders, 2))
+            .build();
+
+        let mut parser = BinlogParser::new();
+        parser.decode_only(|_, table| table == "orders");
+        let events = read_all(&mut parser, &binlog);
+
+        assert!(rows_event(&events[2]).rows.is_empty());
+        assert_eq!(rows_event(&events[4]).rows.len(), 1);
+    }
+
+    #[test]
+    fn raw_bytes_match_the_file() {
+        use sha2::{Digest, Sha256};
+
+        let orders = table("orders", 7);
+        let with_checksum = BinlogBuilder::new()
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&orders)
+            .event(&insert(&orders, 1))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build();
+        // binlog_checksum=NONE时raw中也没有校验和
+        let without_checksum = BinlogBuilder::without_checksum()
+            .event(&orders)
+            .event(&insert(&orders, 1))
+            .build();
+
+        for binlog in [with_checksum, without_checksum] {
+            let mut parser = BinlogParser::new();
+            parser.keep_raw(true);
+
+            for event in read_all(&mut parser, &binlog) {
+                let raw = event.raw.as_ref().unwrap();
+                let region = &binlog[event.position as usize
+                    ..event.position as usize + event.header.event_length as usize];
+                assert_eq!(Sha256::digest(raw.to_vec()), Sha256::digest(region));
+                assert_eq!(raw.header[4], event.header.type_code);
+                assert_eq!(
+                    serde_json::to_value(raw).unwrap(),
+                    BASE64_STANDARD.encode(region)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn raw_bytes_are_not_read_by_default() {
+        let mut parser = BinlogParser::new();
+        let events = read_all(&mut parser, &two_tables());
+        assert!(events.iter().all(|v| v.raw.is_none()));
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::prelude::*;
+use serde_json::{json, Map, Value};
+
+use crate::cli::{InsertMode, PerTableFormat};
+use crate::column_select::{ColumnSelection, ColumnSelector};
+use crate::ddl::DdlExtractor;
+use crate::model::*;
+use crate::schema_drift::SchemaTracker;
+use crate::sql::{quote_identifier, row_statements};
+use crate::table_structure::column_names;
+use crate::util::get_event_type_name;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+// 同时打开的文件数，超过时关闭最久没有写入的文件，避免超过ulimit
+const MAX_OPEN_FILES: usize = 64;
+
+// DDL和不属于任何表的事件写入的文件
+const OTHER_FILE_NAME: &str = "_other";
+
+/// `--per-table-output DIR`，行事件按表写入`DIR/库名.表名.扩展名`，其他事件写入`DIR/_other.扩展名`
+/// 表结构变化之后的行事件写入`DIR/库名.表名.v2.扩展名`，依次类推
+/// 文件在第一次写入时创建（已经存在时会被覆盖），之后按需重新打开并追加
+pub struct PerTableWriter {
+    dir: PathBuf,
+    format: PerTableFormat,
+    // `--idempotent`时SQL文件中INSERT的方式
+    insert_mode: InsertMode,
+    ddl_extractor: DdlExtractor,
+    current_gtid: Option<String>,
+    // 按最近写入的顺序，最后一个是最近写入的
+    open_files: Vec<(PathBuf, BufWriter<File>)>,
+    // 这次运行中已经创建过的文件，CSV的表头只在创建时写入
+    created_files: HashSet<PathBuf>,
+    // `--columns`时CSV只有选择的字段和主键
+    column_selector: ColumnSelector,
+    // 表结构变化之后写入新的文件，CSV的表头和字段一致
+    schema_tracker: SchemaTracker,
+}
+
+impl PerTableWriter {
+    pub fn new(
+        dir: &str,
+        format: PerTableFormat,
+        insert_mode: InsertMode,
+        include_grants: bool,
+        columns: &[ColumnSelection],
+    ) -> Result<PerTableWriter, BoxedError> {
+        fs::create_dir_all(dir)?;
+
+        Ok(PerTableWriter {
+            dir: PathBuf::from(dir),
+            format,
+            insert_mode,
+            ddl_extractor: DdlExtractor::new(include_grants),
+            current_gtid: None,
+            open_files: Vec::new(),
+            created_files: HashSet::new(),
+            column_selector: ColumnSelector::new(columns),
+            schema_tracker: SchemaTracker::new(),
+        })
+    }
+
+    /// position是事件在文件中的起始位置，table map不单独输出
+    /// event_hash是`--hash`时事件的哈希，ndjson和CSV中多一个event_hash字段
+    /// 返回是否写入了内容
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+        event_hash: Option<&str>,
+    ) -> Result<bool, BoxedError> {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+        }
+
+        let ddl_statement = self.ddl_extractor.process(header, position, body);
+
+        if body.downcast_ref::<EventBodyTypeCode19>().is_some() {
+            self.schema_tracker.process(position, body);
+            return Ok(false);
+        }
+
+        let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>();
+        if let Some((rows_event, table_map)) =
+            rows_event.and_then(|v| Some((v, table_structs.get(&v.table_id)?)))
+        {
+            self.write_rows(header, position, rows_event, table_map, event_hash)?;
+            return Ok(true);
+        }
+
+        let sql = ddl_statement.as_ref().map(|v| v.sql.as_str());
+        let lines = match self.format {
+            PerTableFormat::Ndjson => {
+                let mut event = json!({
+                    "position": position,
+                    "header": header,
+                    "body": body,
+                });
+                if let Some(event_hash) = event_hash {
+                    event["event_hash"] = json!(event_hash);
+                }
+                vec![event.to_string()]
+            }
+            PerTableFormat::Csv => {
+                let mut fields = vec![
+                    position.to_string(),
+                    header.timestamp.to_string(),
+                    csv_field(self.current_gtid.as_deref()),
+                ];
+                fields.extend(event_hash.map(|v| v.to_string()));
+                fields.extend([
+                    get_event_type_name(header.type_code).to_string(),
+                    csv_field(ddl_statement.as_ref().map(|v| v.database_name.as_str())),
+                    csv_field(sql),
+                ]);
+                vec![fields.join(",")]
+            }
+            // SQL文件中只有DDL可以执行，其他事件写成注释
+            PerTableFormat::Sql => match &ddl_statement {
+                Some(statement) if !statement.database_name.is_empty() => vec![
+                    format!("USE {};", quote_identifier(&statement.database_name)),
+                    format!("{};", statement.sql),
+                ],
+                Some(statement) => vec![format!("{};", statement.sql)],
+                None => vec![format!(
+                    "-- at {} {}",
+                    position,
+                    get_event_type_name(header.type_code)
+                )],
+            },
+        };
+
+        let header_line = match event_hash {
+            Some(_) => "position,timestamp,gtid,event_hash,type,db,sql".to_string(),
+            None => "position,timestamp,gtid,type,db,sql".to_string(),
+        };
+        self.write_lines(OTHER_FILE_NAME.to_string(), header_line, &lines)?;
+        Ok(true)
+    }
+
+    /// 把所有打开的文件写入磁盘
+    pub fn finish(&mut self) -> Result<(), BoxedError> {
+        for (_, writer) in self.open_files.iter_mut() {
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_rows(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        rows_event: &EventBodyTypeCode23To25,
+        table_map: &EventBodyTypeCode19,
+        event_hash: Option<&str>,
+    ) -> Result<(), BoxedError> {
+        let op = match header.type_code {
+            23 | 30 => "insert",
+            24 | 31 => "update",
+            _ => "delete",
+        };
+
+        let names = column_names(table_map);
+        let output_columns = match self.format {
+            PerTableFormat::Csv => self.column_selector.output_columns(table_map)?,
+            _ => None,
+        };
+        let is_output = |index: usize| {
+            output_columns
+                .as_ref()
+                .is_none_or(|v| v.get(index).copied().unwrap_or(false))
+        };
+        let gtid = self.current_gtid.clone();
+        // update修改后的镜像可能和修改前的字段不同
+        let after_present = rows_event
+            .columns_used_for_update
+            .as_ref()
+            .unwrap_or(&rows_event.columns_used);
+
+        let lines = match self.format {
+            PerTableFormat::Ndjson => rows_event
+                .rows
+                .iter()
+                .map(|row| {
+                    let mut line = json!({
+                        "position": position,
+                        "timestamp": header.timestamp,
+                        "gtid": gtid,
+                        "op": op,
+                        "before": row
+                            .before
+                            .as_ref()
+                            .map(|v| image_object(&names, v, &rows_event.columns_used)),
+                        "after": row.after.as_ref().map(|v| match row.before {
+                            Some(_) => image_object(&names, v, after_present),
+                            None => image_object(&names, v, &rows_event.columns_used),
+                        }),
+                    });
+                    if let Some(event_hash) = event_hash {
+                        line["event_hash"] = json!(event_hash);
+                    }
+                    line.to_string()
+                })
+                .collect(),
+            // update的修改前后各一行，用image区分
+            PerTableFormat::Csv => {
+                let mut lines = Vec::new();
+                for row in &rows_event.rows {
+                    let images = [(&row.before, "before"), (&row.after, "after")];
+                    for (image, image_name) in images {
+                        let image = match image {
+                            Some(image) => image,
+                            None => continue,
+                        };
+
+                        let mut fields = vec![
+                            position.to_string(),
+                            header.timestamp.to_string(),
+                            csv_field(gtid.as_deref()),
+                        ];
+                        fields.extend(event_hash.map(|v| v.to_string()));
+                        fields.extend([op.to_string(), image_name.to_string()]);
+                        fields.extend(
+                            image
+                                .iter()
+                                .enumerate()
+                                .filter(|(index, _)| is_output(*index))
+                                .map(|(_, value)| csv_value(value)),
+                        );
+                        lines.push(fields.join(","));
+                    }
+                }
+                lines
+            }
+            PerTableFormat::Sql => row_statements(
+                header.type_code,
+                rows_event,
+                table_map,
+                &names,
+                true,
+                self.insert_mode,
+            )?
+            .into_iter()
+            // 可能找不到要修改的行时在语句前面加上注释
+            .flat_map(|v| {
+                let warning = v.warning.map(|warning| format!("-- WARNING: {}", warning));
+                warning.into_iter().chain([format!("{};", v.sql)])
+            })
+            .collect(),
+        };
+
+        let mut header_fields = ["position", "timestamp", "gtid"]
+            .into_iter()
+            .chain(event_hash.map(|_| "event_hash"))
+            .chain(["op", "image"])
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>();
+        header_fields.extend(
+            names
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| is_output(*index))
+                .map(|(_, name)| csv_field(Some(name))),
+        );
+
+        let mut file_name = format!(
+            "{}.{}",
+            sanitize_file_name(&table_map.database_name),
+            sanitize_file_name(&table_map.table_name)
+        );
+        match self
+            .schema_tracker
+            .version(&table_map.database_name, &table_map.table_name)
+        {
+            1 => {}
+            version => file_name.push_str(&format!(".v{}", version)),
+        }
+        self.write_lines(file_name, header_fields.join(","), &lines)
+    }
+
+    // header_line只在CSV文件创建时写入
+    fn write_lines(
+        &mut self,
+        file_name: String,
+        header_line: String,
+        lines: &[String],
+    ) -> Result<(), BoxedError> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let extension = match self.format {
+            PerTableFormat::Ndjson => "ndjson",
+            PerTableFormat::Csv => "csv",
+            PerTableFormat::Sql => "sql",
+        };
+        let path = self.dir.join(format!("{}.{}", file_name, extension));
+
+        let writer = self.open(&path, header_line)?;
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    fn open(
+        &mut self,
+        path: &Path,
+        header_line: String,
+    ) -> Result<&mut BufWriter<File>, BoxedError> {
+        match self.open_files.iter().position(|(v, _)| v == path) {
+            Some(index) => {
+                let entry = self.open_files.remove(index);
+                self.open_files.push(entry);
+            }
+            None => {
+                if self.open_files.len() >= MAX_OPEN_FILES {
+                    let (_, mut writer) = self.open_files.remove(0);
+                    writer.flush()?;
+                }
+
+                let is_new = self.created_files.insert(path.to_path_buf());
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(!is_new)
+                    .truncate(is_new)
+                    .open(path)?;
+
+                let mut writer = BufWriter::new(file);
+                if is_new && self.format == PerTableFormat::Csv {
+                    writeln!(writer, "{}", header_line)?;
+                }
+
+                self.open_files.push((path.to_path_buf(), writer));
+            }
+        }
+
+        let (_, writer) = self.open_files.last_mut().unwrap();
+        Ok(writer)
+    }
+}
+
+// 只有镜像中的字段（MINIMAL或者--columns时不是所有字段）
+fn image_object(names: &[String], image: &[ColumnValue], present: &Bitmap) -> Value {
+    let mut object = Map::new();
+    for (index, (name, value)) in names.iter().zip(image).enumerate() {
+        if present.get(index).unwrap_or(false) {
+            object.insert(name.clone(), value.to_json());
+        }
+    }
+    Value::Object(object)
+}
+
+/// null是空字段，空字符串是`""`，二进制数据使用base64
+fn csv_value(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Null | ColumnValue::Unsupported(_) => String::new(),
+        ColumnValue::Int(v) => v.to_string(),
+        ColumnValue::UInt(v) => v.to_string(),
+        ColumnValue::Float(v) => v.to_string(),
+        ColumnValue::Double(v) => v.to_string(),
+        ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => {
+            csv_field(Some(v))
+        }
+        ColumnValue::RawTemporal(v) => csv_field(Some(&v.to_string())),
+        ColumnValue::RawDecimal(v) => csv_field(Some(&v.value)),
+        ColumnValue::Bytes(v) => csv_field(Some(&BASE64_STANDARD.encode(v))),
+    }
+}
+
+// 按RFC 4180转义
+fn csv_field(value: Option<&str>) -> String {
+    match value {
+        None => String::new(),
+        Some(v) if v.is_empty() || v.contains([',', '"', '\r', '\n']) => {
+            format!("\"{}\"", v.replace('"', "\"\""))
+        }
+        Some(v) => v.to_string(),
+    }
+}
+
+/// 除了ASCII字母、数字、`_`和`-`以外的字符都按UTF-8编码成`%XX`
+/// 所以库名和表名中的`.`不会和分隔符混淆，不同的名字也不会得到相同的文件名
+fn sanitize_file_name(name: &str) -> String {
+    let mut result = String::new();
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => result.push(c),
+            _ => {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    result.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn table(database: &str, name: &str, table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new(database, name)
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+    }
+
+    fn row(id: i64, name: &str) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(id), ColumnValue::String(name.to_string())]
+    }
+
+    // 写入一个新的目录，返回目录
+    fn run(binlog: &[u8], format: PerTableFormat, name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("per-table-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        let mut writer = PerTableWriter::new(
+            dir.to_str().unwrap(),
+            format,
+            InsertMode::Insert,
+            false,
+            &[],
+        )
+        .unwrap();
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            writer
+                .process(&header, position, body.as_ref(), &table_structs, None)
+                .unwrap();
+            position = header.next_event_position as u64;
+        }
+        writer.finish().unwrap();
+
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    // 两张表，第二张表的名字需要转义，两个事务之间有一个DDL
+    fn two_tables() -> Vec<u8> {
+        let orders = table("shop", "orders", 7);
+        let users = table("app", "user's.用户", 8);
+        BinlogBuilder::new()
+            .push(gtid(1))
+            .event(&orders)
+            .event(
+                &RowsEventBuilder::insert(&orders)
+                    .row(row(1, "a"))
+                    .row(row(2, "b,c")),
+            )
+            .event(&users)
+            .event(&RowsEventBuilder::insert(&users).row(row(1, "x")))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .event(&QueryEventBuilder::new(
+                "shop",
+                "ALTER TABLE orders ADD INDEX (id)",
+            ))
+            .push(gtid(2))
+            .event(&orders)
+            .event(&RowsEventBuilder::update(&orders).update_row(row(1, "a"), row(1, "z")))
+            .push(EventBuilder::new(16, 2u64.to_le_bytes().to_vec()))
+            .build()
+    }
+
+    #[test]
+    fn csv_files_per_table() {
+        let dir = run(&two_tables(), PerTableFormat::Csv, "csv");
+
+        let users = "app.user%27s%2E%E7%94%A8%E6%88%B7.csv";
+        assert_eq!(file_names(&dir), ["_other.csv", users, "shop.orders.csv"]);
+
+        // 表头只有一次，第二个事务追加到同一个文件
+        let orders = fs::read_to_string(dir.join("shop.orders.csv")).unwrap();
+        let lines = orders.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "position,timestamp,gtid,op,image,col_1,col_2");
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].ends_with(",0-1-1,insert,after,1,a"));
+        assert!(lines[2].ends_with(",0-1-1,insert,after,2,\"b,c\""));
+        assert!(lines[3].ends_with(",0-1-2,update,before,1,a"));
+        assert!(lines[4].ends_with(",0-1-2,update,after,1,z"));
+
+        let users = fs::read_to_string(dir.join(users)).unwrap();
+        assert_eq!(users.lines().count(), 2);
+
+        // GTID、XID和DDL，table map不单独输出
+        let other = fs::read_to_string(dir.join("_other.csv")).unwrap();
+        let lines = other.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "position,timestamp,gtid,type,db,sql");
+        assert_eq!(lines.len(), 7);
+        assert!(other.contains(",QUERY_EVENT,shop,ALTER TABLE orders ADD INDEX (id)"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ndjson_and_sql_files() {
+        let dir = run(&two_tables(), PerTableFormat::Ndjson, "ndjson");
+        let orders = fs::read_to_string(dir.join("shop.orders.ndjson")).unwrap();
+        let lines = orders
+            .lines()
+            .map(|v| serde_json::from_str::<Value>(v).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2]["op"], "update");
+        assert_eq!(lines[2]["before"]["col_2"], "a");
+        assert_eq!(lines[2]["after"]["col_2"], "z");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = run(&two_tables(), PerTableFormat::Sql, "sql");
+        let orders = fs::read_to_string(dir.join("shop.orders.sql")).unwrap();
+        assert_eq!(orders.matches("INSERT INTO").count(), 2);
+        assert_eq!(orders.matches("UPDATE").count(), 1);
+        let other = fs::read_to_string(dir.join("_other.sql")).unwrap();
+        assert!(other.contains("USE `shop`;\nALTER TABLE orders ADD INDEX (id);"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopened_files_keep_a_single_header() {
+        // 比同时打开的文件数多的表，第一张表的文件被关闭之后再写入
+        let tables = (0..MAX_OPEN_FILES as u64 + 2)
+            .map(|i| table("shop", &format!("t{}", i), i + 1))
+            .collect::<Vec<_>>();
+        let mut binlog = BinlogBuilder::new();
+        for table in tables.iter().chain([&tables[0]]) {
+            binlog = binlog
+                .event(table)
+                .event(&RowsEventBuilder::insert(table).row(row(1, "a")));
+        }
+        let dir = run(&binlog.build(), PerTableFormat::Csv, "reopen");
+
+        assert_eq!(file_names(&dir).len(), MAX_OPEN_FILES + 3);
+        let first = fs::read_to_string(dir.join("shop.t0.csv")).unwrap();
+        let lines = first.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("position,"));
+        assert!(lines[1].ends_with(",insert,after,1,a"));
+        assert!(lines[2].ends_with(",insert,after,1,a"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_names_are_sanitized() {
+        assert_eq!(sanitize_file_name("orders_2024-01"), "orders_2024-01");
+        assert_eq!(sanitize_file_name("a.b"), "a%2Eb");
+        assert_eq!(sanitize_file_name("a\"b/c"), "a%22b%2Fc");
+        assert_eq!(sanitize_file_name("é"), "%C3%A9");
+        assert_ne!(sanitize_file_name("a.b"), sanitize_file_name("a%2Eb"));
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, Write},
+    sync::Arc,
+};
+
+use serde_json::json;
+
+use crate::cli::{CliOptions, OutputFormat};
+use crate::diagnostic::Diagnostic;
+use crate::error::{find_unsupported, ErrorCategory, ParseError};
+use crate::flavor::FlavorTracker;
+use crate::model::*;
+use crate::rows_only::RowsOnlyFormatter;
+use crate::schema_drift::SchemaTracker;
+use crate::service::*;
+use crate::style::{ColorChoice, Styler};
+use crate::table_view::{TableRenderer, DEFAULT_MAX_COLUMN_WIDTH};
+
+/// 解码事件时跨事件的状态，顺序处理文件、`--jobs`、`--read-from-remote-server`和`from-base64`共用
+///
+/// 提示（写binlog的服务端、表结构的变化）交给调用方的diagnostics，跳过的还不支持的内容由调用方计入ErrorReport
+pub struct EventPipeline {
+    pub table_structs: HashMap<u64, Arc<EventBodyTypeCode19>>,
+    /// 之后的事件最后的checksum长度，由FDE中的校验算法决定
+    pub checksum_length: usize,
+    value_options: ValueOptions,
+    // 汇总类的输出只需要表和行数，行事件不解码字段的值
+    decode_row_values: bool,
+    strict: bool,
+    flavor_tracker: FlavorTracker,
+    schema_tracker: SchemaTracker,
+}
+
+impl EventPipeline {
+    pub fn new(value_options: ValueOptions, strict: bool) -> EventPipeline {
+        EventPipeline {
+            table_structs: HashMap::new(),
+            checksum_length: CHECKSUM_LENGTH,
+            value_options,
+            decode_row_values: true,
+            strict,
+            flavor_tracker: FlavorTracker::new(),
+            schema_tracker: SchemaTracker::new(),
+        }
+    }
+
+    pub fn from_options(options: &CliOptions) -> EventPipeline {
+        EventPipeline::new(options.value_options(), options.strict)
+    }
+
+    pub fn decode_row_values(mut self, decode_row_values: bool) -> EventPipeline {
+        self.decode_row_values = decode_row_values;
+        self
+    }
+
+    /// 每个文件从FDE之前的默认校验和长度开始，table map在文件之间延续
+    pub fn start_file(&mut self) {
+        self.checksum_length = CHECKSUM_LENGTH;
+    }
+
+    /// position是事件在file中的起始位置，解码失败属于文件损坏
+    pub fn decode<R: Read + Seek>(
+        &mut self,
+        file: &mut R,
+        position: u64,
+        header: &EventHeader,
+    ) -> Result<Box<dyn EventBody>, ParseError> {
+        match self.decode_row_values {
+            true => get_event_body_with_value_options(
+                file,
+                position,
+                header,
+                &mut self.table_structs,
+                self.checksum_length,
+                self.value_options,
+            ),
+            false => get_event_body_without_row_values(
+                file,
+                position,
+                header,
+                &mut self.table_structs,
+                self.checksum_length,
+            ),
+        }
+        .map_err(|e| {
+            ParseError::new(ErrorCategory::Corruption, e.to_string())
+                .at(position, Some(header.type_code))
+        })
+    }
+
+    /// 解码之后更新校验和长度和写binlog的服务端，返回这个事件自己的校验和长度，FDE改变的是之后的事件
+    pub fn update(
+        &mut self,
+        body: &dyn EventBody,
+        diagnostics: &mut dyn FnMut(Diagnostic),
+    ) -> usize {
+        if let Some(server_version) = self.flavor_tracker.update(body) {
+            diagnostics(Diagnostic::ServerVersion(server_version.clone()));
+        }
+
+        let event_checksum_length = self.checksum_length;
+        update_checksum_length(&mut self.checksum_length, body);
+        event_checksum_length
+    }
+
+    /// 表结构的变化和还不支持的内容、不是FDE中的服务端会写的内容
+    ///
+    /// `--strict`时这些内容直接作为错误返回，否则返回跳过的内容，由调用方计入ErrorReport
+    pub fn check(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        diagnostics: &mut dyn FnMut(Diagnostic),
+    ) -> Result<Option<ParseError>, ParseError> {
+        if let Some(change) = self.schema_tracker.process(position, body) {
+            diagnostics(Diagnostic::SchemaChange(change));
+        }
+
+        match find_unsupported(header, position, body)
+            .or_else(|| self.flavor_tracker.check(header, position, body))
+        {
+            Some(issue) if self.strict => Err(issue),
+            issue => Ok(issue),
+        }
+    }
+}
+
+/// 整个body会读到内存中，所以在读取之前检查`--max-event-size`
+pub fn check_event_size(
+    header: &EventHeader,
+    position: u64,
+    max_event_size: Option<u64>,
+) -> Result<(), ParseError> {
+    match max_event_size {
+        Some(max_event_size) if header.event_length as u64 > max_event_size => {
+            Err(ParseError::new(
+                ErrorCategory::Other,
+                format!(
+                    "the event is {} bytes, larger than `--max-event-size` {}",
+                    header.event_length, max_event_size
+                ),
+            )
+            .at(position, Some(header.type_code)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// human、json、table和`--rows-only`的事件输出，写到调用方给的writer中
+///
+/// 其他的输出方式按human输出，`--format`、`--hexdump`这些只有顺序处理文件时才有的内容不在这里
+pub struct EventWriter {
+    output: OutputFormat,
+    styler: Styler,
+    table_renderer: TableRenderer,
+    rows_only_formatter: Option<RowsOnlyFormatter>,
+}
+
+impl EventWriter {
+    pub fn new(output: OutputFormat, color: ColorChoice) -> EventWriter {
+        EventWriter {
+            output,
+            styler: Styler::new(color),
+            table_renderer: TableRenderer::new(DEFAULT_MAX_COLUMN_WIDTH),
+            rows_only_formatter: None,
+        }
+    }
+
+    pub fn from_options(options: &CliOptions) -> EventWriter {
+        EventWriter {
+            output: options.output,
+            styler: Styler::new(options.color),
+            table_renderer: TableRenderer::new(
+                options.max_column_width.unwrap_or(DEFAULT_MAX_COLUMN_WIDTH),
+            ),
+            rows_only_formatter: options.rows_only.then(RowsOnlyFormatter::new),
+        }
+    }
+
+    /// 每个事件都要经过这里，`--rows-only`需要记录GTID和主键，返回是否输出了内容
+    pub fn write(
+        &mut self,
+        out: &mut dyn Write,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> io::Result<bool> {
+        if let Some(rows_only_formatter) = self.rows_only_formatter.as_mut() {
+            let lines = rows_only_formatter.process(header, body, table_structs);
+            for line in &lines {
+                writeln!(out, "{}", line)?;
+            }
+            return Ok(!lines.is_empty());
+        }
+
+        match self.output {
+            OutputFormat::Json => writeln!(
+                out,
+                "{}",
+                json!({
+                    "position": position,
+                    "header": header,
+                    "body": body,
+                })
+            )?,
+            OutputFormat::Table => write!(
+                out,
+                "{}",
+                self.table_renderer
+                    .process(header, position, body, table_structs)
+            )?,
+            _ => write!(out, "{}\n\n", self.styler.event(header, body))?,
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::flavor::ServerVersion;
+    use std::io::Cursor;
+
+    // 依次解码文件中的事件，返回输出和提示
+    fn run(
+        binlog: &[u8],
+        mut pipeline: EventPipeline,
+        mut writer: EventWriter,
+    ) -> Result<(String, Vec<String>, Vec<ParseError>), ParseError> {
+        let mut file = Cursor::new(binlog);
+        let mut out = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut issues = Vec::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = pipeline.decode(&mut file, position, &header)?;
+            pipeline.update(body.as_ref(), &mut |v| diagnostics.push(v.to_string()));
+            issues.extend(pipeline.check(&header, position, body.as_ref(), &mut |v| {
+                diagnostics.push(v.to_string())
+            })?);
+            writer
+                .write(
+                    &mut out,
+                    &header,
+                    position,
+                    body.as_ref(),
+                    &pipeline.table_structs,
+                )
+                .unwrap();
+            position = header.next_event_position as u64;
+        }
+
+        Ok((String::from_utf8(out).unwrap(), diagnostics, issues))
+    }
+
+    fn orders(columns: &[(FieldType, &[u8])]) -> TableMapBuilder {
+        let mut table_map = TableMapBuilder::new("shop", "orders").table_id(7);
+        for (field_type, meta) in columns {
+            table_map = table_map.column(*field_type, meta);
+        }
+        table_map
+    }
+
+    #[test]
+    fn writes_each_event_to_the_writer() {
+        let table_map = orders(&[(FieldType::Long, &[])]);
+        let binlog = BinlogBuilder::new()
+            .event(&table_map)
+            .event(&RowsEventBuilder::insert(&table_map).row(vec![ColumnValue::Int(1)]))
+            .build();
+
+        let (out, diagnostics, issues) = run(
+            &binlog,
+            EventPipeline::new(ValueOptions::default(), false),
+            EventWriter::new(OutputFormat::Json, ColorChoice::Never),
+        )
+        .unwrap();
+
+        let events = out
+            .lines()
+            .map(|v| serde_json::from_str::<serde_json::Value>(v).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["position"], 4);
+        assert_eq!(events[2]["header"]["type_code"], 23);
+        assert_eq!(
+            diagnostics,
+            [Diagnostic::ServerVersion(ServerVersion::parse("10.11.6-MariaDB-log")).to_string()]
+        );
+        assert!(issues.is_empty());
+
+        let (out, _, _) = run(
+            &binlog,
+            EventPipeline::new(ValueOptions::default(), false),
+            EventWriter::new(OutputFormat::Human, ColorChoice::Never),
+        )
+        .unwrap();
+        assert_eq!(out.matches("\n\n").count(), 3);
+    }
+
+    #[test]
+    fn schema_changes_are_diagnostics() {
+        let before = orders(&[(FieldType::Long, &[])]);
+        let after = orders(&[(FieldType::Long, &[]), (FieldType::Short, &[])]).table_id(8);
+        let binlog = BinlogBuilder::new().event(&before).event(&after).build();
+
+        let (_, diagnostics, _) = run(
+            &binlog,
+            EventPipeline::new(ValueOptions::default(), false),
+            EventWriter::new(OutputFormat::Human, ColorChoice::Never),
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[1].starts_with("warning: the schema of `shop`.`orders` changed"));
+    }
+
+    #[test]
+    fn unsupported_content_is_skipped_unless_strict() {
+        let binlog = BinlogBuilder::new()
+            .push(EventBuilder::new(170, vec![0; 8]))
+            .build();
+
+        let (_, _, issues) = run(
+            &binlog,
+            EventPipeline::new(ValueOptions::default(), false),
+            EventWriter::new(OutputFormat::Human, ColorChoice::Never),
+        )
+        .unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].category, ErrorCategory::UnsupportedEvent);
+
+        let error = run(
+            &binlog,
+            EventPipeline::new(ValueOptions::default(), true),
+            EventWriter::new(OutputFormat::Human, ColorChoice::Never),
+        )
+        .unwrap_err();
+        assert_eq!(error.category, ErrorCategory::UnsupportedEvent);
+    }
+
+    #[test]
+    fn event_size_limit() {
+        let header = EventHeader {
+            timestamp: 0,
+            type_code: 2,
+            server_id: 1,
+            event_length: 1000,
+            next_event_position: 1004,
+            flags: 0,
+        };
+
+        assert!(check_event_size(&header, 4, None).is_ok());
+        assert!(check_event_size(&header, 4, Some(1000)).is_ok());
+        let error = check_event_size(&header, 4, Some(999)).unwrap_err();
+        assert_eq!(error.offset, Some(4));
+    }
+}
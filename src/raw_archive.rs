@@ -0,0 +1,321 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::diagnostic::Diagnostic;
+use crate::model::{EventBodyTypeCode4, EventHeader, MyError};
+use crate::service::{deal_type_code_4, detect_checksum_length, get_event_header, strip_checksum};
+use crate::util::check_file_magic_number;
+use crate::writer::BinlogWriter;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+const ROTATE_EVENT: u8 = 4;
+const HEARTBEAT_LOG_EVENT: u8 = 27;
+
+// 服务端生成的事件，不在binlog文件中，例如dump开始时的rotate
+const LOG_EVENT_ARTIFICIAL_F: u16 = 0x20;
+
+/// 文件中最后一个完整事件的结尾，dump中断时文件最后可能只写了事件的一部分
+pub fn last_complete_event_end(file_path: &Path) -> Result<u64, BoxedError> {
+    let mut file = File::open(file_path)?;
+    let length = file.metadata()?.len();
+
+    if length < 4 || !check_file_magic_number(&mut file)? {
+        return Err(Box::new(MyError(format!(
+            "`{}` exists but is not a binlog file, refusing to overwrite it",
+            file_path.display()
+        ))));
+    }
+
+    let mut offset = 4;
+    while offset + EVENT_HEADER_LENGTH <= length {
+        let header = get_event_header(&mut file, offset)?;
+        let end = offset + header.event_length as u64;
+
+        if (header.event_length as u64) < EVENT_HEADER_LENGTH || end > length {
+            break;
+        }
+        offset = end;
+    }
+
+    Ok(offset)
+}
+
+/// `--raw --result-dir DIR`，把服务端发送的事件原样写到DIR中和服务端同名的文件，得到和服务端一样的binlog
+///
+/// 文件名来自服务端的rotate事件，每个文件以rotate结束时和dump结束时fsync。DIR中已经有的文件从最后一个完整的事件之后继续写，
+/// 服务端重新发送的、文件中已经有的事件必须和文件中的字节一样，不一样时报错，不会覆盖已有的文件
+pub struct RawArchiver {
+    dir: PathBuf,
+    // 下一个事件所在的文件和位置，来自rotate事件
+    file_name: Option<String>,
+    position: u64,
+    current: Option<ArchiveFile>,
+    // 截断、写完文件的提示，由调用方取走
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// 正在写的文件，writer的位置之前是文件中已有的内容
+struct ArchiveFile {
+    name: String,
+    path: PathBuf,
+    reader: File,
+    writer: BinlogWriter<File>,
+}
+
+impl RawArchiver {
+    pub fn new(dir: &str) -> Result<RawArchiver, BoxedError> {
+        fs::create_dir_all(dir)
+            .map_err(|e| MyError(format!("can not create the result dir `{}`: {}", dir, e)))?;
+
+        Ok(RawArchiver {
+            dir: PathBuf::from(dir),
+            file_name: None,
+            position: 0,
+            current: None,
+            diagnostics: Vec::new(),
+        })
+    }
+
+    /// 从服务端的哪个位置开始dump：本地没有这个文件时是4，有时是最后一个完整事件的结尾
+    pub fn resume_position(&self, file_name: &str) -> Result<u64, BoxedError> {
+        let path = self.dir.join(file_name);
+
+        match path.exists() {
+            true => last_complete_event_end(&path),
+            false => Ok(4),
+        }
+    }
+
+    /// 处理服务端发送的一个事件，返回它是不是文件中的事件
+    pub fn archive(&mut self, event: &[u8]) -> Result<bool, BoxedError> {
+        let header = get_event_header(&mut Cursor::new(event), 0)?;
+
+        if header.event_length as usize != event.len() {
+            return Err(Box::new(MyError(format!(
+                "the event length is {} but the server sent {} bytes",
+                header.event_length,
+                event.len()
+            ))));
+        }
+
+        // 服务端生成的事件不写入文件，其中的rotate给出之后事件的文件名和位置，
+        // 从文件中间开始时重新发送的FDE的next_event_position为0
+        if header.flags & LOG_EVENT_ARTIFICIAL_F != 0
+            || header.next_event_position == 0
+            || header.type_code == HEARTBEAT_LOG_EVENT
+        {
+            if header.type_code == ROTATE_EVENT {
+                let (next_file_name, next_position) = parse_rotate(&header, event)?;
+                self.switch(&next_file_name, next_position)?;
+            }
+            return Ok(false);
+        }
+
+        let file_name = self
+            .file_name
+            .clone()
+            .ok_or_else(|| MyError("the server sent an event before any rotate".to_string()))?;
+
+        let event_position =
+            (header.next_event_position as u64).saturating_sub(header.event_length as u64);
+        if event_position != self.position {
+            return Err(Box::new(MyError(format!(
+                "expected the event at {} in `{}`, but the server sent the one at {}",
+                self.position, file_name, event_position
+            ))));
+        }
+
+        if self.current.is_none() {
+            self.current = Some(ArchiveFile::open(
+                &self.dir,
+                &file_name,
+                self.position,
+                &mut self.diagnostics,
+            )?);
+        }
+        if let Some(current) = self.current.as_mut() {
+            current.write(event_position, event)?;
+        }
+        self.position = header.next_event_position as u64;
+
+        // 文件的最后一个事件，之后的事件在下一个文件的开头
+        if header.type_code == ROTATE_EVENT {
+            let (next_file_name, next_position) = parse_rotate(&header, event)?;
+            self.switch(&next_file_name, next_position)?;
+        }
+
+        Ok(true)
+    }
+
+    /// 下一个事件的文件名和位置，也就是已经写入的位置
+    pub fn position(&self) -> Option<(&str, u64)> {
+        self.file_name
+            .as_deref()
+            .map(|file_name| (file_name, self.position))
+    }
+
+    /// 把正在写的文件fsync，dump结束和出错时调用
+    pub fn finish(&mut self) -> Result<(), BoxedError> {
+        match self.current.take() {
+            Some(current) => current.close(&mut self.diagnostics),
+            None => Ok(()),
+        }
+    }
+
+    /// 到目前为止的提示，每次调用之后清空
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    fn switch(&mut self, file_name: &str, position: u64) -> Result<(), BoxedError> {
+        if self.file_name.as_deref() == Some(file_name) && self.position == position {
+            return Ok(());
+        }
+
+        // 文件名来自服务端，只能是DIR中的文件
+        if Path::new(file_name).file_name() != Some(file_name.as_ref()) {
+            return Err(Box::new(MyError(format!(
+                "the server rotated to `{}`, which is not a plain file name",
+                file_name
+            ))));
+        }
+
+        self.finish()?;
+        self.file_name = Some(file_name.to_string());
+        self.position = position;
+
+        Ok(())
+    }
+}
+
+impl ArchiveFile {
+    /// 已有的文件截掉最后不完整的事件，从position开始和文件中的内容比较；新的文件只能从4开始
+    fn open(
+        dir: &Path,
+        file_name: &str,
+        position: u64,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<ArchiveFile, BoxedError> {
+        let path = dir.join(file_name);
+
+        let writer = match path.exists() {
+            true => {
+                let end = last_complete_event_end(&path)?;
+                if position > end {
+                    return Err(Box::new(MyError(format!(
+                        "`{}` ends at {}, but the server starts at {}",
+                        path.display(),
+                        end,
+                        position
+                    ))));
+                }
+
+                let mut file = OpenOptions::new().write(true).open(&path)?;
+                if file.metadata()?.len() > end {
+                    diagnostics.push(Diagnostic::Note(format!(
+                        "truncating the incomplete event at the end of `{}` from {}",
+                        path.display(),
+                        end
+                    )));
+                    file.set_len(end)?;
+                }
+                file.seek(SeekFrom::Start(end))?;
+
+                BinlogWriter::resume(file, end)
+            }
+            false => {
+                if position != 4 {
+                    return Err(Box::new(MyError(format!(
+                        "`{}` does not exist, but the server starts at {}, start from position 4 to archive the whole file",
+                        path.display(),
+                        position
+                    ))));
+                }
+
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                let writer = BinlogWriter::new(file)?;
+
+                // 新文件的目录项也要落盘
+                if let Ok(dir) = File::open(dir) {
+                    dir.sync_all().ok();
+                }
+
+                writer
+            }
+        };
+
+        Ok(ArchiveFile {
+            name: file_name.to_string(),
+            reader: File::open(&path)?,
+            path,
+            writer,
+        })
+    }
+
+    /// 文件中已有的事件只比较，之后的追加
+    fn write(&mut self, event_position: u64, event: &[u8]) -> Result<(), BoxedError> {
+        let end = self.writer.position();
+        if event_position >= end {
+            self.writer.write_raw(event)?;
+            return Ok(());
+        }
+
+        let mut existing = vec![0u8; event.len()];
+        let same = event_position + event.len() as u64 <= end
+            && self.reader.seek(SeekFrom::Start(event_position)).is_ok()
+            && self.reader.read_exact(&mut existing).is_ok()
+            && existing == event;
+
+        if !same {
+            return Err(Box::new(MyError(format!(
+                "`{}` diverges from the server at position {}, refusing to overwrite it",
+                self.path.display(),
+                event_position
+            ))));
+        }
+
+        Ok(())
+    }
+
+    fn close(mut self, diagnostics: &mut Vec<Diagnostic>) -> Result<(), BoxedError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+
+        diagnostics.push(Diagnostic::Note(format!(
+            "archived `{}` up to position {}",
+            self.name,
+            self.writer.position()
+        )));
+
+        Ok(())
+    }
+}
+
+/// rotate中的下一个文件名和位置，文件名一直到body的结尾，要先去掉校验和
+fn parse_rotate(header: &EventHeader, event: &[u8]) -> Result<(String, u64), BoxedError> {
+    let body = strip_checksum(
+        event[EVENT_HEADER_LENGTH as usize..].to_vec(),
+        detect_checksum_length(event),
+    )?;
+    let body = deal_type_code_4(body)?;
+    let rotate = body.downcast_ref::<EventBodyTypeCode4>().ok_or_else(|| {
+        MyError(format!(
+            "rotate event of {} bytes can not be parsed",
+            header.event_length
+        ))
+    })?;
+
+    Ok((
+        rotate.file_name_of_next_binary_log.clone(),
+        rotate.position_of_the_first_event_in_next_log_file,
+    ))
+}
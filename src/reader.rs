@@ -0,0 +1,722 @@
+use std::{
+    borrow::Cow,
+    cell::OnceCell,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+use crate::byte_range::{is_url, open_source, ByteRangeSource};
+use crate::diagnostic::Diagnostic;
+
+// 一次读取的大小，比事件头和大多数小事件都大得多
+const BUFFER_SIZE: usize = 256 * 1024;
+
+// 按范围读取时一次请求的大小，对象存储的每次请求都有几十毫秒的延迟
+const RANGE_REQUEST_SIZE: usize = 8 * 1024 * 1024;
+
+// 解压时在当前读取的位置之前保留的字节数，回头读取当前事件的header、校验和时不需要重新解压
+const KEEP_BEHIND: u64 = 64 * 1024;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 归档时压缩过的binlog
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// 先按开头的magic bytes判断，改过名字的文件也能识别，不是压缩格式时再看扩展名
+    pub fn detect(file_path: &str) -> io::Result<Option<Compression>> {
+        let mut magic = Vec::new();
+        File::open(file_path)?.take(4).read_to_end(&mut magic)?;
+
+        let compression = match magic.as_slice() {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd] => Some(Compression::Zstd),
+            _ => match Path::new(file_path).extension().and_then(|v| v.to_str()) {
+                Some("gz") => Some(Compression::Gzip),
+                Some("zst") => Some(Compression::Zstd),
+                _ => None,
+            },
+        };
+
+        Ok(compression)
+    }
+
+    /// 多个gzip member或者zstd frame拼接的文件也会全部解压
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn decoder(self, file: File) -> io::Result<Box<dyn Read>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(file))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(file)?)),
+            #[allow(unreachable_patterns)]
+            others => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} support is not compiled in, please build with `--features {}`",
+                    others, others
+                ),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+enum Source {
+    Buffered(BufReader<File>),
+    /// 映射时文件的长度是固定的，之后追加的内容读不到
+    #[cfg(feature = "mmap")]
+    Mapped {
+        file: File,
+        map: Mmap,
+    },
+    Decompressed(Box<Decompressed>),
+    Ranged(Box<Ranged>),
+}
+
+/// 最近一次按范围读取的内容，需要的字节不在其中时从需要的位置开始重新请求
+struct Ranged {
+    source: Box<dyn ByteRangeSource>,
+    window: Vec<u8>,
+    window_start: u64,
+}
+
+impl Ranged {
+    /// 保证[offset, end)在窗口中，超过结尾的部分不请求
+    fn fill(&mut self, offset: u64, end: u64) -> io::Result<()> {
+        let end = end.min(self.source.length());
+        let window_end = self.window_start + self.window.len() as u64;
+        if offset >= end || (offset >= self.window_start && end <= window_end) {
+            return Ok(());
+        }
+
+        let length = ((end - offset) as usize).max(RANGE_REQUEST_SIZE);
+        self.window = self.source.read_at(offset, length)?;
+        self.window_start = offset;
+
+        Ok(())
+    }
+
+    fn available(&self, offset: u64) -> &[u8] {
+        match offset.checked_sub(self.window_start) {
+            Some(start) if start < self.window.len() as u64 => &self.window[start as usize..],
+            _ => &[],
+        }
+    }
+}
+
+/// 解压后的内容只能向后读取，保留最近解压的一段，在这段之前的位置只能从头重新解压
+struct Decompressed {
+    file_path: String,
+    compression: Compression,
+    // 压缩的文件，只用于get_ref
+    file: File,
+    decoder: Box<dyn Read>,
+    // 已经解压的一段内容，第一个字节在解压后的位置是window_start
+    window: Vec<u8>,
+    window_start: u64,
+    // 解压后的长度，解压到结尾之前不知道
+    length: OnceCell<u64>,
+    restarted: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Decompressed {
+    fn open(file_path: &str, compression: Compression) -> io::Result<Decompressed> {
+        Ok(Decompressed {
+            file_path: file_path.to_string(),
+            compression,
+            file: File::open(file_path)?,
+            decoder: compression.decoder(File::open(file_path)?)?,
+            window: Vec::new(),
+            window_start: 0,
+            length: OnceCell::new(),
+            restarted: false,
+            diagnostics: Vec::new(),
+        })
+    }
+
+    fn window_end(&self) -> u64 {
+        self.window_start + self.window.len() as u64
+    }
+
+    /// 第一次需要长度时单独解压一遍整个文件
+    fn length(&self) -> io::Result<u64> {
+        if let Some(length) = self.length.get() {
+            return Ok(*length);
+        }
+
+        let mut decoder = self.compression.decoder(File::open(&self.file_path)?)?;
+        let length = io::copy(&mut decoder, &mut io::sink())?;
+        let _ = self.length.set(length);
+
+        Ok(length)
+    }
+
+    /// 解压到end为止，文件比end短时到结尾为止，offset之前KEEP_BEHIND以外的内容不再保留
+    fn fill(&mut self, offset: u64, end: u64) -> io::Result<()> {
+        if offset < self.window_start {
+            if !self.restarted {
+                self.diagnostics.push(Diagnostic::Warning(format!(
+                    "reading backwards in the compressed binlog `{}`, decompressing it again from the start",
+                    self.file_path
+                )));
+                self.restarted = true;
+            }
+
+            self.decoder = self.compression.decoder(File::open(&self.file_path)?)?;
+            self.window.clear();
+            self.window_start = 0;
+        }
+
+        let keep_from = offset.saturating_sub(KEEP_BEHIND);
+        while self.window_end() < end {
+            if self.window_start < keep_from {
+                let drop = (keep_from.min(self.window_end()) - self.window_start) as usize;
+                self.window.drain(..drop);
+                self.window_start += drop as u64;
+            }
+
+            let length = self.window.len();
+            self.window.resize(length + BUFFER_SIZE, 0);
+            let read = loop {
+                match self.decoder.read(&mut self.window[length..]) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result,
+                }
+            };
+            self.window
+                .truncate(length + read.as_ref().copied().unwrap_or_default());
+
+            match read? {
+                0 => {
+                    let _ = self.length.set(self.window_end());
+                    break;
+                }
+                _ => continue,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 已经在窗口中的从offset开始的内容
+    fn available(&self, offset: u64) -> &[u8] {
+        match offset.checked_sub(self.window_start) {
+            Some(start) if start < self.window.len() as u64 => &self.window[start as usize..],
+            _ => &[],
+        }
+    }
+}
+
+/// 带缓冲的binlog文件，解析时按顺序读取事件
+/// get_event_header、get_event_body这些函数每次都会seek到事件的位置，
+/// 目标就是当前位置或者还在缓冲区里时不需要系统调用，所以顺序解析时每256KB只有一次read
+///
+/// gzip、zstd压缩的文件边读取边解压，seek只记录位置，向后的seek在读取时跳过中间的内容，
+/// 向前超过保留的范围时从头重新解压，并记录一次警告，由调用方通过take_diagnostics取走
+pub struct BinlogReader {
+    source: Source,
+    // 下一个读取的字节在文件中的位置
+    position: u64,
+}
+
+impl BinlogReader {
+    pub fn new(file: File) -> BinlogReader {
+        BinlogReader {
+            source: Source::Buffered(BufReader::with_capacity(BUFFER_SIZE, file)),
+            position: 0,
+        }
+    }
+
+    /// 打开binlog文件，压缩的文件按Compression::detect识别，需要编译时打开gzip、zstd特性
+    /// `http://`、`https://`开头的是对象存储的URL，按范围读取，需要编译时打开s3特性
+    pub fn open(file_path: &str) -> Result<BinlogReader, BoxedError> {
+        if is_url(file_path) {
+            return Ok(BinlogReader::from_source(open_source(file_path)?));
+        }
+
+        let source = match Compression::detect(file_path)? {
+            Some(compression) => {
+                Source::Decompressed(Box::new(Decompressed::open(file_path, compression)?))
+            }
+            None => Source::Buffered(BufReader::with_capacity(
+                BUFFER_SIZE,
+                File::open(file_path)?,
+            )),
+        };
+
+        Ok(BinlogReader {
+            source,
+            position: 0,
+        })
+    }
+
+    /// 在按范围读取的数据源上读取，顺序读取时每次请求8MB
+    pub fn from_source(source: Box<dyn ByteRangeSource>) -> BinlogReader {
+        BinlogReader {
+            source: Source::Ranged(Box::new(Ranged {
+                source,
+                window: Vec::new(),
+                window_start: 0,
+            })),
+            position: 0,
+        }
+    }
+
+    /// 把整个文件映射到内存，读取时直接从映射中复制，没有系统调用
+    /// 映射失败时（比如空文件、不支持mmap的文件系统）退回到带缓冲的读取，压缩的文件不映射，和open一样
+    ///
+    /// 映射之后文件被截断（比如`repair --truncate`或者手工截断）时，访问截断部分会收到SIGBUS，
+    /// 这在Rust中无法捕获，所以只应该用于不会被截断的文件。正在写的文件追加内容是安全的，
+    /// 只是映射之后追加的内容读不到，所有读取都按映射时的长度检查边界
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap(file_path: &str) -> Result<BinlogReader, BoxedError> {
+        if is_url(file_path) || Compression::detect(file_path)?.is_some() {
+            return BinlogReader::open(file_path);
+        }
+
+        let file = File::open(file_path)?;
+
+        // 安全性见上面的说明
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => Ok(BinlogReader {
+                source: Source::Mapped { file, map },
+                position: 0,
+            }),
+            Err(_) => Ok(BinlogReader::new(file)),
+        }
+    }
+
+    /// 压缩时是压缩的文件，按范围读取时没有文件
+    pub fn get_ref(&self) -> Option<&File> {
+        match &self.source {
+            Source::Buffered(reader) => Some(reader.get_ref()),
+            #[cfg(feature = "mmap")]
+            Source::Mapped { file, .. } => Some(file),
+            Source::Decompressed(decompressed) => Some(&decompressed.file),
+            Source::Ranged(_) => None,
+        }
+    }
+
+    /// 可以读取的长度，映射时是映射的长度，压缩时是解压后的长度
+    pub fn file_length(&self) -> io::Result<u64> {
+        match &self.source {
+            Source::Buffered(reader) => Ok(reader.get_ref().metadata()?.len()),
+            #[cfg(feature = "mmap")]
+            Source::Mapped { map, .. } => Ok(map.len() as u64),
+            Source::Decompressed(decompressed) => decompressed.length(),
+            Source::Ranged(ranged) => Ok(ranged.source.length()),
+        }
+    }
+
+    /// 压缩的文件返回它的压缩格式
+    pub fn compression(&self) -> Option<Compression> {
+        match &self.source {
+            Source::Decompressed(decompressed) => Some(decompressed.compression),
+            _ => None,
+        }
+    }
+
+    /// 映射时直接返回映射中的一段，不复制，可以交给parse_event_ref；没有映射或者超出映射的长度时为None
+    #[cfg_attr(not(feature = "mmap"), allow(unused_variables))]
+    pub fn mapped_slice(&self, offset: u64, length: usize) -> Option<&[u8]> {
+        match &self.source {
+            Source::Buffered(_) | Source::Decompressed(_) | Source::Ranged(_) => None,
+            #[cfg(feature = "mmap")]
+            Source::Mapped { map, .. } => map.get(offset as usize..offset as usize + length),
+        }
+    }
+
+    /// offset开始的length个字节，读取的位置之后是不确定的，和其他读取一样先seek
+    /// 映射时或者这些字节都还在缓冲区里时直接借用，不复制；跨过缓冲区末尾或者比缓冲区大时才读取到新的Vec
+    pub fn peek(&mut self, offset: u64, length: usize) -> io::Result<Cow<'_, [u8]>> {
+        self.seek(SeekFrom::Start(offset))?;
+
+        let borrowed = match &mut self.source {
+            Source::Buffered(reader) => reader.fill_buf()?.len() >= length,
+            #[cfg(feature = "mmap")]
+            Source::Mapped { map, .. } => map.len() >= offset as usize + length,
+            Source::Decompressed(decompressed) => {
+                decompressed.fill(offset, offset + length as u64)?;
+                decompressed.available(offset).len() >= length
+            }
+            Source::Ranged(ranged) => {
+                ranged.fill(offset, offset + length as u64)?;
+                ranged.available(offset).len() >= length
+            }
+        };
+
+        if borrowed {
+            let slice = match &self.source {
+                Source::Buffered(reader) => &reader.buffer()[..length],
+                #[cfg(feature = "mmap")]
+                Source::Mapped { map, .. } => &map[offset as usize..offset as usize + length],
+                Source::Decompressed(decompressed) => &decompressed.available(offset)[..length],
+                Source::Ranged(ranged) => &ranged.available(offset)[..length],
+            };
+            return Ok(Cow::Borrowed(slice));
+        }
+
+        let mut buffer = vec![0u8; length];
+        self.read_exact(&mut buffer)?;
+
+        Ok(Cow::Owned(buffer))
+    }
+
+    /// 到目前为止的提示（从头重新解压），每次调用之后清空
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        match &mut self.source {
+            Source::Decompressed(decompressed) => std::mem::take(&mut decompressed.diagnostics),
+            _ => Vec::new(),
+        }
+    }
+
+    /// 是否读取的是内存映射
+    pub fn is_mapped(&self) -> bool {
+        match &self.source {
+            Source::Buffered(_) | Source::Decompressed(_) | Source::Ranged(_) => false,
+            #[cfg(feature = "mmap")]
+            Source::Mapped { .. } => true,
+        }
+    }
+}
+
+impl Read for BinlogReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = match &mut self.source {
+            Source::Buffered(reader) => reader.read(buf)?,
+            // 超过映射的长度时和文件结束一样返回0
+            #[cfg(feature = "mmap")]
+            Source::Mapped { map, .. } => {
+                let start = (self.position as usize).min(map.len());
+                let length = buf.len().min(map.len() - start);
+                buf[..length].copy_from_slice(&map[start..start + length]);
+                length
+            }
+            Source::Decompressed(decompressed) => {
+                decompressed.fill(self.position, self.position + buf.len() as u64)?;
+                let available = decompressed.available(self.position);
+                let length = buf.len().min(available.len());
+                buf[..length].copy_from_slice(&available[..length]);
+                length
+            }
+            Source::Ranged(ranged) => {
+                ranged.fill(self.position, self.position + buf.len() as u64)?;
+                let available = ranged.available(self.position);
+                let length = buf.len().min(available.len());
+                buf[..length].copy_from_slice(&available[..length]);
+                length
+            }
+        };
+        self.position += length as u64;
+
+        Ok(length)
+    }
+}
+
+impl Seek for BinlogReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => target,
+            SeekFrom::Current(offset) => {
+                self.position.checked_add_signed(offset).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position")
+                })?
+            }
+            SeekFrom::End(offset) => {
+                let length = self.file_length()?;
+                length.checked_add_signed(offset).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position")
+                })?
+            }
+        };
+
+        // 目标在缓冲区内时seek_relative只移动缓冲区中的位置
+        if target != self.position {
+            match &mut self.source {
+                Source::Buffered(reader) => {
+                    reader.seek_relative(target as i64 - self.position as i64)?
+                }
+                #[cfg(feature = "mmap")]
+                Source::Mapped { .. } => {}
+                // 读取时再解压到目标位置，或者请求目标位置开始的范围
+                Source::Decompressed(_) | Source::Ranged(_) => {}
+            }
+            self.position = target;
+        }
+
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::byte_range::LocalFileSource;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+    use std::{collections::HashMap, io::Cursor};
+
+    #[cfg(feature = "gzip")]
+    fn write_gzip(name: &str, bytes: &[u8]) -> String {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("reader-{}-{}.gz", std::process::id(), name));
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path).unwrap(), Default::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // 每个事件的位置和输出
+    fn events<R: Read + Seek>(file: &mut R) -> Vec<(u64, String)> {
+        let file_length = file.seek(SeekFrom::End(0)).unwrap();
+        let mut table_structs = HashMap::new();
+        let mut events = Vec::new();
+
+        let mut position = 4;
+        while position < file_length {
+            let header = get_event_header(file, position).unwrap();
+            let body = get_event_body(file, position, &header, &mut table_structs, CHECKSUM_LENGTH)
+                .unwrap();
+            events.push((position, format!("{:?}", body)));
+            position = header.next_event_position as u64;
+        }
+
+        events
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzipped_binlog_is_parsed_like_the_original() {
+        let mut binlog = BinlogBuilder::new();
+        for i in 0..100 {
+            binlog = binlog.event(&QueryEventBuilder::new(
+                "shop",
+                &format!("DELETE FROM t{}", i),
+            ));
+        }
+        let binlog = binlog.build();
+        let path = write_gzip("parse", &binlog);
+
+        let mut compressed = BinlogReader::open(&path).unwrap();
+        assert_eq!(compressed.compression(), Some(Compression::Gzip));
+        assert_eq!(compressed.file_length().unwrap(), binlog.len() as u64);
+
+        let original = path.trim_end_matches(".gz").to_string();
+        std::fs::write(&original, &binlog).unwrap();
+        let expected = events(&mut BinlogReader::open(&original).unwrap());
+        assert_eq!(expected.len(), 101);
+        assert_eq!(events(&mut compressed), expected);
+        assert!(compressed.take_diagnostics().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(original).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn reading_backwards_is_a_diagnostic() {
+        let bytes = (0..4 * BUFFER_SIZE).map(|v| v as u8).collect::<Vec<_>>();
+        let path = write_gzip("backwards", &bytes);
+
+        let mut file = BinlogReader::open(&path).unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, bytes);
+        assert!(file.take_diagnostics().is_empty());
+
+        for _ in 0..2 {
+            let mut head = [0; 4];
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_exact(&mut head).unwrap();
+            assert_eq!(head, [0, 1, 2, 3]);
+        }
+
+        let diagnostics = file.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(
+            matches!(&diagnostics[0], Diagnostic::Warning(message) if message.contains("decompressing it again"))
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn buffered_reader_is_parsed_like_a_seeking_reader() {
+        // 比缓冲区大，事件会跨过缓冲区的边界
+        let mut binlog = BinlogBuilder::new();
+        for i in 0..3000 {
+            binlog = binlog.event(&QueryEventBuilder::new(
+                "shop",
+                &format!(
+                    "UPDATE t SET c = '{}' WHERE id = {}",
+                    "x".repeat(i % 300),
+                    i
+                ),
+            ));
+        }
+        let binlog = binlog.build();
+        assert!(binlog.len() > 2 * BUFFER_SIZE);
+
+        let path = std::env::temp_dir().join(format!("reader-{}-buffered", std::process::id()));
+        std::fs::write(&path, &binlog).unwrap();
+        let mut file = BinlogReader::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(file.compression(), None);
+        assert_eq!(file.file_length().unwrap(), binlog.len() as u64);
+
+        let expected = events(&mut Cursor::new(&binlog));
+        assert_eq!(expected.len(), 3001);
+        assert_eq!(events(&mut file), expected);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn seeks_inside_and_outside_the_buffer() {
+        let bytes = (0..3 * BUFFER_SIZE as u64)
+            .map(|v| (v % 251) as u8)
+            .collect::<Vec<_>>();
+        let path = std::env::temp_dir().join(format!("reader-{}-seek", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let mut file = BinlogReader::new(File::open(&path).unwrap());
+
+        let mut read_at = |pos: SeekFrom, expected_start: usize| {
+            let mut buffer = [0; 16];
+            assert_eq!(file.seek(pos).unwrap(), expected_start as u64);
+            file.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, bytes[expected_start..expected_start + 16]);
+        };
+
+        read_at(SeekFrom::Start(100), 100);
+        // 缓冲区中向前、向后
+        read_at(SeekFrom::Start(50), 50);
+        read_at(SeekFrom::Current(1000), 1066);
+        // 缓冲区以外
+        read_at(
+            SeekFrom::Start(2 * BUFFER_SIZE as u64 + 7),
+            2 * BUFFER_SIZE + 7,
+        );
+        read_at(SeekFrom::Start(3), 3);
+        read_at(SeekFrom::End(-16), bytes.len() - 16);
+
+        let mut rest = Vec::new();
+        assert_eq!(file.read_to_end(&mut rest).unwrap(), 0);
+        assert!(file
+            .seek(SeekFrom::Current(-(bytes.len() as i64) - 1))
+            .is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // 内存中的数据源，记录每次请求的范围
+    struct MemorySource {
+        bytes: Vec<u8>,
+        requests: std::sync::Arc<std::sync::Mutex<Vec<(u64, usize)>>>,
+    }
+
+    impl ByteRangeSource for MemorySource {
+        fn length(&self) -> u64 {
+            self.bytes.len() as u64
+        }
+
+        fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+            self.requests.lock().unwrap().push((offset, length));
+            let start = (offset as usize).min(self.bytes.len());
+            let end = (start + length).min(self.bytes.len());
+            Ok(self.bytes[start..end].to_vec())
+        }
+    }
+
+    #[test]
+    fn ranged_reads_reuse_the_last_window() {
+        let bytes = (0..RANGE_REQUEST_SIZE as u64 + 4096)
+            .map(|v| (v % 251) as u8)
+            .collect::<Vec<_>>();
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut file = BinlogReader::from_source(Box::new(MemorySource {
+            bytes: bytes.clone(),
+            requests: requests.clone(),
+        }));
+        assert_eq!(file.file_length().unwrap(), bytes.len() as u64);
+        assert!(file.get_ref().is_none());
+
+        let mut read_at = |offset: u64| {
+            let mut buffer = [0; 16];
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, bytes[offset as usize..offset as usize + 16]);
+        };
+
+        read_at(100);
+        // 窗口中向前、向后都不再请求
+        read_at(200);
+        read_at(100);
+        // 窗口之前从需要的位置重新请求
+        read_at(50);
+        // 跨过窗口的末尾时也从读取的位置重新请求，到结尾时返回的比请求的短
+        read_at(RANGE_REQUEST_SIZE as u64 + 42);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                (100, RANGE_REQUEST_SIZE),
+                (50, RANGE_REQUEST_SIZE),
+                (RANGE_REQUEST_SIZE as u64 + 42, RANGE_REQUEST_SIZE),
+            ]
+        );
+
+        // 窗口中的内容直接借用
+        assert!(matches!(
+            file.peek(RANGE_REQUEST_SIZE as u64 + 100, 16).unwrap(),
+            Cow::Borrowed(_)
+        ));
+        let mut rest = Vec::new();
+        file.seek(SeekFrom::End(-8)).unwrap();
+        assert_eq!(file.read_to_end(&mut rest).unwrap(), 8);
+        assert_eq!(rest, bytes[bytes.len() - 8..]);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn ranged_source_is_parsed_like_a_seeking_reader() {
+        let mut binlog = BinlogBuilder::new();
+        for i in 0..100 {
+            binlog = binlog.event(&QueryEventBuilder::new(
+                "shop",
+                &format!("DELETE FROM t{}", i),
+            ));
+        }
+        let binlog = binlog.build();
+        let path = std::env::temp_dir().join(format!("reader-{}-ranged", std::process::id()));
+        std::fs::write(&path, &binlog).unwrap();
+
+        let source = LocalFileSource::open(path.to_str().unwrap()).unwrap();
+        let mut file = BinlogReader::from_source(Box::new(source));
+        assert_eq!(file.compression(), None);
+        assert!(!file.is_mapped());
+
+        let expected = events(&mut Cursor::new(&binlog));
+        assert_eq!(expected.len(), 101);
+        assert_eq!(events(&mut file), expected);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
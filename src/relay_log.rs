@@ -0,0 +1,291 @@
+use serde::Serialize;
+
+use crate::model::{EventBody, EventBodyTypeCode4, EventHeader};
+
+// 服务端生成、不在binlog文件中的事件，比如连接时发送的rotate
+const LOG_EVENT_ARTIFICIAL_F: u16 = 0x20;
+
+/// 事件在主库binlog中的位置，不知道主库的文件名时file为None
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MasterPosition {
+    pub file: Option<String>,
+    pub position: u64,
+}
+
+/// `mysql-bin.000001:806`
+impl std::fmt::Display for MasterPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.position),
+            None => write!(f, "{} in an unknown file", self.position),
+        }
+    }
+}
+
+/// `--relay-log`，跟踪relay log中每个事件在主库binlog中的位置
+///
+/// relay log的第一个事件是从库自己的FDE，之后是从主库收到的事件，header中的next_event_position是主库文件中的位置，
+/// 不是relay log中的位置。每次连接主库时从库会写入主库发送的rotate（没有位置）和主库的FDE，所以文件中间也会有FDE。
+/// 和第一个FDE的server_id相同的事件是从库自己写的，没有主库的位置
+#[derive(Default)]
+pub struct RelayLogTracker {
+    relay_server_id: Option<u32>,
+    master_file: Option<String>,
+    master_position: u64,
+}
+
+impl RelayLogTracker {
+    pub fn new() -> RelayLogTracker {
+        RelayLogTracker::default()
+    }
+
+    /// 返回事件在主库中的起始位置并更新当前的位置，从库自己的事件返回None
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+    ) -> Option<MasterPosition> {
+        if self.relay_server_id.is_none() && header.type_code == 15 {
+            self.relay_server_id = Some(header.server_id);
+        }
+
+        let mut position = self.position_of(header)?;
+
+        match body.downcast_ref::<EventBodyTypeCode4>() {
+            Some(rotate) => {
+                self.master_file = Some(rotate.file_name_of_next_binary_log.clone());
+                self.master_position = rotate.position_of_the_first_event_in_next_log_file;
+
+                // 主库发送的rotate说明的是之后的事件的位置
+                if header.flags & LOG_EVENT_ARTIFICIAL_F != 0 {
+                    position = MasterPosition {
+                        file: self.master_file.clone(),
+                        position: self.master_position,
+                    };
+                }
+            }
+            // heartbeat的next_event_position是主库已经发送到的位置
+            None if header.next_event_position != 0 => {
+                self.master_position = header.next_event_position as u64
+            }
+            None => {}
+        }
+
+        Some(position)
+    }
+
+    /// 不更新当前的位置，用于`--grep`补上的同一个事务中之前的事件
+    pub fn position_of(&self, header: &EventHeader) -> Option<MasterPosition> {
+        if self.relay_server_id == Some(header.server_id) {
+            return None;
+        }
+
+        // 没有位置的事件（主库连接时发送的rotate和FDE）记为当前的位置
+        let position = match header.next_event_position {
+            0 => self.master_position,
+            next_event_position => {
+                (next_event_position as u64).saturating_sub(header.event_length as u64)
+            }
+        };
+
+        Some(MasterPosition {
+            file: self.master_file.clone(),
+            position,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    const REPLICA: u32 = 2;
+    const MASTER: u32 = 1;
+
+    fn rotate(file_name: &str, position: u64) -> EventBuilder {
+        let mut body = position.to_le_bytes().to_vec();
+        body.extend(file_name.as_bytes());
+        EventBuilder::new(4, body)
+    }
+
+    fn fde(server_version: &str) -> EventBuilder {
+        EventBuilder::from_body(&FormatDescriptionBuilder::new(server_version))
+    }
+
+    // 主库连接时发送的事件没有位置，next_event_position为0
+    fn without_position(event: EventBuilder) -> Vec<u8> {
+        let mut event = event.build(0);
+        event[13..17].copy_from_slice(&0u32.to_le_bytes());
+        let length = event.len();
+        let checksum = crc32fast::hash(&event[..length - 4]);
+        event[length - 4..].copy_from_slice(&checksum.to_le_bytes());
+        event
+    }
+
+    /// 从库写的relay log：从库的FDE，每次连接主库时的rotate和主库的FDE，主库的事件按主库中的位置
+    struct RelayLogBuilder {
+        file: Vec<u8>,
+        master_position: u32,
+    }
+
+    impl RelayLogBuilder {
+        fn new() -> RelayLogBuilder {
+            RelayLogBuilder {
+                file: BinlogBuilder::empty()
+                    .push(fde("10.11.6-MariaDB-log").server_id(REPLICA))
+                    .build(),
+                master_position: 0,
+            }
+        }
+
+        fn connect(mut self, master_file: &str, master_position: u32) -> RelayLogBuilder {
+            let rotate = rotate(master_file, master_position as u64)
+                .server_id(MASTER)
+                .flags(LOG_EVENT_ARTIFICIAL_F);
+            self.file.extend(without_position(rotate));
+            self.file.extend(without_position(
+                fde("10.6.16-MariaDB-log").server_id(MASTER),
+            ));
+            self.master_position = master_position;
+            self
+        }
+
+        fn master_event(mut self, event: EventBuilder) -> RelayLogBuilder {
+            let event = event.server_id(MASTER).build(self.master_position);
+            self.master_position += event.len() as u32;
+            self.file.extend(event);
+            self
+        }
+
+        // 主库切换到下一个文件，之后的事件从新文件的4开始
+        fn master_rotate(self, master_file: &str) -> RelayLogBuilder {
+            let mut builder = self.master_event(rotate(master_file, 4));
+            builder.master_position = 4;
+            builder
+        }
+
+        fn replica_event(mut self, event: EventBuilder) -> RelayLogBuilder {
+            let event = event.server_id(REPLICA).build(self.file.len() as u32);
+            self.file.extend(event);
+            self
+        }
+    }
+
+    // 解析整个relay log，返回每个事件的type code和在主库中的位置
+    fn master_positions(relay_log: &[u8]) -> Vec<(u8, Option<String>)> {
+        let mut file = Cursor::new(relay_log);
+        let mut table_structs = HashMap::new();
+        let mut checksum_length = CHECKSUM_LENGTH;
+        let mut tracker = RelayLogTracker::new();
+        let mut positions = Vec::new();
+
+        let mut position = 4;
+        while position < relay_log.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                checksum_length,
+            )
+            .unwrap();
+            update_checksum_length(&mut checksum_length, body.as_ref());
+
+            let master_position = tracker.process(&header, body.as_ref());
+            positions.push((header.type_code, master_position.map(|v| v.to_string())));
+            position += header.event_length as u64;
+        }
+
+        positions
+    }
+
+    fn some(position: &str) -> Option<String> {
+        Some(position.to_string())
+    }
+
+    #[test]
+    fn reconnect_in_the_middle_of_the_file() {
+        let query = QueryEventBuilder::new("shop", "CREATE TABLE t (id int)");
+        let builder = RelayLogBuilder::new()
+            .connect("mysql-bin.000001", 256)
+            .master_event(gtid(6))
+            .master_event(EventBuilder::from_body(&query));
+        let reconnected_at = builder.master_position;
+        let relay_log = builder
+            .connect("mysql-bin.000001", reconnected_at)
+            .master_event(gtid(7))
+            .master_event(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .replica_event(rotate("relay-bin.000002", 4))
+            .file;
+
+        let gtid_length = gtid(6).build(0).len() as u32;
+        let xid_at = reconnected_at + gtid_length;
+        assert_eq!(
+            master_positions(&relay_log),
+            [
+                (15, None),
+                (4, some("mysql-bin.000001:256")),
+                (15, some("mysql-bin.000001:256")),
+                (162, some("mysql-bin.000001:256")),
+                (2, Some(format!("mysql-bin.000001:{}", 256 + gtid_length))),
+                // 文件中间的FDE
+                (4, Some(format!("mysql-bin.000001:{}", reconnected_at))),
+                (15, Some(format!("mysql-bin.000001:{}", reconnected_at))),
+                (162, Some(format!("mysql-bin.000001:{}", reconnected_at))),
+                (16, Some(format!("mysql-bin.000001:{}", xid_at))),
+                // 从库切换到下一个relay log
+                (4, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn master_rotates_to_the_next_file() {
+        let builder = RelayLogBuilder::new()
+            .connect("mysql-bin.000001", 4)
+            .master_event(gtid(6));
+        let rotate_at = builder.master_position;
+        let relay_log = builder
+            .master_rotate("mysql-bin.000002")
+            .master_event(fde("10.6.16-MariaDB-log"))
+            .master_event(gtid(7))
+            .file;
+
+        let positions = master_positions(&relay_log);
+        // 主库文件中的rotate在旧文件中，之后的事件在新文件中
+        assert_eq!(
+            positions[4],
+            (4, Some(format!("mysql-bin.000001:{}", rotate_at)))
+        );
+        assert_eq!(positions[5], (15, some("mysql-bin.000002:4")));
+        let fde_length = fde("10.6.16-MariaDB-log").build(0).len();
+        assert_eq!(
+            positions[6],
+            (162, Some(format!("mysql-bin.000002:{}", 4 + fde_length)))
+        );
+    }
+
+    #[test]
+    fn unknown_master_file() {
+        let tracker = RelayLogTracker::new();
+        let header = EventHeader {
+            timestamp: 0,
+            type_code: 2,
+            server_id: MASTER,
+            event_length: 100,
+            next_event_position: 1100,
+            flags: 0,
+        };
+
+        let position = tracker.position_of(&header).unwrap();
+        assert_eq!(position.to_string(), "1000 in an unknown file");
+        // position_of不更新当前的位置
+        assert_eq!(tracker.master_position, 0);
+    }
+}
@@ -0,0 +1,1761 @@
+use std::{
+    io::{self, BufReader, Cursor, Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::cli::{CliOptions, RemoteServer};
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorCategory, ErrorReport, ParseError};
+use crate::metrics::MetricsRegistry;
+use crate::model::*;
+use crate::pipeline::{EventPipeline, EventWriter};
+use crate::raw_archive::RawArchiver;
+use crate::service::*;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+// 一个包最多这么多字节，正好这么长时后面还有属于同一个payload的包
+const MAX_PACKET_LENGTH: usize = 0xffffff;
+
+const CLIENT_LONG_PASSWORD: u32 = 0x1;
+const CLIENT_PROTOCOL_41: u32 = 0x200;
+const CLIENT_TRANSACTIONS: u32 = 0x2000;
+const CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x80000;
+
+const COM_QUERY: u8 = 0x03;
+const COM_BINLOG_DUMP: u8 = 0x12;
+const COM_REGISTER_SLAVE: u8 = 0x15;
+
+// 服务端读取binlog出错，包括找不到请求的GTID
+const ER_MASTER_FATAL_ERROR_READING_BINLOG: u16 = 1236;
+
+// 到达最后一个binlog的结尾时发送EOF，而不是一直等待新的事件
+const BINLOG_DUMP_NON_BLOCK: u16 = 0x1;
+
+// utf8mb4_general_ci
+const CHARSET: u8 = 45;
+
+const NATIVE_PASSWORD: &str = "mysql_native_password";
+
+// 连接断开之后第一次重连前等待的时间，之后每次翻倍，最多等待MAX_RECONNECT_BACKOFF
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+// 半同步复制时事件包和ACK包开头的magic number，事件包中之后一个字节是flags
+const SEMI_SYNC_MAGIC: u8 = 0xef;
+const SEMI_SYNC_ACK_REQUIRED: u8 = 0x01;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 和从库一样连接服务端，通过COM_BINLOG_DUMP按顺序读取事件
+///
+/// 每个事件是一个包，包的第一个字节0x00之后是和文件中一样的事件，包括校验和；
+/// 0xfe表示已经没有更多的事件，0xff是服务端的错误。认证只支持mysql_native_password
+pub struct ReplicationClient {
+    stream: BufReader<TcpStream>,
+    sequence_id: u8,
+    semi_sync: SemiSync,
+    // 上一个事件的半同步头要求回复ACK
+    ack_requested: bool,
+    // 由调用方取走的提示
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// `--semi-sync`，服务端没有开启半同步复制时不会在事件前加半同步头，由第一个事件判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SemiSync {
+    Off,
+    Requested,
+    Active,
+}
+
+impl ReplicationClient {
+    /// 连接并完成认证
+    pub fn connect(server: &RemoteServer) -> Result<ReplicationClient, BoxedError> {
+        // 仍然是io::Error，重连时和连接断开一样重试
+        let stream = TcpStream::connect((server.host.as_str(), server.port)).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "failed to connect to `{}:{}`: {}",
+                    server.host, server.port, e
+                ),
+            )
+        })?;
+
+        let mut client = ReplicationClient {
+            stream: BufReader::new(stream),
+            sequence_id: 0,
+            semi_sync: match server.semi_sync {
+                true => SemiSync::Requested,
+                false => SemiSync::Off,
+            },
+            ack_requested: false,
+            diagnostics: Vec::new(),
+        };
+        client.handshake(&server.user, &server.password)?;
+
+        Ok(client)
+    }
+
+    /// 开始从file_name的position读取，server_id不能和其他从库相同
+    pub fn dump(
+        &mut self,
+        file_name: &str,
+        position: u64,
+        server_id: u32,
+    ) -> Result<(), BoxedError> {
+        self.register(server_id)?;
+        self.binlog_dump(file_name, position, server_id)
+    }
+
+    /// 从每个domain的GTID之后的事务开始读取，服务端自己找到对应的文件
+    ///
+    /// 和从库一样设置@slave_connect_state，strict mode下GTID不在服务端的binlog中时服务端会报错，而不是从别的位置开始
+    pub fn dump_gtid(&mut self, gtids: &[GTID], server_id: u32) -> Result<(), BoxedError> {
+        self.register(server_id)?;
+
+        self.query(&format!(
+            "SET @slave_connect_state = '{}'",
+            format_gtid_list(gtids)
+        ))?;
+        self.query("SET @slave_gtid_strict_mode = 1")?;
+        self.query("SET @slave_gtid_ignore_duplicates = 0")?;
+
+        // 文件名为空时使用@slave_connect_state
+        self.binlog_dump("", 4, server_id)
+    }
+
+    fn register(&mut self, server_id: u32) -> Result<(), BoxedError> {
+        // 事件带上校验和，和文件中一样解析
+        self.query("SET @master_binlog_checksum = @@global.binlog_checksum")?;
+        // MariaDB按原样发送GTID和annotate事件，不转换成给旧版本从库的事件
+        self.query("SET @mariadb_slave_capability = 4")?;
+        // 告诉开启了半同步复制的服务端这是半同步的从库，服务端等待它的ACK
+        if self.semi_sync != SemiSync::Off {
+            self.query("SET @rpl_semi_sync_slave = 1")?;
+        }
+
+        let mut payload = vec![COM_REGISTER_SLAVE];
+        payload.extend(server_id.to_le_bytes());
+        // 报告给服务端的hostname、user、password，都为空
+        payload.extend([0, 0, 0]);
+        payload.extend(0u16.to_le_bytes());
+        // replication rank和master id
+        payload.extend(0u32.to_le_bytes());
+        payload.extend(0u32.to_le_bytes());
+        self.command(&payload)?;
+        self.read_ok()
+    }
+
+    fn binlog_dump(
+        &mut self,
+        file_name: &str,
+        position: u64,
+        server_id: u32,
+    ) -> Result<(), BoxedError> {
+        let position = u32::try_from(position).map_err(|_| {
+            MyError(format!(
+                "position {} is too large for COM_BINLOG_DUMP",
+                position
+            ))
+        })?;
+
+        let mut payload = vec![COM_BINLOG_DUMP];
+        payload.extend(position.to_le_bytes());
+        payload.extend(BINLOG_DUMP_NON_BLOCK.to_le_bytes());
+        payload.extend(server_id.to_le_bytes());
+        payload.extend(file_name.as_bytes());
+        self.command(&payload)?;
+
+        Ok(())
+    }
+
+    /// 下一个事件的字节，服务端已经发送完所有的binlog时返回None
+    pub fn next_event(&mut self) -> Result<Option<Vec<u8>>, BoxedError> {
+        let mut packet = self.read_packet()?;
+
+        match packet.first() {
+            Some(0x00) => {
+                packet.remove(0);
+                self.strip_semi_sync_header(&mut packet)?;
+                Ok(Some(packet))
+            }
+            Some(0xfe) if packet.len() < 9 => Ok(None),
+            Some(0xff) => Err(Box::new(server_error(&packet))),
+            _ => Err(Box::new(ParseError::new(
+                ErrorCategory::Corruption,
+                format!(
+                    "unexpected packet of {} bytes from the server",
+                    packet.len()
+                ),
+            ))),
+        }
+    }
+
+    /// 到目前为止的提示，每次调用之后清空
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// 上一个事件要求ACK时，回复处理完这个事件之后的文件名和位置，服务端收到之后才让这个事务提交返回
+    pub fn acknowledge(&mut self, file_name: &str, position: u64) -> Result<(), BoxedError> {
+        if !self.ack_requested {
+            return Ok(());
+        }
+        self.ack_requested = false;
+
+        let mut payload = vec![SEMI_SYNC_MAGIC];
+        payload.extend(position.to_le_bytes());
+        payload.extend(file_name.as_bytes());
+        self.command(&payload)
+    }
+
+    /// 半同步复制时每个事件前面有magic number和flags两个字节，去掉之后和文件中的事件一样
+    ///
+    /// 没有半同步头时事件的第一个字节是timestamp的最低字节，也可能是0xef，所以只在第一个事件判断服务端是否开启了半同步，
+    /// 第一个事件是服务端生成的rotate，timestamp为0。之后的事件都按这个结果处理，半同步头不对时报错而不是当作事件解析
+    fn strip_semi_sync_header(&mut self, packet: &mut Vec<u8>) -> Result<(), BoxedError> {
+        if self.semi_sync == SemiSync::Requested {
+            self.semi_sync = match has_semi_sync_header(packet) {
+                true => SemiSync::Active,
+                false => {
+                    self.diagnostics.push(Diagnostic::Warning(
+                        "semi-synchronous replication is not enabled on the server, no ACK will be sent"
+                            .to_string(),
+                    ));
+                    SemiSync::Off
+                }
+            };
+        }
+
+        if self.semi_sync != SemiSync::Active {
+            return Ok(());
+        }
+
+        if !has_semi_sync_header(packet) {
+            return Err(Box::new(ParseError::new(
+                ErrorCategory::Corruption,
+                format!(
+                    "the event packet of {} bytes has no semi-sync header",
+                    packet.len()
+                ),
+            )));
+        }
+
+        self.ack_requested = packet[1] & SEMI_SYNC_ACK_REQUIRED != 0;
+        packet.drain(..2);
+
+        Ok(())
+    }
+
+    /// 执行不返回结果集的语句
+    pub fn query(&mut self, sql: &str) -> Result<(), BoxedError> {
+        let mut payload = vec![COM_QUERY];
+        payload.extend(sql.as_bytes());
+        self.command(&payload)?;
+        self.read_ok()
+    }
+
+    fn handshake(&mut self, user: &str, password: &str) -> Result<(), BoxedError> {
+        let packet = self.read_packet()?;
+        if packet.first() == Some(&0xff) {
+            return Err(Box::new(server_error(&packet)));
+        }
+
+        // 服务端默认的插件不是mysql_native_password时，回复中仍然按它计算，服务端会再发送auth switch
+        let scramble = parse_handshake(&packet)?;
+        let auth_response = native_password(password, &scramble);
+
+        let capabilities = CLIENT_LONG_PASSWORD
+            | CLIENT_PROTOCOL_41
+            | CLIENT_TRANSACTIONS
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH;
+
+        let mut payload = Vec::new();
+        payload.extend(capabilities.to_le_bytes());
+        payload.extend((MAX_PACKET_LENGTH as u32).to_le_bytes());
+        payload.push(CHARSET);
+        payload.extend([0u8; 23]);
+        payload.extend(user.as_bytes());
+        payload.push(0);
+        payload.push(auth_response.len() as u8);
+        payload.extend(&auth_response);
+        payload.extend(NATIVE_PASSWORD.as_bytes());
+        payload.push(0);
+        self.write_packet(&payload)?;
+
+        let packet = self.read_packet()?;
+        match packet.first() {
+            Some(0x00) => Ok(()),
+            Some(0xff) => Err(Box::new(server_error(&packet))),
+            // auth switch request，只能换成mysql_native_password
+            Some(0xfe) => {
+                let (plugin_name, scramble) = split_nul(&packet[1..]);
+                if plugin_name != NATIVE_PASSWORD.as_bytes() {
+                    return Err(Box::new(MyError(format!(
+                        "the server asks for the authentication plugin `{}`, only `{}` is supported",
+                        String::from_utf8_lossy(plugin_name),
+                        NATIVE_PASSWORD
+                    ))));
+                }
+
+                let scramble = scramble.strip_suffix(&[0]).unwrap_or(scramble);
+                self.write_packet(&native_password(password, scramble))?;
+                self.read_ok()
+            }
+            _ => Err(Box::new(MyError(
+                "unexpected reply to the handshake response".to_string(),
+            ))),
+        }
+    }
+
+    fn read_ok(&mut self) -> Result<(), BoxedError> {
+        let packet = self.read_packet()?;
+
+        match packet.first() {
+            Some(0x00) => Ok(()),
+            Some(0xff) => Err(Box::new(server_error(&packet))),
+            _ => Err(Box::new(MyError(
+                "expected an OK packet from the server".to_string(),
+            ))),
+        }
+    }
+
+    /// 每个命令的sequence id从0开始
+    fn command(&mut self, payload: &[u8]) -> Result<(), BoxedError> {
+        self.sequence_id = 0;
+        self.write_packet(payload)
+    }
+
+    /// 包头是3个字节的长度和1个字节的sequence id，超过MAX_PACKET_LENGTH的payload分成多个包
+    fn read_packet(&mut self) -> Result<Vec<u8>, BoxedError> {
+        let mut payload = Vec::new();
+
+        loop {
+            let mut header = [0u8; 4];
+            self.stream.read_exact(&mut header)?;
+
+            let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+            self.sequence_id = header[3].wrapping_add(1);
+
+            let start = payload.len();
+            payload.resize(start + length, 0);
+            self.stream.read_exact(&mut payload[start..])?;
+
+            if length < MAX_PACKET_LENGTH {
+                return Ok(payload);
+            }
+        }
+    }
+
+    fn write_packet(&mut self, payload: &[u8]) -> Result<(), BoxedError> {
+        let mut buffer = Vec::with_capacity(payload.len() + 4);
+
+        let mut offset = 0;
+        loop {
+            let chunk = &payload[offset..payload.len().min(offset + MAX_PACKET_LENGTH)];
+            buffer.extend(&(chunk.len() as u32).to_le_bytes()[..3]);
+            buffer.push(self.sequence_id);
+            buffer.extend(chunk);
+            self.sequence_id = self.sequence_id.wrapping_add(1);
+            offset += chunk.len();
+
+            // 长度正好是MAX_PACKET_LENGTH的倍数时最后还要有一个空包
+            if chunk.len() < MAX_PACKET_LENGTH {
+                break;
+            }
+        }
+
+        let stream = self.stream.get_mut();
+        stream.write_all(&buffer)?;
+        stream.flush()?;
+
+        Ok(())
+    }
+}
+
+/// magic number之后是一个完整的事件，事件的长度正好是剩下的字节数
+fn has_semi_sync_header(packet: &[u8]) -> bool {
+    let event = match packet {
+        [SEMI_SYNC_MAGIC, _, event @ ..] if event.len() >= EVENT_HEADER_LENGTH as usize => event,
+        _ => return false,
+    };
+
+    u32::from_le_bytes([event[9], event[10], event[11], event[12]]) as usize == event.len()
+}
+
+/// protocol version 10的握手包，返回认证用的20个字节的scramble
+fn parse_handshake(packet: &[u8]) -> Result<Vec<u8>, BoxedError> {
+    let invalid = || MyError("invalid handshake packet from the server".to_string());
+
+    if packet.first() != Some(&10) {
+        return Err(Box::new(MyError(format!(
+            "unsupported protocol version {:?}",
+            packet.first()
+        ))));
+    }
+
+    // 服务端版本之后是4个字节的connection id
+    let (_, rest) = split_nul(&packet[1..]);
+    let rest = rest.get(4..).ok_or_else(invalid)?;
+
+    let mut scramble = rest.get(..8).ok_or_else(invalid)?.to_vec();
+    // 1个字节的filler、2个字节的capability、1个字节的字符集、2个字节的状态、2个字节的capability
+    let rest = rest.get(16..).ok_or_else(invalid)?;
+    let auth_data_length = *rest.first().ok_or_else(invalid)? as usize;
+    // 保留的10个字节
+    let rest = rest.get(11..).ok_or_else(invalid)?;
+
+    let second_part_length = auth_data_length.saturating_sub(8).max(13);
+    let second_part = rest.get(..second_part_length).ok_or_else(invalid)?;
+    // 第二部分以0结尾
+    scramble.extend(second_part.strip_suffix(&[0]).unwrap_or(second_part));
+
+    Ok(scramble)
+}
+
+/// SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))，没有密码时为空
+fn native_password(password: &str, scramble: &[u8]) -> Vec<u8> {
+    if password.is_empty() {
+        return Vec::new();
+    }
+
+    let stage1 = Sha1::digest(password.as_bytes());
+    let stage2 = Sha1::digest(stage1);
+
+    let mut hasher = Sha1::new();
+    hasher.update(scramble);
+    hasher.update(stage2);
+    let stage3 = hasher.finalize();
+
+    stage1
+        .iter()
+        .zip(stage3.iter())
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+/// 按第一个0分成两部分，没有0时全部是第一部分
+fn split_nul(buffer: &[u8]) -> (&[u8], &[u8]) {
+    match buffer.iter().position(|v| *v == 0) {
+        Some(index) => (&buffer[..index], &buffer[index + 1..]),
+        None => (buffer, &[]),
+    }
+}
+
+/// 服务端返回的ERR包，保留错误码用来区分GTID已经被purge等情况
+#[derive(Debug)]
+pub struct ServerError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the server returned error {}: {}",
+            self.code, self.message
+        )
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// `0-1-100,1-2-5`
+fn format_gtid_list(gtids: &[GTID]) -> String {
+    gtids
+        .iter()
+        .map(|v| {
+            format!(
+                "{}-{}-{}",
+                v.replication_domain_id, v.server_id, v.gtid_sequence
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// ERR包：错误码、`#`和5个字节的SQL state，之后是错误信息
+fn server_error(packet: &[u8]) -> ServerError {
+    let code = match packet.get(1..3) {
+        Some(code) => u16::from_le_bytes([code[0], code[1]]),
+        None => 0,
+    };
+
+    let message = match packet.get(3) {
+        Some(b'#') => packet.get(9..).unwrap_or_default(),
+        _ => packet.get(3..).unwrap_or_default(),
+    };
+
+    ServerError {
+        code,
+        message: String::from_utf8_lossy(message).to_string(),
+    }
+}
+
+/// `--read-from-remote-server`，从服务端的file_name开始读取事件，和解析文件时一样输出
+///
+/// 服务端最开始发送的rotate事件没有对应的位置（next_event_position为0），只用来告诉客户端当前的文件名；
+/// 从文件中间开始时也会先发送这个文件的FDE，它的next_event_position同样为0。这些事件的位置记为当前的位置。
+///
+/// `--start-gtid`时文件名由服务端的rotate给出，文件开头的GTID list在stderr输出，用来确认没有跳过请求的GTID之后的事务
+///
+/// 连接断开时重连，从最后一个处理完的事件之后继续，见Reconnector。服务端重新发送的rotate、FDE和已经输出过的事件不再输出
+///
+/// 事件写到out中，写binlog的服务端、GTID list这些提示交给diagnostics
+pub fn dump_from_server(
+    server: &RemoteServer,
+    file_name: &str,
+    options: &CliOptions,
+    out: &mut dyn Write,
+    error_report: &mut ErrorReport,
+    metrics: Option<&MetricsRegistry>,
+    diagnostics: &mut dyn FnMut(Diagnostic),
+) -> Result<(), BoxedError> {
+    let mut reconnector = Reconnector::new(server, metrics);
+
+    if let Some(result_dir) = &server.raw_result_dir {
+        return archive_from_server(server, file_name, result_dir, &mut reconnector, diagnostics);
+    }
+
+    let mut client = match &server.start_gtid {
+        Some(gtids) => start_dump(server, &ResumeFrom::Gtid(gtids.clone()))?,
+        None => start_dump(
+            server,
+            &ResumeFrom::Position(file_name.to_string(), server.start_position),
+        )?,
+    };
+
+    let mut pipeline = EventPipeline::from_options(options);
+    let mut event_writer = EventWriter::from_options(options);
+
+    let mut file_name = file_name.to_string();
+    let mut position = server.start_position;
+    let mut gtid_list_checked = false;
+
+    let mut gtid_state = server.start_gtid.clone().map(GtidState::new);
+    // 重连之后，这个文件中这个位置之前的事件都已经输出过
+    let mut resumed_at: Option<(String, u64)> = None;
+    let mut first_event = true;
+
+    loop {
+        let event = client.next_event();
+        client
+            .take_diagnostics()
+            .into_iter()
+            .for_each(&mut *diagnostics);
+        let event = match event {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            // 连接断开以外的错误
+            Err(e) if e.downcast_ref::<io::Error>().is_none() => match &server.start_gtid {
+                Some(gtids) if !gtid_list_checked => return Err(gtid_not_found(e, gtids)),
+                _ => return Err(e),
+            },
+            Err(e) => {
+                let processed = resumed_at
+                    .take()
+                    .unwrap_or_else(|| (file_name.clone(), position));
+                let resume = match &mut gtid_state {
+                    Some(gtid_state) => ResumeFrom::Gtid(gtid_state.resume_gtids(&processed.0)),
+                    None => ResumeFrom::Position(processed.0.clone(), processed.1),
+                };
+
+                client = reconnector.reconnect(e, &resume, diagnostics)?;
+                resumed_at = Some(processed);
+                continue;
+            }
+        };
+
+        let mut cursor = Cursor::new(&event);
+
+        let header = get_event_header(&mut cursor, 0)?;
+        let event_position = (header.next_event_position as u64)
+            .checked_sub(header.event_length as u64)
+            .filter(|_| header.next_event_position != 0)
+            .unwrap_or(position);
+
+        let in_file = |error: ParseError| {
+            let mut error = error.at(event_position, Some(header.type_code));
+            error.message = format!("{} in `{}`", error.message, file_name);
+            error
+        };
+
+        if header.event_length as usize != event.len() {
+            return Err(Box::new(in_file(ParseError::new(
+                ErrorCategory::Corruption,
+                format!(
+                    "the event length is {} but the server sent {} bytes",
+                    header.event_length,
+                    event.len()
+                ),
+            ))));
+        }
+
+        // 第一个FDE之前只有服务端生成的rotate，按它的最后4个字节判断有没有校验和
+        if first_event {
+            pipeline.checksum_length = detect_checksum_length(&event);
+            first_event = false;
+        }
+        let body = pipeline.decode(&mut cursor, 0, &header).map_err(in_file)?;
+        pipeline.update(body.as_ref(), diagnostics);
+
+        if let Some((resumed_file, resumed_position)) = &resumed_at {
+            // 服务端生成的rotate、FDE和处理过的位置之前的事件
+            let duplicate = header.next_event_position == 0
+                || (file_name == *resumed_file
+                    && header.next_event_position as u64 <= *resumed_position);
+
+            if duplicate {
+                if let Some(rotate) = body.downcast_ref::<EventBodyTypeCode4>() {
+                    file_name = rotate.file_name_of_next_binary_log.clone();
+                    position = rotate.position_of_the_first_event_in_next_log_file;
+                } else if header.next_event_position != 0 {
+                    position = header.next_event_position as u64;
+                }
+
+                client.acknowledge(&file_name, position)?;
+                continue;
+            }
+
+            resumed_at = None;
+        }
+        reconnector.reset();
+
+        if let Some(metrics) = metrics {
+            metrics.record_event(
+                &header,
+                &file_name,
+                header.next_event_position as u64,
+                body.as_ref(),
+                &pipeline.table_structs,
+            );
+        }
+
+        if let Some(issue) = pipeline.check(&header, event_position, body.as_ref(), diagnostics)? {
+            if let Some(metrics) = metrics {
+                metrics.record_error(&issue);
+            }
+            error_report.push(issue);
+        }
+
+        event_writer.write(
+            out,
+            &header,
+            event_position,
+            body.as_ref(),
+            &pipeline.table_structs,
+        )?;
+
+        if let (Some(gtids), Some(gtid_list), false) = (
+            &server.start_gtid,
+            body.downcast_ref::<EventBodyTypeCode163>(),
+            gtid_list_checked,
+        ) {
+            check_gtid_list(gtids, &gtid_list.gtids, &file_name, diagnostics)?;
+            gtid_list_checked = true;
+        }
+
+        if let (Some(gtid_state), Some(gtid)) =
+            (&mut gtid_state, body.downcast_ref::<EventBodyTypeCode162>())
+        {
+            gtid_state.begin(
+                GTID {
+                    replication_domain_id: gtid.replication_domain_id,
+                    server_id: header.server_id,
+                    gtid_sequence: gtid.gtid_sequence,
+                },
+                &file_name,
+            );
+        }
+
+        // 之后的事件属于rotate中的文件，包括服务端最开始发送的rotate
+        if let Some(rotate) = body.downcast_ref::<EventBodyTypeCode4>() {
+            file_name = rotate.file_name_of_next_binary_log.clone();
+            position = rotate.position_of_the_first_event_in_next_log_file;
+        } else if header.next_event_position != 0 {
+            position = header.next_event_position as u64;
+        }
+
+        client.acknowledge(&file_name, position)?;
+    }
+
+    reconnector.report(diagnostics);
+
+    Ok(())
+}
+
+/// 开始dump的位置，重连时是最后一个处理完的事件之后
+enum ResumeFrom {
+    Gtid(Vec<GTID>),
+    Position(String, u64),
+}
+
+fn start_dump(server: &RemoteServer, resume: &ResumeFrom) -> Result<ReplicationClient, BoxedError> {
+    let mut client = ReplicationClient::connect(server)?;
+
+    match resume {
+        ResumeFrom::Gtid(gtids) => client.dump_gtid(gtids, server.server_id)?,
+        ResumeFrom::Position(file_name, position) => {
+            client.dump(file_name, *position, server.server_id)?
+        }
+    }
+
+    Ok(client)
+}
+
+/// 连接断开（网络中断、服务端重启）之后按指数退避重连，重新认证并从断开的地方继续dump
+///
+/// 连续重连超过`--max-reconnects`次还没有收到新的事件时放弃。服务端返回的错误和解析错误不重连，原样返回
+struct Reconnector<'a> {
+    server: &'a RemoteServer,
+    metrics: Option<&'a MetricsRegistry>,
+    // 上一次收到新的事件之后重连的次数
+    attempts: u32,
+    reconnects: u64,
+}
+
+impl<'a> Reconnector<'a> {
+    fn new(server: &'a RemoteServer, metrics: Option<&'a MetricsRegistry>) -> Reconnector<'a> {
+        Reconnector {
+            server,
+            metrics,
+            attempts: 0,
+            reconnects: 0,
+        }
+    }
+
+    fn reconnect(
+        &mut self,
+        error: BoxedError,
+        resume: &ResumeFrom,
+        diagnostics: &mut dyn FnMut(Diagnostic),
+    ) -> Result<ReplicationClient, BoxedError> {
+        let mut error = error;
+
+        loop {
+            if error.downcast_ref::<io::Error>().is_none() {
+                return Err(error);
+            }
+
+            if self.server.max_reconnects == 0 {
+                return Err(error);
+            }
+
+            if self.attempts >= self.server.max_reconnects {
+                return Err(Box::new(MyError(format!(
+                    "giving up after {} reconnects: {}",
+                    self.attempts, error
+                ))));
+            }
+            self.attempts += 1;
+
+            let backoff = RECONNECT_BACKOFF
+                .saturating_mul(1 << (self.attempts - 1).min(16))
+                .min(MAX_RECONNECT_BACKOFF);
+            diagnostics(Diagnostic::Note(format!(
+                "connection lost: {}, reconnecting in {}s ({}/{})",
+                error,
+                backoff.as_secs(),
+                self.attempts,
+                self.server.max_reconnects
+            )));
+            thread::sleep(backoff);
+
+            match start_dump(self.server, resume) {
+                Ok(client) => {
+                    self.reconnects += 1;
+                    if let Some(metrics) = self.metrics {
+                        metrics.record_reconnect();
+                    }
+
+                    let message = match resume {
+                        ResumeFrom::Gtid(gtids) => {
+                            format!(
+                                "reconnected, resuming from GTID `{}`",
+                                format_gtid_list(gtids)
+                            )
+                        }
+                        ResumeFrom::Position(file_name, position) => {
+                            format!("reconnected, resuming from `{}` at {}", file_name, position)
+                        }
+                    };
+                    diagnostics(Diagnostic::Note(message));
+
+                    return Ok(client);
+                }
+                Err(e) => error = e,
+            }
+        }
+    }
+
+    /// 收到了新的事件，之后断开时重新计数
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    fn report(&self, diagnostics: &mut dyn FnMut(Diagnostic)) {
+        if self.reconnects > 0 {
+            diagnostics(Diagnostic::Note(format!(
+                "reconnected {} times",
+                self.reconnects
+            )));
+        }
+    }
+}
+
+/// `--start-gtid`时重连使用的GTID：每个domain最后一个完整的事务
+///
+/// 最后一个事务可能只输出了一部分，重连时从它开始，服务端重新发送的部分按位置去掉；
+/// 之后已经rotate到下一个文件时它一定已经完整输出，不再重新请求
+struct GtidState {
+    gtids: Vec<GTID>,
+    // 最后一个事务的GTID和它所在的文件
+    current: Option<(GTID, String)>,
+}
+
+impl GtidState {
+    fn new(gtids: Vec<GTID>) -> GtidState {
+        GtidState {
+            gtids,
+            current: None,
+        }
+    }
+
+    fn begin(&mut self, gtid: GTID, file_name: &str) {
+        if let Some((previous, _)) = self.current.take() {
+            self.complete(previous);
+        }
+
+        self.current = Some((gtid, file_name.to_string()));
+    }
+
+    fn complete(&mut self, gtid: GTID) {
+        match self
+            .gtids
+            .iter_mut()
+            .find(|v| v.replication_domain_id == gtid.replication_domain_id)
+        {
+            Some(v) => *v = gtid,
+            None => self.gtids.push(gtid),
+        }
+    }
+
+    fn resume_gtids(&mut self, file_name: &str) -> Vec<GTID> {
+        if self.current.as_ref().is_some_and(|(_, v)| v != file_name) {
+            if let Some((gtid, _)) = self.current.take() {
+                self.complete(gtid);
+            }
+        }
+
+        self.gtids.clone()
+    }
+}
+
+/// `--raw --result-dir DIR`，不解析事件，原样保存到DIR中，直到服务端发送完所有的binlog
+///
+/// 没有`--start-position`时，DIR中已经有这个文件就从其中最后一个完整的事件之后继续，所以中断之后用同样的参数重新运行即可
+fn archive_from_server(
+    server: &RemoteServer,
+    file_name: &str,
+    result_dir: &str,
+    reconnector: &mut Reconnector,
+    diagnostics: &mut dyn FnMut(Diagnostic),
+) -> Result<(), BoxedError> {
+    let mut archiver = RawArchiver::new(result_dir)?;
+
+    let position = match server.start_position {
+        4 => archiver.resume_position(file_name)?,
+        position => position,
+    };
+    if position > 4 {
+        diagnostics(Diagnostic::Note(format!(
+            "resuming `{}` from position {}",
+            file_name, position
+        )));
+    }
+
+    let mut client = start_dump(
+        server,
+        &ResumeFrom::Position(file_name.to_string(), position),
+    )?;
+
+    // 重连时从已经写入的位置继续，服务端重新发送的rotate和FDE不写入文件
+    let result = (|| loop {
+        let event = client.next_event();
+        client
+            .take_diagnostics()
+            .into_iter()
+            .for_each(&mut *diagnostics);
+        let event = match event {
+            Ok(Some(event)) => event,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                archiver.finish()?;
+                archiver
+                    .take_diagnostics()
+                    .into_iter()
+                    .for_each(&mut *diagnostics);
+                let resume = match archiver.position() {
+                    Some((file_name, position)) => {
+                        ResumeFrom::Position(file_name.to_string(), position)
+                    }
+                    None => ResumeFrom::Position(file_name.to_string(), position),
+                };
+                client = reconnector.reconnect(e, &resume, diagnostics)?;
+                continue;
+            }
+        };
+
+        if archiver.archive(&event)? {
+            reconnector.reset();
+        }
+        archiver
+            .take_diagnostics()
+            .into_iter()
+            .for_each(&mut *diagnostics);
+
+        if let Some((file_name, position)) = archiver.position() {
+            client.acknowledge(file_name, position)?;
+        }
+    })();
+
+    // 出错时也把已经收到的事件落盘，下次从这里继续
+    let finished = archiver.finish();
+    archiver
+        .take_diagnostics()
+        .into_iter()
+        .for_each(&mut *diagnostics);
+    finished?;
+    reconnector.report(diagnostics);
+
+    result
+}
+
+/// 服务端找不到请求的GTID时返回ER_MASTER_FATAL_ERROR_READING_BINLOG，一般是包含它的binlog已经被purge
+fn gtid_not_found(error: BoxedError, gtids: &[GTID]) -> BoxedError {
+    match error.downcast_ref::<ServerError>() {
+        Some(server_error) if server_error.code == ER_MASTER_FATAL_ERROR_READING_BINLOG => {
+            Box::new(MyError(format!(
+                "the server cannot start from GTID `{}`, the binlogs containing it may have been purged: {}",
+                format_gtid_list(gtids),
+                server_error.message
+            )))
+        }
+        _ => error,
+    }
+}
+
+/// 文件开头的GTID list是这个文件之前每个domain最后的GTID，strict mode下不能超过请求的GTID，否则中间的事务被跳过了
+fn check_gtid_list(
+    gtids: &[GTID],
+    gtid_list: &[GTID],
+    file_name: &str,
+    diagnostics: &mut dyn FnMut(Diagnostic),
+) -> Result<(), BoxedError> {
+    diagnostics(Diagnostic::Note(format!(
+        "the server starts from `{}` with GTID list `{}`",
+        file_name,
+        format_gtid_list(gtid_list)
+    )));
+
+    for start in gtids {
+        let skipped = gtid_list.iter().find(|v| {
+            v.replication_domain_id == start.replication_domain_id
+                && v.gtid_sequence > start.gtid_sequence
+        });
+
+        if let Some(skipped) = skipped {
+            return Err(Box::new(MyError(format!(
+                "the server starts after GTID `{}` in `{}`, which is beyond the requested `{}`",
+                format_gtid_list(std::slice::from_ref(skipped)),
+                file_name,
+                format_gtid_list(std::slice::from_ref(start))
+            ))));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    const PASSWORD: &str = "secret";
+    const SCRAMBLE: &[u8; 20] = b"abcdefgh123456789012";
+
+    /// COM_BINLOG_DUMP之后服务端发送的内容
+    enum Reply {
+        Event(Vec<u8>),
+        // 半同步复制的事件，前面有magic number和flags
+        SemiSyncEvent(u8, Vec<u8>),
+        Eof,
+        Error(u16, &'static str),
+        // 发送完之前的内容之后断开连接
+        Disconnect,
+    }
+
+    /// 每个连接按顺序使用一个脚本，返回每个连接收到的命令
+    fn mock_server(scripts: Vec<Vec<Reply>>) -> (u16, JoinHandle<Vec<Vec<Vec<u8>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            scripts
+                .into_iter()
+                .map(|script| {
+                    let (stream, _) = listener.accept().unwrap();
+                    serve(stream, script)
+                })
+                .collect()
+        });
+
+        (port, handle)
+    }
+
+    fn serve(mut stream: TcpStream, script: Vec<Reply>) -> Vec<Vec<u8>> {
+        let mut commands = Vec::new();
+
+        write_packet(&mut stream, 0, &handshake_packet());
+        let response = match read_packet(&mut stream) {
+            Some(response) => response,
+            None => return commands,
+        };
+        // 用户名之后是认证数据的长度和认证数据
+        let (_, rest) = split_nul(&response[32..]);
+        let auth_response = &rest[1..1 + rest[0] as usize];
+        if auth_response != native_password(PASSWORD, SCRAMBLE) {
+            write_packet(&mut stream, 2, &error_packet(1045, "Access denied"));
+            return commands;
+        }
+        write_packet(&mut stream, 2, &[0, 0, 0, 2, 0, 0, 0]);
+
+        let mut script = script.into_iter();
+        while let Some(command) = read_packet(&mut stream) {
+            commands.push(command.clone());
+            match command[0] {
+                COM_BINLOG_DUMP => {
+                    for (sequence_id, reply) in (1..).zip(script.by_ref()) {
+                        let packet = match reply {
+                            Reply::Event(event) => [&[0][..], &event].concat(),
+                            Reply::SemiSyncEvent(flags, event) => {
+                                [&[0, SEMI_SYNC_MAGIC, flags][..], &event].concat()
+                            }
+                            Reply::Eof => vec![0xfe, 0, 0, 2, 0],
+                            Reply::Error(code, message) => error_packet(code, message),
+                            Reply::Disconnect => return commands,
+                        };
+                        write_packet(&mut stream, sequence_id, &packet);
+                    }
+                }
+                SEMI_SYNC_MAGIC => {}
+                _ => write_packet(&mut stream, 1, &[0, 0, 0, 2, 0, 0, 0]),
+            }
+        }
+
+        commands
+    }
+
+    fn handshake_packet() -> Vec<u8> {
+        let mut packet = vec![10];
+        packet.extend(b"5.5.5-10.11.6-MariaDB-log\0");
+        packet.extend(1u32.to_le_bytes());
+        packet.extend(&SCRAMBLE[..8]);
+        packet.push(0);
+        packet.extend(0xf7feu16.to_le_bytes());
+        packet.push(CHARSET);
+        packet.extend(2u16.to_le_bytes());
+        packet.extend(0x81bfu16.to_le_bytes());
+        packet.push(21);
+        packet.extend([0; 10]);
+        packet.extend(&SCRAMBLE[8..]);
+        packet.push(0);
+        packet.extend(NATIVE_PASSWORD.as_bytes());
+        packet.push(0);
+        packet
+    }
+
+    fn error_packet(code: u16, message: &str) -> Vec<u8> {
+        let mut packet = vec![0xff];
+        packet.extend(code.to_le_bytes());
+        packet.extend(b"#HY000");
+        packet.extend(message.as_bytes());
+        packet
+    }
+
+    fn read_packet(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).ok()?;
+        let mut payload =
+            vec![0; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+        stream.read_exact(&mut payload).ok()?;
+        Some(payload)
+    }
+
+    fn write_packet(stream: &mut TcpStream, sequence_id: u8, payload: &[u8]) {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(sequence_id);
+        packet.extend(payload);
+        // 客户端可能已经因为错误断开
+        let _ = stream.write_all(&packet);
+    }
+
+    /// 服务端最开始发送的rotate，next_event_position为0
+    fn fake_rotate(file_name: &str, position: u64) -> Vec<u8> {
+        let mut body = position.to_le_bytes().to_vec();
+        body.extend(file_name.as_bytes());
+        without_position(EventBuilder::new(4, body).flags(0x20).build(0))
+    }
+
+    // 从文件中间开始时重新发送的FDE也没有位置
+    fn without_position(mut event: Vec<u8>) -> Vec<u8> {
+        event[13..17].copy_from_slice(&0u32.to_le_bytes());
+        let length = event.len();
+        let checksum = crc32fast::hash(&event[..length - 4]);
+        event[length - 4..].copy_from_slice(&checksum.to_le_bytes());
+        event
+    }
+
+    /// 文件中magic number之后的每个事件和它的位置
+    fn split_events(binlog: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        let mut events = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() {
+            let length =
+                u32::from_le_bytes(binlog[position + 9..position + 13].try_into().unwrap());
+            events.push((
+                position as u64,
+                binlog[position..position + length as usize].to_vec(),
+            ));
+            position += length as usize;
+        }
+        events
+    }
+
+    /// 服务端从file_name的position开始发送的事件：rotate，从中间开始时还有FDE
+    fn dump_file(file_name: &str, binlog: &[u8], position: u64) -> Vec<Reply> {
+        let events = split_events(binlog);
+        let mut replies = vec![Reply::Event(fake_rotate(file_name, position))];
+        if position > 4 {
+            replies.push(Reply::Event(without_position(events[0].1.clone())));
+        }
+        replies.extend(
+            events
+                .into_iter()
+                .filter(|(v, _)| *v >= position)
+                .map(|(_, event)| Reply::Event(event)),
+        );
+        replies
+    }
+
+    // COM_BINLOG_DUMP中的位置和文件名
+    fn binlog_dump_of(commands: &[Vec<u8>]) -> (u32, String) {
+        let command = commands.iter().find(|v| v[0] == COM_BINLOG_DUMP).unwrap();
+        (
+            u32::from_le_bytes(command[1..5].try_into().unwrap()),
+            String::from_utf8(command[11..].to_vec()).unwrap(),
+        )
+    }
+
+    fn gtid_list(gtids: &[(u32, u32, u64)]) -> EventBuilder {
+        let mut body = (gtids.len() as u32).to_le_bytes().to_vec();
+        for (domain, server_id, sequence) in gtids {
+            body.extend(domain.to_le_bytes());
+            body.extend(server_id.to_le_bytes());
+            body.extend(sequence.to_le_bytes());
+        }
+        EventBuilder::new(163, body)
+    }
+
+    // rotate、FDE、GTID list、一个DDL事务，最后是EOF
+    fn gtid_dump(gtid_list_sequence: u64) -> Vec<Reply> {
+        let binlog = BinlogBuilder::new()
+            .push(gtid_list(&[(0, 1, gtid_list_sequence)]))
+            .push(gtid(6))
+            .event(&QueryEventBuilder::new("shop", "CREATE TABLE t (id int)"))
+            .build();
+
+        let mut replies = dump_file("mysql-bin.000002", &binlog, 4);
+        replies.push(Reply::Eof);
+        replies
+    }
+
+    fn options(port: u16, extra: &[&str]) -> CliOptions {
+        let port = port.to_string();
+        let mut args = vec![
+            "--read-from-remote-server",
+            "--host",
+            "127.0.0.1",
+            "--port",
+            &port,
+            "--user",
+            "repl",
+            "--password",
+            PASSWORD,
+            "--output",
+            "json",
+        ];
+        args.extend(extra);
+        crate::cli::parse_args(&args.iter().map(|v| v.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    // 返回输出的每个事件的type code和提示
+    fn dump(options: &CliOptions, file_name: &str) -> Result<(Vec<u64>, Vec<String>), BoxedError> {
+        dump_with_metrics(options, file_name, None)
+    }
+
+    fn dump_with_metrics(
+        options: &CliOptions,
+        file_name: &str,
+        metrics: Option<&MetricsRegistry>,
+    ) -> Result<(Vec<u64>, Vec<String>), BoxedError> {
+        let mut out = Vec::new();
+        let mut diagnostics = Vec::new();
+        dump_from_server(
+            options.remote_server.as_ref().unwrap(),
+            file_name,
+            options,
+            &mut out,
+            &mut ErrorReport::new(),
+            metrics,
+            &mut |v| diagnostics.push(v.to_string()),
+        )?;
+
+        let type_codes = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|v| {
+                serde_json::from_str::<serde_json::Value>(v).unwrap()["header"]["type_code"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        Ok((type_codes, diagnostics))
+    }
+
+    fn query(command: &[u8]) -> &str {
+        assert_eq!(command[0], COM_QUERY);
+        std::str::from_utf8(&command[1..]).unwrap()
+    }
+
+    #[test]
+    fn dump_from_gtid() {
+        let (port, server) = mock_server(vec![gtid_dump(5)]);
+        let options = options(port, &["--start-gtid", "0-1-5"]);
+
+        let (type_codes, diagnostics) = dump(&options, "").unwrap();
+        assert_eq!(type_codes, [4, 15, 163, 162, 2]);
+        assert!(diagnostics.contains(
+            &"the server starts from `mysql-bin.000002` with GTID list `0-1-5`".to_string()
+        ));
+
+        let commands = server.join().unwrap().remove(0);
+        let queries = [&commands[..2], &commands[3..6]]
+            .concat()
+            .iter()
+            .map(|v| query(v).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            queries,
+            [
+                "SET @master_binlog_checksum = @@global.binlog_checksum",
+                "SET @mariadb_slave_capability = 4",
+                "SET @slave_connect_state = '0-1-5'",
+                "SET @slave_gtid_strict_mode = 1",
+                "SET @slave_gtid_ignore_duplicates = 0",
+            ]
+        );
+        assert_eq!(commands[2][0], COM_REGISTER_SLAVE);
+        // 文件名为空时服务端使用@slave_connect_state
+        let binlog_dump = &commands[6];
+        assert_eq!(binlog_dump[0], COM_BINLOG_DUMP);
+        assert_eq!(binlog_dump.len(), 11);
+    }
+
+    #[test]
+    fn purged_gtid_is_reported() {
+        let (port, server) = mock_server(vec![vec![Reply::Error(
+            ER_MASTER_FATAL_ERROR_READING_BINLOG,
+            "Could not find GTID state requested by slave in any binlog files",
+        )]]);
+        let options = options(port, &["--start-gtid", "0-1-5"]);
+
+        let error = dump(&options, "").unwrap_err().to_string();
+        assert!(error.contains("the server cannot start from GTID `0-1-5`"));
+        assert!(error.contains("may have been purged"));
+        assert!(error.ends_with("Could not find GTID state requested by slave in any binlog files"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn other_server_errors_are_returned_as_is() {
+        let (port, server) = mock_server(vec![vec![Reply::Error(1045, "Access denied")]]);
+        let options = options(port, &["--start-gtid", "0-1-5"]);
+
+        let error = dump(&options, "").unwrap_err();
+        assert_eq!(error.downcast_ref::<ServerError>().unwrap().code, 1045);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn gtid_list_beyond_the_requested_gtid() {
+        let (port, server) = mock_server(vec![gtid_dump(9)]);
+        let options = options(port, &["--start-gtid", "0-1-5"]);
+
+        let error = dump(&options, "").unwrap_err().to_string();
+        assert!(error.ends_with(
+            "the server starts after GTID `0-1-9` in `mysql-bin.000002`, which is beyond the requested `0-1-5`"
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn wrong_password() {
+        let (port, server) = mock_server(vec![vec![]]);
+        let mut options = options(port, &["--start-gtid", "0-1-5"]);
+        options.remote_server.as_mut().unwrap().password = "wrong".to_string();
+
+        let error = dump(&options, "").unwrap_err();
+        assert_eq!(error.downcast_ref::<ServerError>().unwrap().code, 1045);
+        server.join().unwrap();
+    }
+
+    fn rotate(file_name: &str, position: u64) -> EventBuilder {
+        let mut body = position.to_le_bytes().to_vec();
+        body.extend(file_name.as_bytes());
+        EventBuilder::new(4, body)
+    }
+
+    // 以rotate结束的mysql-bin.000001和之后的mysql-bin.000002
+    fn master_binlogs(sql: &str) -> (Vec<u8>, Vec<u8>) {
+        let first = BinlogBuilder::new()
+            .push(gtid(1))
+            .event(&QueryEventBuilder::new("shop", sql))
+            .push(rotate("mysql-bin.000002", 4))
+            .build();
+        let second = BinlogBuilder::new()
+            .push(gtid(2))
+            .event(&QueryEventBuilder::new("shop", "DROP TABLE t"))
+            .build();
+        (first, second)
+    }
+
+    fn result_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("remote-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn archive_the_server_binlogs() {
+        let (first, second) = master_binlogs("CREATE TABLE t (id int)");
+        let mut script = dump_file("mysql-bin.000001", &first, 4);
+        // 文件最后的rotate之后是下一个文件的FDE
+        script.extend(
+            split_events(&second)
+                .into_iter()
+                .map(|(_, v)| Reply::Event(v)),
+        );
+        script.push(Reply::Eof);
+        let (port, server) = mock_server(vec![script]);
+
+        let dir = result_dir("archive");
+        let options = options(
+            port,
+            &[
+                "--raw",
+                "--result-dir",
+                dir.to_str().unwrap(),
+                "mysql-bin.000001",
+            ],
+        );
+        let (type_codes, diagnostics) = dump(&options, "mysql-bin.000001").unwrap();
+        assert!(type_codes.is_empty());
+
+        assert_eq!(std::fs::read(dir.join("mysql-bin.000001")).unwrap(), first);
+        assert_eq!(std::fs::read(dir.join("mysql-bin.000002")).unwrap(), second);
+        assert_eq!(
+            diagnostics,
+            [
+                format!("archived `mysql-bin.000001` up to position {}", first.len()),
+                format!(
+                    "archived `mysql-bin.000002` up to position {}",
+                    second.len()
+                ),
+            ]
+        );
+        let commands = server.join().unwrap().remove(0);
+        assert_eq!(
+            binlog_dump_of(&commands),
+            (4, "mysql-bin.000001".to_string())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archive_resumes_after_the_last_complete_event() {
+        let (first, _) = master_binlogs("CREATE TABLE t (id int)");
+        let events = split_events(&first);
+        // 上次中断时query只写了一部分
+        let resume_at = events[2].0;
+        let dir = result_dir("resume");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("mysql-bin.000001"),
+            &first[..resume_at as usize + 10],
+        )
+        .unwrap();
+
+        let mut script = dump_file("mysql-bin.000001", &first, resume_at);
+        script.push(Reply::Eof);
+        let (port, server) = mock_server(vec![script]);
+
+        let options = options(
+            port,
+            &[
+                "--raw",
+                "--result-dir",
+                dir.to_str().unwrap(),
+                "mysql-bin.000001",
+            ],
+        );
+        let (_, diagnostics) = dump(&options, "mysql-bin.000001").unwrap();
+
+        assert_eq!(std::fs::read(dir.join("mysql-bin.000001")).unwrap(), first);
+        assert_eq!(
+            diagnostics[0],
+            format!("resuming `mysql-bin.000001` from position {}", resume_at)
+        );
+        assert!(diagnostics[1].starts_with("truncating the incomplete event"));
+        let commands = server.join().unwrap().remove(0);
+        assert_eq!(
+            binlog_dump_of(&commands),
+            (resume_at as u32, "mysql-bin.000001".to_string())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diverging_file_is_not_overwritten() {
+        let (local, _) = master_binlogs("CREATE TABLE t (id int)");
+        let (first, _) = master_binlogs("CREATE TABLE u (id int)");
+        let query_at = split_events(&first)[2].0;
+        let dir = result_dir("diverge");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("mysql-bin.000001"), &local).unwrap();
+
+        let mut script = dump_file("mysql-bin.000001", &first, query_at);
+        script.push(Reply::Eof);
+        let (port, server) = mock_server(vec![script]);
+
+        let start_position = query_at.to_string();
+        let options = options(
+            port,
+            &[
+                "--raw",
+                "--result-dir",
+                dir.to_str().unwrap(),
+                "--start-position",
+                &start_position,
+                "mysql-bin.000001",
+            ],
+        );
+        let error = dump(&options, "mysql-bin.000001").unwrap_err().to_string();
+
+        assert!(error.contains(&format!(
+            "diverges from the server at position {}, refusing to overwrite it",
+            query_at
+        )));
+        assert_eq!(std::fs::read(dir.join("mysql-bin.000001")).unwrap(), local);
+        server.join().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archive_reconnects_from_the_last_complete_event() {
+        let (first, _) = master_binlogs("CREATE TABLE t (id int)");
+        let resume_at = split_events(&first)[2].0;
+
+        // rotate、FDE、GTID之后断开
+        let mut interrupted = dump_file("mysql-bin.000001", &first, 4);
+        interrupted.truncate(3);
+        interrupted.push(Reply::Disconnect);
+        let mut resumed = dump_file("mysql-bin.000001", &first, resume_at);
+        resumed.push(Reply::Eof);
+        let (port, server) = mock_server(vec![interrupted, resumed]);
+
+        let dir = result_dir("reconnect");
+        let options = options(
+            port,
+            &[
+                "--raw",
+                "--result-dir",
+                dir.to_str().unwrap(),
+                "--max-reconnects",
+                "1",
+                "mysql-bin.000001",
+            ],
+        );
+        let (_, diagnostics) = dump(&options, "mysql-bin.000001").unwrap();
+
+        assert_eq!(std::fs::read(dir.join("mysql-bin.000001")).unwrap(), first);
+        assert!(diagnostics.contains(&format!(
+            "reconnected, resuming from `mysql-bin.000001` at {}",
+            resume_at
+        )));
+        let commands = server.join().unwrap();
+        assert_eq!(
+            binlog_dump_of(&commands[1]),
+            (resume_at as u32, "mysql-bin.000001".to_string())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 每个事件都加上半同步头，XID要求ACK
+    fn with_semi_sync_header(replies: Vec<Reply>) -> Vec<Reply> {
+        replies
+            .into_iter()
+            .map(|reply| match reply {
+                Reply::Event(event) if event[4] == 16 => {
+                    Reply::SemiSyncEvent(SEMI_SYNC_ACK_REQUIRED, event)
+                }
+                Reply::Event(event) => Reply::SemiSyncEvent(0, event),
+                reply => reply,
+            })
+            .collect()
+    }
+
+    fn transaction() -> Vec<u8> {
+        BinlogBuilder::new()
+            .push(gtid(1))
+            .event(&QueryEventBuilder::new("shop", "INSERT INTO t VALUES (1)"))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .build()
+    }
+
+    #[test]
+    fn semi_sync_acknowledges_the_transaction() {
+        let binlog = transaction();
+        let mut script = with_semi_sync_header(dump_file("mysql-bin.000001", &binlog, 4));
+        script.push(Reply::Eof);
+        let (port, server) = mock_server(vec![script]);
+
+        let options = options(port, &["--semi-sync", "mysql-bin.000001"]);
+        let (type_codes, diagnostics) = dump(&options, "mysql-bin.000001").unwrap();
+        assert_eq!(type_codes, [4, 15, 162, 2, 16]);
+        assert!(diagnostics.iter().all(|v| !v.starts_with("warning")));
+
+        let commands = server.join().unwrap().remove(0);
+        assert!(commands
+            .iter()
+            .any(|v| v[0] == COM_QUERY && query(v) == "SET @rpl_semi_sync_slave = 1"));
+        // 只回复要求ACK的XID，位置是它之后
+        let acks = commands
+            .iter()
+            .filter(|v| v[0] == SEMI_SYNC_MAGIC)
+            .collect::<Vec<_>>();
+        assert_eq!(acks.len(), 1);
+        assert_eq!(
+            u64::from_le_bytes(acks[0][1..9].try_into().unwrap()),
+            binlog.len() as u64
+        );
+        assert_eq!(&acks[0][9..], b"mysql-bin.000001");
+    }
+
+    #[test]
+    fn semi_sync_not_enabled_on_the_server() {
+        let mut script = dump_file("mysql-bin.000001", &transaction(), 4);
+        script.push(Reply::Eof);
+        let (port, server) = mock_server(vec![script]);
+
+        let options = options(port, &["--semi-sync", "mysql-bin.000001"]);
+        let (type_codes, diagnostics) = dump(&options, "mysql-bin.000001").unwrap();
+        assert_eq!(type_codes, [4, 15, 162, 2, 16]);
+        assert!(diagnostics.contains(
+            &"warning: semi-synchronous replication is not enabled on the server, no ACK will be sent"
+                .to_string()
+        ));
+
+        let commands = server.join().unwrap().remove(0);
+        assert!(commands.iter().all(|v| v[0] != SEMI_SYNC_MAGIC));
+    }
+
+    #[test]
+    fn timestamp_like_the_magic_number_is_not_a_header() {
+        // timestamp的最低字节是0xef，没有`--semi-sync`或者服务端没有开启半同步时按普通的事件解析
+        let binlog = BinlogBuilder::new()
+            .push(gtid(1).timestamp(0x6553_f0ef))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()).timestamp(0x6553_f0ef))
+            .build();
+
+        for extra in [&[][..], &["--semi-sync"]] {
+            let mut script = dump_file("mysql-bin.000001", &binlog, 4);
+            script.push(Reply::Eof);
+            let (port, server) = mock_server(vec![script]);
+
+            let options = options(port, &[extra, &["mysql-bin.000001"]].concat());
+            let (type_codes, _) = dump(&options, "mysql-bin.000001").unwrap();
+            assert_eq!(type_codes, [4, 15, 162, 16]);
+
+            let commands = server.join().unwrap().remove(0);
+            assert!(commands.iter().all(|v| v[0] != SEMI_SYNC_MAGIC));
+        }
+    }
+
+    #[test]
+    fn missing_semi_sync_header_is_corruption() {
+        let mut script = with_semi_sync_header(dump_file("mysql-bin.000001", &transaction(), 4));
+        // 第一个事件之后半同步头消失
+        if let Reply::SemiSyncEvent(_, event) = script.remove(2) {
+            script.insert(2, Reply::Event(event));
+        }
+        let (port, server) = mock_server(vec![script]);
+
+        let options = options(port, &["--semi-sync", "mysql-bin.000001"]);
+        let error = dump(&options, "mysql-bin.000001").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ParseError>().unwrap().category,
+            ErrorCategory::Corruption
+        );
+        assert!(error.to_string().contains("has no semi-sync header"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn semi_sync_header_detection() {
+        let event = EventBuilder::new(16, 1u64.to_le_bytes().to_vec()).build(4);
+        assert!(has_semi_sync_header(
+            &[&[SEMI_SYNC_MAGIC, 1][..], &event].concat()
+        ));
+        assert!(!has_semi_sync_header(&event));
+        // 长度对不上的不是半同步头
+        assert!(!has_semi_sync_header(
+            &[&[SEMI_SYNC_MAGIC, 1][..], &event[..event.len() - 1]].concat()
+        ));
+        assert!(!has_semi_sync_header(&[SEMI_SYNC_MAGIC, 1]));
+    }
+
+    #[test]
+    fn reconnect_resumes_from_the_last_position() {
+        let binlog = transaction();
+        let resume_at = split_events(&binlog)[3].0;
+
+        // rotate、FDE、GTID、query之后断开
+        let mut interrupted = dump_file("mysql-bin.000001", &binlog, 4);
+        interrupted.truncate(4);
+        interrupted.push(Reply::Disconnect);
+        let mut resumed = dump_file("mysql-bin.000001", &binlog, resume_at);
+        resumed.push(Reply::Eof);
+        let (port, server) = mock_server(vec![interrupted, resumed]);
+
+        let options = options(port, &["--max-reconnects", "2", "mysql-bin.000001"]);
+        let metrics = MetricsRegistry::new();
+        let (type_codes, diagnostics) =
+            dump_with_metrics(&options, "mysql-bin.000001", Some(&metrics)).unwrap();
+
+        // 重新发送的rotate和FDE不再输出
+        assert_eq!(type_codes, [4, 15, 162, 2, 16]);
+        assert!(diagnostics[1].starts_with("connection lost: "));
+        assert!(diagnostics[1].ends_with("reconnecting in 1s (1/2)"));
+        assert_eq!(
+            diagnostics[2..],
+            [
+                format!(
+                    "reconnected, resuming from `mysql-bin.000001` at {}",
+                    resume_at
+                ),
+                "reconnected 1 times".to_string(),
+            ]
+        );
+        assert!(metrics
+            .render()
+            .contains("mariadb_binlog_reconnects_total 1"));
+
+        let commands = server.join().unwrap();
+        assert_eq!(
+            binlog_dump_of(&commands[1]),
+            (resume_at as u32, "mysql-bin.000001".to_string())
+        );
+    }
+
+    #[test]
+    fn reconnect_resumes_from_the_last_complete_gtid() {
+        let binlog = BinlogBuilder::new()
+            .push(gtid_list(&[(0, 1, 5)]))
+            .push(gtid(6))
+            .event(&QueryEventBuilder::new("shop", "INSERT INTO t VALUES (6)"))
+            .push(EventBuilder::new(16, 6u64.to_le_bytes().to_vec()))
+            .push(gtid(7))
+            .event(&QueryEventBuilder::new("shop", "INSERT INTO t VALUES (7)"))
+            .push(EventBuilder::new(16, 7u64.to_le_bytes().to_vec()))
+            .build();
+
+        // GTID 0-1-7的事务只收到了一部分
+        let mut interrupted = dump_file("mysql-bin.000001", &binlog, 4);
+        interrupted.truncate(8);
+        interrupted.push(Reply::Disconnect);
+        // 服务端从0-1-6之后重新发送，和已经输出的部分重叠
+        let mut resumed = dump_file("mysql-bin.000001", &binlog, 4);
+        resumed.drain(3..6);
+        resumed.push(Reply::Eof);
+        let (port, server) = mock_server(vec![interrupted, resumed]);
+
+        let options = options(port, &["--start-gtid", "0-1-5", "--max-reconnects", "1"]);
+        let (type_codes, diagnostics) = dump(&options, "").unwrap();
+
+        assert_eq!(type_codes, [4, 15, 163, 162, 2, 16, 162, 2, 16]);
+        assert!(diagnostics.contains(&"reconnected, resuming from GTID `0-1-6`".to_string()));
+
+        let commands = server.join().unwrap();
+        assert!(commands[1]
+            .iter()
+            .any(|v| v[0] == COM_QUERY && query(v) == "SET @slave_connect_state = '0-1-6'"));
+    }
+
+    #[test]
+    fn give_up_after_max_reconnects() {
+        let binlog = transaction();
+        let mut interrupted = dump_file("mysql-bin.000001", &binlog, 4);
+        interrupted.truncate(3);
+        interrupted.push(Reply::Disconnect);
+        // 重连之后还没有新的事件就又断开
+        let mut resumed = dump_file("mysql-bin.000001", &binlog, split_events(&binlog)[2].0);
+        resumed.truncate(2);
+        resumed.push(Reply::Disconnect);
+        let (port, server) = mock_server(vec![interrupted, resumed]);
+
+        let options = options(port, &["--max-reconnects", "1", "mysql-bin.000001"]);
+        let error = dump(&options, "mysql-bin.000001").unwrap_err().to_string();
+        assert!(error.contains("giving up after 1 reconnects"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn no_reconnect_without_max_reconnects() {
+        let mut interrupted = dump_file("mysql-bin.000001", &transaction(), 4);
+        interrupted.truncate(3);
+        interrupted.push(Reply::Disconnect);
+        let (port, server) = mock_server(vec![interrupted]);
+
+        let options = options(port, &["--max-reconnects", "0", "mysql-bin.000001"]);
+        let error = dump(&options, "mysql-bin.000001").unwrap_err();
+        assert!(error.downcast_ref::<io::Error>().is_some());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fs::OpenOptions,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use serde::Serialize;
+
+use crate::error::ParseError;
+use crate::model::MyError;
+use crate::util::check_file_magic_number;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+// FDE中的flag，服务端打开binlog时设置，正常关闭时清除
+const LOG_EVENT_BINLOG_IN_USE_F: u16 = 0x1;
+
+// FDE body的最后是1个字节的校验算法和4个字节的校验和
+const BINLOG_CHECKSUM_ALG_CRC32: u8 = 1;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Damage {
+    None,
+    /// 从这个位置开始到文件结束都不是完整的事件，可以安全截断
+    Tail {
+        position: u64,
+    },
+    /// 这个位置的事件损坏了，但后面还有完整的事件
+    Middle {
+        position: u64,
+        next_valid_position: u64,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairReport {
+    pub file_path: String,
+    pub file_length: u64,
+    /// 最后一个完整事件结束的位置，截断后的文件长度
+    pub valid_length: u64,
+    pub last_valid_event_position: Option<u64>,
+    pub valid_events: u64,
+    pub in_use_flag: bool,
+    pub damage: Damage,
+    /// 是否已经修改了文件，dry run时为false
+    pub repaired: bool,
+}
+
+impl std::fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "file: {} ({} bytes)", self.file_path, self.file_length)?;
+        writeln!(
+            f,
+            "valid events: {}, last valid event at {}, valid length {}",
+            self.valid_events,
+            self.last_valid_event_position
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.valid_length
+        )?;
+        writeln!(f, "in use flag: {}", self.in_use_flag)?;
+
+        match &self.damage {
+            Damage::None => writeln!(f, "damage: none")?,
+            Damage::Tail { position } => writeln!(
+                f,
+                "damage: incomplete tail from {} ({} bytes)",
+                position,
+                self.file_length - position
+            )?,
+            Damage::Middle {
+                position,
+                next_valid_position,
+            } => writeln!(
+                f,
+                "damage: corrupted event at {}, but a valid event is found at {}",
+                position, next_valid_position
+            )?,
+        }
+
+        if self.repaired {
+            write!(f, "repaired: truncated to {} bytes", self.valid_length)
+        } else {
+            write!(f, "dry run, nothing is changed")
+        }
+    }
+}
+
+/// 检查文件中最后一个完整并且校验和正确的事件，truncate为true时截断之后的内容并清除FDE中的in use flag
+/// 损坏的位置后面还有完整的事件时说明不是写到一半的文件，除非force为true，否则不做修改
+pub fn repair_binlog(
+    binlog_file_path: &str,
+    truncate: bool,
+    force: bool,
+) -> Result<RepairReport, BoxedError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(truncate)
+        .open(binlog_file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(binlog_file_path)));
+    }
+
+    let file_length = file.metadata()?.len();
+
+    // FDE是第一个事件，从它得知是否有校验和，后面会再按校验和检查一次
+    let format_description = read_event(&mut file, 4, file_length, false)?;
+    let has_checksum = match &format_description {
+        Some(event) if event[4] == 15 && event.len() >= EVENT_HEADER_LENGTH as usize + 5 => {
+            event[event.len() - 5] == BINLOG_CHECKSUM_ALG_CRC32
+        }
+        _ => true,
+    };
+    let in_use_flag = format_description.as_ref().is_some_and(|v| {
+        v[4] == 15 && u16::from_le_bytes([v[17], v[18]]) & LOG_EVENT_BINLOG_IN_USE_F > 0
+    });
+
+    let mut offset = 4;
+    let mut last_valid_event_position = None;
+    let mut valid_events = 0;
+    while offset < file_length {
+        match read_event(&mut file, offset, file_length, has_checksum)? {
+            Some(event) => {
+                last_valid_event_position = Some(offset);
+                valid_events += 1;
+                offset += event.len() as u64;
+            }
+            None => break,
+        }
+    }
+
+    let damage = if offset >= file_length {
+        Damage::None
+    } else {
+        match find_next_valid_event(&mut file, offset + 1, file_length, has_checksum)? {
+            Some(next_valid_position) => Damage::Middle {
+                position: offset,
+                next_valid_position,
+            },
+            None => Damage::Tail { position: offset },
+        }
+    };
+
+    if truncate && matches!(damage, Damage::Middle { .. }) && !force {
+        return Err(Box::new(MyError(format!(
+            "`{}` is corrupted at {} but has valid events after it, use `--force` to truncate anyway",
+            binlog_file_path, offset
+        ))));
+    }
+
+    let mut repaired = false;
+    if truncate && (damage != Damage::None || in_use_flag) {
+        file.set_len(offset)?;
+
+        if let (true, Some(mut event)) = (in_use_flag && offset > 4, format_description) {
+            let flags = u16::from_le_bytes([event[17], event[18]]) & !LOG_EVENT_BINLOG_IN_USE_F;
+            event[17..19].copy_from_slice(&flags.to_le_bytes());
+
+            if has_checksum {
+                let length = event.len();
+                let checksum = crc32fast::hash(&event[..length - 4]);
+                event[length - 4..].copy_from_slice(&checksum.to_le_bytes());
+            }
+
+            file.seek(SeekFrom::Start(4))?;
+            file.write_all(&event)?;
+        }
+
+        file.flush()?;
+        repaired = true;
+    }
+
+    Ok(RepairReport {
+        file_path: binlog_file_path.to_string(),
+        file_length,
+        valid_length: offset,
+        last_valid_event_position,
+        valid_events,
+        in_use_flag,
+        damage,
+        repaired,
+    })
+}
+
+/// 读取一个完整的事件，长度超出文件、next_event_position不对或者校验和错误时返回None
+fn read_event<R: Read + Seek>(
+    file: &mut R,
+    offset: u64,
+    file_length: u64,
+    has_checksum: bool,
+) -> Result<Option<Vec<u8>>, BoxedError> {
+    if offset + EVENT_HEADER_LENGTH > file_length {
+        return Ok(None);
+    }
+
+    let mut header = [0u8; EVENT_HEADER_LENGTH as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+
+    let event_length = u32::from_le_bytes(header[9..13].try_into()?) as u64;
+    let next_event_position = u32::from_le_bytes(header[13..17].try_into()?) as u64;
+
+    let min_length = EVENT_HEADER_LENGTH + if has_checksum { 4 } else { 0 };
+    if event_length < min_length
+        || offset + event_length > file_length
+        || next_event_position != offset + event_length
+    {
+        return Ok(None);
+    }
+
+    let mut event = vec![0u8; event_length as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut event)?;
+
+    if has_checksum {
+        let length = event.len();
+        let checksum = u32::from_le_bytes(event[length - 4..].try_into()?);
+        if crc32fast::hash(&event[..length - 4]) != checksum {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(event))
+}
+
+/// 从start开始逐字节查找下一个完整的事件，`--skip-errors`时也用来跳过header损坏的事件
+pub fn find_next_valid_event<R: Read + Seek>(
+    file: &mut R,
+    start: u64,
+    file_length: u64,
+    has_checksum: bool,
+) -> Result<Option<u64>, BoxedError> {
+    for offset in start..file_length {
+        if read_event(file, offset, file_length, has_checksum)?.is_some() {
+            return Ok(Some(offset));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::checksum::ChecksumVerifier;
+    use crate::reader::BinlogReader;
+    use crate::service::get_event_header;
+
+    // 服务端还在写的文件：FDE中有in use flag，最后一个事件只写了一半
+    fn fixture() -> (Vec<u8>, u64) {
+        let binlog = BinlogBuilder::empty()
+            .push(
+                EventBuilder::from_body(&FormatDescriptionBuilder::new("10.11.6-MariaDB-log"))
+                    .flags(LOG_EVENT_BINLOG_IN_USE_F),
+            )
+            .event(&QueryEventBuilder::new("shop", "CREATE TABLE t1 (id INT)"))
+            .event(&QueryEventBuilder::new("shop", "CREATE TABLE t2 (id INT)"));
+        let valid_length = binlog.position();
+
+        let incomplete =
+            EventBuilder::from_body(&QueryEventBuilder::new("shop", "CREATE TABLE t3 (id INT)"))
+                .build(valid_length as u32);
+
+        (binlog.raw(&incomplete[..30]).build(), valid_length)
+    }
+
+    fn copy(name: &str, binlog: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("repair-{}-{}", std::process::id(), name));
+        std::fs::write(&path, binlog).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // 从头到尾解析并检查校验和，返回事件数
+    fn parse_to_end(file_path: &str) -> usize {
+        let mut file = BinlogReader::open(file_path).unwrap();
+        let file_length = file.file_length().unwrap();
+        let mut checksum_verifier = ChecksumVerifier::new();
+
+        let mut events = 0;
+        let mut position = 4;
+        while position < file_length {
+            let header = get_event_header(&mut file, position).unwrap();
+            checksum_verifier
+                .verify(&mut file, position, &header)
+                .unwrap();
+            position = header.next_event_position as u64;
+            events += 1;
+        }
+        assert_eq!(position, file_length);
+
+        events
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let (binlog, valid_length) = fixture();
+        let path = copy("dry-run", &binlog);
+
+        let report = repair_binlog(&path, false, false).unwrap();
+        assert_eq!(
+            report.damage,
+            Damage::Tail {
+                position: valid_length
+            }
+        );
+        assert_eq!(report.valid_length, valid_length);
+        assert_eq!(report.valid_events, 3);
+        assert!(report.in_use_flag);
+        assert!(!report.repaired);
+        assert_eq!(std::fs::read(&path).unwrap(), binlog);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncate_the_incomplete_tail() {
+        let (binlog, valid_length) = fixture();
+        let path = copy("truncate", &binlog);
+
+        let report = repair_binlog(&path, true, false).unwrap();
+        assert!(report.repaired);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_length);
+        assert_eq!(parse_to_end(&path), 3);
+
+        // 再检查一次时文件已经是正常关闭的状态
+        let report = repair_binlog(&path, false, false).unwrap();
+        assert_eq!(report.damage, Damage::None);
+        assert!(!report.in_use_flag);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn damage_in_the_middle_needs_force() {
+        let (binlog, _) = fixture();
+        let mut binlog = binlog;
+        // 第二个事件的校验和错误，后面还有完整的事件
+        let second = BinlogBuilder::new().position() as usize;
+        binlog[second + 25] ^= 0xff;
+        let path = copy("middle", &binlog);
+
+        let report = repair_binlog(&path, false, false).unwrap();
+        assert!(
+            matches!(report.damage, Damage::Middle { position, .. } if position == second as u64)
+        );
+
+        assert!(repair_binlog(&path, true, false).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), binlog);
+
+        let report = repair_binlog(&path, true, true).unwrap();
+        assert!(report.repaired);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), second as u64);
+        assert_eq!(parse_to_end(&path), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::model::*;
+
+/// `--rewrite-db from->to`，和mysqlbinlog一样只改写table map中的库名和query事件的默认库
+/// SQL中`库名.表名`形式的名字不会改写
+/// 过滤仍然按原来的库名，所以table_structs中保留原来的table map，改写后的另外保存一份用于输出
+#[derive(Default)]
+pub struct DatabaseRewriter {
+    rules: HashMap<String, String>,
+    table_structs: HashMap<u64, Arc<EventBodyTypeCode19>>,
+}
+
+impl DatabaseRewriter {
+    pub fn new(rules: &[(String, String)]) -> DatabaseRewriter {
+        DatabaseRewriter {
+            rules: rules.iter().cloned().collect(),
+            table_structs: HashMap::new(),
+        }
+    }
+
+    pub fn rewrite<'a>(&'a self, database_name: &'a str) -> &'a str {
+        self.rules
+            .get(database_name)
+            .map(|v| v.as_str())
+            .unwrap_or(database_name)
+    }
+
+    /// 每个事件都要经过这里，以便记录改写后的table map
+    pub fn rewrite_body(&mut self, body: Box<dyn EventBody>) -> Box<dyn EventBody> {
+        if self.rules.is_empty() {
+            return body;
+        }
+
+        if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+            let mut table_map = table_map.clone();
+            table_map.database_name = self.rewrite(&table_map.database_name).to_string();
+            table_map.database_name_length = table_map.database_name.len() as u8;
+
+            let table_map = Arc::new(table_map);
+            self.table_structs
+                .insert(table_map.table_id, table_map.clone());
+
+            return Box::new(table_map);
+        }
+
+        match body.downcast_ref::<EventBodyTypeCode2>() {
+            Some(query_event) if self.rules.contains_key(&query_event.database_name) => {
+                let mut query_event = query_event.clone();
+                query_event.database_name = self.rewrite(&query_event.database_name).to_string();
+                query_event.length_of_database_name = query_event.database_name.len() as u8;
+
+                Box::new(query_event)
+            }
+            _ => body,
+        }
+    }
+
+    /// 输出时使用的table map，没有改写规则时就是原来的
+    pub fn table_structs<'a>(
+        &'a self,
+        original: &'a HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> &'a HashMap<u64, Arc<EventBodyTypeCode19>> {
+        match self.rules.is_empty() {
+            true => original,
+            false => &self.table_structs,
+        }
+    }
+
+    /// 被改写过的table map返回原来的库名，输出时两个名字都要能看到
+    pub fn original_database_name<'a>(
+        &self,
+        body: &dyn EventBody,
+        original: &'a HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Option<&'a str> {
+        let table_map = body.downcast_ref::<EventBodyTypeCode19>()?;
+
+        original
+            .get(&table_map.table_id)
+            .map(|v| v.database_name.as_str())
+            .filter(|v| *v != table_map.database_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::column_select::ColumnRef;
+    use crate::field_type::FieldType;
+    use crate::maxwell::MaxwellConverter;
+    use crate::row_filter::{PredicateOp, PredicateValue, RowFilter, RowPredicate, WhereImage};
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    fn rewriter() -> DatabaseRewriter {
+        DatabaseRewriter::new(&[("shop".to_string(), "staging".to_string())])
+    }
+
+    fn table(database: &str, table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new(database, "orders")
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+    }
+
+    fn fixture() -> Vec<u8> {
+        let shop = table("shop", 7);
+        let other = table("other", 8);
+
+        BinlogBuilder::new()
+            .event(&QueryEventBuilder::new(
+                "shop",
+                "CREATE TABLE orders (id INT)",
+            ))
+            .event(&QueryEventBuilder::new(
+                "other",
+                "CREATE TABLE orders (id INT)",
+            ))
+            .event(&shop)
+            .event(&other)
+            .event(
+                &RowsEventBuilder::insert(&shop)
+                    .row(vec![ColumnValue::Int(1)])
+                    .row(vec![ColumnValue::Int(2)]),
+            )
+            .event(&RowsEventBuilder::insert(&other).row(vec![ColumnValue::Int(3)]))
+            .build()
+    }
+
+    // 和main中的顺序一样：先按原来的库名过滤，再改写库名，输出使用改写后的table map
+    fn run(rows_in: &str) -> (Vec<String>, Vec<serde_json::Value>, Vec<Option<String>>) {
+        let binlog = fixture();
+        let mut rewriter = rewriter();
+        let mut row_filter = RowFilter::new(
+            &[RowPredicate {
+                database: rows_in.to_string(),
+                table: "orders".to_string(),
+                column: ColumnRef::Position(1),
+                op: PredicateOp::IsNotNull,
+                value: PredicateValue::None,
+            }],
+            WhereImage::Any,
+        );
+        let mut maxwell_converter = MaxwellConverter::new("mysql-bin.000001");
+
+        let mut file = Cursor::new(binlog.clone());
+        let mut table_structs = HashMap::new();
+        let mut databases = Vec::new();
+        let mut rows = Vec::new();
+        let mut originals = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            let (body, accepted) = row_filter.filter_body(body, &table_structs).unwrap();
+            let body = rewriter.rewrite_body(body);
+
+            if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+                databases.push(query_event.database_name.clone());
+            }
+            if header.type_code == 19 {
+                originals.push(
+                    rewriter
+                        .original_database_name(body.as_ref(), &table_structs)
+                        .map(|v| v.to_string()),
+                );
+            }
+            if accepted {
+                rows.extend(maxwell_converter.process(
+                    &header,
+                    position,
+                    body.as_ref(),
+                    rewriter.table_structs(&table_structs),
+                ));
+            }
+            position = header.next_event_position as u64;
+        }
+        rows.extend(maxwell_converter.finish());
+
+        (databases, rows, originals)
+    }
+
+    #[test]
+    fn filter_on_original_emit_rewritten() {
+        let (databases, rows, originals) = run("shop");
+
+        assert_eq!(databases, ["staging", "other"]);
+        assert_eq!(originals, [Some("shop".to_string()), None]);
+        assert_eq!(rows.len(), 2);
+        for row in &rows {
+            assert_eq!(row["database"], "staging");
+            assert_eq!(row["table"], "orders");
+        }
+
+        // 改写后的库名不能用来过滤
+        let (_, rows, _) = run("staging");
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn names_without_a_rule_are_kept() {
+        let rewriter = rewriter();
+        assert_eq!(rewriter.rewrite("shop"), "staging");
+        assert_eq!(rewriter.rewrite("other"), "other");
+
+        // 没有规则时不复制table map
+        let mut rewriter = DatabaseRewriter::new(&[]);
+        let original = HashMap::from([(7, table("shop", 7).table_info().unwrap())]);
+        let body = rewriter.rewrite_body(Box::new(original[&7].clone()));
+        assert!(rewriter
+            .original_database_name(body.as_ref(), &original)
+            .is_none());
+        assert!(std::ptr::eq(rewriter.table_structs(&original), &original));
+    }
+}
@@ -0,0 +1,697 @@
+use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+
+use crate::column_select::ColumnRef;
+use crate::mask::like;
+use crate::model::*;
+use crate::table_structure::metadata_column_names;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PredicateOp {
+    Eq,
+    NotEq,
+    Less,
+    Greater,
+    IsNull,
+    IsNotNull,
+    /// `LIKE 'abc%'`，只支持前缀
+    Prefix,
+}
+
+/// `--where`中比较的值，加了引号的是字符串，否则按原样保留，比较时再按字段的类型解释
+#[derive(Debug, Clone, PartialEq)]
+pub enum PredicateValue {
+    None,
+    Quoted(String),
+    Unquoted(String),
+}
+
+impl PredicateValue {
+    fn text(&self) -> &str {
+        match self {
+            PredicateValue::None => "",
+            PredicateValue::Quoted(v) | PredicateValue::Unquoted(v) => v,
+        }
+    }
+}
+
+/// `库名.表名.字段 OP 值`，库名和表名可以使用%通配符，不区分大小写，字段按名字或者`#N`
+#[derive(Debug, Clone)]
+pub struct RowPredicate {
+    pub database: String,
+    pub table: String,
+    pub column: ColumnRef,
+    pub op: PredicateOp,
+    pub value: PredicateValue,
+}
+
+impl RowPredicate {
+    fn matches_table(&self, table_map: &EventBodyTypeCode19) -> bool {
+        like(&self.database, &table_map.database_name) && like(&self.table, &table_map.table_name)
+    }
+
+    /// 字段不在镜像中（binlog_row_image=MINIMAL）时不匹配
+    fn matches(&self, value: Option<&ColumnValue>) -> bool {
+        let value = match value {
+            Some(value) => value,
+            None => return false,
+        };
+
+        match (self.op, value) {
+            (PredicateOp::IsNull, value) => *value == ColumnValue::Null,
+            (PredicateOp::IsNotNull, value) => *value != ColumnValue::Null,
+            // 和SQL一样，NULL和任何值比较都不成立
+            (_, ColumnValue::Null | ColumnValue::Unsupported(_)) => false,
+            (PredicateOp::Prefix, value) => match value_text(value) {
+                Some(text) => text.starts_with(self.value.text()),
+                None => false,
+            },
+            (op, value) => match self.compare(value) {
+                Some(ordering) => match op {
+                    PredicateOp::Eq => ordering == Ordering::Equal,
+                    PredicateOp::NotEq => ordering != Ordering::Equal,
+                    PredicateOp::Less => ordering == Ordering::Less,
+                    _ => ordering == Ordering::Greater,
+                },
+                None => false,
+            },
+        }
+    }
+
+    // 字段的值和self.value比较，类型不能比较时为None
+    fn compare(&self, value: &ColumnValue) -> Option<Ordering> {
+        let text = self.value.text();
+
+        match value {
+            // 整数字段和整数比较，浮点数按f64比较，不是数字的值不会当作0
+            ColumnValue::Int(v) => match text.parse::<i64>() {
+                Ok(literal) => Some(v.cmp(&literal)),
+                Err(_) => (*v as f64).partial_cmp(&text.parse::<f64>().ok()?),
+            },
+            // 负数的字面量比任何unsigned的值都小
+            ColumnValue::UInt(v) => match (text.parse::<u64>(), text.parse::<i64>()) {
+                (Ok(literal), _) => Some(v.cmp(&literal)),
+                (_, Ok(_)) => Some(Ordering::Greater),
+                _ => (*v as f64).partial_cmp(&text.parse::<f64>().ok()?),
+            },
+            ColumnValue::Float(v) => (*v as f64).partial_cmp(&text.parse::<f64>().ok()?),
+            ColumnValue::Double(v) => v.partial_cmp(&text.parse::<f64>().ok()?),
+            // DECIMAL按十进制的数字比较，不经过f64，超过f64精度的值也不会相等
+            ColumnValue::Decimal(v) => compare_decimal(v, text),
+            ColumnValue::RawDecimal(v) => compare_decimal(&v.value, text),
+            // 二进制值和`0x`开头的十六进制比较，否则和值的字节比较
+            ColumnValue::Bytes(v) => {
+                let literal = match (&self.value, text.strip_prefix("0x")) {
+                    (PredicateValue::Unquoted(_), Some(hex)) => parse_hex(hex)?,
+                    _ => text.as_bytes().to_vec(),
+                };
+                Some(v.as_slice().cmp(literal.as_slice()))
+            }
+            // 字符串和时间按字节比较，数字不会转换，`'007'`和`7`不相等
+            value => Some(value_text(value)?.as_bytes().cmp(text.as_bytes())),
+        }
+    }
+}
+
+// 字符串、时间类型的文本，数字字段不支持前缀匹配
+fn value_text(value: &ColumnValue) -> Option<String> {
+    match value {
+        ColumnValue::String(v) | ColumnValue::Temporal(v) => Some(v.clone()),
+        ColumnValue::RawTemporal(v) => Some(v.to_string()),
+        ColumnValue::Bytes(v) => String::from_utf8(v.clone()).ok(),
+        _ => None,
+    }
+}
+
+// 十进制数字的符号、去掉前导0的整数部分和去掉末尾0的小数部分，不支持指数形式
+fn parse_decimal(text: &str) -> Option<(bool, &str, &str)> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if integer.is_empty() && fraction.is_empty()
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|v| v.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let integer = integer.trim_start_matches('0');
+    let fraction = fraction.trim_end_matches('0');
+    // -0和0相等
+    let negative = negative && !(integer.is_empty() && fraction.is_empty());
+
+    Some((negative, integer, fraction))
+}
+
+fn compare_decimal(value: &str, literal: &str) -> Option<Ordering> {
+    let (value_negative, value_integer, value_fraction) = parse_decimal(value)?;
+    let (literal_negative, literal_integer, literal_fraction) = parse_decimal(literal)?;
+
+    let ordering = match (value_negative, literal_negative) {
+        (false, true) => return Some(Ordering::Greater),
+        (true, false) => return Some(Ordering::Less),
+        _ => value_integer
+            .len()
+            .cmp(&literal_integer.len())
+            .then_with(|| value_integer.cmp(literal_integer))
+            .then_with(|| value_fraction.cmp(literal_fraction)),
+    };
+
+    match value_negative {
+        true => Some(ordering.reverse()),
+        false => Some(ordering),
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `--where-image`，按哪个镜像匹配，insert只有after，delete只有before
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum WhereImage {
+    #[default]
+    Any,
+    Before,
+    After,
+}
+
+impl WhereImage {
+    pub fn from_name(name: &str) -> Result<WhereImage, BoxedError> {
+        match name {
+            "any" => Ok(WhereImage::Any),
+            "before" => Ok(WhereImage::Before),
+            "after" => Ok(WhereImage::After),
+            others => Err(Box::new(MyError(format!(
+                "unknown image `{}`, it should be any, before or after",
+                others
+            )))),
+        }
+    }
+}
+
+/// `--where`，只保留行事件中字段的值满足所有条件的行，没有剩下的行的事件不输出
+///
+/// 条件按同一个镜像计算，update的before或者after满足即可（`--where-image`可以指定镜像）。
+/// 没有条件涉及的表的行事件也不输出，其他事件不受影响。
+/// 按原来的库名和字段值匹配（在--rewrite-db和--mask-column之前），字段名来自table map中的元数据
+#[derive(Default)]
+pub struct RowFilter {
+    predicates: Vec<RowPredicate>,
+    image: WhereImage,
+    // table_id -> 这张表的条件和字段的位置，在table map事件时确定
+    plans: HashMap<u64, Vec<(usize, RowPredicate)>>,
+}
+
+impl RowFilter {
+    pub fn new(predicates: &[RowPredicate], image: WhereImage) -> RowFilter {
+        RowFilter {
+            predicates: predicates.to_vec(),
+            image,
+            plans: HashMap::new(),
+        }
+    }
+
+    /// 每个事件都要经过这里，返回过滤之后的事件和是否还要输出
+    /// 按字段名的条件在table map中没有字段名、或者没有这个字段时返回错误
+    pub fn filter_body(
+        &mut self,
+        body: Box<dyn EventBody>,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Result<(Box<dyn EventBody>, bool), BoxedError> {
+        if self.predicates.is_empty() {
+            return Ok((body, true));
+        }
+
+        if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+            let plan = self.plan(table_map)?;
+            self.plans.insert(table_map.table_id, plan);
+            return Ok((body, true));
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Ok((body, true)),
+        };
+
+        let plan = match self.plans.get(&rows_event.table_id) {
+            Some(plan) if !plan.is_empty() => plan,
+            _ => return Ok((body, false)),
+        };
+
+        let mut rows_event = rows_event.clone();
+        if !rows_event.is_decoded() {
+            if let Some(table_map) = table_structs.get(&rows_event.table_id) {
+                rows_event.rows = rows_event.decode_rows(table_map)?;
+            }
+        }
+        rows_event.row_images = Vec::new();
+
+        let matches = |image: &Option<Vec<ColumnValue>>| {
+            image.as_ref().is_some_and(|image| {
+                plan.iter()
+                    .all(|(index, predicate)| predicate.matches(image.get(*index)))
+            })
+        };
+        rows_event.rows.retain(|row| match self.image {
+            WhereImage::Any => matches(&row.before) || matches(&row.after),
+            WhereImage::Before => matches(&row.before),
+            WhereImage::After => matches(&row.after),
+        });
+        rows_event.number_of_rows = rows_event.rows.len() as u64;
+
+        // 原有的column_data是第一行的镜像，也要换成剩下的第一行
+        if let Some(row) = rows_event.rows.first() {
+            if let Some(image) = row.before.as_ref().or(row.after.as_ref()) {
+                rows_event.column_data = column_data_for_human(image);
+            }
+
+            if let (Some(_), Some(after)) = (&row.before, &row.after) {
+                rows_event.column_data_for_update = Some(column_data_for_human(after));
+            }
+        }
+
+        let keep = !rows_event.rows.is_empty();
+        Ok((Box::new(rows_event), keep))
+    }
+
+    // 这张表的所有条件，没有条件的表为空
+    fn plan(
+        &self,
+        table_map: &EventBodyTypeCode19,
+    ) -> Result<Vec<(usize, RowPredicate)>, BoxedError> {
+        let column_count = table_map.columns.len();
+        let table = format!("`{}`.`{}`", table_map.database_name, table_map.table_name);
+        let names = metadata_column_names(table_map);
+
+        let mut plan = Vec::new();
+        for predicate in self
+            .predicates
+            .iter()
+            .filter(|v| v.matches_table(table_map))
+        {
+            let index = match &predicate.column {
+                ColumnRef::Position(position) if (1..=column_count).contains(position) => {
+                    position - 1
+                }
+                ColumnRef::Position(position) => {
+                    return Err(Box::new(MyError(format!(
+                        "`--where` uses column #{} but {} has {} columns",
+                        position, table, column_count
+                    ))));
+                }
+                ColumnRef::Name(name) => {
+                    let names = names.as_ref().ok_or_else(|| {
+                        MyError(format!(
+                            "`--where` needs the column names of {}, please set binlog_row_metadata=FULL on the server or use `#N`",
+                            table
+                        ))
+                    })?;
+
+                    names
+                        .iter()
+                        .position(|v| v.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| MyError(format!("{} has no column `{}`", table, name)))?
+                }
+            };
+
+            plan.push((index, predicate.clone()));
+        }
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn predicate(column: usize, op: PredicateOp, value: PredicateValue) -> RowPredicate {
+        RowPredicate {
+            database: "shop".to_string(),
+            table: "orders".to_string(),
+            column: ColumnRef::Position(column),
+            op,
+            value,
+        }
+    }
+
+    fn unquoted(op: PredicateOp, value: &str) -> RowPredicate {
+        predicate(1, op, PredicateValue::Unquoted(value.to_string()))
+    }
+
+    fn quoted(op: PredicateOp, value: &str) -> RowPredicate {
+        predicate(1, op, PredicateValue::Quoted(value.to_string()))
+    }
+
+    fn decimal(value: &str) -> ColumnValue {
+        ColumnValue::Decimal(value.to_string())
+    }
+
+    #[test]
+    fn compare_numbers() {
+        let int = ColumnValue::Int(42);
+        assert!(unquoted(PredicateOp::Eq, "42").matches(Some(&int)));
+        assert!(unquoted(PredicateOp::Eq, "42.0").matches(Some(&int)));
+        assert!(unquoted(PredicateOp::Less, "42.5").matches(Some(&int)));
+        assert!(unquoted(PredicateOp::Greater, "-1").matches(Some(&int)));
+        // 不是数字的值不会当作0
+        assert!(!unquoted(PredicateOp::Greater, "abc").matches(Some(&int)));
+        assert!(!unquoted(PredicateOp::NotEq, "abc").matches(Some(&int)));
+
+        // 超过i64范围的BIGINT UNSIGNED
+        let uint = ColumnValue::UInt(18446744073709551615);
+        assert!(unquoted(PredicateOp::Eq, "18446744073709551615").matches(Some(&uint)));
+        assert!(unquoted(PredicateOp::Greater, "9223372036854775807").matches(Some(&uint)));
+        assert!(unquoted(PredicateOp::Greater, "-1").matches(Some(&uint)));
+        assert!(unquoted(PredicateOp::Less, "1e20").matches(Some(&uint)));
+        assert!(!unquoted(PredicateOp::Eq, "abc").matches(Some(&uint)));
+
+        let double = ColumnValue::Double(0.5);
+        assert!(unquoted(PredicateOp::Eq, "0.5").matches(Some(&double)));
+        assert!(unquoted(PredicateOp::Less, "1e3").matches(Some(&double)));
+    }
+
+    #[test]
+    fn compare_decimals_exactly() {
+        let cases = [
+            ("12.50", "12.5", Ordering::Equal),
+            ("12.50", "12.500000", Ordering::Equal),
+            ("0012.5", "12.5", Ordering::Equal),
+            ("-0.00", "0", Ordering::Equal),
+            ("12.5", "12.45", Ordering::Greater),
+            ("12.5", "9.99", Ordering::Greater),
+            ("-12.5", "-9.99", Ordering::Less),
+            ("-12.5", "3", Ordering::Less),
+            (".5", "0.5", Ordering::Equal),
+            ("+7", "7.", Ordering::Equal),
+            // f64只有大约17位有效数字，这些值转换成f64之后是相等的
+            (
+                "12345678901234567890.123456789",
+                "12345678901234567890.123456788",
+                Ordering::Greater,
+            ),
+            ("0.10000000000000000001", "0.1", Ordering::Greater),
+            (
+                "-99999999999999999999999999999.9",
+                "-99999999999999999999999999999.8",
+                Ordering::Less,
+            ),
+        ];
+        for (value, literal, ordering) in cases {
+            assert_eq!(
+                unquoted(PredicateOp::Eq, literal).compare(&decimal(value)),
+                Some(ordering),
+                "{} vs {}",
+                value,
+                literal
+            );
+            // RawDecimal和Decimal一样
+            let raw = ColumnValue::RawDecimal(RawDecimal {
+                value: value.to_string(),
+                precision: 65,
+                scale: 30,
+            });
+            assert_eq!(
+                unquoted(PredicateOp::Eq, literal).compare(&raw),
+                Some(ordering)
+            );
+        }
+
+        // 不是十进制数字的值不能比较
+        for literal in ["abc", "1e3", "", "-", ".", "1.2.3", "0x10"] {
+            assert_eq!(
+                unquoted(PredicateOp::Eq, literal).compare(&decimal("1")),
+                None,
+                "{}",
+                literal
+            );
+        }
+        assert!(!unquoted(PredicateOp::NotEq, "abc").matches(Some(&decimal("1"))));
+        assert!(unquoted(PredicateOp::NotEq, "1.01").matches(Some(&decimal("1.0"))));
+    }
+
+    #[test]
+    fn compare_strings_and_bytes() {
+        let string = ColumnValue::String("007".to_string());
+        assert!(quoted(PredicateOp::Eq, "007").matches(Some(&string)));
+        // 字符串不会转换成数字
+        assert!(!unquoted(PredicateOp::Eq, "7").matches(Some(&string)));
+        assert!(quoted(PredicateOp::Less, "01").matches(Some(&string)));
+        assert!(quoted(PredicateOp::Greater, "").matches(Some(&string)));
+        assert!(quoted(PredicateOp::Prefix, "00").matches(Some(&string)));
+        assert!(!quoted(PredicateOp::Prefix, "07").matches(Some(&string)));
+
+        let temporal = ColumnValue::Temporal("2024-01-02 03:04:05".to_string());
+        assert!(quoted(PredicateOp::Greater, "2024-01-01").matches(Some(&temporal)));
+        assert!(quoted(PredicateOp::Prefix, "2024-01").matches(Some(&temporal)));
+
+        let bytes = ColumnValue::Bytes(vec![0xca, 0xfe]);
+        assert!(unquoted(PredicateOp::Eq, "0xCAFE").matches(Some(&bytes)));
+        assert!(unquoted(PredicateOp::Less, "0xcb").matches(Some(&bytes)));
+        // 加了引号的0x是字符串本身
+        assert!(!quoted(PredicateOp::Eq, "0xcafe").matches(Some(&bytes)));
+        assert!(!unquoted(PredicateOp::Eq, "0xcaf").matches(Some(&bytes)));
+
+        // 数字字段不支持前缀匹配
+        assert!(!quoted(PredicateOp::Prefix, "4").matches(Some(&ColumnValue::Int(42))));
+    }
+
+    #[test]
+    fn compare_null() {
+        let is_null = predicate(1, PredicateOp::IsNull, PredicateValue::None);
+        let is_not_null = predicate(1, PredicateOp::IsNotNull, PredicateValue::None);
+
+        assert!(is_null.matches(Some(&ColumnValue::Null)));
+        assert!(!is_null.matches(Some(&ColumnValue::Int(0))));
+        assert!(is_not_null.matches(Some(&ColumnValue::String(String::new()))));
+        assert!(!is_not_null.matches(Some(&ColumnValue::Null)));
+
+        // NULL和任何值比较都不成立
+        for op in [
+            PredicateOp::Eq,
+            PredicateOp::NotEq,
+            PredicateOp::Less,
+            PredicateOp::Greater,
+        ] {
+            assert!(!unquoted(op, "0").matches(Some(&ColumnValue::Null)));
+        }
+        assert!(!quoted(PredicateOp::Prefix, "").matches(Some(&ColumnValue::Null)));
+
+        // 不在镜像中的字段不匹配，IS NULL也一样
+        assert!(!is_null.matches(None));
+        assert!(!is_not_null.matches(None));
+        assert!(!unquoted(PredicateOp::NotEq, "0").matches(None));
+    }
+
+    #[test]
+    fn where_image_from_name() {
+        assert_eq!(WhereImage::from_name("any").unwrap(), WhereImage::Any);
+        assert_eq!(WhereImage::from_name("before").unwrap(), WhereImage::Before);
+        assert_eq!(WhereImage::from_name("after").unwrap(), WhereImage::After);
+        assert!(WhereImage::from_name("both").is_err());
+    }
+
+    mod images {
+        use std::io::Cursor;
+
+        use super::*;
+        use crate::builders::*;
+        use crate::field_type::FieldType;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+        fn row(id: i64, status: &str) -> Vec<ColumnValue> {
+            vec![
+                ColumnValue::Int(id),
+                ColumnValue::String(status.to_string()),
+            ]
+        }
+
+        // 一个update和一个insert，按条件过滤之后剩下的每个事件的行
+        fn filter(predicates: &[RowPredicate], image: WhereImage) -> Vec<serde_json::Value> {
+            let table = TableMapBuilder::new("shop", "orders")
+                .table_id(42)
+                .column(FieldType::Long, &[])
+                .column(FieldType::Varchar, &64u16.to_le_bytes());
+            let other = TableMapBuilder::new("shop", "users")
+                .table_id(43)
+                .column(FieldType::Long, &[])
+                .column(FieldType::Varchar, &64u16.to_le_bytes());
+            let binlog = BinlogBuilder::new()
+                .event(&table)
+                .event(&other)
+                .event(
+                    &RowsEventBuilder::update(&table)
+                        .update_row(row(1, "new"), row(1, "paid"))
+                        .update_row(row(2, "paid"), row(2, "done")),
+                )
+                .event(&RowsEventBuilder::insert(&table).row(row(3, "paid")))
+                .event(&RowsEventBuilder::insert(&other).row(row(4, "paid")))
+                .build();
+
+            let mut file = Cursor::new(binlog.clone());
+            let mut table_structs = HashMap::new();
+            let mut filter = RowFilter::new(predicates, image);
+            let mut rows = Vec::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                let (body, keep) = filter.filter_body(body, &table_structs).unwrap();
+                if keep && body.downcast_ref::<EventBodyTypeCode23To25>().is_some() {
+                    rows.push(serde_json::to_value(&body).unwrap()["rows"].clone());
+                }
+                position = header.next_event_position as u64;
+            }
+
+            rows
+        }
+
+        fn status(value: &str) -> RowPredicate {
+            predicate(
+                2,
+                PredicateOp::Eq,
+                PredicateValue::Quoted(value.to_string()),
+            )
+        }
+
+        fn update(before: (i64, &str), after: (i64, &str)) -> serde_json::Value {
+            serde_json::json!({"before": [before.0, before.1], "after": [after.0, after.1]})
+        }
+
+        fn insert(id: i64, status: &str) -> serde_json::Value {
+            serde_json::json!({"before": null, "after": [id, status]})
+        }
+
+        #[test]
+        fn filter_rows_by_image() {
+            assert_eq!(
+                filter(&[status("paid")], WhereImage::Any),
+                vec![
+                    serde_json::json!([
+                        update((1, "new"), (1, "paid")),
+                        update((2, "paid"), (2, "done"))
+                    ]),
+                    serde_json::json!([insert(3, "paid")]),
+                ]
+            );
+            assert_eq!(
+                filter(&[status("paid")], WhereImage::Before),
+                vec![serde_json::json!([update((2, "paid"), (2, "done"))])]
+            );
+            assert_eq!(
+                filter(&[status("paid")], WhereImage::After),
+                vec![
+                    serde_json::json!([update((1, "new"), (1, "paid"))]),
+                    serde_json::json!([insert(3, "paid")]),
+                ]
+            );
+        }
+
+        #[test]
+        fn all_predicates_match_the_same_image() {
+            let id = predicate(
+                1,
+                PredicateOp::Eq,
+                PredicateValue::Unquoted("2".to_string()),
+            );
+            assert_eq!(
+                filter(&[id, status("paid")], WhereImage::Any),
+                vec![serde_json::json!([update((2, "paid"), (2, "done"))])]
+            );
+            // 第一行的before中是new，after中是paid，但没有一个镜像同时满足两个条件
+            assert!(filter(&[status("new"), status("paid")], WhereImage::Any).is_empty());
+            assert!(filter(&[status("gone")], WhereImage::Any).is_empty());
+        }
+
+        // DECIMAL(10,2)的价格按binlog中的格式写入，返回满足条件的行的id
+        fn filter_prices(op: PredicateOp, literal: &str) -> Vec<i64> {
+            let table = TableMapBuilder::new("shop", "orders")
+                .table_id(42)
+                .column(FieldType::Long, &[])
+                .column(FieldType::NewDecimal, &[10, 2]);
+            let prices: [(i64, &[u8]); 5] = [
+                (1, &[0x80, 0, 0, 1, 5]),
+                (2, &[0x80, 0, 0, 1, 50]),
+                (3, &[0x7f, 0xff, 0xff, 0xfe, 0xfa]),
+                (4, &[0x7f, 0xff, 0xff, 0xff, 0xfa]),
+                (5, &[0x80, 0, 0, 0, 0]),
+            ];
+            let mut rows = RowsEventBuilder::insert(&table);
+            for (id, price) in prices {
+                rows = rows.row(vec![
+                    ColumnValue::Int(id),
+                    ColumnValue::Bytes(price.to_vec()),
+                ]);
+            }
+            let binlog = BinlogBuilder::new().event(&table).event(&rows).build();
+
+            let mut file = Cursor::new(binlog.clone());
+            let mut table_structs = HashMap::new();
+            let mut filter = RowFilter::new(
+                &[predicate(
+                    2,
+                    op,
+                    PredicateValue::Unquoted(literal.to_string()),
+                )],
+                WhereImage::Any,
+            );
+            let mut ids = Vec::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                let (body, keep) = filter.filter_body(body, &table_structs).unwrap();
+                if let (true, Some(rows_event)) =
+                    (keep, body.downcast_ref::<EventBodyTypeCode23To25>())
+                {
+                    ids.extend(rows_event.rows.iter().map(|v| match v.after.as_deref() {
+                        Some([ColumnValue::Int(id), ..]) => *id,
+                        others => panic!("unexpected row {:?}", others),
+                    }));
+                }
+                position = header.next_event_position as u64;
+            }
+
+            ids
+        }
+
+        #[test]
+        fn negative_and_leading_zero_decimals() {
+            // 1.05、1.50、-1.05、-0.05、0.00
+            assert_eq!(filter_prices(PredicateOp::Less, "0"), [3, 4]);
+            assert_eq!(filter_prices(PredicateOp::Eq, "1.05"), [1]);
+            assert_eq!(filter_prices(PredicateOp::Eq, "1.5"), [2]);
+            assert_eq!(filter_prices(PredicateOp::Eq, "-1.05"), [3]);
+            assert_eq!(filter_prices(PredicateOp::Greater, "-0.06"), [1, 2, 4, 5]);
+            assert_eq!(filter_prices(PredicateOp::Eq, "0"), [5]);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::{collections::HashMap, sync::Arc};
+
+use base64::prelude::*;
+
+use crate::model::*;
+use crate::table_structure::primary_key_columns;
+use crate::util::format_timestamp;
+
+/// 没有主键元数据时输出的字段数
+pub const FALLBACK_COLUMNS: usize = 3;
+
+/// `--rows-only`，每个行事件中的每一行输出一行，用tab分隔：时间、GTID、`库名.表名`、操作、
+/// 主键的值（table map中有主键元数据时），否则是镜像中的前FALLBACK_COLUMNS个字段
+///
+/// 其他事件都不输出，没有表头。值中的tab、换行和`\`和`mysql -B`一样转义，NULL输出为`NULL`。
+/// update和delete按修改前的镜像，insert按插入的镜像；`--columns`去掉的字段不在镜像中，不会输出
+#[derive(Default)]
+pub struct RowsOnlyFormatter {
+    current_gtid: Option<String>,
+    // table_id -> 主键的位置，在table map事件时确定
+    primary_keys: HashMap<u64, Option<Vec<usize>>>,
+}
+
+impl RowsOnlyFormatter {
+    pub fn new() -> RowsOnlyFormatter {
+        RowsOnlyFormatter::default()
+    }
+
+    /// 每个事件都要经过这里，以便记录GTID和主键，返回要输出的行
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Vec<String> {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+        }
+
+        if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+            self.primary_keys
+                .insert(table_map.table_id, primary_key_columns(table_map));
+        }
+
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return Vec::new(),
+        };
+        let table_map = match table_structs.get(&rows_event.table_id) {
+            Some(table_map) => table_map,
+            None => return Vec::new(),
+        };
+
+        let op = match header.type_code {
+            23 | 30 => "insert",
+            24 | 31 => "update",
+            _ => "delete",
+        };
+        let prefix = [
+            format_timestamp(header.timestamp),
+            self.current_gtid.clone().unwrap_or_default(),
+            escape(&format!(
+                "{}.{}",
+                table_map.database_name, table_map.table_name
+            )),
+            op.to_string(),
+        ];
+        let primary_key = self
+            .primary_keys
+            .get(&rows_event.table_id)
+            .cloned()
+            .flatten();
+        // insert只有after，update和delete的before都按columns_used
+        let key = key_columns(primary_key.as_deref(), &rows_event.columns_used);
+
+        rows_event
+            .rows
+            .iter()
+            .filter_map(|row| {
+                let image = row.before.as_ref().or(row.after.as_ref())?;
+                let fields = prefix.iter().cloned().chain(
+                    key.iter()
+                        .map(|index| image.get(*index).map(format_value).unwrap_or_default()),
+                );
+                Some(fields.collect::<Vec<String>>().join("\t"))
+            })
+            .collect()
+    }
+}
+
+// 主键的字段都在镜像中时用主键，否则用镜像中的前几个字段
+fn key_columns(primary_key: Option<&[usize]>, present: &Bitmap) -> Vec<usize> {
+    let is_present = |index: &usize| present.get(*index).unwrap_or(false);
+
+    match primary_key {
+        Some(primary_key) if !primary_key.is_empty() && primary_key.iter().all(is_present) => {
+            primary_key.to_vec()
+        }
+        _ => (0..present.len())
+            .filter(is_present)
+            .take(FALLBACK_COLUMNS)
+            .collect(),
+    }
+}
+
+/// 二进制数据使用base64，和CSV的输出一致
+fn format_value(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Null | ColumnValue::Unsupported(_) => "NULL".to_string(),
+        ColumnValue::Int(v) => v.to_string(),
+        ColumnValue::UInt(v) => v.to_string(),
+        ColumnValue::Float(v) => v.to_string(),
+        ColumnValue::Double(v) => v.to_string(),
+        ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => escape(v),
+        ColumnValue::RawTemporal(v) => escape(&v.to_string()),
+        ColumnValue::RawDecimal(v) => escape(&v.value),
+        ColumnValue::Bytes(v) => BASE64_STANDARD.encode(v),
+    }
+}
+
+// 和`mysql -B`一样转义，保证每行一条记录、字段之间只有分隔符的tab
+fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn text(v: &str) -> ColumnValue {
+        ColumnValue::String(v.to_string())
+    }
+
+    // shop.users(id, name)的主键是id，shop.logs(level, message, payload, at)没有主键元数据
+    fn users() -> TableMapBuilder {
+        crate::builders::users()
+            .table_id(70)
+            .optional_metadata(&[8, 1, 0])
+    }
+
+    fn logs() -> TableMapBuilder {
+        TableMapBuilder::new("shop", "logs")
+            .table_id(71)
+            .column(FieldType::Varchar, &16u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Blob, &[2])
+            .nullable(true)
+            .column(FieldType::Long, &[])
+    }
+
+    fn lines(binlog: &[u8]) -> Vec<String> {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut formatter = RowsOnlyFormatter::new();
+        let mut lines = Vec::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            lines.extend(formatter.process(&header, body.as_ref(), &table_structs));
+            position = header.next_event_position as u64;
+        }
+
+        lines
+    }
+
+    fn event(builder: &dyn EventBodyBuilder, timestamp: u32) -> EventBuilder {
+        EventBuilder::from_body(builder).timestamp(timestamp)
+    }
+
+    #[test]
+    fn mixed_fixture() {
+        let users = users();
+        let logs = logs();
+        let binlog = BinlogBuilder::new()
+            .push(gtid(1).timestamp(1700000000))
+            .push(event(&QueryEventBuilder::new("shop", "BEGIN"), 1700000000))
+            .push(event(&users, 1700000000))
+            .push(event(
+                &RowsEventBuilder::insert(&users)
+                    .row(vec![ColumnValue::Int(1), text("ann")])
+                    .row(vec![ColumnValue::Int(2), ColumnValue::Null]),
+                1700000000,
+            ))
+            .push(event(
+                &RowsEventBuilder::update(&users).update_row(
+                    vec![ColumnValue::Int(2), ColumnValue::Null],
+                    vec![ColumnValue::Int(2), text("bob")],
+                ),
+                1700000001,
+            ))
+            .push(xid(1))
+            .push(gtid(2).timestamp(1700000060))
+            .push(event(
+                &QueryEventBuilder::new("shop", "CREATE TABLE logs (level VARCHAR(4))"),
+                1700000060,
+            ))
+            .push(gtid(3).timestamp(1700000120))
+            .push(event(&QueryEventBuilder::new("shop", "BEGIN"), 1700000120))
+            .push(event(&logs, 1700000120))
+            .push(event(
+                &RowsEventBuilder::insert(&logs).row(vec![
+                    text("warn"),
+                    text("disk\tfull\nretry \\"),
+                    ColumnValue::Bytes(vec![0xff, 0x00, 0x01]),
+                    ColumnValue::Int(9),
+                ]),
+                1700000120,
+            ))
+            .push(event(&users, 1700000121))
+            .push(event(
+                &RowsEventBuilder::delete(&users).row(vec![ColumnValue::Int(1), text("ann")]),
+                1700000121,
+            ))
+            .push(xid(3))
+            .build();
+
+        assert_eq!(
+            lines(&binlog),
+            [
+                "2023-11-14 22:13:20\t0-1-1\tshop.users\tinsert\t1",
+                "2023-11-14 22:13:20\t0-1-1\tshop.users\tinsert\t2",
+                "2023-11-14 22:13:21\t0-1-1\tshop.users\tupdate\t2",
+                "2023-11-14 22:15:20\t0-1-3\tshop.logs\tinsert\twarn\tdisk\\tfull\\nretry \\\\\t/wAB",
+                "2023-11-14 22:15:21\t0-1-3\tshop.users\tdelete\t1",
+            ]
+        );
+    }
+
+    #[test]
+    fn minimal_image_without_the_primary_key() {
+        // 主键不在镜像中时用镜像中的前几个字段
+        let users = users();
+        let binlog = BinlogBuilder::new()
+            .push(gtid(1).timestamp(0))
+            .event(&users)
+            .event(
+                &RowsEventBuilder::update(&users)
+                    .columns_used(&[false, true])
+                    .update_row(
+                        vec![ColumnValue::Null, text("ann")],
+                        vec![ColumnValue::Int(1), text("amy")],
+                    ),
+            )
+            .build();
+
+        assert_eq!(
+            lines(&binlog),
+            ["1970-01-01 00:00:00\t0-1-1\tshop.users\tupdate\tann"]
+        );
+    }
+}
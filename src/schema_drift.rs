@@ -0,0 +1,316 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Serialize;
+
+use crate::model::*;
+use crate::sql::quote_identifier;
+use crate::table_structure::{column_type_definitions, metadata_column_names};
+
+/// 同一个位置的字段在两个版本中的定义，新增的字段before为None，删除的字段after为None
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnChange {
+    /// 从1开始，和`col_1`一致
+    pub position: usize,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// 同一张表的table map和上一次的定义不同，通常是中间执行了ALTER TABLE
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaChange {
+    /// table map事件的位置
+    pub position: u64,
+    pub database: String,
+    pub table: String,
+    /// 第一次出现的定义是版本1，每次变化加1
+    pub version: u32,
+    pub previous_table_id: u64,
+    pub table_id: u64,
+    pub previous_column_count: usize,
+    pub column_count: usize,
+    pub changed_columns: Vec<ColumnChange>,
+    pub primary_key_changed: bool,
+}
+
+impl Display for SchemaChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "warning: the schema of {}.{} changed at {} (version {}): {} -> {} columns",
+            quote_identifier(&self.database),
+            quote_identifier(&self.table),
+            self.position,
+            self.version,
+            self.previous_column_count,
+            self.column_count
+        )?;
+
+        for change in &self.changed_columns {
+            write!(
+                f,
+                ", #{} {} -> {}",
+                change.position,
+                change.before.as_deref().unwrap_or("(none)"),
+                change.after.as_deref().unwrap_or("(none)")
+            )?;
+        }
+
+        if self.primary_key_changed {
+            write!(f, ", primary key changed")?;
+        }
+
+        Ok(())
+    }
+}
+
+// 一张表最近的定义，只在第一次出现或者变化时保存
+struct TableSchema {
+    version: u32,
+    table_map: EventBodyTypeCode19,
+}
+
+/// 按(库名, 表名)而不是table_id记录表的定义，table_id变了但是定义相同时不算变化
+/// 比较字段的类型、元数据、是否可以为NULL，以及可选元数据中的字段名、字符集、unsigned和主键
+#[derive(Default)]
+pub struct SchemaTracker {
+    tables: HashMap<(String, String), TableSchema>,
+}
+
+impl SchemaTracker {
+    pub fn new() -> SchemaTracker {
+        SchemaTracker::default()
+    }
+
+    /// position是事件的位置，只处理table map事件，定义变化时返回变化的内容
+    pub fn process(&mut self, position: u64, body: &dyn EventBody) -> Option<SchemaChange> {
+        let table_map = body.downcast_ref::<EventBodyTypeCode19>()?;
+
+        let key = (
+            table_map.database_name.clone(),
+            table_map.table_name.clone(),
+        );
+
+        let previous = match self.tables.get_mut(&key) {
+            Some(previous) => previous,
+            None => {
+                self.tables.insert(
+                    key,
+                    TableSchema {
+                        version: 1,
+                        table_map: table_map.clone(),
+                    },
+                );
+                return None;
+            }
+        };
+
+        if previous.table_map.columns == table_map.columns
+            && previous.table_map.primary_key == table_map.primary_key
+            && previous.table_map.primary_key_prefixes == table_map.primary_key_prefixes
+        {
+            previous.table_map.table_id = table_map.table_id;
+            return None;
+        }
+
+        let before = describe_columns(&previous.table_map);
+        let after = describe_columns(table_map);
+        let changed_columns = (0..before.len().max(after.len()))
+            .filter(|&i| before.get(i) != after.get(i))
+            .map(|i| ColumnChange {
+                position: i + 1,
+                before: before.get(i).cloned(),
+                after: after.get(i).cloned(),
+            })
+            .collect();
+
+        let change = SchemaChange {
+            position,
+            database: table_map.database_name.clone(),
+            table: table_map.table_name.clone(),
+            version: previous.version + 1,
+            previous_table_id: previous.table_map.table_id,
+            table_id: table_map.table_id,
+            previous_column_count: before.len(),
+            column_count: after.len(),
+            changed_columns,
+            primary_key_changed: previous.table_map.primary_key != table_map.primary_key
+                || previous.table_map.primary_key_prefixes != table_map.primary_key_prefixes,
+        };
+
+        previous.version = change.version;
+        previous.table_map = table_map.clone();
+
+        Some(change)
+    }
+
+    /// 表的定义的版本，还没有见过的表是1
+    pub fn version(&self, database: &str, table: &str) -> u32 {
+        self.tables
+            .get(&(database.to_string(), table.to_string()))
+            .map_or(1, |v| v.version)
+    }
+}
+
+// 每个字段的定义，例如`name` VARCHAR(20) NOT NULL，没有字段名时只有类型
+fn describe_columns(table_map: &EventBodyTypeCode19) -> Vec<String> {
+    let names = metadata_column_names(table_map);
+
+    column_type_definitions(table_map)
+        .into_iter()
+        .enumerate()
+        .map(|(i, definition)| {
+            let nullable = match table_map.columns.get(i).map(|v| v.nullable) {
+                Some(false) => " NOT NULL",
+                _ => "",
+            };
+
+            match names.as_ref().and_then(|v| v.get(i)) {
+                Some(name) => format!("{} {}{}", quote_identifier(name), definition, nullable),
+                None => format!("{}{}", definition, nullable),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn orders(table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new("shop", "orders")
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true)
+    }
+
+    fn changes(binlog: &[u8]) -> Vec<SchemaChange> {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut schema_tracker = SchemaTracker::new();
+        let mut changes = Vec::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            changes.extend(schema_tracker.process(position, body.as_ref()));
+            position = header.next_event_position as u64;
+        }
+
+        changes
+    }
+
+    fn insert(table: &TableMapBuilder, values: Vec<ColumnValue>) -> RowsEventBuilder {
+        RowsEventBuilder::insert(table).row(values)
+    }
+
+    #[test]
+    fn alter_between_two_inserts() {
+        let before = orders(70);
+        // ALTER TABLE之后table_id变化，多了一个字段，第二个字段变长
+        let after = TableMapBuilder::new("shop", "orders")
+            .table_id(71)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &128u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::LongLong, &[]);
+
+        let mut binlog = BinlogBuilder::new();
+        let alter =
+            "ALTER TABLE orders MODIFY note VARCHAR(128), ADD COLUMN amount BIGINT NOT NULL";
+        binlog = binlog.event(&before).event(&insert(
+            &before,
+            vec![ColumnValue::Int(1), ColumnValue::Null],
+        ));
+        binlog = binlog.event(&QueryEventBuilder::new("shop", alter));
+        let table_map_position = binlog.position();
+        let binlog = binlog
+            .event(&after)
+            .event(&insert(
+                &after,
+                vec![ColumnValue::Int(2), ColumnValue::Null, ColumnValue::Int(3)],
+            ))
+            // 同样的定义再出现不算变化
+            .event(&after)
+            .build();
+
+        let changes = changes(&binlog);
+        assert_eq!(changes.len(), 1);
+
+        let change = &changes[0];
+        assert_eq!(change.position, table_map_position);
+        assert_eq!(
+            (change.database.as_str(), change.table.as_str()),
+            ("shop", "orders")
+        );
+        assert_eq!(change.version, 2);
+        assert_eq!((change.previous_table_id, change.table_id), (70, 71));
+        assert_eq!((change.previous_column_count, change.column_count), (2, 3));
+        assert_eq!(
+            change
+                .changed_columns
+                .iter()
+                .map(|v| v.position)
+                .collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert!(change.changed_columns[1].before.is_none());
+        assert!(!change.primary_key_changed);
+        assert!(change.to_string().starts_with(&format!(
+            "warning: the schema of `shop`.`orders` changed at {} (version 2): 2 -> 3 columns, #2 ",
+            table_map_position
+        )));
+    }
+
+    #[test]
+    fn new_table_id_with_the_same_definition() {
+        let binlog = BinlogBuilder::new()
+            .event(&orders(70))
+            .event(&orders(71))
+            .event(
+                &TableMapBuilder::new("shop", "customers")
+                    .table_id(72)
+                    .column(FieldType::Long, &[]),
+            )
+            .build();
+        assert!(changes(&binlog).is_empty());
+
+        // 主键变化也算
+        let with_key = orders(72).optional_metadata(&[8, 1, 0]);
+        let binlog = BinlogBuilder::new()
+            .event(&orders(70))
+            .event(&with_key)
+            .build();
+        let changes = changes(&binlog);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].changed_columns.is_empty());
+        assert!(changes[0].primary_key_changed);
+        assert!(changes[0]
+            .to_string()
+            .ends_with("2 -> 2 columns, primary key changed"));
+    }
+
+    #[test]
+    fn versions_per_table() {
+        let mut schema_tracker = SchemaTracker::new();
+        let longer = orders(71).column(FieldType::Long, &[]);
+        for table in [orders(70), longer, orders(72)] {
+            schema_tracker.process(4, table.table_info().unwrap().as_ref());
+        }
+
+        assert_eq!(schema_tracker.version("shop", "orders"), 3);
+        assert_eq!(schema_tracker.version("shop", "customers"), 1);
+    }
+}
@@ -1,18 +1,27 @@
 use std::{
     collections::HashMap,
-    fs::File,
     io::{Read, Seek},
+    ops::Range,
+    sync::Arc,
 };
 
+use crate::collation::collation_name;
+use crate::error::ConsumedLengthMismatch;
+use crate::field_type::FieldType;
 use crate::model::*;
+use crate::table_structure::{apply_optional_metadata, column_descriptors, split_column_metadata};
+use crate::trace;
 use crate::util::*;
 
 const EVENT_HEADER_LENGTH: usize = 19;
 
+/// CRC32校验和的长度，第一个FDE之前按有校验和处理
+pub const CHECKSUM_LENGTH: usize = 4;
+
 type BoxedError = Box<dyn std::error::Error>;
 
-pub fn get_event_header(file: &mut File, offset: u64) -> Result<EventHeader, BoxedError> {
-    let mut buffer = [0u8; EVENT_HEADER_LENGTH as usize];
+pub fn get_event_header<R: Read + Seek>(file: &mut R, offset: u64) -> Result<EventHeader, BoxedError> {
+    let mut buffer = [0u8; EVENT_HEADER_LENGTH];
 
     file.seek(std::io::SeekFrom::Start(offset))?;
 
@@ -30,85 +39,278 @@ pub fn get_event_header(file: &mut File, offset: u64) -> Result<EventHeader, Box
     Ok(event_header)
 }
 
-pub fn get_event_body(
-    file: &mut File,
-    offset: u64,
-    event_length: u32,
-    type_code: u8,
-    table_structs: &mut HashMap<u64, EventBodyTypeCode19>,
+/// 读取完整的事件原始字节，包括header和校验和
+pub fn get_event_raw<R: Read + Seek>(file: &mut R, offset: u64, event_length: u32) -> Result<Vec<u8>, BoxedError> {
+    let mut buffer = vec![0u8; event_length as usize];
+
+    file.seek(std::io::SeekFrom::Start(offset))?;
+
+    file.read_exact(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+/// 行事件body开头6个字节的table_id，offset是body的位置
+pub fn get_rows_event_table_id<R: Read + Seek>(file: &mut R, offset: u64) -> Result<u64, BoxedError> {
+    let mut buffer = [0u8; 8];
+
+    file.seek(std::io::SeekFrom::Start(offset))?;
+
+    file.read_exact(&mut buffer[0..6])?;
+
+    Ok(u64::from_le_bytes(buffer))
+}
+
+/// position是事件的起始位置，也就是header的位置，body的长度由header中的event_length得到
+/// checksum_length是body最后校验和的长度，由之前的FDE决定（见EventBodyTypeCode15::checksum_length），
+/// 解码器拿到的是去掉校验和之后的部分；FDE总是带着校验算法和校验和，不受checksum_length影响
+/// 有固定结构的事件解码之后检查是否正好用完了这些字节，不一致时返回ConsumedLengthMismatch；
+/// query、rotate、format description、table map、annotate rows和行事件的最后一部分一直读到结尾，不需要检查
+pub fn get_event_body<R: Read + Seek>(
+    file: &mut R,
+    position: u64,
+    header: &EventHeader,
+    table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+    checksum_length: usize,
 ) -> Result<Box<dyn EventBody>, BoxedError> {
-    let body_length = (event_length - EVENT_HEADER_LENGTH as u32) as usize;
+    let type_code = header.type_code;
+    let buffer = match type_code {
+        15 => read_event_body(file, position, header)?,
+        _ => read_event_data(file, position, header, checksum_length)?,
+    };
+
+    let event_body: Result<Box<dyn EventBody>, BoxedError> = match type_code {
+        2 => deal_type_code_2(buffer),
+        5 => deal_type_code_5(buffer),
+        4 => deal_type_code_4(buffer),
+        13 => deal_type_code_13(buffer),
+        14 => deal_type_code_14(buffer),
+        15 => deal_type_code_15(buffer),
+        16 => deal_type_code_16(buffer),
+        19 => deal_type_code_19(buffer, table_structs),
+        23..=25 => deal_type_code_23_to_25(buffer, type_code, table_structs),
+        30..=32 => deal_type_code_30_to_32(buffer, type_code, table_structs),
+        33 | 34 => deal_type_code_33(buffer),
+        35 => deal_type_code_35(buffer),
+        38 => deal_type_code_38(buffer),
+        160 => deal_type_code_160(buffer),
+        161 => deal_type_code_161(buffer),
+        162 => deal_type_code_162(buffer),
+        163 => deal_type_code_163(buffer),
+        164 => deal_type_code_164(buffer),
+        _ => Ok(Box::new(EventBodyTypeSkip(type_code))),
+    };
+
+    let event_body = event_body?;
+
+    Ok(event_body)
+}
+
+/// position处的事件的body，包括最后的checksum
+pub fn read_event_body<R: Read + Seek>(
+    file: &mut R,
+    position: u64,
+    header: &EventHeader,
+) -> Result<Vec<u8>, BoxedError> {
+    let body_length = (header.event_length as usize)
+        .checked_sub(EVENT_HEADER_LENGTH)
+        .ok_or_else(|| {
+            MyError(format!(
+                "the event length {} is shorter than the event header",
+                header.event_length
+            ))
+        })?;
     let mut buffer = vec![0u8; body_length];
 
+    let offset = position + EVENT_HEADER_LENGTH as u64;
     file.seek(std::io::SeekFrom::Start(offset))?;
 
     file.read_exact(&mut buffer)?;
 
-    if cfg!(feature = "test") {
-        let event_body: Result<Box<dyn EventBody>, BoxedError> = match type_code {
-            2 => deal_type_code_2(buffer),
-            5 => deal_type_code_5(buffer),
-            4 => deal_type_code_4(buffer),
-            15 => deal_type_code_15(buffer),
-            16 => deal_type_code_16(buffer),
-            23..=25 => deal_type_code_23_to_25(buffer, type_code, table_structs),
-            160 => deal_type_code_160(buffer),
-            163 => deal_type_code_163(buffer),
-            19 => deal_type_code_19(buffer, table_structs),
-            161 => deal_type_code_161(buffer),
-            162 => deal_type_code_162(buffer),
-            _ => Ok(Box::new(EventBodyTypeSkip(type_code))),
-        };
+    Ok(buffer)
+}
 
-        let event_body = event_body?;
-
-        Ok(event_body)
-    } else {
-        let event_body: Result<Box<dyn EventBody>, BoxedError> = match type_code {
-            2 => deal_type_code_2(buffer),
-            5 => deal_type_code_5(buffer),
-            4 => deal_type_code_4(buffer),
-            13 => deal_type_code_13(buffer),
-            14 => deal_type_code_14(buffer),
-            15 => deal_type_code_15(buffer),
-            16 => deal_type_code_16(buffer),
-            19 => deal_type_code_19(buffer, table_structs),
-            23..=25 => deal_type_code_23_to_25(buffer, type_code, table_structs),
-            38 => deal_type_code_38(buffer),
-            160 => deal_type_code_160(buffer),
-            161 => deal_type_code_161(buffer),
-            162 => deal_type_code_162(buffer),
-            163 => deal_type_code_163(buffer),
-            164 => deal_type_code_164(buffer),
-            _ => Ok(Box::new(EventBodyTypeSkip(type_code))),
-        };
+/// 和read_event_body一样，但去掉最后checksum_length个字节的checksum，交给解码器的是这一部分
+pub fn read_event_data<R: Read + Seek>(
+    file: &mut R,
+    position: u64,
+    header: &EventHeader,
+    checksum_length: usize,
+) -> Result<Vec<u8>, BoxedError> {
+    strip_checksum(read_event_body(file, position, header)?, checksum_length)
+}
+
+/// 去掉body最后checksum_length个字节的checksum，body比checksum还短时返回错误
+pub fn strip_checksum(mut buffer: Vec<u8>, checksum_length: usize) -> Result<Vec<u8>, BoxedError> {
+    let data_length = buffer.len().checked_sub(checksum_length).ok_or_else(|| {
+        MyError(format!(
+            "the event body of {} bytes is shorter than its {} bytes checksum",
+            buffer.len(),
+            checksum_length
+        ))
+    })?;
+    buffer.truncate(data_length);
+
+    Ok(buffer)
+}
 
-        let event_body = event_body?;
+/// 还没有FDE时，按事件最后4个字节是不是前面所有字节的CRC32判断有没有校验和，
+/// 用于服务端在FDE之前发送的rotate，event是包括header的整个事件
+pub fn detect_checksum_length(event: &[u8]) -> usize {
+    let data_length = event.len().saturating_sub(CHECKSUM_LENGTH);
+    let has_checksum = data_length >= EVENT_HEADER_LENGTH
+        && event[data_length..] == crc32fast::hash(&event[..data_length]).to_le_bytes();
 
-        Ok(event_body)
+    match has_checksum {
+        true => CHECKSUM_LENGTH,
+        false => 0,
     }
 }
 
+/// FDE之后的事件的checksum长度，其他事件不改变checksum_length，在按顺序解码的循环中调用
+pub fn update_checksum_length(checksum_length: &mut usize, body: &dyn EventBody) {
+    if let Some(format_description) = body.downcast_ref::<EventBodyTypeCode15>() {
+        *checksum_length = format_description.checksum_length();
+    }
+}
+
+/// buffer中offset开始的length个字节，body不够长时和check_consumed_length一样返回ConsumedLengthMismatch而不是panic
+fn read_bytes(buffer: &[u8], type_code: u8, offset: usize, length: usize) -> Result<&[u8], BoxedError> {
+    let end = offset.saturating_add(length);
+
+    buffer.get(offset..end).ok_or_else(|| {
+        Box::new(ConsumedLengthMismatch {
+            type_code,
+            body_length: buffer.len(),
+            consumed: end,
+        }) as BoxedError
+    })
+}
+
+/// buffer中offset之后的所有字节，用于lenenc这些长度由内容决定的字段
+fn read_rest(buffer: &[u8], type_code: u8, offset: usize) -> Result<&[u8], BoxedError> {
+    read_bytes(buffer, type_code, offset, buffer.len().saturating_sub(offset))
+}
+
+/// 解码器用掉了consumed个字节，去掉checksum之后的body必须正好用完，多了或者少了都说明解析的位置不对
+fn check_consumed_length(type_code: u8, buffer: &[u8], consumed: usize) -> Result<(), BoxedError> {
+    let body_length = buffer.len();
+
+    if consumed != body_length {
+        return Err(Box::new(ConsumedLengthMismatch {
+            type_code,
+            body_length,
+            consumed,
+        }));
+    }
+
+    Ok(())
+}
+
+/// 和get_event_body一样，但行事件中超过max_value_length的VARCHAR和BLOB只保留前面的部分，用于`--max-value-length`
+pub fn get_event_body_with_value_limit<R: Read + Seek>(
+    file: &mut R,
+    position: u64,
+    header: &EventHeader,
+    table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+    checksum_length: usize,
+    max_value_length: Option<usize>,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    let value_options = ValueOptions {
+        max_value_length,
+        ..Default::default()
+    };
+
+    get_event_body_with_value_options(
+        file,
+        position,
+        header,
+        table_structs,
+        checksum_length,
+        value_options,
+    )
+}
+
+/// 和get_event_body一样，但行事件按value_options解码字段的值，见parse_column_data_for_row_event
+pub fn get_event_body_with_value_options<R: Read + Seek>(
+    file: &mut R,
+    position: u64,
+    header: &EventHeader,
+    table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+    checksum_length: usize,
+    value_options: ValueOptions,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    if !matches!(header.type_code, 23..=25 | 30..=32) || value_options == ValueOptions::default() {
+        return get_event_body(file, position, header, table_structs, checksum_length);
+    }
+
+    let buffer = read_event_data(file, position, header, checksum_length)?;
+
+    let (buffer, type_code) = rows_event_v2_to_v1(buffer, header.type_code)?;
+
+    deal_type_code_23_to_25_with_value_options(buffer, type_code, table_structs, value_options)
+}
+
+/// 和get_event_body一样，但行事件只计算行数，不解码字段的值
+/// 用于只需要表和行数的统计，需要字段值时可以调用decode_rows
+pub fn get_event_body_without_row_values<R: Read + Seek>(
+    file: &mut R,
+    position: u64,
+    header: &EventHeader,
+    table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
+    checksum_length: usize,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    if !matches!(header.type_code, 23..=25 | 30..=32) {
+        return get_event_body(file, position, header, table_structs, checksum_length);
+    }
+
+    let buffer = read_event_data(file, position, header, checksum_length)?;
+
+    let (buffer, type_code) = rows_event_v2_to_v1(buffer, header.type_code)?;
+
+    deal_type_code_23_to_25_without_values(buffer, type_code, table_structs)
+}
+
 pub fn deal_type_code_15(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let event_body = EventBodyTypeCode15 {
-        binlog_version: { u16::from_le_bytes(buffer[0..2].try_into()?) },
+        binlog_version: { u16::from_le_bytes(read_bytes(&buffer, 15, 0, 2)?.try_into()?) },
         server_version: {
-            String::from_utf8(buffer[2..52].try_into()?)?
+            String::from_utf8(read_bytes(&buffer, 15, 2, 50)?.to_vec())?
                 .trim_end_matches(char::from(0))
                 .to_string()
         },
-        create_timestamp: { u32::from_le_bytes(buffer[52..56].try_into()?) },
-        header_length: u8::from_be_bytes(buffer[56..57].try_into()?),
+        create_timestamp: {
+            u32::from_le_bytes(read_bytes(&buffer, 15, 52, 4)?.try_into()?)
+        },
+        header_length: u8::from_be_bytes(read_bytes(&buffer, 15, 56, 1)?.try_into()?),
+        // 最后是1个字节的校验算法和4个字节的校验和
+        post_header_lengths: buffer
+            .get(57..buffer.len().saturating_sub(5))
+            .unwrap_or_default()
+            .to_vec(),
+        checksum_algorithm: buffer
+            .len()
+            .checked_sub(5)
+            .and_then(|v| buffer.get(v))
+            .copied()
+            .unwrap_or_default(),
     };
 
+    trace::field("binlog_version", 0, 2);
+    trace::field("server_version", 2, 52);
+    trace::field("create_timestamp", 52, 56);
+    trace::field("header_length", 56, 57);
+    if buffer.len() >= 62 {
+        trace::field("post_header_lengths", 57, buffer.len() - 5);
+        trace::field("checksum_algorithm", buffer.len() - 5, buffer.len() - 4);
+    }
+
     Ok(Box::new(event_body))
 }
 
 pub fn deal_type_code_160(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
-    let buffer_length = buffer.len();
-    // 这里做掉的4byte是CRC32
     let event_body = EventBodyTypeCode160 {
-        sql: { String::from_utf8(buffer[0..buffer_length - 4].try_into()?)? },
+        sql: String::from_utf8(buffer)?,
     };
 
     Ok(Box::new(event_body))
@@ -117,30 +319,35 @@ pub fn deal_type_code_160(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedEr
 pub fn deal_type_code_163(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
-    let number_of_gtids = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+    let number_of_gtids = u32::from_le_bytes(read_bytes(&buffer, 163, offset, 4)?.try_into()?);
     offset += 4;
 
     let mut gtids = Vec::new();
 
-    for _ in 0..number_of_gtids {
+    // 高4位是flags，只有低28位是GTID的个数
+    for _ in 0..number_of_gtids & 0x0fff_ffff {
         gtids.push(GTID {
-            replication_domain_id: { u32::from_le_bytes(buffer[offset..offset + 4].try_into()?) },
+            replication_domain_id: {
+                u32::from_le_bytes(read_bytes(&buffer, 163, offset, 4)?.try_into()?)
+            },
             server_id: {
                 offset += 4;
-                u32::from_le_bytes(buffer[offset..offset + 4].try_into()?)
+                u32::from_le_bytes(read_bytes(&buffer, 163, offset, 4)?.try_into()?)
             },
             gtid_sequence: {
                 offset += 4;
-                u64::from_le_bytes(buffer[offset..offset + 8].try_into()?)
+                u64::from_le_bytes(read_bytes(&buffer, 163, offset, 8)?.try_into()?)
             },
         });
 
         offset += 8;
     }
 
+    check_consumed_length(163, &buffer, offset)?;
+
     let event_body = EventBodyTypeCode163 {
-        number_of_gtids: number_of_gtids,
-        gtids: gtids,
+        number_of_gtids,
+        gtids,
     };
 
     Ok(Box::new(event_body))
@@ -148,122 +355,192 @@ pub fn deal_type_code_163(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedEr
 
 pub fn deal_type_code_19(
     buffer: Vec<u8>,
-    table_structs: &mut HashMap<u64, EventBodyTypeCode19>,
+    table_structs: &mut HashMap<u64, Arc<EventBodyTypeCode19>>,
 ) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
-    let mut buffer_for_table_name = buffer[offset..offset + 6].to_vec();
+    let mut buffer_for_table_name = read_bytes(&buffer, 19, offset, 6)?.to_vec();
     buffer_for_table_name.splice(
         buffer_for_table_name.len()..buffer_for_table_name.len(),
         vec![0, 0],
     );
 
     let table_id = u64::from_le_bytes(buffer_for_table_name.as_slice().try_into()?);
+    trace::field("table_id", offset, offset + 6);
     offset += 6;
 
-    let reserved_for_future_use = u16::from_le_bytes(buffer[offset..offset + 2].try_into()?);
+    let reserved_for_future_use =
+        u16::from_le_bytes(read_bytes(&buffer, 19, offset, 2)?.try_into()?);
+    trace::field("reserved_for_future_use", offset, offset + 2);
     offset += 2;
 
-    let database_name_length = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    let database_name_length =
+        u8::from_le_bytes(read_bytes(&buffer, 19, offset, 1)?.try_into()?);
+    trace::field("database_name_length", offset, offset + 1);
     offset += 1;
 
-    let database_name =
-        String::from_utf8(buffer[offset..offset + database_name_length as usize].to_vec())?;
+    let database_name = String::from_utf8(
+        read_bytes(&buffer, 19, offset, database_name_length as usize)?.to_vec(),
+    )?;
+    trace::field(
+        "database_name",
+        offset,
+        offset + database_name_length as usize + 1,
+    );
     offset += database_name_length as usize;
     // 这里多加一个1是因为他是以null结尾的
     offset += 1;
 
-    let table_name_length = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    let table_name_length = u8::from_le_bytes(read_bytes(&buffer, 19, offset, 1)?.try_into()?);
+    trace::field("table_name_length", offset, offset + 1);
     offset += 1;
 
     let table_name =
-        String::from_utf8(buffer[offset..offset + table_name_length as usize].to_vec())?;
+        String::from_utf8(read_bytes(&buffer, 19, offset, table_name_length as usize)?.to_vec())?;
+    trace::field(
+        "table_name",
+        offset,
+        offset + table_name_length as usize + 1,
+    );
     offset += table_name_length as usize;
     // 这里多加一个1是因为他是以null结尾的
     offset += 1;
 
-    let (number_of_columns, skip_bytes) = parse_lenenc(&buffer[offset..])?;
+    let (number_of_columns, skip_bytes) = parse_lenenc(read_rest(&buffer, 19, offset)?)?;
+    trace::field("number_of_columns", offset, offset + skip_bytes as usize);
     offset += skip_bytes as usize;
 
-    let column_types = buffer[offset..offset + number_of_columns as usize].to_vec();
+    let column_types = read_bytes(&buffer, 19, offset, number_of_columns as usize)?.to_vec();
+    trace::field("column_types", offset, offset + column_types.len());
     offset += number_of_columns as usize;
 
-    let mut column_types_string_for_human = Vec::new();
-    let column_types_mapping = get_field_types_mapping()?;
-    for column_type in &column_types {
-        column_types_string_for_human
-            .push(column_types_mapping.get(column_type).unwrap().to_string());
-    }
-
-    let (number_of_metadata_block, skip_bytes) = parse_lenenc(&buffer[offset..])?;
+    let (number_of_metadata_block, skip_bytes) =
+        parse_lenenc(read_rest(&buffer, 19, offset)?)?;
+    trace::field(
+        "number_of_metadata_block",
+        offset,
+        offset + skip_bytes as usize,
+    );
     offset += skip_bytes as usize;
 
-    let metadata_block = buffer[offset..offset + number_of_metadata_block as usize].to_vec();
+    let metadata_block =
+        read_bytes(&buffer, 19, offset, number_of_metadata_block as usize)?.to_vec();
+    trace::field("metadata_block", offset, offset + metadata_block.len());
     offset += number_of_metadata_block as usize;
 
-    let mut metadata_block_string_for_human = Vec::new();
-    let mut metadata_block_data_raw = Vec::new();
-    let metadata_block_mapping: HashMap<u8, u8> = get_metadata_block_mapping()?;
-    let mut metadata_block_offset = 0;
-    for column_type in &column_types {
-        let (metadata_block_for_human, metadata_block_raw, skip) = parse_metadata_block(
-            &metadata_block_mapping,
-            &column_types_mapping,
-            &metadata_block,
-            metadata_block_offset,
-            *column_type,
-        )?;
-
-        if metadata_block_for_human.is_empty() {
-            continue;
-        }
-
-        metadata_block_offset += skip;
-
-        metadata_block_string_for_human.push(metadata_block_for_human);
-        metadata_block_data_raw.push(metadata_block_raw);
-    }
-
-    let columns_can_be_null_byte_vec_length = (number_of_columns + 7) / 8;
+    let columns_can_be_null_byte_vec_length = number_of_columns.div_ceil(8);
 
     let columns_can_be_null = parse_bitmap(
-        &buffer[offset..offset + columns_can_be_null_byte_vec_length as usize],
+        read_bytes(&buffer, 19, offset, columns_can_be_null_byte_vec_length as usize)?,
         number_of_columns,
     );
+    trace::field(
+        "columns_can_be_null",
+        offset,
+        offset + columns_can_be_null_byte_vec_length as usize,
+    );
+
+    let columns = table_map_columns(&column_types, &metadata_block, &columns_can_be_null)?;
+
+    // 旧的按下标对应的数组从columns得到
+    let column_types_string_for_human = columns.iter().map(|v| v.type_name().to_string()).collect();
+    let metadata_block_string_for_human = columns
+        .iter()
+        .filter(|v| !v.metadata_raw.is_empty())
+        .map(|v| v.metadata_for_human())
+        .collect();
+    let metadata_block_data_raw = columns
+        .iter()
+        .filter(|v| !v.metadata_raw.is_empty())
+        .map(|v| v.metadata_raw.clone())
+        .collect();
 
     let optional_metadata_block = buffer[offset..].to_vec();
+    // optional_metadata_block中还有null bitmap，这里只标出真正的可选元数据
+    trace::field(
+        "optional_metadata_block",
+        offset + columns_can_be_null_byte_vec_length as usize,
+        buffer.len(),
+    );
 
-    let event_body = EventBodyTypeCode19 {
-        table_id: table_id,
-        reserved_for_future_use: reserved_for_future_use,
-        database_name_length: database_name_length,
-        database_name: database_name,
-        table_name_length: table_name_length,
-        table_name: table_name,
-        number_of_columns: number_of_columns,
-        column_types: column_types,
-        column_types_string_for_human: column_types_string_for_human,
-        number_of_metadata_block: number_of_metadata_block,
-        metadata_block: metadata_block,
-        metadata_block_string_for_human: metadata_block_string_for_human,
-        metadata_block_data_raw: metadata_block_data_raw,
-        columns_can_be_null: columns_can_be_null,
-        optional_metadata_block: optional_metadata_block,
+    #[allow(deprecated)]
+    let mut event_body = EventBodyTypeCode19 {
+        table_id,
+        reserved_for_future_use,
+        database_name_length,
+        database_name,
+        table_name_length,
+        table_name,
+        number_of_columns,
+        column_types,
+        column_types_string_for_human,
+        number_of_metadata_block,
+        metadata_block,
+        metadata_block_string_for_human,
+        metadata_block_data_raw,
+        columns_can_be_null,
+        optional_metadata_block,
+        columns,
+        column_names: None,
+        primary_key: None,
+        primary_key_prefixes: None,
+        column_descriptors: Vec::new(),
+        column_index: Default::default(),
     };
 
-    let a = table_structs.entry(table_id).or_insert(event_body.clone());
-    *a = event_body.clone();
+    // 可选元数据中的字符集、unsigned、字段名和主键
+    apply_optional_metadata(&mut event_body);
+
+    // 每个字段的解码方式只在这里计算一次
+    event_body.column_descriptors = column_descriptors(&event_body);
+    event_body.column_index = Arc::new(ColumnIndex::new(&event_body));
+
+    // 返回的事件和table_structs共用同一个table map
+    let event_body = Arc::new(event_body);
+    table_structs.insert(table_id, event_body.clone());
 
     Ok(Box::new(event_body))
 }
 
+/// 按字段顺序得到每个字段的定义，字符集、unsigned和字段名由apply_optional_metadata填入
+pub fn table_map_columns(
+    column_types: &[u8],
+    metadata_block: &[u8],
+    nullable: &Bitmap,
+) -> Result<Vec<ColumnDef>, BoxedError> {
+    let field_types = column_types
+        .iter()
+        .map(|v| FieldType::try_from(*v))
+        .collect::<Result<Vec<FieldType>, MyError>>()?;
+    let metadata = split_column_metadata(&field_types, metadata_block);
+
+    let mut columns = Vec::with_capacity(field_types.len());
+    for (i, (field_type, metadata_raw)) in field_types.into_iter().zip(metadata).enumerate() {
+        columns.push(ColumnDef {
+            field_type,
+            metadata_description: describe_column_metadata(field_type, &metadata_raw),
+            metadata_raw,
+            nullable: nullable.get(i).unwrap_or(true),
+            charset: None,
+            unsigned: None,
+            name: None,
+        });
+    }
+
+    Ok(columns)
+}
+
 pub fn deal_type_code_16(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let offset = 0;
 
-    let xid_transaction_number = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    // 先检查长度，截断的事件返回错误而不是panic
+    check_consumed_length(16, &buffer, offset + 8)?;
+
+    let xid_transaction_number = u64::from_le_bytes(buffer[offset..offset + 8].try_into()?);
+    trace::field("xid_transaction_number", offset, offset + 8);
 
     let event_body = EventBodyTypeCode16 {
-        xid_transaction_number: xid_transaction_number,
+        xid_transaction_number,
     };
 
     Ok(Box::new(event_body))
@@ -272,55 +549,75 @@ pub fn deal_type_code_16(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedErr
 pub fn deal_type_code_2(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
-    let id_of_thread = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+    let id_of_thread = u32::from_le_bytes(read_bytes(&buffer, 2, offset, 4)?.try_into()?);
+    trace::field("id_of_thread", offset, offset + 4);
     offset += 4;
 
-    let execute_time = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+    let execute_time = u32::from_le_bytes(read_bytes(&buffer, 2, offset, 4)?.try_into()?);
+    trace::field("execute_time", offset, offset + 4);
     offset += 4;
 
-    let length_of_database_name = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    let length_of_database_name = u8::from_le_bytes(read_bytes(&buffer, 2, offset, 1)?.try_into()?);
+    trace::field("length_of_database_name", offset, offset + 1);
     offset += 1;
 
-    let error_code = u16::from_le_bytes(buffer[offset..offset + 2].try_into()?);
+    let error_code = u16::from_le_bytes(read_bytes(&buffer, 2, offset, 2)?.try_into()?);
+    trace::field("error_code", offset, offset + 2);
     offset += 2;
 
     let length_of_status_variable_block =
-        u16::from_le_bytes(buffer[offset..offset + 2].try_into()?);
+        u16::from_le_bytes(read_bytes(&buffer, 2, offset, 2)?.try_into()?);
+    trace::field("length_of_status_variable_block", offset, offset + 2);
     offset += 2;
 
-    let status_variables;
-    let status_variables_string_vec_for_human;
-    if length_of_status_variable_block > 0 {
-        status_variables =
-            buffer[offset..offset + length_of_status_variable_block as usize].to_vec();
-        status_variables_string_vec_for_human = parse_status_variables(&status_variables)?;
-        offset += length_of_status_variable_block as usize;
-    } else {
-        status_variables = Vec::new();
-        status_variables_string_vec_for_human = Vec::new();
-    }
+    // 长度损坏时返回错误而不是panic，`--skip-errors`时可以跳过这个事件
+    let truncated = || MyError("the query event is truncated".to_string());
+
+    let status_variables = buffer
+        .get(offset..offset + length_of_status_variable_block as usize)
+        .ok_or_else(truncated)?
+        .to_vec();
+    trace::field("status_variables", offset, offset + status_variables.len());
+    let typed_status_variables = decode_status_variables(&status_variables)?;
+    let status_variables_string_vec_for_human = typed_status_variables
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    offset += length_of_status_variable_block as usize;
 
     // 这里多加1是因为尾部的\0
     let database_name = String::from_utf8(
-        buffer[offset..offset + length_of_database_name as usize + 1].try_into()?,
+        buffer
+            .get(offset..offset + length_of_database_name as usize + 1)
+            .ok_or_else(truncated)?
+            .to_vec(),
     )?
     .trim_end_matches(char::from(0))
     .to_string();
+    trace::field(
+        "database_name",
+        offset,
+        offset + length_of_database_name as usize + 1,
+    );
     offset += length_of_database_name as usize + 1;
 
-    // 这里多减1是因为尾部的EOF
-    let sql = String::from_utf8(buffer[offset..buffer.len() - 5].try_into()?)?.to_string();
+    // sql没有结尾的\0，一直到body的结尾
+    let sql = String::from_utf8(buffer.get(offset..).ok_or_else(truncated)?.to_vec())?;
+    trace::field("sql", offset, buffer.len());
 
+    #[allow(deprecated)]
     let event_body = EventBodyTypeCode2 {
-        id_of_thread: id_of_thread,
-        execute_time: execute_time,
-        length_of_database_name: length_of_database_name,
-        error_code: error_code,
-        length_of_status_variable_block: length_of_status_variable_block,
-        status_variables: status_variables,
-        status_variables_string_vec_for_human: status_variables_string_vec_for_human,
-        database_name: database_name,
-        sql: sql,
+        id_of_thread,
+        execute_time,
+        length_of_database_name,
+        error_code,
+        length_of_status_variable_block,
+        status_variables,
+        status_variables_string_vec_for_human,
+        hrnow: get_status_variable_hrnow(&typed_status_variables),
+        typed_status_variables,
+        database_name,
+        sql,
     };
 
     Ok(Box::new(event_body))
@@ -329,22 +626,138 @@ pub fn deal_type_code_2(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedErro
 pub fn deal_type_code_161(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
-    let log_filename_length = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+    let log_filename_length =
+        u32::from_le_bytes(read_bytes(&buffer, 161, offset, 4)?.try_into()?);
     offset += 4;
 
     let log_filename =
-        String::from_utf8(buffer[offset..offset + log_filename_length as usize].try_into()?)?
+        String::from_utf8(read_bytes(&buffer, 161, offset, log_filename_length as usize)?.to_vec())?
             .trim_end_matches(char::from(0))
             .to_string();
+    offset += log_filename_length as usize;
+
+    check_consumed_length(161, &buffer, offset)?;
 
     let event_body = EventBodyTypeCode161 {
-        log_filename_length: log_filename_length,
-        log_filename: log_filename,
+        log_filename_length,
+        log_filename,
+    };
+
+    Ok(Box::new(event_body))
+}
+
+/// MySQL的gtid和anonymous gtid，MySQL 5.7开始gno之后有logical clock
+pub fn deal_type_code_33(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
+    let flags = read_bytes(&buffer, 33, 0, 1)?[0];
+    let uuid = format_uuid(read_bytes(&buffer, 33, 1, 16)?);
+    let gno = i64::from_le_bytes(read_bytes(&buffer, 33, 17, 8)?.try_into()?);
+    let mut offset = 25;
+
+    // 类型2的logical clock是last_committed和sequence_number
+    let (last_committed, sequence_number) = match buffer.get(25) {
+        Some(2) if buffer.len() >= 42 => {
+            offset = 42;
+            (
+                Some(i64::from_le_bytes(buffer[26..34].try_into()?)),
+                Some(i64::from_le_bytes(buffer[34..42].try_into()?)),
+            )
+        }
+        _ => (None, None),
+    };
+
+    // MySQL 8.0之后的字段，每个都是有剩下的字节时才有，这里只跳过：
+    // 7字节的commit timestamp（最高位为1时后面还有7字节的original commit timestamp）、
+    // lenenc的transaction length、4字节的server version（最高位为1时后面还有4字节的original server version）、
+    // 8.0.33开始的8字节commit group ticket
+    let body_length = buffer.len();
+    if offset < body_length {
+        let immediate_commit_timestamp_high = buffer.get(offset + 6).copied().unwrap_or_default();
+        offset += match immediate_commit_timestamp_high & 0x80 {
+            0 => 7,
+            _ => 14,
+        };
+    }
+    if offset < body_length {
+        let (_, skip) = parse_lenenc(read_rest(&buffer, 33, offset)?)?;
+        offset += skip as usize;
+    }
+    if offset < body_length {
+        let immediate_server_version_high = buffer.get(offset + 3).copied().unwrap_or_default();
+        offset += match immediate_server_version_high & 0x80 {
+            0 => 4,
+            _ => 8,
+        };
+    }
+    if offset < body_length {
+        offset += 8;
+    }
+
+    check_consumed_length(33, &buffer, offset)?;
+
+    let event_body = EventBodyTypeCode33 {
+        flags,
+        uuid,
+        gno,
+        last_committed,
+        sequence_number,
+    };
+
+    Ok(Box::new(event_body))
+}
+
+/// MySQL的previous gtids，每个server uuid有若干个区间，区间的结束是不包括在内的
+pub fn deal_type_code_35(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
+    let mut offset = 0;
+
+    let number_of_sids = u64::from_le_bytes(read_bytes(&buffer, 35, offset, 8)?.try_into()?);
+    offset += 8;
+
+    let mut sids = Vec::new();
+    for _ in 0..number_of_sids {
+        let mut sid = format_uuid(read_bytes(&buffer, 35, offset, 16)?);
+        offset += 16;
+
+        let number_of_intervals =
+            u64::from_le_bytes(read_bytes(&buffer, 35, offset, 8)?.try_into()?);
+        offset += 8;
+
+        for _ in 0..number_of_intervals {
+            let start = i64::from_le_bytes(read_bytes(&buffer, 35, offset, 8)?.try_into()?);
+            let end = i64::from_le_bytes(read_bytes(&buffer, 35, offset + 8, 8)?.try_into()?);
+            offset += 16;
+
+            match end - start {
+                1 => sid.push_str(&format!(":{}", start)),
+                _ => sid.push_str(&format!(":{}-{}", start, end - 1)),
+            }
+        }
+
+        sids.push(sid);
+    }
+
+    check_consumed_length(35, &buffer, offset)?;
+
+    let event_body = EventBodyTypeCode35 {
+        gtid_set: sids.join(","),
     };
 
     Ok(Box::new(event_body))
 }
 
+// 16字节的server uuid，`3e11fa47-71ca-11e1-9e33-c80aa9429562`
+fn format_uuid(buffer: &[u8]) -> String {
+    let hex: String = buffer.iter().map(|v| format!("{:02x}", v)).collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
 pub fn deal_type_code_162(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
@@ -361,19 +774,23 @@ pub fn deal_type_code_162(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedEr
 
     let mariadb_flags_mapping = mariadb_flags.into_iter().collect::<HashMap<&str, u8>>();
 
-    let gtid_sequence = u64::from_le_bytes(buffer[offset..offset + 8].try_into()?);
+    let gtid_sequence = u64::from_le_bytes(read_bytes(&buffer, 162, offset, 8)?.try_into()?);
+    trace::field("gtid_sequence", offset, offset + 8);
     offset += 8;
 
-    let replication_domain_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+    let replication_domain_id =
+        u32::from_le_bytes(read_bytes(&buffer, 162, offset, 4)?.try_into()?);
+    trace::field("replication_domain_id", offset, offset + 4);
     offset += 4;
 
-    let flags = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    let flags = u8::from_le_bytes(read_bytes(&buffer, 162, offset, 1)?.try_into()?);
+    trace::field("flags", offset, offset + 1);
     offset += 1;
 
     let mut event_body = EventBodyTypeCode162 {
-        gtid_sequence: gtid_sequence,
-        replication_domain_id: replication_domain_id,
-        flags: flags,
+        gtid_sequence,
+        replication_domain_id,
+        flags,
         commit_id: None,
         format_id: None,
         gtid_length: None,
@@ -382,28 +799,58 @@ pub fn deal_type_code_162(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedEr
     };
 
     if flags & mariadb_flags_mapping.get("FL_GROUP_COMMIT_ID").unwrap() > 0 {
-        let commit_id = u64::from_le_bytes(buffer[offset..offset + 8].try_into()?);
+        let commit_id = u64::from_le_bytes(read_bytes(&buffer, 162, offset, 8)?.try_into()?);
         event_body.commit_id = Some(commit_id);
-    } else if flags
+        offset += 8;
+    }
+
+    if flags
         & (mariadb_flags_mapping.get("FL_PREPARED_XA").unwrap()
             | mariadb_flags_mapping.get("FL_COMPLETED_XA").unwrap())
         > 0
     {
-        let format_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        let format_id = u32::from_le_bytes(read_bytes(&buffer, 162, offset, 4)?.try_into()?);
         event_body.format_id = Some(format_id);
         offset += 4;
 
-        let gtid_length = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+        let gtid_length = u8::from_le_bytes(read_bytes(&buffer, 162, offset, 1)?.try_into()?);
         event_body.gtid_length = Some(gtid_length);
         offset += 1;
 
-        let bqual_length = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+        let bqual_length = u8::from_le_bytes(read_bytes(&buffer, 162, offset, 1)?.try_into()?);
         event_body.bqual_length = Some(bqual_length);
         offset += 1;
 
-        let xid = buffer[offset..offset + gtid_length as usize + bqual_length as usize].to_vec();
+        let xid_length = gtid_length as usize + bqual_length as usize;
+        let xid = read_bytes(&buffer, 162, offset, xid_length)?.to_vec();
         event_body.xid = Some(xid);
+        offset += xid_length;
+    }
+
+    // MariaDB 10.8开始的flags_extra，只有不为0时才写入，之后的字段由它决定
+    let body_length = buffer.len();
+    if offset < body_length && buffer[offset] != 0 {
+        let flags_extra = buffer[offset];
+        offset += 1;
+
+        // FL_EXTRA_MULTI_ENGINE_E1后面是1字节的引擎数，FL_COMMIT_ALTER_E1和FL_ROLLBACK_ALTER_E1后面是8字节的start alter的sequence
+        if flags_extra & 0x01 > 0 {
+            offset += 1;
+        }
+        if flags_extra & (0x04 | 0x08) > 0 {
+            offset += 8;
+        }
+        // 还不认识的flag后面的字段长度未知，不检查剩下的部分
+        if flags_extra & !0x0f > 0 {
+            offset = offset.max(body_length);
+        }
+    }
+
+    // 剩下的都是0时是补齐用的，没有commit id时补齐到19字节，有commit id时后面也会补0
+    if offset < body_length && buffer[offset..body_length].iter().all(|&b| b == 0) {
+        offset = body_length;
     }
+    check_consumed_length(162, &buffer, offset)?;
 
     Ok(Box::new(event_body))
 }
@@ -411,14 +858,16 @@ pub fn deal_type_code_162(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedEr
 pub fn deal_type_code_5(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
-    let data_type = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    let data_type = u8::from_le_bytes(read_bytes(&buffer, 5, offset, 1)?.try_into()?);
     offset += 1;
 
-    let value = u64::from_le_bytes(buffer[offset..offset + 8].try_into()?);
+    let value = u64::from_le_bytes(read_bytes(&buffer, 5, offset, 8)?.try_into()?);
+
+    check_consumed_length(5, &buffer, offset + 8)?;
 
     let event_body = EventBodyTypeCode5 {
-        data_type: data_type,
-        value: value,
+        data_type,
+        value,
     };
 
     Ok(Box::new(event_body))
@@ -428,27 +877,44 @@ pub fn deal_type_code_4(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedErro
     let mut offset = 0;
 
     let position_of_the_first_event_in_next_log_file =
-        u64::from_le_bytes(buffer[offset..offset + 8].try_into()?);
+        u64::from_le_bytes(read_bytes(&buffer, 4, offset, 8)?.try_into()?);
+    trace::field(
+        "position_of_the_first_event_in_next_log_file",
+        offset,
+        offset + 8,
+    );
     offset += 8;
 
-    let file_name_of_next_binary_log =
-        String::from_utf8(buffer[offset..buffer.len() - 4].try_into()?)?
-            .trim_end_matches(char::from(0))
-            .to_string();
+    let file_name_of_next_binary_log = String::from_utf8(buffer[offset..].to_vec())?
+        .trim_end_matches(char::from(0))
+        .to_string();
+
+    trace::field("file_name_of_next_binary_log", offset, buffer.len());
 
     let event_body = EventBodyTypeCode4 {
-        position_of_the_first_event_in_next_log_file: position_of_the_first_event_in_next_log_file,
-        file_name_of_next_binary_log: file_name_of_next_binary_log,
+        position_of_the_first_event_in_next_log_file,
+        file_name_of_next_binary_log,
     };
 
     Ok(Box::new(event_body))
 }
 
-pub fn deal_type_code_23_to_25(
-    mut buffer: Vec<u8>,
+/// 只解析行事件的结构：table id、flags、bitmap和行镜像的原始字节，不需要table map，也不解码字段的值
+pub fn parse_rows_event(
+    buffer: &[u8],
     type_code: u8,
-    table_structs: &HashMap<u64, EventBodyTypeCode19>,
-) -> Result<Box<dyn EventBody>, BoxedError> {
+) -> Result<EventBodyTypeCode23To25, BoxedError> {
+    let (mut event_body, row_images) = parse_rows_event_framing(buffer, type_code)?;
+    event_body.row_images = buffer[row_images].to_vec();
+
+    Ok(event_body)
+}
+
+/// 和parse_rows_event一样，但row_images为空，返回行镜像在buffer中的范围，解码时直接从buffer中取
+fn parse_rows_event_framing(
+    buffer: &[u8],
+    type_code: u8,
+) -> Result<(EventBodyTypeCode23To25, Range<usize>), BoxedError> {
     let mut offset = 0;
 
     let mariadb_flags = vec![
@@ -468,13 +934,15 @@ pub fn deal_type_code_23_to_25(
     .to_string();
 
     // table id part
-    let mut table_id_vec = buffer[offset..offset + 6].to_vec();
+    let mut table_id_vec = read_bytes(buffer, type_code, offset, 6)?.to_vec();
     table_id_vec.splice(table_id_vec.len()..table_id_vec.len(), vec![0, 0]);
     let table_id = u64::from_le_bytes(table_id_vec.as_slice().try_into()?);
+    trace::field("table_id", offset, offset + 6);
     offset += 6;
 
     // flags part
-    let flags = u16::from_le_bytes(buffer[offset..offset + 2].try_into()?);
+    let flags = u16::from_le_bytes(read_bytes(buffer, type_code, offset, 2)?.try_into()?);
+    trace::field("flags", offset, offset + 2);
     offset += 2;
 
     // flags for human part
@@ -486,92 +954,254 @@ pub fn deal_type_code_23_to_25(
     }
 
     // number of columns part
-    let (number_of_columns, skip) = parse_lenenc(&buffer[offset..])?;
+    let (number_of_columns, skip) = parse_lenenc(read_rest(buffer, type_code, offset)?)?;
+    trace::field("number_of_columns", offset, offset + skip as usize);
 
     offset += skip as usize;
 
     // columns used part
-    let columns_used_n_byte = (number_of_columns + 7) / 8;
+    let columns_used_n_byte = number_of_columns.div_ceil(8);
     let columns_used = parse_bitmap(
-        &buffer[offset..offset + columns_used_n_byte as usize],
+        read_bytes(buffer, type_code, offset, columns_used_n_byte as usize)?,
         number_of_columns,
     );
+    trace::field(
+        "columns_used",
+        offset,
+        offset + columns_used_n_byte as usize,
+    );
 
     offset += columns_used_n_byte as usize;
 
     // columns used for update part
     let mut columns_used_for_update = None;
     if type_code == 24 {
-        let columns_used_for_update_n_byte = (number_of_columns + 7) / 8;
+        let columns_used_for_update_n_byte = number_of_columns.div_ceil(8);
 
         let result = parse_bitmap(
-            &buffer[offset..offset + columns_used_for_update_n_byte as usize],
+            read_bytes(
+                buffer,
+                type_code,
+                offset,
+                columns_used_for_update_n_byte as usize,
+            )?,
             number_of_columns,
         );
 
         columns_used_for_update = Some(result);
+        trace::field(
+            "columns_used_for_update",
+            offset,
+            offset + columns_used_for_update_n_byte as usize,
+        );
 
         offset += columns_used_for_update_n_byte as usize;
     }
 
-    // null bitmap part
-    let null_bitmap_n_byte = (number_of_columns + 7) / 8;
-    let null_bitmap = parse_bitmap(
-        &buffer[offset..offset + null_bitmap_n_byte as usize],
+    // 之后一直到body的结尾都是行数据
+    let rows_end = buffer.len();
+
+    let mut event_body = EventBodyTypeCode23To25 {
+        type_string_for_human,
+        table_id,
+        flags,
         number_of_columns,
-    );
-    offset += null_bitmap_n_byte as usize;
+        columns_used,
+        columns_used_for_update,
+        null_bitmap: Bitmap::default(),
+        column_data: Vec::new(),
+        null_bitmap_for_update: None,
+        column_data_for_update: None,
+        rows: Vec::new(),
+        number_of_rows: 0,
+        row_images: Vec::new(),
+        row_image_mismatch: None,
+        row_image: Inferred {
+            value: RowImageMode::Full,
+            certain: false,
+        },
+    };
+    event_body.row_image = event_body.infer_row_image(None);
 
-    // get table info
-    let table_info = table_structs.get(&table_id).unwrap();
+    Ok((event_body, offset..rows_end))
+}
 
-    // column data part
-    let (column_data_vec, skip) =
-        parse_column_data_for_row_event(&mut buffer[offset..], &table_info, &null_bitmap)?;
+// 从文件中间开始解析时table map可能在之前的文件中
+pub fn get_rows_event_table_info(
+    table_id: u64,
+    table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+) -> Result<&EventBodyTypeCode19, BoxedError> {
+    let table_info = table_structs.get(&table_id).ok_or_else(|| {
+        Box::new(MyError(format!(
+            "the table map of table id {} is missing, it may be in the previous binlog file, \
+             use `--report-missing-table-maps` to skip rows events like this",
+            table_id
+        ))) as BoxedError
+    })?;
+
+    Ok(table_info.as_ref())
+}
 
-    offset += skip;
+pub fn deal_type_code_23_to_25(
+    buffer: Vec<u8>,
+    type_code: u8,
+    table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    deal_type_code_23_to_25_with_value_options(
+        buffer,
+        type_code,
+        table_structs,
+        ValueOptions::default(),
+    )
+}
 
-    // create a basic event body
-    let mut event_body = EventBodyTypeCode23To25 {
-        type_string_for_human: type_string_for_human,
-        table_id: table_id,
-        flags: flags,
-        number_of_columns: number_of_columns,
-        columns_used: columns_used,
-        columns_used_for_update: columns_used_for_update,
-        null_bitmap: null_bitmap,
-        column_data: column_data_vec,
-        null_bitmap_for_update: None,
-        column_data_for_update: None,
+/// value_options见parse_column_data_for_row_event
+pub fn deal_type_code_23_to_25_with_value_options(
+    buffer: Vec<u8>,
+    type_code: u8,
+    table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    value_options: ValueOptions,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    let table_id = get_rows_event_table_id(&mut std::io::Cursor::new(&buffer), 0)?;
+    let table_info = get_rows_event_table_info(table_id, table_structs)?;
+
+    Ok(Box::new(decode_rows_event(
+        &buffer,
+        type_code,
+        table_info,
+        value_options,
+    )?))
+}
+
+/// MySQL的v2行事件，去掉extra data之后按v1解析
+pub fn deal_type_code_30_to_32(
+    buffer: Vec<u8>,
+    type_code: u8,
+    table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    let (buffer, type_code) = rows_event_v2_to_v1(buffer, type_code)?;
+
+    deal_type_code_23_to_25(buffer, type_code, table_structs)
+}
+
+/// v2行事件在table id和flags之后是2字节的extra data长度（包括这2个字节）和extra data，
+/// 去掉之后就是v1的格式，类型码也换成对应的v1类型，v1行事件原样返回
+pub fn rows_event_v2_to_v1(
+    mut buffer: Vec<u8>,
+    type_code: u8,
+) -> Result<(Vec<u8>, u8), BoxedError> {
+    if !matches!(type_code, 30..=32) {
+        return Ok((buffer, type_code));
+    }
+
+    let extra_data_length = match buffer.get(8..10) {
+        Some(v) => u16::from_le_bytes(v.try_into()?) as usize,
+        None => 0,
     };
 
+    if extra_data_length < 2 || 8 + extra_data_length > buffer.len() {
+        return Err(Box::new(MyError(format!(
+            "the extra data length {} of the rows event is invalid",
+            extra_data_length
+        ))));
+    }
+
+    buffer.drain(8..8 + extra_data_length);
+
+    Ok((buffer, type_code - 7))
+}
+
+/// 用给定的表结构解码行事件，不需要访问table_structs，可以在其他线程中调用
+/// 字段的值直接从buffer中解码，解码之后row_images为空，所以除了buffer本身只多出解码的值
+pub fn decode_rows_event(
+    buffer: &[u8],
+    type_code: u8,
+    table_info: &EventBodyTypeCode19,
+    value_options: ValueOptions,
+) -> Result<EventBodyTypeCode23To25, BoxedError> {
+    let (mut event_body, row_images) = parse_rows_event_framing(buffer, type_code)?;
+    event_body.row_image = event_body.infer_row_image(Some(table_info));
+
+    // row images part
+    // 一个事件里可能有多行，每一行是null bitmap加上字段数据，update的每一行有前后两个镜像
+    // 不一致时默认返回RowImageLengthMismatch；`--lenient-row-images`时保留完整解码的行，
+    // 记录在事件中，由find_unsupported汇总
+    let trace_offset = trace::offset_by(row_images.start);
+    let (images, mismatch) = parse_row_images_lenient(
+        &buffer[row_images.clone()],
+        &event_body.columns_used,
+        event_body.columns_used_for_update.as_ref(),
+        table_info,
+        value_options,
+    );
+    drop(trace_offset);
+    let mismatch = mismatch.map(|v| v.offset_by(row_images.start));
+    if let Some(mismatch) = mismatch {
+        if !value_options.lenient_row_images {
+            return Err(Box::new(mismatch));
+        }
+
+        event_body.row_image_mismatch = Some(mismatch);
+    }
+
+    let mut images_iter = images.iter();
+
+    // 原有的字段只保留第一行的数据
+    if let Some(first_image) = images_iter.next() {
+        event_body.null_bitmap = first_image.0.clone();
+        event_body.column_data = column_data_for_human(&first_image.1);
+    }
+
     // if this is a update record
     if type_code == 24 {
-        // null bitmap for update part
-        let null_bitmap_for_update_n_byte = (number_of_columns + 7) / 8;
-        let null_bitmap_for_update = parse_bitmap(
-            &mut buffer[offset..offset + null_bitmap_for_update_n_byte as usize],
-            number_of_columns,
-        );
-        offset += null_bitmap_for_update_n_byte as usize;
+        if let Some(image_for_update) = images_iter.next() {
+            event_body.null_bitmap_for_update = Some(image_for_update.0.clone());
+            event_body.column_data_for_update = Some(column_data_for_human(&image_for_update.1));
+        }
+    }
 
-        // column data for update part
-        let (column_data_for_update_vec, _skip) = parse_column_data_for_row_event(
-            &mut buffer[offset..],
-            &table_info,
-            &null_bitmap_for_update,
-        )?;
+    // 字段的值移动到rows中，不再复制
+    event_body.rows = RowsEventRow::from_images(images, type_code);
+    event_body.number_of_rows = event_body.rows.len() as u64;
 
-        event_body.null_bitmap_for_update = Some(null_bitmap_for_update);
-        event_body.column_data_for_update = Some(column_data_for_update_vec);
+    Ok(event_body)
+}
 
-        // offset += skip;
+/// 和deal_type_code_23_to_25一样，但只计算行数，不解码字段的值，rows为空
+/// 用于只需要表和行数的统计，遇到还不支持的字段类型时完整地解码，这样问题汇总和原来一样
+pub fn deal_type_code_23_to_25_without_values(
+    buffer: Vec<u8>,
+    type_code: u8,
+    table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+) -> Result<Box<dyn EventBody>, BoxedError> {
+    let mut event_body = parse_rows_event(&buffer, type_code)?;
+    let table_info = get_rows_event_table_info(event_body.table_id, table_structs)?;
+
+    // 只有部分字段的镜像count_row_images不能计算，完整地解码
+    if !event_body.is_full_image() {
+        return deal_type_code_23_to_25(buffer, type_code, table_structs);
     }
 
+    let number_of_images = match count_row_images(
+        &event_body.row_images,
+        event_body.number_of_columns,
+        table_info,
+    ) {
+        Some(number_of_images) => number_of_images,
+        None => return deal_type_code_23_to_25(buffer, type_code, table_structs),
+    };
+
+    event_body.number_of_rows = match type_code {
+        24 => number_of_images.div_ceil(2),
+        _ => number_of_images,
+    };
+
     Ok(Box::new(event_body))
 }
 
 pub fn deal_type_code_13(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
+    check_consumed_length(13, &buffer, 16)?;
+
     let event_body = EventBodyTypeCode13 {
         first_seed: u64::from_le_bytes(buffer[0..8].try_into()?),
         second_seed: u64::from_le_bytes(buffer[8..16].try_into()?),
@@ -581,6 +1211,8 @@ pub fn deal_type_code_13(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedErr
 }
 
 pub fn deal_type_code_164(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
+    check_consumed_length(164, &buffer, 17)?;
+
     let event_body = EventBodyTypeCode164 {
         encryption_scheme: u8::from_le_bytes(buffer[0..1].try_into()?),
         encryption_key_version: u32::from_le_bytes(buffer[1..5].try_into()?),
@@ -590,16 +1222,24 @@ pub fn deal_type_code_164(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedEr
     Ok(Box::new(event_body))
 }
 
+/// one phase commit、format id之后gtrid和bqual的长度都是4字节
 pub fn deal_type_code_38(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
-    let length_of_gtrid = u32::from_le_bytes(buffer[5..9].try_into()?);
-    let length_of_bqual = u8::from_le_bytes(buffer[9..10].try_into()?);
+    // 长度损坏时返回错误而不是panic，`--skip-errors`时可以跳过这个事件
+    let fixed_part = buffer
+        .get(0..13)
+        .ok_or_else(|| MyError("the XA prepare event is truncated".to_string()))?;
+    let length_of_gtrid = u32::from_le_bytes(fixed_part[5..9].try_into()?);
+    let length_of_bqual = u32::from_le_bytes(fixed_part[9..13].try_into()?);
+    let xid_end = 13 + length_of_gtrid as usize + length_of_bqual as usize;
+
+    check_consumed_length(38, &buffer, xid_end)?;
 
     let event_body = EventBodyTypeCode38 {
-        one_phase_commit: u8::from_le_bytes(buffer[0..1].try_into()?),
-        format_id: u32::from_le_bytes(buffer[1..5].try_into()?),
-        length_of_gtrid: length_of_gtrid,
-        length_of_bqual: length_of_bqual,
-        xid: buffer[10..10 + length_of_gtrid as usize + length_of_bqual as usize].to_vec(),
+        one_phase_commit: fixed_part[0],
+        format_id: u32::from_le_bytes(fixed_part[1..5].try_into()?),
+        length_of_gtrid,
+        length_of_bqual,
+        xid: buffer[13..xid_end].to_vec(),
     };
 
     Ok(Box::new(event_body))
@@ -608,62 +1248,594 @@ pub fn deal_type_code_38(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedErr
 pub fn deal_type_code_14(buffer: Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError> {
     let mut offset = 0;
 
-    let length_of_user_variable_name = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+    let length_of_user_variable_name =
+        u32::from_le_bytes(read_bytes(&buffer, 14, offset, 4)?.try_into()?);
     offset += 4;
 
     let name_of_user_variable = String::from_utf8(
-        buffer[offset..offset + length_of_user_variable_name as usize].try_into()?,
+        read_bytes(&buffer, 14, offset, length_of_user_variable_name as usize)?.to_vec(),
     )?;
     offset += length_of_user_variable_name as usize;
 
-    let null_indicator = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    let null_indicator = u8::from_le_bytes(read_bytes(&buffer, 14, offset, 1)?.try_into()?);
     offset += 1;
 
     let mut event_body = EventBodyTypeCode14 {
-        length_of_user_variable_name: length_of_user_variable_name,
-        name_of_user_variable: name_of_user_variable,
-        null_indicator: null_indicator,
+        length_of_user_variable_name,
+        name_of_user_variable,
+        null_indicator,
         variable_type: None,
         variable_type_string_for_human: None,
         collation_number: None,
+        collation_string_for_human: None,
         length_of_value: None,
         value: None,
         flags: None,
     };
 
-    if null_indicator > 0 {
-        let variable_type = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+    // null_indicator为1时值是NULL，后面没有其他部分
+    if null_indicator == 0 {
+        let variable_type = u8::from_le_bytes(read_bytes(&buffer, 14, offset, 1)?.try_into()?);
         offset+=1;
         event_body.variable_type = Some(variable_type);
 
-        let variable_type_mapping = vec![
-            (0,"STRING_RESULT"),
+        let variable_type_mapping = [(0,"STRING_RESULT"),
             (1,"REAL_RESULT"),
             (2,"INT_RESULT"),
             (3,"ROW_RESULT"),
-            (4,"DECIMAL_RESULT")
-        ].iter()
+            (4,"DECIMAL_RESULT")].iter()
         .map(|v| {
             (v.0, v.1.to_string())
         })
         .collect::<HashMap<u8, String>>();
-        event_body.variable_type_string_for_human = Some(variable_type_mapping.get(&variable_type).unwrap().to_owned());
+        let variable_type_string_for_human = variable_type_mapping
+            .get(&variable_type)
+            .ok_or_else(|| MyError(format!("unknown user variable type {}", variable_type)))?;
+        event_body.variable_type_string_for_human = Some(variable_type_string_for_human.to_owned());
 
-        let collation_number = u32::from_le_bytes(buffer[offset..offset+4].try_into()?);
+        let collation_number = u32::from_le_bytes(read_bytes(&buffer, 14, offset, 4)?.try_into()?);
         offset+=4;
         event_body.collation_number = Some(collation_number);
 
-        let length_of_value = u32::from_le_bytes(buffer[offset..offset+4].try_into()?);
+        let length_of_value = u32::from_le_bytes(read_bytes(&buffer, 14, offset, 4)?.try_into()?);
         offset+=4;
         event_body.length_of_value=Some(length_of_value);
 
-        let value = String::from_utf8(buffer[offset..offset+length_of_value as usize].try_into()?)?;
+        // 只有STRING_RESULT是字符串，其他类型是二进制的值
+        let value = String::from_utf8_lossy(read_bytes(&buffer, 14, offset, length_of_value as usize)?)
+            .to_string();
         offset += length_of_value as usize;
         event_body.value = Some(value);
+        event_body.collation_string_for_human = Some(collation_name(collation_number as u64));
 
-        let flags = u8::from_le_bytes(buffer[offset..offset+1].try_into()?);
-        event_body.flags = Some(flags);
+        // 旧版本没有flags
+        if offset < buffer.len() {
+            let flags = u8::from_le_bytes(read_bytes(&buffer, 14, offset, 1)?.try_into()?);
+            offset += 1;
+            event_body.flags = Some(flags);
+        }
     }
 
+    check_consumed_length(14, &buffer, offset)?;
+
     Ok(Box::new(event_body))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::{
+        BinlogBuilder, EventBodyBuilder, EventBuilder, FormatDescriptionBuilder,
+        QueryEventBuilder, RowsEventBuilder, TableMapBuilder,
+    };
+    use crate::error::RowImageLengthMismatch;
+
+    type Decoder = fn(Vec<u8>) -> Result<Box<dyn EventBody>, BoxedError>;
+
+    // 正好的body可以解码，少一个或者多一个字节都是错误，checksum的长度算错时就是这样
+    fn assert_exact_length(decoder: Decoder, body: &[u8]) {
+        assert!(decoder(body.to_vec()).is_ok());
+
+        assert!(decoder(body[..body.len() - 1].to_vec()).is_err());
+
+        let mut extended = body.to_vec();
+        extended.push(0xff);
+        assert!(decoder(extended).is_err());
+    }
+
+    fn xa_prepare(gtrid: &[u8], bqual: &[u8]) -> Vec<u8> {
+        let mut body = vec![0u8];
+        body.extend(1u32.to_le_bytes());
+        body.extend((gtrid.len() as u32).to_le_bytes());
+        body.extend((bqual.len() as u32).to_le_bytes());
+        body.extend(gtrid);
+        body.extend(bqual);
+        body
+    }
+
+    fn user_var(flags: Option<u8>) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend(1u32.to_le_bytes());
+        body.push(b'a');
+        body.push(0);
+        body.push(0);
+        body.extend(33u32.to_le_bytes());
+        body.extend(1u32.to_le_bytes());
+        body.push(b'x');
+        body.extend(flags);
+        body
+    }
+
+    fn gtid_list() -> Vec<u8> {
+        let mut body = 1u32.to_le_bytes().to_vec();
+        body.extend(0u32.to_le_bytes());
+        body.extend(1u32.to_le_bytes());
+        body.extend(42u64.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn deal_type_code_38_decodes_xid() {
+        let body = deal_type_code_38(xa_prepare(b"trx1", b"b")).unwrap();
+        let body = body.downcast_ref::<EventBodyTypeCode38>().unwrap();
+
+        assert_eq!(body.format_id, 1);
+        assert_eq!(body.length_of_gtrid, 4);
+        assert_eq!(body.length_of_bqual, 1);
+        assert_eq!(body.xid, b"trx1b");
+    }
+
+    #[test]
+    fn deal_type_code_38_rejects_truncated_body() {
+        let body = xa_prepare(b"trx1", b"b");
+
+        for length in [0, 4, 9, 12, 16] {
+            assert!(deal_type_code_38(body[..length].to_vec()).is_err());
+        }
+        // gtrid的长度大于body
+        let mut corrupt = body.clone();
+        corrupt[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(deal_type_code_38(corrupt).is_err());
+    }
+
+    #[test]
+    fn fixed_length_decoders_reject_one_byte_off() {
+        let mut intvar = vec![2u8];
+        intvar.extend(7u64.to_le_bytes());
+        assert_exact_length(deal_type_code_5, &intvar);
+
+        assert_exact_length(deal_type_code_13, &[1u8; 16]);
+        assert_exact_length(deal_type_code_16, &7u64.to_le_bytes());
+        assert_exact_length(deal_type_code_38, &xa_prepare(b"trx1", b"b"));
+        assert_exact_length(deal_type_code_164, &[1u8; 17]);
+
+        let mut checkpoint = 16u32.to_le_bytes().to_vec();
+        checkpoint.extend(b"mysql-bin.000001");
+        assert_exact_length(deal_type_code_161, &checkpoint);
+
+        assert_exact_length(deal_type_code_163, &gtid_list());
+    }
+
+    #[test]
+    fn mysql_gtid_decoders_reject_one_byte_off() {
+        // 5.7的gtid：flags、uuid、gno和类型2的logical clock
+        let mut gtid = vec![1u8];
+        gtid.extend([0x3eu8; 16]);
+        gtid.extend(5i64.to_le_bytes());
+        gtid.push(2);
+        gtid.extend(3i64.to_le_bytes());
+        gtid.extend(4i64.to_le_bytes());
+        assert_exact_length(deal_type_code_33, &gtid);
+
+        let body = deal_type_code_33(gtid).unwrap();
+        let body = body.downcast_ref::<EventBodyTypeCode33>().unwrap();
+        assert_eq!((body.last_committed, body.sequence_number), (Some(3), Some(4)));
+
+        // 一个server uuid，一个区间[1, 11)
+        let mut previous_gtids = 1u64.to_le_bytes().to_vec();
+        previous_gtids.extend([0x3eu8; 16]);
+        previous_gtids.extend(1u64.to_le_bytes());
+        previous_gtids.extend(1i64.to_le_bytes());
+        previous_gtids.extend(11i64.to_le_bytes());
+        assert_exact_length(deal_type_code_35, &previous_gtids);
+    }
+
+    #[test]
+    fn mariadb_gtid_rejects_one_byte_off() {
+        // 有commit id的gtid没有补齐的0
+        let mut gtid = 42u64.to_le_bytes().to_vec();
+        gtid.extend(0u32.to_le_bytes());
+        gtid.push(2);
+        gtid.extend(9u64.to_le_bytes());
+        assert!(deal_type_code_162(gtid.clone()).is_ok());
+        assert!(deal_type_code_162(gtid[..gtid.len() - 1].to_vec()).is_err());
+        // 多出来的一个字节按flags_extra解析，FL_EXTRA_MULTI_ENGINE_E1后面缺少引擎数
+        let mut extended = gtid.clone();
+        extended.push(0x01);
+        assert!(deal_type_code_162(extended).is_err());
+
+        // 没有commit id时补齐到19字节，补齐的0可以少，但固定的部分不能少
+        let mut padded = gtid[..13].to_vec();
+        padded[12] = 0;
+        assert!(deal_type_code_162(padded[..12].to_vec()).is_err());
+        padded.extend([0u8; 6]);
+        let body = deal_type_code_162(padded).unwrap();
+        let body = body.downcast_ref::<EventBodyTypeCode162>().unwrap();
+        assert_eq!(body.gtid_sequence, 42);
+    }
+
+    #[test]
+    fn user_var_rejects_one_byte_off() {
+        // 旧版本没有flags，少一个字节时值不完整
+        let body = user_var(None);
+        assert!(deal_type_code_14(body.clone()).is_ok());
+        assert!(deal_type_code_14(body[..body.len() - 1].to_vec()).is_err());
+
+        // 有flags时多一个字节
+        let mut body = user_var(Some(0));
+        let decoded = deal_type_code_14(body.clone()).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode14>().unwrap();
+        assert_eq!(decoded.value.as_deref(), Some("x"));
+        assert_eq!(decoded.flags, Some(0));
+        body.push(0);
+        assert!(deal_type_code_14(body).is_err());
+    }
+
+    #[test]
+    fn decoders_reading_to_the_end_keep_every_byte() {
+        // query、rotate和annotate rows的最后一部分一直读到结尾，多出来或者少了的字节都会进入sql或者文件名
+        let query = QueryEventBuilder::new("db", "BEGIN").body();
+        let decoded = deal_type_code_2(query.clone()).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode2>().unwrap();
+        assert_eq!(decoded.sql, "BEGIN");
+        let decoded = deal_type_code_2(query[..query.len() - 1].to_vec()).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode2>().unwrap();
+        assert_eq!(decoded.sql, "BEGI");
+        let mut extended = query.clone();
+        extended.push(b';');
+        let decoded = deal_type_code_2(extended).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode2>().unwrap();
+        assert_eq!(decoded.sql, "BEGIN;");
+        // 截断到固定的部分或者库名时是错误
+        for length in [0, 12, 13, 15] {
+            assert!(deal_type_code_2(query[..length].to_vec()).is_err());
+        }
+
+        let mut rotate = 4u64.to_le_bytes().to_vec();
+        rotate.extend(b"mysql-bin.000002");
+        let decoded = deal_type_code_4(rotate[..rotate.len() - 1].to_vec()).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode4>().unwrap();
+        assert_eq!(decoded.file_name_of_next_binary_log, "mysql-bin.00000");
+        let mut extended = rotate.clone();
+        extended.push(b'0');
+        let decoded = deal_type_code_4(extended).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode4>().unwrap();
+        assert_eq!(decoded.file_name_of_next_binary_log, "mysql-bin.0000020");
+        assert!(deal_type_code_4(rotate[..7].to_vec()).is_err());
+
+        let decoded = deal_type_code_160(b"INSERT INTO t VALUES (1)".to_vec()).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode160>().unwrap();
+        assert_eq!(decoded.sql, "INSERT INTO t VALUES (1)");
+        let decoded = deal_type_code_160(b"INSERT INTO t VALUES (1))".to_vec()).unwrap();
+        let decoded = decoded.downcast_ref::<EventBodyTypeCode160>().unwrap();
+        assert_eq!(decoded.sql, "INSERT INTO t VALUES (1))");
+    }
+
+    // 按顺序解码整个文件，checksum的长度由FDE决定
+    fn decode_all(binlog: Vec<u8>) -> Result<Vec<Box<dyn EventBody>>, BoxedError> {
+        let length = binlog.len() as u64;
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut checksum_length = CHECKSUM_LENGTH;
+        let mut bodies = Vec::new();
+
+        let mut position = 4;
+        while position < length {
+            let header = get_event_header(&mut file, position)?;
+            let body =
+                get_event_body(&mut file, position, &header, &mut table_structs, checksum_length)?;
+            update_checksum_length(&mut checksum_length, body.as_ref());
+            bodies.push(body);
+            position += header.event_length as u64;
+        }
+
+        Ok(bodies)
+    }
+
+    fn transaction(binlog: BinlogBuilder) -> Vec<u8> {
+        let table = TableMapBuilder::new("db", "t").column(FieldType::Long, &[]);
+
+        binlog
+            .push(EventBuilder::new(163, gtid_list()))
+            .event(&QueryEventBuilder::new("db", "BEGIN"))
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(vec![ColumnValue::Int(1)]))
+            .push(EventBuilder::new(16, 7u64.to_le_bytes().to_vec()))
+            .build()
+    }
+
+    #[test]
+    fn get_event_body_uses_checksum_length_from_format_description() {
+        for binlog in [
+            transaction(BinlogBuilder::new()),
+            transaction(BinlogBuilder::without_checksum()),
+        ] {
+            let bodies = decode_all(binlog).unwrap();
+            assert_eq!(bodies.len(), 6);
+
+            let gtid_list = bodies[1].downcast_ref::<EventBodyTypeCode163>().unwrap();
+            assert_eq!(gtid_list.gtids[0].gtid_sequence, 42);
+            let query = bodies[2].downcast_ref::<EventBodyTypeCode2>().unwrap();
+            assert_eq!(query.sql, "BEGIN");
+            let xid = bodies[5].downcast_ref::<EventBodyTypeCode16>().unwrap();
+            assert_eq!(xid.xid_transaction_number, 7);
+        }
+    }
+
+    #[test]
+    fn checksum_none_file_read_as_crc32_is_rejected() {
+        let binlog = transaction(BinlogBuilder::without_checksum());
+        let mut file = Cursor::new(binlog);
+        let position = 4 + get_event_header(&mut file, 4).unwrap().event_length as u64;
+        let header = get_event_header(&mut file, position).unwrap();
+
+        let error =
+            get_event_body(&mut file, position, &header, &mut HashMap::new(), CHECKSUM_LENGTH)
+                .unwrap_err();
+        let error = error.downcast_ref::<ConsumedLengthMismatch>().unwrap();
+        assert_eq!(error.delta(), 4);
+    }
+
+    // 截断在任何位置都返回错误或者解码出较短的值，不会panic
+    #[test]
+    fn truncated_bodies_never_panic() {
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &20u16.to_le_bytes())
+            .column(FieldType::NewDecimal, &[10, 2]);
+        let rows = RowsEventBuilder::insert(&table)
+            .row(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String("a".to_string()),
+                // 1.50，4个字节的整数部分和1个字节的小数部分
+                ColumnValue::Bytes(vec![0x80, 0, 0, 1, 50]),
+            ])
+            .body();
+        let mut status_variables = vec![0u8];
+        status_variables.extend(0u32.to_le_bytes());
+        status_variables.extend([128, 1, 0, 0]);
+        let query = QueryEventBuilder::new("db", "BEGIN")
+            .status_variables(&status_variables)
+            .body();
+        let format_description = FormatDescriptionBuilder::new("10.11.6-MariaDB-log").body();
+
+        let decoders: Vec<(Decoder, Vec<u8>)> = vec![
+            (deal_type_code_2, query),
+            (deal_type_code_14, user_var(Some(0))),
+            (deal_type_code_15, format_description),
+            (deal_type_code_163, gtid_list()),
+        ];
+        for (decoder, body) in decoders {
+            for length in 0..body.len() {
+                let _ = decoder(body[..length].to_vec());
+            }
+        }
+
+        let table_map = table.body();
+        for length in 0..table_map.len() {
+            assert!(deal_type_code_19(table_map[..length].to_vec(), &mut HashMap::new()).is_err());
+        }
+
+        let mut table_structs = HashMap::new();
+        deal_type_code_19(table_map, &mut table_structs).unwrap();
+        assert!(deal_type_code_23_to_25(rows.clone(), 23, &table_structs).is_ok());
+        for length in 0..rows.len() {
+            let _ = deal_type_code_23_to_25(rows[..length].to_vec(), 23, &table_structs);
+        }
+    }
+
+    // table map中第二个字段是SHORT，行镜像是按两个LONG写的，第一行解码之后还剩2个字节
+    #[test]
+    fn row_image_disagreeing_with_table_map_is_an_error() {
+        let written = TableMapBuilder::new("db", "t")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Long, &[]);
+        let rows = RowsEventBuilder::insert(&written)
+            .row(vec![ColumnValue::Int(1), ColumnValue::Int(2)])
+            .body();
+        let table_map = TableMapBuilder::new("db", "t")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Short, &[])
+            .body();
+        let mut table_structs = HashMap::new();
+        deal_type_code_19(table_map, &mut table_structs).unwrap();
+
+        let error = deal_type_code_23_to_25(rows.clone(), 23, &table_structs).unwrap_err();
+        let mismatch = error.downcast_ref::<RowImageLengthMismatch>().unwrap();
+        assert_eq!(mismatch.table, "db.t");
+        assert_eq!(mismatch.column_count, 2);
+        assert_eq!(mismatch.expected, rows.len());
+        assert_eq!(mismatch.consumed, rows.len() - 2);
+        assert_eq!(mismatch.remaining.len(), 2);
+
+        let value_options = ValueOptions {
+            lenient_row_images: true,
+            ..Default::default()
+        };
+        let body =
+            deal_type_code_23_to_25_with_value_options(rows, 23, &table_structs, value_options)
+                .unwrap();
+        let event = body.downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+        assert_eq!(event.row_image_mismatch.as_ref(), Some(mismatch));
+        assert_eq!(event.rows.len(), 1);
+        assert_eq!(event.rows[0].after.as_ref().unwrap()[0], ColumnValue::Int(1));
+    }
+
+    #[test]
+    fn unknown_user_variable_type_is_an_error() {
+        let mut body = user_var(None);
+        body[6] = 9;
+
+        let error = deal_type_code_14(body).unwrap_err();
+        assert!(error.to_string().ends_with("unknown user variable type 9"));
+    }
+
+    // 先只解析结构，需要值时再调用decode_rows，和直接解码的结果一样
+    #[test]
+    fn lazy_rows_decode_like_eager() {
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &20u16.to_le_bytes())
+            .column(FieldType::NewDecimal, &[10, 2])
+            .column(FieldType::Timestamp2, &[0])
+            .nullable(true);
+        let row = |id: i64, name: Option<&str>| {
+            vec![
+                ColumnValue::Int(id),
+                name.map(|v| ColumnValue::String(v.to_string())).unwrap_or(ColumnValue::Null),
+                ColumnValue::Bytes(vec![0x80, 0, 0, 1, 50]),
+                ColumnValue::Bytes(0x6553f100u32.to_be_bytes().to_vec()),
+            ]
+        };
+        let mut table_structs = HashMap::new();
+        deal_type_code_19(table.body(), &mut table_structs).unwrap();
+        let table_info = table_structs.values().next().unwrap().clone();
+
+        let insert = RowsEventBuilder::insert(&table)
+            .row(row(1, Some("a")))
+            .row(row(2, None));
+        let update = RowsEventBuilder::update(&table).update_row(row(1, Some("a")), row(1, Some("b")));
+        let delete = RowsEventBuilder::delete(&table).row(row(2, None));
+        let events = [(23, insert.body()), (24, update.body()), (25, delete.body())];
+        for (type_code, body) in events {
+            let eager = deal_type_code_23_to_25(body.clone(), type_code, &table_structs).unwrap();
+            let eager = eager.downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+
+            // 结构的部分不需要table map
+            let lazy = parse_rows_event(&body, type_code).unwrap();
+            assert_eq!(lazy.table_id, table.get_table_id());
+            assert_eq!(lazy.number_of_columns, 4);
+            assert!(lazy.rows.is_empty());
+            assert!(!lazy.row_images.is_empty());
+
+            let rows = lazy.decode_rows(&table_info).unwrap();
+            assert_eq!(rows.len(), eager.rows.len());
+            for (lazy, eager) in rows.iter().zip(&eager.rows) {
+                assert_eq!(lazy.before, eager.before);
+                assert_eq!(lazy.after, eager.after);
+            }
+
+            // 只计算行数时不解码值
+            let counted =
+                deal_type_code_23_to_25_without_values(body, type_code, &table_structs).unwrap();
+            let counted = counted.downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+            assert!(counted.rows.is_empty());
+            assert_eq!(counted.number_of_rows, eager.rows.len() as u64);
+            assert_eq!(counted.number_of_rows, eager.number_of_rows);
+        }
+    }
+
+    // 当前线程分配的字节数和其中的峰值，测试并行运行时互不影响
+    struct PeakAllocator;
+
+    thread_local! {
+        static ALLOCATED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+        static PEAK: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn record_allocation(grown: usize, shrunk: usize) {
+        let _ = ALLOCATED.try_with(|allocated| {
+            let current = (allocated.get() + grown).saturating_sub(shrunk);
+            allocated.set(current);
+            let _ = PEAK.try_with(|peak| peak.set(peak.get().max(current)));
+        });
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for PeakAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            record_allocation(layout.size(), 0);
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            record_allocation(0, layout.size());
+            std::alloc::System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: std::alloc::Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            record_allocation(new_size, layout.size());
+            std::alloc::System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: PeakAllocator = PeakAllocator;
+
+    // f执行过程中比开始时多分配的最大字节数
+    fn peak_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATED.with(|v| v.get());
+        PEAK.with(|v| v.set(before));
+        let result = f();
+        (result, PEAK.with(|v| v.get()) - before)
+    }
+
+    #[test]
+    fn large_blob_is_decoded_in_bounded_memory() {
+        const BLOB_LENGTH: usize = 64 * 1024 * 1024;
+
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Blob, &[4]);
+        let binlog = BinlogBuilder::new()
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .row(vec![ColumnValue::Int(42), ColumnValue::Bytes(vec![b'x'; BLOB_LENGTH])]),
+            )
+            .build();
+
+        let mut file = Cursor::new(binlog.as_slice());
+        let mut table_structs = HashMap::new();
+        let header = get_event_header(&mut file, 4).unwrap();
+        let position = header.next_event_position as u64;
+        let header = get_event_header(&mut file, position).unwrap();
+        get_event_body(&mut file, position, &header, &mut table_structs, CHECKSUM_LENGTH).unwrap();
+        let position = header.next_event_position as u64;
+        let header = get_event_header(&mut file, position).unwrap();
+        let event_length = header.event_length as usize;
+
+        // 截断时只有读取的body，输出截断之后的值也不会产生大的字符串
+        let (output, peak) = peak_allocation(|| {
+            let body = get_event_body_with_value_limit(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+                Some(1024),
+            )
+            .unwrap();
+            format!("{:?}", body)
+        });
+        assert!(output.contains(&format!("({} bytes)", BLOB_LENGTH)));
+        assert!(output.len() < 64 * 1024);
+        assert!(peak < event_length + event_length / 10, "peak {} for {}", peak, event_length);
+
+        // 完整解码时值从body中直接构造，之前的实现会多复制两次，峰值是6倍
+        let (body, peak) = peak_allocation(|| {
+            get_event_body(&mut file, position, &header, &mut table_structs, CHECKSUM_LENGTH)
+                .unwrap()
+        });
+        let rows = &body.downcast_ref::<EventBodyTypeCode23To25>().unwrap().rows;
+        let after = rows[0].after.as_ref().unwrap();
+        assert!(matches!(&after[1], ColumnValue::String(v) if v.len() == BLOB_LENGTH));
+        assert!(peak < 9 * event_length / 2, "peak {} for {}", peak, event_length);
+    }
+}
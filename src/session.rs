@@ -0,0 +1,409 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::model::*;
+use crate::transaction::{TransactionSummary, TransactionTracker};
+use crate::util::format_timestamp;
+
+/// 一个连接（query事件中的线程id）的活动
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionActivity {
+    pub thread_id: u32,
+    pub first_timestamp: u32,
+    pub last_timestamp: u32,
+    /// query事件的个数，不包括BEGIN、COMMIT和ROLLBACK
+    pub statements: u64,
+    /// 事务中第一个query事件是这个连接的事务数
+    pub transactions: u64,
+    pub row_events: u64,
+    pub inserted_rows: u64,
+    pub updated_rows: u64,
+    pub deleted_rows: u64,
+    /// query的当前库和行事件的库，按第一次出现的顺序
+    pub databases: Vec<String>,
+}
+
+impl SessionActivity {
+    fn new(thread_id: u32, timestamp: u32) -> SessionActivity {
+        SessionActivity {
+            thread_id,
+            first_timestamp: timestamp,
+            last_timestamp: timestamp,
+            statements: 0,
+            transactions: 0,
+            row_events: 0,
+            inserted_rows: 0,
+            updated_rows: 0,
+            deleted_rows: 0,
+            databases: Vec::new(),
+        }
+    }
+
+    pub fn total_rows(&self) -> u64 {
+        self.inserted_rows + self.updated_rows + self.deleted_rows
+    }
+
+    fn touch(&mut self, timestamp: u32) {
+        self.first_timestamp = self.first_timestamp.min(timestamp);
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+    }
+
+    fn add_database(&mut self, database: &str) {
+        if !database.is_empty() && !self.databases.iter().any(|v| v == database) {
+            self.databases.push(database.to_string());
+        }
+    }
+}
+
+impl Display for SessionActivity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>10}  {:19}  {:19}  {:>10}  {:>12}  {:>10}  {:>8}  {:>8}  {:>8}  {}",
+            self.thread_id,
+            format_timestamp(self.first_timestamp),
+            format_timestamp(self.last_timestamp),
+            self.statements,
+            self.transactions,
+            self.total_rows(),
+            self.inserted_rows,
+            self.updated_rows,
+            self.deleted_rows,
+            self.databases.join(", ")
+        )
+    }
+}
+
+/// `--sessions`，按query事件中的线程id汇总每个连接的活动
+///
+/// 行事件中没有线程id，按所在的事务计入事务中第一个query事件（通常是BEGIN）的连接。
+/// 事务按TransactionTracker划分，只认MariaDB的GTID，没有query事件的事务计入unattributed_transactions
+#[derive(Default)]
+pub struct SessionReport {
+    // 按线程id排列输出
+    sessions: BTreeMap<u32, SessionActivity>,
+    transaction_tracker: TransactionTracker,
+    // 当前事务中第一个query事件的线程id，和行事件涉及的库
+    thread_id: Option<u32>,
+    databases: Vec<String>,
+    unattributed_transactions: u64,
+}
+
+impl SessionReport {
+    pub fn new() -> SessionReport {
+        SessionReport::default()
+    }
+
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        // 新的GTID会以未提交的状态结束上一个事务，先按上一个事务的连接统计
+        if !matches!(header.type_code, 162 | 33) {
+            self.observe(header, body, table_structs);
+        }
+
+        let finished = self
+            .transaction_tracker
+            .process(header, position, body, table_structs);
+        if let Some(transaction) = finished {
+            self.attribute(&transaction);
+        }
+
+        if matches!(header.type_code, 162 | 33) {
+            self.thread_id = None;
+            self.databases.clear();
+        }
+    }
+
+    /// 文件结束时还没有提交的事务也计入它的连接
+    pub fn finish(&mut self) {
+        if let Some(transaction) = self.transaction_tracker.finish() {
+            self.attribute(&transaction);
+        }
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = &SessionActivity> {
+        self.sessions.values()
+    }
+
+    /// 找不到连接的事务数
+    pub fn unattributed_transactions(&self) -> u64 {
+        self.unattributed_transactions
+    }
+
+    fn observe(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+            let thread_id = query_event.id_of_thread;
+            self.thread_id.get_or_insert(thread_id);
+
+            let session = self
+                .sessions
+                .entry(thread_id)
+                .or_insert_with(|| SessionActivity::new(thread_id, header.timestamp));
+            session.touch(header.timestamp);
+            session.add_database(&query_event.database_name);
+
+            let sql = query_event.sql.trim();
+            if !["BEGIN", "COMMIT", "ROLLBACK"]
+                .iter()
+                .any(|v| sql.eq_ignore_ascii_case(v))
+            {
+                session.statements += 1;
+            }
+        }
+
+        if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+            if let Some(table_info) = table_structs.get(&rows_event.table_id) {
+                if !self.databases.contains(&table_info.database_name) {
+                    self.databases.push(table_info.database_name.clone());
+                }
+            }
+        }
+    }
+
+    fn attribute(&mut self, transaction: &TransactionSummary) {
+        let databases = std::mem::take(&mut self.databases);
+
+        let session = match self.thread_id.take() {
+            Some(thread_id) => self
+                .sessions
+                .entry(thread_id)
+                .or_insert_with(|| SessionActivity::new(thread_id, transaction.begin_timestamp)),
+            None => {
+                self.unattributed_transactions += 1;
+                return;
+            }
+        };
+
+        session.touch(transaction.begin_timestamp);
+        if let Some(commit_timestamp) = transaction.commit_timestamp {
+            session.touch(commit_timestamp);
+        }
+
+        session.transactions += 1;
+        session.row_events += transaction.row_events;
+        session.inserted_rows += transaction.inserted_rows;
+        session.updated_rows += transaction.updated_rows;
+        session.deleted_rows += transaction.deleted_rows;
+
+        for database in &databases {
+            session.add_database(database);
+        }
+    }
+}
+
+impl Display for SessionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>10}  {:19}  {:19}  {:>10}  {:>12}  {:>10}  {:>8}  {:>8}  {:>8}  databases",
+            "thread",
+            "first activity",
+            "last activity",
+            "statements",
+            "transactions",
+            "rows",
+            "insert",
+            "update",
+            "delete"
+        )?;
+
+        for session in self.sessions() {
+            writeln!(f, "{}", session)?;
+        }
+
+        if self.unattributed_transactions > 0 {
+            writeln!(
+                f,
+                "{} transaction(s) without a query event are not attributed to any thread",
+                self.unattributed_transactions
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn gtid(sequence: u64, flags: u8, timestamp: u32) -> EventBuilder {
+        EventBuilder::from_body(&GtidEventBuilder::new(sequence).flags(flags)).timestamp(timestamp)
+    }
+
+    fn query(thread_id: u32, database: &str, sql: &str, timestamp: u32) -> EventBuilder {
+        EventBuilder::from_body(&QueryEventBuilder::new(database, sql).thread_id(thread_id))
+            .timestamp(timestamp)
+    }
+
+    fn table(database: &str, name: &str, table_id: u64) -> TableMapBuilder {
+        TableMapBuilder::new(database, name)
+            .table_id(table_id)
+            .column(FieldType::Long, &[])
+    }
+
+    fn int(v: i64) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(v)]
+    }
+
+    fn report(binlog: &[u8]) -> SessionReport {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut session_report = SessionReport::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            session_report.process(&header, position, body.as_ref(), &table_structs);
+            position = header.next_event_position as u64;
+        }
+        session_report.finish();
+
+        session_report
+    }
+
+    // 连接10和20交替提交事务
+    fn two_connections() -> Vec<u8> {
+        let orders = table("shop", "orders", 70);
+        let users = table("crm", "users", 71);
+
+        BinlogBuilder::new()
+            // 10：插入两行
+            .push(gtid(1, 0, 100))
+            .push(query(10, "shop", "BEGIN", 100))
+            .event(&orders)
+            .event(&RowsEventBuilder::insert(&orders).row(int(1)).row(int(2)))
+            .push(xid(1).timestamp(101))
+            // 20：当前库是shop，修改的是crm中的表
+            .push(gtid(2, 0, 105))
+            .push(query(20, "shop", "BEGIN", 105))
+            .event(&users)
+            .event(&RowsEventBuilder::update(&users).update_row(int(1), int(2)))
+            .push(xid(2).timestamp(106))
+            // 10：DDL
+            .push(gtid(3, 1, 110))
+            .push(query(
+                10,
+                "shop",
+                "ALTER TABLE orders ADD COLUMN note TEXT",
+                110,
+            ))
+            // 20：同一个事务中两个行事件
+            .push(gtid(4, 0, 120))
+            .push(query(20, "shop", "BEGIN", 120))
+            .event(&orders)
+            .event(&RowsEventBuilder::delete(&orders).row(int(1)))
+            .event(&RowsEventBuilder::insert(&orders).row(int(3)))
+            .push(xid(4).timestamp(125))
+            // 没有query事件的事务
+            .push(gtid(5, 0, 130))
+            .event(&orders)
+            .event(&RowsEventBuilder::insert(&orders).row(int(4)))
+            .push(xid(5).timestamp(130))
+            .build()
+    }
+
+    #[test]
+    fn activity_is_attributed_to_each_connection() {
+        let session_report = report(&two_connections());
+        let sessions = session_report.sessions().collect::<Vec<_>>();
+        assert_eq!(sessions.len(), 2);
+
+        let first = sessions[0];
+        assert_eq!(first.thread_id, 10);
+        assert_eq!((first.first_timestamp, first.last_timestamp), (100, 110));
+        // BEGIN不算语句
+        assert_eq!(first.statements, 1);
+        assert_eq!(first.transactions, 2);
+        assert_eq!(first.row_events, 1);
+        assert_eq!(
+            (first.inserted_rows, first.updated_rows, first.deleted_rows),
+            (2, 0, 0)
+        );
+        assert_eq!(first.databases, ["shop"]);
+
+        let second = sessions[1];
+        assert_eq!(second.thread_id, 20);
+        assert_eq!((second.first_timestamp, second.last_timestamp), (105, 125));
+        assert_eq!(second.statements, 0);
+        assert_eq!(second.transactions, 2);
+        assert_eq!(second.row_events, 3);
+        assert_eq!(
+            (
+                second.inserted_rows,
+                second.updated_rows,
+                second.deleted_rows
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(second.total_rows(), 3);
+        assert_eq!(second.databases, ["shop", "crm"]);
+
+        assert_eq!(session_report.unattributed_transactions(), 1);
+    }
+
+    #[test]
+    fn table_and_json_output() {
+        let session_report = report(&two_connections());
+
+        let output = session_report.to_string();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].trim_start().starts_with("thread  first activity"));
+        assert!(lines[1].starts_with("        10  1970-01-01 00:01:40  1970-01-01 00:01:50"));
+        assert!(lines[2].ends_with("  shop, crm"));
+        assert_eq!(
+            lines[3],
+            "1 transaction(s) without a query event are not attributed to any thread"
+        );
+
+        let json = serde_json::to_value(session_report.sessions().collect::<Vec<_>>()).unwrap();
+        assert_eq!(json[1]["thread_id"], 20);
+        assert_eq!(json[1]["deleted_rows"], 1);
+        assert_eq!(json[1]["databases"], serde_json::json!(["shop", "crm"]));
+    }
+
+    #[test]
+    fn uncommitted_transaction_at_the_end() {
+        let orders = table("shop", "orders", 70);
+        let binlog = BinlogBuilder::new()
+            .push(gtid(1, 0, 100))
+            .push(query(30, "shop", "BEGIN", 100))
+            .event(&orders)
+            .event(&RowsEventBuilder::insert(&orders).row(int(1)))
+            .build();
+
+        let session_report = report(&binlog);
+        let session = session_report.sessions().next().unwrap();
+        assert_eq!(session.thread_id, 30);
+        assert_eq!(session.transactions, 1);
+        assert_eq!(session.inserted_rows, 1);
+    }
+}
@@ -0,0 +1,341 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Serialize;
+
+pub use crate::fingerprint::fingerprint;
+use crate::model::*;
+
+// 每组保留的例子数，保留执行时间最长的
+const MAX_EXAMPLES: usize = 3;
+
+// 例子中的SQL在human输出中最多显示的字符数
+const MAX_SQL_CHARS: usize = 200;
+
+/// 执行时间达到阈值的一个query事件
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowStatement {
+    pub position: u64,
+    pub timestamp: u32,
+    pub gtid: Option<String>,
+    pub database: String,
+    pub thread_id: u32,
+    /// 秒，来自query事件的execute_time
+    pub execute_time: u32,
+    pub sql: String,
+}
+
+/// 指纹相同的语句
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowStatementGroup {
+    pub fingerprint: String,
+    pub count: u64,
+    pub total_execute_time: u64,
+    pub max_execute_time: u32,
+    pub avg_execute_time: f64,
+    /// 执行时间最长的几条
+    pub examples: Vec<SlowStatement>,
+}
+
+impl SlowStatementGroup {
+    fn add(&mut self, statement: SlowStatement) {
+        self.count += 1;
+        self.total_execute_time += statement.execute_time as u64;
+        self.max_execute_time = self.max_execute_time.max(statement.execute_time);
+        self.avg_execute_time = self.total_execute_time as f64 / self.count as f64;
+
+        // 时间相同时保留先出现的
+        let index = self
+            .examples
+            .iter()
+            .position(|v| v.execute_time < statement.execute_time)
+            .unwrap_or(self.examples.len());
+        if index < MAX_EXAMPLES {
+            self.examples.insert(index, statement);
+            self.examples.truncate(MAX_EXAMPLES);
+        }
+    }
+}
+
+impl Display for SlowStatementGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "count={} total={}s max={}s avg={:.1}s {}",
+            self.count,
+            self.total_execute_time,
+            self.max_execute_time,
+            self.avg_execute_time,
+            self.fingerprint
+        )?;
+
+        for example in &self.examples {
+            let sql = example
+                .sql
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let sql = match sql.char_indices().nth(MAX_SQL_CHARS) {
+                Some((index, _)) => format!("{}...", &sql[..index]),
+                None => sql,
+            };
+
+            writeln!(
+                f,
+                "    at {} gtid={} db={} thread={} exec={}s {}",
+                example.position,
+                example.gtid.as_deref().unwrap_or("-"),
+                example.database,
+                example.thread_id,
+                example.execute_time,
+                sql
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `--slow-report`，按query事件的execute_time找出慢语句，按指纹分组
+/// execute_time只精确到秒，并且只有语句格式的binlog（DDL、statement格式的DML）才有意义
+pub struct SlowReport {
+    min_exec_time: u32,
+    current_gtid: Option<String>,
+    groups: HashMap<String, SlowStatementGroup>,
+}
+
+impl SlowReport {
+    pub fn new(min_exec_time: u32) -> SlowReport {
+        SlowReport {
+            min_exec_time,
+            current_gtid: None,
+            groups: HashMap::new(),
+        }
+    }
+
+    pub fn process(&mut self, header: &EventHeader, position: u64, body: &dyn EventBody) {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+            return;
+        }
+
+        let query_event = match body.downcast_ref::<EventBodyTypeCode2>() {
+            Some(query_event) if query_event.execute_time >= self.min_exec_time => query_event,
+            _ => return,
+        };
+
+        let fingerprint = fingerprint(&query_event.sql);
+        let statement = SlowStatement {
+            position,
+            timestamp: header.timestamp,
+            gtid: self.current_gtid.clone(),
+            database: query_event.database_name.clone(),
+            thread_id: query_event.id_of_thread,
+            execute_time: query_event.execute_time,
+            sql: query_event.sql.clone(),
+        };
+
+        self.groups
+            .entry(fingerprint.clone())
+            .or_insert_with(|| SlowStatementGroup {
+                fingerprint,
+                count: 0,
+                total_execute_time: 0,
+                max_execute_time: 0,
+                avg_execute_time: 0.0,
+                examples: Vec::new(),
+            })
+            .add(statement);
+    }
+
+    /// 按总执行时间从大到小排列，相同时按最长的执行时间
+    pub fn groups(&self) -> Vec<&SlowStatementGroup> {
+        let mut groups = self.groups.values().collect::<Vec<&SlowStatementGroup>>();
+        groups.sort_by(|a, b| {
+            b.total_execute_time
+                .cmp(&a.total_execute_time)
+                .then(b.max_execute_time.cmp(&a.max_execute_time))
+                .then(a.fingerprint.cmp(&b.fingerprint))
+        });
+        groups
+    }
+}
+
+impl Display for SlowReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let groups = self.groups();
+        writeln!(
+            f,
+            "{} statement(s) in {} group(s) with execute_time >= {}s",
+            groups.iter().map(|v| v.count).sum::<u64>(),
+            groups.len(),
+            self.min_exec_time
+        )?;
+
+        for (rank, group) in groups.into_iter().enumerate() {
+            write!(f, "#{} {}", rank + 1, group)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn gtid(seq: u64) -> EventBuilder {
+        let body = [
+            seq.to_le_bytes().to_vec(),
+            1u32.to_le_bytes().to_vec(),
+            vec![0],
+            vec![0; 6],
+        ]
+        .concat();
+        EventBuilder::new(162, body)
+    }
+
+    fn query(sql: &str, execute_time: u32) -> EventBuilder {
+        EventBuilder::from_body(
+            &QueryEventBuilder::new("shop", sql)
+                .thread_id(42)
+                .execute_time(execute_time),
+        )
+    }
+
+    fn report(binlog: &[u8], min_exec_time: u32) -> SlowReport {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut slow_report = SlowReport::new(min_exec_time);
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            slow_report.process(&header, position, body.as_ref());
+            position = header.next_event_position as u64;
+        }
+
+        slow_report
+    }
+
+    fn fixture() -> Vec<u8> {
+        BinlogBuilder::new()
+            .push(gtid(10))
+            .push(query("UPDATE orders SET state = 'paid' WHERE id = 1", 2))
+            .push(gtid(11))
+            .push(query("UPDATE orders SET state = 'paid' WHERE id = 2", 8))
+            .push(gtid(12))
+            .push(query("update  orders set state = 'sent' where id = 3", 5))
+            .push(gtid(13))
+            .push(query("ALTER TABLE orders ADD COLUMN note TEXT", 12))
+            .push(gtid(14))
+            .push(query("UPDATE orders SET state = 'paid' WHERE id = 4", 4))
+            .build()
+    }
+
+    #[test]
+    fn grouped_by_fingerprint_above_the_threshold() {
+        let slow_report = report(&fixture(), 5);
+        let groups = slow_report.groups();
+
+        // id = 1和id = 4低于阈值，大小写和空白不同的UPDATE是同一组
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups[0].fingerprint,
+            fingerprint("UPDATE orders SET state = 'paid' WHERE id = 2")
+        );
+        assert_eq!(
+            groups[0].fingerprint,
+            fingerprint("update  orders set state = 'sent' where id = 3")
+        );
+        assert_eq!(groups[0].count, 2);
+        assert_eq!(groups[0].total_execute_time, 13);
+        assert_eq!(groups[0].max_execute_time, 8);
+        assert_eq!(groups[0].avg_execute_time, 6.5);
+        assert_eq!(
+            groups[0]
+                .examples
+                .iter()
+                .map(|v| (v.execute_time, v.gtid.as_deref().unwrap()))
+                .collect::<Vec<_>>(),
+            [(8, "1-1-11"), (5, "1-1-12")]
+        );
+        assert_eq!(groups[0].examples[0].database, "shop");
+        assert_eq!(groups[0].examples[0].thread_id, 42);
+
+        assert_eq!(groups[1].count, 1);
+        assert_eq!(groups[1].max_execute_time, 12);
+
+        let output = slow_report.to_string();
+        assert!(output.starts_with("3 statement(s) in 2 group(s) with execute_time >= 5s\n#1 "));
+        assert!(output.contains("\n#2 count=1 total=12s max=12s avg=12.0s "));
+
+        let json = serde_json::to_value(&groups).unwrap();
+        assert_eq!(json[0]["count"], 2);
+        assert_eq!(
+            json[0]["examples"][1]["sql"],
+            "update  orders set state = 'sent' where id = 3"
+        );
+    }
+
+    #[test]
+    fn thresholds() {
+        // 阈值是包含的
+        let groups = report(&fixture(), 12)
+            .groups()
+            .into_iter()
+            .map(|v| v.count)
+            .collect::<Vec<_>>();
+        assert_eq!(groups, [1]);
+
+        assert!(report(&fixture(), 13).groups().is_empty());
+        assert_eq!(
+            report(&fixture(), 0)
+                .groups()
+                .iter()
+                .map(|v| v.count)
+                .sum::<u64>(),
+            5
+        );
+    }
+
+    #[test]
+    fn examples_keep_the_slowest() {
+        let mut binlog = BinlogBuilder::new();
+        for (id, execute_time) in [(1, 3), (2, 9), (3, 3), (4, 7), (5, 9)] {
+            binlog = binlog.push(query(
+                &format!("DELETE FROM carts WHERE id = {}", id),
+                execute_time,
+            ));
+        }
+        let slow_report = report(&binlog.build(), 1);
+        let groups = slow_report.groups();
+
+        // 时间相同时保留先出现的，没有GTID
+        assert_eq!(
+            groups[0]
+                .examples
+                .iter()
+                .map(|v| (v.sql.as_str(), v.gtid.is_none()))
+                .collect::<Vec<_>>(),
+            [
+                ("DELETE FROM carts WHERE id = 2", true),
+                ("DELETE FROM carts WHERE id = 5", true),
+                ("DELETE FROM carts WHERE id = 4", true),
+            ]
+        );
+    }
+}
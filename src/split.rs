@@ -0,0 +1,450 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Cursor},
+    path::Path,
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::cli::RangeBound;
+use crate::error::ParseError;
+use crate::model::*;
+use crate::reader::BinlogReader;
+use crate::service::*;
+use crate::util::*;
+use crate::writer::BinlogWriter;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 在哪里切分，都只会在事务的边界（GTID事件）切分
+#[derive(Debug, Clone)]
+pub enum SplitPoints {
+    /// 每个文件达到这个大小后在下一个事务之前切分
+    Size(u64),
+    /// 在这些位置或者GTID所在的事务之前切分
+    At(Vec<RangeBound>),
+}
+
+/// 切分出的一个文件
+#[derive(Debug, Serialize)]
+pub struct SplitPart {
+    pub file_path: String,
+    /// 第一个和最后一个事件在原文件中的位置
+    pub first_position: u64,
+    pub last_position: u64,
+    pub transactions: u64,
+    pub bytes: u64,
+}
+
+impl std::fmt::Display for SplitPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} from={} to={} transactions={} bytes={}",
+            self.file_path, self.first_position, self.last_position, self.transactions, self.bytes
+        )
+    }
+}
+
+/// 写入一个切分出的文件，事件的next_event_position会改成在新文件中的位置，并重新计算校验和
+struct PartWriter {
+    writer: BinlogWriter<BufWriter<File>>,
+    part: SplitPart,
+    // 这个文件中已经写过table map的table_id
+    table_ids: HashSet<u64>,
+}
+
+impl PartWriter {
+    fn create(
+        file_path: String,
+        format_description: &[u8],
+        first_position: u64,
+    ) -> Result<PartWriter, BoxedError> {
+        let writer = BinlogWriter::new(BufWriter::new(File::create(&file_path)?))?;
+        let bytes = writer.position();
+
+        let mut part_writer = PartWriter {
+            writer,
+            part: SplitPart {
+                file_path,
+                first_position,
+                last_position: first_position,
+                transactions: 0,
+                bytes,
+            },
+            table_ids: HashSet::new(),
+        };
+
+        // 第一个文件的FDE就是原文件中的那个，不需要再写一次
+        if !format_description.is_empty() {
+            part_writer.write_event(format_description)?;
+        }
+
+        Ok(part_writer)
+    }
+
+    fn write_event(&mut self, raw_event: &[u8]) -> Result<(), BoxedError> {
+        self.writer.write_raw(raw_event)?;
+        self.part.bytes = self.writer.position();
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<SplitPart, BoxedError> {
+        self.writer.flush()?;
+
+        Ok(self.part)
+    }
+}
+
+/// 把一个binlog文件切分成多个可以单独解析的文件，输出到output_dir中
+/// 每个文件都以magic number和原文件的FDE开头，行事件用到的table map在文件中还没有出现时会在它前面补上
+pub fn split_binlog(
+    binlog_file_path: &str,
+    output_dir: &str,
+    split_points: &SplitPoints,
+) -> Result<Vec<SplitPart>, BoxedError> {
+    let mut file = BinlogReader::open(binlog_file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(binlog_file_path)));
+    }
+
+    std::fs::create_dir_all(output_dir)?;
+
+    let file_name = get_file_name(binlog_file_path);
+    let part_path = |index: usize| {
+        Path::new(output_dir)
+            .join(format!("{}.part{:03}", file_name, index))
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let file_length = file.file_length()?;
+
+    let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+    // 原文件中每个table_id最近一次的table map
+    let mut table_maps: HashMap<u64, Vec<u8>> = HashMap::new();
+    let mut format_description = Vec::new();
+    let mut checksum_length = CHECKSUM_LENGTH;
+
+    let mut parts = Vec::new();
+    let mut writer = PartWriter::create(part_path(1), &[], 4)?;
+    let mut next_split_point = 0;
+
+    let mut offset = 4;
+    while offset < file_length {
+        let event_position = offset;
+
+        let header = get_event_header(&mut file, offset)?;
+        let raw_event = get_event_raw(&mut file, offset, header.event_length)?;
+        offset = header.next_event_position as u64;
+
+        // MariaDB的GTID事件，MySQL的GTID和anonymous GTID事件都是一个事务的开始
+        if matches!(header.type_code, 162 | 33 | 34) {
+            let body = get_event_body(
+                &mut file,
+                event_position,
+                &header,
+                &mut table_structs,
+                checksum_length,
+            )?;
+
+            let gtid = match body.downcast_ref::<EventBodyTypeCode162>() {
+                Some(v) => Some(format!(
+                    "{}-{}-{}",
+                    v.replication_domain_id, header.server_id, v.gtid_sequence
+                )),
+                // anonymous GTID没有GTID
+                None => body
+                    .downcast_ref::<EventBodyTypeCode33>()
+                    .filter(|v| v.gno > 0)
+                    .map(|v| format!("{}:{}", v.uuid, v.gno)),
+            };
+
+            let is_split = match split_points {
+                SplitPoints::Size(size) => {
+                    writer.part.transactions > 0 && writer.part.bytes >= *size
+                }
+                SplitPoints::At(points) => {
+                    let mut is_split = false;
+                    while let Some(point) = points.get(next_split_point) {
+                        let reached = match point {
+                            RangeBound::Position(position) => event_position >= *position,
+                            RangeBound::Gtid(value) => gtid.as_ref() == Some(value),
+                        };
+                        if !reached {
+                            break;
+                        }
+
+                        is_split = true;
+                        next_split_point += 1;
+                    }
+                    is_split && writer.part.transactions > 0
+                }
+            };
+
+            if is_split {
+                parts.push(writer.finish()?);
+                writer = PartWriter::create(
+                    part_path(parts.len() + 1),
+                    &format_description,
+                    event_position,
+                )?;
+            }
+
+            writer.part.transactions += 1;
+        }
+
+        match header.type_code {
+            15 => {
+                format_description = raw_event.clone();
+                let body = get_event_body(
+                    &mut file,
+                    event_position,
+                    &header,
+                    &mut table_structs,
+                    checksum_length,
+                )?;
+                update_checksum_length(&mut checksum_length, body.as_ref());
+            }
+            19 => {
+                let table_id = get_rows_event_table_id(&mut Cursor::new(&raw_event), 19)?;
+                table_maps.insert(table_id, raw_event.clone());
+                writer.table_ids.insert(table_id);
+            }
+            23..=25 | 30..=32 => {
+                let table_id = get_rows_event_table_id(&mut Cursor::new(&raw_event), 19)?;
+                if !writer.table_ids.contains(&table_id) {
+                    if let Some(table_map) = table_maps.get(&table_id) {
+                        writer.write_event(table_map)?;
+                        writer.table_ids.insert(table_id);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        writer.write_event(&raw_event)?;
+        writer.part.last_position = event_position;
+    }
+
+    parts.push(writer.finish()?);
+
+    Ok(parts)
+}
+
+// table map和行事件的body都以6个字节的table_id开头
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::checksum::ChecksumVerifier;
+    use crate::field_type::FieldType;
+
+    const UUID: [u8; 16] = [
+        0x3e, 0x11, 0xfa, 0x47, 0x71, 0xca, 0x11, 0xe1, 0x9e, 0x33, 0xc8, 0x0a, 0xa9, 0x42, 0x95,
+        0x62,
+    ];
+
+    // MySQL的GTID事件，类型2的logical clock
+    fn mysql_gtid(gno: i64) -> EventBuilder {
+        let mut body = vec![1];
+        body.extend(UUID);
+        body.extend(gno.to_le_bytes());
+        body.push(2);
+        body.extend((gno - 1).to_le_bytes());
+        body.extend(gno.to_le_bytes());
+
+        EventBuilder::new(33, body)
+    }
+
+    // MySQL 8.0的binlog，三个事务，table map只在第一个事务中
+    fn mysql_fixture(path: &Path) {
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(81)
+            .column(FieldType::Long, &[]);
+
+        let mut builder = BinlogBuilder::with_server_version("8.0.36")
+            .push(mysql_gtid(1))
+            .event(&QueryEventBuilder::new("shop", "BEGIN"))
+            .event(&table);
+        for gno in 1..=3 {
+            if gno > 1 {
+                builder = builder
+                    .push(mysql_gtid(gno))
+                    .event(&QueryEventBuilder::new("shop", "BEGIN"));
+            }
+            builder = builder
+                .event(
+                    &RowsEventBuilder::insert(&table)
+                        .v2(true)
+                        .row(vec![ColumnValue::Int(gno)]),
+                )
+                .push(EventBuilder::new(16, (gno as u64).to_le_bytes().to_vec()));
+        }
+
+        std::fs::write(path, builder.build()).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("split-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    // 每个事件的类型和行事件中的值，table map不存在或者校验和不一致时会出错
+    fn parse(file_path: &str) -> Vec<(u8, Option<serde_json::Value>)> {
+        let mut file = BinlogReader::open(file_path).unwrap();
+        assert!(check_file_magic_number(&mut file).unwrap());
+        let file_length = file.file_length().unwrap();
+        let mut checksum_verifier = ChecksumVerifier::new();
+        let mut table_structs = HashMap::new();
+        let mut events = Vec::new();
+        let mut position = 4;
+        while position < file_length {
+            let header = get_event_header(&mut file, position).unwrap();
+            checksum_verifier
+                .verify(&mut file, position, &header)
+                .unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            let rows = match header.type_code {
+                23..=25 | 30..=32 => Some(serde_json::to_value(&body).unwrap()["rows"].clone()),
+                _ => None,
+            };
+            events.push((header.type_code, rows));
+            position = header.next_event_position as u64;
+        }
+
+        events
+    }
+
+    #[test]
+    fn split_mysql_binlog_at_gtid() {
+        let dir = temp_dir("gtid");
+        let binlog_path = dir.join("mysql-bin.000001");
+        mysql_fixture(&binlog_path);
+
+        let gtid = "3e11fa47-71ca-11e1-9e33-c80aa9429562:3".to_string();
+        let parts = split_binlog(
+            binlog_path.to_str().unwrap(),
+            dir.join("out").to_str().unwrap(),
+            &SplitPoints::At(vec![RangeBound::Gtid(gtid)]),
+        )
+        .unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].transactions, 2);
+        assert_eq!(parts[1].transactions, 1);
+
+        let type_codes = |events: &[(u8, Option<serde_json::Value>)]| {
+            events.iter().map(|v| v.0).collect::<Vec<_>>()
+        };
+        let first = parse(&parts[0].file_path);
+        assert_eq!(
+            type_codes(&first),
+            vec![15, 33, 2, 19, 30, 16, 33, 2, 30, 16]
+        );
+
+        // 第二个文件从GTID事件开始，行事件之前补上了table map
+        let second = parse(&parts[1].file_path);
+        assert_eq!(type_codes(&second), vec![15, 33, 2, 19, 30, 16]);
+        assert_eq!(
+            second[4].1,
+            Some(serde_json::json!([{"before": null, "after": [3]}]))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_mysql_binlog_by_size() {
+        let dir = temp_dir("size");
+        let binlog_path = dir.join("mysql-bin.000001");
+        mysql_fixture(&binlog_path);
+
+        // 每个事务都会切分到单独的文件中
+        let parts = split_binlog(
+            binlog_path.to_str().unwrap(),
+            dir.join("out").to_str().unwrap(),
+            &SplitPoints::Size(1),
+        )
+        .unwrap();
+
+        assert_eq!(parts.len(), 3);
+        for part in &parts {
+            assert_eq!(part.transactions, 1);
+            let events = parse(&part.file_path);
+            assert_eq!(events.iter().filter(|v| v.0 == 19).count(), 1);
+            assert_eq!(events.iter().filter(|v| v.0 == 30).count(), 1);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_mariadb_binlog_into_three_parts() {
+        let dir = temp_dir("three");
+        let binlog_path = dir.join("mysql-bin.000001");
+
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[]);
+        let mut builder = BinlogBuilder::new();
+        let mut starts = Vec::new();
+        for sequence in 1..=3 {
+            starts.push(builder.position());
+            builder = builder.push(gtid(sequence));
+            // table map只在第一个事务中，之后的文件需要补上
+            if sequence == 1 {
+                builder = builder.event(&table);
+            }
+            builder = builder
+                .event(
+                    &RowsEventBuilder::insert(&table).row(vec![ColumnValue::Int(sequence as i64)]),
+                )
+                .push(EventBuilder::new(16, sequence.to_le_bytes().to_vec()));
+        }
+        std::fs::write(&binlog_path, builder.build()).unwrap();
+
+        let parts = split_binlog(
+            binlog_path.to_str().unwrap(),
+            dir.join("out").to_str().unwrap(),
+            &SplitPoints::At(vec![
+                RangeBound::Position(starts[1]),
+                RangeBound::Position(starts[2]),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(parts.len(), 3);
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(part.transactions, 1);
+            // 第一个文件从原文件的FDE开始
+            assert_eq!(part.first_position, if i == 0 { 4 } else { starts[i] });
+
+            let events = parse(&part.file_path);
+            assert_eq!(
+                events.iter().map(|v| v.0).collect::<Vec<_>>(),
+                vec![15, 162, 19, 23, 16]
+            );
+            assert_eq!(
+                events[3].1,
+                Some(serde_json::json!([{"before": null, "after": [i + 1]}]))
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,892 @@
+use crate::cli::InsertMode;
+use crate::field_type::FieldType;
+use crate::model::*;
+use crate::table_structure::primary_key_columns;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 行事件中的一行对应的SQL，不内联值时以`?`占位，按顺序放在params中
+#[derive(Debug, Clone)]
+pub struct RowStatement {
+    pub sql: String,
+    pub params: Vec<ColumnValue>,
+    /// WHERE条件只能比较浮点数时，可能找不到要修改的行
+    pub warning: Option<String>,
+}
+
+/// 生成SQL时的值，inline为true时直接写成字面量
+struct Params {
+    inline: bool,
+    values: Vec<ColumnValue>,
+}
+
+impl Params {
+    fn bind(&mut self, value: &ColumnValue) -> String {
+        if self.inline {
+            return sql_literal(value);
+        }
+
+        self.values.push(value.clone());
+        "?".to_string()
+    }
+}
+
+/// 按行事件生成INSERT、UPDATE、DELETE语句，column_names是目标表按顺序排列的字段名
+/// WHERE条件见WhereClause，UPDATE只修改修改后的镜像中有的字段
+/// UPDATE和DELETE找不到行时什么都不做，所以只有INSERT需要按insert_mode处理已经存在的行，
+/// insert_mode不是Insert（`--idempotent`）时UPDATE按完整的修改前的镜像定位，见WhereClause::full_image，
+/// 目标库中的行已经是修改后的值时影响0行，和DELETE找不到行一样不是错误
+/// 修改前的镜像中没有任何字段时按推断的binlog_row_image报错，NOBLOB时只能按BLOB以外的字段定位，给出警告
+pub fn row_statements(
+    type_code: u8,
+    rows_event: &EventBodyTypeCode23To25,
+    table_map: &EventBodyTypeCode19,
+    column_names: &[String],
+    inline: bool,
+    insert_mode: InsertMode,
+) -> Result<Vec<RowStatement>, BoxedError> {
+    let table = format!(
+        "{}.{}",
+        quote_identifier(&table_map.database_name),
+        quote_identifier(&table_map.table_name)
+    );
+
+    let primary_key = primary_key_columns(table_map);
+    let field_types = table_map
+        .columns
+        .iter()
+        .map(|v| v.field_type)
+        .collect::<Vec<FieldType>>();
+    let present_for_update = rows_event
+        .columns_used_for_update
+        .as_ref()
+        .unwrap_or(&rows_event.columns_used);
+
+    let row_image = rows_event.row_image;
+    if matches!(type_code, 24 | 25 | 31 | 32) && rows_event.columns_used.count_ones() == 0 {
+        return Err(Box::new(MyError(format!(
+            "the row image of {} is {} and has no column to locate the row",
+            table, row_image
+        ))));
+    }
+
+    let mut result = Vec::new();
+    for row in &rows_event.rows {
+        for image in row.before.iter().chain(row.after.iter()) {
+            check_image(image, column_names, &table)?;
+        }
+
+        let mut params = Params {
+            inline,
+            values: Vec::new(),
+        };
+        let mut warning = None;
+
+        let sql = match (type_code, &row.before, &row.after) {
+            (23 | 30, _, Some(after)) => {
+                // 只插入镜像中的字段，其余的字段使用默认值
+                let present = (0..column_names.len())
+                    .filter(|index| rows_event.columns_used.get(*index).unwrap_or(false))
+                    .collect::<Vec<usize>>();
+                let columns = present
+                    .iter()
+                    .map(|index| quote_identifier(&column_names[*index]))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let values = present
+                    .iter()
+                    .map(|index| params.bind(&after[*index]))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                match insert_mode {
+                    InsertMode::Insert => {
+                        format!("INSERT INTO {} ({}) VALUES ({})", table, columns, values)
+                    }
+                    InsertMode::Replace => {
+                        format!("REPLACE INTO {} ({}) VALUES ({})", table, columns, values)
+                    }
+                    InsertMode::OnDuplicateUpdate => format!(
+                        "INSERT INTO {} ({}) VALUES ({}) ON DUPLICATE KEY UPDATE {}",
+                        table,
+                        columns,
+                        values,
+                        duplicate_assignments(column_names, &present, primary_key.as_deref())
+                    ),
+                }
+            }
+            (24 | 31, Some(before), Some(after)) => {
+                let assignments = column_names
+                    .iter()
+                    .zip(after)
+                    .enumerate()
+                    .filter(|(index, _)| present_for_update.get(*index).unwrap_or(false))
+                    .map(|(_, (name, value))| {
+                        format!("{} = {}", quote_identifier(name), params.bind(value))
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let clause = match insert_mode {
+                    InsertMode::Insert => WhereClause::new(
+                        before,
+                        &rows_event.columns_used,
+                        primary_key.as_deref(),
+                        &field_types,
+                    ),
+                    InsertMode::Replace | InsertMode::OnDuplicateUpdate => WhereClause::full_image(
+                        before,
+                        &rows_event.columns_used,
+                        primary_key.as_deref(),
+                        &field_types,
+                    ),
+                };
+                warning = clause
+                    .warning(&table)
+                    .or_else(|| noblob_warning(&clause, row_image, &table));
+                let condition = clause.render(before, column_names, &mut params)?;
+
+                format!("UPDATE {} SET {} WHERE {}", table, assignments, condition)
+            }
+            (25 | 32, Some(before), _) => {
+                let clause = WhereClause::new(
+                    before,
+                    &rows_event.columns_used,
+                    primary_key.as_deref(),
+                    &field_types,
+                );
+                warning = clause
+                    .warning(&table)
+                    .or_else(|| noblob_warning(&clause, row_image, &table));
+                let condition = clause.render(before, column_names, &mut params)?;
+
+                format!("DELETE FROM {} WHERE {}", table, condition)
+            }
+            _ => continue,
+        };
+
+        result.push(RowStatement {
+            sql,
+            params: params.values,
+            warning,
+        });
+    }
+
+    Ok(result)
+}
+
+// NOBLOB的镜像中没有BLOB字段，没有主键时只有BLOB不同的行无法区分
+fn noblob_warning(
+    clause: &WhereClause,
+    row_image: Inferred<RowImageMode>,
+    table: &str,
+) -> Option<String> {
+    match (clause.limit, row_image.value) {
+        (true, RowImageMode::Noblob) => Some(format!(
+            "the row image of {} is {}, rows that differ only in BLOB columns can not be told apart",
+            table, row_image
+        )),
+        _ => None,
+    }
+}
+
+/// UPDATE和DELETE定位修改前的行的WHERE条件，columns是参与比较的字段序号
+/// 主键的所有字段都在镜像中时按主键定位；否则按镜像中的字段定位，每个镜像只对应一行，加上LIMIT 1
+/// 浮点数不能精确比较，按table map中的类型判断，值是NULL的FLOAT和DOUBLE字段也一样，
+/// 有其他字段时不参与比较，只有浮点数时仍然比较，并给出警告
+/// MINIMAL的镜像中没有的字段不参与比较，而不是当成NULL
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhereClause {
+    pub columns: Vec<usize>,
+    pub limit: bool,
+    pub compares_floats: bool,
+}
+
+impl WhereClause {
+    /// image是按字段顺序排列的修改前的值，present是镜像中有哪些字段（行事件的columns_used），
+    /// field_types是table map中字段的类型
+    pub fn new(
+        image: &[ColumnValue],
+        present: &Bitmap,
+        primary_key: Option<&[usize]>,
+        field_types: &[FieldType],
+    ) -> WhereClause {
+        let in_image = |index: &usize| *index < image.len() && present.get(*index).unwrap_or(false);
+
+        if let Some(primary_key) = primary_key.filter(|v| !v.is_empty() && v.iter().all(in_image)) {
+            return WhereClause {
+                columns: primary_key.to_vec(),
+                limit: false,
+                compares_floats: false,
+            };
+        }
+
+        let columns = (0..image.len()).filter(in_image).collect::<Vec<usize>>();
+        let exact = columns
+            .iter()
+            .copied()
+            .filter(|v| {
+                !matches!(
+                    field_types.get(*v),
+                    Some(FieldType::Float | FieldType::Double)
+                )
+            })
+            .collect::<Vec<usize>>();
+
+        match exact.is_empty() {
+            true => WhereClause {
+                compares_floats: !columns.is_empty(),
+                columns,
+                limit: true,
+            },
+            false => WhereClause {
+                columns: exact,
+                limit: true,
+                compares_floats: false,
+            },
+        }
+    }
+
+    /// 按完整的修改前的镜像定位，有主键时也比较镜像中所有的字段，浮点数和new中一样处理
+    /// 这样只有仍然是修改前的值的行才会被修改，主键的所有字段都参与比较时不需要LIMIT 1
+    pub fn full_image(
+        image: &[ColumnValue],
+        present: &Bitmap,
+        primary_key: Option<&[usize]>,
+        field_types: &[FieldType],
+    ) -> WhereClause {
+        let mut clause = WhereClause::new(image, present, None, field_types);
+        clause.limit = !primary_key
+            .is_some_and(|v| !v.is_empty() && v.iter().all(|index| clause.columns.contains(index)));
+
+        clause
+    }
+
+    /// 只能比较浮点数时的警告
+    pub fn warning(&self, table: &str) -> Option<String> {
+        match self.compares_floats {
+            true => Some(format!(
+                "the row of {} can only be located by floating-point columns and may not match",
+                table
+            )),
+            false => None,
+        }
+    }
+
+    /// NULL写成IS NULL，其他的值按params绑定或者写成字面量，二进制数据是X'...'
+    fn render(
+        &self,
+        image: &[ColumnValue],
+        column_names: &[String],
+        params: &mut Params,
+    ) -> Result<String, BoxedError> {
+        if self.columns.is_empty() {
+            return Err(Box::new(MyError(
+                "the row image has no column to locate the row".to_string(),
+            )));
+        }
+
+        let conditions = self
+            .columns
+            .iter()
+            .map(|index| {
+                let name = quote_identifier(&column_names[*index]);
+                match &image[*index] {
+                    ColumnValue::Null => format!("{} IS NULL", name),
+                    value => format!("{} = {}", name, params.bind(value)),
+                }
+            })
+            .collect::<Vec<String>>();
+
+        let limit = match self.limit {
+            true => " LIMIT 1",
+            false => "",
+        };
+
+        Ok(format!("{}{}", conditions.join(" AND "), limit))
+    }
+
+    /// 内联值的WHERE条件，不包括WHERE关键字
+    pub fn to_sql(
+        &self,
+        image: &[ColumnValue],
+        column_names: &[String],
+    ) -> Result<String, BoxedError> {
+        let mut params = Params {
+            inline: true,
+            values: Vec::new(),
+        };
+
+        self.render(image, column_names, &mut params)
+    }
+}
+
+/// 有主键时只更新镜像中非主键的字段，没有主键的元数据时更新镜像中所有的字段
+fn duplicate_assignments(
+    column_names: &[String],
+    present: &[usize],
+    primary_key: Option<&[usize]>,
+) -> String {
+    let assignments = present
+        .iter()
+        .filter(|index| !primary_key.is_some_and(|v| v.contains(index)))
+        .map(|index| {
+            let name = quote_identifier(&column_names[*index]);
+            format!("{} = VALUES({})", name, name)
+        })
+        .collect::<Vec<String>>();
+
+    match assignments.is_empty() {
+        // 镜像中的字段都是主键时没有要更新的字段，但ON DUPLICATE KEY UPDATE后面不能为空，
+        // 写一个`col = col`，已经存在的行保持原样，和INSERT IGNORE不同的是其他错误仍然会报告
+        true => {
+            let name = quote_identifier(&column_names[0]);
+            format!("{} = {}", name, name)
+        }
+        false => assignments.join(", "),
+    }
+}
+
+// 字段数和目标表不一致、或者有还不支持的字段类型时无法生成正确的SQL
+fn check_image(
+    image: &[ColumnValue],
+    column_names: &[String],
+    table: &str,
+) -> Result<(), BoxedError> {
+    if image.len() != column_names.len() {
+        return Err(Box::new(MyError(format!(
+            "the row of {} has {} columns but the table has {} columns",
+            table,
+            image.len(),
+            column_names.len()
+        ))));
+    }
+
+    if let Some(ColumnValue::Unsupported(type_name)) = image
+        .iter()
+        .find(|v| matches!(v, ColumnValue::Unsupported(_)))
+    {
+        return Err(Box::new(MyError(format!(
+            "column type `{}` of {} is not supported",
+            type_name, table
+        ))));
+    }
+
+    Ok(())
+}
+
+pub fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+/// 字符串按quote_string转义，二进制数据使用十六进制
+pub fn sql_literal(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Null | ColumnValue::Unsupported(_) => "NULL".to_string(),
+        ColumnValue::Int(v) => v.to_string(),
+        ColumnValue::UInt(v) => v.to_string(),
+        ColumnValue::Float(v) => v.to_string(),
+        ColumnValue::Double(v) => v.to_string(),
+        ColumnValue::Decimal(v) => v.clone(),
+        ColumnValue::String(v) | ColumnValue::Temporal(v) => quote_string(v),
+        // TIMESTAMP是UTC的秒数，由服务端按会话的时区转换
+        ColumnValue::RawTemporal(v @ RawTemporal::Timestamp { .. }) => {
+            format!("FROM_UNIXTIME({})", v)
+        }
+        ColumnValue::RawTemporal(v) => quote_string(&v.to_string()),
+        ColumnValue::RawDecimal(v) => v.value.clone(),
+        ColumnValue::Bytes(v) if v.is_empty() => "''".to_string(),
+        ColumnValue::Bytes(v) => format!(
+            "X'{}'",
+            v.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+        ),
+    }
+}
+
+/// 和mysql_real_escape_string转义相同的字符
+pub fn quote_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('\'');
+    for c in value.chars() {
+        match c {
+            '\0' => result.push_str("\\0"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\x1a' => result.push_str("\\Z"),
+            '\\' | '\'' | '"' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('\'');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn string(value: &str) -> ColumnValue {
+        ColumnValue::String(value.to_string())
+    }
+
+    fn all(length: usize) -> Bitmap {
+        Bitmap::repeat(true, length)
+    }
+
+    use FieldType::{Blob, Double, Float, Long, Varchar};
+
+    fn where_sql(
+        image: &[ColumnValue],
+        present: &Bitmap,
+        primary_key: Option<&[usize]>,
+        field_types: &[FieldType],
+        column_names: &[&str],
+    ) -> String {
+        WhereClause::new(image, present, primary_key, field_types)
+            .to_sql(image, &names(column_names))
+            .unwrap()
+    }
+
+    #[test]
+    fn locate_by_primary_key() {
+        let image = [
+            ColumnValue::Int(7),
+            string("it's"),
+            ColumnValue::Double(1.5),
+        ];
+        let clause = WhereClause::new(&image, &all(3), Some(&[0]), &[Long, Varchar, Double]);
+        assert_eq!(
+            clause,
+            WhereClause {
+                columns: vec![0],
+                limit: false,
+                compares_floats: false,
+            }
+        );
+        assert_eq!(clause.warning("`t`"), None);
+        assert_eq!(
+            clause
+                .to_sql(&image, &names(&["id", "name", "score"]))
+                .unwrap(),
+            "`id` = 7"
+        );
+    }
+
+    #[test]
+    fn composite_key_with_null_part() {
+        let image = [
+            ColumnValue::Int(1),
+            ColumnValue::Null,
+            ColumnValue::Bytes(vec![0xca, 0xfe]),
+            string("x"),
+        ];
+        let types = [Long, Long, Blob, Varchar];
+        // 按主键中字段的顺序
+        assert_eq!(
+            where_sql(
+                &image,
+                &all(4),
+                Some(&[2, 0, 1]),
+                &types,
+                &["a", "b", "c", "d"]
+            ),
+            "`c` = X'CAFE' AND `a` = 1 AND `b` IS NULL"
+        );
+        assert_eq!(
+            where_sql(
+                &image,
+                &all(4),
+                Some(&[0, 1]),
+                &types,
+                &["a", "b", "c", "d"]
+            ),
+            "`a` = 1 AND `b` IS NULL"
+        );
+    }
+
+    #[test]
+    fn locate_by_all_columns_without_primary_key() {
+        let image = [
+            ColumnValue::Int(1),
+            ColumnValue::Null,
+            ColumnValue::Float(0.1),
+            ColumnValue::Bytes(vec![0x00, 0xff]),
+            ColumnValue::Bytes(Vec::new()),
+        ];
+        let types = [Long, Varchar, Float, Blob, Blob];
+        let clause = WhereClause::new(&image, &all(5), None, &types);
+        // 浮点数不参与比较
+        assert_eq!(clause.columns, vec![0, 1, 3, 4]);
+        assert_eq!(clause.warning("`t`"), None);
+        assert_eq!(
+            clause
+                .to_sql(&image, &names(&["a", "b", "c", "d", "e"]))
+                .unwrap(),
+            "`a` = 1 AND `b` IS NULL AND `d` = X'00FF' AND `e` = '' LIMIT 1"
+        );
+
+        // 空的主键和没有主键一样
+        assert_eq!(WhereClause::new(&image, &all(5), Some(&[]), &types), clause);
+    }
+
+    #[test]
+    fn float_only_table() {
+        let image = [
+            ColumnValue::Float(0.5),
+            ColumnValue::Double(2.25),
+            ColumnValue::Null,
+        ];
+        // z是值为NULL的DOUBLE字段，也不能单独用来定位
+        let types = [Float, Double, Double];
+        let clause = WhereClause::new(&image, &all(3), None, &types);
+        assert!(clause.compares_floats);
+        assert_eq!(
+            clause.warning("`shop`.`points`").unwrap(),
+            "the row of `shop`.`points` can only be located by floating-point columns and may not match"
+        );
+        assert_eq!(
+            clause.to_sql(&image, &names(&["x", "y", "z"])).unwrap(),
+            "`x` = 0.5 AND `y` = 2.25 AND `z` IS NULL LIMIT 1"
+        );
+
+        // 浮点数是主键时按主键定位，不需要警告
+        let clause = WhereClause::new(&image, &all(3), Some(&[0]), &types);
+        assert!(!clause.compares_floats);
+        assert_eq!(clause.warning("`t`"), None);
+    }
+
+    #[test]
+    fn minimal_image() {
+        // MINIMAL的修改前的镜像中只有主键，其他字段解码为NULL，不能比较
+        let image = [ColumnValue::Int(5), ColumnValue::Null, ColumnValue::Null];
+        let present = Bitmap::from_iter([true, false, false]);
+        let types = [Long, Varchar, Double];
+        assert_eq!(
+            where_sql(
+                &image,
+                &present,
+                Some(&[0]),
+                &types,
+                &["id", "name", "score"]
+            ),
+            "`id` = 5"
+        );
+        // 没有主键的元数据时按镜像中的字段定位
+        assert_eq!(
+            where_sql(&image, &present, None, &types, &["id", "name", "score"]),
+            "`id` = 5 LIMIT 1"
+        );
+
+        // 主键的字段不在镜像中时按镜像中的字段定位
+        let image = [ColumnValue::Null, string("a"), ColumnValue::Null];
+        let present = Bitmap::from_iter([false, true, true]);
+        let types = [Long, Varchar, Varchar];
+        assert_eq!(
+            where_sql(
+                &image,
+                &present,
+                Some(&[0]),
+                &types,
+                &["id", "name", "note"]
+            ),
+            "`name` = 'a' AND `note` IS NULL LIMIT 1"
+        );
+
+        // 镜像中没有字段时无法定位
+        let clause = WhereClause::new(&image, &Bitmap::repeat(false, 3), None, &types);
+        assert!(clause.columns.is_empty());
+        assert!(clause
+            .to_sql(&image, &names(&["id", "name", "note"]))
+            .is_err());
+    }
+
+    mod statements {
+        use std::collections::HashMap;
+        use std::io::Cursor;
+
+        use super::*;
+        use crate::builders::*;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+        // optional metadata中的SIMPLE_PRIMARY_KEY
+        fn table(primary_key: &[u8]) -> TableMapBuilder {
+            let mut optional_metadata = Vec::new();
+            if !primary_key.is_empty() {
+                optional_metadata.push(8);
+                optional_metadata.push(primary_key.len() as u8);
+                optional_metadata.extend(primary_key);
+            }
+
+            TableMapBuilder::new("shop", "users")
+                .table_id(42)
+                .column(FieldType::Long, &[])
+                .column(FieldType::Varchar, &64u16.to_le_bytes())
+                .column(FieldType::Double, &[8])
+                .nullable(true)
+                .optional_metadata(&optional_metadata)
+        }
+
+        // 第二个事件（行事件）生成的SQL
+        fn statements(binlog: &[u8], inline: bool) -> Vec<RowStatement> {
+            statements_with_mode(binlog, inline, InsertMode::Insert)
+        }
+
+        fn statements_with_mode(
+            binlog: &[u8],
+            inline: bool,
+            insert_mode: InsertMode,
+        ) -> Vec<RowStatement> {
+            let mut file = Cursor::new(binlog.to_vec());
+            let mut table_structs = HashMap::new();
+            let mut events = Vec::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                position = header.next_event_position as u64;
+                events.push((header, body));
+            }
+
+            let (header, body) = &events[2];
+            let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+            row_statements(
+                header.type_code,
+                rows_event,
+                &table_structs[&42],
+                &names(&["id", "name", "score"]),
+                inline,
+                insert_mode,
+            )
+            .unwrap()
+        }
+
+        fn row(id: i64, name: &str, score: f64) -> Vec<ColumnValue> {
+            vec![
+                ColumnValue::Int(id),
+                string(name),
+                ColumnValue::Double(score),
+            ]
+        }
+
+        #[test]
+        fn update_and_delete_with_minimal_image() {
+            let table = table(&[0]);
+            let update = RowsEventBuilder::update(&table)
+                .columns_used(&[true, false, false])
+                .columns_used_for_update(&[false, true, false])
+                .update_row(row(5, "", 0.0), row(5, "new", 0.0));
+            let binlog = BinlogBuilder::new().event(&table).event(&update).build();
+
+            let update = &statements(&binlog, false)[0];
+            assert_eq!(
+                update.sql,
+                "UPDATE `shop`.`users` SET `name` = ? WHERE `id` = ?"
+            );
+            assert_eq!(update.params, vec![string("new"), ColumnValue::Int(5)]);
+            assert_eq!(update.warning, None);
+
+            let delete = RowsEventBuilder::delete(&table)
+                .columns_used(&[true, false, false])
+                .row(row(6, "", 0.0));
+            let binlog = BinlogBuilder::new().event(&table).event(&delete).build();
+            assert_eq!(
+                statements(&binlog, true)[0].sql,
+                "DELETE FROM `shop`.`users` WHERE `id` = 6"
+            );
+        }
+
+        #[test]
+        fn update_with_full_image_and_null() {
+            // 没有主键时按镜像中除了浮点数以外的字段定位
+            let table = table(&[]);
+            let update = RowsEventBuilder::update(&table).update_row(
+                vec![
+                    ColumnValue::Int(1),
+                    ColumnValue::Null,
+                    ColumnValue::Double(1.5),
+                ],
+                row(1, "b", 1.5),
+            );
+            let binlog = BinlogBuilder::new().event(&table).event(&update).build();
+
+            assert_eq!(
+                statements(&binlog, true)[0].sql,
+                "UPDATE `shop`.`users` SET `id` = 1, `name` = 'b', `score` = 1.5 \
+                 WHERE `id` = 1 AND `name` IS NULL LIMIT 1"
+            );
+        }
+
+        #[test]
+        fn mysql_rows_events_v2() {
+            // 行事件的类型是30到32，生成的SQL和v1一样
+            let table = table(&[0]);
+            let insert = RowsEventBuilder::insert(&table)
+                .v2(true)
+                .row(row(1, "a", 0.5));
+            let update = RowsEventBuilder::update(&table)
+                .v2(true)
+                .update_row(row(1, "a", 0.5), row(1, "b", 0.5));
+            let delete = RowsEventBuilder::delete(&table)
+                .v2(true)
+                .row(row(1, "b", 0.5));
+
+            let expected = [
+                (insert, "INSERT INTO `shop`.`users` (`id`, `name`, `score`) VALUES (1, 'a', 0.5)"),
+                (
+                    update,
+                    "UPDATE `shop`.`users` SET `id` = 1, `name` = 'b', `score` = 0.5 WHERE `id` = 1",
+                ),
+                (delete, "DELETE FROM `shop`.`users` WHERE `id` = 1"),
+            ];
+            for (rows_event, sql) in expected {
+                let binlog = BinlogBuilder::with_server_version("8.0.36")
+                    .event(&table)
+                    .event(&rows_event)
+                    .build();
+                assert_eq!(statements(&binlog, true)[0].sql, sql);
+            }
+        }
+
+        // 同一个事务的insert、update、delete，按insert_mode生成的语句
+        fn idempotent_sql(table: &TableMapBuilder, insert_mode: InsertMode) -> Vec<String> {
+            let rows_events = [
+                RowsEventBuilder::insert(table).row(row(1, "a", 0.5)),
+                RowsEventBuilder::update(table).update_row(row(1, "a", 0.5), row(1, "b", 0.5)),
+                RowsEventBuilder::delete(table).row(row(1, "b", 0.5)),
+            ];
+
+            rows_events
+                .iter()
+                .map(|rows_event| {
+                    let binlog = BinlogBuilder::new().event(table).event(rows_event).build();
+                    statements_with_mode(&binlog, true, insert_mode)[0]
+                        .sql
+                        .clone()
+                })
+                .collect()
+        }
+
+        #[test]
+        fn idempotent_statements() {
+            let table = table(&[0]);
+            assert_eq!(
+                idempotent_sql(&table, InsertMode::Insert),
+                [
+                    "INSERT INTO `shop`.`users` (`id`, `name`, `score`) VALUES (1, 'a', 0.5)",
+                    "UPDATE `shop`.`users` SET `id` = 1, `name` = 'b', `score` = 0.5 WHERE `id` = 1",
+                    "DELETE FROM `shop`.`users` WHERE `id` = 1",
+                ]
+            );
+
+            // UPDATE按完整的修改前的镜像定位，已经是修改后的值时影响0行，DELETE不变
+            assert_eq!(
+                idempotent_sql(&table, InsertMode::Replace),
+                [
+                    "REPLACE INTO `shop`.`users` (`id`, `name`, `score`) VALUES (1, 'a', 0.5)",
+                    "UPDATE `shop`.`users` SET `id` = 1, `name` = 'b', `score` = 0.5 \
+                     WHERE `id` = 1 AND `name` = 'a'",
+                    "DELETE FROM `shop`.`users` WHERE `id` = 1",
+                ]
+            );
+
+            // 有主键时只更新非主键的字段
+            assert_eq!(
+                idempotent_sql(&table, InsertMode::OnDuplicateUpdate),
+                [
+                    "INSERT INTO `shop`.`users` (`id`, `name`, `score`) VALUES (1, 'a', 0.5) \
+                     ON DUPLICATE KEY UPDATE `name` = VALUES(`name`), `score` = VALUES(`score`)",
+                    "UPDATE `shop`.`users` SET `id` = 1, `name` = 'b', `score` = 0.5 \
+                     WHERE `id` = 1 AND `name` = 'a'",
+                    "DELETE FROM `shop`.`users` WHERE `id` = 1",
+                ]
+            );
+        }
+
+        #[test]
+        fn idempotent_statements_without_primary_key() {
+            assert_eq!(
+                idempotent_sql(&table(&[]), InsertMode::OnDuplicateUpdate),
+                [
+                    "INSERT INTO `shop`.`users` (`id`, `name`, `score`) VALUES (1, 'a', 0.5) \
+                     ON DUPLICATE KEY UPDATE `id` = VALUES(`id`), `name` = VALUES(`name`), \
+                     `score` = VALUES(`score`)",
+                    "UPDATE `shop`.`users` SET `id` = 1, `name` = 'b', `score` = 0.5 \
+                     WHERE `id` = 1 AND `name` = 'a' LIMIT 1",
+                    "DELETE FROM `shop`.`users` WHERE `id` = 1 AND `name` = 'b' LIMIT 1",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn full_image_where_clause() {
+        let image = [ColumnValue::Double(1.5), string("a"), ColumnValue::Null];
+        let types = [Double, Varchar, Varchar];
+
+        // 浮点数的主键不参与比较，只按其他字段定位时需要LIMIT 1
+        let clause = WhereClause::full_image(&image, &all(3), Some(&[0]), &types);
+        assert_eq!(clause.columns, vec![1, 2]);
+        assert!(clause.limit);
+
+        let clause = WhereClause::full_image(&image, &all(3), Some(&[1]), &types);
+        assert_eq!(
+            clause.to_sql(&image, &names(&["x", "y", "z"])).unwrap(),
+            "`y` = 'a' AND `z` IS NULL"
+        );
+
+        // MINIMAL的镜像中没有的字段仍然不参与比较
+        let present = Bitmap::from_iter([false, true, false]);
+        let clause = WhereClause::full_image(&image, &present, Some(&[1]), &types);
+        assert_eq!(clause.columns, vec![1]);
+        assert!(!clause.limit);
+    }
+
+    #[test]
+    fn escape_like_mysql_real_escape_string() {
+        assert_eq!(quote_string("it's"), "'it\\'s'");
+        assert_eq!(
+            quote_string("a\0b\nc\rd\x1ae\\f\"g"),
+            "'a\\0b\\nc\\rd\\Ze\\\\f\\\"g'"
+        );
+        assert_eq!(quote_string("é"), "'é'");
+        assert_eq!(sql_literal(&string("\n")), "'\\n'");
+    }
+
+    #[test]
+    fn negative_decimals_and_unsigned_bigint() {
+        assert_eq!(
+            sql_literal(&ColumnValue::Decimal("-1.05".to_string())),
+            "-1.05"
+        );
+        assert_eq!(
+            sql_literal(&ColumnValue::UInt(u64::MAX)),
+            "18446744073709551615"
+        );
+    }
+
+    #[test]
+    fn duplicate_assignments_when_every_column_is_key() {
+        let column_names = names(&["a", "b"]);
+        assert_eq!(
+            duplicate_assignments(&column_names, &[0, 1], Some(&[0, 1])),
+            "`a` = `a`"
+        );
+        assert_eq!(
+            duplicate_assignments(&column_names, &[0, 1], Some(&[0])),
+            "`b` = VALUES(`b`)"
+        );
+    }
+}
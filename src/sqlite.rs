@@ -0,0 +1,394 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use rusqlite::{params, Connection};
+
+use crate::ddl::{DdlExtractor, DdlStatement};
+use crate::model::*;
+use crate::table_structure::column_names;
+use crate::util::get_event_type_name;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+// 每个事务中写入的事件数，太小时每次提交都要同步磁盘
+const EVENTS_PER_TRANSACTION: u64 = 10000;
+
+const SCHEMA: &str = "
+CREATE TABLE events (
+    \"offset\" INTEGER NOT NULL,
+    ts INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    server_id INTEGER NOT NULL,
+    gtid TEXT,
+    db TEXT,
+    tbl TEXT
+);
+CREATE TABLE row_changes (
+    event_offset INTEGER NOT NULL,
+    op TEXT NOT NULL,
+    row_index INTEGER NOT NULL,
+    column_index INTEGER NOT NULL,
+    column_name TEXT NOT NULL,
+    value_text TEXT,
+    value_blob BLOB,
+    is_null INTEGER NOT NULL,
+    is_before_image INTEGER NOT NULL
+);
+CREATE TABLE ddl (
+    ts INTEGER NOT NULL,
+    gtid TEXT,
+    sql TEXT NOT NULL
+);
+CREATE INDEX row_changes_event_offset ON row_changes (event_offset);
+";
+
+/// `--output sqlite`，把事件、行的每个字段和DDL写入SQLite数据库，方便用SQL查询
+/// 写入按批放在事务中，结束时必须调用finish提交最后一批
+pub struct SqliteWriter {
+    conn: Connection,
+    ddl_extractor: DdlExtractor,
+    current_gtid: Option<String>,
+    // 当前事务中已经写入的事件数
+    pending_events: u64,
+}
+
+impl SqliteWriter {
+    /// 为了不覆盖已有的数据，文件已经存在时返回错误
+    pub fn new(path: &str, include_grants: bool) -> Result<SqliteWriter, BoxedError> {
+        if Path::new(path).exists() {
+            return Err(Box::new(MyError(format!(
+                "result file `{}` already exists",
+                path
+            ))));
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN")?;
+
+        Ok(SqliteWriter {
+            conn,
+            ddl_extractor: DdlExtractor::new(include_grants),
+            current_gtid: None,
+            pending_events: 0,
+        })
+    }
+
+    /// position是事件在文件中的起始位置，写入events的offset
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Result<(), BoxedError> {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+        }
+
+        if let Some(statement) = self.ddl_extractor.process(header, position, body) {
+            self.ddl(&statement)?;
+        }
+
+        let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>();
+        let table_map = match (body.downcast_ref::<EventBodyTypeCode19>(), rows_event) {
+            (Some(table_map), _) => Some(table_map),
+            (None, Some(rows_event)) => table_structs.get(&rows_event.table_id).map(|v| v.as_ref()),
+            (None, None) => None,
+        };
+
+        // query事件记录默认库
+        let (db, tbl) = match (table_map, body.downcast_ref::<EventBodyTypeCode2>()) {
+            (Some(table_map), _) => (
+                Some(table_map.database_name.clone()),
+                Some(table_map.table_name.clone()),
+            ),
+            (None, Some(query_event)) if !query_event.database_name.is_empty() => {
+                (Some(query_event.database_name.clone()), None)
+            }
+            _ => (None, None),
+        };
+
+        self.conn
+            .prepare_cached(
+                "INSERT INTO events (\"offset\", ts, type, server_id, gtid, db, tbl) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?
+            .execute(params![
+                position,
+                header.timestamp,
+                get_event_type_name(header.type_code),
+                header.server_id,
+                self.current_gtid,
+                db,
+                tbl
+            ])?;
+
+        if let (Some(rows_event), Some(table_map)) = (rows_event, table_map) {
+            self.row_changes(header.type_code, position, rows_event, table_map)?;
+        }
+
+        self.pending_events += 1;
+        if self.pending_events >= EVENTS_PER_TRANSACTION {
+            self.conn.execute_batch("COMMIT; BEGIN")?;
+            self.pending_events = 0;
+        }
+
+        Ok(())
+    }
+
+    /// `--ddl-only`时只写入ddl表
+    pub fn ddl(&mut self, statement: &DdlStatement) -> Result<(), BoxedError> {
+        self.conn
+            .prepare_cached("INSERT INTO ddl (ts, gtid, sql) VALUES (?1, ?2, ?3)")?
+            .execute(params![statement.timestamp, statement.gtid, statement.sql])?;
+
+        Ok(())
+    }
+
+    /// 提交最后一批写入
+    pub fn finish(&mut self) -> Result<(), BoxedError> {
+        self.conn.execute_batch("COMMIT")?;
+        self.pending_events = 0;
+
+        Ok(())
+    }
+
+    // 每行的每个字段一条记录，update的修改前后各一条
+    fn row_changes(
+        &mut self,
+        type_code: u8,
+        position: u64,
+        rows_event: &EventBodyTypeCode23To25,
+        table_map: &EventBodyTypeCode19,
+    ) -> Result<(), BoxedError> {
+        let op = match type_code {
+            23 | 30 => "insert",
+            24 | 31 => "update",
+            _ => "delete",
+        };
+
+        let names = column_names(table_map);
+        let after_present = rows_event
+            .columns_used_for_update
+            .as_ref()
+            .unwrap_or(&rows_event.columns_used);
+
+        let mut statement = self.conn.prepare_cached(
+            "INSERT INTO row_changes (event_offset, op, row_index, column_index, column_name, \
+             value_text, value_blob, is_null, is_before_image) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+
+        for (row_index, row) in rows_event.rows.iter().enumerate() {
+            let images = [(&row.before, true), (&row.after, false)];
+            for (image, is_before_image) in images {
+                let image = match image {
+                    Some(image) => image,
+                    None => continue,
+                };
+                // 只记录镜像中的字段，update修改后的镜像按columns_used_for_update
+                let present = match is_before_image || row.before.is_none() {
+                    true => &rows_event.columns_used,
+                    false => after_present,
+                };
+
+                for (column_index, value) in image.iter().enumerate() {
+                    if !present.get(column_index).unwrap_or(false) {
+                        continue;
+                    }
+
+                    let (value_text, value_blob) = match value {
+                        ColumnValue::Null | ColumnValue::Unsupported(_) => (None, None),
+                        ColumnValue::Int(v) => (Some(v.to_string()), None),
+                        ColumnValue::UInt(v) => (Some(v.to_string()), None),
+                        ColumnValue::Float(v) => (Some(v.to_string()), None),
+                        ColumnValue::Double(v) => (Some(v.to_string()), None),
+                        ColumnValue::Decimal(v)
+                        | ColumnValue::String(v)
+                        | ColumnValue::Temporal(v) => (Some(v.clone()), None),
+                        ColumnValue::RawTemporal(v) => (Some(v.to_string()), None),
+                        ColumnValue::RawDecimal(v) => (Some(v.value.clone()), None),
+                        ColumnValue::Bytes(v) => (None, Some(v.clone())),
+                    };
+
+                    statement.execute(params![
+                        position,
+                        op,
+                        row_index,
+                        column_index,
+                        names.get(column_index),
+                        value_text,
+                        value_blob,
+                        matches!(value, ColumnValue::Null),
+                        is_before_image
+                    ])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn orders() -> TableMapBuilder {
+        let mut optional_metadata = vec![4u8, 0];
+        for name in ["id", "name"] {
+            optional_metadata.push(name.len() as u8);
+            optional_metadata.extend(name.as_bytes());
+        }
+        optional_metadata[1] = (optional_metadata.len() - 2) as u8;
+
+        TableMapBuilder::new("shop", "orders")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .optional_metadata(&optional_metadata)
+    }
+
+    fn export(binlog: &[u8], name: &str) -> Connection {
+        let path = std::env::temp_dir().join(format!("sqlite-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let mut writer = SqliteWriter::new(path.to_str().unwrap(), false).unwrap();
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            writer
+                .process(&header, position, body.as_ref(), &table_structs)
+                .unwrap();
+            position = header.next_event_position as u64;
+        }
+        writer.finish().unwrap();
+
+        // 已经存在的文件不会被覆盖
+        assert!(SqliteWriter::new(path.to_str().unwrap(), false).is_err());
+
+        let conn = Connection::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        conn
+    }
+
+    #[test]
+    fn export_and_query() {
+        let table_map = orders();
+        let binlog = BinlogBuilder::new()
+            .push(gtid(1).server_id(3).timestamp(1700000000))
+            .event(&table_map)
+            .event(&RowsEventBuilder::insert(&table_map).row(vec![
+                ColumnValue::Int(1),
+                ColumnValue::String("apple".to_string()),
+            ]))
+            .event(&RowsEventBuilder::update(&table_map).update_row(
+                vec![
+                    ColumnValue::Int(1),
+                    ColumnValue::String("apple".to_string()),
+                ],
+                vec![ColumnValue::Int(1), ColumnValue::Null],
+            ))
+            .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()))
+            .push(gtid(2).server_id(3).timestamp(1700000060))
+            .push(
+                EventBuilder::from_body(&QueryEventBuilder::new(
+                    "shop",
+                    "ALTER TABLE orders ADD COLUMN note TEXT",
+                ))
+                .server_id(3)
+                .timestamp(1700000060),
+            )
+            .build();
+
+        let conn = export(&binlog, "export_and_query");
+
+        let events: i64 = conn
+            .query_row("SELECT COUNT(*) FROM events", [], |v| v.get(0))
+            .unwrap();
+        assert_eq!(events, 8);
+
+        // 表中的事件带上GTID、库名和表名
+        let mut statement = conn
+            .prepare("SELECT type, gtid FROM events WHERE db = 'shop' AND tbl = 'orders' ORDER BY \"offset\"")
+            .unwrap();
+        let rows = statement
+            .query_map([], |v| Ok((v.get::<_, String>(0)?, v.get::<_, String>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("TABLE_MAP_EVENT".to_string(), "0-3-1".to_string()),
+                ("WRITE_ROWS_EVENT_V1".to_string(), "0-3-1".to_string()),
+                ("UPDATE_ROWS_EVENT_V1".to_string(), "0-3-1".to_string()),
+            ]
+        );
+
+        // name在update前后的值
+        let mut statement = conn
+            .prepare(
+                "SELECT op, value_text, is_null, is_before_image FROM row_changes \
+                 WHERE column_name = 'name' ORDER BY event_offset, is_before_image DESC",
+            )
+            .unwrap();
+        let rows = statement
+            .query_map([], |v| {
+                Ok((
+                    v.get::<_, String>(0)?,
+                    v.get::<_, Option<String>>(1)?,
+                    v.get::<_, bool>(2)?,
+                    v.get::<_, bool>(3)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (
+                    "insert".to_string(),
+                    Some("apple".to_string()),
+                    false,
+                    false
+                ),
+                ("update".to_string(), Some("apple".to_string()), false, true),
+                ("update".to_string(), None, true, false),
+            ]
+        );
+
+        let ddl: (i64, String, String) = conn
+            .query_row("SELECT ts, gtid, sql FROM ddl", [], |v| {
+                Ok((v.get(0)?, v.get(1)?, v.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(
+            ddl,
+            (
+                1700000060,
+                "0-3-2".to_string(),
+                "ALTER TABLE orders ADD COLUMN note TEXT".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt::Display;
+
+#[cfg(feature = "sqlparser")]
+use std::ops::ControlFlow;
+
+#[cfg(feature = "sqlparser")]
+use sqlparser::{
+    ast::{ObjectName, Query, Visit, Visitor},
+    dialect::MySqlDialect,
+    parser::Parser,
+};
+
+use crate::model::*;
+
+/// 语句中引用的一张表
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementTable {
+    pub database: String,
+    pub table: String,
+}
+
+impl Display for StatementTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.database, self.table)
+    }
+}
+
+/// 用SQL解析器从query事件和annotate事件的语句中找出引用的表
+///
+/// 没有写库名的表按语句的当前库补全：query事件用事件中的库，annotate事件没有库，用事务中BEGIN的库。
+/// 每个query事件都要经过process才能知道annotate的库。
+/// 需要打开sqlparser特性，否则process总是返回None，也不计入unparsed
+#[derive(Default)]
+pub struct StatementTableExtractor {
+    // 最近一个query事件的库
+    database: String,
+    unparsed: u64,
+}
+
+impl StatementTableExtractor {
+    pub fn new() -> StatementTableExtractor {
+        StatementTableExtractor::default()
+    }
+
+    /// 返回语句引用的表，按第一次出现的顺序。不是语句的事件、事务控制语句和无法解析的语句为None
+    pub fn process(&mut self, body: &dyn EventBody) -> Option<Vec<StatementTable>> {
+        let sql = match body.downcast_ref::<EventBodyTypeCode2>() {
+            Some(query_event) => {
+                self.database = query_event.database_name.clone();
+                &query_event.sql
+            }
+            None => &body.downcast_ref::<EventBodyTypeCode160>()?.sql,
+        };
+
+        if is_transaction_control(sql) {
+            return None;
+        }
+
+        let tables = extract_tables(sql, &self.database);
+        if tables.is_none() && cfg!(feature = "sqlparser") {
+            self.unparsed += 1;
+        }
+
+        tables
+    }
+
+    /// 无法解析的语句数
+    pub fn unparsed(&self) -> u64 {
+        self.unparsed
+    }
+}
+
+// BEGIN、COMMIT和XA语句不引用表，MySQL方言也不支持XA
+fn is_transaction_control(sql: &str) -> bool {
+    let sql = sql.trim();
+    let keyword = sql.split_whitespace().next().unwrap_or_default();
+
+    sql.is_empty()
+        || ["BEGIN", "COMMIT", "ROLLBACK", "XA"]
+            .iter()
+            .any(|v| keyword.eq_ignore_ascii_case(v))
+}
+
+/// 解析sql，返回引用的表，没有库名的表属于database。无法解析时为None
+pub fn extract_tables(sql: &str, database: &str) -> Option<Vec<StatementTable>> {
+    #[cfg(feature = "sqlparser")]
+    {
+        let statements = Parser::parse_sql(&MySqlDialect {}, sql).ok()?;
+
+        let mut collector = RelationCollector {
+            database,
+            ctes: Vec::new(),
+            tables: Vec::new(),
+        };
+        let _ = statements.visit(&mut collector);
+
+        Some(collector.tables)
+    }
+
+    #[cfg(not(feature = "sqlparser"))]
+    {
+        let _ = (sql, database);
+        None
+    }
+}
+
+// WITH定义的名字不是表，访问到WITH时记下来，之后同名的引用不计入
+#[cfg(feature = "sqlparser")]
+struct RelationCollector<'a> {
+    database: &'a str,
+    ctes: Vec<String>,
+    tables: Vec<StatementTable>,
+}
+
+#[cfg(feature = "sqlparser")]
+impl Visitor for RelationCollector<'_> {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            self.ctes
+                .extend(with.cte_tables.iter().map(|v| v.alias.name.value.clone()));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<()> {
+        let table = match relation.0.as_slice() {
+            [table] if self.ctes.contains(&table.value) => return ControlFlow::Continue(()),
+            [table] => StatementTable {
+                database: self.database.to_string(),
+                table: table.value.clone(),
+            },
+            [.., database, table] => StatementTable {
+                database: database.value.clone(),
+                table: table.value.clone(),
+            },
+            [] => return ControlFlow::Continue(()),
+        };
+
+        if !self.tables.contains(&table) {
+            self.tables.push(table);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    #[cfg(feature = "sqlparser")]
+    fn table(database: &str, table: &str) -> StatementTable {
+        StatementTable {
+            database: database.to_string(),
+            table: table.to_string(),
+        }
+    }
+
+    // 解码一个query事件，交给extractor
+    fn process(
+        extractor: &mut StatementTableExtractor,
+        database: &str,
+        sql: &str,
+    ) -> Option<Vec<StatementTable>> {
+        process_event(
+            extractor,
+            EventBuilder::from_body(&QueryEventBuilder::new(database, sql)),
+        )
+    }
+
+    fn process_event(
+        extractor: &mut StatementTableExtractor,
+        event: EventBuilder,
+    ) -> Option<Vec<StatementTable>> {
+        let binlog = BinlogBuilder::new().push(event).build();
+        let mut file = Cursor::new(&binlog);
+        let position = 4;
+        let header = get_event_header(&mut file, position).unwrap();
+        let position = header.next_event_position as u64;
+        let header = get_event_header(&mut file, position).unwrap();
+        let body = get_event_body(
+            &mut file,
+            position,
+            &header,
+            &mut HashMap::new(),
+            CHECKSUM_LENGTH,
+        )
+        .unwrap();
+
+        extractor.process(body.as_ref())
+    }
+
+    #[test]
+    fn transaction_control_is_skipped() {
+        let mut extractor = StatementTableExtractor::new();
+        for sql in ["BEGIN", "COMMIT", " rollback ", "XA START 'x'", ""] {
+            assert_eq!(process(&mut extractor, "shop", sql), None, "{}", sql);
+        }
+        assert_eq!(extractor.unparsed(), 0);
+    }
+
+    #[cfg(feature = "sqlparser")]
+    #[test]
+    fn tables_of_statements() {
+        let mut extractor = StatementTableExtractor::new();
+
+        // 多表JOIN，没有库名的表按当前库补全
+        assert_eq!(
+            process(
+                &mut extractor,
+                "shop",
+                "SELECT o.id FROM orders o JOIN crm.users u ON o.user_id = u.id LEFT JOIN items i ON i.order_id = o.id"
+            ),
+            Some(vec![
+                table("shop", "orders"),
+                table("crm", "users"),
+                table("shop", "items")
+            ])
+        );
+        assert_eq!(
+            process(
+                &mut extractor,
+                "shop",
+                "DELETE o FROM orders o JOIN archive.orders a ON a.id = o.id WHERE a.state = 'done'"
+            ),
+            Some(vec![table("shop", "orders"), table("archive", "orders")])
+        );
+        assert_eq!(
+            process(
+                &mut extractor,
+                "",
+                "UPDATE `crm`.`users` SET name = 'x' WHERE id IN (SELECT user_id FROM shop.orders)"
+            ),
+            Some(vec![table("crm", "users"), table("shop", "orders")])
+        );
+        // WITH定义的名字不是表
+        assert_eq!(
+            process(
+                &mut extractor,
+                "shop",
+                "INSERT INTO totals WITH recent AS (SELECT * FROM orders) SELECT COUNT(*) FROM recent"
+            ),
+            Some(vec![table("shop", "totals"), table("shop", "orders")])
+        );
+        assert_eq!(extractor.unparsed(), 0);
+    }
+
+    #[cfg(feature = "sqlparser")]
+    #[test]
+    fn unparseable_statement_is_counted() {
+        let mut extractor = StatementTableExtractor::new();
+
+        assert_eq!(process(&mut extractor, "shop", "HANDLER orders OPEN"), None);
+        assert_eq!(extractor.unparsed(), 1);
+        assert_eq!(
+            process(&mut extractor, "shop", "DELETE FROM orders WHERE id = 1"),
+            Some(vec![table("shop", "orders")])
+        );
+        assert_eq!(extractor.unparsed(), 1);
+    }
+
+    // annotate事件没有库，用之前BEGIN的库
+    #[cfg(feature = "sqlparser")]
+    #[test]
+    fn annotate_uses_the_database_of_begin() {
+        let mut extractor = StatementTableExtractor::new();
+        let annotate = || EventBuilder::new(160, b"DELETE FROM users WHERE id = 1".to_vec());
+
+        assert_eq!(process(&mut extractor, "crm", "BEGIN"), None);
+        assert_eq!(
+            process_event(&mut extractor, annotate()),
+            Some(vec![table("crm", "users")])
+        );
+
+        assert_eq!(process(&mut extractor, "shop", "BEGIN"), None);
+        assert_eq!(
+            process_event(&mut extractor, annotate()),
+            Some(vec![table("shop", "users")])
+        );
+    }
+
+    #[cfg(not(feature = "sqlparser"))]
+    #[test]
+    fn nothing_without_the_feature() {
+        let mut extractor = StatementTableExtractor::new();
+        assert_eq!(
+            process(&mut extractor, "shop", "DELETE FROM orders WHERE id = 1"),
+            None
+        );
+        assert_eq!(extractor.unparsed(), 0);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{env, io::IsTerminal};
+
+use crate::model::{EventBody, EventHeader, EventTime};
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+/// `--color`的取值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// 输出到终端并且没有设置NO_COLOR时才使用颜色
+    Auto,
+    Always,
+    Never,
+}
+
+/// 在Debug格式的输出上加颜色，不改变输出的文本内容
+/// 关闭颜色时输出和原来的纯文本完全一致
+pub struct Styler {
+    enabled: bool,
+}
+
+impl Styler {
+    pub fn new(choice: ColorChoice) -> Styler {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        };
+
+        Styler { enabled }
+    }
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        if !self.enabled || text.is_empty() {
+            return text.to_string();
+        }
+
+        // 缩进不上色，只给内容加颜色
+        let content = text.trim_start();
+        let indent = &text[..text.len() - content.len()];
+
+        format!("{}{}{}{}", indent, color, content, RESET)
+    }
+
+    /// 一个事件的header和body，格式和`{:#?}`相同
+    /// 还没有实现解析的字段类型属于警告，用红色标出
+    /// query事件有Q_HRNOW时在timestamp后面注明带微秒的时间
+    pub fn event(&self, header: &EventHeader, body: &dyn EventBody) -> String {
+        let mut result = String::new();
+        let event_time = EventTime::new(header, body);
+
+        for line in format!("{:#?}", header).lines() {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("timestamp:") && event_time.microseconds.is_some() {
+                let line = format!("{} // {} UTC", line, event_time);
+                result.push_str(&self.paint(&line, DIM));
+            } else if trimmed.starts_with("timestamp:")
+                || trimmed.starts_with("next_event_position:")
+            {
+                result.push_str(&self.paint(line, DIM));
+            } else {
+                result.push_str(line);
+            }
+            result.push('\n');
+        }
+
+        // 行事件按增删改区分颜色，query事件用青色，GTID和XID这类事务边界事件整体变暗
+        let name_color = match header.type_code {
+            23 | 30 => Some(GREEN),
+            24 | 31 => Some(YELLOW),
+            25 | 32 => Some(RED),
+            2 => Some(CYAN),
+            _ => None,
+        };
+        let is_dim = matches!(header.type_code, 16 | 162);
+
+        for (index, line) in format!("{:#?}", body).lines().enumerate() {
+            if is_dim {
+                result.push_str(&self.paint(line, DIM));
+            } else if let (0, Some(color)) = (index, name_color) {
+                result.push_str(&self.paint(line, color));
+            } else if line.contains("is not implement") {
+                result.push_str(&self.paint(line, RED));
+            } else {
+                result.push_str(line);
+            }
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试中stdout不是终端，相当于输出到管道
+    #[test]
+    fn auto_without_terminal() {
+        assert!(!Styler::new(ColorChoice::Auto).enabled);
+        assert!(Styler::new(ColorChoice::Always).enabled);
+        assert!(!Styler::new(ColorChoice::Never).enabled);
+    }
+
+    #[test]
+    fn paint_keeps_indent() {
+        let styler = Styler::new(ColorChoice::Always);
+        assert_eq!(styler.paint("    a: 1,", RED), "    \x1b[31ma: 1,\x1b[0m");
+        assert_eq!(styler.paint("", RED), "");
+        assert_eq!(
+            Styler::new(ColorChoice::Never).paint("    a: 1,", RED),
+            "    a: 1,"
+        );
+    }
+
+    mod events {
+        use std::collections::HashMap;
+        use std::io::Cursor;
+
+        use super::*;
+        use crate::builders::*;
+        use crate::field_type::FieldType;
+        use crate::model::ColumnValue;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+        fn strip_colors(text: &str) -> String {
+            [RESET, DIM, RED, GREEN, YELLOW, CYAN]
+                .iter()
+                .fold(text.to_string(), |text, color| text.replace(color, ""))
+        }
+
+        // 每个事件用三种方式输出：原来的纯文本、never和always
+        fn render(binlog: &[u8]) -> Vec<(u8, String, String, String)> {
+            let never = Styler::new(ColorChoice::Never);
+            let always = Styler::new(ColorChoice::Always);
+
+            let mut file = Cursor::new(binlog);
+            let mut table_structs = HashMap::new();
+            let mut result = Vec::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+
+                result.push((
+                    header.type_code,
+                    format!("{:#?}\n{:#?}\n", header, body),
+                    never.event(&header, body.as_ref()),
+                    always.event(&header, body.as_ref()),
+                ));
+
+                position = header.next_event_position as u64;
+            }
+
+            result
+        }
+
+        fn fixture(binlog: BinlogBuilder, v2: bool) -> Vec<u8> {
+            let table = TableMapBuilder::new("shop", "orders")
+                .table_id(70)
+                .column(FieldType::Long, &[])
+                .column(FieldType::Varchar, &64u16.to_le_bytes())
+                .nullable(true);
+            let one = vec![ColumnValue::Int(1), ColumnValue::String("a".to_string())];
+            let two = vec![ColumnValue::Int(1), ColumnValue::Null];
+
+            binlog
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&table)
+                .event(&RowsEventBuilder::insert(&table).v2(v2).row(one.clone()))
+                .event(&table)
+                .event(
+                    &RowsEventBuilder::update(&table)
+                        .v2(v2)
+                        .update_row(one, two.clone()),
+                )
+                .event(&table)
+                .event(&RowsEventBuilder::delete(&table).v2(v2).row(two))
+                .push(EventBuilder::new(16, 9u64.to_le_bytes().to_vec()))
+                .build()
+        }
+
+        #[test]
+        fn never_is_plain_output() {
+            let mariadb = fixture(BinlogBuilder::new(), false);
+            let mysql = fixture(BinlogBuilder::with_server_version("8.0.36"), true);
+
+            for binlog in [mariadb, mysql] {
+                for (type_code, plain, never, always) in render(&binlog) {
+                    assert_eq!(never, plain, "type code {}", type_code);
+                    assert_ne!(always, plain, "type code {}", type_code);
+                    assert_eq!(strip_colors(&always), plain, "type code {}", type_code);
+                }
+            }
+        }
+
+        #[test]
+        fn event_name_colors() {
+            let mariadb = fixture(BinlogBuilder::new(), false);
+            let mysql = fixture(BinlogBuilder::with_server_version("8.0.36"), true);
+
+            for binlog in [mariadb, mysql] {
+                for (type_code, _, _, always) in render(&binlog) {
+                    // header之后的第一行是事件名
+                    let name = always.lines().skip_while(|v| *v != "}").nth(1).unwrap();
+                    let color = match type_code {
+                        2 => Some(CYAN),
+                        16 => Some(DIM),
+                        23 | 30 => Some(GREEN),
+                        24 | 31 => Some(YELLOW),
+                        25 | 32 => Some(RED),
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        assert!(name.starts_with(color), "type code {}: {}", type_code, name);
+                    }
+                }
+            }
+        }
+    }
+}
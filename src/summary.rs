@@ -0,0 +1,477 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use serde::Serialize;
+
+use crate::error::ParseError;
+use crate::flavor::ServerVersion;
+use crate::model::*;
+use crate::reader::BinlogReader;
+use crate::service::{deal_type_code_15, deal_type_code_162, deal_type_code_163};
+use crate::util::{check_file_magic_number, format_timestamp};
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+// FDE中的flag，服务端打开binlog时设置，正常关闭时清除
+const LOG_EVENT_BINLOG_IN_USE_F: u16 = 0x1;
+
+// FDE body的最后是1个字节的校验算法和4个字节的校验和
+const BINLOG_CHECKSUM_ALG_CRC32: u8 = 1;
+
+// 文件开头读取的长度，FDE、GTID_LIST和第一个GTID事件一般都在这里面
+const HEAD_LENGTH: u64 = 64 * 1024;
+
+// 文件末尾第一次读取的长度，找不到完整的事件或者GTID时按4倍扩大，最多到MAX_TAIL_LENGTH
+const TAIL_LENGTH: u64 = 64 * 1024;
+const MAX_TAIL_LENGTH: u64 = 16 * 1024 * 1024;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// GTID和它所在的位置
+#[derive(Debug, Clone, Serialize)]
+pub struct GtidPosition {
+    pub gtid: String,
+    pub position: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub file_path: String,
+    pub file_size: u64,
+    pub server_version: String,
+    pub binlog_version: u16,
+    /// `none`或者`crc32`
+    pub checksum_algorithm: String,
+    pub in_use_flag: bool,
+    pub first_timestamp: Option<u32>,
+    pub last_timestamp: Option<u32>,
+    /// GTID_LIST事件中的GTID（这个文件之前的位置），没有GTID_LIST事件时为None
+    pub gtid_list: Option<Vec<String>>,
+    pub first_gtid: Option<GtidPosition>,
+    /// 只在文件末尾读取的范围内查找，最后一个事务太大时可能为None
+    pub last_gtid: Option<GtidPosition>,
+    pub last_event_position: Option<u64>,
+    /// 最后一个完整事件结束的位置，等于file_size时说明文件末尾没有写到一半的事件
+    pub valid_length: u64,
+}
+
+impl std::fmt::Display for FileSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "file: {} ({} bytes)", self.file_path, self.file_size)?;
+        writeln!(
+            f,
+            "server version: {}, binlog version: {}, checksum: {}",
+            self.server_version, self.binlog_version, self.checksum_algorithm
+        )?;
+        writeln!(
+            f,
+            "time range: {} .. {}",
+            self.first_timestamp
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".to_string()),
+            self.last_timestamp
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".to_string())
+        )?;
+
+        match &self.gtid_list {
+            Some(gtids) if gtids.is_empty() => writeln!(f, "gtid list: (empty)")?,
+            Some(gtids) => writeln!(f, "gtid list: {}", gtids.join(","))?,
+            None => writeln!(f, "gtid list: -")?,
+        }
+        writeln!(f, "first gtid: {}", format_gtid(&self.first_gtid))?;
+        writeln!(f, "last gtid: {}", format_gtid(&self.last_gtid))?;
+
+        writeln!(
+            f,
+            "last event at {}",
+            self.last_event_position
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        )?;
+        if self.valid_length < self.file_size {
+            writeln!(
+                f,
+                "incomplete tail from {} ({} bytes)",
+                self.valid_length,
+                self.file_size - self.valid_length
+            )?;
+        }
+        write!(f, "in use flag: {}", self.in_use_flag)
+    }
+}
+
+fn format_gtid(gtid: &Option<GtidPosition>) -> String {
+    match gtid {
+        Some(gtid) => format!("{} at {}", gtid.gtid, gtid.position),
+        None => "-".to_string(),
+    }
+}
+
+/// 文件开头或者末尾读取到内存中的一段
+struct Window {
+    start: u64,
+    buffer: Vec<u8>,
+}
+
+impl Window {
+    fn read(file: &mut BinlogReader, start: u64, end: u64) -> Result<Window, BoxedError> {
+        let mut buffer = vec![0u8; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buffer)?;
+
+        Ok(Window { start, buffer })
+    }
+
+    fn end(&self) -> u64 {
+        self.start + self.buffer.len() as u64
+    }
+
+    /// offset处是完整的事件时返回事件的字节，检查的内容和repair一样
+    fn event_at(&self, offset: u64, has_checksum: bool) -> Option<&[u8]> {
+        if offset < self.start || offset + EVENT_HEADER_LENGTH > self.end() {
+            return None;
+        }
+
+        let header = &self.buffer[(offset - self.start) as usize..];
+        let event_length = u32::from_le_bytes(header[9..13].try_into().ok()?) as u64;
+        let next_event_position = u32::from_le_bytes(header[13..17].try_into().ok()?) as u64;
+
+        let min_length = EVENT_HEADER_LENGTH + if has_checksum { 4 } else { 0 };
+        if event_length < min_length
+            || offset + event_length > self.end()
+            || next_event_position != offset + event_length
+        {
+            return None;
+        }
+
+        let event = &header[..event_length as usize];
+        if has_checksum {
+            let length = event.len();
+            let checksum = u32::from_le_bytes(event[length - 4..].try_into().ok()?);
+            if crc32fast::hash(&event[..length - 4]) != checksum {
+                return None;
+            }
+        }
+
+        Some(event)
+    }
+}
+
+/// 去掉header和最后的校验和之后的body，event是event_at返回的完整事件
+fn event_body(event: &[u8], has_checksum: bool) -> Vec<u8> {
+    let end = event.len() - if has_checksum { 4 } else { 0 };
+
+    event[EVENT_HEADER_LENGTH as usize..end].to_vec()
+}
+
+/// 事件中的GTID，server id在事件头中
+fn event_gtid(event: &[u8], has_checksum: bool) -> Option<String> {
+    let server_id = u32::from_le_bytes(event[5..9].try_into().ok()?);
+    let body = deal_type_code_162(event_body(event, has_checksum)).ok()?;
+    let gtid_event = body.downcast_ref::<EventBodyTypeCode162>()?;
+
+    Some(format!(
+        "{}-{}-{}",
+        gtid_event.replication_domain_id, server_id, gtid_event.gtid_sequence
+    ))
+}
+
+fn event_timestamp(event: &[u8]) -> Option<u32> {
+    match u32::from_le_bytes(event[0..4].try_into().ok()?) {
+        0 => None,
+        timestamp => Some(timestamp),
+    }
+}
+
+/// 文件末尾的扫描结果
+#[derive(Default)]
+struct Tail {
+    last_event_position: Option<u64>,
+    last_timestamp: Option<u32>,
+    last_gtid: Option<GtidPosition>,
+    valid_length: u64,
+}
+
+/// 从窗口开始逐字节查找事件边界，找到之后按事件长度往后走到最后一个完整的事件
+/// 没有校验和时单个事件头可能是巧合，要求后面紧跟着另一个完整的事件或者正好到窗口结束
+fn scan_tail(window: &Window, has_checksum: bool) -> Tail {
+    let mut tail = Tail::default();
+
+    let mut offset = window.start;
+    let mut synced = false;
+    while offset < window.end() {
+        let event = match window.event_at(offset, has_checksum) {
+            Some(event) => event,
+            None => {
+                synced = false;
+                offset += 1;
+                continue;
+            }
+        };
+
+        let next_offset = offset + event.len() as u64;
+        if !synced
+            && !has_checksum
+            && next_offset != window.end()
+            && window.event_at(next_offset, has_checksum).is_none()
+        {
+            offset += 1;
+            continue;
+        }
+        synced = true;
+
+        tail.last_event_position = Some(offset);
+        tail.valid_length = next_offset;
+        if let Some(timestamp) = event_timestamp(event) {
+            tail.last_timestamp = Some(timestamp);
+        }
+        if event[4] == 162 {
+            if let Some(gtid) = event_gtid(event, has_checksum) {
+                tail.last_gtid = Some(GtidPosition {
+                    gtid,
+                    position: offset,
+                });
+            }
+        }
+
+        offset = next_offset;
+    }
+
+    tail
+}
+
+/// 只读取文件开头和末尾的一小段，不解析中间的事件
+/// 开头得到FDE、GTID_LIST、第一个GTID和时间，末尾逐字节查找事件边界得到最后一个完整的事件和GTID
+/// 压缩的文件需要解压整个文件才能得到长度和末尾
+pub fn summarize_binlog(binlog_file_path: &str) -> Result<FileSummary, BoxedError> {
+    let mut file = BinlogReader::open(binlog_file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(binlog_file_path)));
+    }
+
+    let file_size = file.file_length()?;
+
+    let head = Window::read(&mut file, 4, file_size.min(4 + HEAD_LENGTH))?;
+
+    // FDE是第一个事件，先不检查校验和，从它得知是否有校验和
+    let format_description = match head.event_at(4, false) {
+        Some(event) if event[4] == 15 && event.len() >= EVENT_HEADER_LENGTH as usize + 57 => event,
+        _ => {
+            return Err(Box::new(MyError(format!(
+                "`{}` has no valid format description event",
+                binlog_file_path
+            ))));
+        }
+    };
+
+    let fde_body = deal_type_code_15(format_description[EVENT_HEADER_LENGTH as usize..].to_vec())?;
+    let fde_body = fde_body
+        .downcast_ref::<EventBodyTypeCode15>()
+        .ok_or_else(|| MyError("invalid format description event".to_string()))?;
+
+    let checksum_algorithm = format_description[format_description.len() - 5];
+    // 旧版本的FDE没有校验算法，最后的字节是post header的长度
+    let has_checksum_algorithm =
+        ServerVersion::parse(&fde_body.server_version).has_checksum_algorithm();
+    let has_checksum = has_checksum_algorithm && checksum_algorithm == BINLOG_CHECKSUM_ALG_CRC32;
+    let in_use_flag = u16::from_le_bytes([format_description[17], format_description[18]])
+        & LOG_EVENT_BINLOG_IN_USE_F
+        > 0;
+
+    let mut first_timestamp = event_timestamp(format_description);
+    let mut gtid_list = None;
+    let mut first_gtid = None;
+
+    let mut offset = 4 + format_description.len() as u64;
+    while let (None, Some(event)) = (&first_gtid, head.event_at(offset, has_checksum)) {
+        if first_timestamp.is_none() {
+            first_timestamp = event_timestamp(event);
+        }
+
+        match event[4] {
+            162 => {
+                first_gtid = event_gtid(event, has_checksum).map(|gtid| GtidPosition {
+                    gtid,
+                    position: offset,
+                });
+            }
+            163 if gtid_list.is_none() => {
+                let body = deal_type_code_163(event_body(event, has_checksum))?;
+                gtid_list = body.downcast_ref::<EventBodyTypeCode163>().map(|v| {
+                    v.gtids
+                        .iter()
+                        .map(|v| {
+                            format!(
+                                "{}-{}-{}",
+                                v.replication_domain_id, v.server_id, v.gtid_sequence
+                            )
+                        })
+                        .collect()
+                });
+            }
+            _ => {}
+        }
+
+        offset += event.len() as u64;
+    }
+
+    // 窗口最多扩大到FDE之前，找不到GTID时扩大窗口重新扫描
+    let mut tail_length = TAIL_LENGTH;
+    let tail = loop {
+        let start = file_size.saturating_sub(tail_length).max(4);
+        let window = Window::read(&mut file, start, file_size)?;
+        let tail = scan_tail(&window, has_checksum);
+
+        if tail.last_gtid.is_some() || start == 4 || tail_length >= MAX_TAIL_LENGTH {
+            break tail;
+        }
+        tail_length *= 4;
+    };
+
+    Ok(FileSummary {
+        file_path: binlog_file_path.to_string(),
+        file_size,
+        server_version: fde_body.server_version.clone(),
+        binlog_version: fde_body.binlog_version,
+        checksum_algorithm: match checksum_algorithm {
+            _ if !has_checksum_algorithm => "none".to_string(),
+            0 => "none".to_string(),
+            BINLOG_CHECKSUM_ALG_CRC32 => "crc32".to_string(),
+            others => format!("unknown ({})", others),
+        },
+        in_use_flag,
+        first_timestamp,
+        last_timestamp: tail.last_timestamp.or(first_timestamp),
+        gtid_list,
+        first_gtid,
+        last_gtid: tail.last_gtid,
+        last_event_position: tail.last_event_position,
+        valid_length: tail.valid_length.max(4),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+
+    fn gtid_list(gtids: &[(u32, u32, u64)]) -> EventBuilder {
+        let mut body = (gtids.len() as u32).to_le_bytes().to_vec();
+        for (domain, server_id, sequence) in gtids {
+            body.extend(domain.to_le_bytes());
+            body.extend(server_id.to_le_bytes());
+            body.extend(sequence.to_le_bytes());
+        }
+        EventBuilder::new(163, body)
+    }
+
+    fn summarize(binlog: &[u8], name: &str) -> FileSummary {
+        let path = std::env::temp_dir().join(format!("summary-{}-{}", std::process::id(), name));
+        std::fs::write(&path, binlog).unwrap();
+        let summary = summarize_binlog(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        summary
+    }
+
+    #[test]
+    fn with_gtid_list_and_incomplete_tail() {
+        let builder = BinlogBuilder::empty()
+            .push(
+                EventBuilder::from_body(
+                    &FormatDescriptionBuilder::new("10.11.6-MariaDB-log")
+                        .create_timestamp(1700000000),
+                )
+                .timestamp(1700000000)
+                .flags(LOG_EVENT_BINLOG_IN_USE_F),
+            )
+            .push(gtid_list(&[(0, 1, 5), (1, 2, 9)]));
+        let first_gtid_position = builder.position();
+        let builder = builder
+            .push(gtid(6).timestamp(1700000010))
+            .push(xid(1).timestamp(1700000010));
+        let last_gtid_position = builder.position();
+        let builder = builder
+            .push(gtid(7).timestamp(1700000020))
+            .push(xid(2).timestamp(1700000030));
+        let valid_length = builder.position();
+        let binlog = builder.raw(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]).build();
+
+        let summary = summarize(&binlog, "gtid-list");
+        assert_eq!(summary.server_version, "10.11.6-MariaDB-log");
+        assert_eq!(summary.binlog_version, 4);
+        assert_eq!(summary.checksum_algorithm, "crc32");
+        assert!(summary.in_use_flag);
+        assert_eq!(summary.file_size, binlog.len() as u64);
+        assert_eq!(summary.first_timestamp, Some(1700000000));
+        assert_eq!(summary.last_timestamp, Some(1700000030));
+        assert_eq!(
+            summary.gtid_list,
+            Some(vec!["0-1-5".to_string(), "1-2-9".to_string()])
+        );
+        let first_gtid = summary.first_gtid.as_ref().unwrap();
+        assert_eq!(
+            (first_gtid.gtid.as_str(), first_gtid.position),
+            ("0-1-6", first_gtid_position)
+        );
+        let last_gtid = summary.last_gtid.as_ref().unwrap();
+        assert_eq!(
+            (last_gtid.gtid.as_str(), last_gtid.position),
+            ("0-1-7", last_gtid_position)
+        );
+        assert_eq!(summary.valid_length, valid_length);
+
+        let text = summary.to_string();
+        assert!(text.contains("gtid list: 0-1-5,1-2-9"));
+        assert!(text.contains("incomplete tail from"));
+        assert!(text.ends_with("in use flag: true"));
+    }
+
+    #[test]
+    fn without_gtid_list_or_checksum() {
+        let binlog = BinlogBuilder::without_checksum()
+            .push(gtid(1).server_id(3).timestamp(1700000000))
+            .event(&QueryEventBuilder::new("shop", "CREATE TABLE t (id INT)"))
+            .build();
+
+        let summary = summarize(&binlog, "no-gtid-list");
+        assert_eq!(summary.checksum_algorithm, "none");
+        assert!(!summary.in_use_flag);
+        assert!(summary.gtid_list.is_none());
+        assert_eq!(summary.first_gtid.as_ref().unwrap().gtid, "0-3-1");
+        assert_eq!(summary.last_gtid.as_ref().unwrap().gtid, "0-3-1");
+        assert_eq!(summary.valid_length, binlog.len() as u64);
+        assert!(summary.to_string().contains("gtid list: -"));
+    }
+
+    #[test]
+    fn last_gtid_before_a_large_transaction() {
+        // 最后一个GTID不在第一次读取的文件末尾中，需要扩大窗口
+        let builder = BinlogBuilder::new().push(gtid(1)).push(xid(1));
+        let last_gtid_position = builder.position();
+        let sql = format!(
+            "INSERT INTO t VALUES ('{}')",
+            "x".repeat(TAIL_LENGTH as usize)
+        );
+        let binlog = builder
+            .push(gtid(2))
+            .event(&QueryEventBuilder::new("shop", &sql))
+            .build();
+
+        let summary = summarize(&binlog, "large");
+        let last_gtid = summary.last_gtid.unwrap();
+        assert_eq!(
+            (last_gtid.gtid.as_str(), last_gtid.position),
+            ("0-1-2", last_gtid_position)
+        );
+        assert_eq!(summary.valid_length, binlog.len() as u64);
+    }
+
+    #[test]
+    fn not_a_binlog() {
+        let path = std::env::temp_dir().join(format!("summary-{}-not-binlog", std::process::id()));
+        std::fs::write(&path, b"hello world").unwrap();
+        assert!(summarize_binlog(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::model::*;
+use crate::sql::quote_identifier;
+
+// 每个字段值的类型标记，写在值的前面，不同类型的相同字节不会得到相同的编码
+const TAG_NULL: u8 = 0;
+const TAG_INT: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_DOUBLE: u8 = 3;
+const TAG_DECIMAL: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_BYTES: u8 = 6;
+const TAG_TEMPORAL: u8 = 7;
+const TAG_UNSUPPORTED: u8 = 8;
+const TAG_UINT: u8 = 9;
+
+/// 一张表的行数和所有行的哈希之和
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TableChecksum {
+    pub database: String,
+    pub table: String,
+    pub rows: u64,
+    pub inserted: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    /// 十六进制
+    #[serde(serialize_with = "serialize_sum")]
+    pub checksum: [u8; 32],
+}
+
+impl TableChecksum {
+    pub fn checksum_hex(&self) -> String {
+        to_hex(&self.checksum)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+fn serialize_sum<S: serde::Serializer>(sum: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(sum))
+}
+
+impl Display for TableChecksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{} {} rows={} (insert {}, update {}, delete {})",
+            quote_identifier(&self.database),
+            quote_identifier(&self.table),
+            self.checksum_hex(),
+            self.rows,
+            self.inserted,
+            self.updated,
+            self.deleted
+        )
+    }
+}
+
+/// `--table-checksums`，按表计算和行的顺序、事件的分批无关的哈希，用来比较两个服务端是否执行了相同的变更
+/// insert和update取修改后的镜像，delete取删除前的镜像，每一行按row_encoding编码后计算SHA-256，
+/// 表的哈希是所有行的SHA-256按256位大端整数相加（模2^256），重复的行不会像XOR那样互相抵消
+#[derive(Default)]
+pub struct TableChecksums {
+    // (库名, 表名) -> 哈希，按名字排列输出
+    tables: BTreeMap<(String, String), TableChecksum>,
+}
+
+impl TableChecksums {
+    pub fn new() -> TableChecksums {
+        TableChecksums::default()
+    }
+
+    pub fn process(
+        &mut self,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        let rows_event = match body.downcast_ref::<EventBodyTypeCode23To25>() {
+            Some(rows_event) => rows_event,
+            None => return,
+        };
+        let table_map = match table_structs.get(&rows_event.table_id) {
+            Some(table_map) => table_map,
+            None => return,
+        };
+
+        let table = self
+            .tables
+            .entry((
+                table_map.database_name.clone(),
+                table_map.table_name.clone(),
+            ))
+            .or_insert_with(|| TableChecksum {
+                database: table_map.database_name.clone(),
+                table: table_map.table_name.clone(),
+                ..TableChecksum::default()
+            });
+
+        for row in &rows_event.rows {
+            // update的修改后的镜像按columns_used_for_update
+            let (image, columns_used) = match (&row.before, &row.after) {
+                (Some(_), Some(after)) => {
+                    table.updated += 1;
+                    let columns_used = rows_event
+                        .columns_used_for_update
+                        .as_ref()
+                        .unwrap_or(&rows_event.columns_used);
+                    (after, columns_used)
+                }
+                (None, Some(after)) => {
+                    table.inserted += 1;
+                    (after, &rows_event.columns_used)
+                }
+                (Some(before), None) => {
+                    table.deleted += 1;
+                    (before, &rows_event.columns_used)
+                }
+                (None, None) => continue,
+            };
+
+            table.rows += 1;
+            let digest = Sha256::digest(row_encoding(image, columns_used));
+            add_digest(&mut table.checksum, &digest);
+        }
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &TableChecksum> {
+        self.tables.values()
+    }
+}
+
+impl Display for TableChecksums {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for table in self.tables() {
+            writeln!(f, "{}", table)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 一行的规范编码，只包括镜像中有的字段（columns_used），按字段顺序：
+/// 4字节小端的字段序号（从0开始），1字节类型标记，然后是值：
+/// NULL没有值；整数是8字节小端的i64；FLOAT、DOUBLE是4、8字节小端的IEEE 754；
+/// DECIMAL、时间类型是MySQL格式的字符串，字符串是按字段的字符集解码后的UTF-8，
+/// 二进制是原始字节，这些都在前面加4字节小端的长度
+/// 同一个值在latin1和utf8mb4的字段中编码相同，VARBINARY和VARCHAR中的相同字节编码不同
+pub fn row_encoding(image: &[ColumnValue], columns_used: &Bitmap) -> Vec<u8> {
+    let mut encoding = Vec::new();
+
+    for (index, value) in image.iter().enumerate() {
+        if columns_used.get(index) == Some(false) {
+            continue;
+        }
+
+        encoding.extend((index as u32).to_le_bytes());
+
+        match value {
+            ColumnValue::Null => encoding.push(TAG_NULL),
+            ColumnValue::Int(v) => {
+                encoding.push(TAG_INT);
+                encoding.extend(v.to_le_bytes());
+            }
+            // 在i64范围内的值和Int一样，同一个值不会因为有没有SIGNEDNESS元数据而不同
+            ColumnValue::UInt(v) => match i64::try_from(*v) {
+                Ok(v) => {
+                    encoding.push(TAG_INT);
+                    encoding.extend(v.to_le_bytes());
+                }
+                Err(_) => {
+                    encoding.push(TAG_UINT);
+                    encoding.extend(v.to_le_bytes());
+                }
+            },
+            ColumnValue::Float(v) => {
+                encoding.push(TAG_FLOAT);
+                encoding.extend(v.to_le_bytes());
+            }
+            ColumnValue::Double(v) => {
+                encoding.push(TAG_DOUBLE);
+                encoding.extend(v.to_le_bytes());
+            }
+            ColumnValue::Decimal(v) => push_bytes(&mut encoding, TAG_DECIMAL, v.as_bytes()),
+            ColumnValue::RawDecimal(v) => {
+                push_bytes(&mut encoding, TAG_DECIMAL, v.value.as_bytes())
+            }
+            ColumnValue::String(v) => push_bytes(&mut encoding, TAG_STRING, v.as_bytes()),
+            ColumnValue::Bytes(v) => push_bytes(&mut encoding, TAG_BYTES, v),
+            ColumnValue::Temporal(v) => push_bytes(&mut encoding, TAG_TEMPORAL, v.as_bytes()),
+            ColumnValue::RawTemporal(v) => {
+                push_bytes(&mut encoding, TAG_TEMPORAL, v.to_string().as_bytes())
+            }
+            ColumnValue::Unsupported(v) => push_bytes(&mut encoding, TAG_UNSUPPORTED, v.as_bytes()),
+        }
+    }
+
+    encoding
+}
+
+// 类型标记、4字节小端的长度和内容
+fn push_bytes(encoding: &mut Vec<u8>, tag: u8, bytes: &[u8]) {
+    encoding.push(tag);
+    encoding.extend((bytes.len() as u32).to_le_bytes());
+    encoding.extend(bytes);
+}
+
+// 按256位大端整数相加，溢出的进位丢掉
+fn add_digest(sum: &mut [u8; 32], digest: &[u8]) {
+    let mut carry = 0u16;
+
+    for (a, b) in sum.iter_mut().zip(digest).rev() {
+        let v = *a as u16 + *b as u16 + carry;
+        *a = v as u8;
+        carry = v >> 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    fn all(length: usize) -> Bitmap {
+        Bitmap::repeat(true, length)
+    }
+
+    fn text(v: &str) -> ColumnValue {
+        ColumnValue::String(v.to_string())
+    }
+
+    #[test]
+    fn canonical_row_encoding() {
+        let image = [
+            ColumnValue::Int(-2),
+            ColumnValue::Null,
+            text("é"),
+            ColumnValue::Bytes(vec![0xff, 0]),
+            ColumnValue::Double(1.5),
+            ColumnValue::Temporal("2024-01-02".to_string()),
+        ];
+
+        let mut expected = vec![0, 0, 0, 0, TAG_INT];
+        expected.extend((-2i64).to_le_bytes());
+        expected.extend([1, 0, 0, 0, TAG_NULL]);
+        expected.extend([2, 0, 0, 0, TAG_STRING, 2, 0, 0, 0, 0xc3, 0xa9]);
+        expected.extend([3, 0, 0, 0, TAG_BYTES, 2, 0, 0, 0, 0xff, 0]);
+        expected.extend([4, 0, 0, 0, TAG_DOUBLE]);
+        expected.extend(1.5f64.to_le_bytes());
+        expected.extend([5, 0, 0, 0, TAG_TEMPORAL, 10, 0, 0, 0]);
+        expected.extend(b"2024-01-02");
+        assert_eq!(row_encoding(&image, &all(6)), expected);
+
+        // 不在镜像中的字段不编码，后面的字段保留自己的序号
+        let columns_used = [true, false, false, false, false, true]
+            .into_iter()
+            .collect::<Bitmap>();
+        let mut expected = vec![0, 0, 0, 0, TAG_INT];
+        expected.extend((-2i64).to_le_bytes());
+        expected.extend([5, 0, 0, 0, TAG_TEMPORAL, 10, 0, 0, 0]);
+        expected.extend(b"2024-01-02");
+        assert_eq!(row_encoding(&image, &columns_used), expected);
+    }
+
+    #[test]
+    fn values_are_not_confused() {
+        let encode = |value: ColumnValue| row_encoding(&[value], &all(1));
+
+        // NULL、空字符串和空的二进制不同，相同的字节作为字符串和二进制不同
+        assert_ne!(encode(ColumnValue::Null), encode(text("")));
+        assert_ne!(encode(text("")), encode(ColumnValue::Bytes(Vec::new())));
+        assert_ne!(
+            encode(text("ab")),
+            encode(ColumnValue::Bytes(b"ab".to_vec()))
+        );
+        assert_ne!(
+            encode(ColumnValue::Decimal("1".to_string())),
+            encode(text("1"))
+        );
+        assert_ne!(
+            encode(ColumnValue::Int(1)),
+            encode(ColumnValue::Double(1.0))
+        );
+
+        // 长度前缀使相邻的字段不会互相串
+        assert_ne!(
+            row_encoding(&[text("ab"), text("c")], &all(2)),
+            row_encoding(&[text("a"), text("bc")], &all(2))
+        );
+
+        // 解码后的字符串只看字符，原始值和--raw-temporals的值相同
+        assert_eq!(
+            encode(ColumnValue::RawDecimal(RawDecimal {
+                value: "1.50".to_string(),
+                precision: 10,
+                scale: 2
+            })),
+            encode(ColumnValue::Decimal("1.50".to_string()))
+        );
+        assert_eq!(
+            encode(ColumnValue::RawTemporal(RawTemporal::Date {
+                year: 2024,
+                month: 1,
+                day: 2
+            })),
+            encode(ColumnValue::Temporal("2024-01-02".to_string()))
+        );
+    }
+
+    fn user(id: i64, name: Option<&str>) -> Vec<ColumnValue> {
+        vec![ColumnValue::Int(id), name.map_or(ColumnValue::Null, text)]
+    }
+
+    fn checksums(binlog: &[u8]) -> Vec<(String, String, u64, u64, u64, u64)> {
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut table_checksums = TableChecksums::new();
+
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            table_checksums.process(body.as_ref(), &table_structs);
+            position = header.next_event_position as u64;
+        }
+
+        table_checksums
+            .tables()
+            .map(|v| {
+                (
+                    v.to_string(),
+                    v.checksum_hex(),
+                    v.rows,
+                    v.inserted,
+                    v.updated,
+                    v.deleted,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn independent_of_batching_and_order() {
+        let table = users();
+        let batched = BinlogBuilder::new()
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .row(user(1, Some("ann")))
+                    .row(user(2, None)),
+            )
+            .event(
+                &RowsEventBuilder::update(&table).update_row(user(2, None), user(2, Some("bob"))),
+            )
+            .event(&RowsEventBuilder::delete(&table).row(user(1, Some("ann"))))
+            .build();
+
+        // 同样的变更，每行一个事件，顺序不同，table_id也不同
+        let table = users().table_id(9);
+        let single = BinlogBuilder::new()
+            .event(&table)
+            .event(&RowsEventBuilder::delete(&table).row(user(1, Some("ann"))))
+            .event(&RowsEventBuilder::insert(&table).row(user(2, None)))
+            .event(
+                &RowsEventBuilder::update(&table).update_row(user(2, None), user(2, Some("bob"))),
+            )
+            .event(&RowsEventBuilder::insert(&table).row(user(1, Some("ann"))))
+            .build();
+
+        let batched = checksums(&batched);
+        assert_eq!(batched, checksums(&single));
+
+        let (line, checksum, rows, inserted, updated, deleted) = &batched[0];
+        assert_eq!((*rows, *inserted, *updated, *deleted), (4, 2, 1, 1));
+        assert_eq!(
+            *line,
+            format!(
+                "`shop`.`users` {} rows=4 (insert 2, update 1, delete 1)",
+                checksum
+            )
+        );
+
+        // 相同的行出现两次不会抵消
+        let table = users();
+        let twice = BinlogBuilder::new()
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .row(user(1, Some("ann")))
+                    .row(user(1, Some("ann"))),
+            )
+            .build();
+        assert_ne!(checksums(&twice)[0].1, "0".repeat(64));
+    }
+
+    #[test]
+    fn different_values_give_different_checksums() {
+        let table = users();
+        let binlog = |name: Option<&str>| {
+            BinlogBuilder::new()
+                .event(&table)
+                .event(&RowsEventBuilder::insert(&table).row(user(1, name)))
+                .build()
+        };
+
+        let ann = checksums(&binlog(Some("ann")));
+        assert_ne!(ann[0].1, checksums(&binlog(Some("Ann")))[0].1);
+        assert_ne!(ann[0].1, checksums(&binlog(None))[0].1);
+        assert_ne!(ann[0].1, checksums(&binlog(Some("")))[0].1);
+        assert_eq!(ann[0].1, checksums(&binlog(Some("ann")))[0].1);
+    }
+
+    #[test]
+    fn decimals_from_the_binlog() {
+        // DECIMAL(10,2)，按binlog中的格式写入，解码后再计算
+        let table = TableMapBuilder::new("shop", "prices")
+            .table_id(7)
+            .column(FieldType::NewDecimal, &[10, 2]);
+        let checksum = |price: &[u8]| {
+            let binlog = BinlogBuilder::new()
+                .event(&table)
+                .event(
+                    &RowsEventBuilder::insert(&table).row(vec![ColumnValue::Bytes(price.to_vec())]),
+                )
+                .build();
+            checksums(&binlog)[0].1.clone()
+        };
+
+        let positive = checksum(&[0x80, 0, 0, 1, 5]);
+        // 1.05和1.5、-1.05和1.05不同
+        assert_ne!(positive, checksum(&[0x80, 0, 0, 1, 50]));
+        assert_ne!(positive, checksum(&[0x7f, 0xff, 0xff, 0xfe, 0xfa]));
+        assert_eq!(positive, checksum(&[0x80, 0, 0, 1, 5]));
+
+        let mut expected = vec![0, 0, 0, 0, TAG_DECIMAL, 5, 0, 0, 0];
+        expected.extend(b"-1.05");
+        assert_eq!(
+            row_encoding(&[ColumnValue::Decimal("-1.05".to_string())], &all(1)),
+            expected
+        );
+    }
+
+    #[test]
+    fn unsigned_bigint() {
+        let encode = |value: ColumnValue| row_encoding(&[value], &all(1));
+
+        // 在i64范围内的unsigned值和Int相同，超过的和同样字节的负数不同
+        assert_eq!(encode(ColumnValue::UInt(7)), encode(ColumnValue::Int(7)));
+        assert_ne!(
+            encode(ColumnValue::UInt(u64::MAX)),
+            encode(ColumnValue::Int(-1))
+        );
+    }
+
+    #[test]
+    fn sum_carries_between_bytes() {
+        let mut one = [0u8; 32];
+        one[31] = 1;
+
+        // 0xff加1进位到前一个字节
+        let mut sum = [0u8; 32];
+        sum[31] = 0xff;
+        add_digest(&mut sum, &one);
+        assert_eq!(sum[30..], [1, 0]);
+
+        // 溢出的进位丢掉
+        let mut sum = [0xff; 32];
+        add_digest(&mut sum, &one);
+        assert_eq!(sum, [0; 32]);
+    }
+}
@@ -0,0 +1,1091 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::collation::{collation_name, lookup};
+use crate::field_type::FieldType;
+use crate::model::*;
+use crate::sql::quote_identifier;
+use crate::util::{
+    parse_lenenc, parse_optional_metadata, parse_quantity_of_bytes_for_decimal_part,
+};
+
+type BoxedError = Box<dyn std::error::Error>;
+
+// 可选元数据的类型
+// 参考 https://github.com/mysql/mysql-server/blob/8.0/libbinlogevents/include/rows_event.h
+const SIGNEDNESS: u8 = 1;
+const DEFAULT_CHARSET: u8 = 2;
+const COLUMN_CHARSET: u8 = 3;
+const COLUMN_NAME: u8 = 4;
+const SET_STR_VALUE: u8 = 5;
+const ENUM_STR_VALUE: u8 = 6;
+const SIMPLE_PRIMARY_KEY: u8 = 8;
+const PRIMARY_KEY_WITH_PREFIX: u8 = 9;
+
+// binary字符集，字符串类型的字段用它时实际是BINARY、VARBINARY或者BLOB
+const BINARY_COLLATION: u64 = 63;
+
+/// 根据table map事件拼出建表语句，每张表只在第一次出现或者结构变化时输出
+#[derive(Default)]
+pub struct TableStructureTracker {
+    // (库名, 表名) -> 上一次输出的建表语句
+    printed: HashMap<(String, String), String>,
+}
+
+impl TableStructureTracker {
+    pub fn new() -> TableStructureTracker {
+        TableStructureTracker::default()
+    }
+
+    pub fn process(&mut self, body: &dyn EventBody) -> Result<Option<String>, BoxedError> {
+        let table_map = match body.downcast_ref::<EventBodyTypeCode19>() {
+            Some(table_map) => table_map,
+            None => return Ok(None),
+        };
+
+        let statement = create_table_statement(table_map)?;
+
+        let key = (
+            table_map.database_name.clone(),
+            table_map.table_name.clone(),
+        );
+        if self.printed.get(&key) == Some(&statement) {
+            return Ok(None);
+        }
+
+        self.printed.insert(key, statement.clone());
+
+        Ok(Some(statement))
+    }
+}
+
+/// 从可选元数据中得到的信息，binlog_row_metadata=FULL时才会有字段名等内容
+#[derive(Default)]
+struct OptionalMetadata {
+    unsigned: Option<Vec<bool>>,
+    // 按字符串类型字段的顺序排列
+    collations: Option<Vec<u64>>,
+    column_names: Option<Vec<String>>,
+    // 按ENUM和SET字段各自的顺序排列
+    enum_values: Option<Vec<Vec<String>>>,
+    set_values: Option<Vec<Vec<String>>>,
+    // (字段序号, 前缀长度)，前缀长度为0表示整个字段
+    primary_key: Option<Vec<(usize, u64)>>,
+}
+
+/// 生成的语句只是一个大概的结构，table map中没有的信息（默认值、索引、字段名等）无法还原
+/// 有歧义的地方会以注释标出
+pub fn create_table_statement(table_map: &EventBodyTypeCode19) -> Result<String, BoxedError> {
+    let metadata = column_metadata(table_map);
+    let optional_metadata = parse_table_optional_metadata(table_map, &metadata)?;
+
+    let mut definitions = Vec::new();
+
+    for (i, (mut definition, comments)) in
+        column_definitions(table_map, &metadata, &optional_metadata)
+            .into_iter()
+            .enumerate()
+    {
+        let column_name = optional_metadata
+            .column_names
+            .as_ref()
+            .and_then(|v| v.get(i).cloned())
+            .unwrap_or_else(|| format!("col_{}", i + 1));
+
+        match table_map.columns.get(i).map(|v| v.nullable) {
+            Some(true) => definition.push_str(" NULL"),
+            Some(false) => definition.push_str(" NOT NULL"),
+            None => {}
+        }
+
+        for comment in comments {
+            definition.push_str(&format!(" /* {} */", comment));
+        }
+
+        definitions.push(format!(
+            "  {} {}",
+            quote_identifier(&column_name),
+            definition
+        ));
+    }
+
+    if let Some(primary_key) = &optional_metadata.primary_key {
+        let columns = primary_key
+            .iter()
+            .map(|(index, prefix)| {
+                let name = optional_metadata
+                    .column_names
+                    .as_ref()
+                    .and_then(|v| v.get(*index).cloned())
+                    .unwrap_or_else(|| format!("col_{}", index + 1));
+
+                match prefix {
+                    0 => quote_identifier(&name),
+                    prefix => format!("{}({})", quote_identifier(&name), prefix),
+                }
+            })
+            .collect::<Vec<String>>();
+
+        definitions.push(format!("  PRIMARY KEY ({})", columns.join(", ")));
+    }
+
+    let mut result = format!(
+        "CREATE TABLE {}.{} (\n{}\n);",
+        quote_identifier(&table_map.database_name),
+        quote_identifier(&table_map.table_name),
+        definitions.join(",\n")
+    );
+
+    if optional_metadata.column_names.is_none() {
+        result.insert_str(
+            0,
+            "-- column names are unknown without binlog_row_metadata=FULL\n",
+        );
+    }
+
+    Ok(result)
+}
+
+/// 每个字段的类型定义（不包含NULL）以及需要以注释标出的歧义
+fn column_definitions(
+    table_map: &EventBodyTypeCode19,
+    metadata: &[Vec<u8>],
+    optional_metadata: &OptionalMetadata,
+) -> Vec<(String, Vec<String>)> {
+    let mut numeric_index = 0;
+    let mut character_index = 0;
+    let mut enum_index = 0;
+    let mut set_index = 0;
+
+    let mut result = Vec::new();
+
+    for (i, column) in table_map.columns.iter().enumerate() {
+        let column_type = &column.type_code();
+        let meta = metadata[i].as_slice();
+
+        let real_type = real_type(*column_type, meta);
+
+        let mut comments = Vec::new();
+
+        let definition = match real_type {
+            247 | 248 => {
+                let values = if real_type == 247 {
+                    enum_index += 1;
+                    optional_metadata
+                        .enum_values
+                        .as_ref()
+                        .and_then(|v| v.get(enum_index - 1))
+                } else {
+                    set_index += 1;
+                    optional_metadata
+                        .set_values
+                        .as_ref()
+                        .and_then(|v| v.get(set_index - 1))
+                };
+
+                let name = if real_type == 247 { "ENUM" } else { "SET" };
+                match values {
+                    Some(values) => format!(
+                        "{}({})",
+                        name,
+                        values
+                            .iter()
+                            .map(|v| format!("'{}'", v.replace('\'', "''")))
+                            .collect::<Vec<String>>()
+                            .join(",")
+                    ),
+                    None => {
+                        comments.push(
+                            "values are unknown without binlog_row_metadata=FULL".to_string(),
+                        );
+                        format!("{}(...)", name)
+                    }
+                }
+            }
+            _ if is_character_type(real_type) => {
+                character_index += 1;
+                let collation = optional_metadata
+                    .collations
+                    .as_ref()
+                    .and_then(|v| v.get(character_index - 1))
+                    .copied();
+
+                let (mut definition, comment) = character_column_type(real_type, meta, collation);
+                if let Some(comment) = comment {
+                    comments.push(comment.to_string());
+                }
+                // 不认识的collation只能在注释中给出id
+                let collation = collation.filter(|v| *v != BINARY_COLLATION);
+                match (collation, collation.and_then(lookup)) {
+                    (_, Some(known)) => definition.push_str(&format!(
+                        " CHARACTER SET {} COLLATE {}",
+                        known.charset, known.name
+                    )),
+                    (Some(id), None) => comments.push(format!("collation {}", collation_name(id))),
+                    (None, None) => {}
+                }
+
+                definition
+            }
+            _ => {
+                let mut definition = column_type_name(real_type, meta);
+
+                if is_numeric_type(real_type) {
+                    numeric_index += 1;
+                    if let Some(true) = optional_metadata
+                        .unsigned
+                        .as_ref()
+                        .and_then(|v| v.get(numeric_index - 1))
+                    {
+                        definition.push_str(" UNSIGNED");
+                    }
+                }
+
+                definition
+            }
+        };
+
+        result.push((definition, comments));
+    }
+
+    result
+}
+
+/// 按字段顺序排列的类型定义，例如`INT UNSIGNED`、`VARCHAR(20)`，和建表语句中的一样
+pub fn column_type_definitions(table_map: &EventBodyTypeCode19) -> Vec<String> {
+    let metadata = column_metadata(table_map);
+    let optional_metadata = parse_table_optional_metadata(table_map, &metadata).unwrap_or_default();
+
+    column_definitions(table_map, &metadata, &optional_metadata)
+        .into_iter()
+        .map(|(definition, _)| definition)
+        .collect()
+}
+
+/// 主键字段的序号，没有binlog_row_metadata=FULL时无法得知，返回None
+/// deal_type_code_19中已经解析到primary_key，直接构造的table map中没有时现在解析
+pub fn primary_key_columns(table_map: &EventBodyTypeCode19) -> Option<Vec<usize>> {
+    if table_map.primary_key.is_some() {
+        return table_map.primary_key.clone();
+    }
+
+    let metadata = column_metadata(table_map);
+
+    parse_table_optional_metadata(table_map, &metadata)
+        .ok()?
+        .primary_key
+        .map(|v| v.into_iter().map(|(index, _)| index).collect())
+}
+
+/// 按字段顺序排列的字段名，没有binlog_row_metadata=FULL时使用col_1这样的名字
+pub fn column_names(table_map: &EventBodyTypeCode19) -> Vec<String> {
+    let column_names = metadata_column_names(table_map).unwrap_or_default();
+
+    (0..table_map.columns.len())
+        .map(|i| {
+            column_names
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| format!("col_{}", i + 1))
+        })
+        .collect()
+}
+
+/// 行事件的表的字段名，输出行事件时附带，没有binlog_row_metadata=FULL时为None
+pub fn rows_event_column_names<'a>(
+    body: &dyn EventBody,
+    table_structs: &'a HashMap<u64, Arc<EventBodyTypeCode19>>,
+) -> Option<&'a [String]> {
+    let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>()?;
+
+    table_structs
+        .get(&rows_event.table_id)?
+        .column_names
+        .as_deref()
+}
+
+/// optional metadata中的字段名，只有binlog_row_metadata=FULL时才有
+/// deal_type_code_19中已经解析到column_names，直接构造的table map中没有时现在解析
+pub fn metadata_column_names(table_map: &EventBodyTypeCode19) -> Option<Vec<String>> {
+    if table_map.column_names.is_some() {
+        return table_map.column_names.clone();
+    }
+
+    let metadata = column_metadata(table_map);
+
+    parse_table_optional_metadata(table_map, &metadata)
+        .ok()
+        .and_then(|v| v.column_names)
+}
+
+/// 把可选元数据中的字符集、unsigned、字段名和主键填入table map，只有binlog_row_metadata=FULL时才有
+pub fn apply_optional_metadata(table_map: &mut EventBodyTypeCode19) {
+    let metadata = column_metadata(table_map);
+    let optional_metadata = parse_table_optional_metadata(table_map, &metadata).unwrap_or_default();
+
+    let mut numeric_index = 0;
+    let mut character_index = 0;
+
+    for (i, column) in table_map.columns.iter_mut().enumerate() {
+        let real_type = real_type(column.type_code(), &metadata[i]);
+
+        if is_numeric_type(real_type) {
+            numeric_index += 1;
+            column.unsigned = optional_metadata
+                .unsigned
+                .as_ref()
+                .and_then(|v| v.get(numeric_index - 1))
+                .copied();
+        }
+
+        if is_character_type(real_type) {
+            character_index += 1;
+            column.charset = optional_metadata
+                .collations
+                .as_ref()
+                .and_then(|v| v.get(character_index - 1))
+                .copied();
+        }
+
+        column.name = optional_metadata
+            .column_names
+            .as_ref()
+            .and_then(|v| v.get(i).cloned());
+    }
+
+    table_map.column_names = optional_metadata.column_names;
+
+    if let Some(primary_key) = optional_metadata.primary_key {
+        table_map.primary_key = Some(primary_key.iter().map(|(index, _)| *index).collect());
+        table_map.primary_key_prefixes = primary_key
+            .iter()
+            .any(|(_, prefix)| *prefix > 0)
+            .then(|| primary_key.iter().map(|(_, prefix)| *prefix).collect());
+    }
+}
+
+/// 每个字段解码需要的类型、长度和精度，以及可选元数据中的字符集和unsigned
+/// deal_type_code_19中计算一次，行事件解码时不再解释元数据
+pub fn column_descriptors(table_map: &EventBodyTypeCode19) -> Vec<ColumnDescriptor> {
+    table_map
+        .columns
+        .iter()
+        .map(|column| {
+            let (kind, length, precision, decimals) =
+                column_layout(column.field_type, &column.metadata_raw);
+
+            ColumnDescriptor {
+                field_type: column.field_type,
+                kind,
+                length,
+                precision,
+                decimals,
+                charset: column.charset,
+                nullable: column.nullable,
+                unsigned: column.unsigned,
+            }
+        })
+        .collect()
+}
+
+/// 字段在行镜像中的类型和长度，以及NEWDECIMAL的精度和时间类型秒的小数位数
+///
+/// 这里列出所有的FieldType，增加类型时编译器会提示在这里处理
+fn column_layout(field_type: FieldType, meta: &[u8]) -> (ColumnKind, ColumnLength, u8, u8) {
+    // 秒的小数部分每两位占一个字节
+    let fsp = meta.first().copied().unwrap_or(0);
+    let fractional_length = (fsp as usize).div_ceil(2);
+
+    let unsupported = (ColumnKind::Unsupported, ColumnLength::Unknown, 0, 0);
+
+    match field_type {
+        FieldType::Tiny => (ColumnKind::Tiny, ColumnLength::Fixed(1), 0, 0),
+        FieldType::Short => (ColumnKind::Short, ColumnLength::Fixed(2), 0, 0),
+        FieldType::Int24 => (ColumnKind::Int24, ColumnLength::Fixed(3), 0, 0),
+        FieldType::Long => (ColumnKind::Long, ColumnLength::Fixed(4), 0, 0),
+        FieldType::LongLong => (ColumnKind::LongLong, ColumnLength::Fixed(8), 0, 0),
+        FieldType::Float => (ColumnKind::Float, ColumnLength::Fixed(4), 0, 0),
+        FieldType::Double => (ColumnKind::Double, ColumnLength::Fixed(8), 0, 0),
+        FieldType::NewDecimal => match meta {
+            [precision, decimals] if precision >= decimals => {
+                let length =
+                    parse_quantity_of_bytes_for_decimal_part((precision - decimals) as usize)
+                        + parse_quantity_of_bytes_for_decimal_part(*decimals as usize);
+
+                (
+                    ColumnKind::NewDecimal,
+                    ColumnLength::Fixed(length),
+                    *precision,
+                    *decimals,
+                )
+            }
+            _ => unsupported,
+        },
+        // 定义的长度超过255时用2个字节表示实际的长度
+        FieldType::Varchar => match meta {
+            [low, high] => match u16::from_le_bytes([*low, *high]) {
+                0..=255 => (ColumnKind::Varchar, ColumnLength::Prefixed(1), 0, 0),
+                _ => (ColumnKind::Varchar, ColumnLength::Prefixed(2), 0, 0),
+            },
+            _ => unsupported,
+        },
+        FieldType::Date => (ColumnKind::Date, ColumnLength::Fixed(3), 0, 0),
+        FieldType::Time2 => (
+            ColumnKind::Time2,
+            ColumnLength::Fixed(3 + fractional_length),
+            0,
+            fsp,
+        ),
+        FieldType::DateTime2 => (
+            ColumnKind::DateTime2,
+            ColumnLength::Fixed(5 + fractional_length),
+            0,
+            fsp,
+        ),
+        FieldType::Timestamp2 => (
+            ColumnKind::Timestamp2,
+            ColumnLength::Fixed(4 + fractional_length),
+            0,
+            fsp,
+        ),
+        // 元数据是长度占用的字节数
+        FieldType::Blob => match meta {
+            [n @ 1..=4] => (ColumnKind::Blob, ColumnLength::Prefixed(*n as usize), 0, 0),
+            _ => unsupported,
+        },
+        FieldType::Json => match meta {
+            [n @ 1..=4] => (ColumnKind::Json, ColumnLength::Prefixed(*n as usize), 0, 0),
+            _ => unsupported,
+        },
+        // 还不支持解码的类型
+        FieldType::Decimal
+        | FieldType::Null
+        | FieldType::Timestamp
+        | FieldType::Time
+        | FieldType::DateTime
+        | FieldType::Year
+        | FieldType::NewDate
+        | FieldType::Bit
+        | FieldType::TypedArray
+        | FieldType::Invalid
+        | FieldType::Bool
+        | FieldType::Enum
+        | FieldType::Set
+        | FieldType::TinyBlob
+        | FieldType::MediumBlob
+        | FieldType::LongBlob
+        | FieldType::VarString
+        | FieldType::String
+        | FieldType::Geometry => unsupported,
+    }
+}
+
+/// 按照字段类型把metadata block切分给每个字段，没有metadata的字段是空的
+pub fn split_column_metadata(field_types: &[FieldType], metadata_block: &[u8]) -> Vec<Vec<u8>> {
+    let mut offset = 0;
+
+    field_types
+        .iter()
+        .map(|field_type| {
+            let end = (offset + field_type.metadata_len()).min(metadata_block.len());
+            let result = metadata_block[offset.min(end)..end].to_vec();
+            offset = end;
+
+            result
+        })
+        .collect()
+}
+
+// 每个字段的元数据，下标和字段的序号对应
+fn column_metadata(table_map: &EventBodyTypeCode19) -> Vec<Vec<u8>> {
+    table_map
+        .columns
+        .iter()
+        .map(|v| v.metadata_raw.clone())
+        .collect()
+}
+
+/// STRING类型的ENUM和SET需要看metadata中的真实类型
+fn real_type(column_type: u8, meta: &[u8]) -> u8 {
+    match (column_type, meta) {
+        (254, [real_type, _]) if *real_type == 247 || *real_type == 248 => *real_type,
+        _ => column_type,
+    }
+}
+
+fn is_numeric_type(column_type: u8) -> bool {
+    matches!(column_type, 0 | 1 | 2 | 3 | 4 | 5 | 8 | 9 | 246)
+}
+
+fn is_character_type(column_type: u8) -> bool {
+    matches!(column_type, 15 | 249..=254)
+}
+
+fn column_type_name(column_type: u8, meta: &[u8]) -> String {
+    match (column_type, meta) {
+        (0, _) => "DECIMAL".to_string(),
+        (1, _) => "TINYINT".to_string(),
+        (2, _) => "SMALLINT".to_string(),
+        (3, _) => "INT".to_string(),
+        (4, _) => "FLOAT".to_string(),
+        (5, _) => "DOUBLE".to_string(),
+        (7, _) => "TIMESTAMP".to_string(),
+        (8, _) => "BIGINT".to_string(),
+        (9, _) => "MEDIUMINT".to_string(),
+        (10, _) | (14, _) => "DATE".to_string(),
+        (11, _) => "TIME".to_string(),
+        (12, _) => "DATETIME".to_string(),
+        (13, _) => "YEAR".to_string(),
+        (16, [bits, bytes]) => format!("BIT({})", *bytes as u32 * 8 + *bits as u32),
+        (17, [0]) | (17, []) => "TIMESTAMP".to_string(),
+        (17, [fsp]) => format!("TIMESTAMP({})", fsp),
+        (18, [0]) | (18, []) => "DATETIME".to_string(),
+        (18, [fsp]) => format!("DATETIME({})", fsp),
+        (19, [0]) | (19, []) => "TIME".to_string(),
+        (19, [fsp]) => format!("TIME({})", fsp),
+        (245, _) => "JSON".to_string(),
+        (246, [precision, scale]) => format!("DECIMAL({},{})", precision, scale),
+        (255, _) => "GEOMETRY".to_string(),
+        (others, _) => format!("UNKNOWN_TYPE_{}", others),
+    }
+}
+
+/// 字符串类型在table map中只有字节长度，没有字符集信息时无法区分BLOB和TEXT、VARCHAR和VARBINARY
+fn character_column_type(
+    column_type: u8,
+    meta: &[u8],
+    collation: Option<u64>,
+) -> (String, Option<&'static str>) {
+    let is_binary = collation.map(|v| v == BINARY_COLLATION);
+
+    match (column_type, meta) {
+        (249..=252, _) => {
+            let prefix = match (column_type, meta) {
+                (249, _) | (252, [1]) => "TINY",
+                (250, _) | (252, [3]) => "MEDIUM",
+                (251, _) | (252, [4]) => "LONG",
+                _ => "",
+            };
+
+            match is_binary {
+                Some(true) => (format!("{}BLOB", prefix), None),
+                Some(false) => (format!("{}TEXT", prefix), None),
+                None => (
+                    format!("{}BLOB", prefix),
+                    Some("may also be TEXT, the charset is unknown"),
+                ),
+            }
+        }
+        (254, [real_type, length]) => {
+            // 长度超过255时高位存放在第一个字节里
+            let length = ((((*real_type as u16) & 0x30) ^ 0x30) << 4) | *length as u16;
+
+            match is_binary {
+                Some(true) => (format!("BINARY({})", length), None),
+                Some(false) => (
+                    format!("CHAR({})", length),
+                    Some(
+                        "length is in bytes, divide by the max bytes per character of the charset",
+                    ),
+                ),
+                None => (
+                    format!("CHAR({})", length),
+                    Some("length is in bytes and may also be BINARY, the charset is unknown"),
+                ),
+            }
+        }
+        (_, [low, high]) => {
+            let length = u16::from_le_bytes([*low, *high]);
+
+            match is_binary {
+                Some(true) => (format!("VARBINARY({})", length), None),
+                Some(false) => (
+                    format!("VARCHAR({})", length),
+                    Some(
+                        "length is in bytes, divide by the max bytes per character of the charset",
+                    ),
+                ),
+                None => (
+                    format!("VARCHAR({})", length),
+                    Some("length is in bytes and may also be VARBINARY, the charset is unknown"),
+                ),
+            }
+        }
+        _ => (format!("UNKNOWN_TYPE_{}", column_type), None),
+    }
+}
+
+/// optional_metadata_block是从nullable bitmap开始的
+fn parse_table_optional_metadata(
+    table_map: &EventBodyTypeCode19,
+    metadata: &[Vec<u8>],
+) -> Result<OptionalMetadata, BoxedError> {
+    let start = table_map.number_of_columns.div_ceil(8) as usize;
+    let block = &table_map.optional_metadata_block;
+
+    let mut result = OptionalMetadata::default();
+
+    if block.len() <= start {
+        return Ok(result);
+    }
+
+    let character_column_count = table_map
+        .columns
+        .iter()
+        .zip(metadata)
+        .filter(|(column, meta)| is_character_type(real_type(column.type_code(), meta)))
+        .count();
+
+    for (field_type, value) in parse_optional_metadata(&block[start..])? {
+        match field_type {
+            SIGNEDNESS => {
+                // 和null bitmap不同，这里是从最高位开始
+                result.unsigned = Some(
+                    value
+                        .iter()
+                        .flat_map(|byte| (0..8).rev().map(move |bit| byte & (1 << bit) > 0))
+                        .collect(),
+                );
+            }
+            DEFAULT_CHARSET => {
+                let mut values = parse_lenenc_list(&value)?.into_iter();
+                let default_collation = values.next().unwrap_or_default();
+
+                let mut collations = vec![default_collation; character_column_count];
+                while let (Some(index), Some(collation)) = (values.next(), values.next()) {
+                    if let Some(v) = collations.get_mut(index as usize) {
+                        *v = collation;
+                    }
+                }
+
+                result.collations = Some(collations);
+            }
+            COLUMN_CHARSET => {
+                result.collations = Some(parse_lenenc_list(&value)?);
+            }
+            COLUMN_NAME => {
+                result.column_names = Some(parse_lenenc_strings(&value)?);
+            }
+            SET_STR_VALUE | ENUM_STR_VALUE => {
+                let mut offset = 0;
+                let mut columns = Vec::new();
+
+                while offset < value.len() {
+                    let (count, skip) = parse_lenenc(&value[offset..])?;
+                    offset += skip as usize;
+
+                    let mut values = Vec::new();
+                    for _ in 0..count {
+                        values.push(take_lenenc_string(&value, &mut offset)?);
+                    }
+
+                    columns.push(values);
+                }
+
+                if field_type == SET_STR_VALUE {
+                    result.set_values = Some(columns);
+                } else {
+                    result.enum_values = Some(columns);
+                }
+            }
+            SIMPLE_PRIMARY_KEY => {
+                result.primary_key = Some(
+                    parse_lenenc_list(&value)?
+                        .into_iter()
+                        .map(|v| (v as usize, 0))
+                        .collect(),
+                );
+            }
+            PRIMARY_KEY_WITH_PREFIX => {
+                result.primary_key = Some(
+                    parse_lenenc_list(&value)?
+                        .chunks(2)
+                        .map(|v| (v[0] as usize, v.get(1).copied().unwrap_or_default()))
+                        .collect(),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn parse_lenenc_list(buffer: &[u8]) -> Result<Vec<u64>, BoxedError> {
+    let mut offset = 0;
+    let mut result = Vec::new();
+
+    while offset < buffer.len() {
+        let (value, skip) = parse_lenenc(&buffer[offset..])?;
+        offset += skip as usize;
+        result.push(value);
+    }
+
+    Ok(result)
+}
+
+fn parse_lenenc_strings(buffer: &[u8]) -> Result<Vec<String>, BoxedError> {
+    let mut offset = 0;
+    let mut result = Vec::new();
+
+    while offset < buffer.len() {
+        result.push(take_lenenc_string(buffer, &mut offset)?);
+    }
+
+    Ok(result)
+}
+
+/// 读取一个以lenenc长度开头的字符串，offset移动到字符串之后
+fn take_lenenc_string(buffer: &[u8], offset: &mut usize) -> Result<String, BoxedError> {
+    if *offset >= buffer.len() {
+        return Err(Box::new(MyError(
+            "optional metadata is truncated".to_string(),
+        )));
+    }
+
+    let (length, skip) = parse_lenenc(&buffer[*offset..])?;
+    *offset += skip as usize;
+
+    let value = buffer
+        .get(*offset..*offset + length as usize)
+        .ok_or_else(|| MyError("optional metadata is truncated".to_string()))?;
+    *offset += length as usize;
+
+    Ok(String::from_utf8_lossy(value).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::TableMapBuilder;
+
+    // 一个TLV格式的可选元数据
+    fn tlv(field_type: u8, value: &[u8]) -> Vec<u8> {
+        let mut result = vec![field_type, value.len() as u8];
+        result.extend(value);
+        result
+    }
+
+    fn lenenc_strings(values: &[&str]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| [&[v.len() as u8], v.as_bytes()].concat())
+            .collect()
+    }
+
+    // CREATE TABLE shop.orders (
+    //   id INT UNSIGNED NOT NULL,
+    //   name VARCHAR(100) CHARACTER SET utf8mb4 NULL,
+    //   price DECIMAL(10,2) NOT NULL,
+    //   status ENUM('new','paid') NULL,
+    //   data BLOB NULL,
+    //   created DATETIME(3) NOT NULL,
+    //   code CHAR(100) CHARACTER SET utf8mb4 NOT NULL,
+    //   PRIMARY KEY (id)
+    // )
+    fn orders(optional_metadata: &[u8]) -> Arc<EventBodyTypeCode19> {
+        TableMapBuilder::new("shop", "orders")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &400u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::NewDecimal, &[10, 2])
+            .column(FieldType::String, &[247, 1])
+            .nullable(true)
+            .column(FieldType::Blob, &[2])
+            .nullable(true)
+            .column(FieldType::DateTime2, &[3])
+            // 长度400的高两位在第一个字节中
+            .column(FieldType::String, &[0xee, 0x90])
+            .optional_metadata(optional_metadata)
+            .table_info()
+            .unwrap()
+    }
+
+    // binlog_row_metadata=FULL
+    fn full_metadata() -> Vec<u8> {
+        [
+            // id和price中只有id是UNSIGNED
+            tlv(SIGNEDNESS, &[0b1000_0000]),
+            // 默认utf8mb4_general_ci，第二个字符串字段（data）是binary
+            tlv(DEFAULT_CHARSET, &[45, 1, 63]),
+            tlv(
+                COLUMN_NAME,
+                &lenenc_strings(&["id", "name", "price", "status", "data", "created", "code"]),
+            ),
+            tlv(
+                ENUM_STR_VALUE,
+                &[[2].as_slice(), &lenenc_strings(&["new", "paid"])].concat(),
+            ),
+            tlv(SIMPLE_PRIMARY_KEY, &[0]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn full_metadata_statement() {
+        assert_eq!(
+            create_table_statement(&orders(&full_metadata())).unwrap(),
+            [
+                "CREATE TABLE `shop`.`orders` (",
+                "  `id` INT UNSIGNED NOT NULL,",
+                "  `name` VARCHAR(400) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NULL \
+                 /* length is in bytes, divide by the max bytes per character of the charset */,",
+                "  `price` DECIMAL(10,2) NOT NULL,",
+                "  `status` ENUM('new','paid') NULL,",
+                "  `data` BLOB NULL,",
+                "  `created` DATETIME(3) NOT NULL,",
+                "  `code` CHAR(400) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL \
+                 /* length is in bytes, divide by the max bytes per character of the charset */,",
+                "  PRIMARY KEY (`id`)",
+                ");",
+            ]
+            .join("\n")
+        );
+    }
+
+    // binlog_row_metadata=MINIMAL，字段名、符号和字符集都不知道
+    #[test]
+    fn minimal_metadata_statement() {
+        assert_eq!(
+            create_table_statement(&orders(&[])).unwrap(),
+            [
+                "-- column names are unknown without binlog_row_metadata=FULL",
+                "CREATE TABLE `shop`.`orders` (",
+                "  `col_1` INT NOT NULL,",
+                "  `col_2` VARCHAR(400) NULL \
+                 /* length is in bytes and may also be VARBINARY, the charset is unknown */,",
+                "  `col_3` DECIMAL(10,2) NOT NULL,",
+                "  `col_4` ENUM(...) NULL /* values are unknown without binlog_row_metadata=FULL */,",
+                "  `col_5` BLOB NULL /* may also be TEXT, the charset is unknown */,",
+                "  `col_6` DATETIME(3) NOT NULL,",
+                "  `col_7` CHAR(400) NOT NULL \
+                 /* length is in bytes and may also be BINARY, the charset is unknown */",
+                ");",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn prefix_primary_key() {
+        let table_map = TableMapBuilder::new("shop", "notes")
+            .column(FieldType::Long, &[])
+            .column(FieldType::Blob, &[2])
+            .optional_metadata(
+                &[
+                    tlv(COLUMN_CHARSET, &[45]),
+                    tlv(COLUMN_NAME, &lenenc_strings(&["id", "body"])),
+                    tlv(PRIMARY_KEY_WITH_PREFIX, &[0, 0, 1, 10]),
+                ]
+                .concat(),
+            )
+            .table_info()
+            .unwrap();
+
+        let statement = create_table_statement(&table_map).unwrap();
+        assert!(
+            statement
+                .contains("`body` TEXT CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL,"),
+            "{}",
+            statement
+        );
+        assert!(
+            statement.ends_with("  PRIMARY KEY (`id`, `body`(10))\n);"),
+            "{}",
+            statement
+        );
+        assert_eq!(primary_key_columns(&table_map), Some(vec![0, 1]));
+    }
+
+    // 多字节的字段名、保留字和反引号
+    #[test]
+    fn multibyte_and_reserved_column_names() {
+        use crate::builders::*;
+        use crate::cli::InsertMode;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+        use crate::sql::row_statements;
+        use std::io::Cursor;
+
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(42)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &400u16.to_le_bytes())
+            .column(FieldType::Long, &[])
+            .column(FieldType::Long, &[])
+            .optional_metadata(
+                &[
+                    tlv(COLUMN_CHARSET, &[45]),
+                    tlv(
+                        COLUMN_NAME,
+                        &lenenc_strings(&["id", "名前", "order", "we`ird"]),
+                    ),
+                    tlv(SIMPLE_PRIMARY_KEY, &[0]),
+                ]
+                .concat(),
+            );
+        let names = ["id", "名前", "order", "we`ird"].map(String::from);
+
+        let table_map = table.table_info().unwrap();
+        assert_eq!(table_map.column_names.as_deref(), Some(&names[..]));
+        assert_eq!(column_names(&table_map), names);
+        let statement = create_table_statement(&table_map).unwrap();
+        assert!(statement.contains("  `名前` VARCHAR(400)"), "{}", statement);
+        assert!(
+            statement.contains("  `order` INT NOT NULL,"),
+            "{}",
+            statement
+        );
+        assert!(
+            statement.contains("  `we``ird` INT NOT NULL,"),
+            "{}",
+            statement
+        );
+
+        // 行事件和生成的SQL使用字段名而不是@1、@2
+        let insert = RowsEventBuilder::insert(&table).row(vec![
+            ColumnValue::Int(1),
+            ColumnValue::String("ア".to_string()),
+            ColumnValue::Int(2),
+            ColumnValue::Int(3),
+        ]);
+        let binlog = BinlogBuilder::new().event(&table).event(&insert).build();
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        let mut bodies = Vec::new();
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            bodies.push(
+                get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap(),
+            );
+            position = header.next_event_position as u64;
+        }
+
+        assert_eq!(
+            rows_event_column_names(bodies[2].as_ref(), &table_structs),
+            Some(&names[..])
+        );
+        let rows_event = bodies[2].downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+        let row = &rows_event.rows(&table_structs[&42]).unwrap()[0];
+        assert_eq!(
+            row.get_by_name("名前"),
+            Some(&ColumnValue::String("ア".to_string()))
+        );
+
+        let statements = row_statements(
+            23,
+            rows_event,
+            &table_structs[&42],
+            &column_names(&table_structs[&42]),
+            true,
+            InsertMode::Insert,
+        )
+        .unwrap();
+        assert_eq!(
+            statements[0].sql,
+            "INSERT INTO `shop`.`orders` (`id`, `名前`, `order`, `we``ird`) VALUES (1, 'ア', 2, 3)"
+        );
+    }
+
+    // 同一张表只在结构变化时再输出
+    #[test]
+    fn print_each_structure_once() {
+        let mut tracker = TableStructureTracker::new();
+        let minimal = orders(&[]);
+        let full = orders(&full_metadata());
+
+        assert!(tracker.process(&minimal).unwrap().is_some());
+        assert!(tracker.process(&minimal).unwrap().is_none());
+        assert!(tracker.process(&full).unwrap().is_some());
+        assert!(tracker.process(&full).unwrap().is_none());
+    }
+
+    #[test]
+    fn descriptor_of_each_type() {
+        let table_map = TableMapBuilder::new("db", "t")
+            .column(FieldType::Tiny, &[])
+            .column(FieldType::Short, &[])
+            .column(FieldType::Long, &[])
+            .column(FieldType::LongLong, &[])
+            .column(FieldType::Float, &[4])
+            .column(FieldType::Double, &[8])
+            .column(FieldType::NewDecimal, &[10, 2])
+            // 小数位数比总位数多的元数据不合法
+            .column(FieldType::NewDecimal, &[2, 5])
+            .column(FieldType::Varchar, &100u16.to_le_bytes())
+            .column(FieldType::Varchar, &300u16.to_le_bytes())
+            .column(FieldType::Date, &[])
+            .column(FieldType::Time2, &[3])
+            .column(FieldType::DateTime2, &[6])
+            .column(FieldType::Timestamp2, &[1])
+            .column(FieldType::Blob, &[4])
+            .column(FieldType::Json, &[4])
+            .column(FieldType::Year, &[])
+            .nullable(true)
+            .table_info()
+            .unwrap();
+
+        let layouts = column_descriptors(&table_map)
+            .iter()
+            .map(|v| (v.kind, v.length, v.precision, v.decimals))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layouts,
+            [
+                (ColumnKind::Tiny, ColumnLength::Fixed(1), 0, 0),
+                (ColumnKind::Short, ColumnLength::Fixed(2), 0, 0),
+                (ColumnKind::Long, ColumnLength::Fixed(4), 0, 0),
+                (ColumnKind::LongLong, ColumnLength::Fixed(8), 0, 0),
+                (ColumnKind::Float, ColumnLength::Fixed(4), 0, 0),
+                (ColumnKind::Double, ColumnLength::Fixed(8), 0, 0),
+                // 整数部分8位占4个字节，小数部分2位占1个字节
+                (ColumnKind::NewDecimal, ColumnLength::Fixed(5), 10, 2),
+                (ColumnKind::Unsupported, ColumnLength::Unknown, 0, 0),
+                (ColumnKind::Varchar, ColumnLength::Prefixed(1), 0, 0),
+                (ColumnKind::Varchar, ColumnLength::Prefixed(2), 0, 0),
+                (ColumnKind::Date, ColumnLength::Fixed(3), 0, 0),
+                // 秒的小数部分每两位一个字节
+                (ColumnKind::Time2, ColumnLength::Fixed(5), 0, 3),
+                (ColumnKind::DateTime2, ColumnLength::Fixed(8), 0, 6),
+                (ColumnKind::Timestamp2, ColumnLength::Fixed(5), 0, 1),
+                (ColumnKind::Blob, ColumnLength::Prefixed(4), 0, 0),
+                (ColumnKind::Json, ColumnLength::Prefixed(4), 0, 0),
+                (ColumnKind::Unsupported, ColumnLength::Unknown, 0, 0),
+            ]
+        );
+
+        // 预先计算的和现在计算的一样
+        assert_eq!(
+            table_map.descriptors().as_ref(),
+            column_descriptors(&table_map).as_slice()
+        );
+        let nullable = table_map
+            .descriptors()
+            .iter()
+            .map(|v| v.nullable)
+            .collect::<Vec<_>>();
+        assert_eq!(nullable.iter().filter(|v| **v).count(), 1);
+        assert!(nullable[16]);
+    }
+
+    #[test]
+    fn descriptor_charset_and_unsigned() {
+        let descriptors = orders(&full_metadata()).descriptors().into_owned();
+        assert_eq!(descriptors[0].unsigned, Some(true));
+        assert_eq!(descriptors[2].unsigned, Some(false));
+        assert_eq!(descriptors[1].charset, Some(45));
+        assert_eq!(descriptors[4].charset, Some(63));
+
+        // 没有可选元数据时不知道
+        let descriptors = orders(&[]).descriptors().into_owned();
+        assert!(descriptors
+            .iter()
+            .all(|v| v.unsigned.is_none() && v.charset.is_none()));
+    }
+}
@@ -0,0 +1,521 @@
+use std::{collections::HashMap, env, sync::Arc};
+
+use crate::model::*;
+use crate::table_structure::metadata_column_names;
+use crate::util::{format_timestamp, get_event_type_name};
+
+/// 没有`--max-column-width`时每个字段最多显示的字符数
+pub const DEFAULT_MAX_COLUMN_WIDTH: usize = 32;
+
+// 没有COLUMNS环境变量时按这个宽度换行
+const DEFAULT_TERMINAL_WIDTH: usize = 120;
+
+// NULL和字符串`NULL`区分开
+const NULL: &str = "∅";
+
+/// `--output table`，行事件按表格输出，其他事件输出为一行摘要
+///
+/// 表头是字段名，没有binlog_row_metadata=FULL时和mysqlbinlog一样是`@1`、`@2`，只有镜像中有的字段。
+/// update的每一行分为before和after两行。超过max_column_width的值截断，末尾是`…`；
+/// 二进制数据按十六进制，值中的换行和tab转义。表格比终端（COLUMNS环境变量）宽时按字段分成几个表格依次输出
+pub struct TableRenderer {
+    max_column_width: usize,
+    terminal_width: usize,
+}
+
+impl TableRenderer {
+    pub fn new(max_column_width: usize) -> TableRenderer {
+        let terminal_width = env::var("COLUMNS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_TERMINAL_WIDTH);
+
+        TableRenderer {
+            max_column_width,
+            terminal_width,
+        }
+    }
+
+    /// position是事件在文件中的起始位置，返回的内容以换行结束
+    pub fn process(
+        &self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> String {
+        let mut result = format!(
+            "# at {}  {}  {}",
+            position,
+            format_timestamp(header.timestamp),
+            get_event_type_name(header.type_code)
+        );
+
+        let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>();
+        let summary = match rows_event {
+            Some(rows_event) => {
+                let table = table_structs
+                    .get(&rows_event.table_id)
+                    .map(|v| format!("{}.{}", v.database_name, v.table_name))
+                    .unwrap_or_else(|| format!("table_id {}", rows_event.table_id));
+                format!("{}, {} row(s)", table, rows_event.rows.len())
+            }
+            None => summary(header, body),
+        };
+        if !summary.is_empty() {
+            result.push_str("  ");
+            result.push_str(&summary);
+        }
+        result.push('\n');
+
+        if let Some(rows_event) = rows_event {
+            if !rows_event.rows.is_empty() {
+                let table_map = table_structs.get(&rows_event.table_id);
+                result.push_str(&self.rows(rows_event, table_map.map(|v| v.as_ref())));
+                result.push('\n');
+            }
+        }
+
+        result
+    }
+
+    fn rows(
+        &self,
+        rows_event: &EventBodyTypeCode23To25,
+        table_map: Option<&EventBodyTypeCode19>,
+    ) -> String {
+        let is_update = rows_event.columns_used_for_update.is_some();
+        let names = table_map.and_then(metadata_column_names);
+
+        // 修改前后的镜像中有任何一个有的字段都显示，另一个镜像中没有时为空
+        let after_columns = rows_event
+            .columns_used_for_update
+            .as_ref()
+            .unwrap_or(&rows_event.columns_used);
+        let columns = (0..rows_event.columns_used.len().max(after_columns.len()))
+            .filter(|index| {
+                rows_event.columns_used.get(*index).unwrap_or(false)
+                    || after_columns.get(*index).unwrap_or(false)
+            })
+            .collect::<Vec<usize>>();
+
+        let mut header = Vec::new();
+        if is_update {
+            header.push(String::new());
+        }
+        header.extend(columns.iter().map(|index| {
+            names
+                .as_ref()
+                .and_then(|v| v.get(*index))
+                .cloned()
+                .unwrap_or_else(|| format!("@{}", index + 1))
+        }));
+
+        let mut lines = Vec::new();
+        for row in &rows_event.rows {
+            let images = [
+                ("before", row.before.as_ref(), &rows_event.columns_used),
+                ("after", row.after.as_ref(), after_columns),
+            ];
+            for (label, image, present) in images {
+                let image = match image {
+                    Some(image) => image,
+                    None => continue,
+                };
+
+                let mut line = Vec::new();
+                if is_update {
+                    line.push(label.to_string());
+                }
+                line.extend(columns.iter().map(|index| {
+                    match (present.get(*index).unwrap_or(false), image.get(*index)) {
+                        (true, Some(value)) => format_value(value),
+                        _ => String::new(),
+                    }
+                }));
+                lines.push(line);
+            }
+        }
+
+        let header = header
+            .into_iter()
+            .map(|v| self.truncate(&v))
+            .collect::<Vec<String>>();
+        let lines = lines
+            .into_iter()
+            .map(|line| line.iter().map(|v| self.truncate(v)).collect())
+            .collect::<Vec<Vec<String>>>();
+
+        // update的before/after标签在每个表格中都要有
+        let fixed = if is_update { 1 } else { 0 };
+        let widths = (0..header.len())
+            .map(|i| {
+                lines
+                    .iter()
+                    .map(|line| width(&line[i]))
+                    .chain(std::iter::once(width(&header[i])))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<usize>>();
+
+        let mut result = String::new();
+        for group in self.column_groups(&widths, fixed) {
+            let indexes = (0..fixed).chain(group).collect::<Vec<usize>>();
+            result.push_str(&draw(&indexes, &widths, &header, &lines));
+        }
+
+        result
+    }
+
+    // 按终端宽度把字段分组，每组加上固定的字段后不超过终端宽度，一组至少有一个字段
+    fn column_groups(&self, widths: &[usize], fixed: usize) -> Vec<Vec<usize>> {
+        // 每个字段占`| `、值和一个空格，最后还有一个`|`
+        let fixed_width = 1 + widths[..fixed].iter().map(|v| v + 3).sum::<usize>();
+
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut current_width = fixed_width;
+        for (index, width) in widths.iter().enumerate().skip(fixed) {
+            match groups.last_mut() {
+                Some(group) if current_width + width + 3 <= self.terminal_width => {
+                    group.push(index);
+                    current_width += width + 3;
+                }
+                _ => {
+                    groups.push(vec![index]);
+                    current_width = fixed_width + width + 3;
+                }
+            }
+        }
+
+        groups
+    }
+
+    fn truncate(&self, value: &str) -> String {
+        match value.chars().count() > self.max_column_width {
+            true => {
+                let mut result = value
+                    .chars()
+                    .take(self.max_column_width.saturating_sub(1))
+                    .collect::<String>();
+                result.push('…');
+                result
+            }
+            false => value.to_string(),
+        }
+    }
+}
+
+// 和mysql客户端一样的表格
+fn draw(indexes: &[usize], widths: &[usize], header: &[String], lines: &[Vec<String>]) -> String {
+    let separator = indexes
+        .iter()
+        .map(|i| "-".repeat(widths[*i] + 2))
+        .collect::<Vec<String>>()
+        .join("+");
+    let separator = format!("+{}+\n", separator);
+
+    let line = |cells: &[String]| {
+        let cells = indexes
+            .iter()
+            .map(|i| {
+                let padding = " ".repeat(widths[*i] - width(&cells[*i]));
+                format!(" {}{} ", cells[*i], padding)
+            })
+            .collect::<Vec<String>>()
+            .join("|");
+        format!("|{}|\n", cells)
+    };
+
+    let mut result = separator.clone();
+    result.push_str(&line(header));
+    result.push_str(&separator);
+    for cells in lines {
+        result.push_str(&line(cells));
+    }
+    result.push_str(&separator);
+
+    result
+}
+
+// 按字符数计算宽度，不考虑全角字符
+fn width(value: &str) -> usize {
+    value.chars().count()
+}
+
+/// 非行事件的一行摘要
+fn summary(header: &EventHeader, body: &dyn EventBody) -> String {
+    if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+        return match query_event.database_name.is_empty() {
+            true => one_line(&query_event.sql),
+            false => format!(
+                "{}: {}",
+                query_event.database_name,
+                one_line(&query_event.sql)
+            ),
+        };
+    }
+    if let Some(annotate) = body.downcast_ref::<EventBodyTypeCode160>() {
+        return one_line(&annotate.sql);
+    }
+    if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+        return format!(
+            "{}-{}-{}",
+            gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+        );
+    }
+    if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+        return format!(
+            "{}.{} (table_id {})",
+            table_map.database_name, table_map.table_name, table_map.table_id
+        );
+    }
+    if let Some(xid_event) = body.downcast_ref::<EventBodyTypeCode16>() {
+        return format!("xid={}", xid_event.xid_transaction_number);
+    }
+    if let Some(rotate) = body.downcast_ref::<EventBodyTypeCode4>() {
+        return rotate.file_name_of_next_binary_log.clone();
+    }
+    if let Some(format_description) = body.downcast_ref::<EventBodyTypeCode15>() {
+        return format_description.server_version.clone();
+    }
+
+    String::new()
+}
+
+// SQL中的换行替换为空格
+fn one_line(sql: &str) -> String {
+    sql.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// 二进制数据按十六进制，换行、tab这类控制字符转义，保证表格对齐
+fn format_value(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Null => NULL.to_string(),
+        ColumnValue::Int(v) => v.to_string(),
+        ColumnValue::UInt(v) => v.to_string(),
+        ColumnValue::Float(v) => v.to_string(),
+        ColumnValue::Double(v) => v.to_string(),
+        ColumnValue::Decimal(v) | ColumnValue::String(v) | ColumnValue::Temporal(v) => v
+            .chars()
+            .flat_map(|c| match c.is_control() {
+                true => c.escape_default().collect::<Vec<char>>(),
+                false => vec![c],
+            })
+            .collect(),
+        ColumnValue::RawTemporal(v) => v.to_string(),
+        ColumnValue::RawDecimal(v) => v.value.clone(),
+        ColumnValue::Bytes(v) => format!(
+            "0x{}",
+            v.iter().map(|v| format!("{:02x}", v)).collect::<String>()
+        ),
+        ColumnValue::Unsupported(v) => format!("<{}>", v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::*;
+    use std::io::Cursor;
+
+    // shop.users(id, name, avatar)带字段名
+    fn users() -> TableMapBuilder {
+        let names = ["id", "name", "avatar"]
+            .iter()
+            .flat_map(|v| [&[v.len() as u8], v.as_bytes()].concat())
+            .collect::<Vec<u8>>();
+
+        TableMapBuilder::new("shop", "users")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Blob, &[2])
+            .nullable(true)
+            .optional_metadata(&[vec![4, names.len() as u8], names].concat())
+    }
+
+    fn user(id: i64, name: Option<&str>, avatar: Option<&[u8]>) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::Int(id),
+            name.map_or(ColumnValue::Null, |v| ColumnValue::String(v.to_string())),
+            avatar.map_or(ColumnValue::Null, |v| ColumnValue::Bytes(v.to_vec())),
+        ]
+    }
+
+    fn render(
+        renderer: &TableRenderer,
+        table: &TableMapBuilder,
+        rows_event: &RowsEventBuilder,
+    ) -> String {
+        let binlog = BinlogBuilder::new()
+            .push(EventBuilder::from_body(table).timestamp(1700000000))
+            .push(EventBuilder::from_body(rows_event).timestamp(1700000000))
+            .build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut output = String::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            if header.type_code != 15 {
+                output.push_str(&renderer.process(
+                    &header,
+                    position,
+                    body.as_ref(),
+                    &table_structs,
+                ));
+            }
+            position = header.next_event_position as u64;
+        }
+
+        output
+    }
+
+    fn renderer(max_column_width: usize, terminal_width: usize) -> TableRenderer {
+        TableRenderer {
+            max_column_width,
+            terminal_width,
+        }
+    }
+
+    #[test]
+    fn insert() {
+        let table = users();
+        let output = render(
+            &renderer(DEFAULT_MAX_COLUMN_WIDTH, 120),
+            &table,
+            &RowsEventBuilder::insert(&table)
+                .row(user(1, Some("ann"), Some(b"\x89PNG")))
+                .row(user(2, Some("bob"), None)),
+        );
+        assert_eq!(
+            output,
+            concat!(
+                "# at 249  2023-11-14 22:13:20  TABLE_MAP_EVENT  shop.users (table_id 70)\n",
+                "# at 319  2023-11-14 22:13:20  WRITE_ROWS_EVENT_V1  shop.users, 2 row(s)\n",
+                "+----+------+------------+\n",
+                "| id | name | avatar     |\n",
+                "+----+------+------------+\n",
+                "| 1  | ann  | 0x89504e47 |\n",
+                "| 2  | bob  | ∅          |\n",
+                "+----+------+------------+\n",
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn update() {
+        let table = users();
+        let output = render(
+            &renderer(DEFAULT_MAX_COLUMN_WIDTH, 120),
+            &table,
+            &RowsEventBuilder::update(&table)
+                .update_row(user(1, Some("ann"), None), user(1, Some("amy"), None))
+                .update_row(user(2, None, None), user(2, Some("bob"), None)),
+        );
+        assert_eq!(
+            output,
+            concat!(
+                "# at 249  2023-11-14 22:13:20  TABLE_MAP_EVENT  shop.users (table_id 70)\n",
+                "# at 319  2023-11-14 22:13:20  UPDATE_ROWS_EVENT_V1  shop.users, 2 row(s)\n",
+                "+--------+----+------+--------+\n",
+                "|        | id | name | avatar |\n",
+                "+--------+----+------+--------+\n",
+                "| before | 1  | ann  | ∅      |\n",
+                "| after  | 1  | amy  | ∅      |\n",
+                "| before | 2  | ∅    | ∅      |\n",
+                "| after  | 2  | bob  | ∅      |\n",
+                "+--------+----+------+--------+\n",
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn null_binary_and_long_values() {
+        // 没有字段名时是@1、@2
+        let table = TableMapBuilder::new("shop", "notes")
+            .table_id(71)
+            .column(FieldType::Varchar, &255u16.to_le_bytes())
+            .nullable(true)
+            .column(FieldType::Blob, &[2])
+            .nullable(true);
+        // 超过12个字符的值截断，字符串NULL和NULL值不同
+        let output = render(
+            &renderer(12, 120),
+            &table,
+            &RowsEventBuilder::insert(&table)
+                .row(vec![
+                    ColumnValue::String("line 1\nline 2\tend".to_string()),
+                    ColumnValue::Bytes(vec![0, 1, 2, 0xfe, 0xff, 0x10, 0x20]),
+                ])
+                .row(vec![
+                    ColumnValue::String("NULL".to_string()),
+                    ColumnValue::Null,
+                ]),
+        );
+        assert_eq!(
+            output,
+            concat!(
+                "# at 249  2023-11-14 22:13:20  TABLE_MAP_EVENT  shop.notes (table_id 71)\n",
+                "# at 301  2023-11-14 22:13:20  WRITE_ROWS_EVENT_V1  shop.notes, 2 row(s)\n",
+                "+--------------+--------------+\n",
+                "| @1           | @2           |\n",
+                "+--------------+--------------+\n",
+                "| line 1\\nlin… | 0x000102fef… |\n",
+                "| NULL         | ∅            |\n",
+                "+--------------+--------------+\n",
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn wide_table_is_split() {
+        // 终端只有30列，按字段分成两个表格，每个表格都有before/after
+        let table = users();
+        let output = render(
+            &renderer(DEFAULT_MAX_COLUMN_WIDTH, 30),
+            &table,
+            &RowsEventBuilder::update(&table).update_row(
+                user(1, Some("ann"), Some(b"\x89PNG")),
+                user(1, Some("amy"), Some(b"\x89PNG")),
+            ),
+        );
+        assert_eq!(
+            output,
+            concat!(
+                "# at 249  2023-11-14 22:13:20  TABLE_MAP_EVENT  shop.users (table_id 70)\n",
+                "# at 319  2023-11-14 22:13:20  UPDATE_ROWS_EVENT_V1  shop.users, 1 row(s)\n",
+                "+--------+----+------+\n",
+                "|        | id | name |\n",
+                "+--------+----+------+\n",
+                "| before | 1  | ann  |\n",
+                "| after  | 1  | amy  |\n",
+                "+--------+----+------+\n",
+                "+--------+------------+\n",
+                "|        | avatar     |\n",
+                "+--------+------------+\n",
+                "| before | 0x89504e47 |\n",
+                "| after  | 0x89504e47 |\n",
+                "+--------+------------+\n",
+                "\n",
+            )
+        );
+    }
+}
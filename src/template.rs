@@ -0,0 +1,422 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::model::*;
+use crate::util::get_event_type_name;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 模板中可以使用的字段
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Offset,
+    EndOffset,
+    Timestamp,
+    Type,
+    ServerId,
+    Db,
+    Table,
+    Op,
+    Gtid,
+    Rows,
+    Sql,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "offset" => Some(Field::Offset),
+            "end_offset" => Some(Field::EndOffset),
+            "timestamp" => Some(Field::Timestamp),
+            "type" => Some(Field::Type),
+            "server_id" => Some(Field::ServerId),
+            "db" => Some(Field::Db),
+            "table" => Some(Field::Table),
+            "op" => Some(Field::Op),
+            "gtid" => Some(Field::Gtid),
+            "rows" => Some(Field::Rows),
+            "sql" => Some(Field::Sql),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    /// `{sql:80}`中的80是最多保留的字符数
+    Placeholder(Field, Option<usize>),
+}
+
+/// `--format`的模板，`{offset}`这样的占位符替换成事件中的值，`{{`和`}}`是花括号本身
+/// 占位符后面加上`:N`时最多保留N个字符，事件中没有的值输出为空
+#[derive(Debug, Clone)]
+pub struct EventTemplate {
+    parts: Vec<Part>,
+}
+
+impl EventTemplate {
+    /// 不认识的占位符和没有闭合的花括号都返回错误
+    pub fn parse(template: &str) -> Result<EventTemplate, BoxedError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                ('{', _) => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(Box::new(MyError(format!(
+                                    "unclosed `{{` in format `{}`",
+                                    template
+                                ))));
+                            }
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&placeholder)?);
+                }
+                ('}', _) => {
+                    return Err(Box::new(MyError(format!(
+                        "unmatched `}}` in format `{}`, use `}}}}` for `}}`",
+                        template
+                    ))));
+                }
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(EventTemplate { parts })
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, BoxedError> {
+    let (name, width) = match placeholder.split_once(':') {
+        Some((name, width)) => (name, Some(width)),
+        None => (placeholder, None),
+    };
+
+    let field = Field::from_name(name.trim())
+        .ok_or_else(|| MyError(format!("unknown placeholder `{{{}}}`", placeholder)))?;
+
+    let width = match width {
+        Some(width) => Some(width.trim().parse::<usize>().map_err(|_| {
+            MyError(format!(
+                "invalid width in placeholder `{{{}}}`",
+                placeholder
+            ))
+        })?),
+        None => None,
+    };
+
+    Ok(Part::Placeholder(field, width))
+}
+
+/// 按模板把每个事件输出为一行，需要每个事件都经过process以便记录当前的GTID
+pub struct EventFormatter {
+    template: EventTemplate,
+    current_gtid: Option<String>,
+}
+
+impl EventFormatter {
+    pub fn new(template: &EventTemplate) -> EventFormatter {
+        EventFormatter {
+            template: template.clone(),
+            current_gtid: None,
+        }
+    }
+
+    /// position是事件在文件中的起始位置
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> String {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.current_gtid = Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ));
+        }
+        // MySQL的anonymous GTID事件没有GTID
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            self.current_gtid =
+                (gtid_event.gno > 0).then(|| format!("{}:{}", gtid_event.uuid, gtid_event.gno));
+        }
+
+        let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>();
+        let query_event = body.downcast_ref::<EventBodyTypeCode2>();
+        let table_map = match (body.downcast_ref::<EventBodyTypeCode19>(), rows_event) {
+            (Some(table_map), _) => Some(table_map),
+            (None, Some(rows_event)) => table_structs.get(&rows_event.table_id).map(|v| v.as_ref()),
+            (None, None) => None,
+        };
+
+        let mut result = String::new();
+        for part in &self.template.parts {
+            let (field, width) = match part {
+                Part::Literal(literal) => {
+                    result.push_str(literal);
+                    continue;
+                }
+                Part::Placeholder(field, width) => (*field, *width),
+            };
+
+            let value = match field {
+                Field::Offset => position.to_string(),
+                Field::EndOffset => header.next_event_position.to_string(),
+                Field::Timestamp => header.timestamp.to_string(),
+                Field::Type => get_event_type_name(header.type_code).to_string(),
+                Field::ServerId => header.server_id.to_string(),
+                Field::Db => match (table_map, query_event) {
+                    (Some(table_map), _) => table_map.database_name.clone(),
+                    (None, Some(query_event)) => query_event.database_name.clone(),
+                    (None, None) => String::new(),
+                },
+                Field::Table => table_map.map(|v| v.table_name.clone()).unwrap_or_default(),
+                Field::Op => match (rows_event, header.type_code) {
+                    (Some(_), 23 | 30) => "insert".to_string(),
+                    (Some(_), 24 | 31) => "update".to_string(),
+                    (Some(_), _) => "delete".to_string(),
+                    (None, _) => String::new(),
+                },
+                Field::Gtid => self.current_gtid.clone().unwrap_or_default(),
+                Field::Rows => rows_event
+                    .map(|v| v.rows.len().to_string())
+                    .unwrap_or_default(),
+                // 一个事件输出为一行，SQL中的换行替换为空格
+                Field::Sql => query_event
+                    .map(|v| v.sql.as_str())
+                    .or_else(|| {
+                        body.downcast_ref::<EventBodyTypeCode160>()
+                            .map(|v| v.sql.as_str())
+                    })
+                    .map(|v| v.replace(['\r', '\n'], " "))
+                    .unwrap_or_default(),
+            };
+
+            match width {
+                Some(width) => result.extend(value.chars().take(width)),
+                None => result.push_str(&value),
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(type_code: u8) -> EventHeader {
+        EventHeader {
+            timestamp: 1700000000,
+            type_code,
+            server_id: 7,
+            event_length: 100,
+            next_event_position: 356,
+            flags: 0,
+        }
+    }
+
+    fn render(template: &str, header: &EventHeader, body: &dyn EventBody) -> String {
+        let template = EventTemplate::parse(template).unwrap();
+        EventFormatter::new(&template).process(header, 256, body, &HashMap::new())
+    }
+
+    fn annotate(sql: &str) -> EventBodyTypeCode160 {
+        EventBodyTypeCode160 {
+            sql: sql.to_string(),
+        }
+    }
+
+    fn parse_error(template: &str) -> String {
+        match EventTemplate::parse(template) {
+            Ok(_) => panic!("`{}` should be invalid", template),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn escape_braces() {
+        let body = annotate("SELECT 1");
+        assert_eq!(
+            render("{{offset}} {{{offset}}} }}{type}", &header(160), &body),
+            "{offset} {256} }ANNOTATE_ROWS_EVENT"
+        );
+        assert_eq!(render("{{}}", &header(160), &body), "{}");
+        assert_eq!(render("", &header(160), &body), "");
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(parse_error("{offset").contains("unclosed `{`"));
+        assert!(parse_error("offset}").contains("unmatched `}`"));
+        assert!(parse_error("{offset}}").contains("unmatched `}`"));
+        assert!(parse_error("{offsett}").contains("unknown placeholder `{offsett}`"));
+        assert!(parse_error("{}").contains("unknown placeholder `{}`"));
+        assert!(parse_error("{sql:abc}").contains("invalid width in placeholder `{sql:abc}`"));
+        assert!(parse_error("{sql:-1}").contains("invalid width"));
+        // 名字和宽度前后可以有空格
+        assert!(EventTemplate::parse("{ sql : 10 }").is_ok());
+    }
+
+    #[test]
+    fn truncate_values() {
+        let body = annotate("INSERT INTO t\nVALUES ('中文')");
+        // 换行替换为空格
+        assert_eq!(
+            render("[{sql:20}]", &header(160), &body),
+            "[INSERT INTO t VALUES]"
+        );
+        // 按字符截断，不会截断在多字节字符中间
+        assert_eq!(
+            render("[{sql:24}]", &header(160), &body),
+            "[INSERT INTO t VALUES ('中]"
+        );
+        assert_eq!(render("[{sql:0}]", &header(160), &body), "[]");
+        assert_eq!(
+            render("[{sql:100}]", &header(160), &body),
+            "[INSERT INTO t VALUES ('中文')]"
+        );
+        assert_eq!(render("{type:5}", &header(160), &body), "ANNOT");
+    }
+
+    #[test]
+    fn missing_values_are_empty() {
+        let body = EventBodyTypeCode16 {
+            xid_transaction_number: 9,
+        };
+        assert_eq!(
+            render(
+                "{offset}-{end_offset} {timestamp} {server_id} {type} db=[{db}] table=[{table}] \
+                 op=[{op}] gtid=[{gtid}] rows=[{rows}] sql=[{sql}]",
+                &header(16),
+                &body
+            ),
+            "256-356 1700000000 7 XID_EVENT db=[] table=[] op=[] gtid=[] rows=[] sql=[]"
+        );
+    }
+
+    mod events {
+        use std::io::Cursor;
+
+        use super::*;
+        use crate::builders::*;
+        use crate::field_type::FieldType;
+        use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+        // 按模板输出每个事件
+        fn render_all(template: &str, binlog: &[u8]) -> Vec<String> {
+            let template = EventTemplate::parse(template).unwrap();
+            let mut formatter = EventFormatter::new(&template);
+            let mut file = Cursor::new(binlog.to_vec());
+            let mut table_structs = HashMap::new();
+            let mut lines = Vec::new();
+            let mut position = 4;
+            while position < binlog.len() as u64 {
+                let header = get_event_header(&mut file, position).unwrap();
+                let body = get_event_body(
+                    &mut file,
+                    position,
+                    &header,
+                    &mut table_structs,
+                    CHECKSUM_LENGTH,
+                )
+                .unwrap();
+                lines.push(formatter.process(&header, position, body.as_ref(), &table_structs));
+                position = header.next_event_position as u64;
+            }
+
+            lines
+        }
+
+        fn events(builder: BinlogBuilder, gtid: EventBuilder, v2: bool) -> Vec<u8> {
+            let table = TableMapBuilder::new("shop", "orders")
+                .table_id(42)
+                .column(FieldType::Long, &[]);
+            let row = |id: i64| vec![ColumnValue::Int(id)];
+
+            builder
+                .push(gtid)
+                .event(&QueryEventBuilder::new("shop", "BEGIN"))
+                .event(&table)
+                .event(
+                    &RowsEventBuilder::insert(&table)
+                        .v2(v2)
+                        .row(row(1))
+                        .row(row(2)),
+                )
+                .event(
+                    &RowsEventBuilder::update(&table)
+                        .v2(v2)
+                        .update_row(row(1), row(3)),
+                )
+                .event(&RowsEventBuilder::delete(&table).v2(v2).row(row(2)))
+                .build()
+        }
+
+        const TEMPLATE: &str = "{type} {db}.{table} {op} {rows} {gtid}";
+
+        #[test]
+        fn render_mariadb_events() {
+            let mut gtid = 11u64.to_le_bytes().to_vec();
+            gtid.extend(1u32.to_le_bytes());
+            gtid.push(0);
+            gtid.extend([0u8; 6]);
+            let binlog = events(BinlogBuilder::new(), EventBuilder::new(162, gtid), false);
+
+            assert_eq!(
+                render_all(TEMPLATE, &binlog)[1..],
+                [
+                    "GTID_EVENT .   1-1-11",
+                    "QUERY_EVENT shop.   1-1-11",
+                    "TABLE_MAP_EVENT shop.orders   1-1-11",
+                    "WRITE_ROWS_EVENT_V1 shop.orders insert 2 1-1-11",
+                    "UPDATE_ROWS_EVENT_V1 shop.orders update 1 1-1-11",
+                    "DELETE_ROWS_EVENT_V1 shop.orders delete 1 1-1-11",
+                ]
+            );
+        }
+
+        #[test]
+        fn render_mysql_events() {
+            let mut gtid = vec![1];
+            gtid.extend([0x11; 16]);
+            gtid.extend(5i64.to_le_bytes());
+            let binlog = events(
+                BinlogBuilder::with_server_version("8.0.36"),
+                EventBuilder::new(33, gtid),
+                true,
+            );
+
+            let gtid = "11111111-1111-1111-1111-111111111111:5";
+            assert_eq!(
+                render_all("{op} {gtid}", &binlog)[4..],
+                [
+                    format!("insert {}", gtid),
+                    format!("update {}", gtid),
+                    format!("delete {}", gtid),
+                ]
+            );
+        }
+    }
+}
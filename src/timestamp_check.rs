@@ -0,0 +1,293 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::model::EventHeader;
+use crate::util::{format_timestamp, get_event_type_name};
+
+/// 没有`--timestamp-tolerance`时允许时间后退的秒数，长事务的事件时间是语句开始的时间，会比之前提交的事务早
+pub const DEFAULT_TOLERANCE: u32 = 10;
+
+/// 没有`--max-timestamp-jump`时允许时间前进的秒数
+pub const DEFAULT_MAX_JUMP: u32 = 3600;
+
+const LOG_EVENT_ARTIFICIAL_F: u16 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampAnomalyKind {
+    Backward,
+    ForwardJump,
+}
+
+/// 时间和上一个参与比较的事件相差超过阈值的事件
+#[derive(Debug, Clone, Serialize)]
+pub struct TimestampAnomaly {
+    pub kind: TimestampAnomalyKind,
+    pub file_name: String,
+    pub position: u64,
+    pub type_code: u8,
+    pub timestamp: u32,
+    pub previous_file_name: String,
+    pub previous_position: u64,
+    pub previous_timestamp: u32,
+    /// timestamp减previous_timestamp的秒数，后退时为负数
+    pub delta: i64,
+}
+
+impl Display for TimestampAnomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            TimestampAnomalyKind::Backward => "backward",
+            TimestampAnomalyKind::ForwardJump => "forward jump",
+        };
+        write!(
+            f,
+            "{}:{}  {}  {}  {} {:+}s from {}:{} ({})",
+            self.file_name,
+            self.position,
+            get_event_type_name(self.type_code),
+            format_timestamp(self.timestamp),
+            kind,
+            self.delta,
+            self.previous_file_name,
+            self.previous_position,
+            format_timestamp(self.previous_timestamp)
+        )
+    }
+}
+
+/// 按顺序检查事件头中的时间，找出主库时钟的跳变
+///
+/// 每个事件和上一个参与比较的事件比较，早了超过tolerance秒或者晚了超过max_jump秒时记录下来。
+/// artificial事件、rotate和FDE的时间不是写入时的时间，不参与比较。多个文件按顺序连续比较。
+/// `--check-timestamps`时输出所有的异常，其他时候在结束时输出异常的个数
+pub struct TimestampChecker {
+    tolerance: u32,
+    max_jump: u32,
+    // 按顺序处理的文件名，最后一个是当前的文件
+    file_names: Vec<String>,
+    // 上一个参与比较的事件所在的文件（file_names中的下标）、位置和时间
+    previous: Option<(usize, u64, u32)>,
+    anomalies: Vec<TimestampAnomaly>,
+}
+
+impl TimestampChecker {
+    pub fn new(tolerance: u32, max_jump: u32) -> TimestampChecker {
+        TimestampChecker {
+            tolerance,
+            max_jump,
+            file_names: Vec::new(),
+            previous: None,
+            anomalies: Vec::new(),
+        }
+    }
+
+    pub fn start_file(&mut self, file_name: &str) {
+        self.file_names.push(file_name.to_string());
+    }
+
+    pub fn process(&mut self, header: &EventHeader, position: u64) {
+        if header.flags & LOG_EVENT_ARTIFICIAL_F != 0 || matches!(header.type_code, 4 | 15) {
+            return;
+        }
+
+        let file_index = self.file_names.len().saturating_sub(1);
+
+        if let Some((previous_file_index, previous_position, previous_timestamp)) = self.previous {
+            let delta = header.timestamp as i64 - previous_timestamp as i64;
+            let kind = match delta {
+                _ if delta < -(self.tolerance as i64) => Some(TimestampAnomalyKind::Backward),
+                _ if delta > self.max_jump as i64 => Some(TimestampAnomalyKind::ForwardJump),
+                _ => None,
+            };
+
+            if let Some(kind) = kind {
+                self.anomalies.push(TimestampAnomaly {
+                    kind,
+                    file_name: self.file_name(file_index),
+                    position,
+                    type_code: header.type_code,
+                    timestamp: header.timestamp,
+                    previous_file_name: self.file_name(previous_file_index),
+                    previous_position,
+                    previous_timestamp,
+                    delta,
+                });
+            }
+        }
+
+        // 时钟后退之后按新的时间继续比较，否则之后的每个事件都会被记录
+        self.previous = Some((file_index, position, header.timestamp));
+    }
+
+    fn file_name(&self, index: usize) -> String {
+        self.file_names.get(index).cloned().unwrap_or_default()
+    }
+
+    pub fn anomalies(&self) -> &[TimestampAnomaly] {
+        &self.anomalies
+    }
+
+    pub fn tolerance(&self) -> u32 {
+        self.tolerance
+    }
+
+    pub fn max_jump(&self) -> u32 {
+        self.max_jump
+    }
+}
+
+impl Display for TimestampChecker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for anomaly in &self.anomalies {
+            writeln!(f, "{}", anomaly)?;
+        }
+
+        let backward = self
+            .anomalies
+            .iter()
+            .filter(|v| v.kind == TimestampAnomalyKind::Backward)
+            .count();
+        writeln!(
+            f,
+            "{} backward step(s) of more than {}s, {} forward jump(s) of more than {}s",
+            backward,
+            self.tolerance,
+            self.anomalies.len() - backward,
+            self.max_jump
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::get_event_header;
+    use std::io::Cursor;
+
+    const T: u32 = 1700000000;
+
+    fn query(timestamp: u32) -> EventBuilder {
+        EventBuilder::from_body(&QueryEventBuilder::new("shop", "BEGIN")).timestamp(timestamp)
+    }
+
+    fn rotate(timestamp: u32) -> EventBuilder {
+        let mut body = 4u64.to_le_bytes().to_vec();
+        body.extend(b"mysql-bin.000002");
+        EventBuilder::new(4, body).timestamp(timestamp)
+    }
+
+    // 返回每个事件的位置
+    fn check(checker: &mut TimestampChecker, file_name: &str, binlog: &[u8]) -> Vec<u64> {
+        checker.start_file(file_name);
+
+        let mut file = Cursor::new(binlog);
+        let mut positions = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            checker.process(&header, position);
+            positions.push(position);
+            position = header.next_event_position as u64;
+        }
+        positions
+    }
+
+    // 算上开头的FDE，第6个事件后退了60秒，第9个事件前进了2小时，其他的时间变化都不应该被记录
+    fn sequence() -> Vec<u8> {
+        BinlogBuilder::new()
+            .push(query(T))
+            .push(xid(1).timestamp(T + 1))
+            // 长事务的语句开始的时间，在容忍范围内
+            .push(query(T - 5))
+            .push(xid(1).timestamp(T + 2))
+            .push(query(T - 58))
+            .push(xid(1).timestamp(T - 57))
+            // artificial事件和rotate的时间不参与比较
+            .push(rotate(0).flags(0x20))
+            .push(query(T + 7200))
+            .push(rotate(T - 100000))
+            .push(xid(1).timestamp(T + 7201))
+            .build()
+    }
+
+    #[test]
+    fn one_backward_step_and_one_forward_jump() {
+        let mut checker = TimestampChecker::new(DEFAULT_TOLERANCE, DEFAULT_MAX_JUMP);
+        let positions = check(&mut checker, "mysql-bin.000001", &sequence());
+
+        let anomalies = checker.anomalies();
+        assert_eq!(anomalies.len(), 2);
+
+        assert_eq!(anomalies[0].kind, TimestampAnomalyKind::Backward);
+        assert_eq!(anomalies[0].position, positions[5]);
+        assert_eq!(anomalies[0].previous_position, positions[4]);
+        assert_eq!(anomalies[0].delta, -60);
+
+        assert_eq!(anomalies[1].kind, TimestampAnomalyKind::ForwardJump);
+        assert_eq!(anomalies[1].type_code, 2);
+        assert_eq!(anomalies[1].position, positions[8]);
+        assert_eq!(anomalies[1].previous_position, positions[6]);
+        assert_eq!(anomalies[1].delta, 7257);
+
+        assert_eq!(
+            checker.to_string(),
+            format!(
+                concat!(
+                    "mysql-bin.000001:{}  QUERY_EVENT  2023-11-14 22:12:22  backward -60s from mysql-bin.000001:{} (2023-11-14 22:13:22)\n",
+                    "mysql-bin.000001:{}  QUERY_EVENT  2023-11-15 00:13:20  forward jump +7257s from mysql-bin.000001:{} (2023-11-14 22:12:23)\n",
+                    "1 backward step(s) of more than 10s, 1 forward jump(s) of more than 3600s\n",
+                ),
+                positions[5], positions[4], positions[8], positions[6]
+            )
+        );
+    }
+
+    #[test]
+    fn thresholds() {
+        // 容忍范围更小时长事务的语句也被记录，跳变的阈值更大时不再记录
+        let mut checker = TimestampChecker::new(3, 8000);
+        check(&mut checker, "mysql-bin.000001", &sequence());
+        assert_eq!(
+            checker
+                .anomalies()
+                .iter()
+                .map(|v| (v.kind, v.delta))
+                .collect::<Vec<_>>(),
+            [
+                (TimestampAnomalyKind::Backward, -6),
+                (TimestampAnomalyKind::Backward, -60)
+            ]
+        );
+    }
+
+    #[test]
+    fn compared_across_files() {
+        let mut checker = TimestampChecker::new(DEFAULT_TOLERANCE, DEFAULT_MAX_JUMP);
+        check(
+            &mut checker,
+            "mysql-bin.000001",
+            &BinlogBuilder::new()
+                .push(xid(1).timestamp(T))
+                .push(rotate(T))
+                .build(),
+        );
+        let positions = check(
+            &mut checker,
+            "mysql-bin.000002",
+            &BinlogBuilder::new().push(query(T - 30)).build(),
+        );
+
+        let anomalies = checker.anomalies();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].file_name, "mysql-bin.000002");
+        assert_eq!(anomalies[0].position, positions[1]);
+        assert_eq!(anomalies[0].previous_file_name, "mysql-bin.000001");
+        assert_eq!(anomalies[0].delta, -30);
+
+        let json = serde_json::to_value(&anomalies[0]).unwrap();
+        assert_eq!(json["kind"], "backward");
+    }
+}
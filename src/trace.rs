@@ -0,0 +1,184 @@
+#[cfg(feature = "trace")]
+use std::cell::RefCell;
+
+use crate::hexdump::FieldRange;
+
+#[cfg(feature = "trace")]
+const EVENT_HEADER_LENGTH: usize = 19;
+
+// 一个事件中已经记录的字段，base是当前解析的部分在body中的起始位置
+#[cfg(feature = "trace")]
+#[derive(Default)]
+struct Recorder {
+    fields: Vec<FieldRange>,
+    base: usize,
+}
+
+#[cfg(feature = "trace")]
+thread_local! {
+    // 只有start之后才记录，其他时候解码不受影响
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+/// 开始记录当前线程中解码的事件
+///
+/// 解码器通过field、field_with记录读取的每个字段的名字和字节范围，`--trace`用它们输出带注释的hexdump。
+/// 没有打开trace特性时这些函数都是空的，解码的热路径上不会留下任何代码
+pub fn start() {
+    #[cfg(feature = "trace")]
+    RECORDER.with(|v| *v.borrow_mut() = Some(Recorder::default()));
+}
+
+/// 结束记录，返回按起始位置排列的字段，范围是相对事件起始位置的偏移，没有打开trace特性时为空
+pub fn finish() -> Vec<FieldRange> {
+    #[cfg(feature = "trace")]
+    {
+        let mut fields = RECORDER
+            .with(|v| v.borrow_mut().take())
+            .map(|v| v.fields)
+            .unwrap_or_default();
+        // 同一个位置开始时外层的字段在前
+        fields.sort_by_key(|v| (v.start, std::cmp::Reverse(v.end)));
+        fields
+    }
+
+    #[cfg(not(feature = "trace"))]
+    Vec::new()
+}
+
+/// start和end是相对当前位置（body的开头，或者offset_by设置的位置）的偏移
+#[inline(always)]
+pub fn field(name: &str, start: usize, end: usize) {
+    field_with(|| name.to_string(), start, end)
+}
+
+/// 和field一样，字段名只在记录时才生成，例如行镜像中的`col_3`
+#[inline(always)]
+pub fn field_with<F: FnOnce() -> String>(name: F, start: usize, end: usize) {
+    // 长度为0的字段（比如没有状态变量）不标出
+    #[cfg(feature = "trace")]
+    RECORDER.with(|v| {
+        if let (Some(recorder), true) = (v.borrow_mut().as_mut(), start < end) {
+            let base = EVENT_HEADER_LENGTH + recorder.base;
+            recorder
+                .fields
+                .push(FieldRange::new(base + start, base + end, &name()));
+        }
+    });
+
+    #[cfg(not(feature = "trace"))]
+    let _ = (name, start, end);
+}
+
+/// 在返回值drop之前，field中的偏移都再加上offset，用于行镜像这类从body中间开始解析的部分
+#[inline(always)]
+pub fn offset_by(offset: usize) -> OffsetGuard {
+    #[cfg(feature = "trace")]
+    RECORDER.with(|v| {
+        if let Some(recorder) = v.borrow_mut().as_mut() {
+            recorder.base += offset;
+        }
+    });
+
+    #[cfg(not(feature = "trace"))]
+    let _ = offset;
+
+    OffsetGuard {
+        #[cfg(feature = "trace")]
+        offset,
+    }
+}
+
+/// 见offset_by，出错提前返回时也会恢复原来的位置
+pub struct OffsetGuard {
+    #[cfg(feature = "trace")]
+    offset: usize,
+}
+
+impl Drop for OffsetGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "trace")]
+        RECORDER.with(|v| {
+            if let Some(recorder) = v.borrow_mut().as_mut() {
+                recorder.base -= self.offset;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::hexdump::{hexdump, traced_layout};
+    use crate::service::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    // 解码binlog中的table map事件，返回带注释的hexdump
+    fn traced_table_map() -> String {
+        let table = TableMapBuilder::new("shop", "users")
+            .table_id(7)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true);
+        let binlog = BinlogBuilder::new().event(&table).build();
+
+        let mut file = Cursor::new(&binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        loop {
+            let header = get_event_header(&mut file, position).unwrap();
+            start();
+            get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            let fields = finish();
+
+            if header.type_code == 19 {
+                let raw_event = &binlog[position as usize..header.next_event_position as usize];
+                let layout = traced_layout(raw_event, CHECKSUM_LENGTH, &fields);
+                return hexdump(position, raw_event, &layout);
+            }
+            position = header.next_event_position as u64;
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn annotated_table_map() {
+        assert_eq!(
+            traced_table_map(),
+            concat!(
+                "000000f9  00 00 00 00 13 01 00 00  00 33 00 00 00 2c 01 00  |.........3...,..|  ",
+                "timestamp[0..4] type_code[4..5] server_id[5..9] event_length[9..13] next_event_position[13..17]\n",
+                "00000109  00 00 00 07 00 00 00 00  00 01 00 04 73 68 6f 70  |............shop|  ",
+                "flags[17..19] table_id[19..25] reserved_for_future_use[25..27] database_name_length[27..28] ",
+                "database_name[28..33]\n",
+                "00000119  00 05 75 73 65 72 73 00  02 03 0f 02 40 00 02 66  |..users.....@..f|  ",
+                "table_name_length[33..34] table_name[34..40] number_of_columns[40..41] column_types[41..43] ",
+                "number_of_metadata_block[43..44] metadata_block[44..46] columns_can_be_null[46..47] crc32[47..51]\n",
+                "00000129  6d c3 ae                                          |m..             |\n",
+            )
+        );
+    }
+
+    // 没有打开trace特性时不记录字段，只有event_layout中的结构
+    #[cfg(not(feature = "trace"))]
+    #[test]
+    fn nothing_is_recorded_without_the_feature() {
+        start();
+        field("table_id", 0, 6);
+        assert!(finish().is_empty());
+
+        assert!(traced_table_map().contains(
+            "flags[17..19] body[19..47] table_id[19..25] reserved_for_future_use[25..27]\n"
+        ));
+    }
+}
@@ -0,0 +1,590 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::cli::{HashAlgorithm, TransactionSort};
+use crate::event_hash::{to_hex, TransactionHash};
+use crate::model::*;
+
+// GTID事件的flags
+const FL_STANDALONE: u8 = 1;
+
+/// 一个事务的概要，事务从GTID事件开始，到XID或者COMMIT结束
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionSummary {
+    pub gtid: String,
+    /// GTID事件的位置
+    pub start_position: u64,
+    /// 最后一个事件结束的位置
+    pub end_position: u64,
+    pub begin_timestamp: u32,
+    pub commit_timestamp: Option<u32>,
+    pub duration_seconds: Option<u32>,
+    /// BEGIN的Q_HRNOW中的微秒，秒是begin_timestamp
+    pub begin_microseconds: Option<u32>,
+    /// COMMIT的Q_HRNOW中的微秒，XID事件没有微秒
+    pub commit_microseconds: Option<u32>,
+    /// 开始和提交都有微秒时精确到微秒的耗时
+    pub duration_microseconds: Option<u64>,
+    pub row_events: u64,
+    pub inserted_rows: u64,
+    pub updated_rows: u64,
+    pub deleted_rows: u64,
+    /// `库名.表名`，按第一次出现的顺序
+    pub tables: Vec<String>,
+    pub bytes: u64,
+    /// 文件结束时还没有提交的事务为false
+    pub committed: bool,
+    /// `--hash`时事务中所有事件的哈希拼起来的哈希，十六进制
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_hash: Option<String>,
+}
+
+impl TransactionSummary {
+    pub fn total_rows(&self) -> u64 {
+        self.inserted_rows + self.updated_rows + self.deleted_rows
+    }
+}
+
+impl Display for TransactionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gtid={} start={} end={} begin={} commit={} duration={} row_events={} rows={} (insert {}, update {}, delete {}) bytes={} tables=[{}]",
+            self.gtid,
+            self.start_position,
+            self.end_position,
+            self.begin_timestamp,
+            self.commit_timestamp
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            match (self.duration_microseconds, self.duration_seconds) {
+                (Some(v), _) => format!("{}.{:06}s", v / 1_000_000, v % 1_000_000),
+                (None, Some(v)) => format!("{}s", v),
+                (None, None) => "-".to_string(),
+            },
+            self.row_events,
+            self.total_rows(),
+            self.inserted_rows,
+            self.updated_rows,
+            self.deleted_rows,
+            self.bytes,
+            self.tables.join(", "),
+        )?;
+
+        if let Some(transaction_hash) = &self.transaction_hash {
+            write!(f, " hash={}", transaction_hash)?;
+        }
+
+        if !self.committed {
+            write!(f, " NOT COMMITTED")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 把事件按事务分组，每个事件都要经过process
+#[derive(Default)]
+pub struct TransactionTracker {
+    current: Option<TransactionSummary>,
+    // GTID带有FL_STANDALONE时后面只有一个query事件（通常是DDL），它就是整个事务
+    is_standalone: bool,
+    // MySQL的GTID事件没有FL_STANDALONE，后面第一个事件不是BEGIN时就是单独的语句
+    after_mysql_gtid: bool,
+    // process_with_hash时当前事务中的事件的哈希
+    transaction_hash: Option<TransactionHash>,
+}
+
+impl TransactionTracker {
+    pub fn new() -> TransactionTracker {
+        TransactionTracker::default()
+    }
+
+    /// 返回这个事件结束的事务
+    /// 如果上一个事务还没有提交就遇到了新的GTID，上一个事务会以未提交的状态返回
+    pub fn process(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) -> Option<TransactionSummary> {
+        let gtid = if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            self.is_standalone = gtid_event.flags & FL_STANDALONE > 0;
+            self.after_mysql_gtid = false;
+            Some(format!(
+                "{}-{}-{}",
+                gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+            ))
+        } else if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            self.is_standalone = false;
+            self.after_mysql_gtid = true;
+            Some(format!("{}:{}", gtid_event.uuid, gtid_event.gno))
+        } else {
+            None
+        };
+
+        if let Some(gtid) = gtid {
+            let unfinished = self.current.take().map(|v| self.with_hash(v));
+
+            self.current = Some(TransactionSummary {
+                gtid,
+                start_position: position,
+                end_position: position + header.event_length as u64,
+                begin_timestamp: header.timestamp,
+                commit_timestamp: None,
+                duration_seconds: None,
+                begin_microseconds: None,
+                commit_microseconds: None,
+                duration_microseconds: None,
+                row_events: 0,
+                inserted_rows: 0,
+                updated_rows: 0,
+                deleted_rows: 0,
+                tables: Vec::new(),
+                bytes: header.event_length as u64,
+                committed: false,
+                transaction_hash: None,
+            });
+
+            return unfinished;
+        }
+
+        let transaction = self.current.as_mut()?;
+
+        if self.after_mysql_gtid {
+            self.after_mysql_gtid = false;
+            self.is_standalone = body
+                .downcast_ref::<EventBodyTypeCode2>()
+                .is_some_and(|v| !v.sql.trim().eq_ignore_ascii_case("BEGIN"));
+        }
+
+        transaction.end_position = position + header.event_length as u64;
+        transaction.bytes += header.event_length as u64;
+
+        if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+            transaction.row_events += 1;
+
+            let rows = rows_event.number_of_rows;
+            match header.type_code {
+                23 | 30 => transaction.inserted_rows += rows,
+                24 | 31 => transaction.updated_rows += rows,
+                _ => transaction.deleted_rows += rows,
+            }
+
+            if let Some(table_info) = table_structs.get(&rows_event.table_id) {
+                let table = format!("{}.{}", table_info.database_name, table_info.table_name);
+                if !transaction.tables.contains(&table) {
+                    transaction.tables.push(table);
+                }
+            }
+        }
+
+        // XA事务在XA PREPARE之后就结束了这一段
+        let is_commit = match header.type_code {
+            16 | 38 => true,
+            2 => body
+                .downcast_ref::<EventBodyTypeCode2>()
+                .is_some_and(|v| self.is_standalone || v.sql.trim().eq_ignore_ascii_case("COMMIT")),
+            _ => false,
+        };
+
+        let event_time = EventTime::new(header, body);
+
+        if !is_commit {
+            // GTID没有微秒，用同一秒中的BEGIN
+            if header.type_code == 2 && header.timestamp == transaction.begin_timestamp {
+                transaction.begin_microseconds =
+                    transaction.begin_microseconds.or(event_time.microseconds);
+            }
+            return None;
+        }
+
+        let mut transaction = self.current.take()?;
+        transaction.commit_timestamp = Some(header.timestamp);
+        transaction.duration_seconds =
+            Some(header.timestamp.saturating_sub(transaction.begin_timestamp));
+        transaction.commit_microseconds = event_time.microseconds;
+        if let (Some(begin), Some(_)) = (transaction.begin_microseconds, event_time.microseconds) {
+            let begin = EventTime {
+                seconds: transaction.begin_timestamp,
+                microseconds: Some(begin),
+            };
+            transaction.duration_microseconds =
+                Some(event_time.as_micros().saturating_sub(begin.as_micros()));
+        }
+        transaction.committed = true;
+
+        Some(self.with_hash(transaction))
+    }
+
+    /// 和process一样，event_hash是这个事件的`--hash`，返回的事务带有transaction_hash
+    pub fn process_with_hash(
+        &mut self,
+        header: &EventHeader,
+        position: u64,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+        algorithm: HashAlgorithm,
+        event_hash: &[u8],
+    ) -> Option<TransactionSummary> {
+        // GTID之前的哈希属于上一个事务，不在事务中的事件没有哈希
+        let is_gtid = matches!(header.type_code, 162 | 33);
+        if !is_gtid {
+            if let Some(transaction_hash) = self.transaction_hash.as_mut() {
+                transaction_hash.update(event_hash);
+            }
+        }
+
+        let finished = self.process(header, position, body, table_structs);
+
+        if is_gtid {
+            let mut transaction_hash = TransactionHash::new(algorithm);
+            transaction_hash.update(event_hash);
+            self.transaction_hash = Some(transaction_hash);
+        }
+
+        finished
+    }
+
+    /// 文件结束时还没有提交的事务
+    pub fn finish(&mut self) -> Option<TransactionSummary> {
+        self.current.take().map(|v| self.with_hash(v))
+    }
+
+    fn with_hash(&mut self, mut transaction: TransactionSummary) -> TransactionSummary {
+        transaction.transaction_hash = self.transaction_hash.take().map(|v| to_hex(&v.finish()));
+        transaction
+    }
+}
+
+/// `--transactions`的输出顺序，Start是原来的顺序
+pub fn sort_transactions(transactions: &mut [TransactionSummary], sort: TransactionSort) {
+    match sort {
+        TransactionSort::Start => {}
+        TransactionSort::Rows => transactions.sort_by_key(|v| std::cmp::Reverse(v.total_rows())),
+        TransactionSort::Bytes => transactions.sort_by_key(|v| std::cmp::Reverse(v.bytes)),
+    }
+}
+
+/// 事务内容的哈希，只包含行数据和SQL，不包含server_id、时间戳和校验和
+/// 同样的内容在不同的服务器、不同的运行中得到的哈希相同
+#[derive(Default)]
+pub struct TransactionHasher {
+    hasher: Sha256,
+}
+
+impl TransactionHasher {
+    pub fn new() -> TransactionHasher {
+        TransactionHasher::default()
+    }
+
+    /// table_id在不同的服务器上不一样，所以行事件用`库名.表名`代替
+    pub fn update(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+        table_structs: &HashMap<u64, Arc<EventBodyTypeCode19>>,
+    ) {
+        if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+            let sql = query_event.sql.trim();
+            if sql.eq_ignore_ascii_case("BEGIN") || sql.eq_ignore_ascii_case("COMMIT") {
+                return;
+            }
+
+            self.write(&["query", &query_event.database_name, sql]);
+        }
+
+        if let Some(rows_event) = body.downcast_ref::<EventBodyTypeCode23To25>() {
+            let table = table_structs
+                .get(&rows_event.table_id)
+                .map(|v| format!("{}.{}", v.database_name, v.table_name))
+                .unwrap_or_default();
+
+            self.write(&["rows", &header.type_code.to_string(), &table]);
+
+            for row in &rows_event.rows {
+                let before = serde_json::to_string(&row.before).unwrap_or_default();
+                let after = serde_json::to_string(&row.after).unwrap_or_default();
+                self.write(&[&before, &after]);
+            }
+        }
+    }
+
+    /// 返回十六进制的sha256并重新开始
+    pub fn finish(&mut self) -> String {
+        let digest = std::mem::take(&mut self.hasher).finalize();
+
+        digest.iter().map(|v| format!("{:02x}", v)).collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.hasher = Sha256::new();
+    }
+
+    // 每个字段前面加上长度，避免不同的拆分得到相同的字节序列
+    fn write(&mut self, fields: &[&str]) {
+        for field in fields {
+            self.hasher.update((field.len() as u64).to_le_bytes());
+            self.hasher.update(field.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body, get_event_header, CHECKSUM_LENGTH};
+
+    fn mariadb_gtid(sequence: u64, flags: u8) -> EventBuilder {
+        let mut body = sequence.to_le_bytes().to_vec();
+        body.extend(0u32.to_le_bytes());
+        body.push(flags);
+        body.extend([0; 6]);
+
+        EventBuilder::new(162, body)
+    }
+
+    fn mysql_gtid(gno: u64, _flags: u8) -> EventBuilder {
+        let mut body = vec![1];
+        body.extend([0x3e; 16]);
+        body.extend((gno as i64).to_le_bytes());
+
+        EventBuilder::new(33, body)
+    }
+
+    fn query(sql: &str, timestamp: u32) -> EventBuilder {
+        EventBuilder::from_body(&QueryEventBuilder::new("shop", sql)).timestamp(timestamp)
+    }
+
+    // 一行的小事务、单独的DDL、两张表多行的大事务，最后一个事务没有提交
+    fn fixture(binlog: BinlogBuilder, gtid: fn(u64, u8) -> EventBuilder, v2: bool) -> Vec<u8> {
+        let orders = TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[]);
+        let items = TableMapBuilder::new("shop", "items")
+            .table_id(71)
+            .column(FieldType::Long, &[]);
+        let row = |id: i64| vec![ColumnValue::Int(id)];
+
+        binlog
+            .push(gtid(1, 0).timestamp(100))
+            .push(query("BEGIN", 100))
+            .event(&orders)
+            .event(&RowsEventBuilder::insert(&orders).v2(v2).row(row(1)))
+            .push(xid(1).timestamp(101))
+            .push(gtid(2, FL_STANDALONE).timestamp(110))
+            .push(query("ALTER TABLE orders ADD c INT", 112))
+            .push(gtid(3, 0).timestamp(120))
+            .push(query("BEGIN", 120))
+            .event(&orders)
+            .event(
+                &RowsEventBuilder::insert(&orders)
+                    .v2(v2)
+                    .row(row(2))
+                    .row(row(3))
+                    .row(row(4)),
+            )
+            .event(&items)
+            .event(
+                &RowsEventBuilder::update(&items)
+                    .v2(v2)
+                    .update_row(row(1), row(2)),
+            )
+            .event(&items)
+            .event(
+                &RowsEventBuilder::delete(&items)
+                    .v2(v2)
+                    .row(row(2))
+                    .row(row(3)),
+            )
+            .push(query("COMMIT", 125))
+            .push(gtid(4, 0).timestamp(130))
+            .push(query("BEGIN", 130))
+            .event(&orders)
+            .event(&RowsEventBuilder::delete(&orders).v2(v2).row(row(1)))
+            .build()
+    }
+
+    fn transactions(binlog: &[u8]) -> Vec<TransactionSummary> {
+        let mut tracker = TransactionTracker::new();
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut result = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            result.extend(tracker.process(&header, position, body.as_ref(), &table_structs));
+
+            position = header.next_event_position as u64;
+        }
+        result.extend(tracker.finish());
+
+        result
+    }
+
+    // (begin, commit, duration, row_events, insert, update, delete, tables, committed)
+    type Summary = (
+        u32,
+        Option<u32>,
+        Option<u32>,
+        u64,
+        u64,
+        u64,
+        u64,
+        Vec<String>,
+        bool,
+    );
+
+    fn summary(transaction: &TransactionSummary) -> Summary {
+        (
+            transaction.begin_timestamp,
+            transaction.commit_timestamp,
+            transaction.duration_seconds,
+            transaction.row_events,
+            transaction.inserted_rows,
+            transaction.updated_rows,
+            transaction.deleted_rows,
+            transaction.tables.clone(),
+            transaction.committed,
+        )
+    }
+
+    fn expected() -> Vec<Summary> {
+        let tables = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+        vec![
+            (
+                100,
+                Some(101),
+                Some(1),
+                1,
+                1,
+                0,
+                0,
+                tables(&["shop.orders"]),
+                true,
+            ),
+            (110, Some(112), Some(2), 0, 0, 0, 0, tables(&[]), true),
+            (
+                120,
+                Some(125),
+                Some(5),
+                3,
+                3,
+                1,
+                2,
+                tables(&["shop.orders", "shop.items"]),
+                true,
+            ),
+            (130, None, None, 1, 0, 0, 1, tables(&["shop.orders"]), false),
+        ]
+    }
+
+    // 事务首尾相接，覆盖了第一个GTID之后的所有字节
+    fn assert_contiguous(binlog: &[u8], transactions: &[TransactionSummary]) {
+        for pair in transactions.windows(2) {
+            assert_eq!(pair[0].end_position, pair[1].start_position);
+        }
+        for transaction in transactions {
+            assert_eq!(
+                transaction.bytes,
+                transaction.end_position - transaction.start_position
+            );
+        }
+        assert_eq!(
+            transactions.last().unwrap().end_position,
+            binlog.len() as u64
+        );
+    }
+
+    #[test]
+    fn mariadb_transactions() {
+        let binlog = fixture(BinlogBuilder::new(), mariadb_gtid, false);
+        let transactions = transactions(&binlog);
+
+        assert_eq!(
+            transactions.iter().map(summary).collect::<Vec<Summary>>(),
+            expected()
+        );
+        assert_eq!(
+            transactions
+                .iter()
+                .map(|v| v.gtid.as_str())
+                .collect::<Vec<&str>>(),
+            ["0-1-1", "0-1-2", "0-1-3", "0-1-4"]
+        );
+        assert_contiguous(&binlog, &transactions);
+        assert!(transactions[3].to_string().ends_with(" NOT COMMITTED"));
+    }
+
+    #[test]
+    fn mysql_transactions() {
+        let binlog = fixture(
+            BinlogBuilder::with_server_version("8.0.36"),
+            mysql_gtid,
+            true,
+        );
+        let transactions = transactions(&binlog);
+
+        assert_eq!(
+            transactions.iter().map(summary).collect::<Vec<Summary>>(),
+            expected()
+        );
+        assert_eq!(
+            transactions[1].gtid,
+            "3e3e3e3e-3e3e-3e3e-3e3e-3e3e3e3e3e3e:2"
+        );
+        assert_contiguous(&binlog, &transactions);
+    }
+
+    #[test]
+    fn sort_by_rows_and_bytes() {
+        let binlog = fixture(BinlogBuilder::new(), mariadb_gtid, false);
+        let order = |sort: TransactionSort| {
+            let mut transactions = transactions(&binlog);
+            sort_transactions(&mut transactions, sort);
+            transactions
+                .into_iter()
+                .map(|v| v.gtid)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            order(TransactionSort::Start),
+            ["0-1-1", "0-1-2", "0-1-3", "0-1-4"]
+        );
+        // 行数相同时保持原来的顺序
+        assert_eq!(
+            order(TransactionSort::Rows),
+            ["0-1-3", "0-1-1", "0-1-4", "0-1-2"]
+        );
+        assert_eq!(order(TransactionSort::Bytes)[0], "0-1-3");
+        assert_eq!(order(TransactionSort::Bytes)[3], "0-1-2");
+    }
+
+    #[test]
+    fn json_fields() {
+        let binlog = fixture(BinlogBuilder::new(), mariadb_gtid, false);
+        let value = serde_json::to_value(&transactions(&binlog)[0]).unwrap();
+
+        assert_eq!(value["gtid"], "0-1-1");
+        assert_eq!(value["commit_timestamp"], 101);
+        assert_eq!(value["committed"], true);
+        assert!(value.get("transaction_hash").is_none());
+    }
+}
@@ -0,0 +1,750 @@
+use std::{collections::HashMap, fs::File, sync::Arc};
+
+use chrono::NaiveDateTime;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use serde_json::json;
+
+use crate::error::ParseError;
+use crate::model::*;
+use crate::service::*;
+use crate::style::{ColorChoice, Styler};
+use crate::util::*;
+
+const EVENT_HEADER_LENGTH: u64 = 19;
+
+const PAGE_SIZE: isize = 20;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 索引中的一个事件，只读取了header，GTID和table map这样很小的事件会顺便解析出摘要
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub position: u64,
+    pub timestamp: u32,
+    pub type_code: u8,
+    pub server_id: u32,
+    pub event_length: u32,
+    /// 所在事务的GTID
+    pub gtid: Option<String>,
+    /// `库名.表名`，只有table map和行事件才有
+    pub table: Option<String>,
+}
+
+impl IndexEntry {
+    pub fn summary(&self) -> String {
+        match (&self.gtid, &self.table) {
+            (Some(gtid), _) if matches!(self.type_code, 162 | 33) => gtid.clone(),
+            (_, Some(table)) => table.clone(),
+            _ => String::new(),
+        }
+    }
+
+    /// 事件列表中的一行：位置、时间（UTC）、类型、摘要
+    pub fn line(&self) -> String {
+        format!(
+            "{:>10}  {}  {:<26} {}",
+            self.position,
+            format_timestamp(self.timestamp),
+            get_event_type_name(self.type_code),
+            self.summary()
+        )
+    }
+}
+
+/// 只读取header建立的事件索引，打开很大的文件时也不需要解析事件内容
+pub struct EventIndex {
+    pub entries: Vec<IndexEntry>,
+    /// 事件最后的校验和长度，来自FDE
+    pub checksum_length: usize,
+}
+
+impl EventIndex {
+    pub fn build(file: &mut File) -> Result<EventIndex, BoxedError> {
+        let file_length = file.metadata()?.len();
+
+        let mut entries = Vec::new();
+        let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+        let mut current_gtid = None;
+        let mut checksum_length = CHECKSUM_LENGTH;
+
+        let mut offset = 4;
+        while offset < file_length {
+            let header = get_event_header(file, offset)?;
+
+            let mut table = None;
+            match header.type_code {
+                15 | 162 | 33 | 19 => {
+                    let body =
+                        get_event_body(file, offset, &header, &mut table_structs, checksum_length)?;
+                    update_checksum_length(&mut checksum_length, body.as_ref());
+
+                    if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+                        current_gtid = Some(format!(
+                            "{}-{}-{}",
+                            gtid_event.replication_domain_id,
+                            header.server_id,
+                            gtid_event.gtid_sequence
+                        ));
+                    }
+
+                    // MySQL的anonymous GTID事件没有GTID
+                    if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+                        current_gtid = (gtid_event.gno > 0)
+                            .then(|| format!("{}:{}", gtid_event.uuid, gtid_event.gno));
+                    }
+
+                    if let Some(table_map) = body.downcast_ref::<EventBodyTypeCode19>() {
+                        table = Some(format!(
+                            "{}.{}",
+                            table_map.database_name, table_map.table_name
+                        ));
+                    }
+                }
+                // 行事件只读取开头的table_id
+                23..=25 | 30..=32 => {
+                    let table_id = get_rows_event_table_id(file, offset + EVENT_HEADER_LENGTH)?;
+                    table = table_structs
+                        .get(&table_id)
+                        .map(|v| format!("{}.{}", v.database_name, v.table_name));
+                }
+                _ => {}
+            }
+
+            entries.push(IndexEntry {
+                position: offset,
+                timestamp: header.timestamp,
+                type_code: header.type_code,
+                server_id: header.server_id,
+                event_length: header.event_length,
+                gtid: current_gtid.clone(),
+                table,
+            });
+
+            offset = header.next_event_position as u64;
+        }
+
+        Ok(EventIndex {
+            entries,
+            checksum_length,
+        })
+    }
+
+    /// 包含这个位置的事件
+    pub fn find_position(&self, position: u64) -> Option<usize> {
+        self.entries
+            .partition_point(|v| v.position <= position)
+            .checked_sub(1)
+    }
+
+    /// 第一个时间不早于timestamp的事件
+    pub fn find_timestamp(&self, timestamp: u32) -> Option<usize> {
+        self.entries.iter().position(|v| v.timestamp >= timestamp)
+    }
+
+    pub fn find_gtid(&self, gtid: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|v| matches!(v.type_code, 162 | 33) && v.gtid.as_deref() == Some(gtid))
+    }
+
+    /// 解析一个事件，行事件会先解析同一个事务中它前面的table map
+    pub fn decode(
+        &self,
+        file: &mut File,
+        index: usize,
+    ) -> Result<(EventHeader, Box<dyn EventBody>), BoxedError> {
+        let entry = &self.entries[index];
+
+        let mut table_structs: HashMap<u64, Arc<EventBodyTypeCode19>> = HashMap::new();
+        if matches!(entry.type_code, 23..=25 | 30..=32) {
+            for previous in self.entries[..index].iter().rev() {
+                if matches!(previous.type_code, 162 | 33) {
+                    break;
+                }
+
+                if previous.type_code == 19 {
+                    let previous_header = get_event_header(file, previous.position)?;
+                    get_event_body(
+                        file,
+                        previous.position,
+                        &previous_header,
+                        &mut table_structs,
+                        self.checksum_length,
+                    )?;
+                }
+            }
+        }
+
+        let header = get_event_header(file, entry.position)?;
+        let body = get_event_body(
+            file,
+            entry.position,
+            &header,
+            &mut table_structs,
+            self.checksum_length,
+        )?;
+
+        Ok((header, body))
+    }
+}
+
+/// 过滤条件，格式为`type=query db=test table=t1`，type可以是类型编号或者类型名的前缀
+#[derive(Debug, Default, Clone)]
+pub struct EventFilter {
+    text: String,
+    type_codes: Option<Vec<u8>>,
+    database: Option<String>,
+    table: Option<String>,
+}
+
+impl EventFilter {
+    pub fn parse(text: &str) -> Result<EventFilter, BoxedError> {
+        let mut filter = EventFilter {
+            text: text.trim().to_string(),
+            ..EventFilter::default()
+        };
+
+        for condition in text.split_whitespace() {
+            let (key, value) = condition.split_once('=').ok_or_else(|| {
+                MyError(format!("`{}` is not in the form of key=value", condition))
+            })?;
+
+            match key {
+                "type" => {
+                    let type_codes = match value.parse::<u8>() {
+                        Ok(type_code) => vec![type_code],
+                        Err(_) => {
+                            let name = value.to_uppercase();
+                            (0..=u8::MAX)
+                                .filter(|v| {
+                                    let type_name = get_event_type_name(*v);
+                                    type_name != "UNKNOWN_EVENT" && type_name.starts_with(&name)
+                                })
+                                .collect()
+                        }
+                    };
+
+                    if type_codes.is_empty() {
+                        return Err(Box::new(MyError(format!("unknown event type `{}`", value))));
+                    }
+
+                    filter.type_codes = Some(type_codes);
+                }
+                "db" => filter.database = Some(value.to_string()),
+                "table" => filter.table = Some(value.to_string()),
+                others => {
+                    return Err(Box::new(MyError(format!(
+                        "unknown filter `{}`, expected type, db or table",
+                        others
+                    ))));
+                }
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        if let Some(type_codes) = &self.type_codes {
+            if !type_codes.contains(&entry.type_code) {
+                return false;
+            }
+        }
+
+        if self.database.is_none() && self.table.is_none() {
+            return true;
+        }
+
+        let (database, table) = match entry.table.as_deref().and_then(|v| v.split_once('.')) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        self.database.as_deref().is_none_or(|v| v == database)
+            && self.table.as_deref().is_none_or(|v| v == table)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PromptKind {
+    Search,
+    Jump,
+    Filter,
+}
+
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+    // 开始输入时选中的位置，增量搜索从这里开始
+    origin: usize,
+}
+
+struct App {
+    file: File,
+    file_name: String,
+    index: EventIndex,
+    filter: EventFilter,
+    // 通过过滤的事件在索引中的下标
+    visible: Vec<usize>,
+    // visible中的下标
+    selected: usize,
+    list_offset: usize,
+    detail: String,
+    // detail对应的事件，选中的事件变化时才重新解析
+    detail_for: Option<(usize, bool)>,
+    detail_scroll: u16,
+    json: bool,
+    search: String,
+    prompt: Option<Prompt>,
+    message: String,
+}
+
+/// 打开交互式的浏览界面
+pub fn run(binlog_file_path: &str) -> Result<(), BoxedError> {
+    let mut file = get_file(binlog_file_path)?;
+
+    if !check_file_magic_number(&mut file)? {
+        return Err(Box::new(ParseError::not_binlog(binlog_file_path)));
+    }
+
+    let index = EventIndex::build(&mut file)?;
+    let mut app = App::new(file, get_file_name(binlog_file_path), index);
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
+
+impl App {
+    fn new(file: File, file_name: String, index: EventIndex) -> App {
+        App {
+            file,
+            file_name,
+            visible: (0..index.entries.len()).collect(),
+            index,
+            filter: EventFilter::default(),
+            selected: 0,
+            list_offset: 0,
+            detail: String::new(),
+            detail_for: None,
+            detail_scroll: 0,
+            json: false,
+            search: String::new(),
+            prompt: None,
+            message: String::new(),
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), BoxedError> {
+        loop {
+            self.refresh_detail();
+
+            terminal.draw(|frame| self.render(frame))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && self.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn selected_entry(&self) -> Option<usize> {
+        self.visible.get(self.selected).copied()
+    }
+
+    /// 选中索引中的某个事件，它被过滤掉时选中它后面的第一个事件
+    fn select_entry(&mut self, entry: usize) {
+        let selected = self.visible.partition_point(|v| *v < entry);
+        self.selected = selected.min(self.visible.len().saturating_sub(1));
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.visible.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    fn apply_filter(&mut self, filter: EventFilter) {
+        let current = self.selected_entry();
+
+        self.visible = (0..self.index.entries.len())
+            .filter(|v| filter.matches(&self.index.entries[*v]))
+            .collect();
+        self.filter = filter;
+
+        self.message = format!("{} events match", self.visible.len());
+        self.select_entry(current.unwrap_or_default());
+    }
+
+    /// 选中的事件变化时才解析事件内容
+    fn refresh_detail(&mut self) {
+        let entry = match self.selected_entry() {
+            Some(entry) => entry,
+            None => {
+                self.detail = String::new();
+                self.detail_for = None;
+                return;
+            }
+        };
+
+        if self.detail_for == Some((entry, self.json)) {
+            return;
+        }
+
+        self.detail = match self.index.decode(&mut self.file, entry) {
+            Ok((header, body)) if self.json => serde_json::to_string_pretty(&json!({
+                "position": self.index.entries[entry].position,
+                "header": header,
+                "body": body,
+            }))
+            .unwrap_or_else(|e| e.to_string()),
+            Ok((header, body)) => Styler::new(ColorChoice::Never).event(&header, body.as_ref()),
+            Err(e) => format!("failed to decode the event: {}", e),
+        };
+        self.detail_for = Some((entry, self.json));
+        self.detail_scroll = 0;
+    }
+
+    /// 从start开始查找行文本中包含search的事件，不区分大小写
+    fn search_from(&mut self, start: usize, forward: bool) -> bool {
+        if self.search.is_empty() || self.visible.is_empty() {
+            return false;
+        }
+
+        let search = self.search.to_lowercase();
+        let is_match = |v: &usize| {
+            self.index.entries[self.visible[*v]]
+                .line()
+                .to_lowercase()
+                .contains(&search)
+        };
+
+        let found = if forward {
+            (start..self.visible.len()).find(is_match)
+        } else {
+            (0..=start.min(self.visible.len() - 1)).rev().find(is_match)
+        };
+
+        match found {
+            Some(v) => {
+                self.selected = v;
+                self.message = String::new();
+                true
+            }
+            None => {
+                self.message = format!("`{}` is not found", self.search);
+                false
+            }
+        }
+    }
+
+    /// 跳转的目标可以是文件位置、`@`开头的unix时间戳、UTC时间`2024-01-02 03:04:05`或者GTID
+    fn jump(&mut self, input: &str) {
+        let input = input.trim();
+
+        let target = if let Some(timestamp) = input.strip_prefix('@') {
+            timestamp
+                .parse::<u32>()
+                .ok()
+                .and_then(|v| self.index.find_timestamp(v))
+        } else if let Ok(position) = input.parse::<u64>() {
+            self.index.find_position(position)
+        } else if let Ok(datetime) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S") {
+            self.index
+                .find_timestamp(datetime.and_utc().timestamp().max(0) as u32)
+        } else {
+            self.index.find_gtid(input)
+        };
+
+        match target {
+            Some(entry) => {
+                self.select_entry(entry);
+                self.message = if self.filter.matches(&self.index.entries[entry]) {
+                    String::new()
+                } else {
+                    "the target is filtered out, jumped to the next visible event".to_string()
+                };
+            }
+            None => self.message = format!("`{}` is not found", input),
+        }
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind, input: String) {
+        self.prompt = Some(Prompt {
+            kind,
+            input,
+            origin: self.selected,
+        });
+    }
+
+    /// 返回true时退出
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        if let Some(mut prompt) = self.prompt.take() {
+            match code {
+                KeyCode::Esc => {
+                    if prompt.kind == PromptKind::Search {
+                        self.selected = prompt.origin;
+                    }
+                    return false;
+                }
+                KeyCode::Enter => {
+                    match prompt.kind {
+                        PromptKind::Search => self.search = prompt.input,
+                        PromptKind::Jump => self.jump(&prompt.input),
+                        PromptKind::Filter => match EventFilter::parse(&prompt.input) {
+                            Ok(filter) => self.apply_filter(filter),
+                            Err(e) => self.message = e.to_string(),
+                        },
+                    }
+                    return false;
+                }
+                KeyCode::Backspace => {
+                    prompt.input.pop();
+                }
+                KeyCode::Char(c) => prompt.input.push(c),
+                _ => {}
+            }
+
+            // 增量搜索，每输入一个字符就从开始的位置重新查找
+            if prompt.kind == PromptKind::Search {
+                self.search = prompt.input.clone();
+                if !self.search_from(prompt.origin, true) {
+                    self.selected = prompt.origin;
+                }
+            }
+
+            self.prompt = Some(prompt);
+            return false;
+        }
+
+        self.message = String::new();
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::PageDown => self.move_selection(PAGE_SIZE),
+            KeyCode::PageUp => self.move_selection(-PAGE_SIZE),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.move_selection(isize::MAX),
+            KeyCode::Char('d') => self.detail_scroll = self.detail_scroll.saturating_add(10),
+            KeyCode::Char('u') => self.detail_scroll = self.detail_scroll.saturating_sub(10),
+            KeyCode::Char('v') => self.json = !self.json,
+            KeyCode::Char('/') => self.open_prompt(PromptKind::Search, String::new()),
+            KeyCode::Char(':') => self.open_prompt(PromptKind::Jump, String::new()),
+            KeyCode::Char('f') => self.open_prompt(PromptKind::Filter, self.filter.text.clone()),
+            KeyCode::Char('n') => {
+                self.search_from(self.selected + 1, true);
+            }
+            KeyCode::Char('N') if self.selected > 0 => {
+                self.search_from(self.selected - 1, false);
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [main_area, status_area] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, detail_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main_area);
+
+        // 只为屏幕上能看到的事件生成列表项，事件很多时也不会变慢
+        let height = list_area.height.saturating_sub(2).max(1) as usize;
+        if self.selected < self.list_offset {
+            self.list_offset = self.selected;
+        } else if self.selected >= self.list_offset + height {
+            self.list_offset = self.selected + 1 - height;
+        }
+
+        let end = (self.list_offset + height).min(self.visible.len());
+        let items = self.visible[self.list_offset.min(end)..end]
+            .iter()
+            .map(|v| ListItem::new(self.index.entries[*v].line()))
+            .collect::<Vec<ListItem>>();
+
+        let mut list_state = ListState::default();
+        if !self.visible.is_empty() {
+            list_state.select(Some(self.selected - self.list_offset));
+        }
+
+        let title = if self.filter.text.is_empty() {
+            format!(" {} ({} events) ", self.file_name, self.visible.len())
+        } else {
+            format!(
+                " {} ({} events, filter: {}) ",
+                self.file_name,
+                self.visible.len(),
+                self.filter.text
+            )
+        };
+
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            list_area,
+            &mut list_state,
+        );
+
+        frame.render_widget(
+            Paragraph::new(self.detail.as_str())
+                .block(Block::bordered().title(if self.json { " json " } else { " event " }))
+                .wrap(Wrap { trim: false })
+                .scroll((self.detail_scroll, 0)),
+            detail_area,
+        );
+
+        let status = match &self.prompt {
+            Some(prompt) => {
+                let label = match prompt.kind {
+                    PromptKind::Search => "/",
+                    PromptKind::Jump => "jump to (offset, @unix time, yyyy-mm-dd hh:mm:ss, gtid): ",
+                    PromptKind::Filter => "filter (type=.. db=.. table=..): ",
+                };
+                format!("{}{}", label, prompt.input)
+            }
+            None if !self.message.is_empty() => self.message.clone(),
+            None => "q quit  j/k move  / search  n/N next/previous  : jump  f filter  v json  d/u scroll detail"
+                .to_string(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use ratatui::{backend::TestBackend, Terminal};
+
+    // 两个事务，时间分别是100和200
+    fn open_binlog(name: &str) -> File {
+        let mut binlog = BinlogBuilder::new();
+        for (sequence, timestamp) in [(1, 100), (2, 200)] {
+            binlog = binlog
+                .push(gtid(sequence).timestamp(timestamp))
+                .push(EventBuilder::from_body(&orders()).timestamp(timestamp))
+                .push(
+                    EventBuilder::from_body(
+                        &RowsEventBuilder::insert(&orders())
+                            .row(vec![ColumnValue::Int(sequence as i64)]),
+                    )
+                    .timestamp(timestamp),
+                )
+                .push(EventBuilder::new(16, 1u64.to_le_bytes().to_vec()).timestamp(timestamp));
+        }
+
+        let path = std::env::temp_dir().join(format!("tui-{}-{}", std::process::id(), name));
+        std::fs::write(&path, binlog.build()).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    }
+
+    #[test]
+    fn index_summaries_and_lookups() {
+        let mut file = open_binlog("index");
+        let index = EventIndex::build(&mut file).unwrap();
+
+        let type_codes = index
+            .entries
+            .iter()
+            .map(|v| v.type_code)
+            .collect::<Vec<_>>();
+        assert_eq!(type_codes, [15, 162, 19, 23, 16, 162, 19, 23, 16]);
+        assert_eq!(index.entries[1].summary(), "0-1-1");
+        assert_eq!(index.entries[3].summary(), "shop.orders");
+        assert_eq!(index.entries[8].gtid.as_deref(), Some("0-1-2"));
+        assert!(index.entries[5].line().contains("GTID_EVENT"));
+        assert!(index.entries[5].line().contains("1970-01-01 00:03:20"));
+
+        let third = &index.entries[3];
+        assert_eq!(index.find_position(third.position), Some(3));
+        assert_eq!(index.find_position(third.position + 1), Some(3));
+        assert_eq!(index.find_position(0), None);
+        assert_eq!(index.find_timestamp(150), Some(5));
+        assert_eq!(index.find_timestamp(300), None);
+        assert_eq!(index.find_gtid("0-1-2"), Some(5));
+        assert_eq!(index.find_gtid("0-1-3"), None);
+    }
+
+    #[test]
+    fn rows_events_are_decoded_with_their_table_map() {
+        let mut file = open_binlog("decode");
+        let index = EventIndex::build(&mut file).unwrap();
+
+        let (header, body) = index.decode(&mut file, 7).unwrap();
+        assert_eq!(header.type_code, 23);
+        let rows_event = body.downcast_ref::<EventBodyTypeCode23To25>().unwrap();
+        assert_eq!(rows_event.rows[0].after, Some(vec![ColumnValue::Int(2)]));
+    }
+
+    #[test]
+    fn filter_by_type_and_table() {
+        let mut file = open_binlog("filter");
+        let index = EventIndex::build(&mut file).unwrap();
+        let matched = |text: &str| {
+            let filter = EventFilter::parse(text).unwrap();
+            index.entries.iter().filter(|v| filter.matches(v)).count()
+        };
+
+        assert_eq!(matched(""), 9);
+        assert_eq!(matched("type=gtid"), 2);
+        assert_eq!(matched("type=16"), 2);
+        assert_eq!(matched("db=shop"), 4);
+        assert_eq!(matched("type=write db=shop table=orders"), 2);
+        assert_eq!(matched("table=customers"), 0);
+
+        assert!(EventFilter::parse("type=nothing").is_err());
+        assert!(EventFilter::parse("schema=shop").is_err());
+        assert!(EventFilter::parse("shop").is_err());
+    }
+
+    #[test]
+    fn keys_and_rendering() {
+        let mut file = open_binlog("render");
+        let index = EventIndex::build(&mut file).unwrap();
+        let mut app = App::new(file, "mysql-bin.000001".to_string(), index);
+
+        for c in ":0-1-2".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.selected_entry(), Some(5));
+
+        for c in "/orders".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        assert_eq!(app.selected_entry(), Some(6));
+        app.handle_key(KeyCode::Enter);
+        app.handle_key(KeyCode::Char('n'));
+        assert_eq!(app.selected_entry(), Some(7));
+
+        app.apply_filter(EventFilter::parse("type=xid").unwrap());
+        assert_eq!(app.visible, [4, 8]);
+        assert_eq!(app.selected_entry(), Some(8));
+        assert!(!app.handle_key(KeyCode::Char('k')));
+        assert_eq!(app.selected_entry(), Some(4));
+
+        app.refresh_detail();
+        let mut terminal = Terminal::new(TestBackend::new(120, 20)).unwrap();
+        terminal.draw(|frame| app.render(frame)).unwrap();
+        let screen = format!("{:?}", terminal.backend().buffer());
+        assert!(screen.contains("mysql-bin.000001 (2 events, filter: type=xid)"));
+        assert!(screen.contains("XID_EVENT"));
+
+        assert!(app.handle_key(KeyCode::Char('q')));
+    }
+}
@@ -1,41 +1,127 @@
-use std::{collections::HashMap, fs::{File, OpenOptions}, io::Read};
+use std::{fs::{File, OpenOptions}, io::Read, path::Path};
 
-use chrono::{DateTime, FixedOffset};
-use lazy_static::lazy_static;
+use chrono::DateTime;
 
-use base64::prelude::*;
 use bitvec::prelude::*;
 
+use base64::prelude::*;
+
+use crate::byte_range::is_url;
+use crate::collation::collation_name;
+use crate::error::RowImageLengthMismatch;
+use crate::field_type::FieldType;
+use crate::locale::locale_name;
 use crate::model::*;
+use crate::mysql_json::parse_json_binary;
+use crate::reader::BinlogReader;
+use crate::trace;
 
 type BoxedError = Box<dyn std::error::Error>;
 
-lazy_static! {
-    static ref FIXED_LENGTH_DATA_TYPE: HashMap<String, u8> = {
-        let mut m = HashMap::new();
-        m.insert("MYSQL_TYPE_NULL".to_string(), 0);
-        m.insert("MYSQL_TYPE_TINY".to_string(), 1);
-        m.insert("MYSQL_TYPE_YEAR".to_string(), 2);
-        m.insert("MYSQL_TYPE_SHORT".to_string(), 2);
-        m.insert("MYSQL_TYPE_INT24".to_string(), 3);
-        m.insert("MYSQL_TYPE_LONG".to_string(), 4);
-        m.insert("MYSQL_TYPE_LONGLONG".to_string(), 8);
-        m.insert("MYSQL_TYPE_FLOAT".to_string(), 4);
-        m.insert("MYSQL_TYPE_DOUBLE".to_string(), 8);
-        m
-    };
-}
-
 pub fn get_file(file_path: &str) -> Result<File, BoxedError> {
     let f = OpenOptions::new().read(true).open(file_path)?;
 
     Ok(f)
 }
 
-pub fn check_file_magic_number(file: &mut File) -> Result<bool, BoxedError> {
-    let mut buffer = [0u8; 4];
+/// 取路径中的文件名部分，例如mysql-bin.000001，URL中的query（预签名URL的签名）不算在内
+pub fn get_file_name(file_path: &str) -> String {
+    let file_path = match is_url(file_path) {
+        true => file_path.split(['?', '#']).next().unwrap_or(file_path),
+        false => file_path,
+    };
+
+    Path::new(file_path)
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_path.to_string())
+}
+
+/// 事件头中的时间，使用UTC，例如`2023-11-14 22:13:20`
+pub fn format_timestamp(timestamp: u32) -> String {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|v| v.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// 目录中所有的binlog文件（以magic number判断，压缩的文件按解压后的内容），按文件名排序
+pub fn get_binlog_files_in_dir(dir_path: &str) -> Result<Vec<String>, BoxedError> {
+    let mut result = Vec::new();
+
+    for entry in std::fs::read_dir(dir_path)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
 
-    file.read_exact(&mut buffer)?;
+        let path = path.to_string_lossy().to_string();
+        if BinlogReader::open(&path)
+            .is_ok_and(|mut v| check_file_magic_number(&mut v).unwrap_or(false))
+        {
+            result.push(path);
+        }
+    }
+
+    result.sort();
+
+    Ok(result)
+}
+
+/// 事件类型的名字，和mysqlbinlog中的叫法一致
+pub fn get_event_type_name(type_code: u8) -> &'static str {
+    match type_code {
+        1 => "START_EVENT_V3",
+        2 => "QUERY_EVENT",
+        3 => "STOP_EVENT",
+        4 => "ROTATE_EVENT",
+        5 => "INTVAR_EVENT",
+        13 => "RAND_EVENT",
+        14 => "USER_VAR_EVENT",
+        15 => "FORMAT_DESCRIPTION_EVENT",
+        16 => "XID_EVENT",
+        17 => "BEGIN_LOAD_QUERY_EVENT",
+        18 => "EXECUTE_LOAD_QUERY_EVENT",
+        19 => "TABLE_MAP_EVENT",
+        23 => "WRITE_ROWS_EVENT_V1",
+        24 => "UPDATE_ROWS_EVENT_V1",
+        25 => "DELETE_ROWS_EVENT_V1",
+        26 => "INCIDENT_EVENT",
+        27 => "HEARTBEAT_LOG_EVENT",
+        28 => "IGNORABLE_LOG_EVENT",
+        29 => "ROWS_QUERY_LOG_EVENT",
+        30 => "WRITE_ROWS_EVENT",
+        31 => "UPDATE_ROWS_EVENT",
+        32 => "DELETE_ROWS_EVENT",
+        33 => "GTID_LOG_EVENT",
+        34 => "ANONYMOUS_GTID_LOG_EVENT",
+        35 => "PREVIOUS_GTIDS_LOG_EVENT",
+        36 => "TRANSACTION_CONTEXT_EVENT",
+        37 => "VIEW_CHANGE_EVENT",
+        38 => "XA_PREPARE_LOG_EVENT",
+        39 => "PARTIAL_UPDATE_ROWS_EVENT",
+        40 => "TRANSACTION_PAYLOAD_EVENT",
+        41 => "HEARTBEAT_LOG_EVENT_V2",
+        160 => "ANNOTATE_ROWS_EVENT",
+        161 => "BINLOG_CHECKPOINT_EVENT",
+        162 => "GTID_EVENT",
+        163 => "GTID_LIST_EVENT",
+        164 => "START_ENCRYPTION_EVENT",
+        165 => "QUERY_COMPRESSED_EVENT",
+        166 => "WRITE_ROWS_COMPRESSED_EVENT_V1",
+        167 => "UPDATE_ROWS_COMPRESSED_EVENT_V1",
+        168 => "DELETE_ROWS_COMPRESSED_EVENT_V1",
+        169 => "WRITE_ROWS_COMPRESSED_EVENT",
+        170 => "UPDATE_ROWS_COMPRESSED_EVENT",
+        171 => "DELETE_ROWS_COMPRESSED_EVENT",
+        _ => "UNKNOWN_EVENT",
+    }
+}
+
+/// 不到4个字节的文件（包括空文件）也不是binlog，返回false而不是读取错误
+pub fn check_file_magic_number<R: Read>(file: &mut R) -> Result<bool, BoxedError> {
+    let mut buffer = Vec::with_capacity(4);
+
+    file.take(4).read_to_end(&mut buffer)?;
 
     let hex_string = buffer
         .iter()
@@ -47,251 +133,239 @@ pub fn check_file_magic_number(file: &mut File) -> Result<bool, BoxedError> {
 }
 
 pub fn parse_lenenc(buffer: &[u8]) -> Result<(u64, u8), BoxedError> {
-    let first_byte = u8::from_le_bytes(buffer[0..1].try_into()?);
+    let first_byte = u8::from_le_bytes(read_slice(buffer, 0, 1, "lenenc integer")?.try_into()?);
 
     if first_byte < 251 {
-        return Ok((first_byte as u64, 1));
+        Ok((first_byte as u64, 1))
     } else if first_byte == 252 {
-        let result = u16::from_le_bytes(buffer[1..3].try_into()?);
-        return Ok((result as u64, 3));
+        let result = u16::from_le_bytes(read_slice(buffer, 1, 2, "lenenc integer")?.try_into()?);
+        Ok((result as u64, 3))
     } else if first_byte == 253 {
-        let mut temp_buffer = buffer[1..4].to_vec();
+        let mut temp_buffer = read_slice(buffer, 1, 3, "lenenc integer")?.to_vec();
         temp_buffer.splice(temp_buffer.len()..temp_buffer.len(), vec![0]);
         let result = u32::from_le_bytes(temp_buffer.as_slice().try_into()?);
-        return Ok((result as u64, 4));
+        Ok((result as u64, 4))
     } else if first_byte == 254 {
-        let result = u64::from_le_bytes(buffer[1..9].try_into()?);
-        return Ok((result as u64, 9));
+        let result = u64::from_le_bytes(read_slice(buffer, 1, 8, "lenenc integer")?.try_into()?);
+        Ok((result, 9))
     } else {
-        return Err(Box::new(MyError("lenenc parse error".to_string())));
+        Err(Box::new(MyError("lenenc parse error".to_string())))
     }
 }
 
-// 参考 https://github.com/mysql/mysql-server/blob/mysql-cluster-8.0.22/include/field_types.h#L52
-pub fn get_field_types_mapping() -> Result<HashMap<u8, String>, BoxedError> {
-    let mut f = OpenOptions::new().read(true).open("field_types.txt")?;
-
-    let mut s = String::new();
-    f.read_to_string(&mut s)?;
-
-    // 这里定位u16是因为最大值是255
-    // +1之后会越界
-    let mut count: u16 = 0;
-    let result = s
-        .lines()
-        .map(|line| {
-            let data = line.split(",").nth(0).unwrap();
-            let data_vec = data.split(" = ").collect::<Vec<&str>>();
-
-            let return_data;
-
-            if data_vec.len() == 1 {
-                return_data = (count as u8, data_vec[0].to_string());
-                count += 1;
-            } else {
-                let id = data_vec[1].parse::<u8>().unwrap();
-                return_data = (id as u8, data_vec[0].to_string());
-                count = id as u16 + 1;
-            }
-
-            return_data
-        })
-        .collect::<HashMap<u8, String>>();
-
-    Ok(result)
+/// buffer中start开始的length个字节，不够时返回`the {what} is truncated`的错误而不是panic
+pub fn read_slice<'a>(
+    buffer: &'a [u8],
+    start: usize,
+    length: usize,
+    what: &str,
+) -> Result<&'a [u8], BoxedError> {
+    buffer
+        .get(start..start.saturating_add(length))
+        .ok_or_else(|| Box::new(MyError(format!("the {} is truncated", what))) as BoxedError)
 }
 
-// 参考 https://github.com/mysql/mysql-server/blob/mysql-cluster-8.0.22/libbinlogevents/include/rows_event.h#L192
-pub fn get_metadata_block_mapping() -> Result<HashMap<u8, u8>, BoxedError> {
-    let mut f = OpenOptions::new()
-        .read(true)
-        .open("metablock_mapping.txt")?;
-
-    let mut s = String::new();
-
-    f.read_to_string(&mut s)?;
-
-    let result = s
-        .lines()
-        .map(|line| {
-            let data_vec = line.split(",").collect::<Vec<&str>>();
-
-            let column_type = data_vec[1].parse::<u8>().unwrap();
-            let byte_quantity = data_vec[2].parse::<u8>().unwrap();
-
-            (column_type, byte_quantity)
-        })
-        .collect::<HashMap<u8, u8>>();
-
-    Ok(result)
+/// parse_lenenc的逆过程，使用能表示value的最短的编码
+pub fn encode_lenenc(value: u64) -> Vec<u8> {
+    match value {
+        0..=250 => vec![value as u8],
+        251..=0xffff => [&[252], &(value as u16).to_le_bytes()[..]].concat(),
+        0x10000..=0xffffff => [&[253], &(value as u32).to_le_bytes()[..3]].concat(),
+        _ => [&[254], &value.to_le_bytes()[..]].concat(),
+    }
 }
 
+/// 从metadata block的offset处取出一个字段的元数据，返回人类可读的说明、元数据和占用的字节数，没有元数据的类型都为空
 pub fn parse_metadata_block(
-    metadata_block_mapping: &HashMap<u8, u8>,
-    field_types_mapping: &HashMap<u8, String>,
-    metadata_block: &Vec<u8>,
-    metadata_block_offset: u8,
-    content_type: u8,
-) -> Result<(String, Vec<u8>, u8), BoxedError> {
-    let result;
-
-    let metadata_block_length = metadata_block_mapping
-        .get(&content_type)
-        .or(Some(&0))
-        .unwrap()
-        .clone();
+    metadata_block: &[u8],
+    metadata_block_offset: usize,
+    field_type: FieldType,
+) -> Result<(String, Vec<u8>, usize), BoxedError> {
+    let metadata_block_length = field_type.metadata_len();
+    if metadata_block_length == 0 {
+        return Ok((String::new(), Vec::new(), 0));
+    }
 
     let metadata_block_data = metadata_block
-        [metadata_block_offset as usize..(metadata_block_offset + metadata_block_length) as usize]
+        .get(metadata_block_offset..metadata_block_offset + metadata_block_length)
+        .ok_or_else(|| {
+            MyError(format!(
+                "the metadata of {} at {} is out of the metadata block of {} bytes",
+                field_type,
+                metadata_block_offset,
+                metadata_block.len()
+            ))
+        })?
         .to_vec();
 
-    if metadata_block_length == 0 {
-        result = Ok(("".to_string(), Vec::new(), 0));
-    } else {
-        let field_types_string_for_human = field_types_mapping.get(&content_type).unwrap();
-
-        let infomation = match content_type {
-            4 => {
-                format!("the sizeof(float) is {}", metadata_block_data[0])
-            }
-            5 => {
-                format!("the sizeof(dobule) is {}", metadata_block_data[0])
-            }
-            15 => {
-                let mut metadata_block_data_clone = metadata_block_data.clone();
-                metadata_block_data_clone.reverse();
-                format!(
-                    "the maximum length of the string is {} byte",
-                    u16::from_be_bytes(metadata_block_data_clone.try_into().unwrap())
-                )
-            }
-            16 => {
-                format!("the length in bits of the bitfield is {}, the number of bytes occupied by the bitfield is {}", metadata_block_data[0], metadata_block_data[1])
-            }
-            17 => {
-                format!(
-                    "the number of decimals for the fractional part is {}",
-                    metadata_block_data[0]
-                )
-            }
-            18 => {
-                format!(
-                    "the number of decimals for the fractional part is {}",
-                    metadata_block_data[0]
-                )
-            }
-            19 => {
-                format!(
-                    "the number of decimals for the fractional part is {}",
-                    metadata_block_data[0]
-                )
-            }
-            246 => {
-                format!(
-                    "the length of precision is {}, the length of decimals is {}",
-                    metadata_block_data[0], metadata_block_data[1]
-                )
-            }
-            252 => {
-                format!("field size is {} bytes", metadata_block_data[0])
-            }
-            253 => {
-                let real_field_type_id = metadata_block_data[0];
-                let real_field_type_name = field_types_mapping.get(&real_field_type_id).unwrap();
-                let length = metadata_block_data[1];
-
-                format!(
-                    "real field type id is {}, real field type name is {}, storage length is {}",
-                    real_field_type_id, real_field_type_name, length
-                )
-            }
-            254 => {
-                format!("field size is {} bytes", metadata_block_data[1])
-            }
-            255 => {
-                format!(
-                    "the number of bytes needed to represent the length of the geometry is {}",
-                    metadata_block_data[0]
-                )
-            }
-            _ => "".to_string(),
-        };
+    let content = format!(
+        "field type id is: {}, field type name is: {}, infomation is [{}]",
+        field_type.code(),
+        field_type,
+        describe_column_metadata(field_type, &metadata_block_data)
+    );
 
-        let content = format!(
-            "field type id is: {}, field type name is: {}, infomation is [{}]",
-            content_type, field_types_string_for_human, infomation
-        );
+    Ok((content, metadata_block_data, metadata_block_length))
+}
 
-        result = Ok((content, metadata_block_data, metadata_block_length));
+/// 一个字段的元数据的含义，例如`the maximum length of the string is 20 byte`，没有元数据时为空
+pub fn describe_column_metadata(field_type: FieldType, data: &[u8]) -> String {
+    if field_type.metadata_len() == 0 || data.len() < field_type.metadata_len() {
+        return String::new();
     }
 
-    result
+    match field_type {
+        FieldType::Float => {
+            format!("the sizeof(float) is {}", data[0])
+        }
+        FieldType::Double => {
+            format!("the sizeof(dobule) is {}", data[0])
+        }
+        FieldType::Varchar => {
+            format!(
+                "the maximum length of the string is {} byte",
+                u16::from_le_bytes([data[0], data[1]])
+            )
+        }
+        FieldType::Bit => {
+            format!("the length in bits of the bitfield is {}, the number of bytes occupied by the bitfield is {}", data[0], data[1])
+        }
+        FieldType::Timestamp2 | FieldType::DateTime2 | FieldType::Time2 => {
+            format!(
+                "the number of decimals for the fractional part is {}",
+                data[0]
+            )
+        }
+        FieldType::Json => {
+            format!(
+                "the number of bytes needed to represent the length of the json is {}",
+                data[0]
+            )
+        }
+        FieldType::NewDecimal => {
+            format!(
+                "the length of precision is {}, the length of decimals is {}",
+                data[0], data[1]
+            )
+        }
+        FieldType::TinyBlob | FieldType::MediumBlob | FieldType::LongBlob | FieldType::Blob => {
+            format!("field size is {} bytes", data[0])
+        }
+        FieldType::VarString => {
+            let real_field_type_id = data[0];
+            let real_field_type_name = FieldType::try_from(real_field_type_id)
+                .map(|v| v.name())
+                .unwrap_or("UNKNOWN");
+            let length = data[1];
+
+            format!(
+                "real field type id is {}, real field type name is {}, storage length is {}",
+                real_field_type_id, real_field_type_name, length
+            )
+        }
+        FieldType::String => {
+            format!("field size is {} bytes", data[1])
+        }
+        FieldType::Geometry => {
+            format!(
+                "the number of bytes needed to represent the length of the geometry is {}",
+                data[0]
+            )
+        }
+        _ => "".to_string(),
+    }
 }
 
-pub fn parse_bitmap(buffer: &[u8], truncate: u64) -> Vec<bool> {
+/// table map的可选元数据，由若干个(类型, lenenc长度, 内容)组成
+pub fn parse_optional_metadata(buffer: &[u8]) -> Result<Vec<(u8, Vec<u8>)>, BoxedError> {
     let mut offset = 0;
 
     let mut result = Vec::new();
 
-    for _ in 0..buffer.len() {
-        let binary_string = format!("{:08b}", buffer[offset]);
-        result.extend({
-            let mut bool_vec = binary_string
-                .split("")
-                .filter(|v| !v.is_empty())
-                .map(|v| match v {
-                    "1" => true,
-                    "0" => false,
-                    _ => false,
-                })
-                .collect::<Vec<_>>();
-
-            bool_vec.reverse();
-
-            bool_vec
-        });
+    while offset < buffer.len() {
+        let field_type = buffer[offset];
         offset += 1;
+
+        if offset >= buffer.len() {
+            return Err(Box::new(MyError(
+                "optional metadata is truncated".to_string(),
+            )));
+        }
+
+        let (length, skip) = parse_lenenc(&buffer[offset..])?;
+        offset += skip as usize;
+
+        if offset + length as usize > buffer.len() {
+            return Err(Box::new(MyError(
+                "optional metadata is truncated".to_string(),
+            )));
+        }
+
+        result.push((field_type, buffer[offset..offset + length as usize].to_vec()));
+        offset += length as usize;
     }
-    result.truncate(truncate as usize);
 
-    result
+    Ok(result)
 }
 
-fn bin_to_decimal(
+pub fn parse_bitmap(buffer: &[u8], truncate: u64) -> Bitmap {
+    Bitmap::from_bytes(buffer, truncate as usize)
+}
+
+/// newdecimal的二进制格式，precision和decimals是table map中的元数据，返回数值的字符串和占用的字节数
+pub fn bin_to_decimal(
     buffer: &mut [u8],
     precision: usize,
     decimals: usize,
 ) -> Result<(String, usize), BoxedError> {
-    // 获取这个数值的符号
-    let _sign = buffer[0] & 0x80;
-    let sign = match _sign {
-        0 => -1,
-        _ => 1,
-    };
-
-    // 计算需要占用多少字节
-    let integer_part_length = precision - decimals;
+    // 计算需要占用多少字节，元数据损坏时返回错误而不是panic
+    let integer_part_length = precision.checked_sub(decimals).ok_or_else(|| {
+        MyError(format!(
+            "the decimals {} of the decimal is larger than its precision {}",
+            decimals, precision
+        ))
+    })?;
 
     let integer_part_byte_n = parse_quantity_of_bytes_for_decimal_part(integer_part_length);
     let decimal_part_byte_n = parse_quantity_of_bytes_for_decimal_part(decimals);
 
     let total_byte_n = integer_part_byte_n + decimal_part_byte_n;
+    if total_byte_n == 0 || buffer.len() < total_byte_n {
+        return Err(Box::new(MyError("the decimal is truncated".to_string())));
+    }
 
-    // 如果是负数需要对所有的bit进行取反
-    if sign < 0 {
-        for i in 0..total_byte_n {
-            buffer[i] = !buffer[i];
+    // 符号位为1是非负数，负数的所有bit都取反过
+    let negative = buffer[0] & 0x80 == 0;
+    if negative {
+        for byte in buffer.iter_mut().take(total_byte_n) {
+            *byte = !*byte;
         }
     }
 
     // 将最高位取反
     buffer[0] ^= 0x80;
 
-    let mut numberic_string = parse_numberic_for_decimal(&buffer[0..integer_part_byte_n])?;
-    numberic_string.push_str(".");
-    numberic_string.push_str(&parse_numberic_for_decimal(
-        &buffer[integer_part_byte_n..total_byte_n],
-    )?);
+    // 整数部分不足9位的数字在前面，小数部分在后面
+    let integer_part =
+        parse_numberic_for_decimal(&buffer[0..integer_part_byte_n], integer_part_length, true);
+    let integer_part = integer_part.trim_start_matches('0');
+
+    let mut numberic_string = String::with_capacity(precision + 2);
+    if negative {
+        numberic_string.push('-');
+    }
+    match integer_part.is_empty() {
+        true => numberic_string.push('0'),
+        false => numberic_string.push_str(integer_part),
+    }
+    if decimals > 0 {
+        numberic_string.push('.');
+        numberic_string.push_str(&parse_numberic_for_decimal(
+            &buffer[integer_part_byte_n..total_byte_n],
+            decimals,
+            false,
+        ));
+    }
 
     Ok((numberic_string, total_byte_n))
 }
@@ -302,534 +376,1224 @@ fn bin_to_decimal(
 /// 然后不满9位的话会按照一个映射占用相应的字节数量
 /// 详情参考下方链接
 /// https://github.com/google/mysql/blob/master/strings/decimal.c#L1096
-fn parse_quantity_of_bytes_for_decimal_part(n: usize) -> usize {
+pub fn parse_quantity_of_bytes_for_decimal_part(n: usize) -> usize {
     let quantity_for_9_digits = n / 9;
     let remaining_digits = n % 9;
 
-    quantity_for_9_digits * 4 + (remaining_digits + 1) / 2
+    quantity_for_9_digits * 4 + remaining_digits.div_ceil(2)
 }
 
-/// 将decimal的整数部分或者小数部分的bin转换成数字
-fn parse_numberic_for_decimal(buffer: &[u8]) -> Result<String, BoxedError> {
-    let buffer_length = buffer.len();
-
-    let remainder = buffer_length % 4;
+/// 将decimal的整数部分或者小数部分的bin转换成数字，digits是这部分的位数
+///
+/// 每9位数字是4字节的大端，不足9位的部分按位数占用1到4个字节，整数部分在最前面，小数部分在最后面，
+/// 每一组都按位数补0
+fn parse_numberic_for_decimal(buffer: &[u8], digits: usize, leading_remainder: bool) -> String {
+    let remaining_digits = digits % 9;
+    let remaining_byte_n = parse_quantity_of_bytes_for_decimal_part(remaining_digits);
+
+    let (full_groups, remainder) = match leading_remainder {
+        true => {
+            let (remainder, full_groups) = buffer.split_at(remaining_byte_n);
+            (full_groups, remainder)
+        }
+        false => buffer.split_at(buffer.len() - remaining_byte_n),
+    };
 
-    let mut data = buffer.to_owned();
+    let group = |bytes: &[u8], width: usize| {
+        let value = bytes.iter().fold(0u32, |v, b| (v << 8) | *b as u32);
+        format!("{:0width$}", value, width = width)
+    };
 
-    let mut result = Vec::new();
+    let mut result = String::with_capacity(digits);
+    if leading_remainder && remaining_digits > 0 {
+        result.push_str(&group(remainder, remaining_digits));
+    }
+    for bytes in full_groups.chunks(4) {
+        result.push_str(&group(bytes, 9));
+    }
+    if !leading_remainder && remaining_digits > 0 {
+        result.push_str(&group(remainder, remaining_digits));
+    }
 
-    if remainder > 0 {
-        let mut additonal_data = Vec::new();
+    result
+}
 
-        for _ in 0..4 - remainder {
-            additonal_data.push(0);
+/// 1到8字节小端的整数，signed时按最高位扩展符号
+fn parse_integer(field: &[u8], unsigned: bool) -> ColumnValue {
+    let mut data = [0u8; 8];
+    data[..field.len()].copy_from_slice(field);
+    let value = u64::from_le_bytes(data);
+
+    let bits = field.len() as u32 * 8;
+    match unsigned {
+        true if value > i64::MAX as u64 => ColumnValue::UInt(value),
+        true => ColumnValue::Int(value as i64),
+        false => {
+            let shift = 64 - bits;
+            ColumnValue::Int(((value << shift) as i64) >> shift)
         }
-
-        data.splice(0..0, additonal_data);
     }
+}
 
-    for i in 0..data.len() / 4 {
-        result.push(u32::from_be_bytes(data[i * 4..(i + 1) * 4].try_into()?).to_string());
-    }
+/// 解析行事件中的一个镜像，即null bitmap以及紧随其后的字段数据
+/// 返回null bitmap、按字段顺序排列的值（null字段为ColumnValue::Null）以及消耗的字节数
+/// value_options见parse_column_data_for_row_event
+pub fn parse_row_image(
+    buffer: &[u8],
+    number_of_columns: u64,
+    table_info: &EventBodyTypeCode19,
+    value_options: ValueOptions,
+) -> Result<(Bitmap, Vec<ColumnValue>, usize), BoxedError> {
+    let present = Bitmap::repeat(true, number_of_columns as usize);
 
-    Ok(result.join(""))
+    parse_partial_row_image(buffer, &present, table_info, value_options)
 }
 
-pub fn parse_column_data_for_row_event(
-    buffer: &mut [u8],
+/// binlog_row_image为MINIMAL、NOBLOB时镜像中只有present（行事件的columns_used）中的字段，
+/// null bitmap也只有这些字段的位。返回的null bitmap和binlog中一样，
+/// 值仍然按表的字段顺序排列，不在镜像中的字段为ColumnValue::Null，用present区分
+pub fn parse_partial_row_image(
+    buffer: &[u8],
+    present: &Bitmap,
     table_info: &EventBodyTypeCode19,
-    null_bitmap: &Vec<bool>,
-) -> Result<(Vec<String>, usize), BoxedError> {
-    let mut offset = 0;
+    value_options: ValueOptions,
+) -> Result<(Bitmap, Vec<ColumnValue>, usize), BoxedError> {
+    let number_of_present = present.count_ones();
+    let null_bitmap_n_byte = number_of_present.div_ceil(8);
+    let null_bitmap = parse_bitmap(
+        buffer
+            .get(0..null_bitmap_n_byte)
+            .ok_or_else(|| MyError("the row image is truncated".to_string()))?,
+        number_of_present as u64,
+    );
+    trace::field("null_bitmap", 0, null_bitmap_n_byte);
+    let _trace_offset = trace::offset_by(null_bitmap_n_byte);
+
+    // 不在镜像中的字段和null字段一样没有数据
+    let (column_data_vec, skip) = match present.all() {
+        true => parse_column_data_for_row_event(
+            &buffer[null_bitmap_n_byte..],
+            table_info,
+            &null_bitmap,
+            value_options,
+        )?,
+        false => {
+            let mut null_bits = null_bitmap.iter();
+            let without_data: Bitmap = present
+                .iter()
+                .map(|is_present| !is_present || null_bits.next().unwrap_or(true))
+                .collect();
+
+            parse_column_data_for_row_event(
+                &buffer[null_bitmap_n_byte..],
+                table_info,
+                &without_data,
+                value_options,
+            )?
+        }
+    };
 
-    let field_type_vec = table_info
-        .column_types_string_for_human
-        .iter()
-        .map(|v| v.as_str())
-        .collect::<Vec<&str>>();
-
-    let mut metadata_block_raw_iter = table_info.metadata_block_data_raw.iter();
-
-    let mut column_data_vec = Vec::new();
-
-    let should_be_decode_from_table_event_data_type = vec![
-        "MYSQL_TYPE_BIT",
-        "MYSQL_TYPE_ENUM",
-        "MYSQL_TYPE_SET",
-        "MYSQL_TYPE_NEWDECIMAL",
-        "MYSQL_TYPE_DECIMAL",
-        "MYSQL_TYPE_VARCHAR",
-        "MYSQL_TYPE_VAR_STRING",
-        "MYSQL_TYPE_STRING",
-        "MYSQL_TYPE_TINY_BLOB",
-        "MYSQL_TYPE_MEDIUM_BLOB",
-        "MYSQL_TYPE_LONG_BLOB",
-        "MYSQL_TYPE_BLOB",
-        "MYSQL_TYPE_TIMESTAMP2",
-        "MYSQL_TYPE_DATETIME2",
-        "MYSQL_TYPE_TIME2",
-        "MYSQL_TYPE_FLOAT",
-        "MYSQL_TYPE_DOUBLE",
-    ];
-
-    for i in 0..null_bitmap.len() {
-        // 这里的做法不大优雅
-        // 因为存在一种可能是，字段值有metadata但是他的null_bitmap是false
-        // 那么在上述情况下如果将next的动作写在每个字段的处理块中就会发生遗漏next
-        // 从而导致metadata和相应的处理块的错位
-        // 但是如果不给metadata_block_data_raw进行初始化，编译器会报警
-        // 综上所述采用了一个虚假的初始化
-        let fake_data = Vec::new();
-        let mut metadata_block_data_raw: Option<&Vec<u8>> = Some(&fake_data);
-        if should_be_decode_from_table_event_data_type.contains(&field_type_vec[i]) {
-            metadata_block_data_raw = metadata_block_raw_iter.next();
-        }
-
-        if !null_bitmap[i] {
-            let field_length = FIXED_LENGTH_DATA_TYPE
-                .get(field_type_vec[i])
-                .unwrap_or(&0)
-                .to_owned() as usize;
-
-            let data = match field_type_vec[i] {
-                "MYSQL_TYPE_TINY" => {
-                    let result =
-                        i8::from_le_bytes(buffer[offset..offset + field_length].try_into()?);
-                    offset += field_length;
-                    result.to_string()
-                }
-                "MYSQL_TYPE_SHORT" => {
-                    let result =
-                        i16::from_le_bytes(buffer[offset..offset + field_length].try_into()?);
-                    offset += field_length;
-                    result.to_string()
-                }
-                "MYSQL_TYPE_LONG" => {
-                    let result =
-                        i32::from_le_bytes(buffer[offset..offset + field_length].try_into()?);
-                    offset += field_length;
-                    result.to_string()
-                }
-                "MYSQL_TYPE_LONGLONG" => {
-                    let result =
-                        i64::from_le_bytes(buffer[offset..offset + field_length].try_into()?);
-                    offset += field_length;
-                    result.to_string()
-                }
-                "MYSQL_TYPE_FLOAT" => {
-                    let result =
-                        f32::from_le_bytes(buffer[offset..offset + field_length].try_into()?);
-                    offset += field_length;
-                    result.to_string()
-                }
-                "MYSQL_TYPE_DOUBLE" => {
-                    let result =
-                        f64::from_le_bytes(buffer[offset..offset + field_length].try_into()?);
-                    offset += field_length;
-                    result.to_string()
-                }
-                "MYSQL_TYPE_NEWDECIMAL" => {
-                    let metadata_block_data = metadata_block_data_raw.unwrap();
-
-                    let (numberic_string, skip) = bin_to_decimal(
-                        &mut buffer[offset..],
-                        metadata_block_data[0] as usize,
-                        metadata_block_data[1] as usize,
-                    )?;
-                    offset += skip;
-                    numberic_string
-                }
-                "MYSQL_TYPE_VARCHAR" => {
-                    let metadata_block_data = metadata_block_data_raw.unwrap().clone();
-
-                    let varchar_defined_length =
-                        u16::from_le_bytes(metadata_block_data.try_into().unwrap());
-
-                    let varchar_real_length: usize;
-
-                    // 实际的varchar的长度获取是需要依赖19中的metadata的
-                    // 如果定义的varchar长度超过255，那么再23~25的数据中使用2byte表示长度
-                    // 如果定义的varchar长度小于等于255，那么在23~25的数据中使用1byte表示长度
-                    if varchar_defined_length > 255 {
-                        varchar_real_length =
-                            u16::from_le_bytes(buffer[offset..offset + 2].try_into()?) as usize;
-                        offset += 2;
-                    } else {
-                        varchar_real_length =
-                            u8::from_le_bytes(buffer[offset..offset + 1].try_into()?) as usize;
-                        offset += 1;
-                    }
+    Ok((null_bitmap, column_data_vec, null_bitmap_n_byte + skip))
+}
 
-                    let result = try_convert_binary_to_string(&buffer[offset..offset+varchar_real_length]);
+/// 依次解析所有的镜像，遇到还没实现的字段类型时不知道这一行的真实长度，后面的数据就不再解析了
+/// 字段的值直接从row_images中取，不复制整个缓冲区，所以很大的BLOB只在结果中再占用一份
+///
+/// present是行事件的columns_used，update修改后的镜像使用present_for_update
+/// 解码之后没有正好用完row_images时返回RowImageLengthMismatch
+pub fn parse_row_images(
+    row_images: &[u8],
+    present: &Bitmap,
+    present_for_update: Option<&Bitmap>,
+    table_info: &EventBodyTypeCode19,
+    value_options: ValueOptions,
+) -> Result<Vec<RowImage>, BoxedError> {
+    let (images, mismatch) = parse_row_images_lenient(
+        row_images,
+        present,
+        present_for_update,
+        table_info,
+        value_options,
+    );
 
-                    offset += varchar_real_length;
+    match mismatch {
+        Some(mismatch) => Err(Box::new(mismatch)),
+        None => Ok(images),
+    }
+}
 
-                    result
-                }
-                "MYSQL_TYPE_DATE" => {
-                    let bits = buffer[offset..offset + 3].view_bits::<Lsb0>().to_bitvec();
+/// 和parse_row_images一样，但不一致时不返回错误，而是返回已经完整解码的镜像和不一致的情况
+/// 表结构和行镜像不一致（字段数、类型或者元数据不对）时解码的位置会错开，最后超出或者没有用完row_images
+pub fn parse_row_images_lenient(
+    row_images: &[u8],
+    present: &Bitmap,
+    present_for_update: Option<&Bitmap>,
+    table_info: &EventBodyTypeCode19,
+    value_options: ValueOptions,
+) -> (Vec<RowImage>, Option<RowImageLengthMismatch>) {
+    let mut offset = 0;
+    let mut images: Vec<RowImage> = Vec::new();
+    let mismatch = |images_offset: usize, reason: String| RowImageLengthMismatch {
+        table: format!("{}.{}", table_info.database_name, table_info.table_name),
+        column_count: present.len() as u64,
+        consumed: images_offset,
+        expected: row_images.len(),
+        remaining: row_images[images_offset..].to_vec(),
+        reason,
+    };
 
-                    let day = bits.get(0..5).unwrap().to_owned().load_le::<u8>();
+    let mut image_start = 0;
+    while offset < row_images.len() {
+        image_start = offset;
+        let image_present = match present_for_update {
+            Some(present_for_update) if images.len() % 2 == 1 => present_for_update,
+            _ => present,
+        };
 
-                    let month = bits.get(5..9).unwrap().to_owned().load_le::<u8>();
+        let trace_offset = trace::offset_by(offset);
+        let image = parse_partial_row_image(
+            &row_images[offset..],
+            image_present,
+            table_info,
+            value_options,
+        );
+        drop(trace_offset);
+
+        let (null_bitmap, column_data_vec, skip) = match image {
+            Ok(image) => image,
+            Err(error) => {
+                let reason = match error.downcast_ref::<MyError>() {
+                    Some(MyError(reason)) => reason.clone(),
+                    None => error.to_string(),
+                };
+                return (images, Some(mismatch(offset, reason)));
+            }
+        };
+        let index = images.len();
+        trace::field_with(|| format!("row_image[{}]", index), offset, offset + skip);
+        offset += skip;
 
-                    let year = bits.get(9..).unwrap().to_owned().load_le::<u16>();
+        let is_complete = !column_data_vec
+            .iter()
+            .any(|v| matches!(v, ColumnValue::Unsupported(_)));
 
-                    offset += 3;
+        images.push((null_bitmap, column_data_vec));
 
-                    format!("{}-{}-{}", year, month, day)
-                }
-                "MYSQL_TYPE_TIME2" => {
-                    let bits = buffer[offset..offset + 3].view_bits::<Msb0>().to_bitvec();
+        if !is_complete {
+            return (images, None);
+        }
+    }
 
-                    let mut val: i32 = (bits.load_be::<u32>() - 0x800000) as i32;
+    // update的镜像成对出现
+    if present_for_update.is_some() && images.len() % 2 == 1 {
+        images.pop();
+        let reason = "the last update row has no after image".to_string();
+        return (images, Some(mismatch(image_start, reason)));
+    }
 
-                    if val < 0 {
-                        val = -val;
-                    }
+    (images, None)
+}
 
-                    let hour = (val >> 12) % (1 << 10);
-                    let minute = (val >> 6) % (1 << 6);
-                    let second = val % (1 << 6);
+/// 只计算镜像的个数，不解码字段的值，每个字段的长度和parse_column_data_for_row_event一致
+/// 遇到还不支持的字段类型或者数据不完整时返回None，这时只能完整地解码
+pub fn count_row_images(
+    row_images: &[u8],
+    number_of_columns: u64,
+    table_info: &EventBodyTypeCode19,
+) -> Option<u64> {
+    let null_bitmap_n_byte = number_of_columns.div_ceil(8) as usize;
+    let descriptors = table_info.descriptors();
 
-                    offset += 3;
+    let mut offset = 0;
+    let mut count = 0;
+    while offset < row_images.len() {
+        let null_bitmap = row_images.get(offset..offset + null_bitmap_n_byte)?;
+        offset += null_bitmap_n_byte;
 
-                    format!("{:02}:{:02}:{02}", hour, minute, second)
-                }
-                "MYSQL_TYPE_DATETIME2" => {
-                    let bits = buffer[offset..offset + 5].view_bits::<Msb0>().to_bitvec();
+        for i in 0..number_of_columns as usize {
+            let descriptor = descriptors.get(i)?;
 
-                    let val = bits.load_be::<u64>() - 0x8000000000;
+            if null_bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                continue;
+            }
 
-                    let date_val = val >> 17;
-                    let time_val = val % (1 << 17);
+            offset += match descriptor.length {
+                ColumnLength::Fixed(length) => length,
+                ColumnLength::Prefixed(n) => {
+                    let mut buffer = [0u8; 4];
+                    buffer[..n].copy_from_slice(row_images.get(offset..offset + n)?);
+                    n + u32::from_le_bytes(buffer) as usize
+                }
+                ColumnLength::Unknown => return None,
+            };
+        }
 
-                    let day = date_val % (1 << 5);
-                    let month = (date_val >> 5) % 13;
-                    let year = (date_val >> 5) / 13;
-                    let second = time_val % (1 << 6);
-                    let minute = (time_val >> 6) % (1 << 6);
-                    let hour = (time_val >> 12) % (1 << 12);
+        if offset > row_images.len() {
+            return None;
+        }
+        count += 1;
+    }
 
-                    offset += 5;
+    Some(count)
+}
 
-                    format!(
-                        "{}-{:02}-{:02} {:02}:{:02}:{:02}",
-                        year, month, day, hour, minute, second
-                    )
-                }
-                "MYSQL_TYPE_TIMESTAMP2" => {
-                    let timestamp = u32::from_be_bytes(buffer[offset..offset + 4].try_into()?);
+/// value_options.max_value_length是`--max-value-length`，超过这个长度的VARCHAR和BLOB只保留前面的部分，见truncate_binary
+/// value_options.raw_temporals是`--raw-temporals`，时间类型和NEWDECIMAL保留各个部分，见RawTemporal
+/// 类型、长度和精度都取自table map中预先计算的column_descriptors
+pub fn parse_column_data_for_row_event(
+    buffer: &[u8],
+    table_info: &EventBodyTypeCode19,
+    null_bitmap: &Bitmap,
+    value_options: ValueOptions,
+) -> Result<(Vec<ColumnValue>, usize), BoxedError> {
+    let mut offset = 0;
 
-                    let datetime_utc = DateTime::from_timestamp(timestamp as i64, 0).unwrap();
+    let descriptors = table_info.descriptors();
 
-                    let datetime_timezone =
-                        datetime_utc.with_timezone(&FixedOffset::east_opt(8 * 3600).unwrap());
+    let mut column_data_vec = Vec::with_capacity(null_bitmap.len());
 
-                    datetime_timezone.format("%Y-%m-%d %H:%M:%S").to_string()
-                }
-                "MYSQL_TYPE_BLOB" => {
-                    let blob_length_byte_n =
-                        u8::from_le(metadata_block_data_raw.unwrap().clone()[0].try_into()?);
+    for (i, is_null) in null_bitmap.iter().enumerate() {
+        if is_null {
+            column_data_vec.push(ColumnValue::Null);
+            continue;
+        }
 
-                    // println!("buffer is {:?}", blob_length_byte_n);
+        // 表结构和行镜像不一致时可能超出范围，返回错误而不是panic
+        let descriptor = descriptors.get(i).ok_or_else(|| {
+            MyError(format!(
+                "the row image has column #{} but the table map has {} columns",
+                i + 1,
+                descriptors.len()
+            ))
+        })?;
+        let truncated = || MyError(format!("the row image is truncated at column #{}", i + 1));
+        let field_start = offset;
+
+        // 变长类型先读出数据的长度
+        let field_length = match descriptor.length {
+            ColumnLength::Fixed(length) => length,
+            ColumnLength::Prefixed(n) => {
+                let mut data = [0u8; 4];
+                data[..n].copy_from_slice(buffer.get(offset..offset + n).ok_or_else(truncated)?);
+                offset += n;
+
+                u32::from_le_bytes(data) as usize
+            }
+            ColumnLength::Unknown => 0,
+        };
 
-                    let blob_length = match blob_length_byte_n {
-                        1 => {
-                            let result = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
+        let field = buffer
+            .get(offset..offset + field_length)
+            .ok_or_else(truncated)?;
+        trace::field_with(
+            || format!("col_{}", i + 1),
+            field_start,
+            offset + field_length,
+        );
 
-                            offset += 1;
+        let data = match descriptor.kind {
+            // 只有binlog_row_metadata=FULL时才知道是不是unsigned，否则按signed解码
+            ColumnKind::Tiny
+            | ColumnKind::Short
+            | ColumnKind::Int24
+            | ColumnKind::Long
+            | ColumnKind::LongLong => parse_integer(field, descriptor.unsigned == Some(true)),
+            ColumnKind::Float => ColumnValue::Float(f32::from_le_bytes(field.try_into()?)),
+            ColumnKind::Double => ColumnValue::Double(f64::from_le_bytes(field.try_into()?)),
+            ColumnKind::NewDecimal => {
+                // decimal解码时会修改缓冲区，只复制这个字段的字节
+                let (numberic_string, _) = bin_to_decimal(
+                    &mut field.to_vec(),
+                    descriptor.precision as usize,
+                    descriptor.decimals as usize,
+                )?;
+                match value_options.raw_temporals {
+                    true => ColumnValue::RawDecimal(RawDecimal {
+                        value: numberic_string,
+                        precision: descriptor.precision,
+                        scale: descriptor.decimals,
+                    }),
+                    false => ColumnValue::Decimal(numberic_string),
+                }
+            }
+            ColumnKind::Varchar | ColumnKind::Blob => {
+                convert_binary_with_limit(field, value_options.max_value_length)
+            }
+            ColumnKind::Json => ColumnValue::String(parse_json_binary(field)?.to_string()),
+            ColumnKind::Date
+            | ColumnKind::Time2
+            | ColumnKind::DateTime2
+            | ColumnKind::Timestamp2
+                if value_options.raw_temporals =>
+            {
+                ColumnValue::RawTemporal(parse_raw_temporal(descriptor.kind, field))
+            }
+            ColumnKind::Date => {
+                let bits = field.view_bits::<Lsb0>().to_bitvec();
 
-                            result as usize
-                        }
-                        2 => {
-                            let result = u16::from_le_bytes(buffer[offset..offset + 2].try_into()?);
+                let day = bits.get(0..5).unwrap().to_owned().load_le::<u8>();
 
-                            offset += 2;
+                let month = bits.get(5..9).unwrap().to_owned().load_le::<u8>();
 
-                            result as usize
-                        }
-                        3 => {
-                            let mut data = buffer[offset..offset + 3].to_vec();
-                            // data.splice(0..0, [0]);
-                            data.push(0);
+                let year = bits.get(9..).unwrap().to_owned().load_le::<u16>();
 
-                            let result = u32::from_le_bytes(data[..].try_into()?);
+                ColumnValue::Temporal(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+            // 负数和秒的小数部分的借位见parse_raw_temporal，小数部分目前不输出
+            ColumnKind::Time2 => match parse_raw_temporal(descriptor.kind, field) {
+                RawTemporal::Time {
+                    negative,
+                    hour,
+                    minute,
+                    second,
+                    ..
+                } => ColumnValue::Temporal(
+                    RawTemporal::Time {
+                        negative,
+                        hour,
+                        minute,
+                        second,
+                        micros: 0,
+                    }
+                    .to_string(),
+                ),
+                others => ColumnValue::Temporal(others.to_string()),
+            },
+            ColumnKind::DateTime2 => {
+                let bits = field[..5].view_bits::<Msb0>().to_bitvec();
+
+                let val = bits.load_be::<u64>() - 0x8000000000;
+
+                let date_val = val >> 17;
+                let time_val = val % (1 << 17);
+
+                let day = date_val % (1 << 5);
+                let month = (date_val >> 5) % 13;
+                let year = (date_val >> 5) / 13;
+                let second = time_val % (1 << 6);
+                let minute = (time_val >> 6) % (1 << 6);
+                let hour = (time_val >> 12) % (1 << 12);
+
+                ColumnValue::Temporal(format!(
+                    "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    year, month, day, hour, minute, second
+                ))
+            }
+            // binlog中是UTC的秒数，没有写入时会话的时区，按UTC显示
+            ColumnKind::Timestamp2 => {
+                let timestamp = u32::from_be_bytes(field[..4].try_into()?);
 
-                            offset += 3;
+                let datetime_utc = DateTime::from_timestamp(timestamp as i64, 0)
+                    .ok_or_else(|| MyError(format!("invalid timestamp {}", timestamp)))?;
 
-                            result as usize
-                        }
-                        4 => {
-                            let result = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+                ColumnValue::Temporal(datetime_utc.format("%Y-%m-%d %H:%M:%S").to_string())
+            }
+            ColumnKind::Unsupported => {
+                ColumnValue::Unsupported(table_info.columns[i].type_name().to_string())
+            }
+        };
 
-                            offset += 4;
+        offset += field_length;
 
-                            result as usize
-                        }
-                        _others => panic!("blob length by byte is only in range [1,4]"),
-                    };
+        column_data_vec.push(data);
+    }
 
-                    let result = try_convert_binary_to_string(&buffer[offset..offset + blob_length]);
+    Ok((column_data_vec, offset))
+}
 
-                    offset += blob_length;
+/// `--raw-temporals`时取出时间类型的各个部分，field包括后面秒的小数部分
+/// 参考 https://github.com/mysql/mysql-server/blob/8.0/mysys/my_time.cc 中的my_*_packed_from_binary
+pub fn parse_raw_temporal(kind: ColumnKind, field: &[u8]) -> RawTemporal {
+    let be = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, v| (acc << 8) | *v as u64);
+
+    // DATETIME2和TIMESTAMP2的小数部分是无符号的，按字节数补齐到微秒
+    let micros = |fraction: &[u8]| match fraction.len() {
+        0 => 0,
+        1 => be(fraction) as u32 * 10000,
+        2 => be(fraction) as u32 * 100,
+        _ => be(&fraction[..3]) as u32,
+    };
 
-                    result
+    match kind {
+        ColumnKind::Time2 => {
+            let mut int_part = be(&field[..3]) as i64 - 0x800000;
+            // 负数的小数部分按相反的顺序保存，需要向整数部分借位
+            let packed = match field.len() - 3 {
+                0 => int_part << 24,
+                1 => {
+                    let mut fraction = field[3] as i64;
+                    if int_part < 0 && fraction != 0 {
+                        int_part += 1;
+                        fraction -= 0x100;
+                    }
+                    (int_part << 24) + fraction * 10000
                 }
-                others => format!("type `{}` is not implement", others),
+                2 => {
+                    let mut fraction = be(&field[3..5]) as i64;
+                    if int_part < 0 && fraction != 0 {
+                        int_part += 1;
+                        fraction -= 0x10000;
+                    }
+                    (int_part << 24) + fraction * 100
+                }
+                _ => be(&field[..6]) as i64 - 0x800000000000,
             };
 
-            column_data_vec.push(data);
+            let value = packed.unsigned_abs();
+            let hms = value >> 24;
+            RawTemporal::Time {
+                negative: packed < 0,
+                hour: ((hms >> 12) % (1 << 10)) as u16,
+                minute: ((hms >> 6) % (1 << 6)) as u8,
+                second: (hms % (1 << 6)) as u8,
+                micros: (value % (1 << 24)) as u32,
+            }
+        }
+        ColumnKind::DateTime2 => {
+            let int_part = be(&field[..5]) - 0x8000000000;
+            let ymd = int_part >> 17;
+            let hms = int_part % (1 << 17);
+            RawTemporal::DateTime {
+                year: ((ymd >> 5) / 13) as u16,
+                month: ((ymd >> 5) % 13) as u8,
+                day: (ymd % (1 << 5)) as u8,
+                hour: (hms >> 12) as u8,
+                minute: ((hms >> 6) % (1 << 6)) as u8,
+                second: (hms % (1 << 6)) as u8,
+                micros: micros(&field[5..]),
+            }
+        }
+        ColumnKind::Timestamp2 => RawTemporal::Timestamp {
+            epoch: be(&field[..4]) as u32,
+            micros: micros(&field[4..]),
+        },
+        // DATE是3个字节的小端整数
+        _ => {
+            let value = u32::from_le_bytes([field[0], field[1], field[2], 0]);
+            RawTemporal::Date {
+                year: (value >> 9) as u16,
+                month: ((value >> 5) % (1 << 4)) as u8,
+                day: (value % (1 << 5)) as u8,
+            }
         }
     }
-
-    Ok((column_data_vec, offset))
 }
 
 // https://dev.mysql.com/doc/dev/mysql-server/latest/classmysql_1_1binlog_1_1event_1_1Query__event.html#aff85b464cf52841608d74a5568a5c0f1
-pub fn parse_status_variables(buffer: &Vec<u8>) -> Result<Vec<String>, BoxedError> {
-    let length = buffer.len();
+pub fn parse_status_variables(buffer: &[u8]) -> Result<Vec<String>, BoxedError> {
+    Ok(decode_status_variables(buffer)?
+        .iter()
+        .map(|v| v.to_string())
+        .collect())
+}
 
+/// 按出现的顺序解析所有的状态变量，遇到不认识的代码时把之后的字节都放在Unknown中
+pub fn decode_status_variables(buffer: &[u8]) -> Result<Vec<StatusVariable>, BoxedError> {
     let mut offset = 0;
 
     let mut results = Vec::new();
 
-    loop {
-        let code_id = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
-
+    while offset < buffer.len() {
+        let code_id = buffer[offset];
         offset += 1;
 
-        let result = match code_id {
-            0 => parse_status_variables_q_flag32_code(&buffer[offset..])?,
-            1 => parse_status_variables_q_sql_mode_code(&buffer[offset..])?,
-            3 => parse_status_variables_q_auto_increment(&buffer[offset..])?,
-            4 => parse_status_variables_q_charset_code(&buffer[offset..])?,
-            5 => parse_status_variables_q_timezone_code(&buffer[offset..])?,
-            6 => parse_status_variables_q_catalog_nz_code(&buffer[offset..])?,
-            7 => parse_status_variables_q_lc_time_names_code(&buffer[offset..])?,
-            8 => parse_status_variables_q_charset_database_code(&buffer[offset..])?,
-            9 => parse_status_variables_q_table_map_for_update_code(&buffer[offset..])?,
-            11 => parse_status_variables_q_invoker(&buffer[offset..])?,
-            128 => parse_status_variables_q_hrnow(&buffer[offset..])?,
-            129 => parse_status_variables_q_xid(&buffer[offset..])?,
-            others => {
-                panic!(
-                    "we found some unhandled status variables code is `{}`",
-                    others
-                );
-            }
-        };
-
-        offset += result.1;
-        results.push(result.0);
+        let (status_variable, skip) = parse_status_variable(code_id, &buffer[offset..])?;
 
-        if offset >= length {
-            break;
-        }
+        offset += skip;
+        results.push(status_variable);
     }
 
     Ok(results)
 }
 
-fn parse_status_variables_q_flag32_code(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let bitmap = vec![
-        (0x00004000, "OPTION_AUTO_IS_NULL"),
-        (0x00080000, "OPTION_NOT_AUTOCOMMIT"),
-        (0x04000000, "OPTION_NO_FOREIGN_KEY_CHECKS"),
-        (0x08000000, "OPTION_RELAXED_UNIQUE_CHECKS"),
-    ];
-
-    let data = u32::from_le_bytes(buffer[0..4].try_into()?);
-
-    let mut middle_result = Vec::new();
-    for map in bitmap {
-        if map.0 & data > 0 {
-            middle_result.push(map.1.to_string());
-        }
-    }
-
-    let result = format!("FLAGS2 is [{}]", middle_result.join(" | "));
-
-    Ok((result, 4))
+/// 状态变量的代码，按出现的顺序
+pub fn get_status_variable_codes(buffer: &[u8]) -> Result<Vec<u8>, BoxedError> {
+    Ok(decode_status_variables(buffer)?
+        .iter()
+        .map(|v| v.code())
+        .collect())
 }
 
-fn parse_status_variables_q_sql_mode_code(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let bitmap = vec![
-        (0x00000001, "MODE_REAL_AS_FLOAT"),
-        (0x00000002, "MODE_PIPES_AS_CONCAT"),
-        (0x00000004, "MODE_ANSI_QUOTES"),
-        (0x00000008, "MODE_IGNORE_SPACE"),
-        (0x00000010, "MODE_NOT_USED"),
-        (0x00000020, "MODE_ONLY_FULL_GROUP_BY"),
-        (0x00000040, "MODE_NO_UNSIGNED_SUBTRACTION"),
-        (0x00000080, "MODE_NO_DIR_IN_CREATE"),
-        (0x00000100, "MODE_POSTGRESQL"),
-        (0x00000200, "MODE_ORACLE"),
-        (0x00000400, "MODE_MSSQL"),
-        (0x00000800, "MODE_DB2"),
-        (0x00001000, "MODE_MAXDB"),
-        (0x00002000, "MODE_NO_KEY_OPTIONS"),
-        (0x00004000, "MODE_NO_TABLE_OPTIONS"),
-        (0x00008000, "MODE_NO_FIELD_OPTIONS"),
-        (0x00010000, "MODE_MYSQL323"),
-        (0x00020000, "MODE_MYSQL40"),
-        (0x00040000, "MODE_ANSI"),
-        (0x00080000, "MODE_NO_AUTO_VALUE_ON_ZERO"),
-        (0x00100000, "MODE_NO_BACKSLASH_ESCAPES"),
-        (0x00200000, "MODE_STRICT_TRANS_TABLES"),
-        (0x00400000, "MODE_STRICT_ALL_TABLES"),
-        (0x00800000, "MODE_NO_ZERO_IN_DATE"),
-        (0x01000000, "MODE_NO_ZERO_DATE"),
-        (0x02000000, "MODE_INVALID_DATES"),
-        (0x04000000, "MODE_ERROR_FOR_DIVISION_BY_ZERO"),
-        (0x08000000, "MODE_TRADITIONAL"),
-        (0x10000000, "MODE_NO_AUTO_CREATE_USER"),
-        (0x20000000, "MODE_HIGH_NOT_PRECEDENCE"),
-        (0x40000000, "MODE_NO_ENGINE_SUBSTITUTION"),
-        (0x80000000, "MODE_PAD_CHAR_TO_FULL_LENGTH"),
-    ];
+/// 只取出sql_mode，没有时返回None
+pub fn get_status_variable_sql_mode(buffer: &[u8]) -> Result<Option<u64>, BoxedError> {
+    Ok(decode_status_variables(buffer)?
+        .into_iter()
+        .find_map(|v| match v {
+            StatusVariable::SqlMode(sql_mode) => Some(sql_mode),
+            _ => None,
+        }))
+}
 
-    let data = u64::from_le_bytes(buffer[0..8].try_into()?);
+/// Q_HRNOW中的微秒，没有时返回None
+pub fn get_status_variable_hrnow(status_variables: &[StatusVariable]) -> Option<u32> {
+    status_variables.iter().find_map(|v| match v {
+        StatusVariable::MariadbHrNow(hrnow) => Some(*hrnow),
+        _ => None,
+    })
+}
 
-    let mut middle_result = Vec::new();
-    for map in bitmap {
-        if map.0 & data > 0 {
-            middle_result.push(map.1.to_string());
+fn parse_status_variable(
+    code_id: u8,
+    buffer: &[u8],
+) -> Result<(StatusVariable, usize), BoxedError> {
+    // 长度不够时返回错误而不是panic
+    let bytes = |start: usize, length: usize| read_slice(buffer, start, length, "status variable");
+
+    let result = match code_id {
+        0 => (
+            StatusVariable::Flags2(u32::from_le_bytes(bytes(0, 4)?.try_into()?)),
+            4,
+        ),
+        1 => (
+            StatusVariable::SqlMode(u64::from_le_bytes(bytes(0, 8)?.try_into()?)),
+            8,
+        ),
+        3 => (
+            StatusVariable::AutoIncrement {
+                increment: u16::from_le_bytes(bytes(0, 2)?.try_into()?),
+                offset: u16::from_le_bytes(bytes(2, 2)?.try_into()?),
+            },
+            4,
+        ),
+        4 => (
+            StatusVariable::Charset {
+                client: u16::from_le_bytes(bytes(0, 2)?.try_into()?),
+                connection: u16::from_le_bytes(bytes(2, 2)?.try_into()?),
+                server: u16::from_le_bytes(bytes(4, 2)?.try_into()?),
+            },
+            6,
+        ),
+        5 => {
+            let (time_zone, skip) = parse_status_variable_string(buffer)?;
+            (StatusVariable::TimeZone(time_zone), skip)
         }
-    }
-
-    let result = format!("SQL_MODE is [{}]", middle_result.join(" | "));
+        6 => {
+            let (catalog_name, skip) = parse_status_variable_string(buffer)?;
+            (StatusVariable::Catalog(catalog_name), skip)
+        }
+        7 => {
+            let code = u16::from_le_bytes(bytes(0, 2)?.try_into()?);
+            let locale = crate::locale::lookup(code as u64);
+            (StatusVariable::LcTimeNames { code, locale }, 2)
+        }
+        8 => (
+            StatusVariable::CharsetDatabase(u16::from_le_bytes(bytes(0, 2)?.try_into()?)),
+            2,
+        ),
+        9 => (
+            StatusVariable::TableMapForUpdate(u64::from_le_bytes(bytes(0, 8)?.try_into()?)),
+            8,
+        ),
+        11 => {
+            let (user, user_skip) = parse_status_variable_string(buffer)?;
+            let (host, host_skip) = parse_status_variable_string(&buffer[user_skip..])?;
+            (
+                StatusVariable::Invoker { user, host },
+                user_skip + host_skip,
+            )
+        }
+        // 12到20只有MySQL会写
+        12 => parse_status_variables_q_updated_db_names(buffer)?,
+        13 => (StatusVariable::Microseconds(read_u24(buffer)?), 3),
+        16 => (
+            StatusVariable::ExplicitDefaultsForTimestamp(read_u8(buffer)?),
+            1,
+        ),
+        17 => (
+            StatusVariable::DdlLoggedWithXid(u64::from_le_bytes(bytes(0, 8)?.try_into()?)),
+            8,
+        ),
+        18 => (
+            StatusVariable::DefaultCollationForUtf8mb4(u16::from_le_bytes(
+                bytes(0, 2)?.try_into()?,
+            )),
+            2,
+        ),
+        19 => (StatusVariable::SqlRequirePrimaryKey(read_u8(buffer)?), 1),
+        20 => (StatusVariable::DefaultTableEncryption(read_u8(buffer)?), 1),
+        128 => (StatusVariable::MariadbHrNow(read_u24(buffer)?), 3),
+        129 => (
+            StatusVariable::Xid(u64::from_le_bytes(bytes(0, 8)?.try_into()?)),
+            8,
+        ),
+        // 不知道长度，之后的状态变量也无法分开
+        code => (
+            StatusVariable::Unknown {
+                code,
+                raw: buffer.to_vec(),
+            },
+            buffer.len(),
+        ),
+    };
 
-    Ok((result, 8))
+    Ok(result)
 }
 
-fn parse_status_variables_q_catalog_nz_code(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let length = u8::from_le_bytes(buffer[0..1].try_into()?);
-
-    let catalog_name = String::from_utf8(buffer[1..1 + length as usize].try_into()?)?;
+// 1字节的长度加上内容
+fn parse_status_variable_string(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
+    let length = read_u8(buffer)? as usize;
 
-    let result = format!("catalog name is {}", catalog_name);
+    let value = String::from_utf8(
+        buffer
+            .get(1..1 + length)
+            .ok_or_else(|| MyError("the status variable is truncated".to_string()))?
+            .to_vec(),
+    )?;
 
-    Ok((result, length as usize + 1))
+    Ok((value, length + 1))
 }
 
-fn parse_status_variables_q_auto_increment(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let increment = u16::from_le_bytes(buffer[0..2].try_into()?);
+fn read_u8(buffer: &[u8]) -> Result<u8, BoxedError> {
+    Ok(read_slice(buffer, 0, 1, "status variable")?[0])
+}
 
-    let offset = u16::from_le_bytes(buffer[2..4].try_into()?);
+fn read_u24(buffer: &[u8]) -> Result<u32, BoxedError> {
+    let mut data = [0u8; 4];
+    data[..3].copy_from_slice(read_slice(buffer, 0, 3, "status variable")?);
 
-    let result = format!(
-        "auto_increment increment is {}, auto increment offset is {}",
-        increment, offset
-    );
+    Ok(u32::from_le_bytes(data))
+}
 
-    Ok((result, 4))
+const FLAGS2_NAMES: [(u32, &str); 4] = [
+    (0x00004000, "OPTION_AUTO_IS_NULL"),
+    (0x00080000, "OPTION_NOT_AUTOCOMMIT"),
+    (0x04000000, "OPTION_NO_FOREIGN_KEY_CHECKS"),
+    (0x08000000, "OPTION_RELAXED_UNIQUE_CHECKS"),
+];
+
+const SQL_MODE_NAMES: [(u64, &str); 32] = [
+    (0x00000001, "MODE_REAL_AS_FLOAT"),
+    (0x00000002, "MODE_PIPES_AS_CONCAT"),
+    (0x00000004, "MODE_ANSI_QUOTES"),
+    (0x00000008, "MODE_IGNORE_SPACE"),
+    (0x00000010, "MODE_NOT_USED"),
+    (0x00000020, "MODE_ONLY_FULL_GROUP_BY"),
+    (0x00000040, "MODE_NO_UNSIGNED_SUBTRACTION"),
+    (0x00000080, "MODE_NO_DIR_IN_CREATE"),
+    (0x00000100, "MODE_POSTGRESQL"),
+    (0x00000200, "MODE_ORACLE"),
+    (0x00000400, "MODE_MSSQL"),
+    (0x00000800, "MODE_DB2"),
+    (0x00001000, "MODE_MAXDB"),
+    (0x00002000, "MODE_NO_KEY_OPTIONS"),
+    (0x00004000, "MODE_NO_TABLE_OPTIONS"),
+    (0x00008000, "MODE_NO_FIELD_OPTIONS"),
+    (0x00010000, "MODE_MYSQL323"),
+    (0x00020000, "MODE_MYSQL40"),
+    (0x00040000, "MODE_ANSI"),
+    (0x00080000, "MODE_NO_AUTO_VALUE_ON_ZERO"),
+    (0x00100000, "MODE_NO_BACKSLASH_ESCAPES"),
+    (0x00200000, "MODE_STRICT_TRANS_TABLES"),
+    (0x00400000, "MODE_STRICT_ALL_TABLES"),
+    (0x00800000, "MODE_NO_ZERO_IN_DATE"),
+    (0x01000000, "MODE_NO_ZERO_DATE"),
+    (0x02000000, "MODE_INVALID_DATES"),
+    (0x04000000, "MODE_ERROR_FOR_DIVISION_BY_ZERO"),
+    (0x08000000, "MODE_TRADITIONAL"),
+    (0x10000000, "MODE_NO_AUTO_CREATE_USER"),
+    (0x20000000, "MODE_HIGH_NOT_PRECEDENCE"),
+    (0x40000000, "MODE_NO_ENGINE_SUBSTITUTION"),
+    (0x80000000, "MODE_PAD_CHAR_TO_FULL_LENGTH"),
+];
+
+// 按位列出名字，例如`MODE_ANSI_QUOTES | MODE_IGNORE_SPACE`
+fn flag_names<T: Copy + std::ops::BitAnd<Output = T> + PartialEq + Default>(
+    value: T,
+    names: &[(T, &str)],
+) -> String {
+    names
+        .iter()
+        .filter(|(bit, _)| *bit & value != T::default())
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>()
+        .join(" | ")
 }
 
-fn parse_status_variables_q_charset_code(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let client_character_set = u16::from_le_bytes(buffer[0..2].try_into()?);
+/// 和以前status_variables_string_vec_for_human中的字符串一样
+impl std::fmt::Display for StatusVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatusVariable::Flags2(flags) => {
+                write!(f, "FLAGS2 is [{}]", flag_names(*flags, &FLAGS2_NAMES))
+            }
+            StatusVariable::SqlMode(sql_mode) => {
+                write!(
+                    f,
+                    "SQL_MODE is [{}]",
+                    flag_names(*sql_mode, &SQL_MODE_NAMES)
+                )
+            }
+            StatusVariable::Catalog(catalog_name) => write!(f, "catalog name is {}", catalog_name),
+            StatusVariable::AutoIncrement { increment, offset } => write!(
+                f,
+                "auto_increment increment is {}, auto increment offset is {}",
+                increment, offset
+            ),
+            StatusVariable::Charset {
+                client,
+                connection,
+                server,
+            } => write!(
+                f,
+                "client character set is {}, collation connection is {}, collation server is {}",
+                collation_name(*client as u64),
+                collation_name(*connection as u64),
+                collation_name(*server as u64)
+            ),
+            StatusVariable::TimeZone(time_zone) => write!(f, "{}", time_zone),
+            StatusVariable::LcTimeNames { code, .. } => {
+                write!(f, "lc time names is {}", locale_name(*code as u64))
+            }
+            StatusVariable::CharsetDatabase(code) => {
+                write!(f, "charset database is {}", collation_name(*code as u64))
+            }
+            StatusVariable::TableMapForUpdate(map) => {
+                write!(f, "table map for update code is {:08b}", map)
+            }
+            StatusVariable::Invoker { user, host } => {
+                write!(f, "user name is {}, host name is {}", user, host)
+            }
+            StatusVariable::UpdatedDbNames(None) => {
+                write!(f, "updated db names are over the limit")
+            }
+            StatusVariable::UpdatedDbNames(Some(names)) => {
+                write!(f, "updated db names are [{}]", names.join(", "))
+            }
+            StatusVariable::Microseconds(microseconds) => {
+                write!(f, "microseconds is {}", microseconds)
+            }
+            StatusVariable::ExplicitDefaultsForTimestamp(value) => {
+                write!(f, "explicit defaults for timestamp is {}", value)
+            }
+            StatusVariable::DdlLoggedWithXid(xid) => write!(f, "ddl logged with xid {}", xid),
+            StatusVariable::DefaultCollationForUtf8mb4(collation) => {
+                write!(
+                    f,
+                    "default collation for utf8mb4 is {}",
+                    collation_name(*collation as u64)
+                )
+            }
+            StatusVariable::SqlRequirePrimaryKey(value) => {
+                write!(f, "sql require primary key is {}", value)
+            }
+            StatusVariable::DefaultTableEncryption(value) => {
+                write!(f, "default table encryption is {}", value)
+            }
+            StatusVariable::MariadbHrNow(hrnow) => write!(f, "hrnow is {}", hrnow),
+            StatusVariable::Xid(xid) => write!(f, "xid is {}", xid),
+            StatusVariable::Unknown { code, raw } => {
+                write!(
+                    f,
+                    "unknown status variable code {}, the rest is {:?}",
+                    code, raw
+                )
+            }
+        }
+    }
+}
 
-    let collation_connection = u16::from_le_bytes(buffer[2..4].try_into()?);
+// 254表示库的数量超过了限制，这时没有库名
+fn parse_status_variables_q_updated_db_names(
+    buffer: &[u8],
+) -> Result<(StatusVariable, usize), BoxedError> {
+    let count = read_u8(buffer)?;
+    let mut offset = 1;
 
-    let collation_server = u16::from_le_bytes(buffer[4..6].try_into()?);
+    if count == 254 {
+        return Ok((StatusVariable::UpdatedDbNames(None), offset));
+    }
 
-    let result = format!("client character set is {}, collation connection is {}, collation server is {}, for detail please run query `SELECT id, character_set_name, collation_name FROM information_schema.COLLATIONS;`", client_character_set, collation_connection, collation_server);
+    let mut names = Vec::new();
+    for _ in 0..count {
+        let length = buffer
+            .get(offset..)
+            .unwrap_or_default()
+            .iter()
+            .position(|v| *v == 0)
+            .ok_or_else(|| MyError("the updated db name is not terminated".to_string()))?;
+        names.push(String::from_utf8(buffer[offset..offset + length].to_vec())?);
+        offset += length + 1;
+    }
 
-    Ok((result, 6))
+    Ok((StatusVariable::UpdatedDbNames(Some(names)), offset))
 }
 
-fn parse_status_variables_q_timezone_code(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let length = u8::from_le_bytes(buffer[0..1].try_into()?);
-
-    let result = String::from_utf8(buffer[1..1 + length as usize].try_into()?)?;
+fn try_convert_binary_to_string(buffer: &[u8]) -> ColumnValue {
+    match std::str::from_utf8(buffer) {
+        Ok(s) => ColumnValue::String(s.to_string()),
+        Err(_e) => ColumnValue::Bytes(buffer.to_vec()),
+    }
+}
 
-    Ok((result, length as usize + 1))
+fn convert_binary_with_limit(buffer: &[u8], max_value_length: Option<usize>) -> ColumnValue {
+    match max_value_length {
+        Some(max_value_length) if buffer.len() > max_value_length => {
+            ColumnValue::String(truncate_binary(buffer, max_value_length))
+        }
+        _ => try_convert_binary_to_string(buffer),
+    }
 }
 
-fn parse_status_variables_q_lc_time_names_code(
-    buffer: &[u8],
-) -> Result<(String, usize), BoxedError> {
-    let data = u16::from_le_bytes(buffer[0..2].try_into()?);
+/// 只保留前max_value_length个字节，后面标出原来的长度，例如`abc... (67108864 bytes)`
+/// 文本按字符截断，不是文本的值和ColumnValue::Bytes一样用base64表示，所以截断后都是ColumnValue::String
+pub fn truncate_binary(buffer: &[u8], max_value_length: usize) -> String {
+    let prefix = &buffer[..max_value_length.min(buffer.len())];
 
-    let result = format!("lc time names code is {}", data);
+    let prefix = match std::str::from_utf8(prefix) {
+        Ok(s) => s.to_string(),
+        // 截断的位置在一个字符的中间
+        Err(e) if e.error_len().is_none() => {
+            String::from_utf8_lossy(&prefix[..e.valid_up_to()]).to_string()
+        }
+        Err(_e) => BASE64_STANDARD.encode(prefix),
+    };
 
-    Ok((result, 2))
+    format!("{}... ({} bytes)", prefix, buffer.len())
 }
 
-fn parse_status_variables_q_charset_database_code(
-    buffer: &[u8],
-) -> Result<(String, usize), BoxedError> {
-    let data = u16::from_le_bytes(buffer[0..2].try_into()?);
-
-    let result = format!("charset database code is {}", data);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::TableMapBuilder;
 
-    Ok((result, 2))
-}
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14 22:13:20");
+        assert_eq!(format_timestamp(u32::MAX), "2106-02-07 06:28:15");
+    }
 
-fn parse_status_variables_q_table_map_for_update_code(
-    buffer: &[u8],
-) -> Result<(String, usize), BoxedError> {
-    let data = u8::from_le_bytes(buffer[0..1].try_into()?);
+    #[test]
+    fn truncated_lenenc_is_an_error() {
+        assert!(parse_lenenc(&[]).is_err());
+        assert!(parse_lenenc(&[252, 1]).is_err());
+        assert!(parse_lenenc(&[254, 1, 2, 3]).is_err());
+        assert_eq!(parse_lenenc(&[252, 1, 1]).unwrap(), (257, 3));
+    }
 
-    let result = format!("table map for update code is {:08b}", data);
+    #[test]
+    fn truncated_status_variable_is_an_error() {
+        // Q_FLAGS2_CODE后面应该有4个字节
+        let error = decode_status_variables(&[0, 1, 2]).unwrap_err();
+        assert_eq!(error.to_string(), "some error occoured: the status variable is truncated");
 
-    Ok((result, 1))
-}
+        // Q_HRNOW之后截断
+        assert!(decode_status_variables(&[128, 1]).is_err());
+        assert!(decode_status_variables(&[12, 1, b'd']).is_err());
+    }
 
-fn parse_status_variables_q_invoker(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let mut offset = 0;
-    let user_name_length = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
-    offset += 1;
+    #[test]
+    fn corrupt_decimal_is_an_error() {
+        assert!(bin_to_decimal(&mut [0x80, 0, 0, 1], 2, 4).is_err());
+        assert!(bin_to_decimal(&mut [], 0, 0).is_err());
+        assert!(bin_to_decimal(&mut [0x80, 0, 0], 10, 2).is_err());
 
-    let user_name =
-        String::from_utf8(buffer[offset..offset + user_name_length as usize].try_into()?)?;
-    offset += user_name_length as usize;
+        let (value, length) = bin_to_decimal(&mut [0x80, 0, 0, 1, 50], 10, 2).unwrap();
+        assert_eq!((value.as_str(), length), ("1.50", 5));
+    }
 
-    let host_name_length = u8::from_le_bytes(buffer[offset..offset + 1].try_into()?);
-    offset += 1;
+    #[test]
+    fn decimal_values() {
+        let decimal = |bytes: &[u8], precision, decimals| {
+            bin_to_decimal(&mut bytes.to_vec(), precision, decimals)
+                .unwrap()
+                .0
+        };
 
-    let host_name =
-        String::from_utf8(buffer[offset..offset + host_name_length as usize].try_into()?)?;
+        // decimal.c中的例子
+        assert_eq!(
+            decimal(&[0x81, 0x0d, 0xfb, 0x38, 0xd2, 0x04, 0xd2], 14, 4),
+            "1234567890.1234"
+        );
+        assert_eq!(
+            decimal(&[0x7e, 0xf2, 0x04, 0xc7, 0x2d, 0xfb, 0x2d], 14, 4),
+            "-1234567890.1234"
+        );
 
-    let result = format!("user name is {}, host name is {}", user_name, host_name);
+        // 小数部分开头的0
+        assert_eq!(decimal(&[0x80, 0, 0, 1, 5], 10, 2), "1.05");
+        assert_eq!(decimal(&[0x7f, 0xff, 0xff, 0xfe, 0xfa], 10, 2), "-1.05");
+        assert_eq!(decimal(&[0x80, 50], 3, 2), "0.50");
+        assert_eq!(decimal(&[0x7f, 0xfa], 3, 2), "-0.05");
+
+        // 整数部分中间的一组补0
+        assert_eq!(decimal(&[0x81, 0, 0, 0, 1], 10, 0), "1000000001");
+        assert_eq!(
+            decimal(&[0x8c, 0, 0, 0, 0, 0, 0, 0, 0x22], 20, 0),
+            "12000000000000000034"
+        );
 
-    Ok((
-        result,
-        user_name_length as usize + 1 + host_name_length as usize + 1,
-    ))
-}
+        // 小数部分超过9位时不足9位的部分在最后
+        assert_eq!(
+            decimal(&[0x80, 0x00, 0xbc, 0x61, 0x4e, 91], 12, 11),
+            "0.01234567891"
+        );
 
-fn parse_status_variables_q_hrnow(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let mut raw_data = buffer[0..3].to_owned();
-    raw_data.splice(raw_data.len()..raw_data.len(), [0]);
+        // scale为0时没有小数点
+        assert_eq!(decimal(&[0x80, 0, 42], 5, 0), "42");
+        assert_eq!(decimal(&[0x7f, 0xff, 0xd5], 5, 0), "-42");
+        assert_eq!(decimal(&[0x80, 0, 0], 5, 0), "0");
+    }
 
-    let data = u32::from_le_bytes(raw_data[..].try_into()?);
+    // TIME2的整数部分：3个字节的大端，加上0x800000
+    fn time2(negative: bool, hour: i32, minute: i32, second: i32) -> Vec<u8> {
+        let hms = (hour << 12) | (minute << 6) | second;
+        let value = 0x800000 + if negative { -hms } else { hms };
+        value.to_be_bytes()[1..].to_vec()
+    }
 
-    let result = format!("hrnow is {}", data);
+    fn decode(table: &TableMapBuilder, image: &[u8]) -> Vec<ColumnValue> {
+        decode_with_value_options(table, image, ValueOptions::default())
+    }
 
-    Ok((result, 3))
-}
+    fn decode_with_value_options(
+        table: &TableMapBuilder,
+        image: &[u8],
+        value_options: ValueOptions,
+    ) -> Vec<ColumnValue> {
+        let table_info = table.table_info().unwrap();
+        let null_bitmap = Bitmap::repeat(false, table_info.columns.len());
+        let (values, consumed) =
+            parse_column_data_for_row_event(image, &table_info, &null_bitmap, value_options)
+                .unwrap();
+        assert_eq!(consumed, image.len());
+        values
+    }
 
-fn parse_status_variables_q_xid(buffer: &[u8]) -> Result<(String, usize), BoxedError> {
-    let data = u64::from_le_bytes(buffer[0..8].try_into()?);
+    #[test]
+    fn decode_each_column_type() {
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Tiny, &[])
+            .column(FieldType::Short, &[])
+            .column(FieldType::Long, &[])
+            .column(FieldType::LongLong, &[])
+            .column(FieldType::Float, &[4])
+            .column(FieldType::Double, &[8])
+            .column(FieldType::NewDecimal, &[10, 2])
+            .column(FieldType::Varchar, &300u16.to_le_bytes())
+            .column(FieldType::Date, &[])
+            .column(FieldType::Time2, &[0])
+            .column(FieldType::DateTime2, &[0])
+            .column(FieldType::Timestamp2, &[0])
+            .column(FieldType::Blob, &[2]);
+
+        let date = (2024u32 << 9) | (2 << 5) | 9;
+        let ymd = ((2024u64 * 13 + 1) << 5) | 2;
+        let hms = (3u64 << 12) | (4 << 6) | 5;
+        let datetime = 0x8000000000 + ((ymd << 17) | hms);
+
+        let mut image = vec![0xff];
+        image.extend((-2i16).to_le_bytes());
+        image.extend(3i32.to_le_bytes());
+        image.extend((-4i64).to_le_bytes());
+        image.extend(1.5f32.to_le_bytes());
+        image.extend(2.25f64.to_le_bytes());
+        image.extend([0x80, 0, 0, 1, 50]);
+        // 定义的长度超过255，长度占2个字节
+        image.extend([2, 0, b'h', b'i']);
+        image.extend(&date.to_le_bytes()[..3]);
+        image.extend(time2(false, 1, 2, 3));
+        image.extend(&datetime.to_be_bytes()[3..]);
+        image.extend(1700000000u32.to_be_bytes());
+        image.extend([3, 0, b'a', b'b', b'c']);
+
+        assert_eq!(
+            decode(&table, &image),
+            [
+                ColumnValue::Int(-1),
+                ColumnValue::Int(-2),
+                ColumnValue::Int(3),
+                ColumnValue::Int(-4),
+                ColumnValue::Float(1.5),
+                ColumnValue::Double(2.25),
+                ColumnValue::Decimal("1.50".to_string()),
+                ColumnValue::String("hi".to_string()),
+                ColumnValue::Temporal("2024-02-09".to_string()),
+                ColumnValue::Temporal("01:02:03".to_string()),
+                ColumnValue::Temporal("2024-01-02 03:04:05".to_string()),
+                // TIMESTAMP按UTC显示
+                ColumnValue::Temporal("2023-11-14 22:13:20".to_string()),
+                ColumnValue::String("abc".to_string()),
+            ]
+        );
+    }
 
-    let result = format!("xid is {}", data);
+    #[test]
+    fn decode_unsigned_integers() {
+        // SIGNEDNESS：前5个数值字段unsigned，最后一个signed
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Tiny, &[])
+            .column(FieldType::Short, &[])
+            .column(FieldType::Int24, &[])
+            .column(FieldType::Long, &[])
+            .column(FieldType::LongLong, &[])
+            .column(FieldType::Long, &[])
+            .optional_metadata(&[1, 1, 0b1111_1000]);
+
+        let mut image = vec![0xff];
+        image.extend(65535u16.to_le_bytes());
+        image.extend(&16777215u32.to_le_bytes()[..3]);
+        image.extend(4000000000u32.to_le_bytes());
+        image.extend(u64::MAX.to_le_bytes());
+        image.extend(4000000000u32.to_le_bytes());
+
+        let values = decode(&table, &image);
+        assert_eq!(
+            values,
+            [
+                ColumnValue::Int(255),
+                ColumnValue::Int(65535),
+                ColumnValue::Int(16777215),
+                ColumnValue::Int(4000000000),
+                ColumnValue::UInt(u64::MAX),
+                ColumnValue::Int(-294967296),
+            ]
+        );
+        assert_eq!(values[4].to_json(), serde_json::json!(u64::MAX));
+        assert_eq!(values[4].to_string(), "18446744073709551615");
+
+        // 没有SIGNEDNESS时按signed解码，MEDIUMINT扩展符号
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Int24, &[])
+            .column(FieldType::Int24, &[])
+            .column(FieldType::LongLong, &[]);
+        let mut image = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        image.extend(u64::MAX.to_le_bytes());
+        assert_eq!(
+            decode(&table, &image),
+            [
+                ColumnValue::Int(-1),
+                ColumnValue::Int(8388607),
+                ColumnValue::Int(-1)
+            ]
+        );
+    }
 
-    Ok((result, 8))
-}
+    #[test]
+    fn decode_negative_time2() {
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Time2, &[0])
+            .column(FieldType::Time2, &[0])
+            .column(FieldType::Time2, &[1]);
+
+        let mut image = time2(true, 838, 59, 59);
+        image.extend(time2(false, 0, 0, 0));
+        // -00:00:01.5，整数部分是-2，小数部分是-50的补码
+        image.extend([0x7f, 0xff, 0xfe, 0xce]);
+
+        assert_eq!(
+            decode(&table, &image),
+            [
+                ColumnValue::Temporal("-838:59:59".to_string()),
+                ColumnValue::Temporal("00:00:00".to_string()),
+                ColumnValue::Temporal("-00:00:01".to_string()),
+            ]
+        );
+    }
 
-fn try_convert_binary_to_string(buffer: &[u8]) -> String {
-    let try_to_convert_to_string =
-        String::from_utf8(buffer[..].try_into().unwrap());
+    #[test]
+    fn raw_temporals_of_the_same_row() {
+        let table = TableMapBuilder::new("db", "t")
+            .column(FieldType::Date, &[])
+            .column(FieldType::Time2, &[0])
+            .column(FieldType::DateTime2, &[6])
+            .column(FieldType::Timestamp2, &[3])
+            .column(FieldType::NewDecimal, &[10, 2]);
+
+        let date = (2024u32 << 9) | (2 << 5) | 9;
+        let ymd = ((2024u64 * 13 + 1) << 5) | 2;
+        let hms = (3u64 << 12) | (4 << 6) | 5;
+        let datetime = 0x8000000000 + ((ymd << 17) | hms);
+
+        let mut image = date.to_le_bytes()[..3].to_vec();
+        image.extend(time2(true, 1, 2, 3));
+        // 6位小数占3个字节，3位小数占2个字节
+        image.extend(&datetime.to_be_bytes()[3..]);
+        image.extend(&123456u32.to_be_bytes()[1..]);
+        image.extend(1700000000u32.to_be_bytes());
+        image.extend(1234u16.to_be_bytes());
+        image.extend([0x80, 0, 0, 1, 50]);
+
+        assert_eq!(
+            decode(&table, &image),
+            [
+                ColumnValue::Temporal("2024-02-09".to_string()),
+                ColumnValue::Temporal("-01:02:03".to_string()),
+                ColumnValue::Temporal("2024-01-02 03:04:05".to_string()),
+                ColumnValue::Temporal("2023-11-14 22:13:20".to_string()),
+                ColumnValue::Decimal("1.50".to_string()),
+            ]
+        );
 
-    let result = match try_to_convert_to_string {
-        Ok(s) => format!("this is a String, value is `{}`", s),
-        Err(_e) => format!(
-            "this is not a String, value with base64 is {}",
-            BASE64_STANDARD.encode(buffer)
-        ),
-    };
+        let value_options = ValueOptions {
+            raw_temporals: true,
+            ..Default::default()
+        };
+        let values = decode_with_value_options(&table, &image, value_options);
+        assert_eq!(
+            values,
+            [
+                ColumnValue::RawTemporal(RawTemporal::Date {
+                    year: 2024,
+                    month: 2,
+                    day: 9
+                }),
+                ColumnValue::RawTemporal(RawTemporal::Time {
+                    negative: true,
+                    hour: 1,
+                    minute: 2,
+                    second: 3,
+                    micros: 0
+                }),
+                ColumnValue::RawTemporal(RawTemporal::DateTime {
+                    year: 2024,
+                    month: 1,
+                    day: 2,
+                    hour: 3,
+                    minute: 4,
+                    second: 5,
+                    micros: 123456
+                }),
+                ColumnValue::RawTemporal(RawTemporal::Timestamp {
+                    epoch: 1700000000,
+                    micros: 123400
+                }),
+                ColumnValue::RawDecimal(RawDecimal {
+                    value: "1.50".to_string(),
+                    precision: 10,
+                    scale: 2
+                }),
+            ]
+        );
 
-    result
+        // JSON中是各个部分，Display中保留微秒
+        assert_eq!(
+            values.iter().map(|v| v.to_json()).collect::<Vec<_>>(),
+            [
+                serde_json::json!({"year": 2024, "month": 2, "day": 9}),
+                serde_json::json!({"negative": true, "hour": 1, "minute": 2, "second": 3, "micros": 0}),
+                serde_json::json!({
+                    "year": 2024,
+                    "month": 1,
+                    "day": 2,
+                    "hour": 3,
+                    "minute": 4,
+                    "second": 5,
+                    "micros": 123456
+                }),
+                serde_json::json!({"epoch": 1700000000, "micros": 123400}),
+                serde_json::json!({"value": "1.50", "precision": 10, "scale": 2}),
+            ]
+        );
+        assert_eq!(
+            values.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            [
+                "2024-02-09",
+                "-01:02:03",
+                "2024-01-02 03:04:05.123456",
+                "1700000000.123400",
+                "1.50"
+            ]
+        );
+    }
 }
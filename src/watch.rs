@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::diagnostic::{ignore_diagnostic, Diagnostic};
+use crate::model::MyError;
+use crate::service::get_event_header;
+use crate::util::{check_file_magic_number, get_file};
+
+type BoxedError = Box<dyn std::error::Error>;
+
+// 没有文件系统通知时也定期检查一次，用来判断文件是否已经不再变化
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// 最后一个事件不是ROTATE或者STOP时，文件大小这么久没有变化才认为已经复制完成
+const STABLE_FOR: Duration = Duration::from_secs(5);
+
+// 下一个序号的文件迟迟没有出现时，等待这么久之后跳过它继续处理后面的文件；
+// 不以完整的事件结束的文件大小这么久没有变化时，也不再等它变完整
+const GAP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 一个候选文件的状态，每次检查时从上次的位置继续读取header
+#[derive(Debug)]
+struct FileState {
+    size: u64,
+    // 大小变化的时间
+    changed_at: Instant,
+    scan: EventScan,
+}
+
+/// 已经读过的完整的事件
+#[derive(Debug, Default, PartialEq)]
+struct EventScan {
+    // 下一个事件的位置，0表示还不能确定是binlog
+    offset: u64,
+    last_type_code: Option<u8>,
+    // 不是binlog，或者header中的长度损坏，之后的事件无法找到
+    corrupt: bool,
+}
+
+impl EventScan {
+    /// 读取offset之后到file_length之间完整的事件，返回是否正好在file_length结束
+    fn advance(&mut self, file: &mut File, file_length: u64) -> Result<bool, BoxedError> {
+        if self.offset == 0 {
+            if file_length < 4 {
+                return Ok(false);
+            }
+
+            self.corrupt = !check_file_magic_number(file)?;
+            if self.corrupt {
+                return Ok(false);
+            }
+            self.offset = 4;
+        }
+
+        while !self.corrupt && self.offset + 19 <= file_length {
+            let header = get_event_header(file, self.offset)?;
+            if header.event_length < 19 {
+                self.corrupt = true;
+                break;
+            }
+
+            let next_offset = self.offset + header.event_length as u64;
+            if next_offset > file_length {
+                break;
+            }
+
+            self.offset = next_offset;
+            self.last_type_code = Some(header.type_code);
+        }
+
+        Ok(!self.corrupt && self.offset == file_length)
+    }
+}
+
+/// 监视一个目录，按binlog的序号依次返回已经复制完成的文件，没有新文件时会一直阻塞
+/// 复制到一半的文件会等它完成后再返回，不会当作损坏的文件
+pub struct BinlogWatcher {
+    dir: PathBuf,
+    receiver: Receiver<notify::Result<notify::Event>>,
+    // 需要一直持有，drop之后就不再有通知
+    _watcher: RecommendedWatcher,
+    // 下一个文件的序号必须大于它
+    last_sequence: Option<u64>,
+    // 从checkpoint继续时，序号比它小的文件已经处理过了
+    start_sequence: Option<u64>,
+    files: HashMap<PathBuf, FileState>,
+    gap_since: Option<Instant>,
+    // 等待时也要及时提示跳过的文件，所以不等调用方来取
+    diagnostics: Box<dyn FnMut(Diagnostic)>,
+}
+
+impl BinlogWatcher {
+    /// start_file_name是checkpoint中的文件名，从这个文件开始处理
+    pub fn new(dir: &str, start_file_name: Option<&str>) -> Result<BinlogWatcher, BoxedError> {
+        let (sender, receiver) = channel();
+
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(Path::new(dir), RecursiveMode::NonRecursive)?;
+
+        Ok(BinlogWatcher {
+            dir: PathBuf::from(dir),
+            receiver,
+            _watcher: watcher,
+            last_sequence: None,
+            start_sequence: start_file_name.and_then(binlog_sequence),
+            files: HashMap::new(),
+            gap_since: None,
+            diagnostics: Box::new(ignore_diagnostic),
+        })
+    }
+
+    /// 跳过缺少的序号、放弃一直不完整的文件时的提示，默认忽略
+    pub fn on_diagnostic(mut self, diagnostics: impl FnMut(Diagnostic) + 'static) -> BinlogWatcher {
+        self.diagnostics = Box::new(diagnostics);
+        self
+    }
+
+    fn next_file(&mut self) -> Result<String, BoxedError> {
+        loop {
+            if let Some(path) = self.find_ready_file()? {
+                return Ok(path);
+            }
+
+            // 通知只是用来提前醒来，每次都重新扫描目录
+            match self.receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(event) => {
+                    event?;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Box::new(MyError(format!(
+                        "stop watching `{}`",
+                        self.dir.display()
+                    ))));
+                }
+            }
+        }
+    }
+
+    fn find_ready_file(&mut self) -> Result<Option<String>, BoxedError> {
+        let mut candidates = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let sequence = match path
+                .file_name()
+                .and_then(|v| v.to_str())
+                .and_then(binlog_sequence)
+            {
+                Some(sequence) => sequence,
+                None => continue,
+            };
+
+            if self.last_sequence.is_some_and(|v| sequence <= v)
+                || self.start_sequence.is_some_and(|v| sequence < v)
+            {
+                continue;
+            }
+
+            candidates.push((sequence, path));
+        }
+
+        // 按序号而不是修改时间排序
+        let (sequence, path) = match candidates.into_iter().min() {
+            Some(candidate) => candidate,
+            None => return Ok(None),
+        };
+
+        // 序号不连续时可能是前一个文件还没有复制过来，先等一会儿
+        if let Some(last_sequence) = self.last_sequence {
+            if sequence != last_sequence + 1 {
+                let gap_since = *self.gap_since.get_or_insert_with(Instant::now);
+                if gap_since.elapsed() < GAP_TIMEOUT {
+                    return Ok(None);
+                }
+
+                (self.diagnostics)(Diagnostic::Warning(format!(
+                    "binlog with sequence {} is missing, continue with `{}`",
+                    last_sequence + 1,
+                    path.display()
+                )));
+            }
+        }
+
+        let ready = match self.is_ready(&path)? {
+            Readiness::Waiting => return Ok(None),
+            Readiness::Ready => true,
+            Readiness::Abandoned => false,
+        };
+
+        self.files.remove(&path);
+        self.last_sequence = Some(sequence);
+        self.gap_since = None;
+
+        match ready {
+            true => Ok(Some(path.to_string_lossy().to_string())),
+            false => Ok(None),
+        }
+    }
+
+    /// 文件以完整的事件结束，并且最后一个事件是ROTATE或者STOP，或者大小已经一段时间没有变化
+    ///
+    /// 不以完整的事件结束（截断的或者损坏的）的文件大小GAP_TIMEOUT没有变化时不再等待：
+    /// 是binlog时照常处理，最后不完整的事件由解析时报告，不是binlog时跳过
+    fn is_ready(&mut self, path: &Path) -> Result<Readiness, BoxedError> {
+        let size = std::fs::metadata(path)?.len();
+
+        let state = self
+            .files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| FileState {
+                size,
+                changed_at: Instant::now(),
+                scan: EventScan::default(),
+            });
+        if state.size != size {
+            // 变小说明文件被重新复制，从头读取
+            if size < state.size {
+                state.scan = EventScan::default();
+            }
+            state.size = size;
+            state.changed_at = Instant::now();
+            return Ok(Readiness::Waiting);
+        }
+
+        // 只读取上次之后新增的事件的header
+        let mut file = get_file(&path.to_string_lossy())?;
+        let complete = state.scan.advance(&mut file, size)?;
+        let unchanged_for = state.changed_at.elapsed();
+
+        match state.scan.last_type_code {
+            Some(3 | 4) if complete => return Ok(Readiness::Ready),
+            Some(_) if complete && unchanged_for >= STABLE_FOR => return Ok(Readiness::Ready),
+            _ => {}
+        }
+
+        if unchanged_for < GAP_TIMEOUT {
+            return Ok(Readiness::Waiting);
+        }
+
+        if state.scan.offset == 0 {
+            (self.diagnostics)(Diagnostic::Warning(format!(
+                "`{}` is not a binlog and has not changed for {}s, skip it",
+                path.display(),
+                GAP_TIMEOUT.as_secs()
+            )));
+            return Ok(Readiness::Abandoned);
+        }
+
+        (self.diagnostics)(Diagnostic::Warning(format!(
+            "`{}` does not end with a complete event at {} and has not changed for {}s, process it anyway",
+            path.display(),
+            state.scan.offset,
+            GAP_TIMEOUT.as_secs()
+        )));
+        Ok(Readiness::Ready)
+    }
+}
+
+enum Readiness {
+    Waiting,
+    Ready,
+    // 等待超时之后放弃的文件，不返回，继续处理后面的文件
+    Abandoned,
+}
+
+impl Iterator for BinlogWatcher {
+    type Item = Result<String, BoxedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_file())
+    }
+}
+
+/// `mysql-bin.000012`中的12
+pub fn binlog_sequence(file_name: &str) -> Option<u64> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    if extension.is_empty() || !extension.bytes().all(|v| v.is_ascii_digit()) {
+        return None;
+    }
+
+    extension.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // 只有header和body长度的事件，校验和不检查
+    fn event(type_code: u8, body_length: usize) -> Vec<u8> {
+        let event_length = 19 + body_length as u32;
+        let mut event = vec![0u8; 4];
+        event.push(type_code);
+        event.extend(1u32.to_le_bytes());
+        event.extend(event_length.to_le_bytes());
+        event.extend(0u32.to_le_bytes());
+        event.extend(0u16.to_le_bytes());
+        event.resize(event_length as usize, 0);
+        event
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("watch-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn binlog(events: &[Vec<u8>]) -> Vec<u8> {
+        let mut binlog = vec![0xfe, 0x62, 0x69, 0x6e];
+        for event in events {
+            binlog.extend(event);
+        }
+        binlog
+    }
+
+    #[test]
+    fn binlog_sequence_from_extension() {
+        assert_eq!(binlog_sequence("mysql-bin.000012"), Some(12));
+        assert_eq!(binlog_sequence("mysql-bin.index"), None);
+        assert_eq!(binlog_sequence("mysql-bin."), None);
+    }
+
+    #[test]
+    fn scan_continues_from_last_complete_event() {
+        let dir = temp_dir("scan");
+        let path = dir.join("mysql-bin.000001");
+        let content = binlog(&[event(2, 30), event(16, 12)]);
+
+        // 第二个事件只复制了一半
+        std::fs::write(&path, &content[..content.len() - 5]).unwrap();
+        let mut scan = EventScan::default();
+        let mut file = File::open(&path).unwrap();
+        assert!(!scan.advance(&mut file, content.len() as u64 - 5).unwrap());
+        assert_eq!(scan.offset, 4 + 49);
+        assert_eq!(scan.last_type_code, Some(2));
+
+        std::fs::write(&path, &content).unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert!(scan.advance(&mut file, content.len() as u64).unwrap());
+        assert_eq!(scan.offset, content.len() as u64);
+        assert_eq!(scan.last_type_code, Some(16));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn scan_stops_at_corrupt_length_and_non_binlog() {
+        let dir = temp_dir("corrupt");
+
+        let path = dir.join("mysql-bin.000001");
+        let mut corrupt = event(2, 10);
+        corrupt[9..13].copy_from_slice(&5u32.to_le_bytes());
+        let content = binlog(&[event(2, 10), corrupt, event(4, 10)]);
+        std::fs::write(&path, &content).unwrap();
+        let mut scan = EventScan::default();
+        let mut file = File::open(&path).unwrap();
+        assert!(!scan.advance(&mut file, content.len() as u64).unwrap());
+        assert!(scan.corrupt);
+        assert_eq!(scan.offset, 4 + 29);
+
+        let path = dir.join("mysql-bin.000002");
+        std::fs::write(&path, b"not a binlog file").unwrap();
+        let mut scan = EventScan::default();
+        let mut file = File::open(&path).unwrap();
+        assert!(!scan.advance(&mut file, 17).unwrap());
+        assert_eq!(scan.offset, 0);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn watcher_returns_files_in_sequence_order() {
+        let dir = temp_dir("order");
+        std::fs::write(
+            dir.join("mysql-bin.000001"),
+            binlog(&[event(2, 10), event(4, 20)]),
+        )
+        .unwrap();
+
+        let mut watcher = BinlogWatcher::new(&dir.to_string_lossy(), None).unwrap();
+        let first = watcher.next().unwrap().unwrap();
+        assert!(first.ends_with("mysql-bin.000001"));
+
+        // 之后复制过来的文件，先出现不完整的部分
+        let writer_dir = dir.clone();
+        let writer = std::thread::spawn(move || {
+            let content = binlog(&[event(2, 10), event(4, 20)]);
+            let mut file = File::create(writer_dir.join("mysql-bin.000002")).unwrap();
+            file.write_all(&content[..30]).unwrap();
+            file.flush().unwrap();
+            std::thread::sleep(Duration::from_millis(300));
+            file.write_all(&content[30..]).unwrap();
+        });
+
+        let second = watcher.next().unwrap().unwrap();
+        assert!(second.ends_with("mysql-bin.000002"));
+        assert_eq!(
+            std::fs::metadata(&second).unwrap().len(),
+            binlog(&[event(2, 10), event(4, 20)]).len() as u64
+        );
+
+        writer.join().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
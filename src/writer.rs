@@ -0,0 +1,692 @@
+use std::io::Write;
+
+use crate::flavor::ServerVersion;
+use crate::model::*;
+use crate::util::encode_lenenc;
+
+const EVENT_HEADER_LENGTH: usize = 19;
+
+const BINLOG_MAGIC_NUMBER: [u8; 4] = [0xfe, 0x62, 0x69, 0x6e];
+
+// GTID事件的body至少这么长，不足时补0
+const GTID_HEADER_LENGTH: usize = 19;
+
+// FDE中服务端版本固定占用的字节数
+const SERVER_VERSION_LENGTH: usize = 50;
+
+// FDE中server_version的位置，header之后是2字节的binlog版本
+const SERVER_VERSION_RANGE: std::ops::Range<usize> = 21..71;
+
+const BINLOG_CHECKSUM_ALG_CRC32: u8 = 1;
+
+const CHECKSUM_LENGTH: usize = 4;
+
+type BoxedError = Box<dyn std::error::Error>;
+
+/// 从头写一个binlog文件：magic number、FDE和之后的事件
+///
+/// 每个事件的event_length、next_event_position和最后4个字节的CRC32都按写入的位置重新计算，
+/// header中的其他字段（时间戳、server_id、flags）保持不变
+///
+/// 是否写CRC32按最近写入的FDE中的校验算法，FDE之前默认有校验和。
+/// 和服务端一样，有校验算法的版本（MariaDB 5.3、MySQL 5.6.1开始）的FDE自己总是带着校验和，
+/// 算法为NONE时只是之后的事件没有
+pub struct BinlogWriter<W: Write> {
+    writer: W,
+    position: u64,
+    checksum: bool,
+}
+
+impl<W: Write> BinlogWriter<W> {
+    /// 写入magic number，之后的第一个事件应该是FDE
+    pub fn new(mut writer: W) -> Result<BinlogWriter<W>, BoxedError> {
+        writer.write_all(&BINLOG_MAGIC_NUMBER)?;
+
+        Ok(BinlogWriter {
+            writer,
+            position: BINLOG_MAGIC_NUMBER.len() as u64,
+            checksum: true,
+        })
+    }
+
+    /// 继续写一个已有的文件，position是文件的长度，不再写入magic number
+    pub fn resume(writer: W, position: u64) -> BinlogWriter<W> {
+        BinlogWriter {
+            writer,
+            position,
+            checksum: true,
+        }
+    }
+
+    /// 写入magic number和FDE，header来自原来的FDE
+    pub fn with_format_description(
+        writer: W,
+        header: &EventHeader,
+        format_description: &EventBodyTypeCode15,
+    ) -> Result<BinlogWriter<W>, BoxedError> {
+        let mut binlog_writer = BinlogWriter::new(writer)?;
+        binlog_writer.write_event(header, format_description)?;
+
+        Ok(binlog_writer)
+    }
+
+    /// 按header.type_code序列化body，返回事件的起始位置
+    pub fn write_event(
+        &mut self,
+        header: &EventHeader,
+        body: &dyn EventBody,
+    ) -> Result<u64, BoxedError> {
+        let checksum = match body.downcast_ref::<EventBodyTypeCode15>() {
+            Some(format_description) if header.type_code == 15 => self.format_description(
+                &format_description.server_version,
+                format_description.checksum_algorithm,
+            ),
+            _ => self.checksum,
+        };
+
+        let event = serialize_event(header, body, self.position, checksum)?;
+        self.write(&event)
+    }
+
+    /// 原样写入一个完整的事件（包括header和校验和），用于没有解析body的事件。
+    /// 只有next_event_position和写入的位置不一致时才修改它并重新计算校验和，所以位置不变时和原来的字节完全一样
+    pub fn write_raw(&mut self, raw_event: &[u8]) -> Result<u64, BoxedError> {
+        let length = raw_event.len();
+        if length < EVENT_HEADER_LENGTH
+            || u32::from_le_bytes(raw_event[9..13].try_into()?) as usize != length
+        {
+            return Err(Box::new(MyError(format!(
+                "the raw event of {} bytes does not match the event length in its header",
+                length
+            ))));
+        }
+
+        let checksum = match raw_event[4] {
+            15 if length >= EVENT_HEADER_LENGTH + 5 => {
+                let server_version = raw_event
+                    .get(SERVER_VERSION_RANGE)
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                self.format_description(
+                    server_version.trim_end_matches('\0'),
+                    raw_event[length - 5],
+                )
+            }
+            _ => self.checksum,
+        };
+
+        let next_event_position = (self.position + length as u64) as u32;
+        if raw_event[13..17] == next_event_position.to_le_bytes() {
+            return self.write(raw_event);
+        }
+
+        let mut raw_event = raw_event.to_vec();
+        raw_event[13..17].copy_from_slice(&next_event_position.to_le_bytes());
+
+        if checksum && length >= EVENT_HEADER_LENGTH + CHECKSUM_LENGTH {
+            let crc = crc32fast::hash(&raw_event[..length - CHECKSUM_LENGTH]);
+            raw_event[length - CHECKSUM_LENGTH..].copy_from_slice(&crc.to_le_bytes());
+        }
+
+        self.write(&raw_event)
+    }
+
+    /// 下一个事件的位置，也就是已经写入的字节数
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn flush(&mut self) -> Result<(), BoxedError> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // 按FDE更新之后的事件是否有校验和，返回FDE自己是否有校验和
+    fn format_description(&mut self, server_version: &str, checksum_algorithm: u8) -> bool {
+        let has_checksum_algorithm = ServerVersion::parse(server_version).has_checksum_algorithm();
+        self.checksum = has_checksum_algorithm && checksum_algorithm == BINLOG_CHECKSUM_ALG_CRC32;
+
+        has_checksum_algorithm
+    }
+
+    fn write(&mut self, event: &[u8]) -> Result<u64, BoxedError> {
+        self.writer.write_all(event)?;
+
+        let position = self.position;
+        self.position += event.len() as u64;
+
+        Ok(position)
+    }
+}
+
+/// 序列化一个完整的事件，position是事件在文件中的位置，用来计算next_event_position，
+/// checksum为false时（FDE中的校验算法是NONE）最后没有CRC32
+pub fn serialize_event(
+    header: &EventHeader,
+    body: &dyn EventBody,
+    position: u64,
+    checksum: bool,
+) -> Result<Vec<u8>, BoxedError> {
+    let body = serialize_body(header.type_code, body)?;
+
+    let checksum_length = match checksum {
+        true => CHECKSUM_LENGTH,
+        false => 0,
+    };
+    let event_length = (EVENT_HEADER_LENGTH + body.len() + checksum_length) as u32;
+    let next_event_position = u32::try_from(position + event_length as u64).map_err(|_| {
+        MyError(format!(
+            "the event at {} ends beyond 4GB, which a binlog file can not have",
+            position
+        ))
+    })?;
+
+    let mut event = Vec::with_capacity(event_length as usize);
+    event.extend(header.timestamp.to_le_bytes());
+    event.push(header.type_code);
+    event.extend(header.server_id.to_le_bytes());
+    event.extend(event_length.to_le_bytes());
+    event.extend(next_event_position.to_le_bytes());
+    event.extend(header.flags.to_le_bytes());
+    event.extend(body);
+
+    if checksum {
+        let crc = crc32fast::hash(&event);
+        event.extend(crc.to_le_bytes());
+    }
+
+    Ok(event)
+}
+
+/// 事件body的字节，不包括校验和，是service中deal_type_code_*的逆过程。
+/// 各种长度字段按内容重新计算，所以修改过库名、SQL等的事件也是一致的
+pub fn serialize_body(type_code: u8, body: &dyn EventBody) -> Result<Vec<u8>, BoxedError> {
+    let mismatch = || {
+        MyError(format!(
+            "the body does not match event type {} ({})",
+            type_code,
+            crate::util::get_event_type_name(type_code)
+        ))
+    };
+
+    let mut buffer = Vec::new();
+
+    match type_code {
+        2 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode2>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(body.id_of_thread.to_le_bytes());
+            buffer.extend(body.execute_time.to_le_bytes());
+            buffer.push(u8::try_from(body.database_name.len())?);
+            buffer.extend(body.error_code.to_le_bytes());
+            buffer.extend(u16::try_from(body.status_variables.len())?.to_le_bytes());
+            buffer.extend(&body.status_variables);
+            buffer.extend(body.database_name.as_bytes());
+            buffer.push(0);
+            buffer.extend(body.sql.as_bytes());
+        }
+        4 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode4>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(
+                body.position_of_the_first_event_in_next_log_file
+                    .to_le_bytes(),
+            );
+            buffer.extend(body.file_name_of_next_binary_log.as_bytes());
+        }
+        5 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode5>()
+                .ok_or_else(mismatch)?;
+            buffer.push(body.data_type);
+            buffer.extend(body.value.to_le_bytes());
+        }
+        13 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode13>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(body.first_seed.to_le_bytes());
+            buffer.extend(body.second_seed.to_le_bytes());
+        }
+        14 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode14>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(u32::try_from(body.name_of_user_variable.len())?.to_le_bytes());
+            buffer.extend(body.name_of_user_variable.as_bytes());
+            buffer.push(body.null_indicator);
+
+            // 和deal_type_code_14一样，null_indicator为0时才有后面的部分
+            if body.null_indicator == 0 {
+                let value = body.value.as_deref().unwrap_or_default();
+                buffer.push(body.variable_type.unwrap_or_default());
+                buffer.extend(body.collation_number.unwrap_or_default().to_le_bytes());
+                buffer.extend(u32::try_from(value.len())?.to_le_bytes());
+                buffer.extend(value.as_bytes());
+                if let Some(flags) = body.flags {
+                    buffer.push(flags);
+                }
+            }
+        }
+        15 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode15>()
+                .ok_or_else(mismatch)?;
+            let server_version = body.server_version.as_bytes();
+            if server_version.len() > SERVER_VERSION_LENGTH {
+                return Err(Box::new(MyError(format!(
+                    "the server version `{}` is longer than {} bytes",
+                    body.server_version, SERVER_VERSION_LENGTH
+                ))));
+            }
+
+            buffer.extend(body.binlog_version.to_le_bytes());
+            buffer.extend(server_version);
+            buffer.resize(2 + SERVER_VERSION_LENGTH, 0);
+            buffer.extend(body.create_timestamp.to_le_bytes());
+            buffer.push(body.header_length);
+            buffer.extend(&body.post_header_lengths);
+            buffer.push(body.checksum_algorithm);
+        }
+        16 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode16>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(body.xid_transaction_number.to_le_bytes());
+        }
+        19 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode19>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(&body.table_id.to_le_bytes()[..6]);
+            buffer.extend(body.reserved_for_future_use.to_le_bytes());
+            buffer.push(u8::try_from(body.database_name.len())?);
+            buffer.extend(body.database_name.as_bytes());
+            buffer.push(0);
+            buffer.push(u8::try_from(body.table_name.len())?);
+            buffer.extend(body.table_name.as_bytes());
+            buffer.push(0);
+            buffer.extend(encode_lenenc(body.columns.len() as u64));
+            buffer.extend(body.columns.iter().map(|v| v.type_code()));
+            buffer.extend(encode_lenenc(body.metadata_block.len() as u64));
+            buffer.extend(&body.metadata_block);
+
+            // optional_metadata_block从nullable bitmap开始，bitmap按其他字段重新生成
+            let null_bitmap = body
+                .columns
+                .iter()
+                .map(|v| v.nullable)
+                .collect::<Bitmap>()
+                .to_bytes();
+            let optional_metadata = body
+                .optional_metadata_block
+                .get(null_bitmap.len()..)
+                .unwrap_or_default();
+            buffer.extend(null_bitmap);
+            buffer.extend(optional_metadata);
+        }
+        23..=25 | 30..=32 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode23To25>()
+                .ok_or_else(mismatch)?;
+
+            // 解码之后不再保留行镜像，字段的值（比如时间的小数部分）不能完整地还原
+            if body.row_images.is_empty() && body.number_of_rows > 0 {
+                return Err(Box::new(MyError(
+                    "the row images of the rows event were dropped after decoding, parse it with `parse_rows_event` or `get_event_body_without_row_values` to write it back"
+                        .to_string(),
+                )));
+            }
+
+            buffer.extend(&body.table_id.to_le_bytes()[..6]);
+            buffer.extend(body.flags.to_le_bytes());
+            // v2的extra data解析时已经去掉，写回时只有长度本身
+            if matches!(type_code, 30..=32) {
+                buffer.extend(2u16.to_le_bytes());
+            }
+            buffer.extend(encode_lenenc(body.number_of_columns));
+            buffer.extend(body.columns_used.to_bytes());
+            if matches!(type_code, 24 | 31) {
+                let columns_used_for_update = body
+                    .columns_used_for_update
+                    .as_ref()
+                    .unwrap_or(&body.columns_used);
+                buffer.extend(columns_used_for_update.to_bytes());
+            }
+            buffer.extend(&body.row_images);
+        }
+        38 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode38>()
+                .ok_or_else(mismatch)?;
+            buffer.push(body.one_phase_commit);
+            buffer.extend(body.format_id.to_le_bytes());
+            buffer.extend(body.length_of_gtrid.to_le_bytes());
+            buffer.extend(body.length_of_bqual.to_le_bytes());
+            buffer.extend(&body.xid);
+        }
+        160 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode160>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(body.sql.as_bytes());
+        }
+        161 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode161>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(u32::try_from(body.log_filename.len())?.to_le_bytes());
+            buffer.extend(body.log_filename.as_bytes());
+        }
+        162 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode162>()
+                .ok_or_else(mismatch)?;
+            buffer.extend(body.gtid_sequence.to_le_bytes());
+            buffer.extend(body.replication_domain_id.to_le_bytes());
+            buffer.push(body.flags);
+
+            if let Some(commit_id) = body.commit_id {
+                buffer.extend(commit_id.to_le_bytes());
+            }
+            if let (Some(format_id), Some(xid)) = (body.format_id, &body.xid) {
+                buffer.extend(format_id.to_le_bytes());
+                buffer.push(body.gtid_length.unwrap_or_default());
+                buffer.push(body.bqual_length.unwrap_or_default());
+                buffer.extend(xid);
+            }
+
+            if buffer.len() < GTID_HEADER_LENGTH {
+                buffer.resize(GTID_HEADER_LENGTH, 0);
+            }
+        }
+        163 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode163>()
+                .ok_or_else(mismatch)?;
+            // 高4位是flags，只有低28位是GTID的个数
+            let number_of_gtids =
+                (body.number_of_gtids & 0xf000_0000) | u32::try_from(body.gtids.len())?;
+            buffer.extend(number_of_gtids.to_le_bytes());
+
+            for gtid in &body.gtids {
+                buffer.extend(gtid.replication_domain_id.to_le_bytes());
+                buffer.extend(gtid.server_id.to_le_bytes());
+                buffer.extend(gtid.gtid_sequence.to_le_bytes());
+            }
+        }
+        164 => {
+            let body = body
+                .downcast_ref::<EventBodyTypeCode164>()
+                .ok_or_else(mismatch)?;
+            buffer.push(body.encryption_scheme);
+            buffer.extend(body.encryption_key_version.to_le_bytes());
+            buffer.extend(&body.nonce);
+        }
+        _ => {
+            return Err(Box::new(MyError(format!(
+                "event type {} ({}) is not parsed, write the original bytes with `BinlogWriter::write_raw`",
+                type_code,
+                crate::util::get_event_type_name(type_code)
+            ))));
+        }
+    }
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builders::*;
+    use crate::field_type::FieldType;
+    use crate::service::{get_event_body_without_row_values, get_event_header, CHECKSUM_LENGTH};
+
+    // 每个事件的位置、header和body，行事件保留行镜像
+    fn parse(binlog: &[u8]) -> Vec<(u64, EventHeader, Box<dyn EventBody>)> {
+        let mut file = Cursor::new(binlog.to_vec());
+        let mut table_structs = HashMap::new();
+        let mut events = Vec::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body_without_row_values(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            let next_position = position + header.event_length as u64;
+            events.push((position, header, body));
+            position = next_position;
+        }
+
+        events
+    }
+
+    fn to_json(body: &dyn EventBody) -> serde_json::Value {
+        serde_json::to_value(body).unwrap()
+    }
+
+    fn fixture() -> Vec<u8> {
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[])
+            .column(FieldType::Varchar, &64u16.to_le_bytes())
+            .nullable(true);
+        let row = |id: i64, status: &str| {
+            vec![
+                ColumnValue::Int(id),
+                ColumnValue::String(status.to_string()),
+            ]
+        };
+
+        let mut gtid = 11u64.to_le_bytes().to_vec();
+        gtid.extend(1u32.to_le_bytes());
+        gtid.push(0);
+        gtid.extend([0u8; 6]);
+
+        let mut gtid_list = 1u32.to_le_bytes().to_vec();
+        gtid_list.extend(1u32.to_le_bytes());
+        gtid_list.extend(100u32.to_le_bytes());
+        gtid_list.extend(10u64.to_le_bytes());
+
+        let mut rotate = 4u64.to_le_bytes().to_vec();
+        rotate.extend(b"mysql-bin.000002");
+
+        BinlogBuilder::new()
+            .push(EventBuilder::new(163, gtid_list))
+            .push(EventBuilder::new(162, gtid).timestamp(1700000000))
+            .event(&QueryEventBuilder::new("shop", "BEGIN").thread_id(5))
+            .event(&table)
+            .event(&RowsEventBuilder::insert(&table).row(row(1, "new")))
+            .event(
+                &RowsEventBuilder::update(&table)
+                    .update_row(row(1, "new"), row(1, "paid"))
+                    .update_row(row(2, "new"), vec![ColumnValue::Int(2), ColumnValue::Null]),
+            )
+            .event(&RowsEventBuilder::delete(&table).row(row(1, "paid")))
+            .push(EventBuilder::new(16, 9u64.to_le_bytes().to_vec()))
+            .event(&QueryEventBuilder::new("shop", "DROP TABLE t").execute_time(2))
+            .push(EventBuilder::new(4, rotate))
+            .build()
+    }
+
+    #[test]
+    fn serialize_unchanged_events_as_original_bytes() {
+        let binlog = fixture();
+        let events = parse(&binlog);
+        assert_eq!(events.len(), 11);
+
+        for (position, header, body) in &events {
+            let start = *position as usize;
+            let end = start + header.event_length as usize;
+            let event = serialize_event(header, body.as_ref(), *position, true).unwrap();
+            assert_eq!(event, binlog[start..end], "event type {}", header.type_code);
+        }
+    }
+
+    #[test]
+    fn serialize_mysql_rows_events_v2() {
+        let table = TableMapBuilder::new("shop", "orders")
+            .table_id(70)
+            .column(FieldType::Long, &[]);
+        let binlog = BinlogBuilder::with_server_version("8.0.36")
+            .event(&table)
+            .event(
+                &RowsEventBuilder::insert(&table)
+                    .v2(true)
+                    .row(vec![ColumnValue::Int(1)]),
+            )
+            .event(
+                &RowsEventBuilder::update(&table)
+                    .v2(true)
+                    .update_row(vec![ColumnValue::Int(1)], vec![ColumnValue::Int(2)]),
+            )
+            .build();
+
+        // extra data为空时写回的字节和原来一样
+        let events = parse(&binlog);
+        for (position, header, body) in &events[2..] {
+            let start = *position as usize;
+            let end = start + header.event_length as usize;
+            let event = serialize_event(header, body.as_ref(), *position, true).unwrap();
+            assert_eq!(event, binlog[start..end], "event type {}", header.type_code);
+        }
+    }
+
+    #[test]
+    fn parse_serialized_events_as_the_same_events() {
+        let events = parse(&fixture());
+
+        let mut writer = BinlogWriter::new(Vec::new()).unwrap();
+        for (_, header, body) in &events {
+            writer.write_event(header, body.as_ref()).unwrap();
+        }
+        let rewritten = parse(&writer.into_inner());
+
+        assert_eq!(rewritten.len(), events.len());
+        for ((_, header, body), (_, new_header, new_body)) in events.iter().zip(&rewritten) {
+            assert_eq!(
+                serde_json::to_value(header).unwrap(),
+                serde_json::to_value(new_header).unwrap()
+            );
+            assert_eq!(to_json(body.as_ref()), to_json(new_body.as_ref()));
+        }
+    }
+
+    #[test]
+    fn write_raw_keeps_bytes_at_the_same_position() {
+        let binlog = fixture();
+
+        let mut writer = BinlogWriter::new(Vec::new()).unwrap();
+        for (position, header, _) in parse(&binlog) {
+            let start = position as usize;
+            writer
+                .write_raw(&binlog[start..start + header.event_length as usize])
+                .unwrap();
+        }
+
+        assert_eq!(writer.into_inner(), binlog);
+    }
+
+    #[test]
+    fn write_raw_moves_event_and_recomputes_checksum() {
+        let binlog = fixture();
+        let events = parse(&binlog);
+        let (position, header, _) = &events[3];
+        let start = *position as usize;
+        let raw_event = &binlog[start..start + header.event_length as usize];
+
+        // 没有FDE时默认有校验和
+        let mut writer = BinlogWriter::new(Vec::new()).unwrap();
+        writer.write_raw(raw_event).unwrap();
+        let output = writer.into_inner();
+
+        let event = &output[4..];
+        assert_eq!(event.len(), raw_event.len());
+        assert_eq!(event[13..17], (4 + raw_event.len() as u32).to_le_bytes());
+        let length = event.len();
+        assert_eq!(
+            event[length - 4..],
+            crc32fast::hash(&event[..length - 4]).to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn write_events_without_checksum_after_fde_with_none() {
+        let events = parse(&fixture());
+        let format_description = events[0].2.downcast_ref::<EventBodyTypeCode15>().unwrap();
+        let format_description = EventBodyTypeCode15 {
+            binlog_version: format_description.binlog_version,
+            server_version: format_description.server_version.clone(),
+            create_timestamp: format_description.create_timestamp,
+            header_length: format_description.header_length,
+            post_header_lengths: format_description.post_header_lengths.clone(),
+            checksum_algorithm: 0,
+        };
+
+        let mut writer =
+            BinlogWriter::with_format_description(Vec::new(), &events[0].1, &format_description)
+                .unwrap();
+        for (_, header, body) in &events[1..] {
+            writer.write_event(header, body.as_ref()).unwrap();
+        }
+        let output = writer.into_inner();
+
+        // FDE自己仍然有校验和，之后的事件的body后面直接是下一个事件
+        let fde_length = events[0].1.event_length as usize;
+        assert_eq!(output[4 + fde_length - 5], 0);
+        assert_eq!(
+            output[4 + fde_length - 4..4 + fde_length],
+            crc32fast::hash(&output[4..4 + fde_length - 4]).to_le_bytes()
+        );
+
+        let mut position = 4 + fde_length;
+        for (_, header, body) in &events[1..] {
+            let event_length =
+                u32::from_le_bytes(output[position + 9..position + 13].try_into().unwrap());
+            let next_event_position =
+                u32::from_le_bytes(output[position + 13..position + 17].try_into().unwrap());
+            let end = position + event_length as usize;
+            assert_eq!(next_event_position as usize, end);
+            assert_eq!(
+                output[position + EVENT_HEADER_LENGTH..end],
+                serialize_body(header.type_code, body.as_ref()).unwrap()[..]
+            );
+            position = end;
+        }
+        assert_eq!(position, output.len());
+
+        // 原样写入的没有校验和的事件移动位置时，最后4个字节也不变
+        let raw_event = output[4 + fde_length..].to_vec();
+        let first_length = u32::from_le_bytes(raw_event[9..13].try_into().unwrap()) as usize;
+        let mut writer =
+            BinlogWriter::with_format_description(Vec::new(), &events[0].1, &format_description)
+                .unwrap();
+        writer
+            .write_event(&events[2].1, events[2].2.as_ref())
+            .unwrap();
+        writer.write_raw(&raw_event[..first_length]).unwrap();
+        let moved = writer.into_inner();
+        let moved = &moved[moved.len() - first_length..];
+        assert_ne!(moved[13..17], raw_event[13..17]);
+        assert_eq!(moved[17..], raw_event[17..first_length]);
+    }
+}
@@ -0,0 +1,494 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::model::*;
+use crate::util::format_timestamp;
+
+const FL_PREPARED_XA: u8 = 0x40;
+const FL_COMPLETED_XA: u8 = 0x80;
+
+/// XA事务相关的事件是prepare还是XA COMMIT、XA ROLLBACK
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum XaAction {
+    Prepare,
+    Commit,
+    Rollback,
+}
+
+impl Display for XaAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self {
+            XaAction::Prepare => "prepare",
+            XaAction::Commit => "commit",
+            XaAction::Rollback => "rollback",
+        };
+        f.pad(action)
+    }
+}
+
+/// 没有完成的prepare，或者找不到prepare的XA COMMIT、XA ROLLBACK
+#[derive(Debug, Clone, Serialize)]
+pub struct XaEntry {
+    pub action: XaAction,
+    /// `format_id/gtrid/bqual`，见format_xid
+    pub xid: String,
+    /// 所在事务的GTID，MariaDB是`domain-server-seq`，MySQL是`uuid:gno`
+    pub gtid: Option<String>,
+    pub file_name: String,
+    /// 事务开始（GTID事件）的位置，没有GTID时是事件本身的位置
+    pub position: u64,
+    pub timestamp: u32,
+}
+
+impl Display for XaEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:8}  {:19}  {}:{}  {}  {}",
+            self.action,
+            format_timestamp(self.timestamp),
+            self.file_name,
+            self.position,
+            self.gtid.as_deref().unwrap_or("-"),
+            self.xid
+        )
+    }
+}
+
+/// xid按`format_id/gtrid/bqual`输出，gtrid和bqual中可打印的ASCII字符原样输出，
+/// 其他字节和`/`、`\`按`\xNN`转义
+pub fn format_xid(format_id: u32, gtrid: &[u8], bqual: &[u8]) -> String {
+    format!("{}/{}/{}", format_id, escape(gtrid), escape(bqual))
+}
+
+fn escape(value: &[u8]) -> String {
+    value
+        .iter()
+        .map(|v| match v {
+            b'/' | b'\\' => format!("\\x{:02x}", v),
+            0x20..=0x7e => (*v as char).to_string(),
+            _ => format!("\\x{:02x}", v),
+        })
+        .collect()
+}
+
+// 当前事务的GTID，以及MariaDB的GTID中带的xid
+struct CurrentTransaction {
+    gtid: String,
+    position: u64,
+    timestamp: u32,
+    flags: u8,
+    xid: Option<String>,
+}
+
+/// `--xa-report`，把XA PREPARE和之后的XA COMMIT、XA ROLLBACK按xid对应起来
+///
+/// prepare是XA_PREPARE_LOG_EVENT（38），完成是MariaDB带FL_COMPLETED_XA的GTID之后，或者MySQL的
+/// GTID之后的`XA COMMIT`、`XA ROLLBACK`语句；GTID中没有xid时从语句中的`X'..',X'..',N`解析。
+/// one phase commit不需要完成。所有文件都处理完还没有完成的prepare是悬挂的事务，
+/// prepare在所选文件之前的完成单独列出
+#[derive(Default)]
+pub struct XaReport {
+    file_name: String,
+    current: Option<CurrentTransaction>,
+    // 按prepare的顺序，完成后去掉
+    dangling: Vec<XaEntry>,
+    unmatched: Vec<XaEntry>,
+    prepared: u64,
+    committed: u64,
+    rolled_back: u64,
+    one_phase: u64,
+}
+
+impl XaReport {
+    pub fn new() -> XaReport {
+        XaReport::default()
+    }
+
+    /// 每个文件开始时调用，结果中记录所在的文件
+    pub fn start_file(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+        self.current = None;
+    }
+
+    pub fn process(&mut self, header: &EventHeader, position: u64, body: &dyn EventBody) {
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode162>() {
+            let xid = match (
+                gtid_event.format_id,
+                gtid_event.gtid_length,
+                &gtid_event.xid,
+            ) {
+                (Some(format_id), Some(gtid_length), Some(xid))
+                    if gtid_event.flags & (FL_PREPARED_XA | FL_COMPLETED_XA) > 0
+                        && xid.len() >= gtid_length as usize =>
+                {
+                    let (gtrid, bqual) = xid.split_at(gtid_length as usize);
+                    Some(format_xid(format_id, gtrid, bqual))
+                }
+                _ => None,
+            };
+            self.current = Some(CurrentTransaction {
+                gtid: format!(
+                    "{}-{}-{}",
+                    gtid_event.replication_domain_id, header.server_id, gtid_event.gtid_sequence
+                ),
+                position,
+                timestamp: header.timestamp,
+                flags: gtid_event.flags,
+                xid,
+            });
+            return;
+        }
+
+        if let Some(gtid_event) = body.downcast_ref::<EventBodyTypeCode33>() {
+            self.current = Some(CurrentTransaction {
+                gtid: format!("{}:{}", gtid_event.uuid, gtid_event.gno),
+                position,
+                timestamp: header.timestamp,
+                flags: 0,
+                xid: None,
+            });
+            return;
+        }
+
+        if let Some(prepare_event) = body.downcast_ref::<EventBodyTypeCode38>() {
+            let current = self.current.take();
+            if prepare_event.one_phase_commit != 0 {
+                self.one_phase += 1;
+                return;
+            }
+
+            let gtrid_length =
+                (prepare_event.length_of_gtrid as usize).min(prepare_event.xid.len());
+            let (gtrid, bqual) = prepare_event.xid.split_at(gtrid_length);
+            let xid = format_xid(prepare_event.format_id, gtrid, bqual);
+
+            self.prepared += 1;
+            // 同一个xid完成后可以再次使用，这时之前的prepare已经去掉
+            self.dangling.retain(|v| v.xid != xid);
+            self.dangling
+                .push(self.entry(XaAction::Prepare, xid, current, header, position));
+            return;
+        }
+
+        if let Some(query_event) = body.downcast_ref::<EventBodyTypeCode2>() {
+            let (action, sql_xid) = match parse_completion(&query_event.sql) {
+                Some(completion) => completion,
+                None => return,
+            };
+            let current = self.current.take();
+            let gtid_xid = current
+                .as_ref()
+                .filter(|v| v.flags & FL_COMPLETED_XA > 0)
+                .and_then(|v| v.xid.clone());
+            let xid = match gtid_xid.or(sql_xid) {
+                Some(xid) => xid,
+                None => return,
+            };
+
+            match self.dangling.iter().position(|v| v.xid == xid) {
+                Some(index) => {
+                    self.dangling.remove(index);
+                    match action {
+                        XaAction::Rollback => self.rolled_back += 1,
+                        _ => self.committed += 1,
+                    }
+                }
+                None => {
+                    let entry = self.entry(action, xid, current, header, position);
+                    self.unmatched.push(entry);
+                }
+            }
+        }
+    }
+
+    fn entry(
+        &self,
+        action: XaAction,
+        xid: String,
+        current: Option<CurrentTransaction>,
+        header: &EventHeader,
+        position: u64,
+    ) -> XaEntry {
+        let (gtid, position, timestamp) = match current {
+            Some(current) => (Some(current.gtid), current.position, current.timestamp),
+            None => (None, position, header.timestamp),
+        };
+
+        XaEntry {
+            action,
+            xid,
+            gtid,
+            file_name: self.file_name.clone(),
+            position,
+            timestamp,
+        }
+    }
+
+    /// 还没有完成的prepare，按prepare的顺序
+    pub fn dangling(&self) -> impl Iterator<Item = &XaEntry> {
+        self.dangling.iter()
+    }
+
+    /// 在所选文件中找不到prepare的XA COMMIT和XA ROLLBACK
+    pub fn unmatched(&self) -> impl Iterator<Item = &XaEntry> {
+        self.unmatched.iter()
+    }
+}
+
+// `XA COMMIT X'..',X'..',N`和`XA ROLLBACK ...`，返回操作和语句中的xid（能解析时）
+fn parse_completion(sql: &str) -> Option<(XaAction, Option<String>)> {
+    let mut words = sql.trim_start().splitn(3, char::is_whitespace);
+    if !words.next()?.eq_ignore_ascii_case("XA") {
+        return None;
+    }
+    let action = match words.next()? {
+        v if v.eq_ignore_ascii_case("COMMIT") => XaAction::Commit,
+        v if v.eq_ignore_ascii_case("ROLLBACK") => XaAction::Rollback,
+        _ => return None,
+    };
+
+    Some((action, words.next().and_then(parse_xid)))
+}
+
+// 服务器写入的xid总是`X'gtrid',X'bqual',format_id`的形式
+fn parse_xid(value: &str) -> Option<String> {
+    let mut parts = value.trim().splitn(3, ',');
+    let gtrid = parse_hex(parts.next()?)?;
+    let bqual = parse_hex(parts.next()?)?;
+    let format_id = parts
+        .next()?
+        .split_whitespace()
+        .next()?
+        .parse::<u32>()
+        .ok()?;
+
+    Some(format_xid(format_id, &gtrid, &bqual))
+}
+
+fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    let hex = value
+        .strip_prefix("X'")
+        .or_else(|| value.strip_prefix("x'"))?
+        .strip_suffix('\'')?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl Display for XaReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let header = format!("{:8}  {:19}  file:position  gtid  xid", "action", "time");
+
+        writeln!(
+            f,
+            "dangling prepared XA transactions: {}",
+            self.dangling.len()
+        )?;
+        if !self.dangling.is_empty() {
+            writeln!(f, "{}", header)?;
+            for entry in self.dangling() {
+                writeln!(f, "{}", entry)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "XA COMMIT/ROLLBACK without a prepare in these files: {}",
+            self.unmatched.len()
+        )?;
+        if !self.unmatched.is_empty() {
+            writeln!(f, "{}", header)?;
+            for entry in self.unmatched() {
+                writeln!(f, "{}", entry)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "{} prepared, {} committed, {} rolled back, {} one phase commit(s)",
+            self.prepared, self.committed, self.rolled_back, self.one_phase
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::*;
+    use crate::service::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    // MariaDB的XA事务的GTID，带format_id、gtrid和bqual
+    fn gtid(sequence: u64, flags: u8, gtrid: &[u8], bqual: &[u8]) -> EventBuilder {
+        EventBuilder::from_body(
+            &GtidEventBuilder::new(sequence)
+                .flags(flags)
+                .xa(1, gtrid, bqual),
+        )
+        .timestamp(1700000000 + sequence as u32)
+    }
+
+    fn xa_prepare(gtrid: &[u8], bqual: &[u8]) -> EventBuilder {
+        xa_prepare_with(0, gtrid, bqual)
+    }
+
+    fn xa_prepare_with(one_phase_commit: u8, gtrid: &[u8], bqual: &[u8]) -> EventBuilder {
+        let mut body = vec![one_phase_commit];
+        body.extend(1u32.to_le_bytes());
+        body.extend((gtrid.len() as u32).to_le_bytes());
+        body.extend((bqual.len() as u32).to_le_bytes());
+        body.extend(gtrid);
+        body.extend(bqual);
+        EventBuilder::new(38, body)
+    }
+
+    fn query(sql: &str) -> EventBuilder {
+        EventBuilder::from_body(&QueryEventBuilder::new("shop", sql))
+    }
+
+    // trx1提交了，trx2在文件结束时还是prepare，同时返回trx2的GTID事件的位置
+    fn fixture() -> (Vec<u8>, u64) {
+        let binlog = BinlogBuilder::new()
+            .push(gtid(10, FL_PREPARED_XA, b"trx1", b"b"))
+            .push(query("XA START X'74727831',X'62',1"))
+            .push(query("XA END X'74727831',X'62',1"))
+            .push(xa_prepare(b"trx1", b"b"));
+        let position = binlog.position();
+        let binlog = binlog
+            .push(gtid(11, FL_PREPARED_XA, b"trx2", b""))
+            .push(query("XA START X'74727832',X'',1"))
+            .push(query("XA END X'74727832',X'',1"))
+            .push(xa_prepare(b"trx2", b""))
+            .push(gtid(12, FL_COMPLETED_XA, b"trx1", b"b"))
+            .push(query("XA COMMIT X'74727831',X'62',1"))
+            .build();
+        (binlog, position)
+    }
+
+    fn process(report: &mut XaReport, file_name: &str, binlog: &[u8]) {
+        report.start_file(file_name);
+
+        let mut file = Cursor::new(binlog);
+        let mut table_structs = HashMap::new();
+        let mut position = 4;
+        while position < binlog.len() as u64 {
+            let header = get_event_header(&mut file, position).unwrap();
+            let body = get_event_body(
+                &mut file,
+                position,
+                &header,
+                &mut table_structs,
+                CHECKSUM_LENGTH,
+            )
+            .unwrap();
+            report.process(&header, position, body.as_ref());
+            position = header.next_event_position as u64;
+        }
+    }
+
+    #[test]
+    fn completed_and_dangling() {
+        let (binlog, position) = fixture();
+        let mut report = XaReport::new();
+        process(&mut report, "mysql-bin.000001", &binlog);
+
+        let dangling = report.dangling().collect::<Vec<_>>();
+        assert_eq!(dangling.len(), 1);
+        assert_eq!(dangling[0].action, XaAction::Prepare);
+        assert_eq!(dangling[0].xid, "1/trx2/");
+        assert_eq!(dangling[0].gtid.as_deref(), Some("0-1-11"));
+        assert_eq!(dangling[0].file_name, "mysql-bin.000001");
+        assert_eq!(dangling[0].position, position);
+        assert_eq!(report.unmatched().count(), 0);
+
+        assert_eq!(
+            report.to_string(),
+            format!(
+                concat!(
+                    "dangling prepared XA transactions: 1\n",
+                    "action    time                 file:position  gtid  xid\n",
+                    "prepare   2023-11-14 22:13:31  mysql-bin.000001:{}  0-1-11  1/trx2/\n",
+                    "XA COMMIT/ROLLBACK without a prepare in these files: 0\n",
+                    "2 prepared, 1 committed, 0 rolled back, 0 one phase commit(s)\n",
+                ),
+                position
+            )
+        );
+    }
+
+    #[test]
+    fn completion_in_a_later_file() {
+        // 下一个文件中回滚了trx2，还有一个prepare在所选文件之前的提交
+        let next = BinlogBuilder::new()
+            .push(gtid(13, FL_COMPLETED_XA, b"trx2", b""))
+            .push(query("XA ROLLBACK X'74727832',X'',1"))
+            .push(gtid(14, FL_COMPLETED_XA, b"old", b""))
+            .push(query("XA COMMIT X'6f6c64',X'',1"))
+            .build();
+
+        let mut report = XaReport::new();
+        process(&mut report, "mysql-bin.000001", &fixture().0);
+        process(&mut report, "mysql-bin.000002", &next);
+
+        assert_eq!(report.dangling().count(), 0);
+        let unmatched = report.unmatched().collect::<Vec<_>>();
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].action, XaAction::Commit);
+        assert_eq!(unmatched[0].xid, "1/old/");
+        assert_eq!(unmatched[0].gtid.as_deref(), Some("0-1-14"));
+        assert_eq!(unmatched[0].file_name, "mysql-bin.000002");
+        assert!(report
+            .to_string()
+            .ends_with("2 prepared, 1 committed, 1 rolled back, 0 one phase commit(s)\n"));
+
+        let json = serde_json::to_value(unmatched[0]).unwrap();
+        assert_eq!(json["action"], "commit");
+        assert_eq!(json["xid"], "1/old/");
+    }
+
+    #[test]
+    fn xid_from_the_statement_and_one_phase_commit() {
+        // 没有GTID时从语句中解析xid
+        let binlog = BinlogBuilder::new()
+            .push(xa_prepare(b"a/b", b"\x00"))
+            .push(xa_prepare_with(1, b"op", b""))
+            .push(query("xa rollback X'612f62',X'00',1"))
+            .build();
+
+        let mut report = XaReport::new();
+        process(&mut report, "mysql-bin.000003", &binlog);
+        assert_eq!(report.dangling().count(), 0);
+        assert_eq!(report.unmatched().count(), 0);
+        assert!(report
+            .to_string()
+            .ends_with("1 prepared, 0 committed, 1 rolled back, 1 one phase commit(s)\n"));
+    }
+
+    #[test]
+    fn escaped_xid() {
+        assert_eq!(format_xid(1, b"trx1", b"b"), "1/trx1/b");
+        assert_eq!(
+            format_xid(7, b"a/b\\c", b"\x00\xff "),
+            "7/a\\x2fb\\x5cc/\\x00\\xff "
+        );
+        assert_eq!(
+            parse_completion("XA COMMIT X'612f62',X'00',1 ONE PHASE"),
+            Some((XaAction::Commit, Some("1/a\\x2fb/\\x00".to_string())))
+        );
+        assert_eq!(parse_completion("XA START X'61',X'',1"), None);
+        assert_eq!(
+            parse_completion("XA ROLLBACK 'abc'"),
+            Some((XaAction::Rollback, None))
+        );
+    }
+}